@@ -0,0 +1,231 @@
+// Benchmarks of the emulator's core loops.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Baselines for the loops every emulated instruction goes through: decoding
+// (by the reference decoder and by the opcode table the fast engine uses),
+// executing ALU instructions, word accesses to memory and clock phases of
+// the whole system, plus a guest program that calls deep enough to cycle
+// through the register windows. Run with `cargo bench`, criterion keeps the
+// last results under target/criterion and reports the change against them.
+
+#[macro_use]
+extern crate criterion;
+extern crate riscii;
+
+use criterion::{black_box, Criterion, Throughput};
+use riscii::asm::assemble;
+use riscii::config::{ClockMode, Endian, Engine};
+use riscii::data_path::DataPath;
+use riscii::decode::decode_fast;
+use riscii::execute::execute;
+use riscii::instruction::{
+    Conditional, LongConditional, LongInstruction, ShortConditional, ShortInstruction, ShortSource,
+};
+use riscii::loader::Format;
+use riscii::{decode, Config, Image, Instruction, Memory, System};
+
+type I = Instruction;
+type SS = ShortSource;
+type SI = ShortInstruction;
+type SC = ShortConditional;
+
+/// Guest program computing the 7th Fibonacci number by naive recursion,
+/// over and over. Every call takes a window, the deepest calls take all but
+/// one of them. Nothing spills the windows, deeper recursion would clobber
+/// the return addresses of the oldest calls.
+const FIBONACCI: &str = "
+start:  add     r10, r0, 7
+        callr   ra, fib
+        add     r0, r0, r0
+        jmpr    alw, start
+        add     r0, r0, r0
+
+; r26 := fib(r26).
+fib:    sub.cc  r0, r26, 2
+        jmpr    lt, done
+        add     r0, r0, r0
+        sub     r10, r26, 1
+        callr   ra, fib
+        add     r0, r0, r0
+        add     r16, r10, 0
+        sub     r10, r26, 2
+        callr   ra, fib
+        add     r0, r0, r0
+        add     r26, r16, r10
+done:   ret     alw, ra, 8
+        add     r0, r0, r0
+";
+
+/// Address of the instruction `FIBONACCI` retires once per run, when the
+/// result is in r10.
+const FIBONACCI_DONE: u32 = 0xc;
+
+/// Most clock cycles a run of `FIBONACCI` takes, with room to spare.
+const FIBONACCI_CYCLES: u64 = 10_000;
+
+/// Get one of each kind of instruction, so decoding takes every path.
+fn instruction_mix() -> Vec<I> {
+    vec![
+        I::Add(SI::new(false, 16, 17, SS::Reg(18))),
+        I::Sub(SI::new(true, 16, 17, SS::Imm13(0x42))),
+        I::And(SI::new(false, 19, 20, SS::Reg(21))),
+        I::Sll(SI::new(false, 16, 16, SS::Imm13(3))),
+        I::Ldxw(SI::new(false, 22, 1, SS::Imm13(0x100))),
+        I::Stxw(SI::new(false, 22, 1, SS::Imm13(0x104))),
+        I::Ldhi(LongInstruction::new(false, 17, 0x7fff8)),
+        I::Jmpx(SC::new(false, Conditional::Ne, 0, SS::Imm13(0x40))),
+        I::Jmpr(LongConditional::new(false, Conditional::Alw, 0x10)),
+        I::Callr(LongInstruction::new(false, 25, 0x80)),
+        I::Ret(SC::new(false, Conditional::Alw, 25, SS::Imm13(8))),
+        I::GetPSW(SI::new(false, 16, 0, SS::Imm13(0))),
+    ]
+}
+
+/// Bytes of memory the benchmarks run with.
+const MEM_SIZE: u32 = 0x10000;
+
+/// Get the configuration the benchmarks run with: room for their data and
+/// a clock that does not wait.
+fn make_config() -> Config {
+    let mut config = Config::new().unwrap();
+    config.set_mem_size(MEM_SIZE);
+    config.set_clock_mode(ClockMode::Unlimited);
+    config
+}
+
+/// Build a system running `source`.
+fn make_system(source: &str, engine: Engine) -> System {
+    let program = assemble(source, 0).unwrap();
+    let image = Image::parse(&program.to_bytes(Endian::Big), Format::Raw, 0).unwrap();
+    let mut system = System::new(&make_config()).unwrap();
+    system.set_engine(engine);
+    system.load_image(&image).unwrap();
+    system
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let words: Vec<u32> = instruction_mix().iter().map(|i| i.encode()).collect();
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(words.len() as u64));
+    group.bench_function("reference", |b| {
+        b.iter(|| {
+            for word in words.iter() {
+                black_box(decode(black_box(*word)).unwrap());
+            }
+        })
+    });
+    group.bench_function("table", |b| {
+        b.iter(|| {
+            for word in words.iter() {
+                black_box(decode_fast(black_box(*word)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_alu(c: &mut Criterion) {
+    let instructions = [
+        I::Add(SI::new(true, 16, 17, SS::Reg(18))),
+        I::Addc(SI::new(true, 16, 17, SS::Reg(18))),
+        I::Sub(SI::new(true, 16, 17, SS::Imm13(0x42))),
+        I::Subi(SI::new(true, 16, 17, SS::Reg(18))),
+        I::And(SI::new(false, 16, 17, SS::Reg(18))),
+        I::Or(SI::new(false, 16, 17, SS::Imm13(0xff))),
+        I::Xor(SI::new(true, 16, 17, SS::Reg(18))),
+        I::Sll(SI::new(false, 16, 17, SS::Imm13(5))),
+        I::Sra(SI::new(false, 16, 17, SS::Imm13(5))),
+    ];
+    let mut dp = DataPath::new();
+    dp.get_register_file().write(17, 0x8765_4321, 0);
+    dp.get_register_file().write(18, 0x0000_1234, 0);
+    let mut memory = Memory::new(&make_config());
+    let mut group = c.benchmark_group("alu");
+    group.throughput(Throughput::Elements(instructions.len() as u64));
+    group.bench_function("execute", |b| {
+        b.iter(|| {
+            for instruction in instructions.iter() {
+                black_box(execute(instruction, &dp, &mut memory).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_memory(c: &mut Criterion) {
+    const WORDS: u32 = 1024;
+    let mut memory = Memory::new(&make_config());
+    let mut group = c.benchmark_group("memory");
+    group.throughput(Throughput::Elements(WORDS as u64));
+    group.bench_function("get_word", |b| {
+        b.iter(|| {
+            for i in 0..WORDS {
+                black_box(memory.get_word(black_box(i * 4)).unwrap());
+            }
+        })
+    });
+    group.bench_function("set_word", |b| {
+        b.iter(|| {
+            for i in 0..WORDS {
+                memory.set_word(black_box(i * 4), i).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tick");
+    group.throughput(Throughput::Elements(1));
+    for &(name, engine) in [("fast", Engine::Fast), ("cycle", Engine::Cycle)].iter() {
+        let mut system = make_system(FIBONACCI, engine);
+        group.bench_function(name, |b| b.iter(|| system.tick().unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_fibonacci(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fibonacci");
+    group.sample_size(20);
+    for &(name, engine) in [("fast", Engine::Fast), ("cycle", Engine::Cycle)].iter() {
+        let mut system = make_system(FIBONACCI, engine);
+        // Each iteration is one run of the program, check a run is right
+        // first.
+        assert!(system
+            .run_until(FIBONACCI_DONE, FIBONACCI_CYCLES)
+            .unwrap()
+            .is_some());
+        assert_eq!(system.data_path().register_file().read(10, 0), 13);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                system
+                    .run_until(FIBONACCI_DONE, FIBONACCI_CYCLES)
+                    .unwrap()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_decode,
+    bench_alu,
+    bench_memory,
+    bench_tick,
+    bench_fibonacci
+);
+criterion_main!(benches);
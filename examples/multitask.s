@@ -0,0 +1,94 @@
+; Timer driven multitasking demo.
+; (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+; This program is free software: you can redistribute it and/or modify
+; it under the terms of the GNU Affero General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or (at
+; your option) any later version.
+
+; This program is distributed in the hope that it will be useful, but
+; WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+; General Public License for more details.
+
+; You should have received a copy of the GNU Affero General Public License
+; along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+; Two user mode tasks print `A` and `B` to the console forever, and the timer
+; interrupt switches between them. Each task has its own register window:
+; task A runs in window 6 and task B in window 3. The window below each task's
+; (5 and 2) is its handler window, where r16 holds the address the task
+; resumes at and r20 its condition codes. The interrupt handler saves the
+; interrupted task's state there, `calli` writing the address, points the
+; PSW at the other task's handler window and returns from it with `reti`,
+; which pops into the other task's window.
+;
+; Assemble at 0xc0, the interrupt vector's offset from `TRAP_VECTOR_BASE`.
+; The image expects the MMU on with both segments based at `TRAP_VECTOR_BASE`,
+; so the trap vectors are at the bottom of memory and the devices at
+; 0x7fff0000. Both modes need the same mapping, as the instruction in the
+; delay slot of `reti` is fetched in user mode. Enter `boot` in system mode.
+
+; Interrupt vector, entered in the interrupted task's window.
+interrupt:
+        calli   r16, r0, r0         ; r16 = where the task stopped
+        ldhi    r17, 0x3fff8        ; r17 = devices, 0x7fff0000
+        add     r18, r0, 1          ; TIMER_EXPIRED
+        getpsw  r19, r0, r0
+        stxw    r18, r17, 0x9c      ; acknowledge the timer
+        and     r20, r19, 0xf       ; r20 = the task's condition codes
+        and     r19, r19, 0x1c00    ; this handler window
+        add     r0, r0, r0
+        xor     r19, r19, 0x1c00    ; the other task's, 5 <-> 2
+        add     r0, r0, r0
+        or      r19, r19, 0x20      ; system mode, back to user mode on reti
+        add     r0, r0, r0
+        putpsw  r0, r19, r0
+        add     r0, r0, r0          ; the new PSW takes a cycle
+        add     r0, r0, r0
+        getpsw  r19, r0, r0         ; give back the other task's codes
+        add     r0, r0, r0
+        or      r19, r19, r20
+        add     r0, r0, r0
+        putpsw  r0, r19, r0
+        add     r0, r0, r0
+        add     r0, r0, r0
+        reti    alw, r16, r0
+        add     r0, r0, r0
+
+boot:
+        ldhi    r1, 0x40000         ; r1 = TRAP_VECTOR_BASE, where the image is
+        add     r19, r0, 0x820      ; task B's handler window
+        add     r0, r0, r0
+        putpsw  r0, r19, r0
+        add     r0, r0, r0
+        add     r0, r0, r0
+        add     r16, r1, task_b
+        add     r19, r0, 0x1420     ; task A's handler window
+        add     r0, r0, r0
+        putpsw  r0, r19, r0
+        add     r0, r0, r0
+        add     r0, r0, r0
+        add     r16, r1, task_a
+        ldhi    r17, 0x3fff8
+        add     r18, r0, 400        ; switch tasks every 400 cycles
+        add     r0, r0, r0
+        stxw    r18, r17, 0x90      ; TIMER_PERIOD
+        add     r18, r0, 1          ; TIMER_INTERRUPT_ENABLE
+        add     r0, r0, r0
+        stxw    r18, r17, 0x98      ; TIMER_CONTROL
+        reti    alw, r16, r0        ; start task A
+        add     r0, r0, r0
+
+task_a: jmpx    alw, r1, task
+        add     r18, r0, 0x41       ; 'A'
+task_b: add     r18, r0, 0x42       ; 'B'
+task:   ldhi    r17, 0x3fff8        ; console, 0x7fff0000
+        add     r0, r0, r0
+print:  stxb    r18, r17, 0
+        add     r19, r0, 20
+        add     r0, r0, r0
+wait:   sub.cc  r19, r19, 1         ; spin a while between letters
+        jmpx    ne, r1, wait
+        add     r0, r0, r0
+        jmpx    alw, r1, print
+        add     r0, r0, r0
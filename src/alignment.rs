@@ -0,0 +1,131 @@
+// Memory access alignment statistics.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use commit::RetiredInstruction;
+use cpu::Trap;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Number of offending PCs listed in the report.
+const REPORT_OFFENDERS: usize = 10;
+
+/// Access counts for one access width.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WidthCounts {
+    /// Accesses that were aligned.
+    pub aligned: u64,
+    /// Accesses that were attempted on a misaligned address.
+    pub misaligned: u64,
+}
+
+/// A load or store that attempted a misaligned access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Offender {
+    /// Address of the instruction.
+    pub pc: u32,
+    /// Number of misaligned accesses it attempted.
+    pub count: u64,
+    /// Most recent misaligned address it accessed.
+    pub last_address: u32,
+}
+
+/// Counts aligned and misaligned loads and stores and remembers which
+/// instructions attempted misaligned accesses.
+#[derive(Debug, Clone, Default)]
+pub struct AlignmentStats {
+    /// Counts for word, half word and byte accesses, in that order.
+    widths: [WidthCounts; 3],
+    /// Misaligned accesses by PC.
+    offenders: HashMap<u32, Offender>,
+}
+
+impl AlignmentStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the memory access (if any) of a retired instruction.
+    /// # Arguments
+    /// * `retired` - The retired instruction.
+    pub fn observe(&mut self, retired: &RetiredInstruction) {
+        let width = match retired.instruction.access_width() {
+            Some(w) => w,
+            None => return,
+        };
+        let counts = &mut self.widths[width_index(width)];
+        match retired.trap {
+            Some(Trap::Alignment(address)) => {
+                counts.misaligned += 1;
+                let offender = self.offenders.entry(retired.pc).or_insert(Offender {
+                    pc: retired.pc,
+                    count: 0,
+                    last_address: address,
+                });
+                offender.count += 1;
+                offender.last_address = address;
+            }
+            _ => counts.aligned += 1,
+        }
+    }
+
+    /// Get the counts for accesses of `width` bytes (1, 2 or 4).
+    pub fn width(&self, width: u32) -> WidthCounts {
+        self.widths[width_index(width)]
+    }
+
+    /// Total number of misaligned accesses attempted.
+    pub fn misaligned(&self) -> u64 {
+        self.widths.iter().map(|c| c.misaligned).sum()
+    }
+
+    /// Get the `n` instructions that attempted the most misaligned accesses,
+    /// worst first.
+    pub fn top_offenders(&self, n: usize) -> Vec<Offender> {
+        let mut offenders: Vec<Offender> = self.offenders.values().cloned().collect();
+        offenders.sort_by(|a, b| b.count.cmp(&a.count).then(a.pc.cmp(&b.pc)));
+        offenders.truncate(n);
+        offenders
+    }
+}
+
+impl fmt::Display for AlignmentStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<6} {:>12} {:>12}", "width", "aligned", "misaligned")?;
+        for (name, counts) in ["word", "half", "byte"].iter().zip(self.widths.iter()) {
+            writeln!(
+                f,
+                "{:<6} {:>12} {:>12}",
+                name, counts.aligned, counts.misaligned
+            )?;
+        }
+        for offender in self.top_offenders(REPORT_OFFENDERS) {
+            writeln!(
+                f,
+                "pc {:08x}: {} misaligned (last at {:08x})",
+                offender.pc, offender.count, offender.last_address
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Index into `AlignmentStats::widths` for an access of `width` bytes.
+fn width_index(width: u32) -> usize {
+    match width {
+        4 => 0,
+        2 => 1,
+        _ => 2,
+    }
+}
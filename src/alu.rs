@@ -20,6 +20,9 @@ use std::fmt;
 use data_path::SCCBits;
 use instruction::SIGN_BIT_LOC;
 
+/// Bits of the second input a shift uses as its amount.
+const SHIFT_AMOUNT_MASK: u32 = 0x1f;
+
 /// Representation of the Arithmetic Logic Unit of the RISCII.
 /// Implements bitwise and arithmetic operations, except for shifts.
 #[derive(Clone, Copy)]
@@ -116,10 +119,42 @@ impl ALU {
     }
 
     // Arithmetics.
+    //
+    // Every addition and subtraction is one 32 bit add with a carry in, like
+    // the hardware's adder. A subtraction adds the complement of the
+    // subtrahend with a carry in of 1, so the carry out is NOT borrow: c is
+    // set if the minuend was greater than or equal to the subtrahend. The
+    // with carry variants use the carry bit as the carry in, so subc and subci
+    // subtract 1 more when the previous subtraction borrowed. Results wrap.
+
+    /// Add two values and a carry in, return the sum and the SCC values.
+    /// The SCC bits are as follows:
+    /// v = Signed overflow occurred
+    /// c = Carry out of bit 31
+    /// z = result == 0
+    /// n = result as i32 < 0
+    /// # Arguments
+    /// * `a` - First addend.
+    /// * `b` - Second addend.
+    /// * `carry` - Carry in.
+    fn add_with_carry(a: u32, b: u32, carry: bool) -> (u32, SCCBits) {
+        let sum = a as u64 + b as u64 + carry as u64;
+        let result = sum as u32;
+        (
+            result,
+            SCCBits {
+                z: result == 0,
+                n: result & SIGN_BIT_LOC != 0,
+                // The sign changed from that of both addends.
+                v: (a ^ result) & (b ^ result) & SIGN_BIT_LOC != 0,
+                c: sum >> 32 != 0,
+            },
+        )
+    }
 
     /// Add the values in the input latches and return the sum.
     pub fn add(&self) -> u32 {
-        self.ai + self.bi
+        self.add_scc().0
     }
 
     /// Add the values in the input latches, return the sum and SCC values.
@@ -129,25 +164,14 @@ impl ALU {
     /// z = result == 0
     /// n = result as i32 < 0
     pub fn add_scc(&self) -> (u32, SCCBits) {
-        let (iresult, v) = (self.ai as i32).overflowing_add(self.bi as i32);
-        let (result, c) = self.ai.overflowing_add(self.bi);
-        let z = result == 0;
-        let n = iresult < 0;
-
-        (
-            result,
-            SCCBits {
-                z: z,
-                n: n,
-                c: c,
-                v: v,
-            },
-        )
+        Self::add_with_carry(self.ai, self.bi, false)
     }
 
     /// Add the values in the input latches with the carry bit.
+    /// # Arguments
+    /// * `carry` - Carry bit of the PSW.
     pub fn addc(&self, carry: bool) -> u32 {
-        self.ai + self.bi + (carry as u32)
+        self.addc_scc(carry).0
     }
 
     /// Add the values in the input latches with the carry bit, return the
@@ -157,27 +181,16 @@ impl ALU {
     /// c = unsigned overflow occurred
     /// z = result == 0
     /// n = result as i32 < 0
+    /// # Arguments
+    /// * `carry` - Carry bit of the PSW.
     pub fn addc_scc(&self, carry: bool) -> (u32, SCCBits) {
-        let (iresult, v) = (self.ai as i32 + carry as i32).overflowing_add(self.bi as i32);
-        let (result, c) = (self.ai as u32 + carry as u32).overflowing_add(self.bi);
-        let z = result == 0;
-        let n = iresult.is_negative();
-
-        (
-            result,
-            SCCBits {
-                z: z,
-                n: n,
-                c: c,
-                v: v,
-            },
-        )
+        Self::add_with_carry(self.ai, self.bi, carry)
     }
 
     /// Subtract the values in the input latches and return the difference.
     /// Use `self.ai` is the minuend and use `self.bi` as the subtrahend.
     pub fn sub(&self) -> u32 {
-        self.ai - self.bi
+        self.sub_scc().0
     }
 
     /// Subtract the values in the input latches, return SCC values. Return
@@ -189,59 +202,39 @@ impl ALU {
     /// z = result == 0
     /// n = result as i32 < 0
     pub fn sub_scc(&self) -> (u32, SCCBits) {
-        let (iresult, v) = (self.ai as i32).overflowing_sub(self.bi as i32);
-        let (result, c) = self.ai.overflowing_sub(self.bi);
-        let z = result == 0;
-        let n = iresult.is_negative();
-
-        (
-            result,
-            SCCBits {
-                z: z,
-                c: !c,
-                n: n,
-                v: v,
-            },
-        )
+        Self::add_with_carry(self.ai, !self.bi, true)
     }
 
-    /// Subtract the values in the input latches and add the carry bit to the difference.
-    /// Return the sum.
-    /// Use `self.ai` is the minuend and use `self.bi` as the subtrahend and add carry to the difference.
+    /// Subtract the values in the input latches, and 1 more if the carry bit
+    /// is clear. Return the difference.
+    /// Use `self.ai` is the minuend and use `self.bi` as the subtrahend.
+    /// # Arguments
+    /// * `carry` - Carry bit of the PSW, clear if the last subtraction
+    /// borrowed.
     pub fn subc(&self, carry: bool) -> u32 {
-        self.ai - self.bi + carry as u32
+        self.subc_scc(carry).0
     }
 
-    /// Subtract the values in the input latches and add the carry bit.
-    /// Return the sum and SCC values.
-    /// Use `self.ai` is the minuend and use `self.bi` as the subtrahend and add carry to the difference.
+    /// Subtract the values in the input latches, and 1 more if the carry bit
+    /// is clear. Return the difference and SCC values.
+    /// Use `self.ai` is the minuend and use `self.bi` as the subtrahend.
     /// For subtraction the SCC bits are as follows:
     /// v = Signed overflow occurred
     /// c = unsigned overflow NOT occurred
     /// z = result == 0
     /// n = result as i32 < 0
+    /// # Arguments
+    /// * `carry` - Carry bit of the PSW, clear if the last subtraction
+    /// borrowed.
     pub fn subc_scc(&self, carry: bool) -> (u32, SCCBits) {
-        let (iresult, v) = (self.ai as i32 - self.bi as i32).overflowing_add(carry as i32);
-        let (result, c) = (self.ai - self.bi).overflowing_add(carry as u32);
-        let z = result == 0;
-        let n = iresult.is_negative();
-
-        (
-            result,
-            SCCBits {
-                z: z,
-                c: !c,
-                n: n,
-                v: v,
-            },
-        )
+        Self::add_with_carry(self.ai, !self.bi, carry)
     }
 
     /// Subtract the values in the input latches in the reverse order of `sub`, return
     /// the difference.
     /// Use `self.bi` is the minuend and use `self.ai` as the subtrahend.
     pub fn subi(&self) -> u32 {
-        self.bi - self.ai
+        self.subi_scc().0
     }
 
     /// Subtract the values in the input latches in the reverse order of `sub`, return
@@ -253,62 +246,48 @@ impl ALU {
     /// z = result == 0
     /// n = result as i32 < 0
     pub fn subi_scc(&self) -> (u32, SCCBits) {
-        let (iresult, v) = (self.bi as i32).overflowing_sub(self.ai as i32);
-        let (result, c) = self.bi.overflowing_sub(self.ai);
-        let z = result == 0;
-        let n = iresult.is_negative();
-
-        (
-            result,
-            SCCBits {
-                z: z,
-                c: c,
-                n: n,
-                v: v,
-            },
-        )
+        Self::add_with_carry(self.bi, !self.ai, true)
     }
 
     /// Subtract the values in the input latches in the reverse order of `sub`,
-    /// and add the carry bit. Return the sum.
+    /// and 1 more if the carry bit is clear. Return the difference.
     /// Use `self.bi` is the minuend and use `self.ai` as the subtrahend.
+    /// # Arguments
+    /// * `carry` - Carry bit of the PSW, clear if the last subtraction
+    /// borrowed.
     pub fn subci(&self, carry: bool) -> u32 {
-        self.bi - self.ai - (!carry as u32)
+        self.subci_scc(carry).0
     }
 
     /// Subtract the values in the input latches in the reverse order of `sub`,
-    /// and add the carry bit. Return the sum and the SCC values.
+    /// and 1 more if the carry bit is clear. Return the difference and the
+    /// SCC values.
     /// Use `self.bi` is the minuend and use `self.ai` as the subtrahend.
     /// For subtraction the SCC bits are as follows:
     /// v = Signed overflow occurred
     /// c = unsigned overflow NOT occurred
     /// z = result == 0
     /// n = result as i32 < 0
+    /// # Arguments
+    /// * `carry` - Carry bit of the PSW, clear if the last subtraction
+    /// borrowed.
     pub fn subci_scc(&self, carry: bool) -> (u32, SCCBits) {
-        let (iresult, v) = (self.bi as i32 - self.ai as i32).overflowing_add(carry as i32);
-        let (result, c) = (self.bi - self.ai).overflowing_add(carry as u32);
-        let z = result == 0;
-        let n = iresult.is_negative();
+        Self::add_with_carry(self.bi, !self.ai, carry)
+    }
 
-        (
-            result,
-            SCCBits {
-                z: z,
-                c: c,
-                n: n,
-                v: v,
-            },
-        )
+    /// Get the amount shifts shift by, the low 5 bits of `self.bi`.
+    fn shift_amount(&self) -> u32 {
+        self.bi & SHIFT_AMOUNT_MASK
     }
 
     /// Right logical shift of the input latches. Return the result.
-    /// Use `self.ai` as the value to shift, and `self.bi` and the amount to shift by.
+    /// Use `self.ai` as the value to shift, and the low 5 bits of `self.bi` as the amount to shift by.
     pub fn shift_right_logical(&self) -> u32 {
-        self.ai >> self.bi
+        self.ai >> self.shift_amount()
     }
 
     /// Right logical shift of the input latches. Return the result and the SCC values.
-    /// Use `self.ai` as the value to shift, and `self.bi` and the amount to shift by.
+    /// Use `self.ai` as the value to shift, and the low 5 bits of `self.bi` as the amount to shift by.
     /// For bitwise operators the SCC bits are as follows:
     /// v = false
     /// c = false
@@ -328,13 +307,13 @@ impl ALU {
     }
 
     /// Right arithmetic shift of the input latches. Return the result.
-    /// Use `self.ai` as the value to shift, and `self.bi` and the amount to shift by.
+    /// Use `self.ai` as the value to shift, and the low 5 bits of `self.bi` as the amount to shift by.
     pub fn shift_right_arithmetic(&self) -> u32 {
-        ((self.ai as i32) >> self.bi) as u32
+        ((self.ai as i32) >> self.shift_amount()) as u32
     }
 
-    /// Right logical shift of the input latches. Return the result and the SCC values.
-    /// Use `self.ai` as the value to shift, and `self.bi` and the amount to shift by.
+    /// Right arithmetic shift of the input latches. Return the result and the SCC values.
+    /// Use `self.ai` as the value to shift, and the low 5 bits of `self.bi` as the amount to shift by.
     /// For bitwise operators the SCC bits are as follows:
     /// v = false
     /// c = false
@@ -353,14 +332,14 @@ impl ALU {
         )
     }
 
-    /// Left arithmetic shift of the input latches. Return the result.
-    /// Use `self.ai` as the value to shift, and `self.bi` and the amount to shift by.
+    /// Left logical shift of the input latches. Return the result.
+    /// Use `self.ai` as the value to shift, and the low 5 bits of `self.bi` as the amount to shift by.
     pub fn shift_left_logical(&self) -> u32 {
-        self.ai << self.bi
+        self.ai << self.shift_amount()
     }
 
-    /// Right logical shift of the input latches. Return the result and the SCC values.
-    /// Use `self.ai` as the value to shift, and `self.bi` and the amount to shift by.
+    /// Left logical shift of the input latches. Return the result and the SCC values.
+    /// Use `self.ai` as the value to shift, and the low 5 bits of `self.bi` as the amount to shift by.
     /// For bitwise operators the SCC bits are as follows:
     /// v = false
     /// c = false
@@ -378,6 +357,97 @@ impl ALU {
             },
         )
     }
+
+    // Multiply and divide, for the multiply/divide extension.
+
+    /// Get the SCC values of a multiply or divide.
+    /// # Arguments
+    /// * `result` - The result.
+    /// * `overflow` - True if the result did not fit or the divisor was 0.
+    fn muldiv_scc(result: u32, overflow: bool) -> (u32, SCCBits) {
+        (
+            result,
+            SCCBits {
+                z: result == 0,
+                n: result & SIGN_BIT_LOC != 0,
+                v: overflow,
+                c: false,
+            },
+        )
+    }
+
+    /// Multiply the values in the input latches, return the low word of the
+    /// product and the SCC values.
+    /// v = The signed product does not fit in 32 bits
+    /// c = false
+    /// z = result == 0
+    /// n = result as i32 < 0
+    pub fn mul_scc(&self) -> (u32, SCCBits) {
+        let product = (self.ai as i32 as i64) * (self.bi as i32 as i64);
+        Self::muldiv_scc(product as u32, product != product as i32 as i64)
+    }
+
+    /// Multiply the values in the input latches as signed, return the high
+    /// word of the product and the SCC values. V and C are cleared.
+    pub fn mulh_scc(&self) -> (u32, SCCBits) {
+        let product = (self.ai as i32 as i64) * (self.bi as i32 as i64);
+        Self::muldiv_scc((product >> 32) as u32, false)
+    }
+
+    /// Multiply the values in the input latches as unsigned, return the
+    /// high word of the product and the SCC values. V and C are cleared.
+    pub fn mulhu_scc(&self) -> (u32, SCCBits) {
+        let product = (self.ai as u64) * (self.bi as u64);
+        Self::muldiv_scc((product >> 32) as u32, false)
+    }
+
+    /// Divide the values in the input latches as signed, rounding towards
+    /// 0. Return the quotient and the SCC values.
+    /// Use `self.ai` as the dividend and `self.bi` as the divisor. Dividing
+    /// by 0 gives all ones and dividing the most negative number by -1 gives
+    /// it back, both set V.
+    pub fn div_scc(&self) -> (u32, SCCBits) {
+        match (self.ai as i32).checked_div(self.bi as i32) {
+            Some(q) => Self::muldiv_scc(q as u32, false),
+            None if self.bi == 0 => Self::muldiv_scc(u32::MAX, true),
+            None => Self::muldiv_scc(self.ai, true),
+        }
+    }
+
+    /// Divide the values in the input latches as unsigned. Return the
+    /// quotient and the SCC values.
+    /// Use `self.ai` as the dividend and `self.bi` as the divisor. Dividing
+    /// by 0 gives all ones and sets V.
+    pub fn divu_scc(&self) -> (u32, SCCBits) {
+        match self.ai.checked_div(self.bi) {
+            Some(q) => Self::muldiv_scc(q, false),
+            None => Self::muldiv_scc(u32::MAX, true),
+        }
+    }
+
+    /// Get the remainder of the signed division of the values in the input
+    /// latches, with the sign of the dividend, and the SCC values.
+    /// Use `self.ai` as the dividend and `self.bi` as the divisor. Dividing
+    /// by 0 gives the dividend and dividing the most negative number by -1
+    /// gives 0, both set V.
+    pub fn rem_scc(&self) -> (u32, SCCBits) {
+        match (self.ai as i32).checked_rem(self.bi as i32) {
+            Some(r) => Self::muldiv_scc(r as u32, false),
+            None if self.bi == 0 => Self::muldiv_scc(self.ai, true),
+            None => Self::muldiv_scc(0, true),
+        }
+    }
+
+    /// Get the remainder of the unsigned division of the values in the
+    /// input latches and the SCC values.
+    /// Use `self.ai` as the dividend and `self.bi` as the divisor. Dividing
+    /// by 0 gives the dividend and sets V.
+    pub fn remu_scc(&self) -> (u32, SCCBits) {
+        match self.ai.checked_rem(self.bi) {
+            Some(r) => Self::muldiv_scc(r, false),
+            None => Self::muldiv_scc(self.ai, true),
+        }
+    }
 }
 
 impl fmt::Display for ALU {
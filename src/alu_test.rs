@@ -0,0 +1,350 @@
+// Test code for the RISC II ALU.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "alu.rs"]
+mod test {
+    use super::super::*;
+    use alu::ALU;
+    use data_path::SCCBits;
+
+    /// Values around the boundaries of signed and unsigned arithmetic.
+    const BOUNDARIES: [u32; 7] = [0, 1, 2, 0x7ffffffe, 0x7fffffff, 0x80000000, 0xffffffff];
+
+    /// Number of random values each property test tries.
+    const ITERATIONS: usize = 100_000;
+
+    /// Xorshift generator, so every run tries the same values and failures
+    /// can be reproduced.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+    }
+
+    /// Compute `a + b + carry_in` (or `a - b - borrow_in`) in wider
+    /// arithmetic, return the result and the expected SCC bits.
+    fn expected(a: u32, b: u32, subtract: bool, carry: bool) -> (u32, bool, bool, bool, bool) {
+        let (wide, signed) = if subtract {
+            let borrow = !carry as i64;
+            (
+                a as i64 - b as i64 - borrow,
+                a as i32 as i64 - b as i32 as i64 - borrow,
+            )
+        } else {
+            (
+                a as i64 + b as i64 + carry as i64,
+                a as i32 as i64 + b as i32 as i64 + carry as i64,
+            )
+        };
+        let result = wide as u32;
+        let c = if subtract {
+            // NOT borrow.
+            wide >= 0
+        } else {
+            wide > u32::max_value() as i64
+        };
+        let v = signed != result as i32 as i64;
+        (result, result == 0, (result as i32) < 0, v, c)
+    }
+
+    fn check(
+        name: &str,
+        a: u32,
+        b: u32,
+        carry: bool,
+        got: (u32, SCCBits),
+        want: (u32, bool, bool, bool, bool),
+    ) {
+        let (result, scc) = got;
+        assert_eq!(
+            (result, scc.z, scc.n, scc.v, scc.c),
+            want,
+            "{} {:08x}, {:08x} carry {}",
+            name,
+            a,
+            b,
+            carry
+        );
+    }
+
+    #[test]
+    fn add_sub_boundaries() {
+        for &a in BOUNDARIES.iter() {
+            for &b in BOUNDARIES.iter() {
+                let alu = ALU { ai: a, bi: b };
+                check(
+                    "add",
+                    a,
+                    b,
+                    false,
+                    alu.add_scc(),
+                    expected(a, b, false, false),
+                );
+                check("sub", a, b, true, alu.sub_scc(), expected(a, b, true, true));
+                check(
+                    "subi",
+                    a,
+                    b,
+                    true,
+                    alu.subi_scc(),
+                    expected(b, a, true, true),
+                );
+                assert_eq!(alu.add(), a.wrapping_add(b));
+                assert_eq!(alu.sub(), a.wrapping_sub(b));
+                assert_eq!(alu.subi(), b.wrapping_sub(a));
+                for &carry in [false, true].iter() {
+                    check(
+                        "addc",
+                        a,
+                        b,
+                        carry,
+                        alu.addc_scc(carry),
+                        expected(a, b, false, carry),
+                    );
+                    check(
+                        "subc",
+                        a,
+                        b,
+                        carry,
+                        alu.subc_scc(carry),
+                        expected(a, b, true, carry),
+                    );
+                    check(
+                        "subci",
+                        a,
+                        b,
+                        carry,
+                        alu.subci_scc(carry),
+                        expected(b, a, true, carry),
+                    );
+                    assert_eq!(alu.addc(carry), expected(a, b, false, carry).0);
+                    assert_eq!(alu.subc(carry), expected(a, b, true, carry).0);
+                    assert_eq!(alu.subci(carry), expected(b, a, true, carry).0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn carry_chains() {
+        // 0x00000001_ffffffff + 0x00000000_00000001 = 0x00000002_00000000
+        let (low, scc) = ALU {
+            ai: 0xffffffff,
+            bi: 1,
+        }
+        .add_scc();
+        let high = ALU { ai: 1, bi: 0 }.addc(scc.c);
+        assert_eq!((high, low), (2, 0));
+
+        // 0x00000002_00000000 - 0x00000000_00000001 = 0x00000001_ffffffff
+        let (low, scc) = ALU { ai: 0, bi: 1 }.sub_scc();
+        assert!(!scc.c);
+        let high = ALU { ai: 2, bi: 0 }.subc(scc.c);
+        assert_eq!((high, low), (1, 0xffffffff));
+
+        // The same with the operands reversed.
+        let (low, scc) = ALU { ai: 1, bi: 0 }.subi_scc();
+        let high = ALU { ai: 0, bi: 2 }.subci(scc.c);
+        assert_eq!((high, low), (1, 0xffffffff));
+    }
+
+    #[test]
+    fn add_sub_match_wide_arithmetic() {
+        let mut rng = Rng(0x2545f491);
+        for _ in 0..ITERATIONS {
+            let (a, b, carry) = (rng.next(), rng.next(), rng.next() & 1 != 0);
+            let alu = ALU { ai: a, bi: b };
+            check(
+                "add",
+                a,
+                b,
+                false,
+                alu.add_scc(),
+                expected(a, b, false, false),
+            );
+            check(
+                "addc",
+                a,
+                b,
+                carry,
+                alu.addc_scc(carry),
+                expected(a, b, false, carry),
+            );
+            check("sub", a, b, true, alu.sub_scc(), expected(a, b, true, true));
+            check(
+                "subc",
+                a,
+                b,
+                carry,
+                alu.subc_scc(carry),
+                expected(a, b, true, carry),
+            );
+            check(
+                "subi",
+                a,
+                b,
+                true,
+                alu.subi_scc(),
+                expected(b, a, true, true),
+            );
+            check(
+                "subci",
+                a,
+                b,
+                carry,
+                alu.subci_scc(carry),
+                expected(b, a, true, carry),
+            );
+        }
+    }
+
+    #[test]
+    fn shifts_mask_their_amount() {
+        let mut rng = Rng(0x9e3779b9);
+        for _ in 0..ITERATIONS {
+            let (a, b) = (rng.next(), rng.next());
+            let alu = ALU { ai: a, bi: b };
+            let amount = b % 32;
+            let wide = (a as u64) << amount;
+            assert_eq!(alu.shift_left_logical(), wide as u32);
+            assert_eq!(alu.shift_right_logical(), ((a as u64) >> amount) as u32);
+            assert_eq!(
+                alu.shift_right_arithmetic(),
+                ((a as i32 as i64) >> amount) as u32
+            );
+            for (result, scc) in vec![
+                alu.shift_left_arithmetic_scc(),
+                alu.shift_right_logical_scc(),
+                alu.shift_right_arithmetic_scc(),
+            ] {
+                assert_eq!(
+                    (scc.z, scc.n, scc.v, scc.c),
+                    (result == 0, (result as i32) < 0, false, false)
+                );
+            }
+        }
+        assert_eq!(ALU { ai: 1, bi: 32 }.shift_left_logical(), 1);
+        assert_eq!(
+            ALU {
+                ai: 0x80000000,
+                bi: 63
+            }
+            .shift_right_arithmetic(),
+            0xffffffff
+        );
+    }
+
+    #[test]
+    fn mul_div_match_wide_arithmetic() {
+        let mut rng = Rng(0x6c078965);
+        for i in 0..ITERATIONS {
+            let a = rng.next();
+            let b = match i % 3 {
+                0 => rng.next(),
+                // Small divisors, so quotients are not all 0 or 1.
+                1 => rng.next() % 64,
+                _ => BOUNDARIES[i % BOUNDARIES.len()],
+            };
+            let alu = ALU { ai: a, bi: b };
+            let signed = a as i32 as i64 * b as i32 as i64;
+            let unsigned = a as u64 * b as u64;
+            assert_eq!(alu.mul_scc().0, signed as u32);
+            assert_eq!(alu.mul_scc().1.v, signed != signed as i32 as i64);
+            assert_eq!(alu.mulh_scc().0, (signed >> 32) as u32);
+            assert_eq!(alu.mulhu_scc().0, (unsigned >> 32) as u32);
+            if b != 0 && !(a == 0x80000000 && b == 0xffffffff) {
+                assert_eq!(alu.div_scc().0, (a as i32 / b as i32) as u32);
+                assert_eq!(alu.rem_scc().0, (a as i32 % b as i32) as u32);
+            }
+            if b != 0 {
+                assert_eq!(alu.divu_scc().0, a / b);
+                assert_eq!(alu.remu_scc().0, a % b);
+                // The quotient and remainder give the dividend back.
+                let (q, r) = (alu.div_scc().0, alu.rem_scc().0);
+                assert_eq!(q.wrapping_mul(b).wrapping_add(r), a);
+            }
+            for (result, scc) in vec![
+                alu.mul_scc(),
+                alu.mulh_scc(),
+                alu.mulhu_scc(),
+                alu.div_scc(),
+                alu.divu_scc(),
+                alu.rem_scc(),
+                alu.remu_scc(),
+            ] {
+                assert_eq!(
+                    (scc.z, scc.n, scc.c),
+                    (result == 0, (result as i32) < 0, false)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn div_by_zero_and_overflow_set_v() {
+        let by_zero = ALU { ai: 7, bi: 0 };
+        assert_eq!(
+            (by_zero.div_scc().0, by_zero.div_scc().1.v),
+            (0xffffffff, true)
+        );
+        assert_eq!(
+            (by_zero.divu_scc().0, by_zero.divu_scc().1.v),
+            (0xffffffff, true)
+        );
+        assert_eq!((by_zero.rem_scc().0, by_zero.rem_scc().1.v), (7, true));
+        assert_eq!((by_zero.remu_scc().0, by_zero.remu_scc().1.v), (7, true));
+
+        let overflow = ALU {
+            ai: 0x80000000,
+            bi: 0xffffffff,
+        };
+        assert_eq!(
+            (overflow.div_scc().0, overflow.div_scc().1.v),
+            (0x80000000, true)
+        );
+        assert_eq!((overflow.rem_scc().0, overflow.rem_scc().1.v), (0, true));
+        assert_eq!(overflow.divu_scc().1.v, false);
+        assert_eq!(
+            (overflow.mul_scc().0, overflow.mul_scc().1.v),
+            (0x80000000, true)
+        );
+        assert_eq!(
+            ALU {
+                ai: 0x10000,
+                bi: 0x8000
+            }
+            .mul_scc()
+            .1
+            .v,
+            true
+        );
+        assert_eq!(
+            ALU {
+                ai: 0x10000,
+                bi: 0x7fff
+            }
+            .mul_scc()
+            .1
+            .v,
+            false
+        );
+    }
+}
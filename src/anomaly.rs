@@ -0,0 +1,220 @@
+// Instruction stream anomaly detection.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Watches retired instructions for patterns that are usually bugs, in the
+// guest or in the emulator:
+//   Executing data - an instruction was fetched from a word that was
+//     written by a store.
+//   Livelock - an instruction ran more than a limit of times while nothing
+//     (no register, PSW or memory) changed. The conventional halt, a branch
+//     to itself, does not count.
+//   Repeated faults - the same instruction raised privilege violations
+//     several times.
+// Each anomaly is reported once per instruction, livelocks again after
+// another limit of quiet executions.
+
+use commit::RetiredInstruction;
+use cpu::{Trap, NUM_GLOBALS};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+
+/// Number of privilege violations an instruction may raise before it is
+/// reported.
+pub const FAULT_LIMIT: u64 = 3;
+
+/// Kinds of suspicious behaviour.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// The instruction was fetched from a word written by a store.
+    ExecutingData,
+    /// The instruction ran this many times without anything changing.
+    Livelock(u64),
+    /// The instruction raised this many privilege violations.
+    RepeatedFaults(u64),
+}
+
+/// Suspicious behaviour seen in the instruction stream.
+#[derive(Clone)]
+pub struct Anomaly {
+    /// What was seen.
+    pub kind: AnomalyKind,
+    /// The instruction that retired when it was seen.
+    pub retired: RetiredInstruction,
+}
+
+/// Looks for anomalies in the retired instruction stream.
+pub struct AnomalyDetector {
+    /// Quiet executions of an instruction before a livelock is reported.
+    livelock_limit: u64,
+    /// True if the system should pause when an anomaly is found.
+    pause: bool,
+    /// Word addresses written by stores.
+    written: HashSet<u32>,
+    /// Instructions already reported for executing data.
+    executed_data: HashSet<u32>,
+    /// Executions of each instruction since the state last changed.
+    quiet: HashMap<u32, u64>,
+    /// Last value written to each register, by window (0 for globals) and
+    /// register.
+    registers: HashMap<(u8, u8), u32>,
+    /// Privilege violations raised by each instruction.
+    faults: HashMap<u32, u64>,
+    /// Anomalies found so far, oldest first.
+    found: Vec<Anomaly>,
+}
+
+impl AnomalyDetector {
+    /// Create a detector.
+    /// # Arguments
+    /// * `livelock_limit` - Quiet executions of an instruction before a
+    /// livelock is reported.
+    /// * `pause` - True if the system should pause on an anomaly.
+    pub fn new(livelock_limit: u64, pause: bool) -> Self {
+        Self {
+            livelock_limit: livelock_limit.max(1),
+            pause: pause,
+            written: HashSet::new(),
+            executed_data: HashSet::new(),
+            quiet: HashMap::new(),
+            registers: HashMap::new(),
+            faults: HashMap::new(),
+            found: Vec::new(),
+        }
+    }
+
+    /// Check a retired instruction. Return the anomaly it shows, if any.
+    /// # Arguments
+    /// * `r` - The retired instruction.
+    pub fn observe(&mut self, r: &RetiredInstruction) -> Option<Anomaly> {
+        let kind = self.check(r)?;
+        let anomaly = Anomaly {
+            kind: kind,
+            retired: r.clone(),
+        };
+        self.found.push(anomaly.clone());
+        Some(anomaly)
+    }
+
+    /// True if the system should pause when an anomaly is found.
+    pub fn pauses(&self) -> bool {
+        self.pause
+    }
+
+    /// Get the anomalies found so far, oldest first.
+    pub fn found(&self) -> &[Anomaly] {
+        &self.found
+    }
+
+    /// Estimate the host memory taken by what the detector keeps, in bytes.
+    pub fn heap_size(&self) -> u64 {
+        ((self.written.len() + self.executed_data.len()) * mem::size_of::<u32>()
+            + (self.quiet.len() + self.faults.len()) * mem::size_of::<(u32, u64)>()
+            + self.registers.len() * mem::size_of::<((u8, u8), u32)>()
+            + self.found.len() * mem::size_of::<Anomaly>()) as u64
+    }
+
+    /// Forget everything learned about the guest, keeping the anomalies
+    /// found so far. Anomalies that build on history, e.g. executing a word
+    /// stored before, go unnoticed until it builds up again.
+    pub fn forget(&mut self) {
+        self.written = HashSet::new();
+        self.executed_data = HashSet::new();
+        self.quiet = HashMap::new();
+        self.registers = HashMap::new();
+        self.faults = HashMap::new();
+    }
+
+    fn check(&mut self, r: &RetiredInstruction) -> Option<AnomalyKind> {
+        let executed_data =
+            self.written.contains(&(r.pc & !0x3)) && self.executed_data.insert(r.pc);
+
+        let changed = self.changes_state(r);
+        if let Some(access) = r.access.filter(|a| a.store) {
+            for addr in access.address..access.address + access.width {
+                self.written.insert(addr & !0x3);
+            }
+        }
+
+        if executed_data {
+            return Some(AnomalyKind::ExecutingData);
+        }
+
+        if r.trap == Some(Trap::PrivilegeViolation) {
+            let count = self.faults.entry(r.pc).or_insert(0);
+            *count += 1;
+            if *count == FAULT_LIMIT {
+                return Some(AnomalyKind::RepeatedFaults(*count));
+            }
+        }
+
+        if changed || r.branch == Some(r.pc) {
+            self.quiet.clear();
+            return None;
+        }
+        let count = self.quiet.entry(r.pc).or_insert(0);
+        *count += 1;
+        if *count > self.livelock_limit {
+            let count = *count;
+            self.quiet.clear();
+            return Some(AnomalyKind::Livelock(count));
+        }
+        None
+    }
+
+    /// Return true if `r` changed a register, the PSW or memory.
+    fn changes_state(&mut self, r: &RetiredInstruction) -> bool {
+        if r.psw_before.get() != r.psw_after.get() || r.trap.is_some() {
+            return true;
+        }
+        if r.access.map_or(false, |a| a.store) {
+            return true;
+        }
+        match r.dest {
+            // r0 is always 0.
+            Some((0, _)) | None => false,
+            Some((rd, value)) => {
+                // Globals are shared by every window.
+                let window = if (rd as usize) < NUM_GLOBALS {
+                    0
+                } else {
+                    r.psw_after.get_cwp()
+                };
+                self.registers.insert((window, rd), value) != Some(value)
+            }
+        }
+    }
+}
+
+impl fmt::Display for AnomalyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::ExecutingData => write!(f, "executing a word written as data"),
+            Self::Livelock(count) => write!(f, "ran {} times without changing anything", count),
+            Self::RepeatedFaults(count) => write!(f, "raised {} privilege violations", count),
+        }
+    }
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.retired;
+        write!(
+            f,
+            "Anomaly at pc 0x{:08x} (cycle {}): {}\n  {}\n  PSW: {}",
+            r.pc, r.cycle, self.kind, r.instruction, r.psw_after
+        )
+    }
+}
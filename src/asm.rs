@@ -0,0 +1,869 @@
+// Assembler for RISC II guest programs.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Source is one statement per line and `;` starts a comment. A statement may
+// be preceded by labels (`name:`). Instructions use the disassembler's
+// syntax, e.g. `add.cc r16, r0, 0x2`, `jmpx alw, r0, loop` or
+// `callr ra, func`. A label given to a PC relative instruction (callr, jmpr,
+// ldr* and str*) assembles to its offset from the instruction, anywhere else
+// to its address. Extension instructions are written `ext 0x40, rd, rs1, s2`,
+// or by name for the multiply/divide extension, e.g. `mul rd, rs1, s2`.
+// Immediates may be written with a leading `#`, e.g. `add r5, r4, #0x1f69`.
+// `assemble_line` assembles a single instruction without labels, for
+// poking instructions into memory from the debugger.
+//
+// Directives:
+//   .word v, ...        Emit words.
+//   .proc name[, size]  Start procedure `name` with the standard prologue,
+//                       which points sp `size` bytes below fp.
+//   .endproc            End the procedure with the standard epilogue, which
+//                       returns to ra + 8 (past the call and its delay slot).
+//
+// A procedure is called with `callr ra, name` (or `callx ra, ...`) followed
+// by a delay slot, so ra, sp and fp of every window follow the conventions
+// the backtrace relies on.
+//
+// With `Options` the assembler can add the delay slots itself, as the RISC-II
+// compilers did: the source then has no delay slots and every call, jump and
+// return gets a NOP, or where it is safe the instruction before a jump moves
+// into the slot. Branch targets can also be aligned with NOPs. The program's
+// `Report` counts what was added and moved.
+
+use config::{Endian, Syntax};
+use cpu::{register_from_name, FRAME_POINTER_REG, RETURN_ADDRESS_REG, STACK_POINTER_REG};
+use decode::decode;
+use instruction::{
+    Conditional, Instruction, LongConditional, LongInstruction, ShortConditional, ShortInstruction,
+    ShortSource, EXTENSION_OPCODE,
+};
+use memory::Memory;
+use muldiv::{
+    DIVU_OPCODE, DIV_OPCODE, MULHU_OPCODE, MULH_OPCODE, MUL_OPCODE, REMU_OPCODE, REM_OPCODE,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use symbols::Symbols;
+use util::Result;
+
+use berr;
+
+/// An assembled program.
+pub struct Program {
+    /// Address the program is loaded at.
+    pub origin: u32,
+    /// Words of the program, in order.
+    pub words: Vec<u32>,
+    /// Addresses of the program's labels.
+    pub labels: HashMap<String, u32>,
+    /// What was done to fill delay slots and align branch targets.
+    pub report: Report,
+}
+
+/// Operand layout of an instruction.
+enum Format {
+    /// `rd, rs1, source`.
+    Short(fn(ShortInstruction) -> Instruction),
+    /// `rd, imm19`. True if the immediate is relative to the PC.
+    Long(fn(LongInstruction) -> Instruction, bool),
+    /// `cond, rs1, source`.
+    ShortCond(fn(ShortConditional) -> Instruction),
+    /// `cond, imm19`, relative to the PC.
+    LongCond(fn(LongConditional) -> Instruction),
+    /// `opcode, rd, rs1, source`, an instruction of an ISA extension.
+    Extension,
+    /// `rd, rs1, source`, an instruction of an ISA extension with a
+    /// mnemonic of its own, by its opcode.
+    NamedExtension(u8),
+}
+
+/// Options controlling how the assembler lays out a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Options {
+    /// Give every control transfer a delay slot, so the source must not
+    /// have any.
+    pub fill_delay_slots: bool,
+    /// When filling delay slots, move the instruction before a jump into
+    /// its slot where that is safe and only add a NOP otherwise.
+    pub schedule_delay_slots: bool,
+    /// Boundary (in bytes) to align branch targets to with NOPs, 0 for no
+    /// alignment.
+    pub align_targets: u32,
+}
+
+/// What the assembler added or moved to fill delay slots and align branch
+/// targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Report {
+    /// Delay slots filled with a NOP.
+    pub padded_slots: usize,
+    /// Delay slots filled with the instruction before the jump.
+    pub scheduled_slots: usize,
+    /// NOPs added to align branch targets.
+    pub alignment_nops: usize,
+}
+
+/// A statement that assembles to one word.
+enum Statement {
+    Word(String),
+    Instruction(String, Vec<String>),
+}
+
+/// A statement and where it came from.
+struct Item {
+    /// Source line of the statement.
+    line_no: usize,
+    /// Labels pointing at the statement.
+    labels: Vec<String>,
+    statement: Statement,
+}
+
+impl Program {
+    /// Write the program into memory at its origin. Return void on success
+    /// and a string on error.
+    /// # Arguments
+    /// * `mem` - Memory to write to.
+    pub fn load(&self, mem: &mut Memory) -> Result<()> {
+        for (i, word) in self.words.iter().enumerate() {
+            mem.set_word(self.origin + i as u32 * 4, *word)?;
+        }
+        Ok(())
+    }
+
+    /// Get the program's labels as a symbol table.
+    pub fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (name, address) in self.labels.iter() {
+            symbols.insert(name, *address);
+        }
+        symbols
+    }
+
+    /// Get the program as a memory image.
+    /// # Arguments
+    /// * `endian` - Byte order of the image's words.
+    pub fn to_bytes(&self, endian: Endian) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|w| endian.word_bytes(*w).to_vec())
+            .collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Delay slots: {} padded with NOPs, {} scheduled. Alignment NOPs: {}.",
+            self.padded_slots, self.scheduled_slots, self.alignment_nops
+        )
+    }
+}
+
+// Public functions.
+
+/// Assemble a program with the default options. Return the program on
+/// success and a string naming the offending line on error.
+/// # Arguments
+/// * `source` - Assembly source.
+/// * `origin` - Address the program will be loaded at.
+pub fn assemble(source: &str, origin: u32) -> Result<Program> {
+    assemble_with(source, origin, &Options::default())
+}
+
+/// Assemble a single instruction, e.g. `add r5, r4, #0x1f69`. There are no
+/// labels, PC relative instructions take their offset. Return the
+/// instruction on success and a string on error.
+/// # Arguments
+/// * `line` - The instruction, optionally followed by a comment.
+pub fn assemble_line(line: &str) -> Result<Instruction> {
+    let text = line.split(';').next().unwrap_or("").trim();
+    if text.is_empty() {
+        return berr!(Asm, "expected an instruction");
+    }
+    let (mnemonic, operands) = split_statement(text);
+    assemble_instruction(&mnemonic.to_lowercase(), &operands, 0, &HashMap::new())
+}
+
+/// Disassemble a memory image. In the modern syntax the result is
+/// source the assembler accepts. Words that are not instructions become
+/// `.word` directives and symbols become labels. Every line ends with a
+/// comment giving the word's address and value, the symbol a branch or
+/// load targets if there is one close to it, and `(delay slot)` if the word
+/// follows a jump, call or return.
+/// # Arguments
+/// * `image` - The memory image. A trailing partial word is ignored.
+/// * `origin` - Address the image is loaded at.
+/// * `syntax` - Syntax to disassemble in.
+/// * `symbols` - Symbols of the program.
+/// * `endian` - Byte order of the image's words.
+pub fn disassemble(
+    image: &[u8],
+    origin: u32,
+    syntax: Syntax,
+    symbols: &Symbols,
+    endian: Endian,
+) -> String {
+    let mut result = String::new();
+    let mut in_delay_slot = false;
+    for (i, bytes) in image.chunks_exact(4).enumerate() {
+        let word = endian.word([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let addr = origin.wrapping_add(i as u32 * 4);
+        if let Some(name) = symbols.at(addr) {
+            result.push_str(&format!("{}:\n", name));
+        }
+        let (text, target, transfer) = match decode(word) {
+            Ok(instruction) => (
+                format!("{}", instruction.disassemble(syntax)),
+                target_address(&instruction, addr).and_then(|t| symbols.describe(t)),
+                instruction.is_transfer(),
+            ),
+            Err(_) => (format!(".word 0x{:08x}", word), None, false),
+        };
+        result.push_str(&format!("    {:<32} ; {:08x}: {:08x}", text, addr, word));
+        if let Some(target) = target {
+            result.push_str(&format!(" <{}>", target));
+        }
+        if in_delay_slot {
+            result.push_str(" (delay slot)");
+        }
+        result.push('\n');
+        in_delay_slot = transfer;
+    }
+    result
+}
+
+/// Assemble a program. Return the program on success and a string naming
+/// the offending line on error.
+/// # Arguments
+/// * `source` - Assembly source.
+/// * `origin` - Address the program will be loaded at.
+/// * `options` - How to lay out the program.
+pub fn assemble_with(source: &str, origin: u32, options: &Options) -> Result<Program> {
+    let align = options.align_targets;
+    if align != 0 && (align % 4 != 0 || !align.is_power_of_two()) {
+        return berr!(Asm, format!(
+            "Branch targets can not be aligned to {} bytes, it must be a power of two multiple of 4",
+            align
+        ));
+    }
+
+    let (mut items, trailing_labels) = parse(source, options.fill_delay_slots)?;
+    let mut report = Report::default();
+    if options.fill_delay_slots {
+        items = fill_delay_slots(items, options.schedule_delay_slots, &mut report);
+    }
+    if align > 4 {
+        items = align_targets(items, origin, align, &mut report);
+    }
+
+    let mut labels = HashMap::new();
+    for (i, item) in items.iter().enumerate() {
+        for label in item.labels.iter() {
+            labels.insert(label.clone(), origin + i as u32 * 4);
+        }
+    }
+    for label in trailing_labels {
+        labels.insert(label, origin + items.len() as u32 * 4);
+    }
+
+    // Encode now that every label is known.
+    let mut words = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        let pc = origin + i as u32 * 4;
+        let word = match item.statement {
+            Statement::Word(ref v) => value(v, &labels, None).and_then(|v| fit(v, 32)),
+            Statement::Instruction(ref mnemonic, ref operands) => {
+                assemble_instruction(mnemonic, operands, pc, &labels).map(|i| i.encode())
+            }
+        };
+        match word {
+            Ok(w) => words.push(w),
+            Err(e) => return berr!(Asm, format!("line {}: {}", item.line_no, e)),
+        }
+    }
+    Ok(Program {
+        origin: origin,
+        words: words,
+        labels: labels,
+        report: report,
+    })
+}
+
+// Private functions.
+
+/// Split source into statements, expanding directives. Return the statements
+/// and the labels after the last statement on success, and a string on
+/// error.
+/// # Arguments
+/// * `source` - Assembly source.
+/// * `fill_delay_slots` - True if the assembler fills delay slots, so
+///   generated code must not have its own.
+fn parse(source: &str, fill_delay_slots: bool) -> Result<(Vec<Item>, Vec<String>)> {
+    let mut items = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = Vec::new();
+    let mut proc_name: Option<String> = None;
+    for (n, line) in source.lines().enumerate() {
+        let line_no = n + 1;
+        let mut text = line.split(';').next().unwrap_or("").trim();
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) {
+                break;
+            }
+            define_label(&mut seen, &mut pending, label, line_no)?;
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = split_statement(text);
+        let mnemonic = mnemonic.to_lowercase();
+        let mut statements = Vec::new();
+        match mnemonic.as_str() {
+            ".word" => {
+                for op in operands {
+                    statements.push(Statement::Word(op));
+                }
+            }
+            ".proc" => {
+                if let Some(name) = proc_name {
+                    return berr!(
+                        Asm,
+                        format!("line {}: .proc inside procedure {}", line_no, name)
+                    );
+                }
+                let (name, size) = match operands.as_slice() {
+                    [name] => (name.clone(), "0".to_string()),
+                    [name, size] => (name.clone(), size.clone()),
+                    _ => {
+                        return berr!(
+                            Asm,
+                            format!("line {}: expected .proc name[, size]", line_no)
+                        )
+                    }
+                };
+                define_label(&mut seen, &mut pending, &name, line_no)?;
+                statements = prologue(&size);
+                proc_name = Some(name);
+            }
+            ".endproc" => {
+                if proc_name.take().is_none() {
+                    return berr!(
+                        Asm,
+                        format!("line {}: .endproc outside a procedure", line_no)
+                    );
+                }
+                statements = epilogue(!fill_delay_slots);
+            }
+            _ if mnemonic.starts_with('.') => {
+                return berr!(
+                    Asm,
+                    format!("line {}: unknown directive {}", line_no, mnemonic)
+                )
+            }
+            _ => statements.push(Statement::Instruction(mnemonic, operands)),
+        }
+        for statement in statements {
+            items.push(Item {
+                line_no: line_no,
+                labels: mem::replace(&mut pending, Vec::new()),
+                statement: statement,
+            });
+        }
+    }
+    if let Some(name) = proc_name {
+        return berr!(Asm, format!("Procedure {} is missing .endproc", name));
+    }
+    Ok((items, pending))
+}
+
+/// Split a statement into its mnemonic and operands.
+/// # Arguments
+/// * `text` - The statement, without labels or comment.
+fn split_statement(text: &str) -> (&str, Vec<String>) {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    };
+    let operands = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(|s| s.trim().to_string()).collect()
+    };
+    (mnemonic, operands)
+}
+
+/// Give every control transfer a delay slot.
+/// # Arguments
+/// * `items` - Statements without delay slots.
+/// * `schedule` - True to move the instruction before a jump into its
+///   slot where that is safe.
+/// * `report` - Report to count the filled slots in.
+fn fill_delay_slots(items: Vec<Item>, schedule: bool, report: &mut Report) -> Vec<Item> {
+    let mut result: Vec<Item> = Vec::with_capacity(items.len());
+    for mut item in items {
+        if !is_transfer(&item.statement) {
+            result.push(item);
+        } else if schedule && can_schedule(&result, &item) {
+            // Jumping to the moved instruction's labels still runs it before
+            // the jump takes effect.
+            let mut moved = result.pop().unwrap();
+            item.labels = mem::replace(&mut moved.labels, Vec::new());
+            result.push(item);
+            result.push(moved);
+            report.scheduled_slots += 1;
+        } else {
+            let line_no = item.line_no;
+            result.push(item);
+            result.push(Item {
+                line_no: line_no,
+                labels: Vec::new(),
+                statement: nop(),
+            });
+            report.padded_slots += 1;
+        }
+    }
+    result
+}
+
+/// Check that the last statement of `before` can move into the delay slot
+/// of `jump`. It must be an ordinary instruction that is not itself in a
+/// delay slot, does not set the CC's or touch the PSW, and does not write a
+/// register the jump reads. The jump must not be a call or return, which
+/// change windows, and must not be the target of a branch.
+/// # Arguments
+/// * `before` - Statements before the jump.
+/// * `jump` - The jump.
+fn can_schedule(before: &[Item], jump: &Item) -> bool {
+    let (jump_name, jump_operands) = match jump.statement {
+        Statement::Instruction(ref m, ref ops) => (base_mnemonic(m).0, ops),
+        _ => return false,
+    };
+    if (jump_name != "jmpx" && jump_name != "jmpr") || !jump.labels.is_empty() {
+        return false;
+    }
+    let (prev, operands) = match before.last().map(|i| &i.statement) {
+        Some(Statement::Instruction(m, ops)) => (m, ops),
+        _ => return false,
+    };
+    if before.len() >= 2 && is_transfer(&before[before.len() - 2].statement) {
+        return false;
+    }
+    let (name, scc) = base_mnemonic(prev);
+    let ordinary = match format_of(name) {
+        Some(Format::Short(_)) | Some(Format::Long(_, _)) => true,
+        _ => false,
+    };
+    if !ordinary || scc || ["calli", "getpsw", "getlpc", "gettcr", "putpsw"].contains(&name) {
+        return false;
+    }
+    match operands.first().and_then(|op| register_from_name(op)) {
+        Some(written) if written != 0 => !jump_operands
+            .iter()
+            .skip(1)
+            .any(|op| register_from_name(op) == Some(written)),
+        _ => true,
+    }
+}
+
+/// Pad with NOPs so that every branch target starts on an `align` byte
+/// boundary. Targets in delay slots are left alone, as padding would take
+/// their place in the slot.
+/// # Arguments
+/// * `items` - Statements to align.
+/// * `origin` - Address the program will be loaded at.
+/// * `align` - Boundary to align to.
+/// * `report` - Report to count the added NOPs in.
+fn align_targets(items: Vec<Item>, origin: u32, align: u32, report: &mut Report) -> Vec<Item> {
+    let mut targets = HashSet::new();
+    for item in items.iter() {
+        if let Statement::Instruction(_, ref operands) = item.statement {
+            if is_transfer(&item.statement) {
+                targets.extend(operands.iter().cloned());
+            }
+        }
+    }
+
+    let mut result: Vec<Item> = Vec::with_capacity(items.len());
+    for item in items {
+        let is_target = item.labels.iter().any(|l| targets.contains(l));
+        let in_slot = result.last().map_or(false, |i| is_transfer(&i.statement));
+        if is_target && !in_slot {
+            while (origin + result.len() as u32 * 4) % align != 0 {
+                result.push(Item {
+                    line_no: item.line_no,
+                    labels: Vec::new(),
+                    statement: nop(),
+                });
+                report.alignment_nops += 1;
+            }
+        }
+        result.push(item);
+    }
+    result
+}
+
+/// Standard procedure prologue: point sp `size` bytes below fp (the
+/// caller's sp) to make room for the procedure's frame.
+/// # Arguments
+/// * `size` - Size of the frame in bytes.
+fn prologue(size: &str) -> Vec<Statement> {
+    vec![Statement::Instruction(
+        "sub".to_string(),
+        vec![
+            reg_operand(STACK_POINTER_REG),
+            reg_operand(FRAME_POINTER_REG),
+            size.to_string(),
+        ],
+    )]
+}
+
+/// Standard procedure epilogue: return past the call and its delay slot.
+/// # Arguments
+/// * `with_slot` - True to follow the return with a NOP in its delay slot.
+fn epilogue(with_slot: bool) -> Vec<Statement> {
+    let mut result = vec![Statement::Instruction(
+        "ret".to_string(),
+        vec![
+            "alw".to_string(),
+            reg_operand(RETURN_ADDRESS_REG),
+            "8".to_string(),
+        ],
+    )];
+    if with_slot {
+        result.push(nop());
+    }
+    result
+}
+
+fn nop() -> Statement {
+    Statement::Instruction(
+        "add".to_string(),
+        vec!["r0".to_string(), "r0".to_string(), "r0".to_string()],
+    )
+}
+
+fn reg_operand(reg: u8) -> String {
+    format!("r{}", reg)
+}
+
+/// Split the `.cc` suffix off a mnemonic. Return the bare mnemonic and true
+/// if it had the suffix.
+fn base_mnemonic(mnemonic: &str) -> (&str, bool) {
+    match mnemonic.strip_suffix(".cc") {
+        Some(name) => (name, true),
+        None => (mnemonic, false),
+    }
+}
+
+/// True if `statement` is an instruction with a delay slot.
+fn is_transfer(statement: &Statement) -> bool {
+    match statement {
+        Statement::Instruction(m, _) => {
+            ["callx", "callr", "jmpx", "jmpr", "ret", "reti"].contains(&base_mnemonic(m).0)
+        }
+        _ => false,
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+/// Add a label to the labels waiting for the next statement, rejecting
+/// duplicates and names that are not identifiers.
+fn define_label(
+    seen: &mut HashSet<String>,
+    pending: &mut Vec<String>,
+    name: &str,
+    line_no: usize,
+) -> Result<()> {
+    if !is_identifier(name) {
+        return berr!(Asm, format!("line {}: invalid label {}", line_no, name));
+    }
+    if !seen.insert(name.to_string()) {
+        return berr!(
+            Asm,
+            format!("line {}: label {} defined twice", line_no, name)
+        );
+    }
+    pending.push(name.to_string());
+    Ok(())
+}
+
+/// Get the operand layout of a mnemonic (without `.cc`).
+fn format_of(mnemonic: &str) -> Option<Format> {
+    type I = Instruction;
+    type F = Format;
+    Some(match mnemonic {
+        "calli" => F::Short(I::Calli),
+        "getpsw" => F::Short(I::GetPSW),
+        "getlpc" => F::Short(I::GetLPC),
+        "gettcr" => F::Short(I::GetTCR),
+        "putpsw" => F::Short(I::PutPSW),
+        "callx" => F::Short(I::Callx),
+        "callr" => F::Long(I::Callr, true),
+        "jmpx" => F::ShortCond(I::Jmpx),
+        "jmpr" => F::LongCond(I::Jmpr),
+        "ret" => F::ShortCond(I::Ret),
+        "reti" => F::ShortCond(I::Reti),
+        "sll" => F::Short(I::Sll),
+        "srl" => F::Short(I::Srl),
+        "sra" => F::Short(I::Sra),
+        "or" => F::Short(I::Or),
+        "and" => F::Short(I::And),
+        "xor" => F::Short(I::Xor),
+        "add" => F::Short(I::Add),
+        "addc" => F::Short(I::Addc),
+        "sub" => F::Short(I::Sub),
+        "subc" => F::Short(I::Subc),
+        "subi" => F::Short(I::Subi),
+        "subci" => F::Short(I::Subci),
+        "ldhi" => F::Long(I::Ldhi, false),
+        "ldxw" => F::Short(I::Ldxw),
+        "ldrw" => F::Long(I::Ldrw, true),
+        "ldxhs" => F::Short(I::Ldxhs),
+        "ldrhs" => F::Long(I::Ldrhs, true),
+        "ldxhu" => F::Short(I::Ldxhu),
+        "ldrhu" => F::Long(I::Ldrhu, true),
+        "ldxbs" => F::Short(I::Ldxbs),
+        "ldrbs" => F::Long(I::Ldrbs, true),
+        "ldxbu" => F::Short(I::Ldxbu),
+        "ldrbu" => F::Long(I::Ldrbu, true),
+        "stxw" => F::Short(I::Stxw),
+        "strw" => F::Long(I::Strw, true),
+        "stxh" => F::Short(I::Stxh),
+        "strh" => F::Long(I::Strh, true),
+        "stxb" => F::Short(I::Stxb),
+        "strb" => F::Long(I::Strb, true),
+        "ext" => F::Extension,
+        "mul" => F::NamedExtension(MUL_OPCODE),
+        "mulh" => F::NamedExtension(MULH_OPCODE),
+        "mulhu" => F::NamedExtension(MULHU_OPCODE),
+        "div" => F::NamedExtension(DIV_OPCODE),
+        "divu" => F::NamedExtension(DIVU_OPCODE),
+        "rem" => F::NamedExtension(REM_OPCODE),
+        "remu" => F::NamedExtension(REMU_OPCODE),
+        _ => return None,
+    })
+}
+
+/// Assemble one instruction.
+/// # Arguments
+/// * `mnemonic` - Mnemonic, with `.cc` if it sets the CC's.
+/// * `operands` - Operands, in disassembly order.
+/// * `pc` - Address of the instruction.
+/// * `labels` - Addresses of every label.
+fn assemble_instruction(
+    mnemonic: &str,
+    operands: &[String],
+    pc: u32,
+    labels: &HashMap<String, u32>,
+) -> Result<Instruction> {
+    let (name, scc) = base_mnemonic(mnemonic);
+    let format = match format_of(name) {
+        Some(f) => f,
+        None => return berr!(Asm, format!("unknown instruction {}", mnemonic)),
+    };
+    let expected = match format {
+        Format::Short(_) | Format::ShortCond(_) | Format::NamedExtension(_) => 3,
+        Format::Long(_, _) | Format::LongCond(_) => 2,
+        Format::Extension => 4,
+    };
+    if operands.len() != expected {
+        return berr!(
+            Asm,
+            format!(
+                "{} takes {} operands, got {}",
+                name,
+                expected,
+                operands.len()
+            )
+        );
+    }
+    let instruction = match format {
+        Format::Short(make) => make(ShortInstruction::new(
+            scc,
+            register(&operands[0])?,
+            register(&operands[1])?,
+            short_source(&operands[2], labels)?,
+        )),
+        Format::Long(make, relative) => make(LongInstruction::new(
+            scc,
+            register(&operands[0])?,
+            imm19(&operands[1], labels, if relative { Some(pc) } else { None })?,
+        )),
+        Format::ShortCond(make) => make(ShortConditional::new(
+            scc,
+            conditional(&operands[0])?,
+            register(&operands[1])?,
+            short_source(&operands[2], labels)?,
+        )),
+        Format::LongCond(make) => make(LongConditional::new(
+            scc,
+            conditional(&operands[0])?,
+            imm19(&operands[1], labels, Some(pc))?,
+        )),
+        Format::Extension => Instruction::Extension(
+            extension_opcode(&operands[0], labels)?,
+            ShortInstruction::new(
+                scc,
+                register(&operands[1])?,
+                register(&operands[2])?,
+                short_source(&operands[3], labels)?,
+            ),
+        ),
+        Format::NamedExtension(op) => Instruction::Extension(
+            op,
+            ShortInstruction::new(
+                scc,
+                register(&operands[0])?,
+                register(&operands[1])?,
+                short_source(&operands[2], labels)?,
+            ),
+        ),
+    };
+    // Loads and stores read their immediate signed.
+    Ok(instruction.with_immediate_sign())
+}
+
+fn register(operand: &str) -> Result<u8> {
+    match register_from_name(operand) {
+        Some(r) => Ok(r),
+        None => berr!(Asm, format!("expected a register, got {}", operand)),
+    }
+}
+
+/// Parse an opcode of the extension opcode space.
+fn extension_opcode(operand: &str, labels: &HashMap<String, u32>) -> Result<u8> {
+    match value(operand, labels, None)? {
+        v if v >= EXTENSION_OPCODE as i64 && v <= 0x7f => Ok(v as u8),
+        _ => berr!(Asm, format!("{} is not an extension opcode", operand)),
+    }
+}
+
+fn conditional(operand: &str) -> Result<Conditional> {
+    match Conditional::from_mnemonic(&operand.to_lowercase()) {
+        Some(c) => Ok(c),
+        None => berr!(Asm, format!("expected a conditional, got {}", operand)),
+    }
+}
+
+/// Parse a register or a 13 bit immediate.
+fn short_source(operand: &str, labels: &HashMap<String, u32>) -> Result<ShortSource> {
+    if let Some(r) = register_from_name(operand) {
+        return Ok(ShortSource::Reg(r));
+    }
+    Ok(ShortSource::Imm13(fit(value(operand, labels, None)?, 13)?))
+}
+
+/// Parse a 19 bit immediate.
+/// # Arguments
+/// * `operand` - Number or label.
+/// * `labels` - Addresses of every label.
+/// * `pc` - Address labels are relative to, None if they are absolute.
+fn imm19(operand: &str, labels: &HashMap<String, u32>, pc: Option<u32>) -> Result<u32> {
+    fit(value(operand, labels, pc)?, 19)
+}
+
+/// Parse a number or a label.
+/// # Arguments
+/// * `operand` - Number or label.
+/// * `labels` - Addresses of every label.
+/// * `pc` - Address labels are relative to, None if they are absolute.
+fn value(operand: &str, labels: &HashMap<String, u32>, pc: Option<u32>) -> Result<i64> {
+    let operand = operand.strip_prefix('#').unwrap_or(operand);
+    if let Some(address) = labels.get(operand) {
+        return Ok(*address as i64 - pc.map_or(0, |pc| pc as i64));
+    }
+    let (negative, digits) = match operand.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, operand),
+    };
+    let lower = digits.to_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        lower.parse::<i64>()
+    };
+    match parsed {
+        Ok(v) if negative => Ok(-v),
+        Ok(v) => Ok(v),
+        Err(_) if is_identifier(operand) => berr!(Asm, format!("undefined label {}", operand)),
+        Err(_) => berr!(Asm, format!("invalid number {}", operand)),
+    }
+}
+
+/// Check that a value fits in `bits` bits, signed or unsigned, and truncate
+/// it to them.
+fn fit(v: i64, bits: u32) -> Result<u32> {
+    if v < -(1 << (bits - 1)) || v >= 1 << bits {
+        return berr!(Asm, format!("{} does not fit in {} bits", v, bits));
+    }
+    Ok((v & ((1 << bits) - 1)) as u32)
+}
+
+/// Get the address an instruction branches to or loads from, if it can be
+/// known without running it: PC relative addresses and register indexed
+/// ones based on r0.
+/// # Arguments
+/// * `instruction` - The instruction.
+/// * `pc` - Address of the instruction.
+fn target_address(instruction: &Instruction, pc: u32) -> Option<u32> {
+    type I = Instruction;
+    match *instruction {
+        I::Jmpr(c) => Some(pc.wrapping_add(c.offset())),
+        I::Callr(l)
+        | I::Ldrw(l)
+        | I::Ldrhs(l)
+        | I::Ldrhu(l)
+        | I::Ldrbs(l)
+        | I::Ldrbu(l)
+        | I::Strw(l)
+        | I::Strh(l)
+        | I::Strb(l) => Some(pc.wrapping_add(l.offset())),
+        I::Jmpx(c) | I::Ret(c) | I::Reti(c) => absolute_address(c.rs1, c.short_source),
+        I::Callx(s)
+        | I::Ldxw(s)
+        | I::Ldxhs(s)
+        | I::Ldxhu(s)
+        | I::Ldxbs(s)
+        | I::Ldxbu(s)
+        | I::Stxw(s)
+        | I::Stxh(s)
+        | I::Stxb(s) => absolute_address(s.rs1, s.short_source),
+        _ => None,
+    }
+}
+
+/// Get a register indexed address if it is based on r0.
+fn absolute_address(rs1: u8, source: ShortSource) -> Option<u32> {
+    match source {
+        ShortSource::Imm13(i) if rs1 == 0 => Some(i),
+        ShortSource::SImm13(i) if rs1 == 0 => Some(i),
+        _ => None,
+    }
+}
@@ -0,0 +1,392 @@
+// Test code for the assembler.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "asm.rs"]
+mod test {
+    use super::super::*;
+    use asm::*;
+    use config::{Endian, Syntax};
+    use cpu::{FRAME_POINTER_REG, RETURN_ADDRESS_REG, STACK_POINTER_REG};
+    use decode::decode;
+    use instruction::*;
+    use muldiv::{DIV_OPCODE, MUL_OPCODE, REMU_OPCODE};
+    use symbols::Symbols;
+    use util::Result;
+
+    type I = Instruction;
+    type SS = ShortSource;
+    type SI = ShortInstruction;
+    type SC = ShortConditional;
+
+    #[test]
+    fn assembles_disassembly_syntax() -> Result<()> {
+        let program = assemble(
+            "start: add.cc r16, r0, 0x2 ; comment
+                    stxw r16, r0, 32
+             loop:  jmpx alw, r0, loop
+                    sub r17, r16, -1
+                    .word 0xdeadbeef, start",
+            0,
+        )?;
+        assert_eq!(program.labels["loop"], 8);
+        assert_eq!(
+            program.words,
+            vec![
+                I::Add(SI::new(true, 16, 0, SS::Imm13(2))).encode(),
+                I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x20))).encode(),
+                I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(8))).encode(),
+                I::Sub(SI::new(false, 17, 16, SS::Imm13(0x1fff))).encode(),
+                0xdeadbeef,
+                0,
+            ]
+        );
+        // Disassembly assembles back to the same instruction.
+        let add = I::Add(SI::new(true, 16, 0, SS::Imm13(2)));
+        assert_eq!(assemble(&format!("{}", add), 0)?.words, vec![add.encode()]);
+        Ok(())
+    }
+
+    #[test]
+    fn proc_emits_prologue_and_epilogue() -> Result<()> {
+        let program = assemble(
+            "       callr ra, double
+                    add r0, r0, r0
+             halt:  jmpx alw, r0, halt
+                    add r0, r0, r0
+             .proc double, 16
+                    add r1, r1, r1
+             .endproc",
+            0,
+        )?;
+        assert_eq!(program.labels["double"], 0x10);
+        assert_eq!(
+            &program.words[4..],
+            &[
+                I::Sub(SI::new(
+                    false,
+                    STACK_POINTER_REG,
+                    FRAME_POINTER_REG,
+                    SS::Imm13(16)
+                ))
+                .encode(),
+                I::Add(SI::new(false, 1, 1, SS::Reg(1))).encode(),
+                I::Ret(SC::new(
+                    false,
+                    Conditional::Alw,
+                    RETURN_ADDRESS_REG,
+                    SS::Imm13(8)
+                ))
+                .encode(),
+                I::Add(SI::new(false, 0, 0, SS::Reg(0))).encode(),
+            ]
+        );
+        assert_eq!(
+            program.words[0],
+            I::Callr(LongInstruction::new(false, RETURN_ADDRESS_REG, 0x10)).encode()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fills_delay_slots() -> Result<()> {
+        let source = "       add r16, r0, 1
+                             jmpx alw, r0, skip
+                             add r16, r16, r16
+                      skip:  ret alw, r31, 8";
+        let nop = I::Add(SI::new(false, 0, 0, SS::Reg(0))).encode();
+        let padded = assemble_with(
+            source,
+            0,
+            &Options {
+                fill_delay_slots: true,
+                ..Options::default()
+            },
+        )?;
+        assert_eq!(padded.words.len(), 6);
+        assert_eq!(padded.words[2], nop);
+        assert_eq!(padded.words[5], nop);
+        assert_eq!(padded.labels["skip"], 0x10);
+        assert_eq!(padded.report.padded_slots, 2);
+
+        // The add moves into the jump's slot, the return still needs a NOP.
+        let scheduled = assemble_with(
+            source,
+            0,
+            &Options {
+                fill_delay_slots: true,
+                schedule_delay_slots: true,
+                ..Options::default()
+            },
+        )?;
+        assert_eq!(
+            &scheduled.words[..2],
+            &[
+                I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0xc))).encode(),
+                I::Add(SI::new(false, 16, 0, SS::Imm13(1))).encode(),
+            ]
+        );
+        assert_eq!(scheduled.words[4], nop);
+        assert_eq!(scheduled.report.scheduled_slots, 1);
+        assert_eq!(scheduled.report.padded_slots, 1);
+
+        // An instruction writing a register the jump reads stays put.
+        let kept = assemble_with(
+            "add r16, r0, 8\njmpx alw, r16, 0",
+            0,
+            &Options {
+                fill_delay_slots: true,
+                schedule_delay_slots: true,
+                ..Options::default()
+            },
+        )?;
+        assert_eq!(kept.words[2], nop);
+        assert_eq!(kept.report.scheduled_slots, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn aligns_branch_targets() -> Result<()> {
+        let program = assemble_with(
+            "       add r16, r0, 1
+             loop:  sub.cc r16, r16, 1
+                    jmpr ne, loop
+                    add r0, r0, r0
+             done:  add r0, r0, r0",
+            0,
+            &Options {
+                align_targets: 16,
+                ..Options::default()
+            },
+        )?;
+        // Only `loop` is a branch target.
+        assert_eq!(program.labels["loop"], 0x10);
+        assert_eq!(program.labels["done"], 0x1c);
+        assert_eq!(program.report.alignment_nops, 3);
+        assert!(assemble_with(
+            "",
+            0,
+            &Options {
+                align_targets: 6,
+                ..Options::default()
+            }
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn assembles_extension_instructions() -> Result<()> {
+        let program = assemble(
+            "mul.cc r18, r16, r17
+             ext 0x43, r19, r18, 5
+             remu r20, r18, r0",
+            0,
+        )?;
+        let expected = [
+            I::Extension(MUL_OPCODE, SI::new(true, 18, 16, SS::Reg(17))),
+            I::Extension(DIV_OPCODE, SI::new(false, 19, 18, SS::Imm13(5))),
+            I::Extension(REMU_OPCODE, SI::new(false, 20, 18, SS::Reg(0))),
+        ];
+        assert_eq!(
+            program.words,
+            expected.iter().map(|i| i.encode()).collect::<Vec<_>>()
+        );
+        for (word, instruction) in program.words.iter().zip(expected.iter()) {
+            assert_eq!(decode(*word)?, *instruction);
+            // The disassembly names the opcode and assembles back.
+            assert_eq!(assemble(&format!("{}", instruction), 0)?.words, vec![*word]);
+        }
+        assert_eq!(format!("{}", expected[0]), "ext.cc 0x40, r18, r16, r17");
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bad_source() {
+        for source in [
+            "bogus r1, r2, r3",
+            "add r1, r2",
+            "add r1, r2, 0x2000",
+            "add r1, r2, nowhere",
+            "jmpx always, r0, 0",
+            "a: a: add r0, r0, r0",
+            ".proc f",
+            ".endproc",
+            ".proc f\n.proc g\n.endproc\n.endproc",
+            ".bogus",
+            "ext 0x3f, r1, r2, r3",
+            "ext 0x80, r1, r2, r3",
+            "mul r1, r2",
+        ]
+        .iter()
+        {
+            assert!(assemble(source, 0).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn disassembly_assembles_back() -> Result<()> {
+        let program = assemble(
+            "start: add.cc r16, r0, 0x2
+                    ldxw r17, r16, 8
+                    stxw r17, r0, 32
+                    stxh r17, r16, -4
+             loop:  jmpx alw, r0, loop
+                    sub r17, r16, r17
+                    .word 0",
+            0x100,
+        )?;
+        let text = disassemble(
+            &program.to_bytes(Endian::Big),
+            0x100,
+            Syntax::Modern,
+            &program.symbols(),
+            Endian::Big,
+        );
+        assert!(text.contains("; 00000100: "));
+        assert!(text.contains(".word 0x00000000"));
+        // Store displacements are signed.
+        assert!(text.contains("r16, -0x4"));
+        assert_eq!(assemble(&text, 0x100)?.words, program.words);
+        Ok(())
+    }
+
+    #[test]
+    fn parses_single_instructions() -> Result<()> {
+        assert_eq!(
+            Instruction::parse("add r5, r4, #0x1f69")?,
+            I::Add(SI::new(false, 5, 4, SS::Imm13(0x1f69)))
+        );
+        assert_eq!(
+            Instruction::parse("  JMPR ne, -8 ; back")?,
+            I::Jmpr(LongConditional::new(false, Conditional::Ne, 0x7fff8))
+        );
+        for instruction in [
+            I::Sub(SI::new(true, 16, 17, SS::Reg(18))),
+            I::Stxh(SI::new(false, 17, 16, SS::Imm13(0x1ffc))).with_immediate_sign(),
+            I::Callr(LongInstruction::new(false, RETURN_ADDRESS_REG, 0x80)),
+            I::Jmpr(LongConditional::new(false, Conditional::Ne, 0x7fff8)),
+            I::Ldrw(LongInstruction::new(false, 17, 0x7ffe8)),
+            I::Ret(SC::new(
+                false,
+                Conditional::Alw,
+                RETURN_ADDRESS_REG,
+                SS::Imm13(8),
+            )),
+        ]
+        .iter()
+        {
+            let text = instruction.disassemble(Syntax::Modern).to_string();
+            assert_eq!(Instruction::parse(&text)?, *instruction, "{}", text);
+        }
+        // PC relative offsets are signed.
+        assert!(I::Ldrw(LongInstruction::new(false, 17, 0x7ffe8))
+            .disassemble(Syntax::Modern)
+            .to_string()
+            .ends_with("-0x18"));
+        assert!(Instruction::parse("").is_err());
+        assert!(Instruction::parse("loop: add r1, r2, r3").is_err());
+        assert!(Instruction::parse(".word 1").is_err());
+        assert!(Instruction::parse("add r1, r2").is_err());
+        assert!(Instruction::parse("add r1, r2, 0x2000").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn little_endian_images() -> Result<()> {
+        let program = assemble("add r16, r0, 0x2\n.word 0x11223344", 0)?;
+        let bytes = program.to_bytes(Endian::Little);
+        assert_eq!(&bytes[4..], &[0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(
+            disassemble(&bytes, 0, Syntax::Modern, &Symbols::new(), Endian::Little),
+            disassemble(
+                &program.to_bytes(Endian::Big),
+                0,
+                Syntax::Modern,
+                &Symbols::new(),
+                Endian::Big
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn berkeley_disassembly() -> Result<()> {
+        let program = assemble(
+            "start: add.cc r16, r0, 0x2
+                    ldxw r17, r16, 8
+                    stxw r17, r0, 32
+             loop:  jmpx alw, r0, loop
+                    sub r17, r16, r17
+                    ldhi r1, 0x7",
+            0x100,
+        )?;
+        let text = disassemble(
+            &program.to_bytes(Endian::Big),
+            0x100,
+            Syntax::Berkeley,
+            &Symbols::new(),
+            Endian::Big,
+        );
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|l| l.split(';').next().unwrap().trim())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "ADD r0,0x2,r16 {C}",
+                "LDXW (r16)0x8,r17",
+                "STXW r17,(r0)0x20",
+                "JMPX ALW,(r0)0x10c",
+                "SUB r16,r17,r17",
+                "LDHI 0x7,r1",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn disassembly_names_symbols() -> Result<()> {
+        let program = assemble(
+            "main:   callr ra, func
+                     add r0, r0, r0
+                     jmpx alw, r0, main
+                     add r0, r0, r0
+             func:   ldxw r16, r0, table
+             table:  .word 0",
+            0x100,
+        )?;
+        let text = disassemble(
+            &program.to_bytes(Endian::Big),
+            0x100,
+            Syntax::Modern,
+            &program.symbols(),
+            Endian::Big,
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "main:");
+        assert!(lines[1].ends_with("<func>"));
+        assert!(lines[2].ends_with("(delay slot)"));
+        assert!(lines[3].ends_with("<main>"));
+        assert!(lines[4].ends_with("(delay slot)"));
+        assert_eq!(lines[5], "func:");
+        assert!(lines[6].ends_with("<table>"));
+        assert_eq!(lines[7], "table:");
+        assert_eq!(assemble(&text, 0x100)?.words, program.words);
+        Ok(())
+    }
+}
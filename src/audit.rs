@@ -0,0 +1,238 @@
+// Determinism audit of the emulator.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Two systems created the same way run the same program and their
+// fingerprints (a hash of every subsystem's state) are compared every N
+// retired instructions. Once they differ, a fresh pair is run up to the last
+// point they agreed and then compared every clock cycle to find the cycle
+// where the nondeterminism entered.
+
+use r2d2::{Snapshot, Writer};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::Hasher;
+use system::System;
+use util::Result;
+
+/// Most clock cycles an instruction may take when looking for the cycle
+/// the runs diverged on.
+const MAX_INSTRUCTION_CYCLES: u64 = 64;
+
+/// Hash of the state of each subsystem of a system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Name of each subsystem and the hash of its state.
+    parts: Vec<(String, u64)>,
+}
+
+/// First point where two runs disagreed.
+pub struct Nondeterminism {
+    /// Instructions the first run retired when the difference was found.
+    pub instructions: u64,
+    /// Clock cycle the difference appeared on, None if it could not be
+    /// reproduced cycle by cycle.
+    pub cycle: Option<u64>,
+    /// Instructions retired when the runs last agreed.
+    pub last_agreed: u64,
+    /// Subsystems whose state differed.
+    pub subsystems: Vec<String>,
+}
+
+/// Runs pairs of systems and checks that they stay identical.
+pub struct Audit<F>
+where
+    F: Fn() -> Result<System>,
+{
+    /// Creates the systems to compare.
+    make: F,
+    /// Instructions between fingerprint comparisons.
+    every: u64,
+    /// Instructions both runs retired identically.
+    retired: u64,
+}
+
+impl Fingerprint {
+    /// Take the fingerprint of `system`.
+    /// # Arguments
+    /// * `system` - System to fingerprint.
+    pub fn of(system: &System) -> Self {
+        let mut parts = Vec::new();
+        for (id, core) in system.cores().iter().enumerate() {
+            // Only the cores after the boot core are named by number.
+            let prefix = if id == 0 {
+                String::new()
+            } else {
+                format!("core {} ", id)
+            };
+            let mut w = Writer::new();
+            core.data_path().save(&mut w);
+            parts.push((format!("{}data path", prefix), hash(&w)));
+            let mut w = Writer::new();
+            system.phase().save(&mut w);
+            core.cpu().save(&mut w);
+            parts.push((format!("{}engine", prefix), hash(&w)));
+        }
+        let mut w = Writer::new();
+        system.clock().save(&mut w);
+        parts.push(("clock".to_string(), hash(&w)));
+        let mut w = Writer::new();
+        system.memory().save(&mut w);
+        parts.push(("memory".to_string(), hash(&w)));
+        for (name, w) in system.memory().device_states() {
+            parts.push((format!("device {}", name), hash(&w)));
+        }
+        Self { parts: parts }
+    }
+
+    /// Get the names of the subsystems whose state differs from `other`'s.
+    /// # Arguments
+    /// * `other` - Fingerprint to compare to.
+    pub fn differences(&self, other: &Fingerprint) -> Vec<String> {
+        let mut result: Vec<String> = self
+            .parts
+            .iter()
+            .filter(|p| !other.parts.contains(p))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if self.parts.len() != other.parts.len() {
+            result.push("attached devices".to_string());
+        }
+        result
+    }
+}
+
+impl<F> Audit<F>
+where
+    F: Fn() -> Result<System>,
+{
+    /// Audit the systems `make` creates.
+    /// # Arguments
+    /// * `make` - Creates a system, the same way every time.
+    /// * `every` - Instructions between fingerprint comparisons.
+    pub fn new(make: F, every: u64) -> Self {
+        Self {
+            make: make,
+            every: every.max(1),
+            retired: 0,
+        }
+    }
+
+    /// Run two systems until they disagree or the program halts by
+    /// branching to itself. Return where they disagreed, None if they did
+    /// not and a string on error.
+    pub fn run_until_halt(&mut self) -> Result<Option<Nondeterminism>> {
+        let (mut a, mut b) = ((self.make)()?, (self.make)()?);
+        self.retired = 0;
+        loop {
+            let (retired_a, halted_a) = run_instructions(&mut a, self.every)?;
+            let (retired_b, halted_b) = run_instructions(&mut b, self.every)?;
+            let mut differences = Fingerprint::of(&a).differences(&Fingerprint::of(&b));
+            if retired_a != retired_b || halted_a != halted_b {
+                differences.push("retired instructions".to_string());
+            }
+            if !differences.is_empty() {
+                let found = self.pinpoint()?;
+                return Ok(Some(found.unwrap_or(Nondeterminism {
+                    instructions: self.retired + retired_a,
+                    cycle: None,
+                    last_agreed: self.retired,
+                    subsystems: differences,
+                })));
+            }
+            self.retired += retired_a;
+            if halted_a {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Get the number of instructions both runs retired identically.
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    /// Run a fresh pair of systems to where the runs last agreed, then
+    /// compare them every clock cycle until they disagree. Return where
+    /// they disagreed, None if they did not.
+    fn pinpoint(&self) -> Result<Option<Nondeterminism>> {
+        let (mut a, mut b) = ((self.make)()?, (self.make)()?);
+        run_instructions(&mut a, self.retired)?;
+        run_instructions(&mut b, self.retired)?;
+        let mut instructions = self.retired;
+        for _ in 0..=self.every * MAX_INSTRUCTION_CYCLES {
+            let differences = Fingerprint::of(&a).differences(&Fingerprint::of(&b));
+            if !differences.is_empty() {
+                return Ok(Some(Nondeterminism {
+                    instructions: instructions,
+                    cycle: Some(a.clock().count()),
+                    last_agreed: self.retired,
+                    subsystems: differences,
+                }));
+            }
+            instructions += a.run_cycle()? as u64;
+            b.run_cycle()?;
+        }
+        Ok(None)
+    }
+}
+
+impl fmt::Display for Nondeterminism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cycle {
+            Some(cycle) => writeln!(
+                f,
+                "Runs diverged on cycle {} after {} instructions.",
+                cycle, self.instructions
+            )?,
+            None => writeln!(
+                f,
+                "Runs diverged between instructions {} and {}, but not when rerun cycle by cycle.",
+                self.last_agreed, self.instructions
+            )?,
+        }
+        writeln!(f, "Nondeterministic: {}", self.subsystems.join(", "))
+    }
+}
+
+// Private functions.
+
+/// Hash the contents of a snapshot writer.
+fn hash(w: &Writer) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(w.as_bytes());
+    hasher.finish()
+}
+
+/// Run `system` for up to `count` instructions, stopping early if the
+/// program halts by branching to itself. Return the number of instructions
+/// retired and true if it halted.
+/// # Arguments
+/// * `system` - System to run.
+/// * `count` - Instructions to run.
+fn run_instructions(system: &mut System, count: u64) -> Result<(u64, bool)> {
+    let mut retired = 0;
+    while retired < count {
+        match system.run_until_retired()? {
+            Some(r) => {
+                retired += 1;
+                if r.branch == Some(r.pc) {
+                    return Ok((retired, true));
+                }
+            }
+            None => break,
+        }
+    }
+    Ok((retired, false))
+}
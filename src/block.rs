@@ -0,0 +1,209 @@
+// Memory mapped block storage device.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// The disk is a host image file made of `SECTOR_SIZE` byte sectors. To move a
+// sector the guest writes the sector number to `BLOCK_SECTOR`, the memory
+// address to `BLOCK_ADDRESS` and then `BLOCK_READ` or `BLOCK_WRITE` to
+// `BLOCK_COMMAND`. The transfer happens by DMA at the start of the next clock
+// cycle, after which `BLOCK_DONE` (and `BLOCK_ERROR` if it failed) is set in
+// the status register. Reading the status register acknowledges the
+// completion. If enabled, an interrupt is requested while a completion is not
+// acknowledged.
+
+use device::{Device, Reset};
+use error::EmulatorError;
+use r2d2::Writer;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use util::Result;
+
+use berr;
+
+/// Bytes in a sector.
+pub const SECTOR_SIZE: u32 = 512;
+/// Base address of the block device's registers.
+pub const BLOCK_BASE: u32 = 0xffff0020;
+/// Command register. Writing a command starts it.
+pub const BLOCK_COMMAND: u32 = BLOCK_BASE;
+/// Status register, see the `BLOCK_*` status bits.
+pub const BLOCK_STATUS: u32 = BLOCK_BASE + 4;
+/// Sector the next command transfers.
+pub const BLOCK_SECTOR: u32 = BLOCK_BASE + 8;
+/// Memory address the next command transfers to or from.
+pub const BLOCK_ADDRESS: u32 = BLOCK_BASE + 12;
+/// Control register. Bit 0 enables the completion interrupt.
+pub const BLOCK_CONTROL: u32 = BLOCK_BASE + 16;
+/// Number of sectors on the disk (read only).
+pub const BLOCK_SECTORS: u32 = BLOCK_BASE + 20;
+/// Size of the block device's register block.
+const BLOCK_SIZE: u32 = 24;
+/// Command that reads a sector into memory.
+pub const BLOCK_READ: u32 = 1;
+/// Command that writes a sector from memory.
+pub const BLOCK_WRITE: u32 = 2;
+/// Status bit set while a command is waiting to run.
+pub const BLOCK_BUSY: u32 = 0x1;
+/// Status bit set when a command finished and was not acknowledged yet.
+pub const BLOCK_DONE: u32 = 0x2;
+/// Status bit set when the last command failed.
+pub const BLOCK_ERROR: u32 = 0x4;
+/// Control bit that requests an interrupt while a completion is not
+/// acknowledged.
+pub const BLOCK_INTERRUPT_ENABLE: u32 = 0x1;
+
+/// Block device backed by a disk image.
+pub struct BlockDevice {
+    /// The disk image.
+    image: File,
+    /// Number of whole sectors in the image.
+    sectors: u32,
+    /// Command waiting to run, None if idle.
+    command: Option<u32>,
+    /// Contents of the sector register.
+    sector: u32,
+    /// Contents of the address register.
+    address: u32,
+    /// Contents of the control register.
+    control: u32,
+    /// Status bits other than busy.
+    status: u32,
+}
+
+impl BlockDevice {
+    /// Open a disk image for reading and writing. Return the device on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the disk image.
+    pub fn open(path: &String) -> Result<Self> {
+        let image = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(EmulatorError::io(format!("Could not open disk image {}", path), e)),
+        };
+        let sectors = (image.metadata()?.len() / SECTOR_SIZE as u64) as u32;
+        Ok(Self {
+            image: image,
+            sectors: sectors,
+            command: None,
+            sector: 0,
+            address: 0,
+            control: 0,
+            status: 0,
+        })
+    }
+
+    /// Get the number of sectors on the disk.
+    pub fn sectors(&self) -> u32 {
+        self.sectors
+    }
+
+    /// Run `command` against memory.
+    /// # Arguments
+    /// * `command` - Command to run.
+    /// * `ram` - Contents of memory.
+    fn transfer(&mut self, command: u32, ram: &mut [u8]) -> Result<()> {
+        let start = self.address as usize;
+        let end = start + SECTOR_SIZE as usize;
+        if self.sector >= self.sectors {
+            return berr!(Device, format!(
+                "Sector {} is past the end of the disk",
+                self.sector
+            ));
+        }
+        if end > ram.len() {
+            return berr!(Device, format!("DMA address 0x{:x} is out of range", self.address));
+        }
+        self.image
+            .seek(SeekFrom::Start(self.sector as u64 * SECTOR_SIZE as u64))?;
+        match command {
+            BLOCK_READ => self.image.read_exact(&mut ram[start..end])?,
+            BLOCK_WRITE => self.image.write_all(&ram[start..end])?,
+            _ => return berr!(Device, format!("Invalid block command {}", command)),
+        }
+        Ok(())
+    }
+}
+
+impl Device for BlockDevice {
+    fn name(&self) -> &str {
+        "block"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        let _ = self.image.flush();
+        self.command = None;
+        self.sector = 0;
+        self.address = 0;
+        self.control = 0;
+        self.status = 0;
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(BLOCK_BASE..BLOCK_BASE + BLOCK_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        match addr & !0x3 {
+            BLOCK_STATUS => {
+                let mut status = self.status;
+                if self.command.is_some() {
+                    status |= BLOCK_BUSY;
+                }
+                self.status &= !BLOCK_DONE;
+                status
+            }
+            BLOCK_SECTOR => self.sector,
+            BLOCK_ADDRESS => self.address,
+            BLOCK_CONTROL => self.control,
+            BLOCK_SECTORS => self.sectors,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        match addr & !0x3 {
+            BLOCK_COMMAND => {
+                self.command = Some(value);
+                self.status = 0;
+            }
+            BLOCK_SECTOR => self.sector = value,
+            BLOCK_ADDRESS => self.address = value,
+            BLOCK_CONTROL => self.control = value & BLOCK_INTERRUPT_ENABLE,
+            _ => {}
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        w.put_u32(self.command.unwrap_or(0));
+        w.put_bool(self.command.is_some());
+        w.put_u32(self.sector);
+        w.put_u32(self.address);
+        w.put_u32(self.control);
+        w.put_u32(self.status);
+    }
+
+    fn dma(&mut self, ram: &mut [u8]) {
+        if let Some(command) = self.command.take() {
+            self.status = match self.transfer(command, ram) {
+                Ok(()) => BLOCK_DONE,
+                Err(_) => BLOCK_DONE | BLOCK_ERROR,
+            };
+        }
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.control & BLOCK_INTERRUPT_ENABLE != 0 && self.status & BLOCK_DONE != 0
+    }
+}
@@ -0,0 +1,111 @@
+// Test code for the block device.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "block.rs"]
+mod test {
+    use super::super::*;
+    use block::*;
+    use memory::Memory;
+    use std::fs;
+    use util::Result;
+
+    /// Create a disk image of two sectors, the second one counting up from
+    /// 0. Return its path.
+    fn make_image(name: &str) -> Result<String> {
+        let path = std::env::temp_dir()
+            .join(format!("riscii-{}-{}.img", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut image = vec![0u8; SECTOR_SIZE as usize];
+        image.extend((0..SECTOR_SIZE).map(|i| i as u8));
+        fs::write(&path, image)?;
+        Ok(path)
+    }
+
+    /// Create a memory with the block device for the image at `path`
+    /// attached.
+    fn make_memory(path: &String) -> Result<Memory> {
+        let mut mem = Memory::from_size(0x800);
+        mem.attach(Box::new(BlockDevice::open(path)?));
+        Ok(mem)
+    }
+
+    #[test]
+    fn transfers_sectors() -> Result<()> {
+        let path = make_image("transfers")?;
+        let mut mem = make_memory(&path)?;
+        assert_eq!(mem.get_word(BLOCK_SECTORS)?, 2);
+
+        mem.set_word(BLOCK_SECTOR, 1)?;
+        mem.set_word(BLOCK_ADDRESS, 0x200)?;
+        mem.set_word(BLOCK_COMMAND, BLOCK_READ)?;
+        // Nothing moves until the DMA runs.
+        assert_eq!(mem.get_word(BLOCK_STATUS)?, BLOCK_BUSY);
+        assert_eq!(mem.get_byte(0x201)?, 0);
+        mem.run_dma();
+        assert_eq!(mem.get_bytes(0x200, 4)?, &[0, 1, 2, 3]);
+        assert_eq!(mem.get_byte(0x3ff)?, 0xff);
+        assert_eq!(mem.get_word(BLOCK_STATUS)?, BLOCK_DONE);
+        // Reading the status acknowledged the completion.
+        assert_eq!(mem.get_word(BLOCK_STATUS)?, 0);
+
+        mem.set_word(0x600, 0xdeadbeef)?;
+        mem.set_word(BLOCK_SECTOR, 0)?;
+        mem.set_word(BLOCK_ADDRESS, 0x600)?;
+        mem.set_word(BLOCK_COMMAND, BLOCK_WRITE)?;
+        mem.run_dma();
+        assert_eq!(mem.get_word(BLOCK_STATUS)?, BLOCK_DONE);
+        mem.reset_devices(device::Reset::Warm);
+        let image = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(&image[..4], &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(image[SECTOR_SIZE as usize + 1], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_errors_and_interrupts() -> Result<()> {
+        let path = make_image("errors")?;
+        let mut mem = make_memory(&path)?;
+        fs::remove_file(&path)?;
+        mem.set_word(BLOCK_CONTROL, BLOCK_INTERRUPT_ENABLE)?;
+
+        // Past the end of the disk.
+        mem.set_word(BLOCK_SECTOR, 2)?;
+        mem.set_word(BLOCK_COMMAND, BLOCK_READ)?;
+        assert!(!mem.interrupt_pending());
+        mem.run_dma();
+        assert!(mem.interrupt_pending());
+        assert_eq!(mem.get_word(BLOCK_STATUS)?, BLOCK_DONE | BLOCK_ERROR);
+        assert!(!mem.interrupt_pending());
+
+        // Past the end of memory.
+        mem.set_word(BLOCK_SECTOR, 0)?;
+        mem.set_word(BLOCK_ADDRESS, 0x700)?;
+        mem.set_word(BLOCK_COMMAND, BLOCK_READ)?;
+        mem.run_dma();
+        assert_eq!(mem.get_word(BLOCK_STATUS)?, BLOCK_DONE | BLOCK_ERROR);
+
+        // Unknown command.
+        mem.set_word(BLOCK_ADDRESS, 0)?;
+        mem.set_word(BLOCK_COMMAND, 7)?;
+        mem.run_dma();
+        assert_eq!(mem.get_word(BLOCK_STATUS)?, BLOCK_DONE | BLOCK_ERROR);
+
+        assert!(BlockDevice::open(&path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+// The calls in progress on a core, rebuilt from its register windows.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// A call writes the address of the call instruction into ra of the new
+// window, so walking the windows from CWP back to SWP gives every call in
+// progress: the innermost frame is where the PC is, each older frame is at
+// the call the newer one returns past. Frames older than SWP would have
+// been spilled to memory, but the register file does not spill yet, so the
+// stack ends at the oldest frame still in a window.
+
+use cpu::Frame;
+use data_path::DataPath;
+use std::fmt;
+use symbols::Symbols;
+
+/// A call in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallFrame {
+    /// Window of the frame.
+    pub window: u8,
+    /// Where the frame is: the PC for the innermost frame, the call it
+    /// waits on to return for the others.
+    pub pc: u32,
+    /// The symbol `pc` is in, None if there is none close enough.
+    pub function: Option<String>,
+    /// The frame's registers.
+    pub frame: Frame,
+}
+
+/// The calls in progress on a core, innermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallStack {
+    /// The frames, innermost first.
+    pub frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    /// Rebuild the call stack of a data path.
+    /// # Arguments
+    /// * `dp` - The data path.
+    /// * `symbols` - Symbols naming the functions.
+    pub fn new(dp: &DataPath, symbols: &Symbols) -> Self {
+        let mut pc = dp.pc();
+        let frames = dp
+            .register_file()
+            .backtrace(&dp.psw())
+            .into_iter()
+            .map(|frame| {
+                let f = CallFrame {
+                    window: frame.window,
+                    pc: pc,
+                    function: symbols.describe(pc),
+                    frame: frame,
+                };
+                // The caller waits at the call.
+                pc = frame.ra;
+                f
+            })
+            .collect();
+        Self { frames: frames }
+    }
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "w{} {:08x}", self.window, self.pc)?;
+        if let Some(ref function) = self.function {
+            write!(f, " {}", function)?;
+        }
+        write!(f, " (sp:{:08x} fp:{:08x})", self.frame.sp, self.frame.fp)
+    }
+}
+
+impl fmt::Display for CallStack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "#{} {}", i, frame)?;
+        }
+        Ok(())
+    }
+}
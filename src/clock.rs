@@ -13,9 +13,13 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use config::Config;
+use config::{ClockMode, Config};
+use r2d2::{Reader, Snapshot, Writer};
 use std::fmt;
 use std::time::{Duration, Instant};
+use util::Result;
+
+use berr;
 
 /// Phases for RISCII's multi (4) phase clock non-overlapping clock.
 #[derive(PartialEq, Eq, Clone)]
@@ -38,55 +42,206 @@ pub enum Phase {
     Interrupt = 5,
 }
 
+impl Snapshot for Phase {
+    fn save(&self, w: &mut Writer) {
+        w.put_u8(self.clone() as u8);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<()> {
+        *self = match r.get_u8()? {
+            1 => Phase::One,
+            2 => Phase::Two,
+            3 => Phase::Three,
+            4 => Phase::Four,
+            5 => Phase::Interrupt,
+            v => return berr!(Format, format!("Snapshot has invalid clock phase {}", v)),
+        };
+        Ok(())
+    }
+}
+
+/// How often the effective clock rate is measured.
+const MEASURE_PERIOD: Duration = Duration::from_millis(500);
+/// Cycles between looks at the wall clock when not pacing.
+const MEASURE_INTERVAL: u64 = 1024;
+/// How far behind the wall clock a paced clock may fall before it stops
+/// trying to catch up, e.g. after the system was paused.
+const MAX_LAG: Duration = Duration::from_millis(100);
+/// How long the host sleeps for at most between checks of a paced clock.
+const PACE_PERIOD: Duration = Duration::from_millis(1);
+
+/// The system's clock. Counts cycles and, when paced, sleeps to keep the
+/// configured rate. The clock is always compared to where it should be
+/// since it was last in step with the wall clock, so sleeping too long once
+/// is made up for later instead of adding up.
 #[derive(PartialEq, Eq, Clone)]
 pub struct Clock {
+    /// Configured rate in hertz.
     rate: u64,
+    /// How the clock is paced.
+    mode: ClockMode,
+    /// Instructions run per frame in `ClockMode::Frame`.
+    frame_instructions: u64,
+    /// Number of cycles run.
     count: u64,
-    last_time: Instant,
-    seconds_coutner: Duration,
+    /// Wall time the clock was last in step with.
+    epoch: Instant,
+    /// Cycle count at `epoch`.
+    epoch_count: u64,
+    /// Start of the current measurement of the effective rate.
+    sample_time: Instant,
+    /// Cycle count at `sample_time`.
+    sample_count: u64,
+    /// Last measured rate in hertz, None before the first measurement.
+    effective_rate: Option<u64>,
 }
 
 impl Clock {
+    pub fn new(config: &Config) -> Self {
+        let now = Instant::now();
+        Self {
+            rate: config.get_clock_rate(),
+            mode: config.get_clock_mode(),
+            frame_instructions: config.get_frame_instructions(),
+            count: 0,
+            epoch: now,
+            epoch_count: 0,
+            sample_time: now,
+            sample_count: 0,
+            effective_rate: None,
+        }
+    }
+
+    /// Count a phase, without waiting.
+    /// # Arguments
+    /// * `phase` - The phase.
     pub fn tick(&mut self, phase: Phase) {
-        match phase {
-            Phase::One => {
-                self.count += 1;
+        if phase == Phase::One {
+            self.count += 1;
+            if self.count % MEASURE_INTERVAL == 0 {
+                self.measure(Instant::now());
             }
-            _ => {}
         }
     }
 
+    /// Count a phase, then sleep if the clock is ahead of the configured
+    /// rate.
+    /// # Arguments
+    /// * `phase` - The phase.
     pub fn tick_and_wait(&mut self, phase: Phase) {
-        match phase {
-            Phase::One => {
-                self.count += 1;
-                if self.count == self.rate {
-                    self.idle_clock();
-                }
+        if phase == Phase::One {
+            self.count += 1;
+            if self.count % self.pace_interval() == 0 {
+                self.pace();
             }
-            _ => {}
         }
     }
 
-    pub fn new(config: &Config) -> Self {
-        Self {
-            rate: config.get_clock_rate(),
-            count: 0,
-            last_time: Instant::now(),
-            seconds_coutner: Duration::new(0, 0),
-        }
+    /// Get the number of cycles the clock has run for.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the configured rate in hertz.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Get how the clock is paced.
+    pub fn mode(&self) -> ClockMode {
+        self.mode
+    }
+
+    /// Change how the clock is paced.
+    /// # Arguments
+    /// * `mode` - How the clock is paced.
+    pub fn set_mode(&mut self, mode: ClockMode) {
+        self.mode = mode;
+        self.resync(Instant::now());
+    }
+
+    /// Get the number of instructions run per frame in `ClockMode::Frame`.
+    pub fn frame_instructions(&self) -> u64 {
+        self.frame_instructions
+    }
+
+    /// Get the rate the clock actually ran at, in hertz, over the last
+    /// measurement. None until a measurement was taken.
+    pub fn effective_rate(&self) -> Option<u64> {
+        self.effective_rate
+    }
+
+    /// Forget about the wall clock so far, the next cycles are paced from
+    /// now on.
+    /// # Arguments
+    /// * `now` - The current time.
+    fn resync(&mut self, now: Instant) {
+        self.epoch = now;
+        self.epoch_count = self.count;
+        self.sample_time = now;
+        self.sample_count = self.count;
     }
 
-    fn idle_clock(&mut self) {
-        // Calc curTime - lastTime (in nanoseconds). If less than a second has
-        // passed, sleep until we've reached that next second.
-        const ONE_SECOND: Duration = Duration::from_secs(1);
+    /// Get the number of cycles between checks of a paced clock.
+    fn pace_interval(&self) -> u64 {
+        let per_period = self.rate as u128 * PACE_PERIOD.as_nanos() / 1_000_000_000;
+        (per_period as u64).max(1)
+    }
+
+    /// Sleep until the wall clock catches up with the cycles run.
+    fn pace(&mut self) {
         let now = Instant::now();
-        let time_passed = now - self.last_time;
-        if time_passed < ONE_SECOND {
-            std::thread::sleep(time_passed);
-            self.last_time = now + time_passed;
+        if self.rate == 0 {
+            return self.measure(now);
+        }
+        let cycles = (self.count - self.epoch_count) as u128;
+        let nanos = cycles * 1_000_000_000 / self.rate as u128;
+        let due = self.epoch + Duration::from_nanos(nanos.min(u64::MAX as u128) as u64);
+        if due > now {
+            std::thread::sleep(due - now);
+        } else if now - due > MAX_LAG {
+            self.resync(now);
         }
+        self.measure(Instant::now());
+    }
+
+    /// Measure the effective rate if the last measurement is old enough.
+    /// # Arguments
+    /// * `now` - The current time.
+    fn measure(&mut self, now: Instant) {
+        let elapsed = now - self.sample_time;
+        if elapsed < MEASURE_PERIOD {
+            return;
+        }
+        let cycles = (self.count - self.sample_count) as u128;
+        self.effective_rate = Some((cycles * 1_000_000_000 / elapsed.as_nanos()) as u64);
+        self.sample_time = now;
+        self.sample_count = self.count;
+    }
+}
+
+/// Describe a rate in hertz with a metric prefix, e.g. `4.98 MHz`.
+/// # Arguments
+/// * `hertz` - The rate.
+pub fn format_hertz(hertz: u64) -> String {
+    match hertz {
+        0..=999 => format!("{} Hz", hertz),
+        1_000..=999_999 => format!("{:.2} kHz", hertz as f64 / 1e3),
+        1_000_000..=999_999_999 => format!("{:.2} MHz", hertz as f64 / 1e6),
+        _ => format!("{:.2} GHz", hertz as f64 / 1e9),
+    }
+}
+
+impl Snapshot for Clock {
+    /// Only the cycle count is saved, the rate comes from the configuration.
+    fn save(&self, w: &mut Writer) {
+        w.put_u64(self.count);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<()> {
+        self.count = r.get_u64()?;
+        self.resync(Instant::now());
+        Ok(())
     }
 }
 
@@ -98,6 +253,6 @@ impl fmt::Debug for Clock {
 
 impl fmt::Display for Clock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}, {}", self.rate, self.count)
+        write!(f, "{}, {}, {}", self.rate, self.mode, self.count)
     }
 }
@@ -0,0 +1,76 @@
+// Test code for the clock.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "clock.rs"]
+mod test {
+    use super::super::*;
+    use clock::*;
+    use config::{ClockMode, Config};
+    use std::time::{Duration, Instant};
+    use util::Result;
+
+    /// Run `clock` for `cycles` whole cycles.
+    fn run(clock: &mut Clock, cycles: u64, paced: bool) {
+        for _ in 0..cycles {
+            for phase in [Phase::One, Phase::Two, Phase::Three, Phase::Four].iter() {
+                if paced {
+                    clock.tick_and_wait(phase.clone());
+                } else {
+                    clock.tick(phase.clone());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn paced_clock_keeps_its_rate() -> Result<()> {
+        let mut config = Config::new()?;
+        config.set_clock_rate(1_000);
+        let mut clock = Clock::new(&config);
+        let start = Instant::now();
+        run(&mut clock, 50, true);
+        assert_eq!(clock.count(), 50);
+        // 50 cycles at 1 kHz take 50 ms, give or take a sleep.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(49), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+        Ok(())
+    }
+
+    #[test]
+    fn unpaced_clock_measures_its_rate() -> Result<()> {
+        let mut config = Config::new()?;
+        config.set_clock_mode(ClockMode::Unlimited);
+        let mut clock = Clock::new(&config);
+        assert_eq!(clock.mode(), ClockMode::Unlimited);
+        assert_eq!(clock.effective_rate(), None);
+        let start = Instant::now();
+        while clock.effective_rate().is_none() {
+            run(&mut clock, 1024, false);
+            assert!(start.elapsed() < Duration::from_secs(10));
+        }
+        assert!(clock.effective_rate().unwrap() > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn hertz_have_metric_prefixes() {
+        assert_eq!(format_hertz(999), "999 Hz");
+        assert_eq!(format_hertz(12_340), "12.34 kHz");
+        assert_eq!(format_hertz(4_980_000), "4.98 MHz");
+        assert_eq!(format_hertz(2_000_000_000), "2.00 GHz");
+    }
+}
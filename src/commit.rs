@@ -1,7 +1,6 @@
 // Instruction commit. The final step in the three stage RISC II pipeline.
 // See `decode.rs` for the first step and `execute.rs` for the second step
 // of the pipeline.
-// See `decode.rs` for the first step, and `commit.rs` for the third step.
 // (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
 // This program is free software: you can redistribute it and/or modify
 // it under the terms of the GNU Affero General Public License as published by
@@ -16,8 +15,136 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use instruction::*;
+use cpu::{ProcessorStatusWord, Trap};
+use data_path::DataPath;
+use execute::ExecResult;
+use instruction::Instruction;
+
+// Public structs.
+
+/// A load or store performed by a retired instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// Address accessed.
+    pub address: u32,
+    /// Number of bytes accessed (1, 2 or 4).
+    pub width: u32,
+    /// Value read from or written to memory.
+    pub value: u32,
+    /// True for a store, false for a load.
+    pub store: bool,
+}
+
+/// Everything known about an instruction at the moment it retires. Passed to
+/// the retire hooks registered with `System::on_retire`.
+#[derive(Clone)]
+pub struct RetiredInstruction {
+    /// The instruction that retired.
+    pub instruction: Instruction,
+    /// Address the instruction was fetched from.
+    pub pc: u32,
+    /// Destination register and the value written to it, if the instruction
+    /// has a destination and did not trap.
+    pub dest: Option<(u8, u32)>,
+    /// PSW before the instruction executed.
+    pub psw_before: ProcessorStatusWord,
+    /// PSW after the instruction committed.
+    pub psw_after: ProcessorStatusWord,
+    /// Memory access performed by the instruction, if any.
+    pub access: Option<MemoryAccess>,
+    /// Target of the instruction if it was a taken branch.
+    pub branch: Option<u32>,
+    /// Trap raised by the instruction, if any.
+    pub trap: Option<Trap>,
+    /// Clock cycle the instruction retired on.
+    pub cycle: u64,
+}
 
 // Public functions.
 
-pub fn commit() {}
+/// Make the effects of an executed instruction visible to the data path.
+/// If the instruction trapped its results are discarded and the data path
+/// jumps to the trap handler instead.
+/// # Arguments
+/// * `result` - Result of executing the instruction.
+/// * `dp` - Data path to commit to.
+pub fn commit(result: &ExecResult, dp: &mut DataPath) {
+    if let Some(trap) = result.get_trap() {
+        dp.raise_trap(trap);
+        return;
+    }
+
+    *dp.get_register_file() = *result.get_register_file();
+    if result.is_psw_delayed() {
+        dp.delay_psw(result.get_psw());
+    } else {
+        dp.set_psw(result.get_psw().get());
+        dp.apply_pending_psw();
+    }
+    dp.set_mmu(result.get_mmu());
+    match result.get_branch() {
+        Some(addr) => dp.branch_to(addr),
+        None => dp.increment_pcs(),
+    }
+}
+
+// Struct impls.
+
+impl MemoryAccess {
+    /// Describe an access of `width` bytes. Only the low `width` bytes of
+    /// `value` are kept.
+    /// # Arguments
+    /// * `address` - Address accessed.
+    /// * `width` - Number of bytes accessed (1, 2 or 4).
+    /// * `value` - Value read or written.
+    /// * `store` - True for a store, false for a load.
+    pub fn new(address: u32, width: u32, value: u32, store: bool) -> Self {
+        Self {
+            address: address,
+            width: width,
+            value: match width {
+                1 => value & 0xff,
+                2 => value & 0xffff,
+                _ => value,
+            },
+            store: store,
+        }
+    }
+}
+
+impl RetiredInstruction {
+    /// Describe an instruction that was just committed to `dp`.
+    /// # Arguments
+    /// * `instruction` - The instruction that was committed.
+    /// * `pc` - Address the instruction was fetched from.
+    /// * `psw_before` - PSW before the instruction executed.
+    /// * `result` - Result of executing the instruction.
+    /// * `dp` - Data path the instruction was committed to.
+    /// * `cycle` - Clock cycle the instruction retired on.
+    pub fn new(
+        instruction: Instruction,
+        pc: u32,
+        psw_before: ProcessorStatusWord,
+        result: &ExecResult,
+        dp: &DataPath,
+        cycle: u64,
+    ) -> Self {
+        let psw_after = dp.get_psw();
+        let trap = result.get_trap();
+        let dest = match (trap, instruction.dest_reg()) {
+            (None, Some(rd)) => Some((rd, dp.register_file().read(rd, psw_after.get_cwp()))),
+            _ => None,
+        };
+        Self {
+            instruction,
+            pc,
+            dest,
+            psw_before,
+            psw_after,
+            access: result.get_access(),
+            branch: result.get_branch(),
+            trap,
+            cycle,
+        }
+    }
+}
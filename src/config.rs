@@ -19,18 +19,318 @@ extern crate serde;
 extern crate serde_derive;
 extern crate toml;
 
+use asm::Options;
+use console::CONSOLE_BASE;
+use cpu::{DEFAULT_REG_WINDOWS, TRAP_VECTOR_BASE};
+use decode_cache::DEFAULT_DECODE_CACHE;
+use error::EmulatorError;
+use harness::DEFAULT_BUDGET;
+use logging::LogSpec;
+use region::{Permissions, Region};
 use std::env;
 use std::fmt;
 use std::fs;
+use std::io;
 use std::path::Path;
-use util::{concat_paths, get_home_nofail, Result};
+use util::{concat_paths, expand_path, xdg_dir, Result, Xorshift};
 
 use berr;
 
-use self::serde_derive::Deserialize;
+use self::serde_derive::{Deserialize, Serialize};
+use self::toml::value::Table;
+use self::toml::Value;
+
+/// File name of the font the windows draw text in.
+pub const FONT_FILE: &str = "debug.otf";
+/// Data directories searched for the font when $XDG_DATA_DIRS is not set.
+const DEFAULT_XDG_DATA_DIRS: &str = "/usr/local/share:/usr/share";
+
+/// Execution engine used to run the CPU.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// Execute each instruction in one go, bypassing the pipeline. Fast but
+    /// not cycle accurate.
+    Fast,
+    /// Run instructions through the data path's pipeline one clock phase at
+    /// a time.
+    Cycle,
+}
+
+/// How the clock is paced against the wall clock when running with windows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockMode {
+    /// Run as fast as the host can.
+    Unlimited,
+    /// Sleep to keep the configured clock rate.
+    Realtime,
+    /// Run a fixed number of instructions between frames of the windows.
+    Frame,
+}
+
+/// File format of execution traces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceFormat {
+    /// One line of text per instruction.
+    Text,
+    /// Fixed size binary records.
+    Binary,
+}
+
+/// How much of the pipeline's inner workings to log.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PipelineDebug {
+    /// Log nothing.
+    Off,
+    /// One line per clock cycle.
+    Cycle,
+    /// One line per clock cycle and the latches changed by every phase.
+    Phase,
+}
+
+/// Notation instructions are disassembled in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Syntax {
+    /// Lower case, destination first, e.g. `add.cc r16, r0, 0x1`. The
+    /// assembler reads this syntax.
+    Modern,
+    /// As in the Berkeley RISC papers, upper case and destination last, e.g.
+    /// `ADD r0,0x1,r16 {C}`.
+    Berkeley,
+}
+
+/// Format of state dumps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StateFormat {
+    Toml,
+    Json,
+}
+
+/// Format of profile reports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    /// Tables for people to read.
+    Text,
+    Json,
+}
+
+/// Layout of a framebuffer pixel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    /// One bit per pixel, most significant bit first, set bits are lit.
+    Mono,
+    /// One byte of brightness per pixel.
+    Gray8,
+    /// One byte per pixel: 3 bits red, 3 bits green, 2 bits blue.
+    Rgb332,
+    /// Two bytes per pixel: 5 bits red, 6 bits green, 5 bits blue.
+    Rgb565,
+    /// Four bytes per pixel: unused, red, green, blue.
+    Xrgb8888,
+}
+
+/// What memory holds at power on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryFill {
+    /// Every byte is 0x00.
+    Zero,
+    /// Every byte is 0xff.
+    Ones,
+    /// Every word is 0xdeadbeef.
+    Deadbeef,
+    /// Pseudo random bytes, the same for the same seed.
+    Random,
+}
+
+/// What the registers and latches of every core hold at power on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerOnState {
+    /// Every register is 0.
+    Zero,
+    /// Pseudo random values, the same for the same seed.
+    Random,
+    /// Every register is 0xdeadbeef.
+    Pattern,
+}
+
+/// Byte order of words in guest memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    /// Most significant byte first, how the RISC II stores words.
+    Big,
+    /// Least significant byte first.
+    Little,
+}
+
+/// Instruction set extension given to every core.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IsaExtension {
+    /// Integer multiply and divide.
+    Mul,
+}
+
+/// What the emulator was asked to do, picked by the first command line
+/// argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Run the system (the default).
+    Run,
+    /// Run the system with the debug window open.
+    Debug,
+    /// Assemble a source file into a memory image.
+    Asm(AsmArgs),
+    /// Disassemble a memory image.
+    Disasm(DisasmArgs),
+    /// Describe a snapshot file.
+    Snapshot(String),
+    /// Tell what differs between two snapshot files.
+    SnapshotDiff(String, String),
+    /// Print the configuration in effect, as TOML.
+    DumpConfig,
+    /// Run test programs.
+    Test(TestArgs),
+}
+
+/// Arguments of the `asm` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmArgs {
+    /// Path of the assembly source.
+    pub input: String,
+    /// Path of the memory image to write.
+    pub output: String,
+    /// Address the program will be loaded at.
+    pub origin: u32,
+    /// How to lay out the program.
+    pub options: Options,
+    /// Path to write the program's symbols to, if any.
+    pub symbols: Option<String>,
+    /// Byte order to write the program's words in.
+    pub endian: Endian,
+}
+
+/// Arguments of the `disasm` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmArgs {
+    /// Path of the memory image.
+    pub input: String,
+    /// Address the image is loaded at.
+    pub origin: u32,
+    /// Syntax to disassemble in.
+    pub syntax: Syntax,
+    /// Symbol files of the image.
+    pub symbols: Vec<String>,
+    /// Byte order of the image's words.
+    pub endian: Endian,
+    /// True if pipeline hazards should be listed after the disassembly.
+    pub hazards: bool,
+}
+
+/// Arguments of the `test` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestArgs {
+    /// Paths of the test programs and of directories of them.
+    pub paths: Vec<String>,
+    /// Engines to run each program on.
+    pub engines: Vec<Engine>,
+    /// Most clock cycles a program may run.
+    pub budget: u64,
+    /// Directory failure bundles are written to, None for `bundles` in the
+    /// cache directory.
+    pub bundles: Option<String>,
+}
+
+/// A file copied into memory before the system runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryImage {
+    /// Path of the file.
+    pub path: String,
+    /// Address the file is copied to.
+    pub address: u32,
+}
+
+/// Size and place of a window on the screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    /// Width of the window.
+    #[serde(default = "default_width")]
+    pub width: u32,
+    /// Height of the window.
+    #[serde(default = "default_height")]
+    pub height: u32,
+    /// Distance of the window's left edge from the screen's, None to center
+    /// the window.
+    #[serde(default)]
+    pub x: Option<i32>,
+    /// Distance of the window's top edge from the screen's, None to center
+    /// the window.
+    #[serde(default)]
+    pub y: Option<i32>,
+}
+
+/// Geometry of each window, the `[window.NAME]` sections of the
+/// configuration file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowLayout {
+    /// The main window.
+    #[serde(default = "default_window")]
+    pub main: WindowGeometry,
+    /// The debug window.
+    #[serde(default = "default_window")]
+    pub debug: WindowGeometry,
+}
+
+/// The serial console, the `[device.console]` section of the configuration
+/// file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsoleDevice {
+    /// Address of the console's registers.
+    #[serde(default = "default_console_base")]
+    pub base: u32,
+}
+
+/// The framebuffer the main window shows, the `[device.framebuffer]` section
+/// of the configuration file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FramebufferDevice {
+    /// Address of the framebuffer, None for no framebuffer.
+    #[serde(default = "default_fb_base")]
+    pub base: Option<u32>,
+    /// Width of the framebuffer (in pixels).
+    #[serde(default = "default_fb_width")]
+    pub width: u32,
+    /// Height of the framebuffer (in pixels).
+    #[serde(default = "default_fb_height")]
+    pub height: u32,
+    /// Layout of a framebuffer pixel.
+    #[serde(default = "default_fb_format")]
+    pub format: PixelFormat,
+}
+
+/// Devices the system is built with, the `[device.NAME]` sections of the
+/// configuration file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Devices {
+    /// The serial console.
+    #[serde(default = "default_console")]
+    pub console: ConsoleDevice,
+    /// The framebuffer.
+    #[serde(default = "default_framebuffer")]
+    pub framebuffer: FramebufferDevice,
+}
 
 /// Configuration of the emulator.
-#[derive(Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Amount of memory the system will have.
     #[serde(default = "default_mem")]
@@ -38,30 +338,197 @@ pub struct Config {
     /// Number of CPUs the system will have.
     #[serde(default = "default_ncpu")]
     ncpu: u32,
+    /// Number of register windows each CPU has.
+    #[serde(default = "default_windows")]
+    windows: u32,
+    /// What memory holds at power on.
+    #[serde(default = "default_mem_fill")]
+    mem_fill: MemoryFill,
+    /// Seed of the random memory fill and power on state.
+    #[serde(default = "default_mem_fill_seed")]
+    mem_fill_seed: u32,
+    /// What the registers and latches hold at power on.
+    #[serde(default = "default_poweron_state")]
+    poweron_state: PowerOnState,
+    /// Byte order of words in guest memory.
+    #[serde(default = "default_endian")]
+    endian: Endian,
+    /// Regions of memory and what they allow, none for no restrictions.
+    #[serde(skip)]
+    regions: Vec<Region>,
     /// Path to the configuration file.
-    #[serde(skip_deserializing)]
+    #[serde(skip)]
     config_file_path: String,
+    /// Profile of the configuration file in use, if any.
+    #[serde(skip)]
+    profile_name: Option<String>,
+    /// What the emulator was asked to do.
+    #[serde(skip, default = "default_command")]
+    command: Command,
+    /// Files copied into memory before the system runs, in order.
+    #[serde(skip)]
+    loads: Vec<MemoryImage>,
+    /// Address to start running at instead of the reset vector.
+    #[serde(default = "default_entry")]
+    entry: Option<u32>,
+    /// Symbol files of the guest program.
+    #[serde(default = "default_symbols")]
+    symbols: Vec<String>,
+    /// Addresses of the instructions that pause the system once they
+    /// retire.
+    #[serde(default = "default_breakpoints")]
+    breakpoints: Vec<u32>,
+    /// Address of the first handler of the trap vector table after a reset.
+    #[serde(default = "default_trap_vectors")]
+    trap_vectors: u32,
+    /// Instruction set extensions of every core, in order of attachment.
+    #[serde(default = "default_isa_ext")]
+    isa_ext: Vec<IsaExtension>,
     /// Path to the system cache directory.
     #[serde(default = "default_cache")]
     cache_path: String,
+    /// Path to the font the windows draw text in, None to search the data
+    /// directories for it.
+    #[serde(default = "default_font")]
+    font: Option<String>,
     /// The clock rate (in hertz).
     #[serde(default = "default_clock_rate")]
     clock_rate: u64,
-    /// Width of the window.
-    #[serde(default = "default_width")]
-    win_width: u32,
-    /// Height of the window.
-    #[serde(default = "default_height")]
-    win_height: u32,
-    /// Width of the window.
-    #[serde(default = "default_width")]
-    debug_win_width: u32,
-    /// Height of the window.
-    #[serde(default = "default_height")]
-    debug_win_height: u32,
+    /// How the clock is paced.
+    #[serde(default = "default_clock_mode")]
+    clock_mode: ClockMode,
+    /// Instructions run per frame when the clock mode is frame.
+    #[serde(default = "default_frame_instructions")]
+    frame_instructions: u64,
+    /// Geometry of each window.
+    #[serde(default = "default_window_layout")]
+    window: WindowLayout,
     /// True if in debug mode, false otherwise.
     #[serde(default = "default_debug_mode")]
     debug_mode: bool,
+    /// Execution engine.
+    #[serde(default = "default_engine")]
+    engine: Engine,
+    /// Entries of each core's decode cache on the fast engine, a power of
+    /// two, or 0 for none.
+    #[serde(default = "default_decode_cache")]
+    decode_cache: u32,
+    /// True if the instruction after a jump, call or return runs before the
+    /// target, as on a real RISC II.
+    #[serde(default = "default_delay_slots")]
+    delay_slots: bool,
+    /// True if running without any windows, false otherwise.
+    #[serde(default = "default_headless")]
+    headless: bool,
+    /// Address to serve the state of a headless system on, if any.
+    #[serde(default = "default_remote")]
+    remote: Option<String>,
+    /// True if the guest should be profiled.
+    #[serde(default = "default_profile")]
+    profile: bool,
+    /// Path to write the profile report to when done, if any.
+    #[serde(default = "default_profile_report")]
+    profile_report: Option<String>,
+    /// Format of the profile report.
+    #[serde(default = "default_profile_format")]
+    profile_format: ReportFormat,
+    /// True if load and store alignment should be tracked.
+    #[serde(default = "default_align_stats")]
+    align_stats: bool,
+    /// True if the system has a floating point coprocessor.
+    #[serde(default = "default_fpu")]
+    fpu: bool,
+    /// True if the host memory used should be reported.
+    #[serde(default = "default_usage_stats")]
+    usage_stats: bool,
+    /// Most host memory (in megabytes) the emulator may use before it
+    /// evicts old data, 0 for no limit.
+    #[serde(default = "default_host_mem_limit")]
+    host_mem_limit: u32,
+    /// Clock cycle a headless run stops at, 0 for no limit.
+    #[serde(default = "default_max_cycles")]
+    max_cycles: u64,
+    /// Longest (in seconds) a headless run may take, 0 for no limit.
+    #[serde(default = "default_timeout_seconds")]
+    timeout_seconds: u32,
+    /// Log levels of the subsystems, e.g. `pipeline=debug,system=warn`.
+    #[serde(default = "default_log")]
+    log: String,
+    /// True if both engines should run side by side and be compared.
+    #[serde(default = "default_cosim")]
+    cosim: bool,
+    /// True if the program should run twice to check the emulator is
+    /// deterministic.
+    #[serde(default = "default_audit_determinism")]
+    audit_determinism: bool,
+    /// Instructions between comparisons of the two runs of a determinism
+    /// audit.
+    #[serde(default = "default_audit_every")]
+    audit_every: u32,
+    /// True if the instruction stream should be checked for anomalies.
+    #[serde(default = "default_detect_anomalies")]
+    detect_anomalies: bool,
+    /// Times an instruction may run without changing anything before it is
+    /// reported as a livelock.
+    #[serde(default = "default_livelock_limit")]
+    livelock_limit: u32,
+    /// True if the emulator should pause when an anomaly is found.
+    #[serde(default = "default_pause_on_anomaly")]
+    pause_on_anomaly: bool,
+    /// True if the instruction stream should be checked for pipeline
+    /// hazards.
+    #[serde(default = "default_detect_hazards")]
+    detect_hazards: bool,
+    /// Snapshot to restore at startup, if any.
+    #[serde(default = "default_snapshot")]
+    load_snapshot: Option<String>,
+    /// Path to save a snapshot to when the emulator exits, if any.
+    #[serde(default = "default_snapshot")]
+    save_snapshot: Option<String>,
+    /// Expression that captures a snapshot whenever it becomes true, if any.
+    #[serde(default = "default_snapshot")]
+    snapshot_when: Option<String>,
+    /// Clock cycles between snapshots saved to the cache directory, 0 for
+    /// none.
+    #[serde(default = "default_snapshot_every")]
+    snapshot_every: u32,
+    /// Path to log the inputs from outside the guest to, if any.
+    #[serde(default = "default_inputs")]
+    record_inputs: Option<String>,
+    /// Input log to feed the guest instead of the host's inputs, if any.
+    #[serde(default = "default_inputs")]
+    replay_inputs: Option<String>,
+    /// Path to write an execution trace to, if any.
+    #[serde(default = "default_trace")]
+    trace: Option<String>,
+    /// Format of the execution trace.
+    #[serde(default = "default_trace_format")]
+    trace_format: TraceFormat,
+    /// How much of the pipeline's inner workings to log.
+    #[serde(default = "default_pipeline_debug")]
+    pipeline_debug: PipelineDebug,
+    /// Path to write the pipeline log to, None for standard error.
+    #[serde(default = "default_pipeline_debug_log")]
+    pipeline_debug_log: Option<String>,
+    /// Syntax instructions are disassembled in.
+    #[serde(default = "default_syntax")]
+    syntax: Syntax,
+    /// Path to write a dump of the state to when the emulator exits, if any.
+    #[serde(default = "default_export_state")]
+    export_state: Option<String>,
+    /// Format of the state dump.
+    #[serde(default = "default_export_format")]
+    export_format: StateFormat,
+    /// Memory ranges (`start:len`, comma separated) to include in the state
+    /// dump.
+    #[serde(default = "default_export_memory")]
+    export_memory: String,
+    /// Devices the system is built with.
+    #[serde(default = "default_devices")]
+    device: Devices,
+    /// Disk image backing the block device, None for no block device.
+    #[serde(default = "default_disk_image")]
+    disk_image: Option<String>,
 }
 
 // Struct impls.
@@ -69,27 +536,71 @@ pub struct Config {
 impl Config {
     /// Create a new configuration object (with default settings) on success and a string on error.
     pub fn new() -> Result<Config> {
-        let home_dir = get_home_nofail();
-        // Find a configuration path specified on the command line.
-        let config_path = match env::var("XDG_CONFIG_HOME") {
-            Ok(v) => format!("{}", v),
-            Err(e) => format!("{}", home_dir),
-        };
+        let config_dir = xdg_dir("XDG_CONFIG_HOME", ".config")?;
 
         Ok(Config {
-            mem: 0,
-            ncpu: 0,
-            config_file_path: concat_paths(
-                &config_path,
-                &".config/riscii/config.toml".to_string(),
-            )?,
-            clock_rate: 0,
-            cache_path: String::new(),
-            win_width: 0,
-            win_height: 0,
-            debug_win_width: 0,
-            debug_win_height: 0,
-            debug_mode: false,
+            mem: default_mem(),
+            ncpu: default_ncpu(),
+            windows: default_windows(),
+            mem_fill: default_mem_fill(),
+            mem_fill_seed: default_mem_fill_seed(),
+            poweron_state: default_poweron_state(),
+            endian: default_endian(),
+            regions: Vec::new(),
+            config_file_path: concat_paths(&config_dir, &"riscii/config.toml".to_string())?,
+            profile_name: None,
+            command: default_command(),
+            loads: Vec::new(),
+            entry: default_entry(),
+            symbols: default_symbols(),
+            breakpoints: default_breakpoints(),
+            trap_vectors: default_trap_vectors(),
+            isa_ext: default_isa_ext(),
+            clock_rate: default_clock_rate(),
+            clock_mode: default_clock_mode(),
+            frame_instructions: default_frame_instructions(),
+            cache_path: default_cache(),
+            font: default_font(),
+            window: default_window_layout(),
+            debug_mode: default_debug_mode(),
+            engine: default_engine(),
+            decode_cache: default_decode_cache(),
+            delay_slots: default_delay_slots(),
+            headless: default_headless(),
+            remote: default_remote(),
+            profile: default_profile(),
+            profile_report: default_profile_report(),
+            profile_format: default_profile_format(),
+            align_stats: default_align_stats(),
+            fpu: default_fpu(),
+            usage_stats: default_usage_stats(),
+            host_mem_limit: default_host_mem_limit(),
+            max_cycles: default_max_cycles(),
+            timeout_seconds: default_timeout_seconds(),
+            log: default_log(),
+            cosim: default_cosim(),
+            audit_determinism: default_audit_determinism(),
+            audit_every: default_audit_every(),
+            detect_anomalies: default_detect_anomalies(),
+            livelock_limit: default_livelock_limit(),
+            pause_on_anomaly: default_pause_on_anomaly(),
+            detect_hazards: default_detect_hazards(),
+            load_snapshot: default_snapshot(),
+            save_snapshot: default_snapshot(),
+            snapshot_when: default_snapshot(),
+            snapshot_every: default_snapshot_every(),
+            record_inputs: default_inputs(),
+            replay_inputs: default_inputs(),
+            trace: default_trace(),
+            trace_format: default_trace_format(),
+            pipeline_debug: default_pipeline_debug(),
+            pipeline_debug_log: default_pipeline_debug_log(),
+            syntax: default_syntax(),
+            export_state: default_export_state(),
+            export_format: default_export_format(),
+            export_memory: default_export_memory(),
+            device: default_devices(),
+            disk_image: default_disk_image(),
         })
     }
 
@@ -98,60 +609,105 @@ impl Config {
         let mut config = Self::new()?;
         let args: Vec<String> = env::args().collect();
         // Look for custom config file location first. Read it, then override with cmd args.
-        let cmd_config_file = config.find_cmd_config_path(&args)?;
+        let cmd_config_file = args_find(&args, "config_path")?;
+        // Only a file asked for has to exist.
+        let required = cmd_config_file.is_some();
 
         config.config_file_path = match cmd_config_file {
             None => config.config_file_path,
             Some(s) => s.to_string(),
         };
 
-        config.read_config_file()?;
+        config.read_config_file(args_find(&args, "profile_name")?, required)?;
         config.parse_cmd_args(&args)?;
         Ok(config)
     }
 
     /// Read the user's configuration file and update configuration state
-    /// (default ~/.config/riscii/config.toml). Return void on success and a
-    /// string on error.
-    fn read_config_file(&mut self) -> Result<()> {
-        // TODO do not exit if config.toml does not exist
-        // TODO get ~ in paths to expand
-        // Keep the data we want to survive the assignment.
-        let config_file_path = self.config_file_path.clone();
-        *self = match toml::from_str(&match fs::read_to_string(Path::new(&config_file_path)) {
-            Err(e) => return berr!(format!("Could not read {}, {}", config_file_path, e)),
+    /// (default $XDG_CONFIG_HOME/riscii/config.toml). Return void on success
+    /// and a string on error.
+    /// # Arguments
+    /// * `profile` - Name of the profile to use, if any.
+    /// * `required` - False if a missing file means the defaults.
+    fn read_config_file(&mut self, profile: Option<String>, required: bool) -> Result<()> {
+        let path = self.config_file_path.clone();
+        *self = Self::from_file(&path, profile.as_ref().map(|p| p.as_str()), required)?;
+        Ok(())
+    }
+
+    /// Read a configuration file, see `from_toml`. Return the configuration
+    /// on success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the file, `~` and variables are expanded.
+    /// * `profile` - Name of the profile to use, if any.
+    /// * `required` - False if a file that does not exist gives the
+    /// defaults, true if it is an error.
+    pub fn from_file(path: &str, profile: Option<&str>, required: bool) -> Result<Config> {
+        let path = expand_path(path)?;
+        let path = path.as_str();
+        let text = match fs::read_to_string(Path::new(path)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && !required => String::new(),
+            Err(e) => return Err(EmulatorError::io(format!("Could not read {}", path), e)),
             Ok(r) => r,
-        }) {
+        };
+        let mut config = match Self::from_toml(&text, profile) {
             Err(e) => {
-                return berr!(format!(
-                    "Could not parse config file {}, {}",
-                    config_file_path, e
-                ))
+                return berr!(
+                    Config,
+                    format!("Could not parse config file {}, {}", path, e)
+                )
             }
             Ok(r) => r,
         };
-
-        self.config_file_path = config_file_path;
-
-        Ok(())
+        config.config_file_path = path.to_string();
+        Ok(config)
     }
 
-    /// Parse CMD arguments for configuration file path. Return path on
-    /// success and string on error.
+    /// Read a configuration from TOML. The settings of a profile, a
+    /// `[profiles.NAME]` section, take precedence over the rest if one is
+    /// named, settings given nowhere keep their defaults. Return the
+    /// configuration on success and a string on error.
     /// # Arguments
-    /// * `args` - CMD argument vector.
-    fn find_cmd_config_path(&self, args: &Vec<String>) -> Result<Option<String>> {
-        for (i, arg) in args.iter().enumerate() {
-            match arg.as_str() {
-                "--config_path" => {
-                    return Ok(Some(
-                        args_get_next_arg(&args, i, &format!("config_path"))?.clone(),
-                    ))
-                }
-                _ => (),
+    /// * `text` - The configuration.
+    /// * `profile` - Name of the profile to use, if any.
+    pub fn from_toml(text: &str, profile: Option<&str>) -> Result<Config> {
+        let mut table = match toml::from_str::<Table>(text) {
+            Ok(t) => t,
+            Err(e) => return berr!(Config, format!("{}", e)),
+        };
+        let profiles = match table.remove("profiles") {
+            Some(Value::Table(t)) => t,
+            Some(_) => return berr!(Config, "profiles is not a section"),
+            None => Table::new(),
+        };
+        if let Some(name) = profile {
+            match profiles.get(name) {
+                Some(Value::Table(p)) => merge_tables(&mut table, p),
+                Some(_) => return berr!(Config, format!("Profile {} is not a section", name)),
+                None => return berr!(Config, format!("There is no profile {}", name)),
             }
         }
-        Ok(None)
+        let mut config: Config = match Value::Table(table).try_into() {
+            Ok(c) => c,
+            Err(e) => return berr!(Config, format!("{}", e)),
+        };
+        config.profile_name = profile.map(|p| p.to_string());
+        config.cache_path = expand_path(&config.cache_path)?;
+        config.font = match config.font {
+            Some(font) => Some(expand_path(&font)?),
+            None => None,
+        };
+        Ok(config)
+    }
+
+    /// Get the configuration as TOML, as the configuration file would give
+    /// it. Return the TOML on success and a string on error.
+    pub fn to_toml(&self) -> Result<String> {
+        // Going through a value puts every table after the plain settings.
+        match Value::try_from(self).and_then(|v| toml::to_string(&v)) {
+            Ok(s) => Ok(s),
+            Err(e) => berr!(Config, format!("Could not write the configuration, {}", e)),
+        }
     }
 
     /// Parse CMD args and update configuration state. Return void on success
@@ -159,7 +715,39 @@ impl Config {
     /// # Arguments
     /// * `args` - CMD argument vector.
     fn parse_cmd_args(&mut self, args: &Vec<String>) -> Result<()> {
-        let mut skips = 1i32;
+        // The command, if there is one, comes first.
+        let mut skips = match args.get(1).map(|a| a.as_str()) {
+            Some("run") => 2i32,
+            Some("debug") => {
+                self.command = Command::Debug;
+                self.debug_mode = true;
+                2
+            }
+            Some("asm") => return self.parse_asm_args(args),
+            Some("disasm") => return self.parse_disasm_args(args),
+            Some("test") => {
+                self.command = Command::Test(TestArgs {
+                    paths: Vec::new(),
+                    engines: vec![Engine::Fast, Engine::Cycle],
+                    budget: DEFAULT_BUDGET,
+                    bundles: None,
+                });
+                2
+            }
+            Some("snapshot") if args.get(2).map(|a| a.as_str()) == Some("diff") => {
+                self.command = Command::SnapshotDiff(
+                    args_get_next_arg(&args, 2, &format!("snapshot diff"))?.clone(),
+                    args_get_next_arg(&args, 3, &format!("snapshot diff"))?.clone(),
+                );
+                5
+            }
+            Some("snapshot") => {
+                self.command =
+                    Command::Snapshot(args_get_next_arg(&args, 1, &format!("snapshot"))?.clone());
+                3
+            }
+            _ => 1,
+        };
         for (i, arg) in args.iter().enumerate() {
             if skips > 0 {
                 skips -= 1;
@@ -175,130 +763,1784 @@ impl Config {
                     self.ncpu = args_get_next_uint(&args, i, &format!("ncpu"))?;
                     skips += 1;
                 }
+                "--windows" => {
+                    self.windows = args_get_next_uint(&args, i, &format!("windows"))?;
+                    skips += 1;
+                }
+                "--mem_fill" => {
+                    self.mem_fill =
+                        MemoryFill::from_name(args_get_next_arg(&args, i, &format!("mem_fill"))?)?;
+                    skips += 1;
+                }
+                "--mem_fill_seed" | "--poweron_seed" => {
+                    self.mem_fill_seed = args_get_next_uint(&args, i, &format!("mem_fill_seed"))?;
+                    skips += 1;
+                }
+                "--poweron_state" => {
+                    self.poweron_state = PowerOnState::from_name(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("poweron_state"),
+                    )?)?;
+                    self.mem_fill = self.poweron_state.mem_fill();
+                    skips += 1;
+                }
+                "--endian" => {
+                    self.endian =
+                        Endian::from_name(args_get_next_arg(&args, i, &format!("endian"))?)?;
+                    skips += 1;
+                }
+                "--region" => {
+                    self.regions.push(parse_region(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("region"),
+                    )?)?);
+                    skips += 1;
+                }
                 "--cache_path" => {
-                    self.cache_path = args_get_next_arg(&args, i, &format!("cache_path"))?.clone();
+                    self.cache_path =
+                        expand_path(args_get_next_arg(&args, i, &format!("cache_path"))?)?;
+                    skips += 1;
+                }
+                "--font" => {
+                    self.font = Some(expand_path(args_get_next_arg(&args, i, &format!("font"))?)?);
                     skips += 1;
                 }
-                // Skip this argument since it is special.
-                "--config_path" => {
-                    args_get_next_arg(&args, i, &format!("config_path"))?;
+                // Skip these arguments since they are special.
+                "--config_path" | "--profile_name" => {
+                    args_get_next_arg(&args, i, &format!("{}", &arg[2..]))?;
                     skips += 1;
                 }
+                "--dump_config" => {
+                    self.command = Command::DumpConfig;
+                }
                 "--win_width" => {
-                    self.win_width = args_get_next_uint(&args, i, &format!("win_width"))?;
+                    self.window.main.width = args_get_next_uint(&args, i, &format!("win_width"))?;
                     skips += 1;
                 }
                 "--win_height" => {
-                    self.win_height = args_get_next_uint(&args, i, &format!("win_height"))?;
+                    self.window.main.height = args_get_next_uint(&args, i, &format!("win_height"))?;
+                    skips += 1;
+                }
+                "--engine" => {
+                    self.engine =
+                        Engine::from_name(args_get_next_arg(&args, i, &format!("engine"))?)?;
+                    skips += 1;
+                }
+                "--clock" => {
+                    self.clock_mode =
+                        ClockMode::from_name(args_get_next_arg(&args, i, &format!("clock"))?)?;
+                    skips += 1;
+                }
+                "--clock_rate" => {
+                    self.clock_rate = args_get_next_uint(&args, i, &format!("clock_rate"))? as u64;
+                    skips += 1;
+                }
+                "--frame_instructions" => {
+                    self.frame_instructions =
+                        args_get_next_uint(&args, i, &format!("frame_instructions"))? as u64;
+                    skips += 1;
+                }
+                "--fast" => {
+                    self.engine = Engine::Fast;
+                }
+                "--decode_cache" => {
+                    self.decode_cache = args_get_next_uint(&args, i, &format!("decode_cache"))?;
+                    skips += 1;
+                }
+                "--no_delay_slots" => {
+                    self.delay_slots = false;
+                }
+                "--headless" => {
+                    self.headless = true;
+                }
+                "--remote" => {
+                    self.headless = true;
+                    self.remote = Some(args_get_next_arg(&args, i, &format!("remote"))?.clone());
+                    skips += 1;
+                }
+                "--profile" => {
+                    self.profile = true;
+                }
+                "--profile_report" => {
+                    self.profile = true;
+                    self.profile_report =
+                        Some(args_get_next_arg(&args, i, &format!("profile_report"))?.clone());
+                    skips += 1;
+                }
+                "--profile_format" => {
+                    self.profile_format = ReportFormat::from_name(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("profile_format"),
+                    )?)?;
+                    skips += 1;
+                }
+                "--align_stats" => {
+                    self.align_stats = true;
+                }
+                "--fpu" => {
+                    self.fpu = true;
+                }
+                "--usage_stats" => {
+                    self.usage_stats = true;
+                }
+                "--host_mem_limit" => {
+                    self.host_mem_limit = args_get_next_uint(&args, i, &format!("host_mem_limit"))?;
+                    skips += 1;
+                }
+                "--max_cycles" => {
+                    self.max_cycles = args_get_next_u64(&args, i, &format!("max_cycles"))?;
                     skips += 1;
                 }
+                "--timeout_seconds" => {
+                    self.timeout_seconds =
+                        args_get_next_uint(&args, i, &format!("timeout_seconds"))?;
+                    skips += 1;
+                }
+                "--log" => {
+                    let spec = args_get_next_arg(&args, i, &format!("log"))?;
+                    LogSpec::parse(spec)?;
+                    self.log = spec.clone();
+                    skips += 1;
+                }
+                "--cosim" => {
+                    self.cosim = true;
+                }
+                "--audit_determinism" => {
+                    self.audit_determinism = true;
+                }
+                "--audit_every" => {
+                    self.audit_every = args_get_next_uint(&args, i, &format!("audit_every"))?;
+                    skips += 1;
+                }
+                "--detect_anomalies" => {
+                    self.detect_anomalies = true;
+                }
+                "--livelock_limit" => {
+                    self.livelock_limit = args_get_next_uint(&args, i, &format!("livelock_limit"))?;
+                    skips += 1;
+                }
+                "--pause_on_anomaly" => {
+                    self.detect_anomalies = true;
+                    self.pause_on_anomaly = true;
+                }
+                "--detect_hazards" => {
+                    self.detect_hazards = true;
+                }
+                "--load_snapshot" => {
+                    self.load_snapshot =
+                        Some(args_get_next_arg(&args, i, &format!("load_snapshot"))?.clone());
+                    skips += 1;
+                }
+                "--save_snapshot" => {
+                    self.save_snapshot =
+                        Some(args_get_next_arg(&args, i, &format!("save_snapshot"))?.clone());
+                    skips += 1;
+                }
+                "--snapshot_when" => {
+                    self.snapshot_when =
+                        Some(args_get_next_arg(&args, i, &format!("snapshot_when"))?.clone());
+                    skips += 1;
+                }
+                "--snapshot_every" => {
+                    self.snapshot_every = args_get_next_uint(&args, i, &format!("snapshot_every"))?;
+                    skips += 1;
+                }
+                "--record_inputs" => {
+                    self.record_inputs =
+                        Some(args_get_next_arg(&args, i, &format!("record_inputs"))?.clone());
+                    skips += 1;
+                }
+                "--replay_inputs" => {
+                    self.replay_inputs =
+                        Some(args_get_next_arg(&args, i, &format!("replay_inputs"))?.clone());
+                    skips += 1;
+                }
+                "--trace" => {
+                    self.trace = Some(args_get_next_arg(&args, i, &format!("trace"))?.clone());
+                    skips += 1;
+                }
+                "--trace_format" => {
+                    self.trace_format = TraceFormat::from_name(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("trace_format"),
+                    )?)?;
+                    skips += 1;
+                }
+                "--pipeline_debug" => {
+                    self.pipeline_debug = PipelineDebug::from_name(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("pipeline_debug"),
+                    )?)?;
+                    skips += 1;
+                }
+                "--pipeline_debug_log" => {
+                    self.pipeline_debug_log =
+                        Some(args_get_next_arg(&args, i, &format!("pipeline_debug_log"))?.clone());
+                    skips += 1;
+                }
+                "--syntax" => {
+                    self.syntax =
+                        Syntax::from_name(args_get_next_arg(&args, i, &format!("syntax"))?)?;
+                    skips += 1;
+                }
+                "--export_state" => {
+                    self.export_state =
+                        Some(args_get_next_arg(&args, i, &format!("export_state"))?.clone());
+                    skips += 1;
+                }
+                "--export_format" => {
+                    self.export_format = StateFormat::from_name(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("export_format"),
+                    )?)?;
+                    skips += 1;
+                }
+                "--export_memory" => {
+                    self.export_memory =
+                        args_get_next_arg(&args, i, &format!("export_memory"))?.clone();
+                    skips += 1;
+                }
+                "--console_base" => {
+                    self.device.console.base =
+                        args_get_next_addr(&args, i, &format!("console_base"))?;
+                    skips += 1;
+                }
+                "--fb_base" => {
+                    self.device.framebuffer.base =
+                        Some(args_get_next_addr(&args, i, &format!("fb_base"))?);
+                    skips += 1;
+                }
+                "--fb_width" => {
+                    self.device.framebuffer.width =
+                        args_get_next_uint(&args, i, &format!("fb_width"))?;
+                    skips += 1;
+                }
+                "--fb_height" => {
+                    self.device.framebuffer.height =
+                        args_get_next_uint(&args, i, &format!("fb_height"))?;
+                    skips += 1;
+                }
+                "--fb_format" => {
+                    self.device.framebuffer.format = PixelFormat::from_name(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("fb_format"),
+                    )?)?;
+                    skips += 1;
+                }
+                "--disk_image" => {
+                    self.disk_image =
+                        Some(args_get_next_arg(&args, i, &format!("disk_image"))?.clone());
+                    skips += 1;
+                }
+                "--image" | "--load" => {
+                    self.loads.push(MemoryImage::parse(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("image"),
+                    )?)?);
+                    skips += 1;
+                }
+                "--entry" => {
+                    self.entry = Some(args_get_next_addr(&args, i, &format!("entry"))?);
+                    skips += 1;
+                }
+                "--symbols" => {
+                    self.symbols
+                        .push(args_get_next_arg(&args, i, &format!("symbols"))?.clone());
+                    skips += 1;
+                }
+                "--breakpoint" => {
+                    self.breakpoints
+                        .push(args_get_next_addr(&args, i, &format!("breakpoint"))?);
+                    skips += 1;
+                }
+                "--trap_vectors" => {
+                    self.trap_vectors = args_get_next_addr(&args, i, &format!("trap vectors"))?;
+                    skips += 1;
+                }
+                "--isa_ext" => {
+                    self.add_isa_extension(IsaExtension::from_name(args_get_next_arg(
+                        &args,
+                        i,
+                        &format!("isa_ext"),
+                    )?)?);
+                    skips += 1;
+                }
+                "--budget" if matches!(self.command, Command::Test(_)) => {
+                    let budget = args_get_next_uint(&args, i, &format!("budget"))? as u64;
+                    if let Command::Test(ref mut test) = self.command {
+                        test.budget = budget;
+                    }
+                    skips += 1;
+                }
+                "--bundles" if matches!(self.command, Command::Test(_)) => {
+                    let bundles = args_get_next_arg(&args, i, &format!("bundles"))?.clone();
+                    if let Command::Test(ref mut test) = self.command {
+                        test.bundles = Some(bundles);
+                    }
+                    skips += 1;
+                }
+                // A test program, or a directory of them.
+                a if !a.starts_with('-') && matches!(self.command, Command::Test(_)) => {
+                    if let Command::Test(ref mut test) = self.command {
+                        test.paths.push(a.to_string());
+                    }
+                }
+                // A memory image to load, e.g. the program to run.
+                a if !a.starts_with('-')
+                    && !matches!(
+                        self.command,
+                        Command::Snapshot(_) | Command::SnapshotDiff(_, _)
+                    ) =>
+                {
+                    self.loads.push(MemoryImage::parse(a)?);
+                }
                 _ => {
-                    println!(
-                        "Usage: riscii [OPTIONS]
---config_path       Path to configuration file (default=~/.config/riscii/)
---config_file_path  Path to the configuration file (default=~/.config/riscii/config.toml)
---mem               Size of memory (in megabytes) (default=512)
---ncpu              Number of cores to emulate (default=1)
-"
-                    );
-                    return berr!(format!("Invalid command line argument: {}", arg));
+                    print_usage();
+                    return berr!(Config, format!("Invalid command line argument: {}", arg));
                 }
             }
         }
+        if let Command::Test(ref mut test) = self.command {
+            if test.paths.is_empty() {
+                return berr!(Config, "test needs a test program or a directory of them");
+            }
+            // Programs run on both engines unless one is asked for.
+            if args_find(&args, "engine")?.is_some() || args.iter().any(|a| a == "--fast") {
+                test.engines = vec![self.engine];
+            }
+        }
         Ok(())
     }
 
-    // Getters.
-
-    /// Get the user's configured window width.
-    pub fn get_debug_win_width(&self) -> u32 {
-        self.debug_win_width
-    }
-
-    /// Get the user's configured window height.
-    pub fn get_debug_win_height(&self) -> u32 {
-        self.debug_win_height
-    }
+    /// Parse the arguments of the `asm` command. Return void on success and
+    /// a string on error.
+    /// # Arguments
+    /// * `args` - CMD argument vector.
+    fn parse_asm_args(&mut self, args: &Vec<String>) -> Result<()> {
+        let mut input = None;
+        let mut output = None;
+        let mut origin = 0;
+        let mut options = Options::default();
+        let mut symbols = None;
+        let mut skips = 2i32;
+        for (i, arg) in args.iter().enumerate() {
+            if skips > 0 {
+                skips -= 1;
+                continue;
+            }
 
-    /// Get the user's configured window width.
-    pub fn get_win_width(&self) -> u32 {
-        self.win_width
+            match arg.as_str() {
+                "-o" | "--output" => {
+                    output = Some(args_get_next_arg(&args, i, &format!("output"))?.clone());
+                    skips += 1;
+                }
+                "--origin" => {
+                    origin = args_get_next_addr(&args, i, &format!("origin"))?;
+                    skips += 1;
+                }
+                "--fill_delay_slots" => {
+                    options.fill_delay_slots = true;
+                }
+                "--schedule_delay_slots" => {
+                    options.fill_delay_slots = true;
+                    options.schedule_delay_slots = true;
+                }
+                "--align_targets" => {
+                    options.align_targets =
+                        args_get_next_uint(&args, i, &format!("align_targets"))?;
+                    skips += 1;
+                }
+                "--symbols" => {
+                    symbols = Some(args_get_next_arg(&args, i, &format!("symbols"))?.clone());
+                    skips += 1;
+                }
+                "--endian" => {
+                    self.endian =
+                        Endian::from_name(args_get_next_arg(&args, i, &format!("endian"))?)?;
+                    skips += 1;
+                }
+                // Skip these arguments since they are special.
+                "--config_path" | "--profile_name" => {
+                    args_get_next_arg(&args, i, &format!("{}", &arg[2..]))?;
+                    skips += 1;
+                }
+                a if !a.starts_with('-') && input.is_none() => input = Some(a.to_string()),
+                _ => {
+                    print_usage();
+                    return berr!(Config, format!("Invalid command line argument: {}", arg));
+                }
+            }
+        }
+        self.command = match (input, output) {
+            (Some(input), Some(output)) => Command::Asm(AsmArgs {
+                input: input,
+                output: output,
+                origin: origin,
+                options: options,
+                symbols: symbols,
+                endian: self.endian,
+            }),
+            (None, _) => return berr!(Config, "asm needs a source file"),
+            (_, None) => return berr!(Config, "asm needs an output file (-o)"),
+        };
+        Ok(())
     }
 
-    /// Get the user's configured window height.
-    pub fn get_win_height(&self) -> u32 {
-        self.win_height
-    }
+    /// Parse the arguments of the `disasm` command. Return void on success
+    /// and a string on error.
+    /// # Arguments
+    /// * `args` - CMD argument vector.
+    fn parse_disasm_args(&mut self, args: &Vec<String>) -> Result<()> {
+        let mut input = None;
+        let mut origin = 0;
+        let mut hazards = false;
+        let mut skips = 2i32;
+        for (i, arg) in args.iter().enumerate() {
+            if skips > 0 {
+                skips -= 1;
+                continue;
+            }
 
-    /// Get the user's configured memory size.
+            match arg.as_str() {
+                "--origin" => {
+                    origin = args_get_next_addr(&args, i, &format!("origin"))?;
+                    skips += 1;
+                }
+                "--syntax" => {
+                    self.syntax =
+                        Syntax::from_name(args_get_next_arg(&args, i, &format!("syntax"))?)?;
+                    skips += 1;
+                }
+                "--symbols" => {
+                    self.symbols
+                        .push(args_get_next_arg(&args, i, &format!("symbols"))?.clone());
+                    skips += 1;
+                }
+                "--endian" => {
+                    self.endian =
+                        Endian::from_name(args_get_next_arg(&args, i, &format!("endian"))?)?;
+                    skips += 1;
+                }
+                "--hazards" => {
+                    hazards = true;
+                }
+                // Skip these arguments since they are special.
+                "--config_path" | "--profile_name" => {
+                    args_get_next_arg(&args, i, &format!("{}", &arg[2..]))?;
+                    skips += 1;
+                }
+                a if !a.starts_with('-') && input.is_none() => input = Some(a.to_string()),
+                _ => {
+                    print_usage();
+                    return berr!(Config, format!("Invalid command line argument: {}", arg));
+                }
+            }
+        }
+        self.command = match input {
+            Some(input) => Command::Disasm(DisasmArgs {
+                input: input,
+                origin: origin,
+                syntax: self.syntax,
+                symbols: self.symbols.clone(),
+                endian: self.endian,
+                hazards: hazards,
+            }),
+            None => return berr!(Config, "disasm needs a memory image"),
+        };
+        Ok(())
+    }
+
+    // Getters.
+
+    /// Get the user's configured window width.
+    pub fn get_debug_win_width(&self) -> u32 {
+        self.window.debug.width
+    }
+
+    /// Get the user's configured window height.
+    pub fn get_debug_win_height(&self) -> u32 {
+        self.window.debug.height
+    }
+
+    /// Get the user's configured window width.
+    pub fn get_win_width(&self) -> u32 {
+        self.window.main.width
+    }
+
+    /// Get the user's configured window height.
+    pub fn get_win_height(&self) -> u32 {
+        self.window.main.height
+    }
+
+    /// Get the user's configured geometry of each window.
+    pub fn get_window_layout(&self) -> WindowLayout {
+        self.window
+    }
+
+    /// Get the devices the system is built with.
+    pub fn get_devices(&self) -> &Devices {
+        &self.device
+    }
+
+    /// Get the address of the console's registers.
+    pub fn get_console_base(&self) -> u32 {
+        self.device.console.base
+    }
+
+    /// Get the name of the profile in use, None if there is none.
+    pub fn get_profile_name(&self) -> Option<&String> {
+        self.profile_name.as_ref()
+    }
+
+    /// Get the user's configured memory size.
     pub fn get_mem_size(&self) -> u32 {
         self.mem
     }
 
-    /// Get the user's configured number of CPUs.
-    pub fn get_ncpus(&self) -> u32 {
-        self.ncpu
+    /// Get the user's configured number of CPUs.
+    pub fn get_ncpus(&self) -> u32 {
+        self.ncpu
+    }
+
+    /// Get the user's configured number of register windows.
+    pub fn get_windows(&self) -> u32 {
+        self.windows
+    }
+
+    /// Get what the emulator was asked to do.
+    pub fn get_command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Get the files to copy into memory before the system runs, in order.
+    pub fn get_loads(&self) -> &[MemoryImage] {
+        &self.loads
+    }
+
+    /// Get the paths of the guest program's symbol files.
+    pub fn get_symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    /// Get the addresses of the instructions that pause the system once
+    /// they retire.
+    pub fn get_breakpoints(&self) -> &[u32] {
+        &self.breakpoints
+    }
+
+    /// Get the address of the first handler of the trap vector table after a
+    /// reset, the others follow `TRAP_VECTOR_SPACING` bytes apart.
+    pub fn get_trap_vectors(&self) -> u32 {
+        self.trap_vectors
+    }
+
+    /// Set the address of the trap vector table after a reset.
+    /// # Arguments
+    /// * `base` - Address of the first handler.
+    pub fn set_trap_vectors(&mut self, base: u32) {
+        self.trap_vectors = base;
+    }
+
+    /// Get the instruction set extensions of every core, in order of
+    /// attachment.
+    pub fn get_isa_extensions(&self) -> &[IsaExtension] {
+        &self.isa_ext
+    }
+
+    /// Give every core an instruction set extension, doing nothing if they
+    /// have it.
+    /// # Arguments
+    /// * `ext` - The extension.
+    pub fn add_isa_extension(&mut self, ext: IsaExtension) {
+        if !self.isa_ext.contains(&ext) {
+            self.isa_ext.push(ext);
+        }
+    }
+
+    /// Get the address to start running at, if it is not the reset vector.
+    pub fn get_entry(&self) -> Option<u32> {
+        self.entry
+    }
+
+    /// Get what memory holds at power on.
+    pub fn get_mem_fill(&self) -> MemoryFill {
+        self.mem_fill
+    }
+
+    /// Get the seed of the random memory fill and power on state.
+    pub fn get_mem_fill_seed(&self) -> u32 {
+        self.mem_fill_seed
+    }
+
+    /// Get what the registers and latches hold at power on.
+    pub fn get_poweron_state(&self) -> PowerOnState {
+        self.poweron_state
+    }
+
+    /// Get the regions of memory and what they allow.
+    pub fn get_regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Declare a region of memory. Regions declared later take precedence.
+    /// # Arguments
+    /// * `region` - The region.
+    pub fn add_region(&mut self, region: Region) {
+        self.regions.push(region);
+    }
+
+    /// Get the byte order of words in guest memory.
+    pub fn get_endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Set the byte order of words in guest memory.
+    /// # Arguments
+    /// * `endian` - Byte order.
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
+    }
+
+    /// Set the size of memory.
+    /// # Arguments
+    /// * `size` - Size of memory in bytes.
+    pub fn set_mem_size(&mut self, size: u32) {
+        self.mem = size;
+    }
+
+    /// Set what the registers, latches and memory hold at power on.
+    /// # Arguments
+    /// * `state` - The power on state.
+    /// * `seed` - Seed of the random state.
+    pub fn set_poweron_state(&mut self, state: PowerOnState, seed: u32) {
+        self.poweron_state = state;
+        self.mem_fill = state.mem_fill();
+        self.mem_fill_seed = seed;
+    }
+
+    /// Set the number of cores to emulate.
+    /// # Arguments
+    /// * `ncpus` - Number of cores.
+    pub fn set_ncpus(&mut self, ncpus: u32) {
+        self.ncpu = ncpus;
+    }
+
+    /// Set the number of register windows of every core.
+    /// # Arguments
+    /// * `windows` - Number of register windows.
+    pub fn set_windows(&mut self, windows: u32) {
+        self.windows = windows;
+    }
+
+    /// Get the debug mode option.
+    pub fn is_debug_mode(&self) -> bool {
+        self.debug_mode
+    }
+
+    /// Get the headless mode option.
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
+    /// Get the address to serve the system's state on, if any.
+    pub fn get_remote(&self) -> Option<&String> {
+        self.remote.as_ref()
+    }
+
+    /// Get the profiling option.
+    pub fn is_profiling(&self) -> bool {
+        self.profile
+    }
+
+    /// Get the path to write the profile report to when done, if any.
+    pub fn get_profile_report(&self) -> Option<&String> {
+        self.profile_report.as_ref()
+    }
+
+    /// Get the format of the profile report.
+    pub fn get_profile_format(&self) -> ReportFormat {
+        self.profile_format
+    }
+
+    /// Get the alignment statistics option.
+    pub fn is_tracking_alignment(&self) -> bool {
+        self.align_stats
+    }
+
+    /// Get whether the system has a floating point coprocessor.
+    pub fn has_fpu(&self) -> bool {
+        self.fpu
+    }
+
+    /// Give the system a floating point coprocessor or take it away.
+    /// # Arguments
+    /// * `fpu` - True for a floating point coprocessor.
+    pub fn set_fpu(&mut self, fpu: bool) {
+        self.fpu = fpu;
+    }
+
+    /// Get the host memory usage statistics option.
+    pub fn is_reporting_usage(&self) -> bool {
+        self.usage_stats
+    }
+
+    /// Get the most host memory (in megabytes) the emulator may use, 0 for
+    /// no limit.
+    pub fn get_host_mem_limit(&self) -> u32 {
+        self.host_mem_limit
+    }
+
+    /// Get the clock cycle a headless run stops at, 0 for no limit.
+    pub fn get_max_cycles(&self) -> u64 {
+        self.max_cycles
+    }
+
+    /// Get the longest (in seconds) a headless run may take, 0 for no
+    /// limit.
+    pub fn get_timeout_seconds(&self) -> u32 {
+        self.timeout_seconds
+    }
+
+    /// Get the log levels of the subsystems.
+    pub fn get_log(&self) -> &String {
+        &self.log
+    }
+
+    /// Get the co-simulation option.
+    pub fn is_cosim(&self) -> bool {
+        self.cosim
+    }
+
+    /// Get the determinism audit option.
+    pub fn is_auditing_determinism(&self) -> bool {
+        self.audit_determinism
+    }
+
+    /// Get the number of instructions between comparisons of a determinism
+    /// audit.
+    pub fn get_audit_every(&self) -> u32 {
+        self.audit_every
+    }
+
+    /// Get the anomaly detection option.
+    pub fn is_detecting_anomalies(&self) -> bool {
+        self.detect_anomalies
+    }
+
+    /// Get the number of quiet executions of an instruction before it is
+    /// reported as a livelock.
+    pub fn get_livelock_limit(&self) -> u32 {
+        self.livelock_limit
+    }
+
+    /// Get the pause on anomaly option.
+    pub fn is_pausing_on_anomaly(&self) -> bool {
+        self.pause_on_anomaly
+    }
+
+    /// Get the pipeline hazard detection option.
+    pub fn is_detecting_hazards(&self) -> bool {
+        self.detect_hazards
+    }
+
+    /// Get the snapshot to restore at startup, if any.
+    pub fn get_load_snapshot(&self) -> Option<&String> {
+        self.load_snapshot.as_ref()
+    }
+
+    /// Get the path to save a snapshot to when done, if any.
+    pub fn get_save_snapshot(&self) -> Option<&String> {
+        self.save_snapshot.as_ref()
+    }
+
+    /// Get the expression that triggers a snapshot, if any.
+    pub fn get_snapshot_when(&self) -> Option<&String> {
+        self.snapshot_when.as_ref()
+    }
+
+    /// Get the clock cycles between periodic snapshots, 0 if there are none.
+    pub fn get_snapshot_every(&self) -> u32 {
+        self.snapshot_every
+    }
+
+    /// Get the path to log the inputs from outside the guest to, if any.
+    pub fn get_record_inputs(&self) -> Option<&String> {
+        self.record_inputs.as_ref()
+    }
+
+    /// Get the input log to replay, if any.
+    pub fn get_replay_inputs(&self) -> Option<&String> {
+        self.replay_inputs.as_ref()
+    }
+
+    /// Get the path to write an execution trace to, if any.
+    pub fn get_trace(&self) -> Option<&String> {
+        self.trace.as_ref()
+    }
+
+    /// Get the user's configured trace format.
+    pub fn get_trace_format(&self) -> TraceFormat {
+        self.trace_format
+    }
+
+    /// Get how much of the pipeline's inner workings to log.
+    pub fn get_pipeline_debug(&self) -> PipelineDebug {
+        self.pipeline_debug
+    }
+
+    /// Get the path to write the pipeline log to, None for standard error.
+    pub fn get_pipeline_debug_log(&self) -> Option<&String> {
+        self.pipeline_debug_log.as_ref()
+    }
+
+    /// Get the syntax instructions are disassembled in.
+    pub fn get_syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    /// Get the path to write a state dump to when done, if any.
+    pub fn get_export_state(&self) -> Option<&String> {
+        self.export_state.as_ref()
+    }
+
+    /// Get the user's configured state dump format.
+    pub fn get_export_format(&self) -> StateFormat {
+        self.export_format
+    }
+
+    /// Get the memory ranges to include in the state dump.
+    pub fn get_export_memory(&self) -> &String {
+        &self.export_memory
+    }
+
+    /// Get the address of the framebuffer, None if there is none.
+    pub fn get_fb_base(&self) -> Option<u32> {
+        self.device.framebuffer.base
+    }
+
+    pub fn get_fb_width(&self) -> u32 {
+        self.device.framebuffer.width
+    }
+
+    pub fn get_fb_height(&self) -> u32 {
+        self.device.framebuffer.height
+    }
+
+    /// Get the user's configured framebuffer pixel format.
+    pub fn get_fb_format(&self) -> PixelFormat {
+        self.device.framebuffer.format
+    }
+
+    /// Get the disk image backing the block device, None if there is none.
+    pub fn get_disk_image(&self) -> Option<&String> {
+        self.disk_image.as_ref()
+    }
+
+    /// Get the user's configured cache directory.
+    pub fn get_cache_path(&self) -> &String {
+        &self.cache_path
+    }
+
+    /// Get the paths the windows' font is looked for at, in order. Return
+    /// the paths on success and a string on error.
+    /// The font given with --font is the only one. Otherwise it is
+    /// riscii/debug.otf in $XDG_DATA_HOME and then in each of
+    /// $XDG_DATA_DIRS, and last debug.otf in the current directory.
+    pub fn get_font_paths(&self) -> Result<Vec<String>> {
+        let data_dirs = env::var("XDG_DATA_DIRS").unwrap_or_default();
+        self.get_font_paths_in(&xdg_dir("XDG_DATA_HOME", ".local/share")?, &data_dirs)
+    }
+
+    /// Get the paths the windows' font is looked for at, in order, with the
+    /// XDG data directories given rather than read from the environment.
+    /// Return the paths on success and a string on error.
+    /// # Arguments
+    /// * `data_home` - The user's data directory, as in $XDG_DATA_HOME.
+    /// * `data_dirs` - The other data directories, separated by colons as in
+    /// $XDG_DATA_DIRS. Empty for the default ones, relative ones are left
+    /// out.
+    pub fn get_font_paths_in(&self, data_home: &String, data_dirs: &str) -> Result<Vec<String>> {
+        if let Some(font) = self.font.as_ref() {
+            return Ok(vec![font.clone()]);
+        }
+        let data_dirs = if data_dirs.is_empty() {
+            DEFAULT_XDG_DATA_DIRS
+        } else {
+            data_dirs
+        };
+        let mut dirs = vec![data_home.clone()];
+        dirs.extend(
+            data_dirs
+                .split(':')
+                .filter(|d| Path::new(d).is_absolute())
+                .map(|d| d.to_string()),
+        );
+        let mut result = Vec::new();
+        for dir in dirs.iter() {
+            let riscii = concat_paths(dir, &"riscii".to_string())?;
+            result.push(concat_paths(&riscii, &FONT_FILE.to_string())?);
+        }
+        result.push(FONT_FILE.to_string());
+        Ok(result)
+    }
+
+    /// Get the user's configured execution engine.
+    pub fn get_engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Get the number of entries of the fast engine's decode cache, 0 for
+    /// none.
+    pub fn get_decode_cache(&self) -> u32 {
+        self.decode_cache
+    }
+
+    /// Set the number of entries of the fast engine's decode cache.
+    /// # Arguments
+    /// * `entries` - Number of entries, a power of two, or 0 for none.
+    pub fn set_decode_cache(&mut self, entries: u32) {
+        self.decode_cache = entries;
+    }
+
+    /// Get the delay slot option, true if the instruction after a jump, call
+    /// or return runs before the target.
+    pub fn has_delay_slots(&self) -> bool {
+        self.delay_slots
+    }
+
+    /// Get the user's configured clock rate, in hertz.
+    pub fn get_clock_rate(&self) -> u64 {
+        self.clock_rate
+    }
+
+    /// Set the clock rate.
+    /// # Arguments
+    /// * `rate` - Clock rate in hertz.
+    pub fn set_clock_rate(&mut self, rate: u64) {
+        self.clock_rate = rate;
+    }
+
+    /// Get how the user wants the clock paced.
+    pub fn get_clock_mode(&self) -> ClockMode {
+        self.clock_mode
+    }
+
+    /// Set how the clock is paced.
+    /// # Arguments
+    /// * `mode` - How the clock is paced.
+    pub fn set_clock_mode(&mut self, mode: ClockMode) {
+        self.clock_mode = mode;
+    }
+
+    /// Get the number of instructions run per frame when the clock mode is
+    /// frame.
+    pub fn get_frame_instructions(&self) -> u64 {
+        self.frame_instructions
+    }
+}
+
+// Local functions.
+
+/// Print how to use the emulator.
+fn print_usage() {
+    println!(
+        "Usage: riscii [run] [OPTIONS] [IMAGE[@ADDRESS]...]
+       riscii debug [OPTIONS] [IMAGE[@ADDRESS]...]
+       riscii asm SOURCE -o IMAGE [ASM OPTIONS]
+       riscii disasm IMAGE [--origin ADDRESS] [--syntax SYNTAX] [--symbols FILE]
+                     [--hazards]
+       riscii snapshot FILE [OPTIONS]
+       riscii snapshot diff FILE FILE [OPTIONS]
+       riscii test PROGRAM|DIRECTORY... [OPTIONS] [TEST OPTIONS]
+
+Commands:
+run                 Run the system, loading each IMAGE at its ADDRESS
+                    (default=0) first
+debug               Same as run, with the debug window open
+asm                 Assemble SOURCE into a memory image
+disasm              Disassemble a memory image
+snapshot            Describe a snapshot file
+snapshot diff       List the registers and memory that differ between two
+                    snapshot files
+test                Run test programs, assembly source or memory images that
+                    report their result through the host exit register, on
+                    both engines or the one --engine names, and write a
+                    failure bundle for each run that does not pass
+
+Options:
+--config_path       Path to the configuration file, it must exist
+                    (default=$XDG_CONFIG_HOME/riscii/config.toml, if it
+                    exists, $XDG_CONFIG_HOME being ~/.config if not set)
+--cache_path        Directory snapshots are saved to, created when needed
+                    (default=$XDG_CACHE_HOME/riscii, $XDG_CACHE_HOME being
+                    ~/.cache if not set)
+--font              Font the windows draw text in (default=riscii/debug.otf
+                    in $XDG_DATA_HOME or $XDG_DATA_DIRS, else debug.otf in
+                    the current directory)
+--profile_name      Use the settings of a [profiles.NAME] section of the
+                    configuration file over the rest
+--dump_config       Print the configuration in effect, as TOML, and exit
+--mem               Size of memory (in megabytes) (default=512)
+--ncpu              Number of cores to emulate (default=1)
+--windows           Number of register windows per core, 2 to 8 (default=8)
+--mem_fill          What memory holds at power on, zero, ones, deadbeef or
+                    random (default=zero)
+--mem_fill_seed     Seed of the random memory fill and power on state, also
+                    --poweron_seed (default=1)
+--poweron_state     What registers, latches and memory hold at power on and
+                    after a cold reset, zero, random or pattern (0xdeadbeef).
+                    Sets --mem_fill to match (default=zero)
+--endian            Byte order of words in memory, big or little
+                    (default=big). asm writes and disasm reads images in it
+--region            Region of memory as BASE:SIZE:PERMISSIONS, PERMISSIONS
+                    being rom, ram, device or any of r, w and x. Once a
+                    region is given, memory outside of the regions cannot be
+                    accessed and loaded images are executable. Later regions
+                    take precedence. Can be given more than once
+--engine            Execution engine, fast or cycle (default=cycle)
+--fast              Same as --engine fast
+--decode_cache      Entries of the fast engine's cache of decoded
+                    instructions, a power of two or 0 for none
+                    (default=1024)
+--no_delay_slots    Jump, call and return right away instead of running the
+                    instruction after them first
+--clock             How the clock is paced with windows, unlimited,
+                    realtime or frame (default=realtime)
+--clock_rate        Clock rate in hertz for realtime (default=5000000)
+--frame_instructions
+                    Instructions run per frame for --clock frame
+                    (default=100000)
+--headless          Run without any windows until the program halts
+--remote            Run without any windows and serve the state over HTTP on
+                    an address, e.g. 127.0.0.1:8080, until a client quits,
+                    implies --headless (needs the remote feature)
+--profile           Report host time spent on each opcode, the most run
+                    instructions and the call graph when done
+--profile_report    Path to write the profile report to when done, implies
+                    --profile
+--profile_format    Format of the profile report, text or json (default=text)
+--align_stats       Report aligned and misaligned memory accesses when done
+--usage_stats       Report the host memory used when done
+--host_mem_limit    Most host memory (in megabytes) guest memory, traces,
+                    snapshots and bookkeeping may use, evicting the oldest
+                    trace, bookkeeping and automatic snapshots first
+                    (default=0, no limit)
+--max_cycles        Clock cycle a headless run stops at, exiting with code 124
+                    and showing where it was (default=0, no limit)
+--timeout_seconds   Longest (in seconds) a headless run may take, exiting with
+                    code 124 and showing where it was (default=0, no limit)
+--log               Log levels, e.g. pipeline=debug,system=warn: a level for
+                    each subsystem (a module of the emulator) and one without
+                    a name for the rest (default=warn)
+--cosim             Run both engines side by side until they disagree or the
+                    program halts
+--audit_determinism Run the program twice and report the first cycle the runs
+                    differ on and the subsystems responsible
+--audit_every       Instructions between comparisons of the runs of a
+                    determinism audit (default=1000)
+--detect_anomalies  Warn about executing data, livelocks and repeated
+                    privilege violations
+--livelock_limit    Times an instruction may run without changing anything
+                    before it is reported as a livelock (default=10000)
+--pause_on_anomaly  Same as --detect_anomalies, and pause when one is found
+--detect_hazards    Warn about instructions breaking pipeline rules: using a
+                    register loaded by the instruction before, changing CWP
+                    or the flags right after putpsw, or transferring control
+                    from a delay slot
+--load_snapshot     Restore the system from a snapshot file at startup
+--save_snapshot     Save a snapshot of the system to a file when done
+--snapshot_when     Save a snapshot to the cache directory whenever an
+                    expression becomes true, e.g. \"pc == 0x2000 && r5 > 100\"
+--snapshot_every    Save a snapshot to the cache directory every N clock
+                    cycles (default=0, never)
+--record_inputs     Log the key presses, gamepad changes and times of day the
+                    guest gets, with the clock cycles they came on, to a file
+--replay_inputs     Feed the guest the inputs logged in a file instead of the
+                    host's, to run a recorded run again exactly
+--trace             Log every retired instruction to a file
+--trace_format      Format of the trace, text or binary (default=text)
+--pipeline_debug    Log the pipeline's inner workings, off, cycle (a line per
+                    clock cycle) or phase (and the latches every phase
+                    changes) (default=off)
+--pipeline_debug_log
+                    File to write the pipeline log to (default=standard error)
+--syntax            Syntax of disassembled instructions in traces, logs and
+                    disasm, modern or berkeley (default=modern)
+--export_state      Write a human readable dump of the state to a file when
+                    done
+--export_format     Format of the state dump, toml or json (default=json)
+--export_memory     Memory to include in the state dump, as comma separated
+                    start:len ranges, e.g. 0x2000:64
+--fb_base           Address of a framebuffer in memory for the main window to
+                    show, e.g. 0x100000 (default=none)
+--fb_width          Width of the framebuffer in pixels (default=320)
+--fb_height         Height of the framebuffer in pixels (default=240)
+--fb_format         Pixel format of the framebuffer, mono, gray8, rgb332,
+                    rgb565 or xrgb8888 (default=rgb332)
+--console_base      Address of the console's registers (default=0xffff0000)
+--disk_image        Disk image file for the block device (default=none)
+--image             Load a raw binary, Intel HEX (.hex) or S-record (.srec)
+                    file into memory before running, as FILE@ADDRESS, e.g.
+                    boot.bin@0x2000. Can be given more than once
+--load              Same as --image
+--entry             Address to start running at (default=the entry point of
+                    the images, or the reset vector)
+--symbols           Symbol file of the program, one hex address and name per
+                    line (nm output works), to show addresses by name. Can be
+                    given more than once
+--breakpoint        Pause once the instruction at an address retires. Can be
+                    given more than once
+--trap_vectors      Address of the trap vector table after a reset, the
+                    handlers are 0x40 bytes apart (default=0x80000000)
+--isa_ext           Give the cores an instruction set extension: mul, integer
+                    multiply and divide. Can be given more than once
+                    (default=none)
+--fpu               Attach the single precision floating point coprocessor,
+                    if built with the fpu feature
+
+Asm options:
+-o, --output        Memory image to write
+--origin            Address the program will be loaded at (default=0)
+--fill_delay_slots  Give every control transfer a NOP delay slot, the source
+                    must not have any
+--schedule_delay_slots
+                    Same as --fill_delay_slots, moving the instruction before
+                    a jump into its slot where that is safe
+--align_targets     Align branch targets to a number of bytes with NOPs
+                    (default=0, none)
+--symbols           Write the program's labels to a symbol file
+
+Disasm options:
+--hazards           List the instructions breaking pipeline rules after the
+                    disassembly, see --detect_hazards
+
+Test options:
+--budget            Most clock cycles a program may run (default=1000000)
+--bundles           Directory failure bundles are written to, one directory
+                    per program and engine (default=bundles in the cache
+                    directory)
+"
+    );
+}
+
+/// Find an option that must be known before the configuration file is read.
+/// Return its argument, None if it was not given, on success and a string
+/// on error.
+/// # Arguments
+/// * `args` - CMD argument vector.
+/// * `name` - Name of the option, without the leading `--`.
+fn args_find(args: &Vec<String>, name: &str) -> Result<Option<String>> {
+    let option = format!("--{}", name);
+    match args.iter().position(|a| *a == option) {
+        Some(i) => Ok(Some(
+            args_get_next_arg(&args, i, &name.to_string())?.clone(),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Merge the settings of one TOML table into another, section by section,
+/// replacing the settings both have.
+/// # Arguments
+/// * `into` - Table to merge into.
+/// * `from` - Table to take the settings from.
+fn merge_tables(into: &mut Table, from: &Table) {
+    for (key, value) in from.iter() {
+        match (into.get_mut(key), value) {
+            (Some(Value::Table(a)), Value::Table(b)) => merge_tables(a, b),
+            _ => {
+                into.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Check the argument vector to make sure it has at least one more string
+/// after the current argument. Return void on success and a string on error.
+/// # Arguments
+/// * `args` - CMD argument vector.
+/// * `i` - Index of the current argument.
+/// * `what` - String describing the current argument (for error message).
+fn args_check_size(args: &Vec<String>, i: usize, what: &String) -> Result<()> {
+    if i + 1 >= args.len() {
+        berr!(
+            Config,
+            format!("Invalid command line argument: {} takes an argument.", what)
+        )
+    } else {
+        Ok(())
+    }
+}
+
+/// Get the next argument in the argument vector as a string. Return next
+/// string on success and a string on error.
+/// # Arguments
+/// * `args` - CMD argument vector.
+/// * `i` - Index of the current argument.
+/// * `what` - String describing the current argument (for error message).
+fn args_get_next_arg<'a>(args: &'a Vec<String>, i: usize, what: &String) -> Result<&'a String> {
+    args_check_size(&args, i, &what)?;
+    Ok(&args[i + 1])
+}
+
+/// Get the next argument in the argument vector as a u32. Return
+/// u32 on success and a string on error.
+/// # Arguments
+/// * `args` - CMD argument vector.
+/// * `i` - Index of the current argument.
+/// * `what` - String describing the current argument (for error message).
+fn args_get_next_uint(args: &Vec<String>, i: usize, what: &String) -> Result<u32> {
+    args_check_size(&args, i, &what)?;
+    Ok(match args[i + 1].parse::<u32>() {
+        core::result::Result::Ok(u) => u,
+        core::result::Result::Err(e) => {
+            return berr!(
+                Config,
+                format!(
+                    "Invalid command line argument for {}: {}, err: {}.",
+                    what,
+                    args[i + 1],
+                    e
+                )
+            )
+        }
+    })
+}
+
+/// Get the next argument in the argument vector as a u64. Return u64 on
+/// success and a string on error.
+/// # Arguments
+/// * `args` - CMD argument vector.
+/// * `i` - Index of the current argument.
+/// * `what` - String describing the current argument (for error message).
+fn args_get_next_u64(args: &Vec<String>, i: usize, what: &String) -> Result<u64> {
+    args_check_size(&args, i, &what)?;
+    match args[i + 1].parse::<u64>() {
+        Ok(u) => Ok(u),
+        Err(e) => berr!(
+            Config,
+            format!(
+                "Invalid command line argument for {}: {}, err: {}.",
+                what,
+                args[i + 1],
+                e
+            )
+        ),
+    }
+}
+
+/// Get the next argument in the argument vector as an address, in decimal
+/// or in hexadecimal with a 0x prefix. Return the address on success and a
+/// string on error.
+/// # Arguments
+/// * `args` - CMD argument vector.
+/// * `i` - Index of the current argument.
+/// * `what` - String describing the current argument (for error message).
+fn args_get_next_addr(args: &Vec<String>, i: usize, what: &String) -> Result<u32> {
+    args_check_size(&args, i, &what)?;
+    parse_addr(&args[i + 1], what)
+}
+
+/// Parse an address, in decimal or in hexadecimal with a 0x prefix. Return
+/// the address on success and a string on error.
+/// # Arguments
+/// * `arg` - The address.
+/// * `what` - String describing the argument (for error message).
+fn parse_addr(arg: &str, what: &String) -> Result<u32> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => arg.parse::<u32>(),
+    };
+    match parsed {
+        Ok(u) => Ok(u),
+        Err(e) => berr!(
+            Config,
+            format!(
+                "Invalid command line argument for {}: {}, err: {}.",
+                what, arg, e
+            )
+        ),
+    }
+}
+
+/// Parse a region of memory given as `BASE:SIZE:PERMISSIONS`. Return the
+/// region on success and a string on error.
+/// # Arguments
+/// * `arg` - The region.
+fn parse_region(arg: &str) -> Result<Region> {
+    let fields: Vec<&str> = arg.split(':').collect();
+    if fields.len() != 3 {
+        return berr!(
+            Config,
+            format!("Invalid region: {}, expected BASE:SIZE:PERMISSIONS", arg)
+        );
+    }
+    Ok(Region {
+        base: parse_addr(fields[0], &format!("region"))?,
+        size: parse_addr(fields[1], &format!("region"))?,
+        permissions: Permissions::from_name(fields[2])?,
+    })
+}
+
+impl MemoryImage {
+    /// Parse a memory image given as `FILE@ADDRESS`, or `FILE` for address
+    /// 0. Return the image on success and a string on error.
+    /// # Arguments
+    /// * `arg` - The memory image.
+    pub fn parse(arg: &str) -> Result<Self> {
+        let (path, address) = match arg.rfind('@') {
+            Some(at) => (&arg[..at], parse_addr(&arg[at + 1..], &format!("load"))?),
+            None => (arg, 0),
+        };
+        if path.is_empty() {
+            return berr!(
+                Config,
+                format!("Invalid memory image: {}, expected FILE@ADDRESS", arg)
+            );
+        }
+        Ok(Self {
+            path: path.to_string(),
+            address: address,
+        })
+    }
+}
+
+impl Engine {
+    /// Get an engine from its name (`fast` or `cycle`). Return the engine on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the engine.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "fast" => Ok(Self::Fast),
+            "cycle" => Ok(Self::Cycle),
+            _ => berr!(
+                Config,
+                format!("Invalid engine: {}, expected fast or cycle", name)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Fast => "fast",
+                Self::Cycle => "cycle",
+            }
+        )
+    }
+}
+
+impl TraceFormat {
+    /// Get a trace format from its name (`text` or `binary`). Return the
+    /// format on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the format.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "text" => Ok(Self::Text),
+            "binary" => Ok(Self::Binary),
+            _ => berr!(
+                Config,
+                format!("Invalid trace format: {}, expected text or binary", name)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Text => "text",
+                Self::Binary => "binary",
+            }
+        )
+    }
+}
+
+impl ClockMode {
+    /// Get a clock mode from its name (`unlimited`, `realtime` or `frame`).
+    /// Return the mode on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the mode.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "unlimited" => Ok(Self::Unlimited),
+            "realtime" => Ok(Self::Realtime),
+            "frame" => Ok(Self::Frame),
+            _ => berr!(
+                Config,
+                format!(
+                    "Invalid clock mode: {}, expected unlimited, realtime or frame",
+                    name
+                )
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ClockMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Unlimited => "unlimited",
+                Self::Realtime => "realtime",
+                Self::Frame => "frame",
+            }
+        )
+    }
+}
+
+impl PipelineDebug {
+    /// Get a pipeline debug level from its name (`off`, `cycle` or `phase`).
+    /// Return the level on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the level.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "off" => Ok(Self::Off),
+            "cycle" => Ok(Self::Cycle),
+            "phase" => Ok(Self::Phase),
+            _ => berr!(
+                Config,
+                format!(
+                    "Invalid pipeline debug level: {}, expected off, cycle or phase",
+                    name
+                )
+            ),
+        }
+    }
+
+    /// Get the next level, wrapping around to off.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Cycle,
+            Self::Cycle => Self::Phase,
+            Self::Phase => Self::Off,
+        }
+    }
+}
+
+impl fmt::Display for PipelineDebug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Off => "off",
+                Self::Cycle => "cycle",
+                Self::Phase => "phase",
+            }
+        )
+    }
+}
+
+impl Syntax {
+    /// Get a disassembly syntax from its name (`modern` or `berkeley`).
+    /// Return the syntax on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the syntax.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "modern" => Ok(Self::Modern),
+            "berkeley" => Ok(Self::Berkeley),
+            _ => berr!(
+                Config,
+                format!("Invalid syntax: {}, expected modern or berkeley", name)
+            ),
+        }
+    }
+
+    /// Get the other syntax.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Modern => Self::Berkeley,
+            Self::Berkeley => Self::Modern,
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Modern => "modern",
+                Self::Berkeley => "berkeley",
+            }
+        )
     }
+}
 
-    /// Get the debug mode option.
-    pub fn is_debug_mode(&self) -> bool {
-        self.debug_mode
+impl StateFormat {
+    /// Get a state dump format from its name (`toml` or `json`). Return the
+    /// format on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the format.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            _ => berr!(
+                Config,
+                format!("Invalid state format: {}, expected toml or json", name)
+            ),
+        }
     }
+}
 
-    pub fn get_clock_rate(&self) -> u64 {
-        self.clock_rate
+impl ReportFormat {
+    /// Get a profile report format from its name (`text` or `json`). Return
+    /// the format on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the format.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => berr!(
+                Config,
+                format!("Invalid report format: {}, expected text or json", name)
+            ),
+        }
     }
 }
 
-// Local functions.
+impl MemoryFill {
+    /// Get a memory fill from its name (`zero`, `ones`, `deadbeef` or
+    /// `random`). Return the fill on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the fill.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "zero" => Ok(Self::Zero),
+            "ones" => Ok(Self::Ones),
+            "deadbeef" => Ok(Self::Deadbeef),
+            "random" => Ok(Self::Random),
+            _ => berr!(
+                Config,
+                format!(
+                    "Invalid memory fill: {}, expected zero, ones, deadbeef or random",
+                    name
+                )
+            ),
+        }
+    }
+}
 
-/// Check the argument vector to make sure it has at least one more string
-/// after the current argument. Return void on success and a string on error.
-/// # Arguments
-/// * `args` - CMD argument vector.
-/// * `i` - Index of the current argument.
-/// * `what` - String describing the current argument (for error message).
-fn args_check_size(args: &Vec<String>, i: usize, what: &String) -> Result<()> {
-    if i >= args.len() {
-        berr!(format!(
-            "Invalid command line argument: {} takes an argument.",
-            what
-        ))
-    } else {
-        Ok(())
+impl PowerOnState {
+    /// Get a power on state from its name (`zero`, `random` or `pattern`).
+    /// Return the state on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the state.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "zero" => Ok(Self::Zero),
+            "random" => Ok(Self::Random),
+            "pattern" => Ok(Self::Pattern),
+            _ => berr!(
+                Config,
+                format!(
+                    "Invalid power on state: {}, expected zero, random or pattern",
+                    name
+                )
+            ),
+        }
+    }
+
+    /// Get the memory fill of the same kind.
+    pub fn mem_fill(&self) -> MemoryFill {
+        match *self {
+            Self::Zero => MemoryFill::Zero,
+            Self::Random => MemoryFill::Random,
+            Self::Pattern => MemoryFill::Deadbeef,
+        }
+    }
+
+    /// Get the value of the next register to power on.
+    /// # Arguments
+    /// * `rng` - Generator of the random state.
+    pub fn value(&self, rng: &mut Xorshift) -> u32 {
+        match *self {
+            Self::Zero => 0,
+            Self::Random => rng.next_u32(),
+            Self::Pattern => 0xdeadbeef,
+        }
     }
 }
 
-/// Get the next argument in the argument vector as a string. Return next
-/// string on success and a string on error.
-/// # Arguments
-/// * `args` - CMD argument vector.
-/// * `i` - Index of the current argument.
-/// * `what` - String describing the current argument (for error message).
-fn args_get_next_arg<'a>(args: &'a Vec<String>, i: usize, what: &String) -> Result<&'a String> {
-    args_check_size(&args, i, &what)?;
-    Ok(&args[i + 1])
+impl Endian {
+    /// Get a byte order from its name (`big` or `little`). Return the byte
+    /// order on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the byte order.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "big" => Ok(Self::Big),
+            "little" => Ok(Self::Little),
+            _ => berr!(
+                Config,
+                format!("Invalid byte order: {}, expected big or little", name)
+            ),
+        }
+    }
+
+    /// Get a word from its bytes in this order.
+    /// # Arguments
+    /// * `bytes` - The word's bytes, in memory order.
+    pub fn word(&self, bytes: [u8; 4]) -> u32 {
+        match *self {
+            Self::Big => u32::from_be_bytes(bytes),
+            Self::Little => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Get the bytes of a word in this order.
+    /// # Arguments
+    /// * `word` - The word.
+    pub fn word_bytes(&self, word: u32) -> [u8; 4] {
+        match *self {
+            Self::Big => word.to_be_bytes(),
+            Self::Little => word.to_le_bytes(),
+        }
+    }
+
+    /// Get a half word from its bytes in this order.
+    /// # Arguments
+    /// * `bytes` - The half word's bytes, in memory order.
+    pub fn hword(&self, bytes: [u8; 2]) -> u16 {
+        match *self {
+            Self::Big => u16::from_be_bytes(bytes),
+            Self::Little => u16::from_le_bytes(bytes),
+        }
+    }
 }
 
-/// Get the next argument in the argument vector as a u32. Return
-/// u32 on success and a string on error.
-/// # Arguments
-/// * `args` - CMD argument vector.
-/// * `i` - Index of the current argument.
-/// * `what` - String describing the current argument (for error message).
-fn args_get_next_uint(args: &Vec<String>, i: usize, what: &String) -> Result<u32> {
-    args_check_size(&args, i, &what)?;
-    Ok(match args[i + 1].parse::<u32>() {
-        core::result::Result::Ok(u) => u,
-        core::result::Result::Err(e) => {
-            return berr!(format!(
-                "Invalid command line argument for {}: {}, err: {}.",
-                what,
-                args[i + 1],
-                e
-            ))
+impl IsaExtension {
+    /// Get an instruction set extension from its name (`mul`). Return the
+    /// extension on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the extension.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "mul" => Ok(Self::Mul),
+            _ => berr!(
+                Config,
+                format!("Invalid ISA extension: {}, expected mul", name)
+            ),
         }
-    })
+    }
+}
+
+impl fmt::Display for IsaExtension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Mul => "mul",
+            }
+        )
+    }
+}
+
+impl fmt::Display for Endian {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Big => "big",
+                Self::Little => "little",
+            }
+        )
+    }
+}
+
+impl fmt::Display for PowerOnState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Zero => "zero",
+                Self::Random => "random",
+                Self::Pattern => "pattern",
+            }
+        )
+    }
+}
+
+impl fmt::Display for MemoryFill {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Zero => "zero",
+                Self::Ones => "ones",
+                Self::Deadbeef => "deadbeef",
+                Self::Random => "random",
+            }
+        )
+    }
+}
+
+impl PixelFormat {
+    /// Get a pixel format from its name (`mono`, `gray8`, `rgb332`, `rgb565`
+    /// or `xrgb8888`). Return the format on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the format.
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "mono" => Ok(Self::Mono),
+            "gray8" => Ok(Self::Gray8),
+            "rgb332" => Ok(Self::Rgb332),
+            "rgb565" => Ok(Self::Rgb565),
+            "xrgb8888" => Ok(Self::Xrgb8888),
+            _ => berr!(
+                Config,
+                format!(
+                    "Invalid pixel format: {}, expected mono, gray8, rgb332, rgb565 or xrgb8888",
+                    name
+                )
+            ),
+        }
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Mono => "mono",
+                Self::Gray8 => "gray8",
+                Self::Rgb332 => "rgb332",
+                Self::Rgb565 => "rgb565",
+                Self::Xrgb8888 => "xrgb8888",
+            }
+        )
+    }
 }
 
 impl fmt::Display for Config {
@@ -306,16 +2548,55 @@ impl fmt::Display for Config {
         write!(
             f,
             "Number of cpus: {}
+Register windows: {}
 Memory (MB): {}
+Memory fill: {}
+Power on state: {}
+Byte order: {} endian
 Configuration file: {}
+Configuration profile: {}
 Cache Directory: {}
-Window dimensions: ({}, {})",
+Window dimensions: ({}, {})
+Engine: {}
+Decode cache: {} entries
+Delay slots: {}
+Clock: {} at {} Hz
+Headless: {}
+Profile: {}
+Alignment statistics: {}
+Trap vectors: 0x{:08x}
+ISA extensions: {}
+FPU: {}",
             self.ncpu,
+            self.windows,
             self.mem,
+            self.mem_fill,
+            self.poweron_state,
+            self.endian,
             self.config_file_path,
+            self.profile_name.as_ref().map_or("none", |p| p.as_str()),
             self.cache_path,
-            self.win_width,
-            self.win_height
+            self.window.main.width,
+            self.window.main.height,
+            self.engine,
+            self.decode_cache,
+            self.delay_slots,
+            self.clock_mode,
+            self.clock_rate,
+            self.headless,
+            self.profile,
+            self.align_stats,
+            self.trap_vectors,
+            if self.isa_ext.is_empty() {
+                "none".to_string()
+            } else {
+                self.isa_ext
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            },
+            self.fpu
         )
     }
 }
@@ -332,14 +2613,33 @@ fn default_ncpu() -> u32 {
     1
 }
 
-fn default_cache() -> String {
-    let home_dir = get_home_nofail();
+fn default_windows() -> u32 {
+    DEFAULT_REG_WINDOWS as u32
+}
 
-    let cache_dir = ".cache/riscii".to_string();
-    match env::var("XDG_CACHE_HOME") {
-        Ok(v) => concat_paths(&v, &cache_dir).unwrap(),
-        Err(v) => concat_paths(&home_dir, &cache_dir).unwrap(),
-    }
+fn default_command() -> Command {
+    Command::Run
+}
+
+fn default_mem_fill() -> MemoryFill {
+    MemoryFill::Zero
+}
+
+fn default_mem_fill_seed() -> u32 {
+    1
+}
+
+fn default_poweron_state() -> PowerOnState {
+    PowerOnState::Zero
+}
+
+fn default_endian() -> Endian {
+    Endian::Big
+}
+
+fn default_cache() -> String {
+    let cache_dir = xdg_dir("XDG_CACHE_HOME", ".cache").unwrap();
+    concat_paths(&cache_dir, &"riscii".to_string()).unwrap()
 }
 
 fn default_width() -> u32 {
@@ -350,10 +2650,236 @@ fn default_height() -> u32 {
     900
 }
 
+fn default_window() -> WindowGeometry {
+    WindowGeometry {
+        width: default_width(),
+        height: default_height(),
+        x: None,
+        y: None,
+    }
+}
+
+fn default_window_layout() -> WindowLayout {
+    WindowLayout {
+        main: default_window(),
+        debug: default_window(),
+    }
+}
+
 fn default_debug_mode() -> bool {
     true
 }
 
+fn default_headless() -> bool {
+    false
+}
+
+fn default_remote() -> Option<String> {
+    None
+}
+
+fn default_profile() -> bool {
+    false
+}
+
+fn default_profile_report() -> Option<String> {
+    None
+}
+
+fn default_profile_format() -> ReportFormat {
+    ReportFormat::Text
+}
+
+fn default_align_stats() -> bool {
+    false
+}
+
+fn default_fpu() -> bool {
+    false
+}
+
+fn default_usage_stats() -> bool {
+    false
+}
+
+fn default_host_mem_limit() -> u32 {
+    0
+}
+
+fn default_max_cycles() -> u64 {
+    0
+}
+
+fn default_timeout_seconds() -> u32 {
+    0
+}
+
+fn default_log() -> String {
+    String::new()
+}
+
+fn default_cosim() -> bool {
+    false
+}
+
+fn default_audit_determinism() -> bool {
+    false
+}
+
+fn default_audit_every() -> u32 {
+    1000
+}
+
+fn default_detect_anomalies() -> bool {
+    false
+}
+
+fn default_detect_hazards() -> bool {
+    false
+}
+
+fn default_livelock_limit() -> u32 {
+    10000
+}
+
+fn default_pause_on_anomaly() -> bool {
+    false
+}
+
+fn default_snapshot() -> Option<String> {
+    None
+}
+
+fn default_font() -> Option<String> {
+    None
+}
+
+fn default_snapshot_every() -> u32 {
+    0
+}
+
+fn default_inputs() -> Option<String> {
+    None
+}
+
+fn default_trace() -> Option<String> {
+    None
+}
+
+fn default_trace_format() -> TraceFormat {
+    TraceFormat::Text
+}
+
+fn default_pipeline_debug() -> PipelineDebug {
+    PipelineDebug::Off
+}
+
+fn default_pipeline_debug_log() -> Option<String> {
+    None
+}
+
+fn default_syntax() -> Syntax {
+    Syntax::Modern
+}
+
+fn default_export_state() -> Option<String> {
+    None
+}
+
+fn default_export_format() -> StateFormat {
+    StateFormat::Json
+}
+
+fn default_export_memory() -> String {
+    String::new()
+}
+
+fn default_entry() -> Option<u32> {
+    None
+}
+
+fn default_symbols() -> Vec<String> {
+    Vec::new()
+}
+
+fn default_breakpoints() -> Vec<u32> {
+    Vec::new()
+}
+
+fn default_trap_vectors() -> u32 {
+    TRAP_VECTOR_BASE
+}
+
+fn default_isa_ext() -> Vec<IsaExtension> {
+    Vec::new()
+}
+
+fn default_fb_base() -> Option<u32> {
+    None
+}
+
+fn default_fb_width() -> u32 {
+    320
+}
+
+fn default_fb_height() -> u32 {
+    240
+}
+
+fn default_fb_format() -> PixelFormat {
+    PixelFormat::Rgb332
+}
+
+fn default_console_base() -> u32 {
+    CONSOLE_BASE
+}
+
+fn default_console() -> ConsoleDevice {
+    ConsoleDevice {
+        base: default_console_base(),
+    }
+}
+
+fn default_framebuffer() -> FramebufferDevice {
+    FramebufferDevice {
+        base: default_fb_base(),
+        width: default_fb_width(),
+        height: default_fb_height(),
+        format: default_fb_format(),
+    }
+}
+
+fn default_devices() -> Devices {
+    Devices {
+        console: default_console(),
+        framebuffer: default_framebuffer(),
+    }
+}
+
+fn default_disk_image() -> Option<String> {
+    None
+}
+
+fn default_engine() -> Engine {
+    Engine::Cycle
+}
+
+fn default_decode_cache() -> u32 {
+    DEFAULT_DECODE_CACHE
+}
+
+fn default_delay_slots() -> bool {
+    true
+}
+
 fn default_clock_rate() -> u64 {
     5_000_000
 }
+
+fn default_clock_mode() -> ClockMode {
+    ClockMode::Realtime
+}
+
+fn default_frame_instructions() -> u64 {
+    100_000
+}
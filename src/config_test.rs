@@ -0,0 +1,248 @@
+// Test code for the configuration.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "config.rs"]
+mod test {
+    use super::super::*;
+    use config::*;
+    use console::{Console, CONSOLE_BASE, CONSOLE_TX_READY};
+    use device::Device;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use util::{expand_path, xdg_dir, Result};
+
+    const PROFILES: &str = "
+mem = 32
+engine = \"cycle\"
+
+[window.main]
+width = 800
+
+[profiles.fast]
+engine = \"fast\"
+
+[profiles.fast.window.main]
+height = 600
+x = 10
+y = 20
+
+[profiles.debug]
+mem = 128
+";
+
+    #[test]
+    fn profile_overrides_settings() -> Result<()> {
+        let config = Config::from_toml(PROFILES, None)?;
+        assert_eq!(config.get_engine(), Engine::Cycle);
+        assert_eq!(config.get_mem_size(), 32);
+        assert_eq!(config.get_profile_name(), None);
+        let main = config.get_window_layout().main;
+        assert_eq!((main.width, main.height, main.x), (800, 900, None));
+
+        // Only the settings the profile gives change, down to single
+        // settings of a section.
+        let config = Config::from_toml(PROFILES, Some("fast"))?;
+        assert_eq!(config.get_engine(), Engine::Fast);
+        assert_eq!(config.get_mem_size(), 32);
+        assert_eq!(config.get_profile_name(), Some(&"fast".to_string()));
+        let main = config.get_window_layout().main;
+        assert_eq!((main.width, main.height), (800, 600));
+        assert_eq!((main.x, main.y), (Some(10), Some(20)));
+        assert_eq!(config.get_window_layout().debug.width, 1600);
+
+        let config = Config::from_toml(PROFILES, Some("debug"))?;
+        assert_eq!(config.get_engine(), Engine::Cycle);
+        assert_eq!(config.get_mem_size(), 128);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() -> Result<()> {
+        assert!(Config::from_toml(PROFILES, Some("slow")).is_err());
+        // `profile` on its own still turns profiling on.
+        let config = Config::from_toml("profile = true", None)?;
+        assert!(config.is_profiling());
+        assert!(Config::from_toml("profile = true", Some("fast")).is_err());
+        assert!(Config::from_toml("profiles = 1", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn profiling_and_profiles_mix() -> Result<()> {
+        let text = format!("profile = true\n{}", PROFILES);
+        let config = Config::from_toml(&text, None)?;
+        assert!(config.is_profiling());
+        assert_eq!(config.get_engine(), Engine::Cycle);
+
+        let config = Config::from_toml(&text, Some("fast"))?;
+        assert!(config.is_profiling());
+        assert_eq!(config.get_engine(), Engine::Fast);
+
+        // A profile can turn profiling off.
+        let text = format!(
+            "profile = true\n{}\n[profiles.quiet]\nprofile = false\n",
+            PROFILES
+        );
+        assert!(!Config::from_toml(&text, Some("quiet"))?.is_profiling());
+        Ok(())
+    }
+
+    #[test]
+    fn devices_are_declared_in_sections() -> Result<()> {
+        let config = Config::new()?;
+        assert_eq!(config.get_console_base(), CONSOLE_BASE);
+        assert_eq!(config.get_fb_base(), None);
+
+        let config = Config::from_toml(
+            "
+[device.console]
+base = 0xffff1000
+
+[device.framebuffer]
+base = 0x100000
+width = 64
+format = \"mono\"
+",
+            None,
+        )?;
+        assert_eq!(config.get_console_base(), 0xffff1000);
+        assert_eq!(config.get_fb_base(), Some(0x100000));
+        assert_eq!(config.get_fb_width(), 64);
+        assert_eq!(config.get_fb_height(), 240);
+        assert_eq!(config.get_fb_format(), PixelFormat::Mono);
+
+        // The console answers at its new address.
+        let mut console = Console::new(Box::new(io::sink())).with_base(config.get_console_base());
+        assert_eq!(console.mapping(), Some(0xffff1000..0xffff1008));
+        assert_eq!(console.read(0xffff1004), CONSOLE_TX_READY);
+        assert_eq!(console.read(0xffff1000), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn dumped_config_reads_back() -> Result<()> {
+        let config = Config::from_toml(PROFILES, Some("fast"))?;
+        let text = config.to_toml()?;
+        assert!(text.contains("[window.main]"), "{}", text);
+        assert!(text.contains("[device.console]"), "{}", text);
+        assert!(!text.contains("[profiles"), "{}", text);
+
+        let read = Config::from_toml(&text, None)?;
+        assert_eq!(read.get_engine(), Engine::Fast);
+        assert_eq!(read.get_mem_size(), 32);
+        assert_eq!(read.get_window_layout(), config.get_window_layout());
+        assert_eq!(read.get_devices(), config.get_devices());
+        assert_eq!(read.get_trap_vectors(), config.get_trap_vectors());
+        assert_eq!(read.to_toml()?, text);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_file_gives_defaults() -> Result<()> {
+        let dir = env::temp_dir().join("riscii-config-missing");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("config.toml");
+        let path = path.to_str().unwrap();
+
+        let config = Config::from_file(path, None, false)?;
+        assert_eq!(config.get_mem_size(), Config::new()?.get_mem_size());
+        assert_eq!(config.get_engine(), Engine::Cycle);
+        // Unless the file was asked for, or a profile in it was.
+        assert!(Config::from_file(path, None, true).is_err());
+        assert!(Config::from_file(path, Some("fast"), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn paths_expand_variables() -> Result<()> {
+        let dir = env::temp_dir().join("riscii-config-expand");
+        fs::create_dir_all(&dir)?;
+        let root = dir.to_str().unwrap().to_string();
+        env::set_var("RISCII_TEST_ROOT", &root);
+        let path = dir.join("config.toml");
+        fs::write(&path, "cache_path = \"${RISCII_TEST_ROOT}/cache\"\n")?;
+
+        // Both the path of the file and the paths in it expand.
+        let config = Config::from_file("$RISCII_TEST_ROOT/config.toml", None, true)?;
+        assert_eq!(config.get_cache_path(), &format!("{}/cache", root));
+
+        let home = env::var("HOME").unwrap();
+        assert_eq!(expand_path("~")?, home);
+        assert_eq!(expand_path("~/riscii")?, format!("{}/riscii", home));
+        assert_eq!(expand_path("a~/$")?, "a~/$");
+        assert_eq!(expand_path("$RISCII_TEST_ROOT.d")?, format!("{}.d", root));
+        assert!(expand_path("$RISCII_TEST_NOT_SET/x").is_err());
+        assert!(expand_path("${RISCII_TEST_ROOT").is_err());
+        assert!(expand_path("${}").is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn font_is_searched_for() -> Result<()> {
+        let config = Config::new()?;
+        let data_home = "/home/riscii/.data".to_string();
+        assert_eq!(
+            config.get_font_paths_in(&data_home, "/opt/share:share:/usr/share")?,
+            vec![
+                "/home/riscii/.data/riscii/debug.otf",
+                "/opt/share/riscii/debug.otf",
+                "/usr/share/riscii/debug.otf",
+                "debug.otf",
+            ]
+        );
+        assert_eq!(
+            config.get_font_paths_in(&data_home, "")?[1..3],
+            [
+                "/usr/local/share/riscii/debug.otf",
+                "/usr/share/riscii/debug.otf"
+            ]
+        );
+
+        // A font given is the only one tried.
+        let dir = env::temp_dir().join("riscii-config-font");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("config.toml");
+        fs::write(&path, "font = \"~/fonts/mono.ttf\"\n")?;
+        let config = Config::from_file(path.to_str().unwrap(), None, true)?;
+        assert_eq!(
+            config.get_font_paths()?,
+            vec![format!("{}/fonts/mono.ttf", env::var("HOME").unwrap())]
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn xdg_dirs_must_be_absolute() -> Result<()> {
+        let home = env::var("HOME").unwrap();
+        env::set_var("RISCII_TEST_XDG", "/var/riscii");
+        assert_eq!(xdg_dir("RISCII_TEST_XDG", ".config")?, "/var/riscii");
+        // The specification says to ignore relative paths.
+        env::set_var("RISCII_TEST_XDG", "riscii");
+        assert_eq!(
+            xdg_dir("RISCII_TEST_XDG", ".config")?,
+            format!("{}/.config", home)
+        );
+        env::remove_var("RISCII_TEST_XDG");
+        assert_eq!(
+            xdg_dir("RISCII_TEST_XDG", ".cache")?,
+            format!("{}/.cache", home)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+// Memory mapped console (UART) device.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use device::{Device, Reset};
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// Base address of the console's registers.
+pub const CONSOLE_BASE: u32 = 0xffff0000;
+/// Transmit register. Writing a byte here prints it.
+pub const CONSOLE_TX: u32 = CONSOLE_BASE;
+/// Status register. Bit 0 is set when the console is ready to transmit.
+pub const CONSOLE_STATUS: u32 = CONSOLE_BASE + 4;
+/// Size of the console's register block.
+const CONSOLE_SIZE: u32 = 8;
+/// Status bit set when the console can accept a byte.
+pub const CONSOLE_TX_READY: u32 = 0x1;
+
+/// Write only serial console. Guest programs print by storing bytes to
+/// `CONSOLE_TX`.
+pub struct Console {
+    /// Where transmitted bytes go.
+    out: Box<dyn Write>,
+    /// Address of the console's registers.
+    base: u32,
+}
+
+impl Console {
+    /// Create a console that transmits to `out`.
+    /// # Arguments
+    /// * `out` - Destination of transmitted bytes.
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self {
+            out: out,
+            base: CONSOLE_BASE,
+        }
+    }
+
+    /// Create a console that transmits to the host's standard output.
+    pub fn stdout() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+
+    /// Move the console's registers from `CONSOLE_BASE` to another address.
+    /// The registers keep their order, `CONSOLE_TX` first.
+    /// # Arguments
+    /// * `base` - Address of the console's registers.
+    pub fn with_base(self, base: u32) -> Self {
+        Self {
+            out: self.out,
+            base: base,
+        }
+    }
+
+    /// Get the usual address, at `CONSOLE_BASE`, of the register at `addr`.
+    fn register(&self, addr: u32) -> u32 {
+        addr.wrapping_sub(self.base).wrapping_add(CONSOLE_BASE) & !0x3
+    }
+}
+
+impl Device for Console {
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        let _ = self.out.flush();
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(self.base..self.base + CONSOLE_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        match self.register(addr) {
+            CONSOLE_STATUS => CONSOLE_TX_READY,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        if self.register(addr) == CONSOLE_TX {
+            // Output is best effort, a closed stdout should not stop the guest.
+            let _ = self.out.write_all(&[value as u8]);
+            if value as u8 == b'\n' {
+                let _ = self.out.flush();
+            }
+        }
+    }
+}
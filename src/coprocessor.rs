@@ -0,0 +1,205 @@
+// Coprocessors attached to the RISC II system.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Coprocessors run alongside the CPU. Every retired instruction is offered to
+// every coprocessor, which decides whether it starts an operation (usually a
+// store to one of its registers). At the start of every clock cycle each
+// coprocessor reports its status:
+//   Idle, Busy - The CPU carries on.
+//   Stall - The CPU is held for the cycle, e.g. because an operation was
+//     issued while a slow one is still running.
+//   Done - An operation finished. Its completion is forwarded to the CPU
+//     before the next instruction starts: the result is written to a
+//     register, the condition codes replace the PSW's and a trap flushes
+//     the instructions in flight and enters the handler.
+// At most one completion is forwarded per cycle. Completions of coprocessors
+// finishing in the same cycle wait in order of attachment, and the CPU is
+// held while any are waiting.
+
+use commit::RetiredInstruction;
+use cpu::{ProcessorStatusWord, Trap};
+use data_path::DataPath;
+use device::Reset;
+use std::collections::VecDeque;
+
+// Public structs.
+
+/// Condition codes set by a coprocessor operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ConditionCodes {
+    pub zero: bool,
+    pub neg: bool,
+    pub overflow: bool,
+    pub carry: bool,
+}
+
+/// Effects of a finished coprocessor operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Completion {
+    /// Register (in the window current when forwarded) and the value to
+    /// write to it, if the operation has a result.
+    pub dest: Option<(u8, u32)>,
+    /// Condition codes to set, None to leave the PSW's alone.
+    pub cc: Option<ConditionCodes>,
+    /// Trap to raise, if the operation failed. A trapped operation has no
+    /// other effect.
+    pub trap: Option<Trap>,
+}
+
+/// What a coprocessor reports at the start of a clock cycle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Nothing is running.
+    Idle,
+    /// An operation is running, the CPU may carry on.
+    Busy,
+    /// The CPU must not run this cycle.
+    Stall,
+    /// An operation finished.
+    Done(Completion),
+}
+
+/// A coprocessor attached to the system.
+pub trait Coprocessor {
+    /// Name of the coprocessor, for diagnostics.
+    fn name(&self) -> &str;
+
+    /// Return the coprocessor to its reset state, abandoning any operation.
+    /// Called on every system reset.
+    /// # Arguments
+    /// * `kind` - Kind of reset being performed.
+    fn reset(&mut self, kind: Reset);
+
+    /// Look at an instruction that just retired and start an operation if
+    /// it is meant for this coprocessor.
+    /// # Arguments
+    /// * `r` - The retired instruction.
+    fn issue(&mut self, r: &RetiredInstruction);
+
+    /// Advance the coprocessor by one clock cycle and report its status.
+    fn tick(&mut self) -> Status;
+}
+
+/// The coprocessors attached to a system and the completions waiting to be
+/// forwarded to the CPU.
+pub struct Coprocessors {
+    /// Attached coprocessors, in order of attachment.
+    units: Vec<Box<dyn Coprocessor>>,
+    /// Finished operations not forwarded yet, oldest first.
+    pending: VecDeque<Completion>,
+    /// Number of cycles the CPU was held.
+    stalled_cycles: u64,
+}
+
+impl Coprocessors {
+    pub fn new() -> Self {
+        Self {
+            units: Vec::new(),
+            pending: VecDeque::new(),
+            stalled_cycles: 0,
+        }
+    }
+
+    /// Attach a coprocessor. It has the lowest priority of those attached.
+    /// # Arguments
+    /// * `unit` - Coprocessor to attach.
+    pub fn attach(&mut self, unit: Box<dyn Coprocessor>) {
+        self.units.push(unit);
+    }
+
+    /// Reset every coprocessor and drop the completions not forwarded yet.
+    /// # Arguments
+    /// * `kind` - Kind of reset being performed.
+    pub fn reset(&mut self, kind: Reset) {
+        for unit in self.units.iter_mut() {
+            unit.reset(kind);
+        }
+        self.pending.clear();
+    }
+
+    /// Offer a retired instruction to every coprocessor.
+    /// # Arguments
+    /// * `r` - The retired instruction.
+    pub fn issue(&mut self, r: &RetiredInstruction) {
+        for unit in self.units.iter_mut() {
+            unit.issue(r);
+        }
+    }
+
+    /// Run every coprocessor for a clock cycle. Return the completion to
+    /// forward this cycle, if any, and true if the CPU must be held.
+    pub fn tick(&mut self) -> (Option<Completion>, bool) {
+        let mut stall = false;
+        for unit in self.units.iter_mut() {
+            match unit.tick() {
+                Status::Idle | Status::Busy => {}
+                Status::Stall => stall = true,
+                Status::Done(completion) => self.pending.push_back(completion),
+            }
+        }
+        let completion = self.pending.pop_front();
+        stall |= !self.pending.is_empty();
+        if stall {
+            self.stalled_cycles += 1;
+        }
+        (completion, stall)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Get the names of the attached coprocessors, in order of attachment.
+    pub fn names(&self) -> Vec<&str> {
+        self.units.iter().map(|u| u.name()).collect()
+    }
+
+    /// Get the number of cycles the CPU was held by the coprocessors.
+    pub fn stalled_cycles(&self) -> u64 {
+        self.stalled_cycles
+    }
+}
+
+impl Completion {
+    /// Make the effects of the operation visible to the data path. Return
+    /// the trap it raised instead, if any, which the system must take once
+    /// the instructions in flight can be thrown away.
+    /// # Arguments
+    /// * `dp` - Data path to forward to.
+    pub fn forward(&self, dp: &mut DataPath) -> Option<Trap> {
+        if self.trap.is_some() {
+            return self.trap;
+        }
+
+        let mut psw = dp.get_psw();
+        if let Some((rd, value)) = self.dest {
+            dp.get_register_file().write(rd, value, psw.get_cwp());
+        }
+        if let Some(cc) = self.cc {
+            set_cc(&mut psw, cc);
+            dp.set_psw(psw.get());
+        }
+        None
+    }
+}
+
+// Private functions.
+
+fn set_cc(psw: &mut ProcessorStatusWord, cc: ConditionCodes) {
+    psw.set_cc_zero(cc.zero);
+    psw.set_cc_neg(cc.neg);
+    psw.set_cc_overflow(cc.overflow);
+    psw.set_cc_carry(cc.carry);
+}
@@ -0,0 +1,229 @@
+// Differential co-simulation of the two execution engines.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Both engines run the same program one retired instruction at a time. After
+// every instruction the retired instructions, the registers of every window
+// and the PSWs must match, otherwise the engines have diverged.
+
+use commit::RetiredInstruction;
+use config::{Engine, Syntax};
+use cpu::{register_name, NUM_GLOBALS, WINDOW_SIZE};
+use std::collections::VecDeque;
+use std::fmt;
+use system::System;
+use trace::text_record;
+use util::Result;
+
+/// Number of instructions before a divergence kept for context.
+const HISTORY_LEN: usize = 8;
+
+/// Runs a fast and a cycle accurate system side by side.
+pub struct Cosim {
+    /// System running the functional engine.
+    fast: System,
+    /// System running the pipeline.
+    cycle: System,
+    /// Number of instructions both engines retired identically.
+    retired: u64,
+    /// Last instructions both engines retired, oldest first.
+    history: VecDeque<RetiredInstruction>,
+}
+
+/// First point where the engines disagreed.
+pub struct Divergence {
+    /// Number of instructions that retired identically before it.
+    pub index: u64,
+    /// Instruction the functional engine retired, None if it retired none.
+    pub fast: Option<RetiredInstruction>,
+    /// Instruction the pipeline retired, None if it retired none.
+    pub cycle: Option<RetiredInstruction>,
+    /// Description of every difference found.
+    pub differences: Vec<String>,
+    /// Instructions retired just before it, oldest first.
+    pub history: Vec<RetiredInstruction>,
+}
+
+impl Cosim {
+    /// Co-simulate two systems holding the same program, switching them to
+    /// the functional engine and the pipeline respectively.
+    /// # Arguments
+    /// * `fast` - System to run on the functional engine.
+    /// * `cycle` - System to run on the pipeline.
+    pub fn new(mut fast: System, mut cycle: System) -> Self {
+        fast.set_engine(Engine::Fast);
+        cycle.set_engine(Engine::Cycle);
+        Self {
+            fast: fast,
+            cycle: cycle,
+            retired: 0,
+            history: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    /// Retire one instruction on both engines and compare them. Return the
+    /// divergence if they disagree, None if they agree and a string on
+    /// error.
+    pub fn step(&mut self) -> Result<Option<Divergence>> {
+        let fast = self.fast.run_until_retired()?;
+        let cycle = self.cycle.run_until_retired()?;
+        let mut differences = Vec::new();
+        match (&fast, &cycle) {
+            (Some(f), Some(c)) => compare_retired(f, c, &mut differences),
+            (None, Some(_)) => differences.push("functional engine retired nothing".to_string()),
+            (Some(_), None) => differences.push("pipeline retired nothing".to_string()),
+            (None, None) => differences.push("neither engine retired anything".to_string()),
+        }
+        self.compare_state(cycle.as_ref(), &mut differences);
+
+        if !differences.is_empty() {
+            return Ok(Some(Divergence {
+                index: self.retired,
+                fast: fast,
+                cycle: cycle,
+                differences: differences,
+                history: self.history.iter().cloned().collect(),
+            }));
+        }
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        // Both are the same, so either will do.
+        self.history.extend(fast);
+        self.retired += 1;
+        Ok(None)
+    }
+
+    /// Run until the engines diverge or the program halts by branching to
+    /// itself. Return the divergence, None if the program halted and a
+    /// string on error.
+    pub fn run_until_halt(&mut self) -> Result<Option<Divergence>> {
+        loop {
+            if let Some(d) = self.step()? {
+                return Ok(Some(d));
+            }
+            match self.history.back() {
+                Some(r) if r.branch == Some(r.pc) => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+
+    /// Get the number of instructions both engines retired identically.
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    pub fn fast(&self) -> &System {
+        &self.fast
+    }
+
+    pub fn cycle(&self) -> &System {
+        &self.cycle
+    }
+
+    /// Compare the architectural state of the two systems.
+    /// # Arguments
+    /// * `cycle_retired` - Instruction the pipeline just retired, if any.
+    /// * `differences` - List to add differences to.
+    fn compare_state(
+        &self,
+        cycle_retired: Option<&RetiredInstruction>,
+        differences: &mut Vec<String>,
+    ) {
+        let fast = self.fast.data_path();
+        let cycle = self.cycle.data_path();
+        if fast.get_psw().get() != cycle.get_psw().get() {
+            differences.push(format!("psw: {} != {}", fast.get_psw(), cycle.get_psw()));
+        }
+        let cwp = fast.get_psw().get_cwp();
+        // The pipeline writes a result back during the cycle after the
+        // instruction retires.
+        let mut cycle_regs = cycle.copy_register_file();
+        if let Some(&(rd, value)) = cycle_retired.and_then(|r| r.dest.as_ref()) {
+            cycle_regs.write(rd, value, cycle.get_psw().get_cwp());
+        }
+        for reg in 0..(NUM_GLOBALS + WINDOW_SIZE) as u8 {
+            let (f, c) = (
+                fast.register_file().read(reg, cwp),
+                cycle_regs.read(reg, cwp),
+            );
+            if f != c {
+                differences.push(format!("{}: {:08x} != {:08x}", register_name(reg), f, c));
+            }
+        }
+        if fast.copy_register_file() != cycle_regs {
+            differences.push("registers of another window differ".to_string());
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Engines diverged after {} instructions (values are functional != pipeline).",
+            self.index
+        )?;
+        writeln!(f, "Last instructions:")?;
+        for r in self.history.iter() {
+            writeln!(f, "  {}", text_record(r, Syntax::Modern))?;
+        }
+        for (name, r) in [("Functional", &self.fast), ("Pipeline", &self.cycle)].iter() {
+            match r {
+                Some(r) => writeln!(f, "{} retired: {}", name, text_record(r, Syntax::Modern))?,
+                None => writeln!(f, "{} retired nothing", name)?,
+            }
+        }
+        writeln!(f, "Differences:")?;
+        for d in self.differences.iter() {
+            writeln!(f, "  {}", d)?;
+        }
+        Ok(())
+    }
+}
+
+// Private functions.
+
+/// Compare two retired instructions, ignoring when they retired.
+/// # Arguments
+/// * `f` - Instruction the functional engine retired.
+/// * `c` - Instruction the pipeline retired.
+/// * `differences` - List to add differences to.
+fn compare_retired(f: &RetiredInstruction, c: &RetiredInstruction, differences: &mut Vec<String>) {
+    if f.pc != c.pc {
+        differences.push(format!("pc: {:08x} != {:08x}", f.pc, c.pc));
+    }
+    if f.instruction != c.instruction {
+        differences.push(format!(
+            "instruction: {} != {}",
+            f.instruction, c.instruction
+        ));
+    }
+    if f.dest != c.dest {
+        differences.push(format!("destination: {:?} != {:?}", f.dest, c.dest));
+    }
+    if f.psw_after.get() != c.psw_after.get() {
+        differences.push(format!("psw after: {} != {}", f.psw_after, c.psw_after));
+    }
+    if f.access != c.access {
+        differences.push(format!("memory access: {:?} != {:?}", f.access, c.access));
+    }
+    if f.branch != c.branch {
+        differences.push(format!("branch: {:x?} != {:x?}", f.branch, c.branch));
+    }
+    if f.trap != c.trap {
+        differences.push(format!("trap: {:?} != {:?}", f.trap, c.trap));
+    }
+}
@@ -13,14 +13,30 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 use instruction::ShortSource;
+extern crate serde;
+extern crate serde_derive;
+
+use self::serde::de::Error as DeError;
+use self::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use self::serde_derive::{Deserialize, Serialize};
+use config::PowerOnState;
 use memory::Memory;
+use r2d2::{Reader, Snapshot, Writer};
 use std::convert::TryInto;
 use std::fmt;
+use util;
+use util::Xorshift;
 
 use berr;
 
-/// The number of register windows the RISCII supports.
-pub const NUM_REG_WINDOWS: usize = 8;
+/// The number of register windows the RISCII has.
+pub const DEFAULT_REG_WINDOWS: u8 = 8;
+/// The fewest register windows a register file may have, a caller's and a
+/// callee's.
+pub const MIN_REG_WINDOWS: u8 = 2;
+/// The most register windows a register file may have, as many as the
+/// PSW's three bit window pointers can address.
+pub const MAX_REG_WINDOWS: u8 = 8;
 /// The number of local registers per window.
 pub const NUM_LOCALS: usize = 10;
 /// The number of registers shared with the previous register window (input arguments).
@@ -34,7 +50,8 @@ pub const NUM_GLOBALS: usize = 10;
 /// Number of registers that adding a window adds to the total amount of registers.
 pub const NUM_ADDED_PER_WINDOW: usize = NUM_LOCALS + NUM_SHARED_NEXT;
 /// Number of general purpose registers that exist in window_regs.
-pub const NUM_WINDOW_REGISTERS: usize = NUM_REG_WINDOWS * NUM_ADDED_PER_WINDOW;
+/// Room is made for the most windows a register file may have.
+pub const NUM_WINDOW_REGISTERS: usize = MAX_REG_WINDOWS as usize * NUM_ADDED_PER_WINDOW;
 /// Number of "special" registers (cwp, swp, sp, etc.).
 pub const NUM_SPECIAL_REGISTERS: usize = 3;
 /// The total number of registers on the system.
@@ -63,8 +80,60 @@ pub const SWP_LOC: u16 = 0x7 << 7;
 pub const CWP_LOC: u16 = 0x7 << 10;
 /// Location of the processor status word in the 16 bit uint it is stored in.
 pub const PSW_LOC: u16 = 0x1fff;
+/// Address execution starts at after a reset.
+pub const RESET_VECTOR: u32 = 0;
+/// Base address of the trap vector table after a reset.
+pub const TRAP_VECTOR_BASE: u32 = 0x80000000;
+/// Bytes between the handlers of a trap vector table laid out from a base.
+pub const TRAP_VECTOR_SPACING: u32 = 0x40;
+/// Number of kinds of trap, each has its own vector.
+pub const TRAP_KINDS: usize = 6;
+/// Alignment mask for word loads and stores.
+pub const WORD_ALIGN_MASK: u32 = 0x3;
+/// Alignment mask for half word loads and stores.
+pub const HWORD_ALIGN_MASK: u32 = 0x1;
+/// Alignment mask for jump and call targets.
+pub const JUMP_ALIGN_MASK: u32 = 0x1;
+/// Conventional stack pointer register. It is an out register, so the
+/// caller's stack pointer is the callee's frame pointer.
+pub const STACK_POINTER_REG: u8 = 14;
+/// Conventional frame pointer register (the caller's stack pointer).
+pub const FRAME_POINTER_REG: u8 = 30;
+/// Conventional return address register, written by the caller's call in
+/// the callee's window.
+pub const RETURN_ADDRESS_REG: u8 = 31;
 // Struct definitions.
 
+/// Conditions that abort the currently executing instruction and transfer
+/// control to a handler in the trap vector table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trap {
+    /// A memory access or jump target was not aligned. Holds the faulting address.
+    Alignment(u32),
+    /// A privileged instruction (`CALLI`, `GETLPC`, `GETTCR`, `PUTPSW`, `RETI`) was
+    /// executed while the CPU was in user mode.
+    PrivilegeViolation,
+    /// A coprocessor operation failed. Holds the cause reported by the
+    /// coprocessor.
+    Coprocessor(u32),
+    /// A device requested an interrupt while interrupts were enabled. Taken
+    /// between instructions but never before a delay slot, `lstpc` holds the
+    /// instruction to resume at.
+    Interrupt,
+    /// A memory access or instruction fetch was refused by the MMU. Holds the
+    /// faulting (virtual) address.
+    AccessViolation(u32),
+    /// An instruction was not allowed where it was, e.g. one that changes
+    /// the window pointers or condition codes right after `PUTPSW`.
+    IllegalInstruction,
+}
+
+/// Addresses of the handlers of every kind of trap, indexed by
+/// `Trap::index`. Every trap a core takes jumps through its table, which
+/// system code may move through the MMU's `MMU_TRAP_VECTORS` registers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TrapVectors([u32; TRAP_KINDS]);
+
 // TODO maybe convert this into a u16?
 /// PSW. Contains internal state that is usually opaque to the system.
 /// [12:10] -> Current window pointer (CWP).
@@ -79,9 +148,88 @@ pub const PSW_LOC: u16 = 0x1fff;
 #[derive(Copy, Clone, PartialEq)]
 pub struct ProcessorStatusWord(u16);
 
+/// A flag of the PSW, the window pointers aside.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PswField {
+    /// Interrupts enabled (I).
+    InterruptEnabled,
+    /// System mode (S).
+    SystemMode,
+    /// Previous system mode (P).
+    PreviousSystemMode,
+    /// Zero condition code (Z).
+    Zero,
+    /// Negative condition code (N).
+    Negative,
+    /// Overflow condition code (V).
+    Overflow,
+    /// Carry condition code (C).
+    Carry,
+}
+
 /// The CPU's register state.
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct RegisterFile([u32; NUM_GLOBALS + NUM_WINDOW_REGISTERS]);
+pub struct RegisterFile {
+    /// Globals followed by the registers of every window.
+    regs: [u32; NUM_GLOBALS + NUM_WINDOW_REGISTERS],
+    /// Number of register windows, the last window's ins are the first
+    /// window's outs.
+    windows: u8,
+}
+
+/// How a register file is serialized: its number of windows and its
+/// registers in the order `RegisterFile` keeps them.
+#[derive(Serialize, Deserialize)]
+struct RegisterFileState {
+    windows: u8,
+    regs: Vec<u32>,
+}
+
+/// How a PSW is serialized, a field per bit so the format does not follow
+/// the PSW's layout.
+#[derive(Serialize, Deserialize)]
+struct PswState {
+    cwp: u8,
+    swp: u8,
+    interrupts: bool,
+    system: bool,
+    previous_system: bool,
+    z: bool,
+    n: bool,
+    v: bool,
+    c: bool,
+}
+
+/// A procedure's register window, as seen in a backtrace.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The frame's window pointer.
+    pub window: u8,
+    /// Value of the frame's stack pointer.
+    pub sp: u32,
+    /// Value of the frame's frame pointer.
+    pub fp: u32,
+    /// Value of the frame's return address.
+    pub ra: u32,
+}
+
+/// The registers seen from one window, grouped the conventional RISC II
+/// way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterDump {
+    /// Window pointer the registers are seen from.
+    pub cwp: u8,
+    /// r0 to r9, shared by every window.
+    pub globals: [u32; NUM_GLOBALS],
+    /// r10 to r15, shared with the next window as its ins.
+    pub outs: [u32; NUM_SHARED_NEXT],
+    /// r16 to r25, private to the window.
+    pub locals: [u32; NUM_LOCALS],
+    /// r26 to r31, shared with the previous window as its outs.
+    pub ins: [u32; NUM_SHARED_PREV],
+    /// r10 to r31, the window as a whole.
+    pub window: [u32; WINDOW_SIZE],
+}
 
 /// CPU output pins to memory.
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -106,10 +254,34 @@ pub struct OutputPins {
 // Struct implementations.
 
 impl RegisterFile {
-    /// Create a 0'd out register window.
+    /// Create a 0'd out register file with the RISC II's register windows.
     pub fn new() -> Self {
+        Self::with_windows(DEFAULT_REG_WINDOWS)
+    }
+
+    /// Create a 0'd out register file.
+    /// # Arguments
+    /// * `windows` - Number of register windows, between `MIN_REG_WINDOWS`
+    /// and `MAX_REG_WINDOWS`.
+    pub fn with_windows(windows: u8) -> Self {
         Self {
-            0: [0u32; NUM_GLOBALS + NUM_WINDOW_REGISTERS],
+            regs: [0u32; NUM_GLOBALS + NUM_WINDOW_REGISTERS],
+            windows: windows.max(MIN_REG_WINDOWS).min(MAX_REG_WINDOWS),
+        }
+    }
+
+    /// Get the number of register windows.
+    pub fn windows(&self) -> u8 {
+        self.windows
+    }
+
+    /// Put every register but r0 in its power on state, globals first.
+    /// # Arguments
+    /// * `state` - What the registers hold.
+    /// * `rng` - Generator of the random state.
+    pub fn power_on(&mut self, state: PowerOnState, rng: &mut Xorshift) {
+        for reg in self.regs.iter_mut().skip(1) {
+            *reg = state.value(rng);
         }
     }
 
@@ -193,14 +365,16 @@ impl RegisterFile {
     /// * `addr` - Memory address to flush to.
     pub fn flush_to_mem(&self, mem: &mut Memory, addr: u32) {
         let mut address = addr;
-        for i in self.0.iter() {
+        for i in self.regs.iter() {
             mem.set_word(address, *i);
             address += 4;
         }
     }
 
-    /// Get a register's value (unsigned). Return the register's value
-    /// on success and a string message on error.
+    /// Get a register's value (unsigned), for callers that know the
+    /// register exists. Registers that come from outside the emulator go
+    /// through `try_read`. Reading one that does not exist is a bug in the
+    /// caller, it is logged as an error and reads as 0.
     /// Register mapping: [0-9] -> Globals
     ///                   [10-15] -> Outs
     ///                   [16-25] -> Locals
@@ -210,11 +384,68 @@ impl RegisterFile {
     /// * `address` - Which register. [0-31] are the only valid values.
     /// * `cwp` - Current window pointer. Used to determine real address of the register.
     pub fn read(&self, address: u8, cwp: u8) -> u32 {
-        let addr = address as usize;
-        let ptr = cwp as usize;
+        match self.try_read(address, cwp) {
+            Ok(v) => v,
+            Err(_) => {
+                error!(
+                    "Read of r{}, which does not exist, in window {}",
+                    address, cwp
+                );
+                0
+            }
+        }
+    }
+
+    /// Get a register's value. Return the value on success and an error if
+    /// the register does not exist.
+    /// # Arguments
+    /// * `address` - Which register, 0 to 31.
+    /// * `cwp` - Current window pointer.
+    pub fn try_read(&self, address: u8, cwp: u8) -> util::Result<u32> {
+        match self.get_real_address(address, cwp) {
+            Ok(a) => Ok(self.regs[a]),
+            Err(_) => berr!(Register, format!("There is no register r{}", address)),
+        }
+    }
+
+    /// Set a register's value, writes to r0 are a no op. Return an error if
+    /// the register does not exist.
+    /// # Arguments
+    /// * `address` - Which register, 0 to 31.
+    /// * `value` - Value to write into the register.
+    /// * `cwp` - Current window pointer.
+    pub fn try_write(&mut self, address: u8, value: u32, cwp: u8) -> util::Result<()> {
         match self.get_real_address(address, cwp) {
-            Ok(a) => self.0[a],
-            Err(_) => 0, // TODO figure out what to do here.
+            Ok(0) => Ok(()),
+            Ok(a) => {
+                self.regs[a] = value;
+                Ok(())
+            }
+            Err(_) => berr!(Register, format!("There is no register r{}", address)),
+        }
+    }
+
+    /// Get the value of a short source, the register's contents or the
+    /// immediate itself.
+    /// # Arguments
+    /// * `ss` - The short source.
+    /// * `cwp` - Current window pointer.
+    pub fn get_ss_val(&self, ss: ShortSource, cwp: u8) -> u32 {
+        match ss {
+            ShortSource::Reg(r) => self.read(r, cwp),
+            ShortSource::Imm13(i) | ShortSource::SImm13(i) => i,
+        }
+    }
+
+    /// Get the value of a short source. Return the value on success and an
+    /// error if it names a register that does not exist.
+    /// # Arguments
+    /// * `ss` - The short source.
+    /// * `cwp` - Current window pointer.
+    pub fn try_get_ss_val(&self, ss: ShortSource, cwp: u8) -> util::Result<u32> {
+        match ss {
+            ShortSource::Reg(r) => self.try_read(r, cwp),
+            ShortSource::Imm13(i) | ShortSource::SImm13(i) => Ok(i),
         }
     }
 
@@ -225,21 +456,35 @@ impl RegisterFile {
     ///                   [16-25] -> Locals
     ///                   [31-26] -> Ins
     /// Anything outside this [0-31] range is an invalid argument.
+    /// The globals are the same in every window. Each window has outs and
+    /// locals of its own, its ins are the outs of window CWP+1, the caller's
+    /// (CALL decrements CWP), so the outs of window N are the ins of window
+    /// N-1.
     /// # Arguments
     /// * `address` - Which register. [0-31] are the only valid values.
     /// * `cwp` - Current window pointer. Used to determine real address of the register.
     pub fn get_real_address(&self, address: u8, cwp: u8) -> Result<usize, ()> {
         let addr = address as usize;
-        let ptr = cwp as usize;
-        Ok(match addr {
-            0..=9 => addr,
-            10..=31 => NUM_ADDED_PER_WINDOW * ptr + addr + NUM_GLOBALS,
-            _ => return Err(()),
+        let outs = NUM_GLOBALS;
+        let ins = outs + NUM_SHARED_NEXT + NUM_LOCALS;
+        // Where a window's outs and locals start. Windows wrap around, the
+        // ins of the last are the outs of the first.
+        let window = |w: usize| NUM_GLOBALS + w % self.windows as usize * NUM_ADDED_PER_WINDOW;
+        Ok(if addr < outs {
+            addr
+        } else if addr < ins {
+            window(cwp as usize) + addr - outs
+        } else if addr < ins + NUM_SHARED_PREV {
+            window(cwp as usize + 1) + addr - ins
+        } else {
+            return Err(());
         })
     }
 
-    /// Set a register's value (unsigned). Return the register's value on
-    /// success and a string message on error.
+    /// Set a register's value (unsigned), for callers that know the
+    /// register exists. Registers that come from outside the emulator go
+    /// through `try_write`. Writing one that does not exist is a bug in the
+    /// caller, it is logged as an error and the value is dropped.
     /// Register mapping: [0-9] -> Globals
     ///                   [10-15] -> Outs
     ///                   [16-25] -> Locals
@@ -251,20 +496,238 @@ impl RegisterFile {
     /// * `value` - Value to write into the register.
     /// * `cwp` - Current window pointer. Used to determine the real address of the register.
     pub fn write(&mut self, address: u8, value: u32, cwp: u8) {
-        let addr = address as usize;
-        let ptr = cwp as usize;
-        match self.get_real_address(address, cwp) {
-            Ok(a) => self.0[a] = value,
-            Err(_) => {} // TODO figure out what to do here.
+        if self.try_write(address, value, cwp).is_err() {
+            error!(
+                "Write of 0x{:08x} to r{}, which does not exist, in window {}",
+                value, address, cwp
+            );
+        }
+    }
+
+    /// Get the registers seen from a window, grouped by kind.
+    /// # Arguments
+    /// * `cwp` - Window pointer to look from.
+    pub fn dump(&self, cwp: u8) -> RegisterDump {
+        let mut dump = RegisterDump {
+            cwp: cwp,
+            globals: [0; NUM_GLOBALS],
+            outs: [0; NUM_SHARED_NEXT],
+            locals: [0; NUM_LOCALS],
+            ins: [0; NUM_SHARED_PREV],
+            window: [0; WINDOW_SIZE],
+        };
+        for (i, reg) in dump.globals.iter_mut().enumerate() {
+            *reg = self.read(i as u8, cwp);
+        }
+        for (i, reg) in dump.window.iter_mut().enumerate() {
+            *reg = self.read((NUM_GLOBALS + i) as u8, cwp);
+        }
+        let (outs, rest) = dump.window.split_at(NUM_SHARED_NEXT);
+        let (locals, ins) = rest.split_at(NUM_LOCALS);
+        dump.outs.copy_from_slice(outs);
+        dump.locals.copy_from_slice(locals);
+        dump.ins.copy_from_slice(ins);
+        dump
+    }
+
+    /// Get the stack pointer of the window `cwp`.
+    /// # Arguments
+    /// * `cwp` - Window pointer of the frame.
+    pub fn stack_pointer(&self, cwp: u8) -> u32 {
+        self.read(STACK_POINTER_REG, cwp)
+    }
+
+    /// Get the frame pointer of the window `cwp`.
+    /// # Arguments
+    /// * `cwp` - Window pointer of the frame.
+    pub fn frame_pointer(&self, cwp: u8) -> u32 {
+        self.read(FRAME_POINTER_REG, cwp)
+    }
+
+    /// Get the return address of the window `cwp`.
+    /// # Arguments
+    /// * `cwp` - Window pointer of the frame.
+    pub fn return_address(&self, cwp: u8) -> u32 {
+        self.read(RETURN_ADDRESS_REG, cwp)
+    }
+
+    /// Get the frames resident in the register file, starting with the
+    /// current window and walking back to the saved window.
+    /// # Arguments
+    /// * `psw` - Processor status word holding the CWP and SWP.
+    pub fn backtrace(&self, psw: &ProcessorStatusWord) -> Vec<Frame> {
+        let mut result = Vec::new();
+        let mut window = psw.get_cwp();
+        loop {
+            result.push(Frame {
+                window: window,
+                sp: self.stack_pointer(window),
+                fp: self.frame_pointer(window),
+                ra: self.return_address(window),
+            });
+            if window == psw.get_swp() || result.len() == self.windows as usize {
+                break;
+            }
+            window = (window + 1) % self.windows;
+        }
+        result
+    }
+}
+
+impl Snapshot for RegisterFile {
+    fn save(&self, w: &mut Writer) {
+        w.put_u8(self.windows);
+        for reg in self.regs.iter() {
+            w.put_u32(*reg);
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> util::Result<()> {
+        let windows = r.get_u8()?;
+        if windows < MIN_REG_WINDOWS || windows > MAX_REG_WINDOWS {
+            return berr!(
+                Config,
+                format!("Invalid number of register windows {}", windows)
+            );
+        }
+        self.windows = windows;
+        for reg in self.regs.iter_mut() {
+            *reg = r.get_u32()?;
+        }
+        self.regs[0] = 0;
+        Ok(())
+    }
+}
+
+impl Serialize for RegisterFile {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        RegisterFileState {
+            windows: self.windows,
+            regs: self.regs.to_vec(),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for RegisterFile {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let state = RegisterFileState::deserialize(d)?;
+        if state.windows < MIN_REG_WINDOWS || state.windows > MAX_REG_WINDOWS {
+            return Err(D::Error::custom(format!(
+                "Invalid number of register windows {}",
+                state.windows
+            )));
+        }
+        let mut result = Self::with_windows(state.windows);
+        if state.regs.len() != result.regs.len() {
+            return Err(D::Error::custom(format!(
+                "Expected {} registers, got {}",
+                result.regs.len(),
+                state.regs.len()
+            )));
+        }
+        result.regs.copy_from_slice(&state.regs);
+        result.regs[0] = 0;
+        Ok(result)
+    }
+}
+
+impl RegisterDump {
+    /// Get a register's value.
+    /// # Arguments
+    /// * `reg` - Which register, [0-31].
+    pub fn get(&self, reg: u8) -> u32 {
+        let reg = reg as usize;
+        if reg < NUM_GLOBALS {
+            self.globals[reg]
+        } else {
+            self.window[reg - NUM_GLOBALS]
+        }
+    }
+}
+
+impl fmt::LowerHex for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "window {}", self.cwp)?;
+        let first_local = NUM_GLOBALS + NUM_SHARED_NEXT;
+        let groups: [(&str, usize, &[u32]); 4] = [
+            ("globals", 0, &self.globals),
+            ("ins", first_local + NUM_LOCALS, &self.ins),
+            ("locals", first_local, &self.locals),
+            ("outs", NUM_GLOBALS, &self.outs),
+        ];
+        for (name, first, regs) in groups.iter() {
+            write_registers(f, name, *first, regs)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RegisterDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for RegisterFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The raw array, eight words to a row, each row led by the index of
+        // its first word.
+        for (row, regs) in self.regs.chunks(8).enumerate() {
+            if row != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:3}:", row * 8)?;
+            for reg in regs {
+                write_word(f, *reg)?;
+            }
         }
-        // Ensure register is 0.
-        self.0[0] = 0;
+        Ok(())
     }
 }
 
 impl fmt::Display for RegisterFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.0)
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl PswField {
+    /// Every field, from the most significant bit down.
+    pub const ALL: [PswField; 7] = [
+        PswField::InterruptEnabled,
+        PswField::SystemMode,
+        PswField::PreviousSystemMode,
+        PswField::Zero,
+        PswField::Negative,
+        PswField::Overflow,
+        PswField::Carry,
+    ];
+
+    /// Get the letter the field is known by.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PswField::InterruptEnabled => "I",
+            PswField::SystemMode => "S",
+            PswField::PreviousSystemMode => "P",
+            PswField::Zero => "Z",
+            PswField::Negative => "N",
+            PswField::Overflow => "V",
+            PswField::Carry => "C",
+        }
+    }
+
+    /// Get the bit of the field in the PSW.
+    fn mask(&self) -> u16 {
+        match *self {
+            PswField::InterruptEnabled => INTERRUPT_LOC,
+            PswField::SystemMode => SYSTEM_LOC,
+            PswField::PreviousSystemMode => PREV_SYSTEM_LOC,
+            PswField::Zero => ZERO_LOC,
+            PswField::Negative => NEG_LOC,
+            PswField::Overflow => OVERFLOW_LOC,
+            PswField::Carry => CARRY_LOC,
+        }
     }
 }
 
@@ -306,62 +769,83 @@ impl ProcessorStatusWord {
         self.0
     }
 
-    /// Push the register window stack. Set CWP to CWP-1 MOD 8. Push the top
-    /// window to memory and increment SWP if necessary.
-    pub fn push(&mut self) {
-        let cwp = self.get_cwp() - 1;
+    /// Get a flag.
+    /// # Arguments
+    /// * `field` - The flag.
+    pub fn get_field(&self, field: PswField) -> bool {
+        self.0 & field.mask() != 0
+    }
+
+    /// Set or clear a flag.
+    /// # Arguments
+    /// * `field` - The flag.
+    /// * `value` - True to set it.
+    pub fn set_field(&mut self, field: PswField, value: bool) {
+        self.0 = (self.0 & !field.mask()) | if value { field.mask() } else { 0 };
+    }
+
+    /// Push the register window stack. Set CWP to CWP-1 MOD `windows`. Push
+    /// the top window to memory and increment SWP if necessary.
+    /// # Arguments
+    /// * `windows` - Number of windows in the register file.
+    pub fn push(&mut self, windows: u8) {
+        let cwp = (self.get_cwp() + windows - 1) % windows;
         let swp = self.get_swp();
+        self.set_cwp(cwp);
         if cwp == swp {
             // TODO save windows to memory.
-            self.set_swp(swp + 1);
+            self.set_swp((swp + 1) % windows);
         }
     }
 
-    /// Pop the register window stack. Set CWP to CWP+1 MOD 8. Pull the bottom
-    /// window from memory and decrement SWP if necessary.
-    pub fn pop(&mut self) {
-        let cwp = self.get_cwp() + 1;
+    /// Pop the register window stack. Set CWP to CWP+1 MOD `windows`. Pull
+    /// the bottom window from memory and decrement SWP if necessary.
+    /// # Arguments
+    /// * `windows` - Number of windows in the register file.
+    pub fn pop(&mut self, windows: u8) {
+        let cwp = (self.get_cwp() + 1) % windows;
         let swp = self.get_swp();
+        self.set_cwp(cwp);
         if cwp == swp {
             // TODO save windows to memory.
-            self.set_swp(swp - 1);
+            self.set_swp((swp + windows - 1) % windows);
         }
     }
 
     pub fn set_cwp(&mut self, v: u8) {
-        self.0 = ((self.0 & !CWP_LOC) | ((v % NUM_REG_WINDOWS as u8) << 10) as u16) & PSW_LOC;
+        self.0 = ((self.0 & !CWP_LOC) | (((v % MAX_REG_WINDOWS) as u16) << 10)) & PSW_LOC;
     }
 
     pub fn set_swp(&mut self, v: u8) {
-        self.0 = ((self.0 & !SWP_LOC) | ((v % NUM_REG_WINDOWS as u8) << 7) as u16) & PSW_LOC;
+        self.0 = ((self.0 & !SWP_LOC) | (((v % MAX_REG_WINDOWS) as u16) << 7)) & PSW_LOC;
     }
 
     pub fn set_cc_overflow(&mut self, value: bool) {
-        self.0 = (self.0 & !OVERFLOW_LOC) | ((value as u16) << OVERFLOW_LOC);
+        self.0 = (self.0 & !OVERFLOW_LOC) | if value { OVERFLOW_LOC } else { 0 };
     }
 
     pub fn set_cc_carry(&mut self, value: bool) {
-        self.0 = (self.0 & !CARRY_LOC) | ((value as u16) << CARRY_LOC);
+        self.0 = (self.0 & !CARRY_LOC) | if value { CARRY_LOC } else { 0 };
     }
 
     pub fn set_cc_zero(&mut self, value: bool) {
-        self.0 = (self.0 & !ZERO_LOC) | ((value as u16) << ZERO_LOC);
+        self.0 = (self.0 & !ZERO_LOC) | if value { ZERO_LOC } else { 0 };
     }
 
     pub fn set_cc_neg(&mut self, value: bool) {
-        self.0 = (self.0 & !NEG_LOC) | ((value as u16) << NEG_LOC);
+        self.0 = (self.0 & !NEG_LOC) | if value { NEG_LOC } else { 0 };
     }
 
     pub fn set_system_mode(&mut self, value: bool) {
-        self.0 = (self.0 & !SYSTEM_LOC) | ((value as u16) << SYSTEM_LOC);
+        self.0 = (self.0 & !SYSTEM_LOC) | if value { SYSTEM_LOC } else { 0 };
     }
 
     pub fn set_previous_system_mode(&mut self, value: bool) {
-        self.0 = (self.0 & !PREV_SYSTEM_LOC) | ((value as u16) << PREV_SYSTEM_LOC);
+        self.0 = (self.0 & !PREV_SYSTEM_LOC) | if value { PREV_SYSTEM_LOC } else { 0 };
     }
 
     pub fn set_interrupt_enabled(&mut self, value: bool) {
-        self.0 = (self.0 & !INTERRUPT_LOC) | ((value as u16) << INTERRUPT_LOC);
+        self.0 = (self.0 & !INTERRUPT_LOC) | if value { INTERRUPT_LOC } else { 0 };
     }
 
     pub fn get_cwp(&self) -> u8 {
@@ -369,7 +853,7 @@ impl ProcessorStatusWord {
     }
 
     pub fn get_swp(&self) -> u8 {
-        ((self.0 & SWP_LOC) as u8) >> 7
+        ((self.0 & SWP_LOC) >> 7) as u8
     }
 
     pub fn get_cc_overflow(&self) -> bool {
@@ -433,6 +917,137 @@ CC Carry: {}",
     }
 }
 
+impl Serialize for ProcessorStatusWord {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        PswState {
+            cwp: self.get_cwp(),
+            swp: self.get_swp(),
+            interrupts: self.get_interrupt_enabled(),
+            system: self.get_system_mode(),
+            previous_system: self.get_previous_system_mode(),
+            z: self.get_cc_zero(),
+            n: self.get_cc_neg(),
+            v: self.get_cc_overflow(),
+            c: self.get_cc_carry(),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProcessorStatusWord {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let state = PswState::deserialize(d)?;
+        if state.cwp >= MAX_REG_WINDOWS || state.swp >= MAX_REG_WINDOWS {
+            return Err(D::Error::custom(format!(
+                "Invalid window pointers {} and {}",
+                state.cwp, state.swp
+            )));
+        }
+        Ok(Self::init(
+            state.cwp,
+            state.swp,
+            state.interrupts,
+            state.previous_system,
+            state.system,
+            state.z,
+            state.n,
+            state.v,
+            state.c,
+        ))
+    }
+}
+
+impl Trap {
+    /// Get the position of this kind of trap in a trap vector table.
+    pub fn index(&self) -> usize {
+        match *self {
+            Self::Alignment(_) => 0,
+            Self::PrivilegeViolation => 1,
+            Self::Coprocessor(_) => 2,
+            Self::Interrupt => 3,
+            Self::AccessViolation(_) => 4,
+            Self::IllegalInstruction => 5,
+        }
+    }
+
+    /// Get the address of the handler for this trap in the table a core
+    /// has after a reset.
+    pub fn vector(&self) -> u32 {
+        TrapVectors::new().get(*self)
+    }
+}
+
+impl TrapVectors {
+    /// Create the table a core has after a reset, based at
+    /// `TRAP_VECTOR_BASE`.
+    pub fn new() -> Self {
+        Self::at(TRAP_VECTOR_BASE)
+    }
+
+    /// Create a table with the handlers laid out `TRAP_VECTOR_SPACING`
+    /// bytes apart from a base address, in the order of `Trap::index`.
+    /// # Arguments
+    /// * `base` - Address of the first handler.
+    pub fn at(base: u32) -> Self {
+        let mut result = [0; TRAP_KINDS];
+        for (i, vector) in result.iter_mut().enumerate() {
+            *vector = base.wrapping_add(i as u32 * TRAP_VECTOR_SPACING);
+        }
+        TrapVectors(result)
+    }
+
+    /// Get the address of the handler for a trap.
+    /// # Arguments
+    /// * `trap` - The trap.
+    pub fn get(&self, trap: Trap) -> u32 {
+        self.0[trap.index()]
+    }
+
+    /// Get the address of the handler for a kind of trap.
+    /// # Arguments
+    /// * `index` - The kind of trap, as returned by `Trap::index`.
+    pub fn get_index(&self, index: usize) -> u32 {
+        self.0[index]
+    }
+
+    /// Move the handler for a kind of trap.
+    /// # Arguments
+    /// * `index` - The kind of trap, as returned by `Trap::index`.
+    /// * `vector` - Address of the new handler.
+    pub fn set_index(&mut self, index: usize, vector: u32) {
+        self.0[index] = vector;
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Alignment(addr) => write!(f, "Alignment fault at 0x{:08x}", addr),
+            Self::PrivilegeViolation => write!(f, "Privilege violation"),
+            Self::Coprocessor(cause) => write!(f, "Coprocessor fault 0x{:08x}", cause),
+            Self::Interrupt => write!(f, "Interrupt"),
+            Self::AccessViolation(addr) => write!(f, "Access violation at 0x{:08x}", addr),
+            Self::IllegalInstruction => write!(f, "Illegal instruction"),
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{} {}:{:08x} {}:{:08x} {}:{:08x}",
+            self.window,
+            register_name(STACK_POINTER_REG),
+            self.sp,
+            register_name(FRAME_POINTER_REG),
+            self.fp,
+            register_name(RETURN_ADDRESS_REG),
+            self.ra
+        )
+    }
+}
+
 impl OutputPins {
     pub fn new() -> Self {
         Self {
@@ -446,6 +1061,17 @@ impl OutputPins {
         }
     }
 
+    /// Get the width of the memory access in bytes.
+    pub fn width(&self) -> u32 {
+        if self.width_code_word {
+            4
+        } else if self.width_code_half {
+            2
+        } else {
+            1
+        }
+    }
+
     pub fn phase_two_copy(&self, other: &mut Self) {
         let addr = other.address;
         *other = *self;
@@ -453,8 +1079,89 @@ impl OutputPins {
     }
 }
 
+impl Snapshot for OutputPins {
+    fn save(&self, w: &mut Writer) {
+        w.put_u32(self.address);
+        w.put_u32(self.data);
+        w.put_bool(self.width_code_word);
+        w.put_bool(self.width_code_half);
+        w.put_bool(self.read_write);
+        w.put_bool(self.system_mode);
+        w.put_bool(self.instr_or_data_write);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> util::Result<()> {
+        self.address = r.get_u32()?;
+        self.data = r.get_u32()?;
+        self.width_code_word = r.get_bool()?;
+        self.width_code_half = r.get_bool()?;
+        self.read_write = r.get_bool()?;
+        self.system_mode = r.get_bool()?;
+        self.instr_or_data_write = r.get_bool()?;
+        Ok(())
+    }
+}
+
+// Public functions.
+
+/// Get the conventional name of a register, `r<n>` if it has none.
+/// # Arguments
+/// * `reg` - Register number.
+pub fn register_name(reg: u8) -> String {
+    match reg {
+        STACK_POINTER_REG => "sp".to_string(),
+        FRAME_POINTER_REG => "fp".to_string(),
+        RETURN_ADDRESS_REG => "ra".to_string(),
+        _ => format!("r{}", reg),
+    }
+}
+
+/// Parse a register name, either `r<n>` or a conventional alias (`sp`, `fp`,
+/// `ra`). Return None if `name` is not a register.
+/// # Arguments
+/// * `name` - Register name.
+pub fn register_from_name(name: &str) -> Option<u8> {
+    match name.to_lowercase().as_str() {
+        "sp" => Some(STACK_POINTER_REG),
+        "fp" => Some(FRAME_POINTER_REG),
+        "ra" => Some(RETURN_ADDRESS_REG),
+        n => match n.strip_prefix('r').map(|v| v.parse::<u8>()) {
+            Some(Ok(v)) if v < 32 => Some(v),
+            _ => None,
+        },
+    }
+}
+
 // Private functions.
 
+/// Write a row of named registers, e.g. `ins      r26 00000000 ...`.
+/// # Arguments
+/// * `f` - Formatter to write to.
+/// * `name` - Name of the row.
+/// * `first` - Number of the first register in the row.
+/// * `regs` - Values of the registers.
+fn write_registers(f: &mut fmt::Formatter, name: &str, first: usize, regs: &[u32]) -> fmt::Result {
+    write!(f, "\n{:<8}", name)?;
+    for (i, reg) in regs.iter().enumerate() {
+        write!(f, " {:<3}", format!("r{}", first + i))?;
+        write_word(f, *reg)?;
+    }
+    Ok(())
+}
+
+/// Write a register's value as a hex word, with a 0x prefix if the formatter
+/// asked for the alternate form.
+/// # Arguments
+/// * `f` - Formatter to write to.
+/// * `reg` - Register's value.
+fn write_word(f: &mut fmt::Formatter, reg: u32) -> fmt::Result {
+    if f.alternate() {
+        write!(f, " 0x{:08x}", reg)
+    } else {
+        write!(f, " {:08x}", reg)
+    }
+}
+
 /// Create a descriptive string for the system's privilege state bits.
 /// # Arguments
 /// * `s` - Privilege state bit.
@@ -0,0 +1,282 @@
+// Test code for the RISC II register file and status word.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "cpu.rs"]
+mod test {
+    extern crate toml;
+
+    use super::super::*;
+    use cpu::*;
+    use instruction::ShortSource;
+
+    #[test]
+    fn register_names() {
+        assert_eq!(register_name(STACK_POINTER_REG), "sp");
+        assert_eq!(register_name(FRAME_POINTER_REG), "fp");
+        assert_eq!(register_name(RETURN_ADDRESS_REG), "ra");
+        assert_eq!(register_name(3), "r3");
+    }
+
+    #[test]
+    fn register_aliases() {
+        assert_eq!(register_from_name("sp"), Some(14));
+        assert_eq!(register_from_name("FP"), Some(30));
+        assert_eq!(register_from_name("ra"), Some(31));
+        assert_eq!(register_from_name("r17"), Some(17));
+        assert_eq!(register_from_name("r32"), None);
+        assert_eq!(register_from_name("x1"), None);
+    }
+
+    #[test]
+    fn checked_register_access() {
+        let mut regs = RegisterFile::new();
+        assert!(regs.try_write(17, 0x1234, 1).is_ok());
+        assert_eq_hex!(regs.try_read(17, 1).unwrap(), 0x1234);
+        assert!(regs.try_write(0, 5, 0).is_ok());
+        assert_eq!(regs.try_read(0, 0).unwrap(), 0);
+        assert!(regs.try_read(32, 0).is_err());
+        assert!(regs.try_write(32, 5, 0).is_err());
+        // The unchecked accessors log bad registers instead.
+        let before = regs;
+        regs.write(32, 5, 0);
+        assert_eq!(regs, before);
+        assert_eq!(regs.read(32, 0), 0);
+    }
+
+    #[test]
+    fn short_source_values() {
+        let mut regs = RegisterFile::new();
+        regs.write(17, 0x1234, 1);
+        assert_eq_hex!(regs.get_ss_val(ShortSource::Reg(17), 1), 0x1234);
+        assert_eq_hex!(regs.get_ss_val(ShortSource::Imm13(0x1fff), 1), 0x1fff);
+    }
+
+    #[test]
+    fn psw_fields_toggle() {
+        let mut psw = ProcessorStatusWord::new();
+        psw.set_cwp(3);
+        for field in PswField::ALL.iter() {
+            assert!(!psw.get_field(*field));
+            psw.set_field(*field, true);
+            assert!(psw.get_field(*field), "{}", field.name());
+        }
+        assert_eq_hex!(psw.get(), (3 << 10) | 0x7f);
+        psw.set_field(PswField::Zero, false);
+        assert!(!psw.get_cc_zero());
+        assert!(psw.get_cc_neg());
+        assert_eq!(psw.get_cwp(), 3);
+
+        // A PSW waiting to be written keeps the change.
+        let mut dp = data_path::DataPath::new();
+        dp.delay_psw(ProcessorStatusWord::new());
+        dp.set_psw_field(PswField::Carry, true);
+        assert!(dp.psw().get_cc_carry());
+        dp.apply_pending_psw();
+        assert!(dp.psw().get_cc_carry());
+        assert!(!dp.psw().get_cc_zero());
+    }
+
+    #[test]
+    fn callers_sp_is_callees_fp() {
+        let mut regs = RegisterFile::new();
+        let mut psw = ProcessorStatusWord::new();
+        psw.set_cwp(2);
+        psw.set_swp(2);
+        regs.write(STACK_POINTER_REG, 0x1000, psw.get_cwp());
+        psw.push(regs.windows());
+        assert_eq_hex!(regs.frame_pointer(psw.get_cwp()), 0x1000);
+    }
+
+    #[test]
+    fn windows_wrap_around() {
+        let mut regs = RegisterFile::with_windows(4);
+        let mut psw = ProcessorStatusWord::new();
+        regs.write(10, 0x1234, 0);
+        psw.push(regs.windows());
+        assert_eq!(psw.get_cwp(), 3);
+        // The first window's outs are the last window's ins.
+        assert_eq_hex!(regs.read(26, psw.get_cwp()), 0x1234);
+        for _ in 0..3 {
+            psw.push(regs.windows());
+        }
+        // Back in the saved window, which must spill.
+        assert_eq!(psw.get_cwp(), 0);
+        assert_eq!(psw.get_swp(), 1);
+        psw.pop(regs.windows());
+        assert_eq!(psw.get_cwp(), 1);
+        assert_eq!(psw.get_swp(), 0);
+        assert_eq!(regs.backtrace(&psw).len(), 4);
+    }
+
+    #[test]
+    fn outs_alias_callers_ins() {
+        for windows in MIN_REG_WINDOWS..=MAX_REG_WINDOWS {
+            let mut regs = RegisterFile::with_windows(windows);
+            for n in 0..windows {
+                for i in 0..NUM_SHARED_NEXT as u8 {
+                    regs.write(10 + i, (n as u32) << 8 | i as u32, n);
+                }
+            }
+            for n in 0..windows {
+                // The callee of window N is window N-1.
+                let callee = (n + windows - 1) % windows;
+                for i in 0..NUM_SHARED_PREV as u8 {
+                    assert_eq_hex!(regs.read(26 + i, callee), (n as u32) << 8 | i as u32);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_outs_and_ins_alias() {
+        for windows in MIN_REG_WINDOWS..=MAX_REG_WINDOWS {
+            let regs = RegisterFile::with_windows(windows);
+            // True if register `out` of window `a` is register `r_in` of
+            // window `b`.
+            let shared = |(a, out): (u8, u8), (b, r_in): (u8, u8)| {
+                (10..16).contains(&out) && r_in == out + 16 && (a + windows - 1) % windows == b
+            };
+            let mut seen: Vec<(usize, u8, u8)> = Vec::new();
+            for window in 0..windows {
+                for reg in 0..32 {
+                    let real = regs.get_real_address(reg, window).unwrap();
+                    for &(_, w, r) in seen.iter().filter(|s| s.0 == real) {
+                        assert!(
+                            (r == reg && (reg as usize) < NUM_GLOBALS)
+                                || shared((w, r), (window, reg))
+                                || shared((window, reg), (w, r)),
+                            "w{} r{} is w{} r{} with {} windows",
+                            window,
+                            reg,
+                            w,
+                            r,
+                            windows
+                        );
+                    }
+                    seen.push((real, window, reg));
+                }
+            }
+            // Every window adds its outs and locals, no more.
+            let mut used: Vec<usize> = seen.iter().map(|s| s.0).collect();
+            used.sort();
+            used.dedup();
+            assert_eq!(
+                used.len(),
+                NUM_GLOBALS + windows as usize * NUM_ADDED_PER_WINDOW
+            );
+        }
+    }
+
+    #[test]
+    fn r0_is_zero_in_every_window() {
+        let mut regs = RegisterFile::new();
+        for window in 0..regs.windows() {
+            regs.write(0, 0x1234, window);
+            regs.write(1, 0x5678, window);
+        }
+        for window in 0..regs.windows() {
+            assert_eq!(regs.read(0, window), 0);
+            assert_eq_hex!(regs.read(1, window), 0x5678);
+        }
+    }
+
+    #[test]
+    fn backtrace_walks_to_saved_window() {
+        let mut regs = RegisterFile::new();
+        let mut psw = ProcessorStatusWord::new();
+        psw.set_cwp(2);
+        psw.set_swp(2);
+        regs.write(STACK_POINTER_REG, 0x1000, psw.get_cwp());
+        psw.push(regs.windows());
+        regs.write(STACK_POINTER_REG, 0xf00, psw.get_cwp());
+        regs.write(RETURN_ADDRESS_REG, 0x40, psw.get_cwp());
+
+        let frames = regs.backtrace(&psw);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].window, psw.get_cwp());
+        assert_eq_hex!(frames[0].sp, 0xf00);
+        assert_eq_hex!(frames[0].fp, 0x1000);
+        assert_eq_hex!(frames[0].ra, 0x40);
+        assert_eq_hex!(frames[1].sp, 0x1000);
+        assert_eq!(
+            frames[0].to_string(),
+            format!("#{} sp:00000f00 fp:00001000 ra:00000040", psw.get_cwp())
+        );
+    }
+
+    #[test]
+    fn dump_groups_registers_by_window() {
+        let mut regs = RegisterFile::new();
+        for r in 0..32 {
+            regs.write(r, 0x100 + r as u32, 2);
+        }
+        let dump = regs.dump(2);
+        assert_eq!(dump.cwp, 2);
+        assert_eq_hex!(dump.globals[9], 0x109);
+        assert_eq_hex!(dump.outs[0], 0x10a);
+        assert_eq_hex!(dump.locals[0], 0x110);
+        assert_eq_hex!(dump.ins[5], 0x11f);
+        assert_eq_hex!(dump.window[0], 0x10a);
+        for r in 0..32 {
+            assert_eq_hex!(dump.get(r), regs.read(r, 2));
+        }
+
+        // The caller's outs are the callee's ins.
+        let callee = regs.dump(1);
+        assert_eq!(callee.ins, dump.outs);
+        assert_eq!(callee.globals, dump.globals);
+    }
+
+    #[test]
+    fn dump_prints_risc_ii_layout() {
+        let mut regs = RegisterFile::new();
+        regs.write(26, 0xdeadbeef, 0);
+        let dump = regs.dump(0);
+        let text = format!("{:x}", dump);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "window 0");
+        assert!(lines[1].starts_with("globals  r0  00000000 r1  00000000"));
+        assert!(lines[2].starts_with("ins      r26 deadbeef r27 00000000"));
+        assert!(lines[3].starts_with("locals   r16 00000000"));
+        assert!(lines[4].starts_with("outs     r10 00000000"));
+        assert_eq!(dump.to_string(), text);
+        assert!(format!("{:#x}", dump).contains("r26 0xdeadbeef"));
+    }
+
+    #[test]
+    fn register_state_round_trips() {
+        let mut regs = RegisterFile::with_windows(4);
+        regs.write(10, 0x1234, 0);
+        regs.write(31, 0xdeadbeef, 3);
+        let psw = ProcessorStatusWord::init(3, 1, true, false, true, false, true, false, true);
+
+        let text = toml::to_string(&regs).unwrap();
+        assert_eq!(toml::from_str::<RegisterFile>(&text).unwrap(), regs);
+        let text = toml::to_string(&psw).unwrap();
+        assert!(text.contains("cwp = 3"));
+        assert!(text.contains("n = true"));
+        assert!(toml::from_str::<ProcessorStatusWord>(&text).unwrap() == psw);
+    }
+
+    #[test]
+    fn bad_register_state_is_rejected() {
+        assert!(toml::from_str::<RegisterFile>("windows = 9\nregs = []").is_err());
+        assert!(toml::from_str::<RegisterFile>("windows = 8\nregs = [1, 2]").is_err());
+        let psw = toml::to_string(&ProcessorStatusWord::new()).unwrap();
+        assert!(toml::from_str::<ProcessorStatusWord>(&psw.replace("cwp = 0", "cwp = 8")).is_err());
+    }
+}
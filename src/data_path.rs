@@ -13,11 +13,28 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+extern crate serde;
+extern crate serde_derive;
+
+use self::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use self::serde_derive::{Deserialize, Serialize};
 use alu::ALU;
-use cpu::{OutputPins, ProcessorStatusWord, RegisterFile, SIZEOF_INSTRUCTION};
+use config::PowerOnState;
+use cpu::{
+    OutputPins, ProcessorStatusWord, PswField, RegisterFile, Trap, TrapVectors, HWORD_ALIGN_MASK,
+    JUMP_ALIGN_MASK, PSW_LOC, RESET_VECTOR, SIZEOF_INSTRUCTION, WORD_ALIGN_MASK,
+};
+use decode;
+use extension::Extensions;
 use instruction::*;
-use shifter::Shifter;
+use mmu::{Mmu, Target};
+use r2d2::{Reader, Snapshot, Writer};
+use region::{Permission, RegionMap};
+use shifter::{lane_shift, Shifter};
 use std::fmt;
+use util::{Result, Xorshift};
+
+use berr;
 
 pub struct SCCBits {
     pub z: bool,
@@ -38,6 +55,38 @@ pub struct Control {
     pub dest_is_psw: bool,
 }
 
+/// A bus or unit of the data path a value moves over or through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// The external bus, from the pads to the decode latches and DIMM.
+    BusExt,
+    /// The register file (or the PC) to the ALU's AI latch.
+    BusA,
+    /// The register file or the immediate to the ALU's BI latch.
+    BusB,
+    /// The immediate latch onto busB.
+    Imm,
+    /// The shifter, aligning loaded data or replicating stored data.
+    Shifter,
+    /// The ALU.
+    Alu,
+    /// The DST latch to the register file.
+    BusD,
+    /// The PCs, and addresses out to the pads.
+    BusOut,
+}
+
+/// How a data path is serialized: only its architectural state, what
+/// software can see between instructions.
+#[derive(Serialize, Deserialize)]
+struct DataPathState {
+    pc: u32,
+    nxtpc: u32,
+    lstpc: u32,
+    psw: ProcessorStatusWord,
+    regs: RegisterFile,
+}
+
 /// RISC II emulated data path.
 #[derive(Debug, Clone)]
 pub struct DataPath {
@@ -57,6 +106,10 @@ pub struct DataPath {
     /// (or last attempted to be executed). When running an interrupt `lstpc`
     /// holds the address of the instruction that was aborted.
     lstpc: u32,
+    /// Trap cause register, holds the faulting address of the last
+    /// alignment trap or access violation, or the cause of the last
+    /// coprocessor trap. Read with `GETTCR`.
+    trap_cause: u32,
     /// Pins for communicating with the outside world (memory).
     output_pins: OutputPins,
     /// Arithmetic logic unit.
@@ -69,6 +122,8 @@ pub struct DataPath {
     dimm: u32,
     /// Immediate register (for instruction being decoded).
     imm: u32,
+    /// Immediate register (for currently executing instruction).
+    imm2: u32,
     /// Byte address register, bottom two bits of memory address being accesses.
     bar: u8,
     /// Destination register address (for instruction being decoded).
@@ -104,6 +159,29 @@ pub struct DataPath {
     control1: Control,
     control2: Control,
     control3: Control,
+    /// Target of the currently executing instruction's branch, if it is
+    /// taken.
+    branch_target: Option<u32>,
+    /// Trap raised by the currently executing instruction. The pipeline
+    /// must be flushed if set.
+    trap: Option<Trap>,
+    /// PSW written by `PUTPSW`, which takes effect once the instruction
+    /// after it has executed.
+    pending_psw: Option<ProcessorStatusWord>,
+    /// Window the committing instruction writes its result to, the CWP at
+    /// the end of its execute cycle.
+    cwp3: u8,
+    /// Memory management unit, translates the addresses put on the output
+    /// pins.
+    mmu: Mmu,
+    /// Extensions implementing the extension opcodes.
+    extensions: Extensions,
+    /// True if the instruction after a transfer runs before the target,
+    /// false if the target is next.
+    delay_slots: bool,
+    /// Routes that carried a value in the current phase, in the order they
+    /// fired.
+    routes: Vec<Route>,
 }
 
 // Impls.
@@ -132,9 +210,11 @@ impl DataPath {
             op2: 0,
             dimm: 0,
             imm: 0,
-            nxtpc: 0,
-            pc: 0,
+            imm2: 0,
+            nxtpc: RESET_VECTOR + SIZEOF_INSTRUCTION,
+            pc: RESET_VECTOR,
             lstpc: 0,
+            trap_cause: 0,
             output_pins: OutputPins::new(),
             scc_flag1: false,
             scc_flag2: false,
@@ -144,29 +224,67 @@ impl DataPath {
             control1: Control::new(),
             control2: Control::new(),
             control3: Control::new(),
+            branch_target: None,
+            trap: None,
+            pending_psw: None,
+            cwp3: 0,
+            mmu: Mmu::new(),
+            extensions: Extensions::new(),
+            delay_slots: true,
+            routes: Vec::new(),
         }
     }
 
+    /// Warm reset. Restart at the reset vector with a fresh PSW and empty
+    /// pipeline, keeping the contents of the register file.
+    pub fn reset(&mut self) {
+        let regs = self.regs;
+        let cpu_id = self.get_cpu_id();
+        let extensions = self.extensions.clone();
+        let delay_slots = self.delay_slots;
+        *self = Self::new();
+        self.regs = regs;
+        self.set_cpu_id(cpu_id);
+        self.extensions = extensions;
+        self.delay_slots = delay_slots;
+    }
+
+    /// Commit the result of the previous instruction, writing the destination
+    /// latch to its destination register.
     pub fn commit(&mut self) {
         let dest_value = self.dst_latch;
         let dest_reg = self.rd3;
-        let cwp = self.psw.get_cwp();
-        self.regs.write(dest_reg, dest_value, cwp);
+        self.regs.write(dest_reg, dest_value, self.cwp3);
+        self.route(Route::BusD);
     }
 
-    pub fn route_regs_to_alu(&mut self) {
-        if self.control1.pc_relative {
-            self.alu.ai = self.pc;
+    /// Read the executing instruction's source registers into the ALU's
+    /// (and for stores the shifter's) input latches. Return void on success
+    /// and an error if it names a register that does not exist.
+    /// # Arguments
+    /// * `committing` - Rd of the committing instruction, None if it has
+    /// none. Its result is only written back in phase three, so reads of it
+    /// are forwarded from the DST latch.
+    pub fn route_regs_to_alu(&mut self, committing: Option<u8>) -> Result<()> {
+        // TODO investigate interrupts. Should src2 be set no matter what?
+        self.alu.ai = if self.control2.pc_relative {
+            self.pc
         } else {
-            // TODO investigate interrupts. Should src2 be set no matter what?
-            let src1 = self.rs1_2;
-            let src2 = self.rs2_2;
-            let cwp = self.psw.get_cwp();
-            let read1 = self.regs.read(src1, cwp);
-            let read2 = self.regs.read(src2, cwp);
-            self.alu.ai = read1;
-            self.alu.bi = read2;
+            self.read_source(self.rs1_2, committing)?
+        };
+        self.alu.bi = self.read_source(self.rs2_2, committing)?;
+        self.route(Route::BusA);
+        self.route(Route::BusB);
+        if self.control2.store {
+            // Stores send Rd to memory through the shifter.
+            self.shifter.src = self.read_source(self.rd2, committing)?;
+            self.route(Route::Shifter);
+        } else {
+            // Rd is written back when the instruction commits, which cannot
+            // fail, so make sure it exists now.
+            self.regs.try_read(self.rd2, self.psw.get_cwp())?;
         }
+        Ok(())
     }
 
     pub fn set_input_pins(&mut self, value: u32) {
@@ -179,6 +297,24 @@ impl DataPath {
         self.rs1_1 = ((value & RS1_LOC) >> 14) as u8;
         self.rs2_1 = (value & RS2_LOC) as u8;
         self.imm = value & IMM19_LOC;
+        self.route(Route::BusExt);
+    }
+
+    /// Finish a load, aligning and extending the data read from memory
+    /// through the shifter into the destination latch.
+    /// # Arguments
+    /// * `value` - Word read from memory, the loaded data on the byte lanes
+    /// selected by BAR.
+    pub fn load_data(&mut self, value: u32) {
+        self.dimm = value;
+        self.shifter.src = value;
+        self.route(Route::BusExt);
+        self.route(Route::Shifter);
+        let size = self.output_pins.width();
+        let result = self
+            .shifter
+            .align_load(self.bar, size, self.control2.signed_load);
+        self.set_result(logical_scc(result));
     }
 
     pub fn get_out_address(&self) -> u32 {
@@ -194,8 +330,10 @@ impl DataPath {
     }
 
     pub fn route_imm_to_alu(&mut self) {
-        if self.control1.immediate {
-            self.alu.bi = self.dimm;
+        if self.control2.immediate {
+            self.alu.bi = self.imm2;
+            self.route(Route::Imm);
+            self.route(Route::BusB);
         }
     }
 
@@ -217,29 +355,113 @@ impl DataPath {
         // Move the opcode.
         self.op2 = self.op1;
         // Move the actual immediate.
-        self.dimm = if self.control2.long {
-            // Place into highest 19 bits.
-            self.imm << 13
-        } else {
-            // Sign extend immediate to 32 bits.
-            if self.imm & SHORT_IMM_SIGN_LOC != 0 {
-                self.imm & SHORT_IMM_SIGNEXT_BITS
-            } else {
-                self.imm
-            }
-        };
+        self.imm2 = self.imm;
+        // Nothing has been decided about the new instruction's branch yet.
+        self.branch_target = None;
     }
 
-    fn increment_pcs(&mut self) {
+    pub fn increment_pcs(&mut self) {
         self.lstpc = self.pc;
         self.pc = self.nxtpc;
         self.nxtpc += SIZEOF_INSTRUCTION;
     }
 
-    fn branch_to(&mut self, address: u32) {
+    /// Move the PCs to a transfer's target. With delay slots the
+    /// instruction after the transfer runs first and the target after it,
+    /// without them the target is next.
+    /// # Arguments
+    /// * `address` - The target.
+    pub fn branch_to(&mut self, address: u32) {
         self.lstpc = self.pc;
-        self.pc = self.nxtpc;
-        self.nxtpc = address;
+        if self.delay_slots {
+            self.pc = self.nxtpc;
+            self.nxtpc = address;
+        } else {
+            self.pc = address;
+            self.nxtpc = address.wrapping_add(SIZEOF_INSTRUCTION);
+        }
+    }
+
+    /// Abort the current instruction and jump to the handler for `trap` in
+    /// the core's trap vector table.
+    /// The address of the aborted instruction is saved in `lstpc`, the
+    /// faulting address (if any) in the trap cause register, and the
+    /// CPU is switched into system mode with interrupts disabled.
+    /// # Arguments
+    /// * `trap` - The trap that was raised.
+    pub fn raise_trap(&mut self, trap: Trap) {
+        let vector = self.mmu.get_trap_vectors().get(trap);
+        match trap {
+            Trap::Alignment(cause) | Trap::Coprocessor(cause) | Trap::AccessViolation(cause) => {
+                self.trap_cause = cause
+            }
+            Trap::PrivilegeViolation | Trap::Interrupt | Trap::IllegalInstruction => {}
+        }
+        // A `PUTPSW` before the aborted instruction still takes effect.
+        self.apply_pending_psw();
+        self.lstpc = self.pc;
+        self.psw
+            .set_previous_system_mode(self.psw.get_system_mode());
+        self.psw.set_system_mode(true);
+        self.psw.set_interrupt_enabled(false);
+        self.pc = vector;
+        self.nxtpc = vector + SIZEOF_INSTRUCTION;
+    }
+
+    /// Get the PSW written by `PUTPSW` that is not in effect yet, if any.
+    pub fn get_pending_psw(&self) -> Option<ProcessorStatusWord> {
+        self.pending_psw
+    }
+
+    /// Hold a PSW written by `PUTPSW` until the instruction after it has
+    /// executed.
+    /// # Arguments
+    /// * `psw` - The new PSW.
+    pub fn delay_psw(&mut self, psw: ProcessorStatusWord) {
+        self.pending_psw = Some(psw);
+    }
+
+    /// Put the PSW written by `PUTPSW` into effect, if there is one.
+    pub fn apply_pending_psw(&mut self) {
+        if let Some(psw) = self.pending_psw.take() {
+            self.psw = psw;
+        }
+    }
+
+    /// End the execute cycle of the executing instruction: latch the window
+    /// its result is written to and put the PSW of a `PUTPSW` before it into
+    /// effect.
+    pub fn finish_execute(&mut self) {
+        self.cwp3 = self.psw.get_cwp();
+        if !self.control2.dest_is_psw {
+            self.apply_pending_psw();
+        }
+    }
+
+    /// Return (and clear) the trap raised by the executing instruction, if
+    /// any. The pipeline must be flushed if there is one.
+    pub fn take_trap(&mut self) -> Option<Trap> {
+        self.trap.take()
+    }
+
+    /// Get the target of the executing instruction's branch, if it is taken.
+    pub fn get_branch_target(&self) -> Option<u32> {
+        self.branch_target
+    }
+
+    /// Abort the executing instruction with `trap`, e.g. because it could
+    /// not be fetched, and flush the pipeline.
+    /// # Arguments
+    /// * `trap` - The trap to raise.
+    pub fn abort_executing(&mut self, trap: Trap) {
+        self.pipeline_trap(trap);
+    }
+
+    /// Throw away the instruction being decoded.
+    pub fn squash_decode(&mut self) {
+        self.control1 = Control::new();
+        self.scc_flag1 = false;
+        self.imm = 0;
     }
 
     /// Get the 13 bit PSW value. PSW is the state of the system's special
@@ -258,14 +480,6 @@ impl DataPath {
         self.psw.get() as u32
     }
 
-    pub fn call(&mut self, addr: u32) {
-        self.psw.push();
-    }
-
-    pub fn ret(&mut self) {
-        self.psw.pop();
-    }
-
     pub fn get_register_file(&mut self) -> &mut RegisterFile {
         &mut self.regs
     }
@@ -278,6 +492,123 @@ impl DataPath {
         self.lstpc
     }
 
+    pub fn get_trap_cause(&self) -> u32 {
+        self.trap_cause
+    }
+
+    pub fn get_mmu(&self) -> Mmu {
+        self.mmu
+    }
+
+    pub fn set_mmu(&mut self, mmu: Mmu) {
+        self.mmu = mmu;
+    }
+
+    /// Get the number of the core this data path belongs to.
+    pub fn get_cpu_id(&self) -> u32 {
+        self.mmu.get_cpu_id()
+    }
+
+    pub fn set_cpu_id(&mut self, id: u32) {
+        self.mmu.set_cpu_id(id);
+    }
+
+    /// Get the extensions implementing the extension opcodes.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn set_extensions(&mut self, extensions: Extensions) {
+        self.extensions = extensions;
+    }
+
+    /// Return true if the instruction after a transfer runs before the
+    /// target.
+    pub fn has_delay_slots(&self) -> bool {
+        self.delay_slots
+    }
+
+    /// Choose whether the instruction after a transfer runs before the
+    /// target.
+    /// # Arguments
+    /// * `delay_slots` - True for delay slots, false to go to the target
+    /// right away.
+    pub fn set_delay_slots(&mut self, delay_slots: bool) {
+        self.delay_slots = delay_slots;
+    }
+
+    /// Move the handlers of every kind of trap.
+    /// # Arguments
+    /// * `vectors` - The new trap vector table.
+    pub fn set_trap_vectors(&mut self, vectors: TrapVectors) {
+        self.mmu.set_trap_vectors(vectors);
+    }
+
+    /// Put the register file and the data latches in their power on state.
+    /// The control latches stay clear, so the pipeline starts empty.
+    /// # Arguments
+    /// * `state` - What the registers and latches hold.
+    /// * `rng` - Generator of the random state.
+    pub fn power_on(&mut self, state: PowerOnState, rng: &mut Xorshift) {
+        self.regs.power_on(state, rng);
+        for latch in [
+            &mut self.dst_latch,
+            &mut self.dimm,
+            &mut self.imm,
+            &mut self.imm2,
+            &mut self.alu.ai,
+            &mut self.alu.bi,
+            &mut self.shifter.src,
+        ] {
+            *latch = state.value(rng);
+        }
+    }
+
+    /// Replace the register file with a 0'd out one.
+    /// # Arguments
+    /// * `windows` - Number of register windows it has.
+    pub fn set_windows(&mut self, windows: u8) {
+        self.regs = RegisterFile::with_windows(windows);
+    }
+
+    /// Translate the address of an instruction fetch. Return an access
+    /// violation if the MMU refuses it or the memory is not executable.
+    /// # Arguments
+    /// * `addr` - Virtual address of the instruction.
+    /// * `regions` - What each region of memory allows.
+    pub fn translate_fetch(
+        &self,
+        addr: u32,
+        regions: &RegionMap,
+    ) -> std::result::Result<u32, Trap> {
+        let physical = self.mmu.translate_fetch(addr, self.psw.get_system_mode())?;
+        if regions.allows(physical, 4, Permission::Execute) {
+            Ok(physical)
+        } else {
+            Err(Trap::AccessViolation(addr))
+        }
+    }
+
+    /// Abort the executing instruction's load or store before it uses the
+    /// bus, because memory does not allow it.
+    /// # Arguments
+    /// * `regions` - What each region of memory allows.
+    pub fn check_access(&mut self, regions: &RegionMap) {
+        if !self.control2.memory {
+            return;
+        }
+        let pins = &self.output_pins;
+        let permission = if pins.read_write {
+            Permission::Write
+        } else {
+            Permission::Read
+        };
+        if !regions.allows(pins.address, pins.width(), permission) {
+            let addr = self.effective_address();
+            self.pipeline_trap(Trap::AccessViolation(addr));
+        }
+    }
+
     pub fn get_pc(&self) -> u32 {
         self.pc
     }
@@ -286,6 +617,15 @@ impl DataPath {
         self.nxtpc
     }
 
+    /// Point the data path at an instruction to run next, as if it had just
+    /// come out of reset there.
+    /// # Arguments
+    /// * `pc` - Address of the instruction.
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+        self.nxtpc = pc.wrapping_add(SIZEOF_INSTRUCTION);
+    }
+
     pub fn get_psw(&self) -> ProcessorStatusWord {
         self.psw
     }
@@ -294,190 +634,440 @@ impl DataPath {
         self.psw = ProcessorStatusWord::from_u16(psw);
     }
 
+    /// Set or clear a flag of the PSW, e.g. from the debugger. A PSW
+    /// written by `PUTPSW` that is yet to take effect gets the flag too, so
+    /// the change is not lost when it does.
+    /// # Arguments
+    /// * `field` - The flag.
+    /// * `value` - True to set it.
+    pub fn set_psw_field(&mut self, field: PswField, value: bool) {
+        self.psw.set_field(field, value);
+        if let Some(psw) = self.pending_psw.as_mut() {
+            psw.set_field(field, value);
+        }
+    }
+
+    /// Check if the conditional in the destination field holds for the
+    /// CC's. Code 0 is not a conditional and never holds.
     pub fn test_conditional(&self) -> bool {
-        let n = self.psw.get_cc_neg();
-        let v = self.psw.get_cc_overflow();
-        let z = self.psw.get_cc_zero();
-        let c = self.psw.get_cc_carry();
-        // TODO in the book some of these OR's are +, not sure why.
-        match self.rd2 & 0xf {
-            // Greater than.
-            1 => !((n ^ v) | z),
-            // Less than or equal.
-            2 => (n ^ v) | z,
-            // Greater than or equal to.
-            3 => !(n ^ v),
-            // Less than,
-            4 => n ^ v,
-            // Higher than
-            5 => !(!c | z),
-            // Lower than or same.
-            6 => !c | z,
-            // Lower than no carry.
-            7 => !c,
-            // Higher than no sign.
-            8 => c,
-            // Plus (test signed).
-            9 => !n,
-            // Minus (test signed).
-            10 => n,
-            // Not equal.
-            11 => !z,
-            // Equal.
-            12 => z,
-            // No overflow.
-            13 => !v,
-            // Overflow.
-            14 => v,
-            // Always.
-            15 => true,
-            _ => false,
+        Conditional::from_code(self.rd2).map_or(false, |c| c.evaluate(&self.psw))
+    }
+
+    /// Decode the instruction in the DIMM latch, setting the decode stage's
+    /// control bits and immediate. Return the micro operations the
+    /// instruction performs in each phase of its execute cycle, plus the
+    /// one run when it commits, and the decoded instruction. Invalid
+    /// instructions decode to a no-op.
+    pub fn decode(&mut self) -> (InstructionCycle, Option<Instruction>) {
+        type I = Instruction;
+        let instruction = match decode::decode(self.dimm) {
+            Ok(v) => v,
+            Err(_) => {
+                self.squash_decode();
+                return (InstructionCycle::noop_cycle(), None);
+            }
+        };
+
+        let mut control = Control::of(&instruction);
+        self.imm = match instruction {
+            I::Calli(s)
+            | I::GetPSW(s)
+            | I::GetLPC(s)
+            | I::GetTCR(s)
+            | I::PutPSW(s)
+            | I::Callx(s)
+            | I::Sll(s)
+            | I::Srl(s)
+            | I::Sra(s)
+            | I::Or(s)
+            | I::And(s)
+            | I::Xor(s)
+            | I::Add(s)
+            | I::Addc(s)
+            | I::Sub(s)
+            | I::Subc(s)
+            | I::Subi(s)
+            | I::Subci(s)
+            | I::Ldxw(s)
+            | I::Ldxhs(s)
+            | I::Ldxhu(s)
+            | I::Ldxbs(s)
+            | I::Ldxbu(s)
+            | I::Stxw(s)
+            | I::Stxh(s)
+            | I::Stxb(s)
+            | I::Extension(_, s) => short_imm(s.short_source, &mut control),
+            I::Jmpx(s) | I::Ret(s) | I::Reti(s) => short_imm(s.short_source, &mut control),
+            // Ldhi's immediate is shifted into place when it executes, the
+            // others are offsets from the PC.
+            I::Ldhi(l) => long_imm(l.imm19, &mut control),
+            I::Callr(l)
+            | I::Ldrw(l)
+            | I::Ldrhs(l)
+            | I::Ldrhu(l)
+            | I::Ldrbs(l)
+            | I::Ldrbu(l)
+            | I::Strw(l)
+            | I::Strh(l)
+            | I::Strb(l) => long_imm(l.offset(), &mut control),
+            I::Jmpr(l) => long_imm(l.offset(), &mut control),
+        };
+
+        self.control1 = control;
+        (instruction_cycle(&instruction), Some(instruction))
+    }
+
+    pub fn current_instruction_is_memory(&self) -> bool {
+        self.control2.memory
+    }
+
+    /// Forget the routes of the last phase. The cycle engine calls this as
+    /// each phase starts.
+    pub fn clear_routes(&mut self) {
+        self.routes.clear();
+    }
+
+    /// Get the routes that carried a value in the last phase, in the order
+    /// they fired. Only the cycle engine records them, the fast engine
+    /// leaves this empty.
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// True if a route carried a value in the last phase.
+    /// # Arguments
+    /// * `route` - The route.
+    pub fn route_fired(&self, route: Route) -> bool {
+        self.routes.contains(&route)
+    }
+
+    // Micro operations.
+
+    /// Latch `result` as the destination value, setting the CC's if the
+    /// executing instruction asked for it.
+    fn set_result(&mut self, (result, scc): (u32, SCCBits)) {
+        self.dst_latch = result;
+        if self.scc_flag2 {
+            self.set_cc(&scc);
         }
     }
 
-    pub fn decode(&mut self) -> InstructionCycle {
-        let instruction = self.dimm;
-        let memory = (instruction & (0b11 << 6) >> 6) == 1;
-        let store = (instruction & (0b111 << 5) >> 5) == 0b11;
-        let pc_relative = (memory && (instruction & 1) == 1)
-            || ((instruction & 0b11 == 0b01) && (instruction & (0b1111 << 3) == 1));
-        let signed_load = (instruction & (0b1111 << 3) == 0b0101) && (instruction & 0b10 == 0b10);
-        let conditional = instruction & (0b11111 << 2) == 0b00011;
-        let mut long = false;
-        let mut immediate = false;
-        let mut dst_is_psw = false;
-
-        let opcode = ((instruction & OPCODE_LOC) >> 25) as u8;
-
-        let mut result = InstructionCycle::noop_cycle();
-        // TODO set ALU and shift operation.
-        // Match opcode's prefix.
-        match opcode >> 4 {
-            0 => match opcode & 0xf {
-                0x1 => {
-                    // Calli TODO.
-                }
-                0x2 => {
-                    // GetPSW
-                }
-                0x3 => {
-                    // GetLPC
-                }
-                0x4 => {
-                    // PutPSW
-                    dst_is_psw = true;
-                }
-                0x8 => {
-                    // Callx
-                }
-                0x9 => {
-                    // Callr
-                    long = true;
-                    immediate = true;
-
-                    result = InstructionCycle {
-                        0: [
-                            noop,
-                            noop,
-                            |dp: &mut DataPath| -> () {
-                                dp.callr_step3();
-                            },
-                            noop,
-                            |dp: &mut DataPath| -> () {
-                                dp.commit_callr();
-                            },
-                        ],
-                    };
-                }
-                0xc => {
-                    // Jmpx
-                    result = InstructionCycle {
-                        0: [
-                            noop,
-                            noop,
-                            |dp: &mut DataPath| -> () {
-                                dp.jmpx_step3();
-                            },
-                            noop,
-                            |dp: &mut DataPath| -> () {
-                                dp.commit_jmpx();
-                            },
-                        ],
-                    };
-                }
-                0xd => {
-                    // Jmpr
-                    long = true;
-                    immediate = true;
-                }
-                0xe => {
-                    // Ret
-                }
-                0xf => {
-                    // Reti
-                }
-                _ => {}
-            },
-
-            _ => {}
-        }
-
-        immediate = if immediate {
-            immediate
-        } else {
-            instruction & SHORT_SOURCE_TYPE_LOC == 0
+    /// Latch the result of the ALU as the destination value.
+    fn alu_result(&mut self, result: (u32, SCCBits)) {
+        self.route(Route::Alu);
+        self.set_result(result);
+    }
+
+    /// Note that a route carried a value in the current phase.
+    fn route(&mut self, route: Route) {
+        if !self.routes.contains(&route) {
+            self.routes.push(route);
+        }
+    }
+
+    fn set_cc(&mut self, scc: &SCCBits) {
+        self.psw.set_cc_zero(scc.z);
+        self.psw.set_cc_neg(scc.n);
+        self.psw.set_cc_overflow(scc.v);
+        self.psw.set_cc_carry(scc.c);
+    }
+
+    /// Abort the executing instruction with `trap` and flush the pipeline.
+    fn pipeline_trap(&mut self, trap: Trap) {
+        self.raise_trap(trap);
+        self.branch_target = None;
+        self.control2.memory = false;
+        self.trap = Some(trap);
+    }
+
+    /// Read a source register of the executing instruction in the current
+    /// window, or forward the result of the committing instruction if it
+    /// writes the same register.
+    /// # Arguments
+    /// * `reg` - Register to read.
+    /// * `committing` - Rd of the committing instruction, if it has one.
+    fn read_source(&self, reg: u8, committing: Option<u8>) -> Result<u32> {
+        let cwp = self.psw.get_cwp();
+        let bypass = match committing {
+            Some(rd) if rd != 0 => {
+                let source = self.regs.get_real_address(reg, cwp);
+                source.is_ok() && source == self.regs.get_real_address(rd, self.cwp3)
+            }
+            _ => false,
         };
+        if bypass {
+            Ok(self.dst_latch)
+        } else {
+            self.regs.try_read(reg, cwp)
+        }
+    }
+
+    /// Return true if the CPU is in system mode, trap if it is not.
+    fn check_privilege(&mut self) -> bool {
+        if !self.psw.get_system_mode() {
+            self.pipeline_trap(Trap::PrivilegeViolation);
+            return false;
+        }
+        true
+    }
+
+    /// Return true if `addr` is aligned to `mask`, trap if it is not.
+    fn check_alignment(&mut self, addr: u32, mask: u32) -> bool {
+        if addr & mask != 0 {
+            self.pipeline_trap(Trap::Alignment(addr));
+            return false;
+        }
+        true
+    }
 
-        self.control1 = Control::init(
-            long,
-            immediate,
-            memory,
-            store,
-            pc_relative,
-            signed_load,
-            conditional,
-            dst_is_psw,
-        );
-        result
+    /// Update the PC's at the end of the execute cycle, taking the
+    /// (delayed) branch if one was decided.
+    fn next_pc(&mut self) {
+        self.route(Route::BusOut);
+        match self.branch_target.take() {
+            Some(addr) => self.branch_to(addr),
+            None => self.increment_pcs(),
+        }
     }
 
-    fn callr_step3(&mut self) {
-        // Calculate value and send it to dest.
-        let eff_addr = self.alu.add();
+    fn add_step3(&mut self) {
+        let result = self.alu.add_scc();
+        self.alu_result(result);
+    }
 
-        self.dst_latch = eff_addr;
+    fn addc_step3(&mut self) {
+        let result = self.alu.addc_scc(self.psw.get_cc_carry());
+        self.alu_result(result);
     }
 
-    fn jmpx_step3(&mut self) {
-        //
+    fn sub_step3(&mut self) {
+        let result = self.alu.sub_scc();
+        self.alu_result(result);
     }
 
-    fn commit_jmpx(&mut self) {
-        //
-        self.nxtpc = self.dst_latch;
+    fn subc_step3(&mut self) {
+        let result = self.alu.subc_scc(self.psw.get_cc_carry());
+        self.alu_result(result);
     }
 
-    fn commit_callr(&mut self) {
-        //
+    fn subi_step3(&mut self) {
+        let result = self.alu.subi_scc();
+        self.alu_result(result);
     }
 
-    pub fn current_instruction_is_memory(&self) -> bool {
-        self.control2.memory
+    fn subci_step3(&mut self) {
+        let result = self.alu.subci_scc(self.psw.get_cc_carry());
+        self.alu_result(result);
     }
 
-    pub fn add_step(&mut self) {
-        if self.scc_flag2 {
-            self.dst_latch = self.alu.add();
+    fn and_step3(&mut self) {
+        let result = self.alu.and_scc();
+        self.alu_result(result);
+    }
+
+    fn or_step3(&mut self) {
+        let result = self.alu.or_scc();
+        self.alu_result(result);
+    }
+
+    fn xor_step3(&mut self) {
+        let result = self.alu.xor_scc();
+        self.alu_result(result);
+    }
+
+    fn sll_step3(&mut self) {
+        let result = self.alu.shift_left_arithmetic_scc();
+        self.alu_result(result);
+    }
+
+    fn srl_step3(&mut self) {
+        let result = self.alu.shift_right_logical_scc();
+        self.alu_result(result);
+    }
+
+    fn sra_step3(&mut self) {
+        let result = self.alu.shift_right_arithmetic_scc();
+        self.alu_result(result);
+    }
+
+    /// Run the executing extension instruction on the extension that
+    /// implements its opcode, trapping if there is none.
+    fn extension_step3(&mut self) {
+        match self
+            .extensions
+            .execute(self.op2, self.alu.ai, self.alu.bi, self.psw.get_cc_carry())
+        {
+            Ok(result) => self.alu_result(result),
+            Err(trap) => self.pipeline_trap(trap),
+        }
+    }
+
+    fn ldhi_step3(&mut self) {
+        self.set_result(logical_scc(self.imm2 << 13));
+    }
+
+    fn getpsw_step3(&mut self) {
+        self.set_result(logical_scc(0xffffe000 | self.psw.get() as u32));
+    }
+
+    fn getlpc_step3(&mut self) {
+        if self.check_privilege() {
+            self.set_result(logical_scc(self.lstpc));
+        }
+    }
+
+    fn gettcr_step3(&mut self) {
+        if self.check_privilege() {
+            self.set_result(logical_scc(self.trap_cause));
+        }
+    }
+
+    fn putpsw_step3(&mut self) {
+        if self.check_privilege() {
+            self.route(Route::Alu);
+            self.delay_psw(ProcessorStatusWord::from_u16(
+                self.alu.add() as u16 & PSW_LOC,
+            ));
+        }
+    }
+
+    fn calli_step3(&mut self) {
+        if self.check_privilege() {
+            self.set_result(logical_scc(self.lstpc));
+        }
+    }
+
+    fn call_step3(&mut self) {
+        let addr = self.alu.add();
+        self.route(Route::Alu);
+        if self.check_alignment(addr, JUMP_ALIGN_MASK) {
+            self.branch_target = Some(addr);
+            self.dst_latch = self.pc;
+        }
+    }
+
+    fn call_step4(&mut self) {
+        // Rd is written in the new window.
+        self.psw.push(self.regs.windows());
+        self.next_pc();
+    }
+
+    fn jump_step3(&mut self) {
+        if self.test_conditional() {
+            let addr = self.alu.add();
+            self.route(Route::Alu);
+            if self.check_alignment(addr, JUMP_ALIGN_MASK) {
+                self.branch_target = Some(addr);
+            }
+        }
+    }
+
+    fn ret_step4(&mut self) {
+        if self.branch_target.is_some() {
+            self.psw.pop(self.regs.windows());
         }
+        self.next_pc();
     }
 
-    pub fn set_cc_codes_arithmetic(&mut self) {
-        if self.scc_flag3 {
-            self.psw.set_cc_zero(self.dst_latch == 0);
-            self.psw.set_cc_neg(self.dst_latch & SIGN_BIT_LOC != 0);
+    fn reti_step3(&mut self) {
+        if self.check_privilege() {
+            self.jump_step3();
         }
     }
 
+    fn reti_step4(&mut self) {
+        if self.branch_target.is_some() {
+            let previous_system_mode = self.psw.get_previous_system_mode();
+            self.psw.pop(self.regs.windows());
+            self.psw.set_interrupt_enabled(true);
+            self.psw.set_system_mode(previous_system_mode);
+        }
+        self.next_pc();
+    }
+
+    /// Put a memory access for the effective address on the output pins,
+    /// translated by the MMU. Accesses to the MMU's own registers are done
+    /// here and do not use the bus.
+    fn memory_access(&mut self, mask: u32, write: bool) {
+        let addr = self.effective_address();
+        self.route(Route::Alu);
+        if !self.check_alignment(addr, mask) {
+            return;
+        }
+        let target = match self
+            .mmu
+            .translate(addr, mask + 1, self.psw.get_system_mode())
+        {
+            Ok(v) => v,
+            Err(trap) => {
+                self.pipeline_trap(trap);
+                return;
+            }
+        };
+        self.route(Route::BusOut);
+        self.output_pins.address = match target {
+            Target::Memory(physical) => physical,
+            Target::Register => addr,
+        };
+        self.bar = (self.output_pins.address & 0b11) as u8;
+        if write {
+            self.route(Route::Shifter);
+        }
+        self.output_pins.data = self.shifter.replicate_store(mask + 1);
+        self.output_pins.width_code_word = mask == WORD_ALIGN_MASK;
+        self.output_pins.width_code_half = mask == HWORD_ALIGN_MASK;
+        self.output_pins.read_write = write;
+        self.output_pins.system_mode = self.psw.get_system_mode();
+        self.output_pins.instr_or_data_write = false;
+        if target == Target::Register {
+            self.control2.memory = false;
+            if write {
+                self.mmu.write_register(addr, self.shifter.src);
+            } else {
+                let value = self.mmu.read_register(addr);
+                self.load_data(value << lane_shift(self.bar, mask + 1));
+            }
+        }
+    }
+
+    /// Get the effective address of the executing load or store from the
+    /// ALU's input latches.
+    fn effective_address(&self) -> u32 {
+        effective_address(&self.control2, self.pc, self.alu.ai, self.alu.bi)
+    }
+
+    fn load_word_step3(&mut self) {
+        self.memory_access(WORD_ALIGN_MASK, false);
+    }
+
+    fn load_hword_step3(&mut self) {
+        self.memory_access(HWORD_ALIGN_MASK, false);
+    }
+
+    fn load_byte_step3(&mut self) {
+        self.memory_access(0, false);
+    }
+
+    fn store_step3(&mut self, mask: u32) {
+        self.memory_access(mask, true);
+        if self.trap.is_none() && self.scc_flag2 {
+            self.psw.set_cc_overflow(false);
+            self.psw.set_cc_carry(false);
+        }
+    }
+
+    fn store_word_step3(&mut self) {
+        self.store_step3(WORD_ALIGN_MASK);
+    }
+
+    fn store_hword_step3(&mut self) {
+        self.store_step3(HWORD_ALIGN_MASK);
+    }
+
+    fn store_byte_step3(&mut self) {
+        self.store_step3(0);
+    }
+
     pub fn decode_source_registers(&self) -> (u8, u8) {
         (self.rs1_1, self.rs2_1)
     }
@@ -506,6 +1096,10 @@ impl DataPath {
         self.rd2
     }
 
+    pub fn commit_rd(&self) -> u8 {
+        self.rd3
+    }
+
     pub fn bar(&self) -> u8 {
         self.bar
     }
@@ -514,10 +1108,37 @@ impl DataPath {
         self.imm
     }
 
-    pub fn execute_op(&self) -> u8 {
+    pub fn execute_imm(&self) -> u32 {
+        self.imm2
+    }
+
+    /// Get the data last read from memory, an instruction or load data.
+    pub fn dimm(&self) -> u32 {
+        self.dimm
+    }
+
+    pub fn decode_op(&self) -> u8 {
         self.op1
     }
 
+    pub fn execute_op(&self) -> u8 {
+        self.op2
+    }
+
+    /// Get the SCC and immediate flags of the instruction being decoded.
+    pub fn decode_flags(&self) -> (bool, bool) {
+        (self.scc_flag1, self.imm_flag1)
+    }
+
+    /// Get the SCC and immediate flags of the executing instruction.
+    pub fn execute_flags(&self) -> (bool, bool) {
+        (self.scc_flag2, self.imm_flag2)
+    }
+
+    pub fn alu(&self) -> ALU {
+        self.alu
+    }
+
     pub fn register_file(&self) -> &RegisterFile {
         &self.regs
     }
@@ -565,6 +1186,59 @@ impl Control {
         }
     }
 
+    /// Get the control bits set by an instruction's opcode. The bits that
+    /// depend on its source, `long` and `immediate`, are left clear.
+    /// # Arguments
+    /// * `instruction` - The instruction.
+    pub fn of(instruction: &Instruction) -> Self {
+        type I = Instruction;
+        let mut control = Self::new();
+        control.pc_relative = match instruction {
+            I::Callr(_)
+            | I::Jmpr(_)
+            | I::Ldrw(_)
+            | I::Ldrhs(_)
+            | I::Ldrhu(_)
+            | I::Ldrbs(_)
+            | I::Ldrbu(_)
+            | I::Strw(_)
+            | I::Strh(_)
+            | I::Strb(_) => true,
+            _ => false,
+        };
+        control.conditional = match instruction {
+            I::Jmpx(_) | I::Jmpr(_) | I::Ret(_) | I::Reti(_) => true,
+            _ => false,
+        };
+        control.dest_is_psw = match instruction {
+            I::PutPSW(_) => true,
+            _ => false,
+        };
+        control.signed_load = match instruction {
+            I::Ldxhs(_) | I::Ldrhs(_) | I::Ldxbs(_) | I::Ldrbs(_) => true,
+            _ => false,
+        };
+        control.store = match instruction {
+            I::Stxw(_) | I::Strw(_) | I::Stxh(_) | I::Strh(_) | I::Stxb(_) | I::Strb(_) => true,
+            _ => false,
+        };
+
+        control.memory = match instruction {
+            I::Ldxw(_)
+            | I::Ldrw(_)
+            | I::Ldxhs(_)
+            | I::Ldrhs(_)
+            | I::Ldxhu(_)
+            | I::Ldrhu(_)
+            | I::Ldxbs(_)
+            | I::Ldrbs(_)
+            | I::Ldxbu(_)
+            | I::Ldrbu(_) => true,
+            _ => control.store,
+        };
+        control
+    }
+
     pub fn init(
         long: bool,
         immediate: bool,
@@ -594,7 +1268,7 @@ impl Control {
 // Immediates are routed thru shifter in phase 2
 // Loads can use the shifter in phase 4 for aligning data.
 
-// f1: register read and int. forwarding.
+// f1: register read and int. forwarding (see `route_regs_to_alu`).
 // f2: routes sources and imm thru shifter, Reg dec,
 // f3: register write, ALU
 // f4: register dec, shift alignment (for ld)
@@ -609,3 +1283,326 @@ Processor Status Word:\n{}",
         )
     }
 }
+
+impl Snapshot for DataPath {
+    fn save(&self, w: &mut Writer) {
+        self.regs.save(w);
+        w.put_u16(self.psw.get());
+        w.put_u32(self.dst_latch);
+        w.put_u32(self.nxtpc);
+        w.put_u32(self.pc);
+        w.put_u32(self.lstpc);
+        w.put_u32(self.trap_cause);
+        self.output_pins.save(w);
+        w.put_u32(self.alu.ai);
+        w.put_u32(self.alu.bi);
+        w.put_u32(self.shifter.src);
+        w.put_u8(self.shifter.s_ham);
+        w.put_u8(self.shifter.s_dec);
+        w.put_u32(self.dimm);
+        w.put_u32(self.imm);
+        w.put_u32(self.imm2);
+        for latch in [
+            self.bar, self.rd1, self.rd2, self.rd3, self.rs1_1, self.rs2_1, self.rs1_2, self.rs2_2,
+            self.op1, self.op2,
+        ]
+        .iter()
+        {
+            w.put_u8(*latch);
+        }
+        for flag in [
+            self.scc_flag1,
+            self.scc_flag2,
+            self.scc_flag3,
+            self.imm_flag1,
+            self.imm_flag2,
+        ]
+        .iter()
+        {
+            w.put_bool(*flag);
+        }
+        self.control1.save(w);
+        self.control2.save(w);
+        self.control3.save(w);
+        match self.branch_target {
+            Some(addr) => {
+                w.put_bool(true);
+                w.put_u32(addr);
+            }
+            None => w.put_bool(false),
+        }
+        match self.trap {
+            None => w.put_u8(0),
+            Some(Trap::Alignment(addr)) => {
+                w.put_u8(1);
+                w.put_u32(addr);
+            }
+            Some(Trap::PrivilegeViolation) => w.put_u8(2),
+            Some(Trap::Coprocessor(cause)) => {
+                w.put_u8(3);
+                w.put_u32(cause);
+            }
+            Some(Trap::Interrupt) => w.put_u8(4),
+            Some(Trap::AccessViolation(addr)) => {
+                w.put_u8(5);
+                w.put_u32(addr);
+            }
+            Some(Trap::IllegalInstruction) => w.put_u8(6),
+        }
+        match self.pending_psw {
+            Some(psw) => {
+                w.put_bool(true);
+                w.put_u16(psw.get());
+            }
+            None => w.put_bool(false),
+        }
+        w.put_u8(self.cwp3);
+        self.mmu.save(w);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<()> {
+        self.regs.restore(r)?;
+        self.psw = ProcessorStatusWord::from_u16(r.get_u16()? & PSW_LOC);
+        self.dst_latch = r.get_u32()?;
+        self.nxtpc = r.get_u32()?;
+        self.pc = r.get_u32()?;
+        self.lstpc = r.get_u32()?;
+        self.trap_cause = r.get_u32()?;
+        self.output_pins.restore(r)?;
+        self.alu.ai = r.get_u32()?;
+        self.alu.bi = r.get_u32()?;
+        self.shifter.src = r.get_u32()?;
+        self.shifter.s_ham = r.get_u8()?;
+        self.shifter.s_dec = r.get_u8()?;
+        self.dimm = r.get_u32()?;
+        self.imm = r.get_u32()?;
+        self.imm2 = r.get_u32()?;
+        for latch in [
+            &mut self.bar,
+            &mut self.rd1,
+            &mut self.rd2,
+            &mut self.rd3,
+            &mut self.rs1_1,
+            &mut self.rs2_1,
+            &mut self.rs1_2,
+            &mut self.rs2_2,
+            &mut self.op1,
+            &mut self.op2,
+        ]
+        .iter_mut()
+        {
+            **latch = r.get_u8()?;
+        }
+        for flag in [
+            &mut self.scc_flag1,
+            &mut self.scc_flag2,
+            &mut self.scc_flag3,
+            &mut self.imm_flag1,
+            &mut self.imm_flag2,
+        ]
+        .iter_mut()
+        {
+            **flag = r.get_bool()?;
+        }
+        self.control1.restore(r)?;
+        self.control2.restore(r)?;
+        self.control3.restore(r)?;
+        self.branch_target = if r.get_bool()? {
+            Some(r.get_u32()?)
+        } else {
+            None
+        };
+        self.trap = match r.get_u8()? {
+            0 => None,
+            1 => Some(Trap::Alignment(r.get_u32()?)),
+            2 => Some(Trap::PrivilegeViolation),
+            3 => Some(Trap::Coprocessor(r.get_u32()?)),
+            4 => Some(Trap::Interrupt),
+            5 => Some(Trap::AccessViolation(r.get_u32()?)),
+            6 => Some(Trap::IllegalInstruction),
+            v => return berr!(Format, format!("Snapshot has invalid trap {}", v)),
+        };
+        self.pending_psw = if r.get_bool()? {
+            Some(ProcessorStatusWord::from_u16(r.get_u16()? & PSW_LOC))
+        } else {
+            None
+        };
+        self.cwp3 = r.get_u8()?;
+        // No phase has run on the restored state yet.
+        self.routes.clear();
+        self.mmu.restore(r)
+    }
+}
+
+impl Serialize for DataPath {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        DataPathState {
+            pc: self.pc,
+            nxtpc: self.nxtpc,
+            lstpc: self.lstpc,
+            psw: self.psw,
+            regs: self.regs,
+        }
+        .serialize(s)
+    }
+}
+
+/// The pipeline comes out empty and everything outside of the architectural
+/// state as after a reset.
+impl<'de> Deserialize<'de> for DataPath {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let state = DataPathState::deserialize(d)?;
+        let mut result = Self::new();
+        result.pc = state.pc;
+        result.nxtpc = state.nxtpc;
+        result.lstpc = state.lstpc;
+        result.psw = state.psw;
+        result.regs = state.regs;
+        Ok(result)
+    }
+}
+
+impl Snapshot for Control {
+    fn save(&self, w: &mut Writer) {
+        w.put_bool(self.long);
+        w.put_bool(self.immediate);
+        w.put_bool(self.memory);
+        w.put_bool(self.store);
+        w.put_bool(self.pc_relative);
+        w.put_bool(self.signed_load);
+        w.put_bool(self.conditional);
+        w.put_bool(self.dest_is_psw);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<()> {
+        self.long = r.get_bool()?;
+        self.immediate = r.get_bool()?;
+        self.memory = r.get_bool()?;
+        self.store = r.get_bool()?;
+        self.pc_relative = r.get_bool()?;
+        self.signed_load = r.get_bool()?;
+        self.conditional = r.get_bool()?;
+        self.dest_is_psw = r.get_bool()?;
+        Ok(())
+    }
+}
+
+/// Get the effective address of a load or store: PC + imm19 if it is PC
+/// relative, rs1 + S2 if it is register indexed. Both engines compute load
+/// and store addresses here, on the ALU, so they wrap the same way.
+/// # Arguments
+/// * `control` - Control bits of the instruction.
+/// * `pc` - Address of the instruction.
+/// * `s1` - Value of rs1, unused if the instruction is PC relative.
+/// * `s2` - Value of the short source, or imm19 if the instruction is long.
+pub fn effective_address(control: &Control, pc: u32, s1: u32, s2: u32) -> u32 {
+    let base = if control.pc_relative { pc } else { s1 };
+    ALU { ai: base, bi: s2 }.add()
+}
+
+// Micro operation tables.
+
+/// Get the micro operations `instruction` performs in each phase of its
+/// execute cycle, plus the one run when it commits.
+/// # Arguments
+/// * `instruction` - Instruction to get the micro operations of.
+pub fn instruction_cycle(instruction: &Instruction) -> InstructionCycle {
+    type I = Instruction;
+    match *instruction {
+        I::Calli(_) => InstructionCycle::new([
+            noop,
+            noop,
+            DataPath::calli_step3,
+            DataPath::call_step4,
+            DataPath::commit,
+        ]),
+        I::GetPSW(_) => alu_cycle(DataPath::getpsw_step3),
+        I::GetLPC(_) => alu_cycle(DataPath::getlpc_step3),
+        I::GetTCR(_) => alu_cycle(DataPath::gettcr_step3),
+        I::PutPSW(_) => {
+            InstructionCycle::new([noop, noop, DataPath::putpsw_step3, DataPath::next_pc, noop])
+        }
+        I::Callx(_) | I::Callr(_) => InstructionCycle::new([
+            noop,
+            noop,
+            DataPath::call_step3,
+            DataPath::call_step4,
+            DataPath::commit,
+        ]),
+        I::Jmpx(_) | I::Jmpr(_) => {
+            InstructionCycle::new([noop, noop, DataPath::jump_step3, DataPath::next_pc, noop])
+        }
+        I::Ret(_) => {
+            InstructionCycle::new([noop, noop, DataPath::jump_step3, DataPath::ret_step4, noop])
+        }
+        I::Reti(_) => {
+            InstructionCycle::new([noop, noop, DataPath::reti_step3, DataPath::reti_step4, noop])
+        }
+        I::Sll(_) => alu_cycle(DataPath::sll_step3),
+        I::Srl(_) => alu_cycle(DataPath::srl_step3),
+        I::Sra(_) => alu_cycle(DataPath::sra_step3),
+        I::Or(_) => alu_cycle(DataPath::or_step3),
+        I::And(_) => alu_cycle(DataPath::and_step3),
+        I::Xor(_) => alu_cycle(DataPath::xor_step3),
+        I::Add(_) => alu_cycle(DataPath::add_step3),
+        I::Addc(_) => alu_cycle(DataPath::addc_step3),
+        I::Sub(_) => alu_cycle(DataPath::sub_step3),
+        I::Subc(_) => alu_cycle(DataPath::subc_step3),
+        I::Subi(_) => alu_cycle(DataPath::subi_step3),
+        I::Subci(_) => alu_cycle(DataPath::subci_step3),
+        I::Ldhi(_) => alu_cycle(DataPath::ldhi_step3),
+        I::Ldxw(_) | I::Ldrw(_) => alu_cycle(DataPath::load_word_step3),
+        I::Ldxhs(_) | I::Ldrhs(_) | I::Ldxhu(_) | I::Ldrhu(_) => {
+            alu_cycle(DataPath::load_hword_step3)
+        }
+        I::Ldxbs(_) | I::Ldrbs(_) | I::Ldxbu(_) | I::Ldrbu(_) => {
+            alu_cycle(DataPath::load_byte_step3)
+        }
+        I::Stxw(_) | I::Strw(_) => store_cycle(DataPath::store_word_step3),
+        I::Stxh(_) | I::Strh(_) => store_cycle(DataPath::store_hword_step3),
+        I::Stxb(_) | I::Strb(_) => store_cycle(DataPath::store_byte_step3),
+        I::Extension(_, _) => alu_cycle(DataPath::extension_step3),
+    }
+}
+
+/// Execute cycle of an instruction that computes its result in phase three
+/// and writes it to Rd when it commits.
+fn alu_cycle(step3: fn(&mut DataPath)) -> InstructionCycle {
+    InstructionCycle::new([noop, noop, step3, DataPath::next_pc, DataPath::commit])
+}
+
+/// Execute cycle of a store, which has no result to commit.
+fn store_cycle(step3: fn(&mut DataPath)) -> InstructionCycle {
+    InstructionCycle::new([noop, noop, step3, DataPath::next_pc, noop])
+}
+
+/// Get the immediate of a short source, if it is one.
+fn short_imm(source: ShortSource, control: &mut Control) -> u32 {
+    match source {
+        ShortSource::Imm13(v) | ShortSource::SImm13(v) => {
+            control.immediate = true;
+            v
+        }
+        ShortSource::Reg(_) => 0,
+    }
+}
+
+/// Get the immediate of a long instruction.
+fn long_imm(imm19: u32, control: &mut Control) -> u32 {
+    control.long = true;
+    control.immediate = true;
+    imm19
+}
+
+/// SCC bits for results that cannot overflow or carry.
+fn logical_scc(result: u32) -> (u32, SCCBits) {
+    (
+        result,
+        SCCBits {
+            z: result == 0,
+            n: result & SIGN_BIT_LOC != 0,
+            v: false,
+            c: false,
+        },
+    )
+}
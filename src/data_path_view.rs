@@ -0,0 +1,260 @@
+// Copies of the visible data path state, and the differences between them.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use cpu::{
+    register_name, CARRY_LOC, CWP_LOC, INTERRUPT_LOC, NEG_LOC, OVERFLOW_LOC, PREV_SYSTEM_LOC,
+    SWP_LOC, SYSTEM_LOC, ZERO_LOC,
+};
+use data_path::DataPath;
+use std::fmt;
+
+/// Number of registers visible from a window.
+const NUM_VISIBLE_REGS: usize = 32;
+
+/// Names of the PSW bits, from most to least significant.
+const PSW_BITS: [(u16, &str); 9] = [
+    (CWP_LOC, "CWP"),
+    (SWP_LOC, "SWP"),
+    (INTERRUPT_LOC, "I"),
+    (SYSTEM_LOC, "S"),
+    (PREV_SYSTEM_LOC, "P"),
+    (ZERO_LOC, "Z"),
+    (NEG_LOC, "N"),
+    (OVERFLOW_LOC, "V"),
+    (CARRY_LOC, "C"),
+];
+
+/// Latch of the data path shown in a `DataPathView`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Latch {
+    Dst,
+    Src,
+    NxtPc,
+    Pc,
+    LstPc,
+    Imm,
+    Bar,
+    Op,
+    Rd,
+    Rs1,
+    Rs2,
+    SHam,
+    SDec,
+}
+
+/// All latches, in the order differences are listed.
+const LATCHES: [Latch; 13] = [
+    Latch::NxtPc,
+    Latch::Pc,
+    Latch::LstPc,
+    Latch::Op,
+    Latch::Rd,
+    Latch::Rs1,
+    Latch::Rs2,
+    Latch::Imm,
+    Latch::Bar,
+    Latch::Dst,
+    Latch::Src,
+    Latch::SHam,
+    Latch::SDec,
+];
+
+/// Copy of the data path state the debug window shows, taken at one point
+/// in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPathView {
+    /// Processor status word.
+    pub psw: u16,
+    pub dst: u32,
+    pub src: u32,
+    pub nxtpc: u32,
+    pub pc: u32,
+    pub lstpc: u32,
+    pub imm: u32,
+    pub bar: u8,
+    /// Opcode being executed.
+    pub op: u8,
+    /// Destination register being decoded.
+    pub rd: u8,
+    /// Source registers being decoded.
+    pub rs1: u8,
+    pub rs2: u8,
+    pub s_ham: u8,
+    pub s_dec: u8,
+    /// Registers of the current window, indexed by register number.
+    pub regs: [u32; NUM_VISIBLE_REGS],
+}
+
+/// What changed between two `DataPathView`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPathDiff {
+    /// View before the change.
+    pub old: DataPathView,
+    /// View after the change.
+    pub new: DataPathView,
+    /// Latches that changed.
+    pub latches: Vec<Latch>,
+    /// Mask of the PSW bits that changed.
+    pub psw_bits: u16,
+    /// Registers (of the new view's window) that changed.
+    pub registers: Vec<u8>,
+}
+
+impl Latch {
+    /// Get the name the debug window labels the latch with.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Self::Dst => "DST",
+            Self::Src => "SRC",
+            Self::NxtPc => "NXTPC",
+            Self::Pc => "PC",
+            Self::LstPc => "LSTPC",
+            Self::Imm => "IMM",
+            Self::Bar => "BAR",
+            Self::Op => "OP",
+            Self::Rd => "RD",
+            Self::Rs1 => "RS1",
+            Self::Rs2 => "RS2",
+            Self::SHam => "SHam",
+            Self::SDec => "SDec",
+        }
+    }
+}
+
+impl DataPathView {
+    /// Copy the visible state of a data path.
+    /// # Arguments
+    /// * `dp` - Data path to copy.
+    pub fn new(dp: &DataPath) -> Self {
+        let cwp = dp.psw().get_cwp();
+        let (rs1, rs2) = dp.decode_source_registers();
+        let mut regs = [0u32; NUM_VISIBLE_REGS];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            *reg = dp.register_file().read(i as u8, cwp);
+        }
+        Self {
+            psw: dp.psw().get(),
+            dst: dp.dst_latch(),
+            src: dp.src_latch(),
+            nxtpc: dp.nxtpc(),
+            pc: dp.pc(),
+            lstpc: dp.lstpc(),
+            imm: dp.imm(),
+            bar: dp.bar(),
+            op: dp.decode_op(),
+            rd: dp.decode_rd(),
+            rs1: rs1,
+            rs2: rs2,
+            s_ham: dp.shifter().s_ham,
+            s_dec: dp.shifter().s_dec,
+            regs: regs,
+        }
+    }
+
+    /// Get the value of a latch.
+    /// # Arguments
+    /// * `latch` - Latch to read.
+    pub fn latch(&self, latch: Latch) -> u32 {
+        match latch {
+            Latch::Dst => self.dst,
+            Latch::Src => self.src,
+            Latch::NxtPc => self.nxtpc,
+            Latch::Pc => self.pc,
+            Latch::LstPc => self.lstpc,
+            Latch::Imm => self.imm,
+            Latch::Bar => self.bar as u32,
+            Latch::Op => self.op as u32,
+            Latch::Rd => self.rd as u32,
+            Latch::Rs1 => self.rs1 as u32,
+            Latch::Rs2 => self.rs2 as u32,
+            Latch::SHam => self.s_ham as u32,
+            Latch::SDec => self.s_dec as u32,
+        }
+    }
+
+    /// Find what changed since an earlier view.
+    /// # Arguments
+    /// * `prev` - The earlier view.
+    pub fn diff(&self, prev: &Self) -> DataPathDiff {
+        DataPathDiff {
+            old: prev.clone(),
+            new: self.clone(),
+            latches: LATCHES
+                .iter()
+                .cloned()
+                .filter(|l| self.latch(*l) != prev.latch(*l))
+                .collect(),
+            psw_bits: self.psw ^ prev.psw,
+            registers: (0..NUM_VISIBLE_REGS as u8)
+                .filter(|r| self.regs[*r as usize] != prev.regs[*r as usize])
+                .collect(),
+        }
+    }
+}
+
+impl DataPathDiff {
+    /// True if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.latches.is_empty() && self.psw_bits == 0 && self.registers.is_empty()
+    }
+
+    /// True if `latch` changed.
+    /// # Arguments
+    /// * `latch` - Latch to check.
+    pub fn has_latch(&self, latch: Latch) -> bool {
+        self.latches.contains(&latch)
+    }
+
+    /// True if register `reg` changed.
+    /// # Arguments
+    /// * `reg` - Register to check.
+    pub fn has_register(&self, reg: u8) -> bool {
+        self.registers.contains(&reg)
+    }
+}
+
+impl fmt::Display for DataPathDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no changes");
+        }
+        let mut changes = Vec::new();
+        for latch in self.latches.iter() {
+            changes.push(format!(
+                "{} {:x}->{:x}",
+                latch.name(),
+                self.old.latch(*latch),
+                self.new.latch(*latch)
+            ));
+        }
+        if self.psw_bits != 0 {
+            let bits: Vec<&str> = PSW_BITS
+                .iter()
+                .filter(|(mask, _)| self.psw_bits & mask != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            changes.push(format!("PSW {}", bits.join(",")));
+        }
+        for reg in self.registers.iter() {
+            changes.push(format!(
+                "{} {:x}->{:x}",
+                register_name(*reg),
+                self.old.regs[*reg as usize],
+                self.new.regs[*reg as usize]
+            ));
+        }
+        write!(f, "{}", changes.join("  "))
+    }
+}
@@ -13,9 +13,22 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use clock::Phase;
-use config::Config;
-use sdl::{Context, Drawable, Pane};
+use call_stack::CallStack;
+use clock::{format_hertz, Phase};
+use commit::MemoryAccess;
+use config::{Config, PipelineDebug};
+use cpu::{
+    register_name, PswField, FRAME_POINTER_REG, NUM_GLOBALS, STACK_POINTER_REG, WINDOW_SIZE,
+};
+use data_path::{DataPath, Route};
+use data_path_view::{DataPathDiff, DataPathView, Latch};
+use device::Reset;
+use error::EmulatorError;
+use hex_view::{HexView, HEX_VIEW_COLUMNS};
+use pipeline_debug::PipelineLog;
+use r2d2::SnapshotKind;
+use runner::{handle, RunnerCommand};
+use sdl::{load_font, Context, Drawable, Pane};
 use sdl2::gfx::primitives::DrawRenderer;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::*;
@@ -23,14 +36,121 @@ use sdl2::rect::Rect;
 use sdl2::ttf::{Font, Sdl2TtfContext};
 use std::cell::RefCell;
 use std::rc::Rc;
-use system::System;
+use system::{RunState, System};
 use util::Result;
+use window_view::{spill, Spill, SpillKind, WindowRing, WindowUse};
+
+const OBJ_DEFAULT_COLOR: Color = Color::RGB(0xFF, 0xFF, 0xFF);
+const OBJ_USE_COLOR: Color = Color::RGB(0xFa, 0x10, 0x10);
+/// Color of what is unused or off, e.g. windows without a frame.
+const OBJ_OFF_COLOR: Color = Color::RGB(0x60, 0x60, 0x60);
+
+/// Top left corner and width of the snapshot panel.
+const SNAPSHOT_PANEL_X: i32 = 880;
+const SNAPSHOT_PANEL_Y: i32 = 60;
+const SNAPSHOT_PANEL_WIDTH: u32 = 700;
+/// Height of a line of the snapshot panel.
+const SNAPSHOT_ROW_HEIGHT: u32 = 25;
+/// Most snapshots the panel lists at once.
+const SNAPSHOT_PANEL_ROWS: usize = 24;
+/// Rows of memory the memory panel shows. The panel takes the snapshot
+/// panel's place.
+const MEMORY_PANEL_ROWS: u32 = 16;
+/// Clock phases the pipeline history panel shows, four per cycle. The
+/// panel takes the snapshot panel's place.
+const HISTORY_PANEL_ROWS: usize = 20;
+/// Width of a character of the debug font.
+const CHAR_WIDTH: u32 = 9;
+/// Height of the register window panel's ring. The panel takes the
+/// snapshot panel's place.
+const WINDOW_PANEL_RING_HEIGHT: u32 = 450;
+/// Center of the register window panel's ring.
+const WINDOW_RING_CENTER: (i32, i32) = (
+    SNAPSHOT_PANEL_X + 300,
+    SNAPSHOT_PANEL_Y + SNAPSHOT_ROW_HEIGHT as i32 + WINDOW_PANEL_RING_HEIGHT as i32 / 2,
+);
+/// Distance of the windows from the center of the ring.
+const WINDOW_RING_RADIUS: f64 = 160.0;
+/// Radius of a window of the ring.
+const WINDOW_SLOT_RADIUS: i16 = 40;
+/// Registers of an expanded window listed per row.
+const WINDOW_REGISTERS_PER_ROW: usize = 3;
+/// Top left corner of the PSW's flags, and the room each takes.
+const PSW_FLAGS_X: i32 = 305;
+const PSW_FLAGS_Y: i32 = 240;
+const PSW_FLAG_WIDTH: u32 = 17;
+/// Frames a spill is animated for.
+const SPILL_FRAMES: u32 = 30;
+/// Color of the saved window pointer.
+const SWP_COLOR: Color = Color::RGB(0xFa, 0xd0, 0x10);
+/// Color of what is being edited.
+const EDIT_COLOR: Color = Color::RGB(0x10, 0xd0, 0x40);
+/// Top of the command line, under the snapshot panel.
+const COMMAND_LINE_Y: i32 =
+    SNAPSHOT_PANEL_Y + ((SNAPSHOT_PANEL_ROWS as u32 + 1) * SNAPSHOT_ROW_HEIGHT) as i32;
+
+/// How often the debug window compares the data path to find what changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffMode {
+    /// Highlight nothing.
+    Off,
+    /// Highlight what changed in the last clock phase.
+    Phase,
+    /// Highlight what changed in the last whole clock cycle.
+    Cycle,
+}
 
 pub struct DebugWindow<'a> {
     pane: Pane,
     system: Rc<RefCell<System>>,
     config: &'a Config,
     font: Font<'a, 'static>,
+    /// True if the snapshots of the session are listed.
+    show_snapshots: bool,
+    /// Index of the snapshot selected in the list.
+    selected_snapshot: usize,
+    /// Name being typed for the selected snapshot, None if not renaming.
+    renaming: Option<String>,
+    /// How often changes are highlighted.
+    diff_mode: DiffMode,
+    /// Clock cycle and phase `last_view` was taken at.
+    last_seen: Option<(u64, Phase)>,
+    /// Data path as of the last comparison.
+    last_view: Option<DataPathView>,
+    /// What changed at the last comparison.
+    diff: Option<DataPathDiff>,
+    /// Number of the core shown.
+    core: usize,
+    /// True if the memory panel is shown.
+    show_memory: bool,
+    /// Memory the memory panel shows.
+    hex_view: HexView,
+    /// Address being typed to go to, None if not typing one.
+    goto_address: Option<String>,
+    /// True if typing hex digits writes the byte under the memory panel's
+    /// cursor.
+    editing_memory: bool,
+    /// First hex digit of the byte being typed, None if no digit was.
+    edit_nibble: Option<u8>,
+    /// Debugger command being typed, None if not typing one.
+    command: Option<String>,
+    /// Memory access of the last instruction to retire on the boot core.
+    last_access: Rc<RefCell<Option<MemoryAccess>>>,
+    /// True if the pipeline history panel is shown.
+    show_history: bool,
+    /// True if the register window panel is shown.
+    show_windows: bool,
+    /// Window selected in the register window panel.
+    selected_window: u8,
+    /// True if the selected window's registers are listed.
+    window_expanded: bool,
+    /// Last frame an instruction moved between the register file and
+    /// memory, until the panel picks it up.
+    last_spill: Rc<RefCell<Option<Spill>>>,
+    /// Spill being animated and the frames left of it.
+    spill_animation: Option<(Spill, u32)>,
+    /// True if the call stack panel is shown.
+    show_call_stack: bool,
 }
 
 impl<'a> DebugWindow<'a> {
@@ -38,38 +158,896 @@ impl<'a> DebugWindow<'a> {
         config: &'a Config,
         system: Rc<RefCell<System>>,
         context: &mut Context,
-        ttf: &'a mut Sdl2TtfContext,
+        ttf: &'a Sdl2TtfContext,
     ) -> Result<Self> {
-        let pane = Pane::new(
-            config.get_debug_win_width(),
-            config.get_debug_win_height(),
-            format!("Debug"),
-            context,
-        )?;
-        let debug_font = { ttf.load_font("debug.otf", 20)? };
+        let pane = Pane::new(config.get_window_layout().debug, format!("Debug"), context)?;
+        let debug_font = load_font(ttf, config)?;
+        let last_access = Rc::new(RefCell::new(None));
+        let hook_access = last_access.clone();
+        system
+            .borrow_mut()
+            .on_retire(move |r| *hook_access.borrow_mut() = r.access);
+        let last_spill = Rc::new(RefCell::new(None));
+        let hook_spill = last_spill.clone();
+        let windows = system.borrow().data_path().register_file().windows();
+        system.borrow_mut().on_retire(move |r| {
+            if let Some(s) = spill(r, windows) {
+                *hook_spill.borrow_mut() = Some(s);
+            }
+        });
         Ok(Self {
             font: debug_font,
             pane,
             system,
             config,
+            show_snapshots: false,
+            selected_snapshot: 0,
+            renaming: None,
+            diff_mode: DiffMode::Off,
+            last_seen: None,
+            last_view: None,
+            diff: None,
+            core: 0,
+            show_memory: false,
+            hex_view: HexView::new(0, MEMORY_PANEL_ROWS),
+            goto_address: None,
+            editing_memory: false,
+            edit_nibble: None,
+            command: None,
+            last_access: last_access,
+            show_history: false,
+            show_windows: false,
+            selected_window: 0,
+            window_expanded: false,
+            last_spill: last_spill,
+            spill_animation: None,
+            show_call_stack: false,
         })
     }
 
-    fn draw_static_str(&mut self, string: &str, location: Rect, color: Color) -> Result<()> {
-        let name = self
-            .font
-            .render(string)
-            .blended(color)
-            .map_err(|e| e.to_string())?;
-        let texture = self
-            .pane
-            .texture_creator
-            .create_texture_from_surface(&name)
-            .map_err(|e| e.to_string())?;
-        self.pane.canvas.copy(&texture, None, Some(location))?;
+    /// Switch to the next diff mode, forgetting the previous comparison.
+    fn cycle_diff_mode(&mut self) {
+        self.diff_mode = match self.diff_mode {
+            DiffMode::Off => DiffMode::Phase,
+            DiffMode::Phase => DiffMode::Cycle,
+            DiffMode::Cycle => DiffMode::Off,
+        };
+        self.last_seen = None;
+        self.last_view = None;
+        self.diff = None;
+    }
+
+    /// Show the next core, forgetting the previous comparison.
+    fn cycle_core(&mut self) {
+        self.core = (self.core + 1) % self.system.borrow().cores().len();
+        self.last_seen = None;
+        self.last_view = None;
+        self.diff = None;
+    }
+
+    /// Compare the data path to the last view of it if the clock moved on
+    /// far enough for the diff mode.
+    fn update_diff(&mut self) {
+        if self.diff_mode == DiffMode::Off {
+            return;
+        }
+        let system = self.system.clone();
+        let system = system.borrow();
+        let seen = (system.clock().count(), system.phase());
+        if self.last_seen.as_ref() == Some(&seen)
+            || (self.diff_mode == DiffMode::Cycle && seen.1 != Phase::One)
+        {
+            return;
+        }
+        let view = DataPathView::new(system.cores()[self.core].data_path());
+        self.diff = self.last_view.as_ref().map(|prev| view.diff(prev));
+        self.last_view = Some(view);
+        self.last_seen = Some(seen);
+    }
+
+    /// Get the color to draw a latch's value in.
+    /// # Arguments
+    /// * `latch` - The latch.
+    fn latch_color(&self, latch: Latch) -> Color {
+        match self.diff {
+            Some(ref diff) if diff.has_latch(latch) => OBJ_USE_COLOR,
+            _ => OBJ_DEFAULT_COLOR,
+        }
+    }
+
+    /// Get the color to draw a register's value in.
+    /// # Arguments
+    /// * `reg` - The register.
+    fn register_color(&self, reg: u8) -> Color {
+        match self.diff {
+            Some(ref diff) if diff.has_register(reg) => OBJ_USE_COLOR,
+            _ => OBJ_DEFAULT_COLOR,
+        }
+    }
+
+    /// Get the color to draw the PSW in.
+    fn psw_color(&self) -> Color {
+        match self.diff {
+            Some(ref diff) if diff.psw_bits != 0 => OBJ_USE_COLOR,
+            _ => OBJ_DEFAULT_COLOR,
+        }
+    }
+
+    /// Get the color to draw a bus or unit in, highlighted if it carried a
+    /// value in the last clock phase.
+    /// # Arguments
+    /// * `dp` - The data path shown.
+    /// * `route` - The bus or unit.
+    fn route_color(dp: &DataPath, route: Route) -> Color {
+        if dp.route_fired(route) {
+            OBJ_USE_COLOR
+        } else {
+            OBJ_DEFAULT_COLOR
+        }
+    }
+
+    /// Save a snapshot of the system to the cache directory and select it.
+    fn take_snapshot(&mut self) {
+        let result = self.system.borrow_mut().take_snapshot(SnapshotKind::Manual);
+        match result {
+            Ok(path) => {
+                self.selected_snapshot = self.system.borrow().snapshots().snapshots().len() - 1;
+                println!("Saved snapshot {}.", path);
+            }
+            Err(e) => eprintln!("Could not save snapshot: {}", e),
+        }
+    }
+
+    /// Get where a flag of the PSW is drawn.
+    /// # Arguments
+    /// * `index` - Index of the flag in `PswField::ALL`.
+    fn psw_field_rect(index: usize) -> Rect {
+        Rect::new(
+            PSW_FLAGS_X + (index as u32 * PSW_FLAG_WIDTH) as i32,
+            PSW_FLAGS_Y,
+            PSW_FLAG_WIDTH - 4,
+            25,
+        )
+    }
+
+    /// Set or clear a flag of the shown core's PSW. Only a paused system's
+    /// PSW changes, so the change is seen by the next instruction to run.
+    /// # Arguments
+    /// * `field` - The flag.
+    fn toggle_psw_field(&mut self, field: PswField) {
+        let mut system = self.system.borrow_mut();
+        if !system.is_paused() {
+            eprintln!("Pause the system to change the PSW.");
+            return;
+        }
+        let dp = system.cores_mut()[self.core].data_path_mut();
+        let value = !dp.psw().get_field(field);
+        dp.set_psw_field(field, value);
+        println!(
+            "PSW flag {} {}.",
+            field.name(),
+            if value { "set" } else { "cleared" }
+        );
+    }
+
+    /// Log more of the pipeline's inner workings, or stop logging. The log
+    /// goes to standard error unless one was configured.
+    fn cycle_pipeline_debug(&mut self) {
+        let mut system = self.system.borrow_mut();
+        let level = match system.pipeline_log_mut() {
+            Some(log) => {
+                let level = log.level().next();
+                log.set_level(level);
+                level
+            }
+            None => {
+                let level = PipelineDebug::Off.next();
+                if let Err(e) = system.set_pipeline_log(Some(PipelineLog::stderr(level))) {
+                    eprintln!("Could not start the pipeline log: {}", e);
+                }
+                level
+            }
+        };
+        println!("Pipeline debug: {}.", level);
+    }
+
+    /// Restore a snapshot of the session and select it.
+    /// # Arguments
+    /// * `index` - Index of the snapshot in the session.
+    fn restore_snapshot(&mut self, index: usize) {
+        self.selected_snapshot = index;
+        let result = self.system.borrow_mut().restore_session_snapshot(index);
+        match result {
+            Ok(path) => println!("Restored snapshot {}.", path),
+            Err(e) => eprintln!("Could not restore snapshot: {}", e),
+        }
+    }
+
+    /// Get the index of the first snapshot the panel lists, scrolled so the
+    /// selected snapshot is visible.
+    fn first_listed_snapshot(&self) -> usize {
+        (self.selected_snapshot + 1).saturating_sub(SNAPSHOT_PANEL_ROWS)
+    }
+
+    /// Handle a key while the snapshot panel is shown. Return true if the
+    /// key was used by the panel.
+    /// # Arguments
+    /// * `kc` - Key pressed.
+    fn handle_snapshot_key(&mut self, kc: Keycode) -> bool {
+        let count = self.system.borrow().snapshots().snapshots().len();
+        match kc {
+            Keycode::Up => self.selected_snapshot = self.selected_snapshot.saturating_sub(1),
+            Keycode::Down if self.selected_snapshot + 1 < count => self.selected_snapshot += 1,
+            Keycode::Down => {}
+            Keycode::Return if self.selected_snapshot < count => {
+                self.restore_snapshot(self.selected_snapshot)
+            }
+            Keycode::N if self.selected_snapshot < count => self.renaming = Some(String::new()),
+            Keycode::Escape | Keycode::S => self.show_snapshots = false,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handle a key while a snapshot is being renamed. Return/Escape finish
+    /// or cancel renaming, other keys edit the name.
+    /// # Arguments
+    /// * `kc` - Key pressed.
+    fn handle_rename_key(&mut self, kc: Keycode) {
+        let mut name = match self.renaming.take() {
+            Some(name) => name,
+            None => return,
+        };
+        match kc {
+            Keycode::Return => {
+                let result = self
+                    .system
+                    .borrow_mut()
+                    .snapshots_mut()
+                    .rename(self.selected_snapshot, &name);
+                if let Err(e) = result {
+                    eprintln!("Could not rename snapshot: {}", e);
+                }
+                return;
+            }
+            Keycode::Escape => return,
+            Keycode::Backspace => {
+                name.pop();
+            }
+            // Keys of printable characters are their (lower case) ASCII
+            // codes.
+            _ => match std::char::from_u32(kc as i32 as u32) {
+                Some(c) if c.is_ascii_alphanumeric() || c == '-' || c == '_' => name.push(c),
+                _ => {}
+            },
+        }
+        self.renaming = Some(name);
+    }
+
+    /// Handle a key while the memory panel is shown. Return true if the key
+    /// was used by the panel.
+    /// # Arguments
+    /// * `kc` - Key pressed.
+    fn handle_memory_key(&mut self, kc: Keycode) -> bool {
+        match kc {
+            Keycode::PageUp => self.hex_view.page_up(),
+            Keycode::PageDown => self.hex_view.page_down(),
+            Keycode::Left => self.hex_view.move_cursor(-1),
+            Keycode::Right => self.hex_view.move_cursor(1),
+            Keycode::Up => self.hex_view.move_cursor(-(HEX_VIEW_COLUMNS as i32)),
+            Keycode::Down => self.hex_view.move_cursor(HEX_VIEW_COLUMNS as i32),
+            Keycode::G => self.goto_address = Some(String::new()),
+            Keycode::E => {
+                if self.system.borrow().run_state() == RunState::Running {
+                    eprintln!("Pause the system to edit memory.");
+                } else {
+                    self.editing_memory = true;
+                }
+            }
+            Keycode::Escape | Keycode::M => self.show_memory = false,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handle a key while memory is being edited. Two hex digits write the
+    /// byte under the cursor and move on to the next, arrows move the
+    /// cursor and Return/Escape stop editing.
+    /// # Arguments
+    /// * `kc` - Key pressed.
+    fn handle_edit_key(&mut self, kc: Keycode) {
+        match kc {
+            Keycode::Left => self.hex_view.move_cursor(-1),
+            Keycode::Right => self.hex_view.move_cursor(1),
+            Keycode::Up => self.hex_view.move_cursor(-(HEX_VIEW_COLUMNS as i32)),
+            Keycode::Down => self.hex_view.move_cursor(HEX_VIEW_COLUMNS as i32),
+            Keycode::Return | Keycode::Escape => self.editing_memory = false,
+            _ => {
+                let digit = std::char::from_u32(kc as i32 as u32).and_then(|c| c.to_digit(16));
+                match (self.edit_nibble, digit) {
+                    (None, Some(d)) => {
+                        self.edit_nibble = Some(d as u8);
+                        return;
+                    }
+                    (Some(high), Some(d)) => {
+                        let address = self.hex_view.cursor();
+                        let result = self
+                            .system
+                            .borrow_mut()
+                            .set_memory(address, &[high << 4 | d as u8]);
+                        match result {
+                            Ok(()) => self.hex_view.move_cursor(1),
+                            Err(e) => eprintln!("Could not edit memory: {}", e),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.edit_nibble = None;
+    }
+
+    /// Handle a key while a debugger command is being typed, e.g. `set reg
+    /// r5 0x1234`, `set mem 0x100 0xdeadbeef`, `asm 0x100 add r5, r4, 1` or
+    /// `backtrace`. Return/Escape
+    /// run the command or cancel, other keys edit it. Registers are written
+    /// and the call stack listed for the shown core.
+    /// # Arguments
+    /// * `kc` - Key pressed.
+    fn handle_command_key(&mut self, kc: Keycode) {
+        let mut line = match self.command.take() {
+            Some(line) => line,
+            None => return,
+        };
+        match kc {
+            Keycode::Return if line.trim() == "backtrace" => {
+                let system = self.system.borrow();
+                print!(
+                    "{}",
+                    CallStack::new(system.cores()[self.core].data_path(), system.symbols())
+                );
+                return;
+            }
+            Keycode::Return => {
+                let mut system = self.system.borrow_mut();
+                let result = RunnerCommand::parse(&line).and_then(|c| match c {
+                    RunnerCommand::SetRegister(reg, value) => {
+                        system.set_register(self.core, reg, value)
+                    }
+                    c => handle(&mut system, c),
+                });
+                match result {
+                    Ok(()) => println!("{}: done.", line),
+                    Err(e) => eprintln!("{}: {}", line, e),
+                }
+                return;
+            }
+            Keycode::Escape => return,
+            Keycode::Backspace => {
+                line.pop();
+            }
+            _ => match std::char::from_u32(kc as i32 as u32) {
+                Some(c) if c.is_ascii_graphic() || c == ' ' => line.push(c),
+                _ => {}
+            },
+        }
+        self.command = Some(line);
+    }
+
+    /// Handle a key while an address to go to is being typed. Return/Escape
+    /// go to the address or cancel, other keys edit it.
+    /// # Arguments
+    /// * `kc` - Key pressed.
+    fn handle_goto_key(&mut self, kc: Keycode) {
+        let mut address = match self.goto_address.take() {
+            Some(address) => address,
+            None => return,
+        };
+        match kc {
+            Keycode::Return => {
+                match u32::from_str_radix(&address, 16) {
+                    Ok(a) => self.hex_view.goto(a),
+                    Err(_) => eprintln!("Invalid address {}", address),
+                }
+                return;
+            }
+            Keycode::Escape => return,
+            Keycode::Backspace => {
+                address.pop();
+            }
+            _ => match std::char::from_u32(kc as i32 as u32) {
+                Some(c) if c.is_ascii_hexdigit() && address.len() < 8 => address.push(c),
+                _ => {}
+            },
+        }
+        self.goto_address = Some(address);
+    }
+
+    /// Show a page of memory over the right of the window, with the bytes
+    /// the last instruction stored highlighted.
+    fn draw_memory_panel(&mut self) -> Result<()> {
+        let rows = {
+            let system = self.system.borrow();
+            let access = self.last_access.borrow();
+            self.hex_view.rows(system.memory(), access.as_ref())
+        };
+        let title = match self.goto_address {
+            Some(ref address) => format!("Go to address: {}_", address),
+            None if self.editing_memory => format!(
+                "Editing {:08x}: type hex bytes, arrows move, Esc done",
+                self.hex_view.cursor()
+            ),
+            None => format!(
+                "Memory at {:08x}: PgUp/PgDn scroll, G go to, E edit, M close",
+                self.hex_view.start()
+            ),
+        };
+
+        let panel = Rect::new(
+            SNAPSHOT_PANEL_X,
+            SNAPSHOT_PANEL_Y,
+            SNAPSHOT_PANEL_WIDTH,
+            (rows.len() as u32 + 1) * SNAPSHOT_ROW_HEIGHT,
+        );
+        self.pane.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.pane
+            .canvas
+            .fill_rect(panel)
+            .map_err(EmulatorError::sdl)?;
+        self.draw_rect(panel, OBJ_DEFAULT_COLOR)?;
+        self.draw_string(
+            &title,
+            Rect::new(
+                SNAPSHOT_PANEL_X + 5,
+                SNAPSHOT_PANEL_Y,
+                title.chars().count() as u32 * CHAR_WIDTH,
+                SNAPSHOT_ROW_HEIGHT,
+            ),
+            OBJ_DEFAULT_COLOR,
+        )?;
+        // Address, then a cell per byte, then the bytes as ASCII.
+        let first_byte_x = SNAPSHOT_PANEL_X + 5 + 10 * CHAR_WIDTH as i32;
+        let ascii_x = first_byte_x + (HEX_VIEW_COLUMNS * 3 * CHAR_WIDTH) as i32;
+        for (i, row) in rows.iter().enumerate() {
+            let y = SNAPSHOT_PANEL_Y + ((i as u32 + 1) * SNAPSHOT_ROW_HEIGHT) as i32;
+            self.draw_string(
+                &format!("{:08x}:", row.address),
+                Rect::new(SNAPSHOT_PANEL_X + 5, y, 9 * CHAR_WIDTH, SNAPSHOT_ROW_HEIGHT),
+                OBJ_DEFAULT_COLOR,
+            )?;
+            for column in 0..row.bytes.len() {
+                let color = if row.written[column] {
+                    OBJ_USE_COLOR
+                } else {
+                    OBJ_DEFAULT_COLOR
+                };
+                let cell = Rect::new(
+                    first_byte_x + (column as u32 * 3 * CHAR_WIDTH) as i32,
+                    y,
+                    2 * CHAR_WIDTH,
+                    SNAPSHOT_ROW_HEIGHT,
+                );
+                if row.address.wrapping_add(column as u32) != self.hex_view.cursor() {
+                    self.draw_string(&row.hex(column), cell, color)?;
+                    continue;
+                }
+                // The cursor, with the digit typed so far while editing.
+                let (text, cursor_color) = match (self.editing_memory, self.edit_nibble) {
+                    (true, Some(high)) => (format!("{:x}_", high), EDIT_COLOR),
+                    (true, None) => (row.hex(column), EDIT_COLOR),
+                    (false, _) => (row.hex(column), color),
+                };
+                self.draw_string(&text, cell, cursor_color)?;
+                self.draw_rect(
+                    Rect::new(cell.x() - 2, cell.y(), cell.width() + 4, cell.height()),
+                    cursor_color,
+                )?;
+            }
+            self.draw_string(
+                &row.ascii(),
+                Rect::new(
+                    ascii_x,
+                    y,
+                    HEX_VIEW_COLUMNS * CHAR_WIDTH,
+                    SNAPSHOT_ROW_HEIGHT,
+                ),
+                OBJ_DEFAULT_COLOR,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Show or hide the pipeline history panel. The system only records the
+    /// history while it is shown.
+    /// # Arguments
+    /// * `show` - True to show the panel.
+    fn show_history_panel(&mut self, show: bool) {
+        self.show_history = show;
+        let capacity = if show { Some(HISTORY_PANEL_ROWS) } else { None };
+        self.system.borrow_mut().set_pipeline_history(capacity);
+    }
+
+    /// Show what the shown core's stages held over the last clock phases,
+    /// newest last, with the phases the pipeline was suspended highlighted.
+    fn draw_history_panel(&mut self) -> Result<()> {
+        let records: Vec<(String, bool)> = {
+            let system = self.system.borrow();
+            let syntax = system.syntax();
+            match system.pipeline_history(self.core) {
+                Some(history) => history
+                    .records()
+                    .iter()
+                    .map(|r| (r.describe(syntax), r.stages.suspended))
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+        let title = format!("Pipeline of core {}: H close", self.core);
+
+        let panel = Rect::new(
+            SNAPSHOT_PANEL_X,
+            SNAPSHOT_PANEL_Y,
+            SNAPSHOT_PANEL_WIDTH,
+            (HISTORY_PANEL_ROWS as u32 + 1) * SNAPSHOT_ROW_HEIGHT,
+        );
+        self.pane.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.pane
+            .canvas
+            .fill_rect(panel)
+            .map_err(EmulatorError::sdl)?;
+        self.draw_rect(panel, OBJ_DEFAULT_COLOR)?;
+        self.draw_string(
+            &title,
+            Rect::new(
+                SNAPSHOT_PANEL_X + 5,
+                SNAPSHOT_PANEL_Y,
+                title.chars().count() as u32 * CHAR_WIDTH,
+                SNAPSHOT_ROW_HEIGHT,
+            ),
+            OBJ_DEFAULT_COLOR,
+        )?;
+        for (i, &(ref line, suspended)) in records.iter().enumerate() {
+            let width = (line.chars().count() as u32 * CHAR_WIDTH).min(SNAPSHOT_PANEL_WIDTH - 10);
+            self.draw_string(
+                line,
+                Rect::new(
+                    SNAPSHOT_PANEL_X + 5,
+                    SNAPSHOT_PANEL_Y + ((i as u32 + 1) * SNAPSHOT_ROW_HEIGHT) as i32,
+                    width,
+                    SNAPSHOT_ROW_HEIGHT,
+                ),
+                if suspended {
+                    OBJ_USE_COLOR
+                } else {
+                    OBJ_DEFAULT_COLOR
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Handle a key while the register window panel is shown. Return true if
+    /// the key was used by the panel.
+    /// # Arguments
+    /// * `kc` - Key pressed.
+    fn handle_window_key(&mut self, kc: Keycode) -> bool {
+        let windows = self.system.borrow().cores()[self.core]
+            .data_path()
+            .register_file()
+            .windows();
+        match kc {
+            Keycode::Left => self.selected_window = (self.selected_window + windows - 1) % windows,
+            Keycode::Right => self.selected_window = (self.selected_window + 1) % windows,
+            Keycode::Return => self.window_expanded = !self.window_expanded,
+            Keycode::Escape | Keycode::W => self.show_windows = false,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Get the center of a window of the ring. Window 0 is at the top, the
+    /// numbers go clockwise, so calls go counterclockwise.
+    /// # Arguments
+    /// * `window` - Number of the window.
+    /// * `windows` - Number of windows in the ring.
+    fn window_center(window: u8, windows: u8) -> (i32, i32) {
+        let angle = 2.0 * std::f64::consts::PI * window as f64 / windows as f64
+            - std::f64::consts::FRAC_PI_2;
+        (
+            WINDOW_RING_CENTER.0 + (WINDOW_RING_RADIUS * angle.cos()) as i32,
+            WINDOW_RING_CENTER.1 + (WINDOW_RING_RADIUS * angle.sin()) as i32,
+        )
+    }
+
+    /// Draw the register windows of the shown core as a ring with the
+    /// window pointers pointing into it, the selected window's registers
+    /// under it if expanded and the last spill moving between its window
+    /// and memory.
+    fn draw_window_panel(&mut self) -> Result<()> {
+        let ring = {
+            let system = self.system.borrow();
+            let dp = system.cores()[self.core].data_path();
+            WindowRing::new(dp.register_file(), &dp.psw())
+        };
+        let windows = ring.windows();
+        self.selected_window %= windows;
+        if let Some(s) = self.last_spill.borrow_mut().take() {
+            self.spill_animation = Some((s, SPILL_FRAMES));
+        }
+        let title = format!(
+            "Windows: CWP {} SWP {}: Left/Right select, Enter expand, W close",
+            ring.cwp, ring.swp
+        );
+        let rows = if self.window_expanded {
+            (WINDOW_SIZE + WINDOW_REGISTERS_PER_ROW - 1) / WINDOW_REGISTERS_PER_ROW
+        } else {
+            0
+        };
+
+        let panel = Rect::new(
+            SNAPSHOT_PANEL_X,
+            SNAPSHOT_PANEL_Y,
+            SNAPSHOT_PANEL_WIDTH,
+            WINDOW_PANEL_RING_HEIGHT + (rows as u32 + 1) * SNAPSHOT_ROW_HEIGHT,
+        );
+        self.pane.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.pane
+            .canvas
+            .fill_rect(panel)
+            .map_err(EmulatorError::sdl)?;
+        self.draw_rect(panel, OBJ_DEFAULT_COLOR)?;
+        self.draw_string(
+            &title,
+            Rect::new(
+                SNAPSHOT_PANEL_X + 5,
+                SNAPSHOT_PANEL_Y,
+                title.chars().count() as u32 * CHAR_WIDTH,
+                SNAPSHOT_ROW_HEIGHT,
+            ),
+            OBJ_DEFAULT_COLOR,
+        )?;
+
+        // Memory, where spilled frames go.
+        let memory = Rect::new(SNAPSHOT_PANEL_X + 590, WINDOW_RING_CENTER.1 - 25, 100, 50);
+        self.draw_rect(memory, OBJ_OFF_COLOR)?;
+        self.draw_static_str(
+            "Memory",
+            Rect::new(memory.x() + 20, memory.y() + 12, 6 * CHAR_WIDTH, 25),
+            OBJ_OFF_COLOR,
+        )?;
+
+        // The windows, then the pointers from the center to theirs.
+        for slot in ring.slots.iter() {
+            let (x, y) = Self::window_center(slot.window, windows);
+            let color = match slot.usage {
+                WindowUse::Current => OBJ_USE_COLOR,
+                WindowUse::Caller => OBJ_DEFAULT_COLOR,
+                WindowUse::Free => OBJ_OFF_COLOR,
+            };
+            self.draw_circle((x as i16, y as i16, WINDOW_SLOT_RADIUS), color)?;
+            if slot.window == self.selected_window {
+                self.draw_circle((x as i16, y as i16, WINDOW_SLOT_RADIUS + 4), color)?;
+            }
+            self.draw_string(
+                &format!("w{}", slot.window),
+                Rect::new(x - 10, y - 12, 2 * CHAR_WIDTH + 2, 25),
+                color,
+            )?;
+        }
+        for &(window, label, color) in [
+            (ring.swp, "SWP", SWP_COLOR),
+            (ring.cwp, "CWP", OBJ_USE_COLOR),
+        ]
+        .iter()
+        {
+            let (x, y) = Self::window_center(window, windows);
+            // Stop at the edge of the window.
+            let reach = 1.0 - WINDOW_SLOT_RADIUS as f64 / WINDOW_RING_RADIUS;
+            let (dx, dy) = (
+                (x - WINDOW_RING_CENTER.0) as f64,
+                (y - WINDOW_RING_CENTER.1) as f64,
+            );
+            let tip = (
+                WINDOW_RING_CENTER.0 + (dx * reach) as i32,
+                WINDOW_RING_CENTER.1 + (dy * reach) as i32,
+            );
+            self.draw_line(
+                (
+                    WINDOW_RING_CENTER.0 as i16,
+                    WINDOW_RING_CENTER.1 as i16,
+                    tip.0 as i16,
+                    tip.1 as i16,
+                ),
+                color,
+            )?;
+            // The pointers share a line when CWP is SWP, keep both labels
+            // readable.
+            let shift = if label == "CWP" && ring.cwp == ring.swp {
+                25
+            } else {
+                0
+            };
+            self.draw_static_str(
+                label,
+                Rect::new(
+                    WINDOW_RING_CENTER.0 + (dx * 0.45) as i32 - 15,
+                    WINDOW_RING_CENTER.1 + (dy * 0.45) as i32 - 12 + shift,
+                    3 * CHAR_WIDTH,
+                    25,
+                ),
+                color,
+            )?;
+        }
+
+        // The spilled frame moves out to memory, the filled one back in.
+        if let Some((s, frames)) = self.spill_animation {
+            let (x, y) = Self::window_center(s.window, windows);
+            let (mx, my) = (memory.x(), memory.y() + 25);
+            let progress = (SPILL_FRAMES - frames) as f64 / SPILL_FRAMES as f64;
+            let (from, to) = match s.kind {
+                SpillKind::Overflow => ((x, y), (mx, my)),
+                SpillKind::Underflow => ((mx, my), (x, y)),
+            };
+            self.draw_line((x as i16, y as i16, mx as i16, my as i16), OBJ_OFF_COLOR)?;
+            let at = (
+                from.0 + ((to.0 - from.0) as f64 * progress) as i32,
+                from.1 + ((to.1 - from.1) as f64 * progress) as i32,
+            );
+            self.draw_rect(Rect::new(at.0 - 10, at.1 - 10, 20, 20), OBJ_USE_COLOR)?;
+            self.draw_static_str(
+                match s.kind {
+                    SpillKind::Overflow => "overflow",
+                    SpillKind::Underflow => "underflow",
+                },
+                Rect::new(memory.x(), memory.y() + 50, 9 * CHAR_WIDTH, 25),
+                OBJ_USE_COLOR,
+            )?;
+            self.spill_animation = if frames > 1 {
+                Some((s, frames - 1))
+            } else {
+                None
+            };
+        }
+
+        // The selected window's registers, r10 to r31.
+        if self.window_expanded {
+            let slot = &ring.slots[self.selected_window as usize];
+            let top = SNAPSHOT_PANEL_Y + (SNAPSHOT_ROW_HEIGHT + WINDOW_PANEL_RING_HEIGHT) as i32;
+            for (row, registers) in slot.registers.chunks(WINDOW_REGISTERS_PER_ROW).enumerate() {
+                let first = NUM_GLOBALS + row * WINDOW_REGISTERS_PER_ROW;
+                let line = registers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| format!("{:>3}:{:08x}", register_name((first + i) as u8), v))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                self.draw_string(
+                    &line,
+                    Rect::new(
+                        SNAPSHOT_PANEL_X + 5,
+                        top + (row as u32 * SNAPSHOT_ROW_HEIGHT) as i32,
+                        line.chars().count() as u32 * CHAR_WIDTH,
+                        SNAPSHOT_ROW_HEIGHT,
+                    ),
+                    OBJ_DEFAULT_COLOR,
+                )?;
+            }
+        }
         Ok(())
     }
 
+    /// List the snapshots of the session over the right of the window.
+    fn draw_snapshot_panel(&mut self) -> Result<()> {
+        let first = self.first_listed_snapshot();
+        let lines: Vec<String> = {
+            let system = self.system.borrow();
+            let snapshots = system.snapshots().snapshots();
+            let mut lines = vec![format!(
+                "Snapshots ({}): Enter/click restore, N rename, S close",
+                snapshots.len()
+            )];
+            for (i, snapshot) in snapshots
+                .iter()
+                .enumerate()
+                .skip(first)
+                .take(SNAPSHOT_PANEL_ROWS)
+            {
+                lines.push(match self.renaming {
+                    Some(ref name) if i == self.selected_snapshot => {
+                        format!("Rename {} to: {}_", snapshot.name, name)
+                    }
+                    _ => format!("{}", snapshot),
+                });
+            }
+            lines
+        };
+
+        let panel = Rect::new(
+            SNAPSHOT_PANEL_X,
+            SNAPSHOT_PANEL_Y,
+            SNAPSHOT_PANEL_WIDTH,
+            lines.len() as u32 * SNAPSHOT_ROW_HEIGHT,
+        );
+        self.pane.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.pane
+            .canvas
+            .fill_rect(panel)
+            .map_err(EmulatorError::sdl)?;
+        self.draw_rect(panel, OBJ_DEFAULT_COLOR)?;
+        for (row, line) in lines.iter().enumerate() {
+            let color = if row > 0 && first + row - 1 == self.selected_snapshot {
+                OBJ_USE_COLOR
+            } else {
+                OBJ_DEFAULT_COLOR
+            };
+            let width = (line.chars().count() as u32 * 9).min(SNAPSHOT_PANEL_WIDTH - 10);
+            self.draw_string(
+                line,
+                Rect::new(
+                    SNAPSHOT_PANEL_X + 5,
+                    SNAPSHOT_PANEL_Y + (row as u32 * SNAPSHOT_ROW_HEIGHT) as i32,
+                    width,
+                    SNAPSHOT_ROW_HEIGHT,
+                ),
+                color,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// List the calls in progress on the shown core over the right of the
+    /// window, innermost first.
+    fn draw_call_stack_panel(&mut self) -> Result<()> {
+        let lines: Vec<String> = {
+            let system = self.system.borrow();
+            let stack = CallStack::new(system.cores()[self.core].data_path(), system.symbols());
+            let mut lines = vec![format!(
+                "Call stack of core {} ({} frames): B close",
+                self.core,
+                stack.frames.len()
+            )];
+            lines.extend(stack.to_string().lines().map(|l| l.to_string()));
+            lines
+        };
+
+        let panel = Rect::new(
+            SNAPSHOT_PANEL_X,
+            SNAPSHOT_PANEL_Y,
+            SNAPSHOT_PANEL_WIDTH,
+            lines.len() as u32 * SNAPSHOT_ROW_HEIGHT,
+        );
+        self.pane.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.pane
+            .canvas
+            .fill_rect(panel)
+            .map_err(EmulatorError::sdl)?;
+        self.draw_rect(panel, OBJ_DEFAULT_COLOR)?;
+        for (row, line) in lines.iter().enumerate() {
+            // The frame running.
+            let color = if row == 1 {
+                OBJ_USE_COLOR
+            } else {
+                OBJ_DEFAULT_COLOR
+            };
+            let width = (line.chars().count() as u32 * CHAR_WIDTH).min(SNAPSHOT_PANEL_WIDTH - 10);
+            self.draw_string(
+                line,
+                Rect::new(
+                    SNAPSHOT_PANEL_X + 5,
+                    SNAPSHOT_PANEL_Y + (row as u32 * SNAPSHOT_ROW_HEIGHT) as i32,
+                    width,
+                    SNAPSHOT_ROW_HEIGHT,
+                ),
+                color,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn draw_static_str(&mut self, string: &str, location: Rect, color: Color) -> Result<()> {
+        self.pane.draw_text(&self.font, string, color, location)
+    }
+
     fn draw_string(&mut self, string: &String, location: Rect, color: Color) -> Result<()> {
         self.draw_static_str(string.as_str(), location, color)
     }
@@ -90,42 +1068,51 @@ impl<'a> DebugWindow<'a> {
 
     fn draw_line(&mut self, line: (i16, i16, i16, i16), color: Color) -> Result<()> {
         let (x1, y1, x2, y2) = line;
-        self.pane.canvas.line(x1, y1, x2, y2, color)?;
+        self.pane
+            .canvas
+            .line(x1, y1, x2, y2, color)
+            .map_err(EmulatorError::sdl)?;
         Ok(())
     }
 
     fn draw_rect(&mut self, rect: Rect, color: Color) -> Result<()> {
         self.pane.canvas.set_draw_color(color);
-        self.pane.canvas.draw_rect(rect)?;
+        self.pane
+            .canvas
+            .draw_rect(rect)
+            .map_err(EmulatorError::sdl)?;
         Ok(())
     }
 
     fn draw_circle(&mut self, circle: (i16, i16, i16), color: Color) -> Result<()> {
         self.pane
             .canvas
-            .circle(circle.0, circle.1, circle.2, color)?;
+            .circle(circle.0, circle.1, circle.2, color)
+            .map_err(EmulatorError::sdl)?;
         Ok(())
     }
 
     fn draw_polygon(&mut self, xs: &[i16], ys: &[i16], color: Color) -> Result<()> {
-        self.pane.canvas.polygon(xs, ys, color)?;
+        self.pane
+            .canvas
+            .polygon(xs, ys, color)
+            .map_err(EmulatorError::sdl)?;
         Ok(())
     }
 }
 
 impl<'a> Drawable for DebugWindow<'a> {
-    fn draw(&mut self, context: &mut Context) -> Result<()> {
+    fn draw(&mut self, _context: &mut Context) -> Result<()> {
         // Clear the window.
         const CLEAR_COLOR: Color = Color::RGB(0, 0, 0);
         self.pane.canvas.set_draw_color(Color::RGB(0, 0, 0));
         self.pane.canvas.clear();
 
-        const OBJ_DEFAULT_COLOR: Color = Color::RGB(0xFF, 0xFF, 0xFF);
-        const OBJ_USE_COLOR: Color = Color::RGB(0xFa, 0x10, 0x10);
+        self.update_diff();
 
         let system = self.system.clone();
         let system = system.borrow();
-        let dp = system.data_path(); // Data path reference.
+        let dp = system.cores()[self.core].data_path(); // Data path reference.
 
         // Describe the phase of the clock.
         self.draw_static_str(
@@ -140,9 +1127,49 @@ impl<'a> Drawable for DebugWindow<'a> {
             OBJ_DEFAULT_COLOR,
         )?;
 
+        // Say how fast the clock really runs.
+        let clock = system.clock();
+        self.draw_string(
+            &format!(
+                "{} {}",
+                clock.mode(),
+                match clock.effective_rate() {
+                    Some(hertz) => format_hertz(hertz),
+                    None => "-".to_string(),
+                }
+            ),
+            Rect::new(1420, 50, 180, 25),
+            OBJ_DEFAULT_COLOR,
+        )?;
+
+        // Say which core is shown.
+        if system.cores().len() > 1 {
+            self.draw_string(
+                &format!("Core {}", self.core),
+                Rect::new(1450, 0, 90, 50),
+                OBJ_DEFAULT_COLOR,
+            )?;
+        }
+
+        // Describe what changed.
+        if let Some(diff) = self.diff.clone() {
+            let text = format!(
+                "{}: {}",
+                match self.diff_mode {
+                    DiffMode::Cycle => "Cycle",
+                    _ => "Phase",
+                },
+                diff
+            );
+            let width = (text.chars().count() as u32 * 10).min(1500);
+            self.draw_string(&text, Rect::new(0, 0, width, 25), OBJ_DEFAULT_COLOR)?;
+        }
+
+        // Buses and units are highlighted while they carry a value.
+        let bus_ext = Self::route_color(dp, Route::BusExt);
         // busEXT
-        self.draw_line((0, 50, 1450, 50), OBJ_DEFAULT_COLOR)?;
-        self.draw_static_str("busEXT", Rect::new(600, 50, 125, 50), OBJ_DEFAULT_COLOR)?;
+        self.draw_line((0, 50, 1450, 50), bus_ext)?;
+        self.draw_static_str("busEXT", Rect::new(600, 50, 125, 50), bus_ext)?;
 
         // Register file.
         // Draw register file.
@@ -158,35 +1185,51 @@ impl<'a> Drawable for DebugWindow<'a> {
 
         self.draw_string(
             &format!(
-                "R{:02}:{:08x}",
-                rs1,
+                "{}:{:08x}",
+                register_name(rs1),
                 dp.register_file().read(rs1, dp.psw().get_cwp())
             ),
             Rect::new(60, 700, 180, 50),
-            OBJ_DEFAULT_COLOR,
+            self.register_color(rs1),
         )?;
         self.draw_string(
             &format!(
-                "R{:02}:{:08x}",
-                rs2,
+                "{}:{:08x}",
+                register_name(rs2),
                 dp.register_file().read(rs2, dp.psw().get_cwp())
             ),
             Rect::new(60, 750, 180, 50),
-            OBJ_DEFAULT_COLOR,
+            self.register_color(rs2),
         )?;
-        // busA
-        self.draw_static_str("busA", Rect::new(60, 510, 50, 25), OBJ_DEFAULT_COLOR)?;
-        self.draw_lines(
-            &[(60, 500, 425, 500), (425, 500, 425, 700)],
-            OBJ_DEFAULT_COLOR,
+        // Stack and frame pointer of the current window.
+        let cwp = dp.psw().get_cwp();
+        self.draw_string(
+            &format!(
+                "{}:{:08x}",
+                register_name(STACK_POINTER_REG),
+                dp.register_file().stack_pointer(cwp)
+            ),
+            Rect::new(60, 410, 180, 40),
+            self.register_color(STACK_POINTER_REG),
+        )?;
+        self.draw_string(
+            &format!(
+                "{}:{:08x}",
+                register_name(FRAME_POINTER_REG),
+                dp.register_file().frame_pointer(cwp)
+            ),
+            Rect::new(60, 450, 180, 40),
+            self.register_color(FRAME_POINTER_REG),
         )?;
+        // busA
+        let bus_a = Self::route_color(dp, Route::BusA);
+        self.draw_static_str("busA", Rect::new(60, 510, 50, 25), bus_a)?;
+        self.draw_lines(&[(60, 500, 425, 500), (425, 500, 425, 700)], bus_a)?;
 
         // busB
-        self.draw_static_str("busB", Rect::new(60, 585, 50, 25), OBJ_DEFAULT_COLOR)?;
-        self.draw_lines(
-            &[(60, 575, 310, 575), (310, 575, 310, 700)],
-            OBJ_DEFAULT_COLOR,
-        )?;
+        let bus_b = Self::route_color(dp, Route::BusB);
+        self.draw_static_str("busB", Rect::new(60, 585, 50, 25), bus_b)?;
+        self.draw_lines(&[(60, 575, 310, 575), (310, 575, 310, 700)], bus_b)?;
 
         // Draw the latches.
         // Start with DST.
@@ -196,9 +1239,10 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:08x}", dp.dst_latch()),
             Rect::new(280, 600, 275, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Dst),
         )?;
         // busD
+        let bus_d = Self::route_color(dp, Route::BusD);
         self.draw_lines(
             &[
                 (450, 600, 450, 525),
@@ -208,9 +1252,9 @@ impl<'a> Drawable for DebugWindow<'a> {
                 (850, 575, 875, 575),
                 (1275, 525, 1275, 800),
             ],
-            OBJ_DEFAULT_COLOR,
+            bus_d,
         )?;
-        self.draw_static_str("busD", Rect::new(450, 500, 50, 25), OBJ_DEFAULT_COLOR)?;
+        self.draw_static_str("busD", Rect::new(450, 500, 50, 25), bus_d)?;
         // busR
         self.draw_lines(
             &[
@@ -243,7 +1287,7 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:08x}", dp.src_latch()),
             Rect::new(280, 700, 275, 45),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Src),
         )?;
 
         // Now NXTPC.
@@ -252,7 +1296,7 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:08x}", dp.nxtpc()),
             Rect::new(1075, 550, 300, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::NxtPc),
         )?;
         // Now PC.
         self.draw_rect(Rect::new(1075, 675, 300, 50), OBJ_DEFAULT_COLOR)?;
@@ -260,8 +1304,12 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:08x}", dp.pc()),
             Rect::new(1075, 675, 300, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Pc),
         )?;
+        // The symbol the PC is in, if the program has symbols.
+        if let Some(symbol) = system.symbols().describe(dp.pc()) {
+            self.draw_string(&symbol, Rect::new(1175, 725, 200, 50), OBJ_DEFAULT_COLOR)?;
+        }
         // Now LSTPC.
         self.draw_rect(Rect::new(1075, 800, 300, 50), OBJ_DEFAULT_COLOR)?;
         self.draw_static_str("LSTPC", Rect::new(1100, 850, 100, 50), OBJ_DEFAULT_COLOR)?;
@@ -269,17 +1317,17 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:08x}", dp.lstpc()),
             Rect::new(1075, 800, 300, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::LstPc),
         )?;
         // RD
         self.draw_rect(Rect::new(100, 75, 100, 50), OBJ_DEFAULT_COLOR)?;
         self.draw_static_str("RD", Rect::new(125, 125, 50, 50), OBJ_DEFAULT_COLOR)?;
         // busext to RD
-        self.pane.canvas.line(150, 50, 150, 75, OBJ_DEFAULT_COLOR)?;
+        self.draw_line((150, 50, 150, 75), bus_ext)?;
         self.draw_string(
             &format!("R{:02}", dp.decode_rd()),
             Rect::new(125, 75, 50, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Rd),
         )?;
 
         // Source register latches
@@ -290,10 +1338,10 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("R{:02}", rs1),
             Rect::new(75, 200, 50, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Rs1),
         )?;
         // busext to RS1
-        self.draw_line((75, 50, 75, 200), OBJ_DEFAULT_COLOR)?;
+        self.draw_line((75, 50, 75, 200), bus_ext)?;
         // RD to RS1
         self.draw_line((110, 125, 110, 200), OBJ_DEFAULT_COLOR)?;
         // RS2 to Register file
@@ -304,10 +1352,10 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("R{:02}", rs2),
             Rect::new(200, 200, 50, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Rs2),
         )?;
         // busext to RS2
-        self.draw_line((250, 50, 250, 200), OBJ_DEFAULT_COLOR)?;
+        self.draw_line((250, 50, 250, 200), bus_ext)?;
         // RD to RS2
         self.draw_line((190, 125, 190, 200), OBJ_DEFAULT_COLOR)?;
         // RS2 to Register file
@@ -318,9 +1366,23 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_static_str("PSW", Rect::new(325, 275, 75, 50), OBJ_DEFAULT_COLOR)?;
         self.draw_string(
             &format!("{}", dp.psw()),
-            Rect::new(325, 225, 75, 50),
-            OBJ_DEFAULT_COLOR,
+            Rect::new(325, 205, 75, 35),
+            self.psw_color(),
         )?;
+        // Its flags, the set ones highlighted. Clicking one or pressing its
+        // number toggles it.
+        let psw = dp.psw();
+        for (i, field) in PswField::ALL.iter().enumerate() {
+            self.draw_static_str(
+                field.name(),
+                Self::psw_field_rect(i),
+                if psw.get_field(*field) {
+                    OBJ_USE_COLOR
+                } else {
+                    OBJ_OFF_COLOR
+                },
+            )?;
+        }
         // busB to PSW and SHam
         self.draw_lines(
             &[(310, 575, 310, 275), (310, 325, 500, 325)],
@@ -336,20 +1398,20 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:05x}", dp.imm()),
             Rect::new(810, 100, 75, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Imm),
         )?;
         // busEXT to imm
-        self.draw_line((825, 50, 825, 100), OBJ_DEFAULT_COLOR)?;
+        self.draw_line((825, 50, 825, 100), bus_ext)?;
         // dimm
         self.draw_rect(Rect::new(800, 250, 250, 75), OBJ_DEFAULT_COLOR)?;
         self.draw_static_str("DIn/DIMM", Rect::new(900, 325, 150, 50), OBJ_DEFAULT_COLOR)?;
         self.draw_string(
             &format!("{:08x}", dp.imm()),
             Rect::new(800, 255, 250, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Imm),
         )?;
         // busEXT to dimm
-        self.draw_line((1000, 50, 1000, 250), OBJ_DEFAULT_COLOR)?;
+        self.draw_line((1000, 50, 1000, 250), bus_ext)?;
         // imm to dimm and SHAM
         self.draw_lines(
             &[
@@ -358,30 +1420,32 @@ impl<'a> Drawable for DebugWindow<'a> {
                 (475, 175, 475, 315),
                 (475, 315, 500, 315),
             ],
-            OBJ_DEFAULT_COLOR,
+            Self::route_color(dp, Route::Imm),
         )?;
         // op
         self.draw_rect(Rect::new(1100, 125, 50, 50), OBJ_DEFAULT_COLOR)?;
         self.draw_static_str("OP", Rect::new(1100, 175, 50, 50), OBJ_DEFAULT_COLOR)?;
         self.draw_string(
-            &format!("{:02x}", dp.execute_op()),
+            &format!("{:02x}", dp.decode_op()),
             Rect::new(1100, 125, 50, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Op),
         )?;
         // busext to op
-        self.draw_line((1125, 50, 1125, 125), OBJ_DEFAULT_COLOR)?;
+        self.draw_line((1125, 50, 1125, 125), bus_ext)?;
         // Shifter
-        self.draw_rect(Rect::new(600, 500, 175, 300), OBJ_DEFAULT_COLOR)?;
-        self.draw_static_str("Shifter", Rect::new(600, 800, 100, 50), OBJ_DEFAULT_COLOR)?;
+        let shifter = Self::route_color(dp, Route::Shifter);
+        self.draw_rect(Rect::new(600, 500, 175, 300), shifter)?;
+        self.draw_static_str("Shifter", Rect::new(600, 800, 100, 50), shifter)?;
 
-        self.draw_circle((690, 650, 50), OBJ_DEFAULT_COLOR)?;
+        self.draw_circle((690, 650, 50), shifter)?;
         // ALU
+        let alu = Self::route_color(dp, Route::Alu);
         self.draw_polygon(
             &[900, 1000, 1000, 900, 900, 930, 900, 900],
             &[500, 520, 780, 800, 670, 650, 630, 500],
-            OBJ_DEFAULT_COLOR,
+            alu,
         )?;
-        self.draw_static_str("ALU", Rect::new(900, 450, 75, 50), OBJ_DEFAULT_COLOR)?;
+        self.draw_static_str("ALU", Rect::new(900, 450, 75, 50), alu)?;
         // AI (ALU input latch)
         self.draw_rect(Rect::new(875, 500, 25, 120), OBJ_DEFAULT_COLOR)?;
         self.draw_static_str("AI", Rect::new(825, 450, 50, 50), OBJ_DEFAULT_COLOR)?;
@@ -395,7 +1459,7 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:02b}", dp.bar()),
             Rect::new(800, 400, 50, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::Bar),
         )?;
         // Bar to SHam
         self.draw_lines(
@@ -408,6 +1472,7 @@ impl<'a> Drawable for DebugWindow<'a> {
             OBJ_DEFAULT_COLOR,
         )?;
         // Busout
+        let bus_out = Self::route_color(dp, Route::BusOut);
         self.draw_lines(
             &[
                 (1050, 899, 1450, 899),
@@ -420,9 +1485,9 @@ impl<'a> Drawable for DebugWindow<'a> {
                 // Connection of the ALU to busOUT
                 (1000, 650, 1050, 650),
             ],
-            OBJ_DEFAULT_COLOR,
+            bus_out,
         )?;
-        self.draw_static_str("busOUT", Rect::new(1450, 750, 100, 50), OBJ_DEFAULT_COLOR)?;
+        self.draw_static_str("busOUT", Rect::new(1450, 750, 100, 50), bus_out)?;
         // PADS (pins in/out)
         self.draw_rect(Rect::new(1300, 25, 100, 100), OBJ_DEFAULT_COLOR)?;
         self.draw_static_str("PADS", Rect::new(1300, 125, 100, 50), OBJ_DEFAULT_COLOR)?;
@@ -437,30 +1502,223 @@ impl<'a> Drawable for DebugWindow<'a> {
         self.draw_string(
             &format!("{:02x}", dp.shifter().s_ham),
             Rect::new(500, 300, 50, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::SHam),
         )?;
         self.draw_string(
             &format!("{:02x}", dp.shifter().s_dec),
             Rect::new(600, 300, 50, 50),
-            OBJ_DEFAULT_COLOR,
+            self.latch_color(Latch::SDec),
         )?;
         // Connect SDec to Shifter
         self.draw_line((600, 350, 700, 600), OBJ_DEFAULT_COLOR)?;
+
+        if self.show_snapshots {
+            self.draw_snapshot_panel()?;
+        } else if self.show_memory {
+            self.draw_memory_panel()?;
+        } else if self.show_history {
+            self.draw_history_panel()?;
+        } else if self.show_windows {
+            self.draw_window_panel()?;
+        } else if self.show_call_stack {
+            self.draw_call_stack_panel()?;
+        }
+        if let Some(ref line) = self.command {
+            let text = format!("> {}_", line);
+            self.draw_string(
+                &text,
+                Rect::new(
+                    SNAPSHOT_PANEL_X,
+                    COMMAND_LINE_Y,
+                    text.chars().count() as u32 * CHAR_WIDTH,
+                    SNAPSHOT_ROW_HEIGHT,
+                ),
+                EDIT_COLOR,
+            )?;
+        }
         // Draw the debug window.
-        self.pane.canvas.present();
+        self.pane.present();
 
         Ok(())
     }
 
     fn handle_key_down(&mut self, kc: Keycode) {
+        if self.renaming.is_some() {
+            self.handle_rename_key(kc);
+            return;
+        }
+        if self.goto_address.is_some() {
+            self.handle_goto_key(kc);
+            return;
+        }
+        if self.command.is_some() {
+            self.handle_command_key(kc);
+            return;
+        }
+        if self.show_memory && self.editing_memory {
+            self.handle_edit_key(kc);
+            return;
+        }
+        if self.show_snapshots && self.handle_snapshot_key(kc) {
+            return;
+        }
+        if self.show_memory && self.handle_memory_key(kc) {
+            return;
+        }
+        if self.show_windows && self.handle_window_key(kc) {
+            return;
+        }
+        // 1 to 7 toggle the PSW's flags, I S P Z N V C.
+        let flag_keys = [
+            Keycode::Num1,
+            Keycode::Num2,
+            Keycode::Num3,
+            Keycode::Num4,
+            Keycode::Num5,
+            Keycode::Num6,
+            Keycode::Num7,
+        ];
+        if let Some(i) = flag_keys.iter().position(|k| *k == kc) {
+            self.toggle_psw_field(PswField::ALL[i]);
+            return;
+        }
         match kc {
             Keycode::P => {
                 self.system.clone().borrow_mut().toggle_pause();
             }
+            // Warm reset.
+            Keycode::R => {
+                if let Err(e) = self.system.borrow_mut().reset(Reset::Warm) {
+                    eprintln!("Could not reset: {}", e);
+                }
+            }
+            // Cold reset.
+            Keycode::C => {
+                if let Err(e) = self.system.borrow_mut().reset(Reset::Cold) {
+                    eprintln!("Could not reset: {}", e);
+                }
+            }
+            // Highlight changes per phase, per cycle or not at all.
+            Keycode::D => self.cycle_diff_mode(),
+            // Show the next core.
+            Keycode::Tab => self.cycle_core(),
+            // Log the pipeline per cycle, per phase or not at all.
+            Keycode::L => self.cycle_pipeline_debug(),
+            // Disassemble in the other syntax.
+            Keycode::Y => {
+                let mut system = self.system.borrow_mut();
+                let syntax = system.syntax().next();
+                system.set_syntax(syntax);
+                println!("Disassembly syntax: {}.", syntax);
+            }
+            // List the snapshots of the session.
+            Keycode::S => {
+                self.show_snapshots = true;
+                self.show_memory = false;
+                self.show_windows = false;
+                self.show_call_stack = false;
+                self.show_history_panel(false);
+            }
+            // Show memory.
+            Keycode::M => {
+                self.show_memory = true;
+                self.show_snapshots = false;
+                self.show_windows = false;
+                self.show_call_stack = false;
+                self.show_history_panel(false);
+            }
+            // Show or hide the pipeline history.
+            Keycode::H => {
+                let show = !self.show_history;
+                self.show_history_panel(show);
+                if show {
+                    self.show_snapshots = false;
+                    self.show_memory = false;
+                    self.show_windows = false;
+                    self.show_call_stack = false;
+                }
+            }
+            // Show the register windows.
+            Keycode::W => {
+                self.show_windows = true;
+                self.show_snapshots = false;
+                self.show_memory = false;
+                self.show_call_stack = false;
+                self.show_history_panel(false);
+            }
+            // Show or hide the call stack.
+            Keycode::B => {
+                self.show_call_stack = !self.show_call_stack;
+                if self.show_call_stack {
+                    self.show_snapshots = false;
+                    self.show_memory = false;
+                    self.show_windows = false;
+                    self.show_history_panel(false);
+                }
+            }
+            // Run one clock cycle, paused or not.
+            Keycode::F10 => {
+                if let Err(e) = self.system.borrow_mut().run_for(1) {
+                    eprintln!("Could not run: {}", e);
+                }
+            }
+            // Type a debugger command.
+            Keycode::Semicolon => self.command = Some(String::new()),
+            // Save a snapshot.
+            Keycode::F5 => self.take_snapshot(),
+            // Restore the latest snapshot of the session.
+            Keycode::F9 => {
+                let count = self.system.borrow().snapshots().snapshots().len();
+                if count > 0 {
+                    self.restore_snapshot(count - 1);
+                }
+            }
             _ => {}
         }
     }
     fn handle_key_up(&mut self, kc: Keycode) {}
+    fn handle_mouse_down(&mut self, x: i32, y: i32) {
+        // Clicking a flag of the PSW toggles it.
+        if let Some(i) =
+            (0..PswField::ALL.len()).position(|i| Self::psw_field_rect(i).contains_point((x, y)))
+        {
+            self.toggle_psw_field(PswField::ALL[i]);
+            return;
+        }
+        // Clicking a window of the ring expands it, or collapses it again.
+        if self.show_windows {
+            let windows = self.system.borrow().cores()[self.core]
+                .data_path()
+                .register_file()
+                .windows();
+            let r = WINDOW_SLOT_RADIUS as i32;
+            for window in 0..windows {
+                let (cx, cy) = Self::window_center(window, windows);
+                if (x - cx) * (x - cx) + (y - cy) * (y - cy) <= r * r {
+                    self.window_expanded =
+                        !(self.window_expanded && self.selected_window == window);
+                    self.selected_window = window;
+                }
+            }
+            return;
+        }
+        // Clicking a listed snapshot restores it.
+        if !self.show_snapshots || self.renaming.is_some() {
+            return;
+        }
+        let first_row_y = SNAPSHOT_PANEL_Y + SNAPSHOT_ROW_HEIGHT as i32;
+        if x < SNAPSHOT_PANEL_X
+            || x >= SNAPSHOT_PANEL_X + SNAPSHOT_PANEL_WIDTH as i32
+            || y < first_row_y
+        {
+            return;
+        }
+        let row = ((y - first_row_y) as u32 / SNAPSHOT_ROW_HEIGHT) as usize;
+        let index = self.first_listed_snapshot() + row;
+        if row < SNAPSHOT_PANEL_ROWS && index < self.system.borrow().snapshots().snapshots().len() {
+            self.restore_snapshot(index);
+        }
+    }
     fn get_window_id(&self) -> u32 {
         self.pane.get_id()
     }
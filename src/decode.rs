@@ -15,7 +15,8 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 extern crate core;
 
-use core::convert::TryInto;
+use config::Endian;
+use error::EmulatorError;
 use std::error::Error;
 use std::fmt;
 use util::Result;
@@ -25,7 +26,7 @@ use instruction::*;
 macro_rules! bdeii {
     ( $( $loc:expr, $opcode:expr ),* ) => {
         {
-            Err(Box::new($( DecodeError::InvalidInstruction { loc: $loc, opcode: $opcode } )*))
+            Err(EmulatorError::Decode($( DecodeError::InvalidInstruction { loc: $loc, opcode: $opcode } )*))
         }
     };
 }
@@ -33,7 +34,7 @@ macro_rules! bdeii {
 macro_rules! bdeij {
     ( $( $code:expr ),* ) => {
         {
-            Err(Box::new($( DecodeError::InvalidJumpCondition { code: $code } )*))
+            Err(EmulatorError::Decode($( DecodeError::InvalidJumpCondition { code: $code } )*))
         }
     };
 }
@@ -41,7 +42,7 @@ macro_rules! bdeij {
 macro_rules! bdece {
     ( $( $descr:expr ),* ) => {
         {
-            Err(Box::new($( DecodeError::CodeError { descr: $descr } )*))
+            Err(EmulatorError::Decode($( DecodeError::CodeError { descr: $descr } )*))
         }
     };
 }
@@ -67,6 +68,23 @@ pub enum DecodeError {
     },
 }
 
+/// How to decode the instructions with an opcode: the format of their
+/// fields and the instruction the fields make.
+#[derive(Clone, Copy)]
+pub enum OpcodeEntry {
+    /// No instruction has the opcode.
+    Invalid,
+    Short(fn(ShortInstruction) -> Instruction),
+    Long(fn(LongInstruction) -> Instruction),
+    ShortConditional(fn(ShortConditional) -> Instruction),
+    LongConditional(fn(LongConditional) -> Instruction),
+    /// An extension opcode, decoded in the short format.
+    Extension,
+}
+
+/// Every opcode's entry, by opcode.
+pub static OPCODE_TABLE: [OpcodeEntry; 128] = opcode_table();
+
 // Public function declarations.
 
 impl fmt::Debug for DecodeError {
@@ -98,7 +116,7 @@ pub fn decode(opcode: u32) -> Result<Instruction> {
 
     let bottom_nibble = op & 0xf;
     // Match the opcode's prefix.
-    Ok(match op >> 4 {
+    let instruction = match op >> 4 {
         // Match the bottom four bytes of the opcode's prefix.
         0 => match bottom_nibble {
             0 => return bdeii!(0xf, opcode),
@@ -106,7 +124,8 @@ pub fn decode(opcode: u32) -> Result<Instruction> {
             2 => I::GetPSW(ShortInstruction::new(scc, dest, rs1, short_source)),
             3 => I::GetLPC(ShortInstruction::new(scc, dest, rs1, short_source)),
             4 => I::PutPSW(ShortInstruction::new(scc, dest, rs1, short_source)),
-            5..=7 => return bdeii!(0xf, opcode),
+            5 => I::GetTCR(ShortInstruction::new(scc, dest, rs1, short_source)),
+            6..=7 => return bdeii!(0xf, opcode),
             8 => I::Callx(ShortInstruction::new(scc, dest, rs1, short_source)),
             9 => I::Callr(LongInstruction::new(scc, dest, imm19)),
             10..=11 => return bdeii!(0xf, opcode),
@@ -164,20 +183,68 @@ pub fn decode(opcode: u32) -> Result<Instruction> {
             // Should never be reached.
             _ => return bdece!(format!("Match bottom four bytes of opcode prefix")),
         },
-        // Top bit is 1, meaning an extension opcode.
-        4..=8 => match opcode {
-            // TODO
-            _ => return bdece!(format!("Not yet implemented!")),
-        },
-        _ => return bdeii!(0x8, opcode),
-    })
+        // Top bit is 1, meaning an extension opcode. What it does is up to
+        // the extension implementing it, if any.
+        4..=7 => I::Extension(
+            op as u8,
+            ShortInstruction::new(scc, dest, rs1, short_source),
+        ),
+        // Should never be reached.
+        _ => return bdece!(format!("Match opcode prefix")),
+    };
+    // The short source was decoded unsigned, loads and stores sign extend it.
+    Ok(instruction.with_immediate_sign())
 }
 
-pub fn decode_file(file: &Vec<u8>, pos: usize) -> Result<()> {
-    let result = 0usize;
+/// Decode an instruction through `OPCODE_TABLE`. Gives the same results as
+/// `decode`, which stays the reference, with one lookup instead of nested
+/// matches and nothing built for the fields the format does not have.
+/// Return the instruction on success and a decode error if it is invalid.
+/// # Arguments
+/// * `opcode` - The instruction word.
+#[inline]
+pub fn decode_fast(opcode: u32) -> Result<Instruction> {
+    type E = OpcodeEntry;
+    type I = Instruction;
+    let op = opcode >> 25;
+    let scc = opcode & SCC_LOC != 0;
+    let dest = ((opcode & DEST_LOC) >> 19) as u8;
+    let rs1 = ((opcode & RS1_LOC) >> 14) as u8;
+    let short_source = || {
+        if opcode & SHORT_SOURCE_TYPE_LOC != 0 {
+            ShortSource::Imm13(opcode & 0x1fff)
+        } else {
+            ShortSource::Reg((opcode & 0x1f) as u8)
+        }
+    };
+    let cond = || match Conditional::from_code(dest) {
+        Some(cond) => Ok(cond),
+        None => bdeij!((dest & 0xf) as u32),
+    };
+    Ok(match OPCODE_TABLE[op as usize] {
+        E::Invalid => return bdeii!(0xf, opcode),
+        E::Short(make) => {
+            make(ShortInstruction::new(scc, dest, rs1, short_source())).with_immediate_sign()
+        }
+        E::Long(make) => make(LongInstruction::new(scc, dest, opcode & IMM19_LOC)),
+        E::ShortConditional(make) => make(ShortConditional::new(scc, cond()?, rs1, short_source())),
+        E::LongConditional(make) => make(LongConditional::new(scc, cond()?, opcode & IMM19_LOC)),
+        E::Extension => I::Extension(
+            op as u8,
+            ShortInstruction::new(scc, dest, rs1, short_source()),
+        ),
+    })
+}
 
-    for i in (0..file.len()).step_by(4) {
-        decode(u32::from_ne_bytes(file[pos..pos + 4].try_into().unwrap()))?;
+/// Decode every word of a file from `pos` on. Return void if they are all
+/// instructions and the first decode error if not.
+/// # Arguments
+/// * `file` - Contents of the file.
+/// * `pos` - Offset of the first word.
+/// * `endian` - Byte order of the file's words.
+pub fn decode_file(file: &Vec<u8>, pos: usize, endian: Endian) -> Result<()> {
+    for word in file[pos..].chunks_exact(4) {
+        decode(endian.word([word[0], word[1], word[2], word[3]]))?;
     }
 
     Ok(())
@@ -209,23 +276,63 @@ impl Error for DecodeError {}
 /// opcode A RISC-II opcode.
 /// return RISC-II conditional, or DecodeError if 0.
 fn get_cond_from_opcode(opcode: u32) -> Result<Conditional> {
-    type C = Conditional;
-    Ok(match (opcode & 0x780000) >> 19 {
-        1 => C::Gt,
-        2 => C::Le,
-        3 => C::Ge,
-        4 => C::Lt,
-        5 => C::Hi,
-        6 => C::Los,
-        7 => C::Lonc,
-        8 => C::Hisc,
-        9 => C::Pl,
-        10 => C::Mi,
-        11 => C::Ne,
-        12 => C::Eq,
-        13 => C::Nv,
-        14 => C::V,
-        15 => C::Alw,
-        code => return bdeij!(code),
-    })
+    let code = (opcode & 0x780000) >> 19;
+    match Conditional::from_code(code as u8) {
+        Some(cond) => Ok(cond),
+        None => bdeij!(code),
+    }
+}
+
+/// Build `OPCODE_TABLE`, after the opcode map `decode` matches.
+const fn opcode_table() -> [OpcodeEntry; 128] {
+    type E = OpcodeEntry;
+    type I = Instruction;
+    let mut table = [E::Invalid; 128];
+    table[0x01] = E::Short(I::Calli);
+    table[0x02] = E::Short(I::GetPSW);
+    table[0x03] = E::Short(I::GetLPC);
+    table[0x04] = E::Short(I::PutPSW);
+    table[0x05] = E::Short(I::GetTCR);
+    table[0x08] = E::Short(I::Callx);
+    table[0x09] = E::Long(I::Callr);
+    table[0x0c] = E::ShortConditional(I::Jmpx);
+    table[0x0d] = E::LongConditional(I::Jmpr);
+    table[0x0e] = E::ShortConditional(I::Ret);
+    table[0x0f] = E::ShortConditional(I::Reti);
+    table[0x11] = E::Short(I::Sll);
+    table[0x12] = E::Short(I::Sra);
+    table[0x13] = E::Short(I::Srl);
+    table[0x14] = E::Long(I::Ldhi);
+    table[0x15] = E::Short(I::And);
+    table[0x16] = E::Short(I::Or);
+    table[0x17] = E::Short(I::Xor);
+    table[0x18] = E::Short(I::Add);
+    table[0x19] = E::Short(I::Addc);
+    table[0x1c] = E::Short(I::Sub);
+    table[0x1d] = E::Short(I::Subc);
+    table[0x1e] = E::Short(I::Subi);
+    table[0x1f] = E::Short(I::Subci);
+    table[0x26] = E::Short(I::Ldxw);
+    table[0x27] = E::Long(I::Ldrw);
+    table[0x28] = E::Short(I::Ldxhu);
+    table[0x29] = E::Long(I::Ldrhu);
+    table[0x2a] = E::Short(I::Ldxhs);
+    table[0x2b] = E::Long(I::Ldrhs);
+    table[0x2c] = E::Short(I::Ldxbu);
+    table[0x2d] = E::Long(I::Ldrbu);
+    table[0x2e] = E::Short(I::Ldxbs);
+    table[0x2f] = E::Long(I::Ldrbs);
+    table[0x36] = E::Short(I::Stxw);
+    table[0x37] = E::Long(I::Strw);
+    table[0x3a] = E::Short(I::Stxh);
+    table[0x3b] = E::Long(I::Strh);
+    table[0x3e] = E::Short(I::Stxb);
+    table[0x3f] = E::Long(I::Strb);
+    // Top bit set, an extension opcode.
+    let mut op = 0x40;
+    while op < 0x80 {
+        table[op] = E::Extension;
+        op += 1;
+    }
+    table
 }
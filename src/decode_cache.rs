@@ -0,0 +1,132 @@
+// Cache of decoded instructions for the fast engine.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// A direct-mapped table from the physical address of an instruction word to
+// the instruction it decoded to, so a tight loop is decoded once instead of
+// on every pass. Each entry keeps the word it was decoded from and only
+// answers while memory still holds that word: a store over the instruction,
+// from the guest, DMA, the loader or the debugger, invalidates it. The word
+// is fetched either way, what the cache saves is decoding it.
+
+use decode::decode_fast;
+use instruction::Instruction;
+use std::fmt;
+use util::Result;
+
+/// Default number of entries.
+pub const DEFAULT_DECODE_CACHE: u32 = 1024;
+
+/// A decoded instruction and where it came from.
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Physical address of the word.
+    address: u32,
+    /// The word.
+    word: u32,
+    /// What the word decoded to.
+    instruction: Instruction,
+}
+
+/// How well a decode cache did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeCacheStats {
+    /// Fetches that found their instruction decoded.
+    pub hits: u64,
+    /// Fetches that had to decode, the first time or after the entry was
+    /// evicted or invalidated.
+    pub misses: u64,
+}
+
+impl DecodeCacheStats {
+    /// Get the share of fetches that hit, 0 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        let fetches = self.hits + self.misses;
+        if fetches == 0 {
+            0.0
+        } else {
+            self.hits as f64 / fetches as f64
+        }
+    }
+
+    /// Add the counts of another cache, e.g. another core's.
+    /// # Arguments
+    /// * `other` - The other cache's stats.
+    pub fn add(&mut self, other: &Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+    }
+}
+
+impl fmt::Display for DecodeCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Decode cache: {:.1}% hit rate ({} hits, {} misses)",
+            self.hit_rate() * 100.0,
+            self.hits,
+            self.misses
+        )
+    }
+}
+
+/// Direct-mapped cache of decoded instructions.
+#[derive(Clone)]
+pub struct DecodeCache {
+    entries: Vec<Option<Entry>>,
+    stats: DecodeCacheStats,
+}
+
+impl DecodeCache {
+    /// Create an empty cache.
+    /// # Arguments
+    /// * `entries` - Number of entries, a power of two.
+    pub fn new(entries: u32) -> Self {
+        Self {
+            entries: vec![None; entries as usize],
+            stats: DecodeCacheStats::default(),
+        }
+    }
+
+    /// Get how well the cache did so far.
+    pub fn stats(&self) -> DecodeCacheStats {
+        self.stats
+    }
+
+    /// Decode a fetched word, from the cache if it was decoded at the same
+    /// address before. Return the instruction on success and a decode error
+    /// if it is invalid, which is not cached.
+    /// # Arguments
+    /// * `address` - Physical address the word was fetched from.
+    /// * `word` - The word.
+    pub fn decode(&mut self, address: u32, word: u32) -> Result<Instruction> {
+        let index = (address >> 2) as usize & (self.entries.len() - 1);
+        match self.entries[index] {
+            Some(e) if e.address == address && e.word == word => {
+                self.stats.hits += 1;
+                Ok(e.instruction)
+            }
+            _ => {
+                self.stats.misses += 1;
+                let instruction = decode_fast(word)?;
+                self.entries[index] = Some(Entry {
+                    address: address,
+                    word: word,
+                    instruction: instruction,
+                });
+                Ok(instruction)
+            }
+        }
+    }
+}
@@ -55,6 +55,15 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn decode_gettcr() -> Result<()> {
+        assert_eq!(
+            decode(0x0b293f69)?,
+            I::GetTCR(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+        );
+        Ok(())
+    }
+
     #[test]
     fn decode_putpsw() -> Result<()> {
         assert_eq!(
@@ -107,6 +116,27 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn decode_backward_offsets() -> Result<()> {
+        match decode(0x12fffff8)? {
+            I::Callr(l) => {
+                assert_eq!(l.dest, 31);
+                assert_eq!(l.offset() as i32, -8);
+            }
+            _ => panic!("0x12fffff8 is not callr"),
+        }
+        match decode(0x4e8fffe8)? {
+            I::Ldrw(l) => assert_eq!(l.offset() as i32, -24),
+            _ => panic!("0x4e8fffe8 is not ldrw"),
+        }
+        let jmpr = I::Jmpr(LongConditional::new(false, Conditional::Ne, 0x40000));
+        match decode(jmpr.encode())? {
+            I::Jmpr(c) => assert_eq!(c.offset() as i32, -0x40000),
+            _ => panic!("{} is not jmpr", jmpr),
+        }
+        Ok(())
+    }
+
     #[test]
     fn decode_ret() -> Result<()> {
         assert_eq!(
@@ -260,7 +290,7 @@ mod test {
     fn decode_ldxw() -> Result<()> {
         assert_eq!(
             decode(0x4d293f69)?,
-            I::Ldxw(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Ldxw(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -278,7 +308,7 @@ mod test {
     fn decode_ldxhu() -> Result<()> {
         assert_eq!(
             decode(0x51293f69)?,
-            I::Ldxhu(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Ldxhu(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -296,7 +326,7 @@ mod test {
     fn decode_ldxhs() -> Result<()> {
         assert_eq!(
             decode(0x55293f69)?,
-            I::Ldxhs(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Ldxhs(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -314,7 +344,7 @@ mod test {
     fn decode_ldxbu() -> Result<()> {
         assert_eq!(
             decode(0x59293f69)?,
-            I::Ldxbu(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Ldxbu(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -332,7 +362,7 @@ mod test {
     fn decode_ldxbs() -> Result<()> {
         assert_eq!(
             decode(0x5d293f69)?,
-            I::Ldxbs(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Ldxbs(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -352,7 +382,7 @@ mod test {
     fn decode_stxw() -> Result<()> {
         assert_eq!(
             decode(0x6d293f69)?,
-            I::Stxw(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Stxw(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -370,7 +400,7 @@ mod test {
     fn decode_stxh() -> Result<()> {
         assert_eq!(
             decode(0x75293f69)?,
-            I::Stxh(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Stxh(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -388,7 +418,7 @@ mod test {
     fn decode_stxb() -> Result<()> {
         assert_eq!(
             decode(0x7d293f69)?,
-            I::Stxb(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+            I::Stxb(ShortInstruction::new(true, 5, 4, SS::SImm13(0xffffff69)))
         );
         Ok(())
     }
@@ -402,13 +432,28 @@ mod test {
         Ok(())
     }
 
+    // Extension instructions.
+
+    #[test]
+    fn decode_extension() -> Result<()> {
+        assert_eq!(
+            decode(0x81293f69)?,
+            I::Extension(0x40, ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69)))
+        );
+        assert_eq!(
+            decode(0xfe293f69)?,
+            I::Extension(0x7f, ShortInstruction::new(false, 5, 4, SS::Imm13(0x1f69)))
+        );
+        Ok(())
+    }
+
     // Short source tests.
 
     #[test]
     fn ss_uimm_to_simm1() {
         assert_eq!(
             SS::new(0xf00f, false).uimm_to_simm(),
-            SS::Imm13(-4111i32 as u32)
+            SS::SImm13(-4081i32 as u32)
         );
     }
 
@@ -416,10 +461,66 @@ mod test {
     fn ss_uimm_to_simm2() {
         assert_eq!(
             SS::new(0xf0ff, false).uimm_to_simm(),
-            SS::Imm13(-0x10ffi32 as u32)
+            SS::SImm13(-0xf01i32 as u32)
         );
     }
 
+    #[test]
+    fn ss_uimm_to_simm_positive() {
+        assert_eq!(SS::new(0x2fff, false).uimm_to_simm(), SS::SImm13(0xfff));
+        assert_eq!(SS::Reg(3).uimm_to_simm(), SS::Reg(3));
+    }
+
+    #[test]
+    fn only_loads_and_stores_sign_their_immediate() -> Result<()> {
+        let source = SS::Imm13(0x1ff0);
+        assert_eq!(
+            decode(I::Ldxw(ShortInstruction::new(false, 16, 1, source)).encode())?,
+            I::Ldxw(ShortInstruction::new(false, 16, 1, SS::SImm13(0xfffffff0)))
+        );
+        assert_eq!(
+            decode(I::Stxb(ShortInstruction::new(false, 16, 1, source)).encode())?,
+            I::Stxb(ShortInstruction::new(false, 16, 1, SS::SImm13(0xfffffff0)))
+        );
+        assert_eq!(
+            decode(I::And(ShortInstruction::new(false, 16, 1, source)).encode())?,
+            I::And(ShortInstruction::new(false, 16, 1, source))
+        );
+        assert_eq!(
+            decode(I::Add(ShortInstruction::new(false, 16, 1, source)).encode())?,
+            I::Add(ShortInstruction::new(false, 16, 1, source))
+        );
+        Ok(())
+    }
+
+    // Opcode table.
+
+    #[test]
+    fn fast_decode_matches_decode() {
+        // Every opcode, with and without SCC, register and immediate short
+        // sources, the invalid jump condition 0 and the others.
+        let fields = [
+            0x0000_0000,
+            0x0100_0000,
+            0x0000_2fff,
+            0x0029_3f69,
+            0x0080_0000,
+            0x01ff_ffff,
+            0x0078_1234,
+            0x0000_1ff0,
+        ];
+        for op in 0..0x80u32 {
+            for f in fields.iter() {
+                let word = op << 25 | f;
+                match (decode(word), decode_fast(word)) {
+                    (Ok(a), Ok(b)) => assert_eq!(a, b, "0x{:08x}", word),
+                    (Err(a), Err(b)) => assert_eq!(a.to_string(), b.to_string()),
+                    (a, b) => panic!("0x{:08x}: {:?} and {:?}", word, a.ok(), b.ok()),
+                }
+            }
+        }
+    }
+
     impl fmt::Debug for SS {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             write!(f, "{}", self)
@@ -436,6 +537,7 @@ mod test {
                     I::Calli(o) => format!("Calli {}", o),
                     I::GetPSW(o) => format!("GetPSW {}", o),
                     I::GetLPC(o) => format!("GetLPC {}", o),
+                    I::GetTCR(o) => format!("GetTCR {}", o),
                     I::PutPSW(o) => format!("GetPSW {}", o),
                     I::Callx(o) => format!("Callx {}", o),
                     I::Callr(o) => format!("Callr {}", o),
@@ -472,6 +574,7 @@ mod test {
                     I::Strh(o) => format!("Strh {}", o),
                     I::Stxb(o) => format!("Stxb {}", o),
                     I::Strb(o) => format!("Strb {}", o),
+                    I::Extension(op, o) => format!("Extension 0x{:02x} {}", op, o),
                 }
             )
         }
@@ -0,0 +1,80 @@
+// Devices attached to the RISC II system.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use r2d2::Writer;
+use std::ops::Range;
+
+/// Kinds of system reset.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reset {
+    /// Warm (soft) reset. The CPU restarts at the reset vector with a fresh
+    /// PSW, memory and registers keep their contents.
+    Warm,
+    /// Cold reset, as if the power was cycled. Memory and registers are
+    /// cleared as well.
+    Cold,
+}
+
+/// A device attached to the system.
+pub trait Device {
+    /// Name of the device, for diagnostics.
+    fn name(&self) -> &str;
+
+    /// Return the device to its reset state. Called on every system reset.
+    /// # Arguments
+    /// * `kind` - Kind of reset being performed.
+    fn reset(&mut self, kind: Reset);
+
+    /// Range of addresses the device answers to, None if it is not memory
+    /// mapped.
+    fn mapping(&self) -> Option<Range<u32>> {
+        None
+    }
+
+    /// Read the device register at `addr`. Narrower reads are truncated.
+    /// # Arguments
+    /// * `addr` - Address inside the device's mapping.
+    fn read(&mut self, _addr: u32) -> u32 {
+        0
+    }
+
+    /// Write `value` to the device register at `addr`.
+    /// # Arguments
+    /// * `addr` - Address inside the device's mapping.
+    /// * `value` - Value written, zero extended for narrower writes.
+    fn write(&mut self, _addr: u32, _value: u32) {}
+
+    /// Write the device's internal state to `w`, to compare it between
+    /// runs. It is never restored.
+    /// # Arguments
+    /// * `w` - Writer to write the state to.
+    fn fingerprint(&self, _w: &mut Writer) {}
+
+    /// Advance the device by a clock cycle. Called at the start of every
+    /// clock cycle, before `dma`.
+    fn tick(&mut self) {}
+
+    /// Move data between the device and memory directly. Called at the start
+    /// of every clock cycle.
+    /// # Arguments
+    /// * `ram` - Contents of memory.
+    fn dma(&mut self, _ram: &mut [u8]) {}
+
+    /// Return true if the device is requesting an interrupt. The request is
+    /// taken before the next instruction if the PSW enables interrupts.
+    fn interrupt_pending(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,116 @@
+// Memory mapped inter-processor interrupt doorbell.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Lets the cores of a multi-core system interrupt each other. Writing a core's
+// number to `DOORBELL_RING` rings its doorbell, which sets bit `n` of the
+// pending register and requests an interrupt on core `n` until the bit is
+// cleared. Writing the pending register clears the bits that are set in the
+// value written, so a core acknowledges its doorbell by writing `1 << id`,
+// where `id` comes from its MMU's `MMU_CPU_ID` register. Unlike the other
+// devices the doorbell does not interrupt through the bus, which only reaches
+// the boot core, the system asks it for each core through a `DoorbellLines`.
+
+use device::{Device, Reset};
+use r2d2::Writer;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Base address of the doorbell's registers.
+pub const DOORBELL_BASE: u32 = 0xffff0080;
+/// Ring register. Writing a core's number rings its doorbell.
+pub const DOORBELL_RING: u32 = DOORBELL_BASE;
+/// Pending register, bit `n` is set while core `n`'s doorbell rings.
+/// Writing it clears the bits set in the value written.
+pub const DOORBELL_PENDING: u32 = DOORBELL_BASE + 4;
+/// Size of the doorbell's register block.
+const DOORBELL_SIZE: u32 = 8;
+
+/// Inter-processor interrupt doorbell.
+pub struct Doorbell {
+    /// Number of cores that have a doorbell.
+    cores: u32,
+    /// Doorbells ringing, one bit per core.
+    pending: Rc<RefCell<u32>>,
+}
+
+/// Handle the system uses to see which cores a `Doorbell` interrupts.
+#[derive(Clone)]
+pub struct DoorbellLines {
+    pending: Rc<RefCell<u32>>,
+}
+
+impl Doorbell {
+    /// Create a doorbell for a number of cores.
+    /// # Arguments
+    /// * `cores` - Number of cores, at most 32.
+    pub fn new(cores: u32) -> Self {
+        Self {
+            cores: cores,
+            pending: Rc::new(RefCell::new(0)),
+        }
+    }
+
+    /// Get a handle that tells which cores are interrupted.
+    pub fn lines(&self) -> DoorbellLines {
+        DoorbellLines {
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl DoorbellLines {
+    /// Return true if a core's doorbell rings.
+    /// # Arguments
+    /// * `core` - Number of the core.
+    pub fn is_ringing(&self, core: usize) -> bool {
+        core < 32 && *self.pending.borrow() & (1 << core) != 0
+    }
+}
+
+impl Device for Doorbell {
+    fn name(&self) -> &str {
+        "doorbell"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        *self.pending.borrow_mut() = 0;
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(DOORBELL_BASE..DOORBELL_BASE + DOORBELL_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        match addr & !0x3 {
+            DOORBELL_PENDING => *self.pending.borrow(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        let mut pending = self.pending.borrow_mut();
+        match addr & !0x3 {
+            // Cores that do not exist have no doorbell.
+            DOORBELL_RING if value < self.cores => *pending |= 1 << value,
+            DOORBELL_PENDING => *pending &= !value,
+            _ => {}
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        w.put_u32(*self.pending.borrow());
+    }
+}
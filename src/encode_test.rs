@@ -74,6 +74,15 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn encode_gettcr() -> Result<()> {
+        assert_eq_hex!(
+            0x0b293f69,
+            I::GetTCR(ShortInstruction::new(true, 5, 4, SS::Imm13(0x1f69))).encode()
+        );
+        Ok(())
+    }
+
     #[test]
     fn encode_putpsw() -> Result<()> {
         assert_eq_hex!(
@@ -423,4 +432,22 @@ mod test {
         );
         Ok(())
     }
+
+    #[test]
+    fn encode_backward_offsets() -> Result<()> {
+        assert_eq_hex!(
+            0x12fffff8,
+            I::Callr(LongInstruction::new(false, 31, 0x7fff8)).encode()
+        );
+        // A sign extended offset encodes the same.
+        assert_eq_hex!(
+            0x12fffff8,
+            I::Callr(LongInstruction::new(false, 31, -8i32 as u32)).encode()
+        );
+        assert_eq_hex!(
+            0x4e8fffe8,
+            I::Ldrw(LongInstruction::new(false, 17, 0x7ffe8)).encode()
+        );
+        Ok(())
+    }
 }
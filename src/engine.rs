@@ -0,0 +1,179 @@
+// RISC II execution engines.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use clock::Phase;
+use commit::{commit, RetiredInstruction};
+use config::{Engine, Syntax};
+use data_path::DataPath;
+use decode::decode_fast;
+use decode_cache::{DecodeCache, DecodeCacheStats};
+use execute::execute;
+use memory::Memory;
+use pipeline::Pipeline;
+use pipeline_history::Occupancy;
+use r2d2::{Reader, Snapshot, Writer};
+use util::Result;
+
+/// Something that can run instructions on the data path. Its snapshot holds
+/// whatever it keeps in flight between clock phases.
+pub trait Cpu: Snapshot {
+    /// Run the CPU for one clock phase. Return the instruction that retired
+    /// during the phase, if any.
+    /// # Arguments
+    /// * `phase` - Current clock phase.
+    /// * `dp` - Data path to run on.
+    /// * `mem` - System memory.
+    /// * `cycle` - Current clock cycle.
+    fn tick(
+        &mut self,
+        phase: &Phase,
+        dp: &mut DataPath,
+        mem: &mut Memory,
+        cycle: u64,
+    ) -> Result<Option<RetiredInstruction>>;
+
+    /// Finish writing back the instructions that retired, so the ones in
+    /// flight can be thrown away. Called at the start of a clock cycle.
+    /// Return false, doing nothing, if an instruction is halfway through
+    /// and cannot be thrown away yet.
+    /// # Arguments
+    /// * `dp` - Data path to write back to.
+    fn drain(&mut self, dp: &mut DataPath) -> bool;
+
+    /// Describe what the CPU has in flight, in one line, for debugging the
+    /// engine itself.
+    /// # Arguments
+    /// * `syntax` - Syntax to disassemble instructions in.
+    fn describe(&self, syntax: Syntax) -> String;
+
+    /// Get what each stage of the CPU holds, for the pipeline history.
+    fn occupancy(&self) -> Occupancy;
+
+    /// Throw away the instructions in flight, so the next one fetched is
+    /// the first to run, as when a trap is taken.
+    /// # Arguments
+    /// * `dp` - Data path the CPU runs on.
+    fn flush(&mut self, dp: &DataPath);
+
+    /// Get how the CPU's decode cache did, None if it has none.
+    fn decode_cache_stats(&self) -> Option<DecodeCacheStats>;
+}
+
+/// Fast engine. Fetches, decodes, executes and commits a whole instruction
+/// in phase one of every cycle, bypassing the pipeline.
+pub struct Functional {
+    /// Instructions decoded before, None if they are decoded every time.
+    cache: Option<DecodeCache>,
+}
+
+impl Functional {
+    /// Create the fast engine.
+    /// # Arguments
+    /// * `decode_cache` - Entries of its decode cache, a power of two, or 0
+    /// for none.
+    pub fn new(decode_cache: u32) -> Self {
+        Self {
+            cache: if decode_cache == 0 {
+                None
+            } else {
+                Some(DecodeCache::new(decode_cache))
+            },
+        }
+    }
+}
+
+impl Cpu for Functional {
+    fn tick(
+        &mut self,
+        phase: &Phase,
+        dp: &mut DataPath,
+        mem: &mut Memory,
+        cycle: u64,
+    ) -> Result<Option<RetiredInstruction>> {
+        if *phase != Phase::One {
+            return Ok(None);
+        }
+
+        let pc = dp.get_pc();
+        let physical = match dp.translate_fetch(pc, mem.regions()) {
+            Ok(v) => v,
+            Err(trap) => {
+                // Nothing was fetched, so nothing retires.
+                dp.raise_trap(trap);
+                return Ok(None);
+            }
+        };
+        let word = mem.get_word(physical)?;
+        let instruction = match self.cache.as_mut() {
+            Some(cache) => cache.decode(physical, word)?,
+            None => decode_fast(word)?,
+        };
+        let psw_before = dp.get_psw();
+        let result = execute(&instruction, dp, mem)?;
+        commit(&result, dp);
+        Ok(Some(RetiredInstruction::new(
+            instruction,
+            pc,
+            psw_before,
+            &result,
+            dp,
+            cycle,
+        )))
+    }
+
+    fn drain(&mut self, _dp: &mut DataPath) -> bool {
+        // Instructions commit as they retire.
+        true
+    }
+
+    fn describe(&self, _syntax: Syntax) -> String {
+        // Nothing is in flight between clock phases.
+        format!("functional")
+    }
+
+    fn occupancy(&self) -> Occupancy {
+        Occupancy::default()
+    }
+
+    fn flush(&mut self, _dp: &DataPath) {
+        // Nothing is in flight, decoded instructions stay cached.
+    }
+
+    fn decode_cache_stats(&self) -> Option<DecodeCacheStats> {
+        self.cache.as_ref().map(|c| c.stats())
+    }
+}
+
+impl Snapshot for Functional {
+    // Instructions never outlive a clock phase, there is nothing to save.
+    fn save(&self, _w: &mut Writer) {}
+
+    fn restore(&mut self, _r: &mut Reader) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Create a CPU for `engine`.
+/// # Arguments
+/// * `engine` - Which engine to run.
+/// * `dp` - Data path the CPU will run on.
+/// * `decode_cache` - Entries of the fast engine's decode cache, a power of
+/// two, or 0 for none.
+pub fn new_cpu(engine: Engine, dp: &DataPath, decode_cache: u32) -> Box<dyn Cpu> {
+    match engine {
+        Engine::Fast => Box::new(Functional::new(decode_cache)),
+        Engine::Cycle => Box::new(Pipeline::new(dp)),
+    }
+}
@@ -0,0 +1,159 @@
+// Errors of the RISC II emulator.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Every fallible function returns util::Result, whose error says what kind
+// of thing went wrong so callers can tell a bus error from a bad command
+// line argument without parsing messages. Errors that only ever reach a
+// person carry a message, built with berr!, the others keep the error they
+// came from.
+
+use decode::DecodeError;
+use memory::MemError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// What went wrong.
+pub enum EmulatorError {
+    /// A word is not an instruction.
+    Decode(DecodeError),
+    /// A memory access failed.
+    Mem(MemError),
+    /// An operation needs system mode.
+    Privilege(String),
+    /// The configuration, a command line argument or the system it
+    /// describes is invalid.
+    Config(String),
+    /// Reading or writing a host file failed.
+    Io {
+        /// What was being done, empty if unknown.
+        context: String,
+        /// The host's error.
+        error: io::Error,
+    },
+    /// The SDL front-end failed.
+    Sdl(String),
+    /// A source file does not assemble.
+    Asm(String),
+    /// An input (image, symbols, snapshot, expression, ...) is malformed.
+    Format(String),
+    /// A device was asked to do something it cannot.
+    Device(String),
+    /// A register that does not exist was named.
+    Register(String),
+    /// Two runs that should agree did not.
+    Mismatch(String),
+    /// The emulator got into a state it should never be in, a bug in the
+    /// emulator rather than the program.
+    Internal(String),
+}
+
+impl EmulatorError {
+    /// Create an error for a failed host file operation.
+    /// # Arguments
+    /// * `context` - What was being done, e.g. `Could not open foo.bin`.
+    /// * `error` - The host's error.
+    pub fn io(context: String, error: io::Error) -> Self {
+        Self::Io {
+            context: context,
+            error: error,
+        }
+    }
+
+    /// Turn the error of a register access made by an instruction into an
+    /// internal fault naming the instruction and its address. Decoded
+    /// instructions only name registers that exist, so one that does not
+    /// is a bug in the emulator. Other errors are returned as they are.
+    /// # Arguments
+    /// * `instruction` - The instruction that made the access.
+    /// * `pc` - Address of the instruction.
+    pub fn in_instruction<I: fmt::Display>(self, instruction: &I, pc: u32) -> Self {
+        match self {
+            Self::Register(s) => {
+                Self::Internal(format!("{} at pc 0x{:08x}: {}", s, pc, instruction))
+            }
+            e => e,
+        }
+    }
+
+    /// Create an error for a failed SDL call.
+    /// # Arguments
+    /// * `error` - SDL's error.
+    pub fn sdl<E: fmt::Display>(error: E) -> Self {
+        Self::Sdl(error.to_string())
+    }
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "{}", e),
+            Self::Mem(e) => write!(f, "{}", e),
+            Self::Privilege(s) => write!(f, "Privilege violation: {}", s),
+            Self::Io { context, error } if context.is_empty() => write!(f, "{}", error),
+            Self::Io { context, error } => write!(f, "{}: {}", context, error),
+            Self::Sdl(s) => write!(f, "SDL: {}", s),
+            Self::Internal(s) => write!(f, "Internal fault: {}", s),
+            Self::Config(s)
+            | Self::Asm(s)
+            | Self::Format(s)
+            | Self::Device(s)
+            | Self::Register(s)
+            | Self::Mismatch(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Shown as the message, so main returning an error prints it readably.
+impl fmt::Debug for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Error for EmulatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Decode(e) => Some(e),
+            Self::Mem(e) => Some(e),
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for EmulatorError {
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<MemError> for EmulatorError {
+    fn from(e: MemError) -> Self {
+        Self::Mem(e)
+    }
+}
+
+impl From<fmt::Error> for EmulatorError {
+    fn from(e: fmt::Error) -> Self {
+        Self::Format(format!("Could not format output: {}", e))
+    }
+}
+
+impl From<io::Error> for EmulatorError {
+    fn from(e: io::Error) -> Self {
+        Self::io(String::new(), e)
+    }
+}
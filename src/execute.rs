@@ -14,588 +14,475 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use cpu::ProcessorStatusWord;
+use alu::ALU;
+use commit::MemoryAccess;
+use cpu::{
+    ProcessorStatusWord, RegisterFile, Trap, HWORD_ALIGN_MASK, JUMP_ALIGN_MASK, PSW_LOC,
+    WORD_ALIGN_MASK,
+};
+use data_path::{effective_address, Control, DataPath, SCCBits};
 use instruction::*;
-use system::System;
-use util::U32_MSB;
+use memory::{Access, Memory, Width};
+use mmu::{Mmu, Target};
+use region::Permission;
+use util::{Result, U32_MSB};
 
 // Public structs.
 
+/// The effects of executing a single instruction. Nothing in here is
+/// visible to the data path until it is committed (see `commit.rs`).
 pub struct ExecResult {
+    /// PSW after the instruction executed.
     psw: ProcessorStatusWord,
+    /// Register file after the instruction executed.
     regs: RegisterFile,
-    was_branch: bool,
+    /// Target address if the instruction was a taken branch.
+    branch: Option<u32>,
+    /// True if the new PSW was written by `PUTPSW`, and only takes effect
+    /// after the next instruction.
     psw_delayed: bool,
+    /// Trap raised by the instruction, if any. A trapped instruction has
+    /// no other effect.
+    trap: Option<Trap>,
+    /// Load or store the instruction performed, if any.
+    access: Option<MemoryAccess>,
+    /// MMU after the instruction executed.
+    mmu: Mmu,
+}
+
+/// Abort the instruction with an alignment trap if `addr` has any of the
+/// bits in `mask` set.
+macro_rules! abort_unaligned {
+    ( $result:expr, $addr:expr, $mask:expr ) => {
+        if $addr & $mask != 0 {
+            return Ok($result.abort(Trap::Alignment($addr)));
+        }
+    };
+}
+
+/// Load `width` bytes from the virtual address `addr` and record the
+/// access. Abort the instruction with an access violation if the MMU or the
+/// memory's regions do not allow it.
+macro_rules! load {
+    ( $result:expr, $memory:expr, $addr:expr, $width:expr, $system_mode:expr ) => {
+        match $result.mmu.translate($addr, $width, $system_mode) {
+            Ok(Target::Memory(physical)) => {
+                if !$memory.regions().allows(physical, $width, Permission::Read) {
+                    return Ok($result.abort(Trap::AccessViolation($addr)));
+                }
+                let d = read_physical($memory, physical, $width)?;
+                $result.access = Some(MemoryAccess::new(physical, $width, d, false));
+                d
+            }
+            Ok(Target::Register) => $result.mmu.read_register($addr),
+            Err(trap) => return Ok($result.abort(trap)),
+        }
+    };
+}
+
+/// Store the low `width` bytes of `value` to the virtual address `addr` and
+/// record the access. Abort the instruction with an access violation if the
+/// MMU or the memory's regions do not allow it.
+macro_rules! store {
+    ( $result:expr, $memory:expr, $addr:expr, $width:expr, $value:expr, $system_mode:expr ) => {
+        match $result.mmu.translate($addr, $width, $system_mode) {
+            Ok(Target::Memory(physical)) => {
+                if !$memory
+                    .regions()
+                    .allows(physical, $width, Permission::Write)
+                {
+                    return Ok($result.abort(Trap::AccessViolation($addr)));
+                }
+                write_physical($memory, physical, $width, $value)?;
+                $result.access = Some(MemoryAccess::new(physical, $width, $value, true));
+            }
+            Ok(Target::Register) => $result.mmu.write_register($addr, $value),
+            Err(trap) => return Ok($result.abort(trap)),
+        }
+    };
 }
 
 // Public functions.
 
 // TODO timing and memory reads/writes. Need to emulate the pipeline and cpu clock.
-pub fn execute(instruction: &Instruction, system: &mut System) -> Result<ExecResult> {
+/// Execute `instruction` against the state of `dp`. Memory writes are
+/// performed immediately, everything else is held in the returned
+/// `ExecResult` until it is committed. An instruction naming a register
+/// that does not exist is an internal fault.
+/// # Arguments
+/// * `instruction` - Decoded instruction to execute.
+/// * `dp` - Data path whose registers and PSW the instruction reads.
+/// * `memory` - Memory for loads and stores.
+pub fn execute(
+    instruction: &Instruction,
+    dp: &DataPath,
+    memory: &mut Memory,
+) -> Result<ExecResult> {
+    execute_instruction(instruction, dp, memory)
+        .map_err(|e| e.in_instruction(instruction, dp.get_pc()))
+}
+
+/// Execute `instruction`, see `execute`.
+/// # Arguments
+/// * `instruction` - Decoded instruction to execute.
+/// * `dp` - Data path whose registers and PSW the instruction reads.
+/// * `memory` - Memory for loads and stores.
+fn execute_instruction(
+    instruction: &Instruction,
+    dp: &DataPath,
+    memory: &mut Memory,
+) -> Result<ExecResult> {
     type I = Instruction;
 
-    let mut result = ExecResult::from_system(system);
-    let mut register_file = result.get_register_file();
-    let cur_pc = register_file.get_pc();
-    let cur_psw = system.get_psw();
-    let mut memory = system.get_mem_ref();
+    let mut result = ExecResult::from_data_path(dp);
+    let cur_pc = dp.get_pc();
+    let cur_psw = dp.get_psw();
+    let cwp = cur_psw.get_cwp();
+    let system_mode = cur_psw.get_system_mode();
+    let control = Control::of(instruction);
+
+    if dp.get_pending_psw().is_some() && instruction.conflicts_with_putpsw() {
+        return Ok(result.abort(Trap::IllegalInstruction));
+    }
 
     match *instruction {
-        I::Calli {
-            scc: scc,
-            dest: dest,
-            rs1: _,
-            short_source: _,
-        } => {
-            if !system.is_system_mode {
-                // TODO error
-            }
-            system.call();
-            let lstpc = system.get_last_pc();
-            if scc {
-                system.set_cc_zero(lstpc == 0);
-                system.set_cc_neg(lstpc & U32_MSB != 0);
-            }
-            register_file.rus(dest, lstpc)?;
-            // TODO maybe handle interrupts.
-        }
-        I::GetPSW {
-            scc: scc,
-            dest: dest,
-            rs1: _,
-            short_source: ss,
-        } => {
-            let psw = cur_psw & 0xffff7;
-            register_file.rus(dest, psw)?;
-            if scc {
-                let dest_val = register_file.ru(dest)?;
-                self.set_cc_neg(dest_val & U32_MSB != 1);
-                self.set_cc_zero(dest_val == 0);
-                self.set_cc_carry(false);
-                self.set_cc_overflow(false);
-            }
-        }
-        I::GetLPC {
-            scc: scc,
-            dest: dest,
-            rs1: _,
-            short_source: _,
-        } => {
-            if !system.is_system_mode {
-                // TODO error
-            }
-            let lstpc = system.get_last_pc();
-            register_file.rus(dest, lstpc)?;
-            if scc {
-                system.set_cc_zero(lstpc == 0);
-                system.set_cc_neg(lstpc & U32_MSB != 0);
-            }
-        }
-        I::PutPSW {
-            scc: scc,
-            dest: _,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            if !system.is_system_mode {
-                // TODO error
+        I::Calli(ShortInstruction { scc, dest, .. }) => {
+            if !system_mode {
+                return Ok(result.abort(Trap::PrivilegeViolation));
             }
+            // Taking the interrupt or trap already entered system mode,
+            // disabled interrupts and saved the PC to resume at in LSTPC.
+            // CALLI gives the handler a window of its own, rd is in it.
+            result.psw.push(result.regs.windows());
+            let lstpc = dp.get_last_pc();
             if scc {
-                // TODO error
-            }
-
-            let val = register_file.get_ss_val(ss, cur_psw)?;
-            result.set_psw(register_file.ru(rs1)? + val);
-        }
-        I::Callx {
-            scc: _,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            // TODO test alignment (addr[0] == 1).
-            let rs_val = register_file.ru(rs1)?;
-            let addr = register_file.get_ss_val(ss, cur_psw)? + rs_val;
-            register_file.push_reg_window();
-            register_file.branch_to(addr);
-            result.rus(cur_pc)?;
-        }
-        I::Callr {
-            scc: _,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            // TODO test alignment (addr[0] == 1).
-            result.set_branch(true);
-            register_file.push_reg_window();
-            register_file.branch_to(cur_pc + imm19);
-        }
-        I::Jmpx {
-            scc: _,
-            dest: cond,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            // TODO test alignment (addr[0] == 1).
-            if exec_conditional(cond, result.get_psw()) {
-                result.set_branch(true);
-                let rs_val = register_file.ru(rs1)?;
-                let addr = register_file.get_ss_val(ss, cur_psw)? + rs_val;
-                register_file.branch_to(addr);
-            }
-        }
-        I::Jmpr {
-            scc: _,
-            dest: cond,
-            imm19: imm19,
-        } => {
-            if exec_conditional(cond, result.get_psw()) {
-                result.set_branch(true);
-                register_file.branch_to(cur_pc + imm19);
+                set_shift_cc(&mut result.psw, lstpc);
             }
+            result.regs.try_write(dest, lstpc, result.psw.get_cwp())?;
         }
-        I::Ret {
-            scc: _,
-            dest: cond,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            if exec_conditional(cond, result.get_psw()) {
-                result.set_branch(true);
-                let rs_val = register_file.ru(rs1)?;
-                register_file.branch_to(rs_val + (SIZEOF_INSTRUCTION * 2));
-                register_file.pop_reg_window();
-            }
-        }
-        I::Reti {
-            scc: _,
-            dest: cond,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            if !system.is_system_mode {
-                // TODO error
-            }
-            if exec_conditional(cond, result.get_psw()) {
-                result.set_branch(true);
-                let rs_val = register_file.ru(rs1)?;
-                register_file.branch_to(rs_val + (SIZEOF_INSTRUCTION * 2));
-                register_file.pop_reg_window();
-            }
-        }
-        I::Sll {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, s1_val << s2_val)?;
-            if scc {
-                set_shift_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Srl {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, s1_val >> s2_val)?;
-            if scc {
-                set_shift_cc(scc, result.get_psw_ref(), d);
-            }
-        }
-        I::Sra {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, s1_val as i32 >> s2_val)?;
+        I::GetPSW(ShortInstruction { scc, dest, .. }) => {
+            let psw = 0xffffe000 | cur_psw.get() as u32;
+            result.regs.try_write(dest, psw, cwp)?;
             if scc {
-                set_shift_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Or {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, s1_val | s2_val)?;
-            if scc {
-                set_shift_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::And {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, s1_val & s2_val)?;
-            if scc {
-                set_shift_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Xor {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, s1_val ^ s2_val)?;
-            if scc {
-                set_shift_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Add {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let (res, o) = s1_val.overflowing_add(s2_val);
-            let d = register_file.rus(dest, res)?;
-            if scc {
-                let mut psw = result.get_psw_ref();
-                set_operator_cc(psw, d);
-                psw.set_cc_overflow(o);
-                psw.set_cc_carry(o);
-            }
-        }
-        I::Addc {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let mut psw = result.get_psw_ref();
-            let (r1, o1) = s1_val.overflowing_add(s2_val);
-            let (res, o2) = r1.overflowing_add(psw.get_cc_carry() as u32);
-            let o = o1 || o2;
-            let d = register_file.rus(dest, res)?;
-            if scc {
-                set_operator_cc(psw, d);
-                psw.set_cc_overflow(o);
-                psw.set_cc_carry(o);
-            }
-        }
-        I::Sub {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let (res, o) = s1_val.overflowing_sub(s2_val);
-            let d = register_file.rus(dest, res)?;
-            if scc {
-                let mut psw = result.get_psw_ref();
-                set_operator_cc(psw, d);
-                psw.set_cc_overflow(o);
-                psw.set_cc_carry(!o);
-            }
-        }
-        I::Subc {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let mut psw = result.get_psw_ref();
-            let (r1, o1) = s1_val.overflowing_sub(s2_val);
-            let (res, o2) = r1.overflowing_sub(!psw.get_cc_carry() as u32);
-            let o = o2 || o1;
-            let d = register_file.rus(dest, res);
-            if scc {
-                let mut psw = result.get_psw_ref();
-                set_operator_cc(psw, d);
-                psw.set_cc_overflow(o);
-                psw.set_cc_carry(!o);
-            }
-        }
-        I::Subi {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let (res, o) = s2_val.overflowing_sub(s1_val);
-            let d = register_file.rus(dest, res)?;
-            if scc {
-                let mut psw = result.get_psw_ref();
-                set_operator_cc(psw, d);
-                let v = d > s2_val;
-                psw.set_cc_overflow(v);
-                psw.set_cc_carry(!v);
-            }
-        }
-        I::Subci {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let s1_val = register_file.ru(rs1)?;
-            let s2_val = register_file.get_ss_val(ss, cur_psw)?;
-            let mut psw = result.get_psw_ref();
-            let (r1, o1) = s2_val.overflowing_sub(s1_val);
-            let (res, o2) = r1.overflowing_sub(!psw.get_cc_carry() as u32);
-            let o = o1 || o2;
-            let d = register_file.rus(dest, res)?;
-            if scc {
-                set_operator_cc(psw, d);
-                psw.set_cc_overflow(o);
-                psw.set_cc_carry(!o);
-            }
-        }
-        I::Ldhi {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            // TODO Test alignment
-            let cur_d = register_file.ru(dest)?;
-            let d = register_file.rus(dest, dest & ((imm19 << 13) & 0x1fff))?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Ldxw {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            // TODO Test alignment
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, memory.get_word(ss_val)?)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Ldrw {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            let addr = imm19 + regs.get_pc();
-            let d = register_file.rus(dest, memory.get_word(ss_val)?)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Ldxhs {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, memory.get_hword(ss_val)? as i32 as u32)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
+                set_shift_cc(&mut result.psw, psw);
             }
         }
-        I::Ldrhs {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            let addr = imm19 + regs.get_pc();
-            let d = register_file.rus(dest, memory.get_hword(ss_val)? as i32 as u32)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
+        I::GetLPC(ShortInstruction { scc, dest, .. }) => {
+            if !system_mode {
+                return Ok(result.abort(Trap::PrivilegeViolation));
             }
-        }
-        I::Ldxhu {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, memory.get_hword(ss_val)? as u32)?;
+            let lstpc = dp.get_last_pc();
+            result.regs.try_write(dest, lstpc, cwp)?;
             if scc {
-                set_load_cc(result.get_psw_ref(), d);
+                set_shift_cc(&mut result.psw, lstpc);
             }
         }
-        I::Ldrhu {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            let addr = imm19 + regs.get_pc();
-            let d = register_file.rus(dest, memory.get_hword(ss_val)? as u32)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
+        I::GetTCR(ShortInstruction { scc, dest, .. }) => {
+            if !system_mode {
+                return Ok(result.abort(Trap::PrivilegeViolation));
             }
-        }
-        I::Ldxbs {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, memory.get_byte(ss_val)? as i32 as u32)?;
+            let tcr = dp.get_trap_cause();
+            result.regs.try_write(dest, tcr, cwp)?;
             if scc {
-                set_load_cc(result.get_psw_ref(), d);
+                set_shift_cc(&mut result.psw, tcr);
             }
         }
-        I::Ldrbs {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            let addr = imm19 + regs.get_pc();
-            let d = register_file.rus(dest, memory.get_byte(ss_val)? as i32 as u32)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
+        I::PutPSW(ShortInstruction {
+            rs1, short_source, ..
+        }) => {
+            if !system_mode {
+                return Ok(result.abort(Trap::PrivilegeViolation));
             }
+            let val = source_sum(rs1, short_source, &result.regs, cwp)?;
+            result.psw = ProcessorStatusWord::from_u16(val as u16 & PSW_LOC);
+            result.psw_delayed = true;
         }
-        I::Ldxbu {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let d = register_file.rus(dest, memory.get_byte(ss_val)? as u32)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
-            }
+        I::Callx(ShortInstruction {
+            dest,
+            rs1,
+            short_source,
+            ..
+        }) => {
+            let addr = source_sum(rs1, short_source, &result.regs, cwp)?;
+            abort_unaligned!(result, addr, JUMP_ALIGN_MASK);
+            result.psw.push(result.regs.windows());
+            result.regs.try_write(dest, cur_pc, result.psw.get_cwp())?;
+            result.branch = Some(addr);
         }
-        I::Ldrbu {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            let addr = imm19 + regs.get_pc();
-            let d = register_file.rus(dest, memory.get_byte(ss_val)? as u32)?;
-            if scc {
-                set_load_cc(result.get_psw_ref(), d);
-            }
-        }
-        I::Stxw {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            if short_source == ShortSource::Reg(_) {
-                // warn
-                // return Err("Store instructions should be immediate only (not registers)");
-            }
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let rs1_val = register_file.ru(rs1);
-            let dest_val = register_file.ru(dest);
-            memory.set_word(ss_val + rs1_val, dest_val);
-            if scc {
-                set_store_cc(result.get_psw_ref());
-            }
+        I::Callr(l) => {
+            let addr = cur_pc.wrapping_add(l.offset());
+            abort_unaligned!(result, addr, JUMP_ALIGN_MASK);
+            result.psw.push(result.regs.windows());
+            result
+                .regs
+                .try_write(l.dest, cur_pc, result.psw.get_cwp())?;
+            result.branch = Some(addr);
         }
-        I::Strw {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            if short_source == ShortSource::Reg(_) {
-                // warn
-                // return Err("Store instructions should be immediate only (not registers)");
-            }
-            let dest_val = register_file.ru(dest);
-            memory.set_word(register_file.get_pc() + imm19, dest_val);
-            if scc {
-                set_store_cc(result.get_psw_ref());
-            }
-        }
-        I::Stxh {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            if short_source == ShortSource::Reg(_) {
-                // warn
-                // return Err("Store instructions should be immediate only (not registers)");
-            }
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let rs1_val = register_file.ru(rs1);
-            let dest_val = register_file.ru(dest);
-            memory.set_hword(ss_val + rs1_val, dest_val as u16);
-            if scc {
-                set_store_cc(result.get_psw_ref());
+        I::Jmpx(ShortConditional {
+            dest: cond,
+            rs1,
+            short_source,
+            ..
+        }) => {
+            if cond.evaluate(&cur_psw) {
+                let addr = source_sum(rs1, short_source, &result.regs, cwp)?;
+                abort_unaligned!(result, addr, JUMP_ALIGN_MASK);
+                result.branch = Some(addr);
             }
         }
-        I::Strh {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            if short_source == ShortSource::Reg(_) {
-                // warn
-                // return Err("Store instructions should be immediate only (not registers)");
-            }
-            let dest_val = register_file.ru(dest);
-            memory.set_hword(register_file.get_pc() + imm19, dest_val as u16);
-            if scc {
-                set_store_cc(result.get_psw_ref());
-            }
-        }
-        I::Stxb {
-            scc: scc,
-            dest: dest,
-            rs1: rs1,
-            short_source: ss,
-        } => {
-            if short_source == ShortSource::Reg(_) {
-                // warn
-                // return Err("Store instructions should be immediate only (not registers)");
-            }
-            let ss_val = register_file.get_ss_val(ss, cur_psw)?;
-            let rs1_val = register_file.ru(rs1);
-            let dest_val = register_file.ru(dest);
-            memory.set_byte(ss_val + rs1_val, dest_val as u8);
-            if scc {
-                set_store_cc(result.get_psw_ref());
+        I::Jmpr(l) => {
+            if l.dest.evaluate(&cur_psw) {
+                let addr = cur_pc.wrapping_add(l.offset());
+                abort_unaligned!(result, addr, JUMP_ALIGN_MASK);
+                result.branch = Some(addr);
             }
         }
-        I::Strb {
-            scc: scc,
-            dest: dest,
-            imm19: imm19,
-        } => {
-            if short_source == ShortSource::Reg(_) {
-                // warn
-                // return Err("Store instructions should be immediate only (not registers)");
-            }
-            let dest_val = register_file.ru(dest);
-            memory.set_byte(register_file.get_pc() + imm19, dest_val as u8);
-            if scc {
-                set_store_cc(result.get_psw_ref());
+        I::Ret(ShortConditional {
+            dest: cond,
+            rs1,
+            short_source,
+            ..
+        }) => {
+            if cond.evaluate(&cur_psw) {
+                let addr = source_sum(rs1, short_source, &result.regs, cwp)?;
+                abort_unaligned!(result, addr, JUMP_ALIGN_MASK);
+                result.branch = Some(addr);
+                result.psw.pop(result.regs.windows());
+            }
+        }
+        I::Reti(ShortConditional {
+            dest: cond,
+            rs1,
+            short_source,
+            ..
+        }) => {
+            if !system_mode {
+                return Ok(result.abort(Trap::PrivilegeViolation));
+            }
+            if cond.evaluate(&cur_psw) {
+                let addr = source_sum(rs1, short_source, &result.regs, cwp)?;
+                abort_unaligned!(result, addr, JUMP_ALIGN_MASK);
+                result.branch = Some(addr);
+                result.psw.pop(result.regs.windows());
+                result.psw.set_interrupt_enabled(true);
+                result
+                    .psw
+                    .set_system_mode(cur_psw.get_previous_system_mode());
+            }
+        }
+        I::Sll(s) => {
+            let (s1_val, s2_val) = short_operands(&s, &result.regs, cwp)?;
+            let d = s1_val << (s2_val & 0x1f);
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_shift_cc(&mut result.psw, d);
+            }
+        }
+        I::Srl(s) => {
+            let (s1_val, s2_val) = short_operands(&s, &result.regs, cwp)?;
+            let d = s1_val >> (s2_val & 0x1f);
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_shift_cc(&mut result.psw, d);
+            }
+        }
+        I::Sra(s) => {
+            let (s1_val, s2_val) = short_operands(&s, &result.regs, cwp)?;
+            let d = ((s1_val as i32) >> (s2_val & 0x1f)) as u32;
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_shift_cc(&mut result.psw, d);
+            }
+        }
+        I::Or(s) => {
+            let (s1_val, s2_val) = short_operands(&s, &result.regs, cwp)?;
+            let d = s1_val | s2_val;
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_shift_cc(&mut result.psw, d);
+            }
+        }
+        I::And(s) => {
+            let (s1_val, s2_val) = short_operands(&s, &result.regs, cwp)?;
+            let d = s1_val & s2_val;
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_shift_cc(&mut result.psw, d);
+            }
+        }
+        I::Xor(s) => {
+            let (s1_val, s2_val) = short_operands(&s, &result.regs, cwp)?;
+            let d = s1_val ^ s2_val;
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_shift_cc(&mut result.psw, d);
+            }
+        }
+        I::Add(s) => arithmetic(&s, &mut result, cwp, |alu| alu.add_scc())?,
+        I::Addc(s) => arithmetic(&s, &mut result, cwp, |alu| {
+            alu.addc_scc(cur_psw.get_cc_carry())
+        })?,
+        I::Sub(s) => arithmetic(&s, &mut result, cwp, |alu| alu.sub_scc())?,
+        I::Subc(s) => arithmetic(&s, &mut result, cwp, |alu| {
+            alu.subc_scc(cur_psw.get_cc_carry())
+        })?,
+        I::Subi(s) => arithmetic(&s, &mut result, cwp, |alu| alu.subi_scc())?,
+        I::Subci(s) => arithmetic(&s, &mut result, cwp, |alu| {
+            alu.subci_scc(cur_psw.get_cc_carry())
+        })?,
+        I::Extension(op, s) => {
+            let (s1_val, s2_val) = short_operands(&s, &result.regs, cwp)?;
+            match dp
+                .extensions()
+                .execute(op, s1_val, s2_val, cur_psw.get_cc_carry())
+            {
+                Ok(v) => arithmetic(&s, &mut result, cwp, |_| v)?,
+                Err(trap) => return Ok(result.abort(trap)),
+            }
+        }
+        I::Ldhi(LongInstruction { scc, dest, imm19 }) => {
+            let d = imm19 << 13;
+            result.regs.try_write(dest, d, cwp)?;
+            if scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldxw(s) => {
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            abort_unaligned!(result, addr, WORD_ALIGN_MASK);
+            let d = load!(result, memory, addr, 4, system_mode);
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldrw(l) => {
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            abort_unaligned!(result, addr, WORD_ALIGN_MASK);
+            let d = load!(result, memory, addr, 4, system_mode);
+            result.regs.try_write(l.dest, d, cwp)?;
+            if l.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldxhs(s) => {
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            abort_unaligned!(result, addr, HWORD_ALIGN_MASK);
+            let d = load!(result, memory, addr, 2, system_mode) as u16 as i16 as i32 as u32;
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldrhs(l) => {
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            abort_unaligned!(result, addr, HWORD_ALIGN_MASK);
+            let d = load!(result, memory, addr, 2, system_mode) as u16 as i16 as i32 as u32;
+            result.regs.try_write(l.dest, d, cwp)?;
+            if l.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldxhu(s) => {
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            abort_unaligned!(result, addr, HWORD_ALIGN_MASK);
+            let d = load!(result, memory, addr, 2, system_mode);
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldrhu(l) => {
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            abort_unaligned!(result, addr, HWORD_ALIGN_MASK);
+            let d = load!(result, memory, addr, 2, system_mode);
+            result.regs.try_write(l.dest, d, cwp)?;
+            if l.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldxbs(s) => {
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            let d = load!(result, memory, addr, 1, system_mode) as u8 as i8 as i32 as u32;
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldrbs(l) => {
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            let d = load!(result, memory, addr, 1, system_mode) as u8 as i8 as i32 as u32;
+            result.regs.try_write(l.dest, d, cwp)?;
+            if l.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldxbu(s) => {
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            let d = load!(result, memory, addr, 1, system_mode);
+            result.write_dest(&s, d, cwp)?;
+            if s.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Ldrbu(l) => {
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            let d = load!(result, memory, addr, 1, system_mode);
+            result.regs.try_write(l.dest, d, cwp)?;
+            if l.scc {
+                set_load_cc(&mut result.psw, d);
+            }
+        }
+        I::Stxw(s) => {
+            let dest_val = result.regs.try_read(s.dest, cwp)?;
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            abort_unaligned!(result, addr, WORD_ALIGN_MASK);
+            store!(result, memory, addr, 4, dest_val, system_mode);
+            if s.scc {
+                set_store_cc(&mut result.psw);
+            }
+        }
+        I::Strw(l) => {
+            let dest_val = result.regs.try_read(l.dest, cwp)?;
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            abort_unaligned!(result, addr, WORD_ALIGN_MASK);
+            store!(result, memory, addr, 4, dest_val, system_mode);
+            if l.scc {
+                set_store_cc(&mut result.psw);
+            }
+        }
+        I::Stxh(s) => {
+            let dest_val = result.regs.try_read(s.dest, cwp)?;
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            abort_unaligned!(result, addr, HWORD_ALIGN_MASK);
+            store!(result, memory, addr, 2, dest_val, system_mode);
+            if s.scc {
+                set_store_cc(&mut result.psw);
+            }
+        }
+        I::Strh(l) => {
+            let dest_val = result.regs.try_read(l.dest, cwp)?;
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            abort_unaligned!(result, addr, HWORD_ALIGN_MASK);
+            store!(result, memory, addr, 2, dest_val, system_mode);
+            if l.scc {
+                set_store_cc(&mut result.psw);
+            }
+        }
+        I::Stxb(s) => {
+            let dest_val = result.regs.try_read(s.dest, cwp)?;
+            let addr = index_address(&control, cur_pc, &s, &result.regs, cwp)?;
+            store!(result, memory, addr, 1, dest_val, system_mode);
+            if s.scc {
+                set_store_cc(&mut result.psw);
+            }
+        }
+        I::Strb(l) => {
+            let dest_val = result.regs.try_read(l.dest, cwp)?;
+            let addr = effective_address(&control, cur_pc, 0, l.offset());
+            store!(result, memory, addr, 1, dest_val, system_mode);
+            if l.scc {
+                set_store_cc(&mut result.psw);
             }
         }
     }
@@ -606,45 +493,135 @@ pub fn execute(instruction: &Instruction, system: &mut System) -> Result<ExecRes
 // Struct impls.
 
 impl ExecResult {
-    pub fn from_system(system: &System) -> Self {
+    pub fn from_data_path(dp: &DataPath) -> Self {
         Self {
-            psw: system.get_psw(),
-            register_file: system.copy_register_file(),
-            was_branch: false,
+            psw: dp.get_psw(),
+            regs: dp.copy_register_file(),
+            branch: None,
             psw_delayed: false,
+            trap: None,
+            access: None,
+            mmu: dp.get_mmu(),
         }
     }
 
-    pub fn set_psw(&mut self, psw: u32) {
-        self.psw.from_u32(psw);
+    /// Discard the effects of the instruction and record `trap` instead.
+    /// # Arguments
+    /// * `trap` - The trap raised by the instruction.
+    fn abort(mut self, trap: Trap) -> Self {
+        self.trap = Some(trap);
+        self
     }
 
-    pub fn get_psw_ref(&mut self) -> &mut ProcessorStatusWord {
-        &mut self.psw
+    /// Write `value` to the destination register of `s`.
+    fn write_dest(&mut self, s: &ShortInstruction, value: u32, cwp: u8) -> Result<()> {
+        self.regs.try_write(s.dest, value, cwp)
     }
 
-    pub fn get_register_file(&mut self) -> &mut RegisterFile {
-        &mut self.regs
+    pub fn get_psw(&self) -> ProcessorStatusWord {
+        self.psw
+    }
+
+    pub fn get_register_file(&self) -> &RegisterFile {
+        &self.regs
+    }
+
+    pub fn get_branch(&self) -> Option<u32> {
+        self.branch
     }
 
     pub fn was_branch(&self) -> bool {
-        self.was_branch
+        self.branch.is_some()
+    }
+
+    pub fn is_psw_delayed(&self) -> bool {
+        self.psw_delayed
+    }
+
+    pub fn get_trap(&self) -> Option<Trap> {
+        self.trap
     }
 
-    pub fn set_branch(&mut self, v: bool) {
-        self.was_branch = v;
+    pub fn get_access(&self) -> Option<MemoryAccess> {
+        self.access
+    }
+
+    pub fn get_mmu(&self) -> Mmu {
+        self.mmu
     }
 }
 
 // Private functions.
 
-fn exec_conditional(what: Conditional, psw: ProcessorStatusWord) -> bool {
-    todo!()
+/// Read `width` bytes from physical memory, zero extended.
+fn read_physical(memory: &mut Memory, addr: u32, width: u32) -> Result<u32> {
+    Ok(memory.access(addr, Width::from_bytes(width), Access::Read)?)
+}
+
+/// Write the low `width` bytes of `value` to physical memory.
+fn write_physical(memory: &mut Memory, addr: u32, width: u32, value: u32) -> Result<()> {
+    memory.access(addr, Width::from_bytes(width), Access::Write(value))?;
+    Ok(())
+}
+
+/// Get the sum of rs1 and a short source, as jumps, calls and PUTPSW use it.
+fn source_sum(rs1: u8, ss: ShortSource, regs: &RegisterFile, cwp: u8) -> Result<u32> {
+    Ok(regs
+        .try_read(rs1, cwp)?
+        .wrapping_add(regs.try_get_ss_val(ss, cwp)?))
+}
+
+/// Get the values of `rs1` and the short source of `s`.
+fn short_operands(s: &ShortInstruction, regs: &RegisterFile, cwp: u8) -> Result<(u32, u32)> {
+    Ok((
+        regs.try_read(s.rs1, cwp)?,
+        regs.try_get_ss_val(s.short_source, cwp)?,
+    ))
+}
+
+/// Get the effective address of a register indexed load or store.
+fn index_address(
+    control: &Control,
+    pc: u32,
+    s: &ShortInstruction,
+    regs: &RegisterFile,
+    cwp: u8,
+) -> Result<u32> {
+    let (s1_val, s2_val) = short_operands(s, regs, cwp)?;
+    Ok(effective_address(control, pc, s1_val, s2_val))
+}
+
+/// Run an add or subtract on the ALU, write the result to the destination
+/// and set the condition codes if asked to. Both engines share the ALU, so
+/// they agree on the carry and overflow bits. Return void on success and
+/// an error if the instruction names a register that does not exist.
+/// # Arguments
+/// * `s` - The instruction.
+/// * `result` - Result to write to.
+/// * `cwp` - Current window pointer.
+/// * `op` - Operation on the ALU, rs1 is its first input.
+fn arithmetic<F>(s: &ShortInstruction, result: &mut ExecResult, cwp: u8, op: F) -> Result<()>
+where
+    F: FnOnce(&ALU) -> (u32, SCCBits),
+{
+    let (s1_val, s2_val) = short_operands(s, &result.regs, cwp)?;
+    let (d, scc) = op(&ALU {
+        ai: s1_val,
+        bi: s2_val,
+    });
+    result.write_dest(s, d, cwp)?;
+    if s.scc {
+        result.psw.set_cc_zero(scc.z);
+        result.psw.set_cc_neg(scc.n);
+        result.psw.set_cc_overflow(scc.v);
+        result.psw.set_cc_carry(scc.c);
+    }
+    Ok(())
 }
 
 fn set_operator_cc(psw: &mut ProcessorStatusWord, dest_val: u32) {
-    psw.set_cc_zero(register_file.ru(dest)? == 0);
-    psw.set_cc_neg(register_file.ru(dest)? & U32_MSB != 0);
+    psw.set_cc_zero(dest_val == 0);
+    psw.set_cc_neg(dest_val & U32_MSB != 0);
 }
 
 fn set_shift_cc(psw: &mut ProcessorStatusWord, dest_val: u32) {
@@ -654,10 +631,7 @@ fn set_shift_cc(psw: &mut ProcessorStatusWord, dest_val: u32) {
 }
 
 fn set_load_cc(psw: &mut ProcessorStatusWord, dest_val: u32) {
-    psw.set_cc_carry(false);
-    psw.set_cc_overflow(false);
-    psw.set_cc_zero(d == 0);
-    psw.set_cc_neg(d & U32_MSB != 0);
+    set_shift_cc(psw, dest_val);
 }
 
 fn set_store_cc(psw: &mut ProcessorStatusWord) {
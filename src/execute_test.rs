@@ -0,0 +1,478 @@
+// Test code for RISC II instruction execution.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "execute.rs"]
+mod test {
+    use super::super::*;
+    use commit::commit;
+    use cpu::*;
+    use data_path::{effective_address, Control, DataPath};
+    use error::EmulatorError;
+    use execute::*;
+    use instruction::*;
+    use memory::Memory;
+    use util::Result;
+
+    type I = Instruction;
+    type SS = ShortSource;
+    type SI = ShortInstruction;
+    type SC = ShortConditional;
+
+    /// Execute and commit a single instruction.
+    fn run(instruction: I, dp: &mut DataPath, mem: &mut Memory) -> Result<()> {
+        let result = execute(&instruction, dp, mem)?;
+        commit(&result, dp);
+        Ok(())
+    }
+
+    fn user_data_path() -> DataPath {
+        let mut dp = DataPath::new();
+        // User mode with every CC set, so any change is visible.
+        dp.set_psw(CARRY_LOC | OVERFLOW_LOC | NEG_LOC | ZERO_LOC);
+        dp
+    }
+
+    fn assert_privilege_trap(dp: &DataPath, psw_before: ProcessorStatusWord) {
+        let psw = dp.get_psw();
+        assert_eq_hex!(dp.get_pc(), Trap::PrivilegeViolation.vector());
+        assert_eq_hex!(dp.get_last_pc(), 0);
+        assert!(psw.get_system_mode());
+        assert!(!psw.get_previous_system_mode());
+        // The condition codes and windows are untouched by the aborted instruction.
+        assert_eq!(psw.get_cwp(), psw_before.get_cwp());
+        assert_eq!(psw.get_cc_carry(), psw_before.get_cc_carry());
+        assert_eq!(psw.get_cc_zero(), psw_before.get_cc_zero());
+        assert_eq!(psw.get_cc_neg(), psw_before.get_cc_neg());
+        assert_eq!(psw.get_cc_overflow(), psw_before.get_cc_overflow());
+    }
+
+    #[test]
+    fn putpsw_user_mode_traps() -> Result<()> {
+        let mut dp = user_data_path();
+        let mut mem = Memory::from_size(64);
+        let before = dp.get_psw();
+        run(
+            I::PutPSW(SI::new(false, 0, 0, SS::Imm13(0x1c00))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_privilege_trap(&dp, before);
+        Ok(())
+    }
+
+    #[test]
+    fn getlpc_user_mode_traps() -> Result<()> {
+        let mut dp = user_data_path();
+        let mut mem = Memory::from_size(64);
+        let before = dp.get_psw();
+        run(
+            I::GetLPC(SI::new(true, 16, 0, SS::Reg(0))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_privilege_trap(&dp, before);
+        assert_eq!(dp.register_file().read(16, before.get_cwp()), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn calli_user_mode_traps() -> Result<()> {
+        let mut dp = user_data_path();
+        let mut mem = Memory::from_size(64);
+        let before = dp.get_psw();
+        run(
+            I::Calli(SI::new(false, 16, 0, SS::Reg(0))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_privilege_trap(&dp, before);
+        Ok(())
+    }
+
+    #[test]
+    fn reti_user_mode_traps() -> Result<()> {
+        let mut dp = user_data_path();
+        let mut mem = Memory::from_size(64);
+        let before = dp.get_psw();
+        run(
+            I::Reti(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x100))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_privilege_trap(&dp, before);
+        Ok(())
+    }
+
+    #[test]
+    fn putpsw_system_mode() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        dp.set_psw(SYSTEM_LOC);
+        run(
+            I::PutPSW(SI::new(
+                false,
+                0,
+                0,
+                SS::Imm13((SYSTEM_LOC | CARRY_LOC) as u32),
+            )),
+            &mut dp,
+            &mut mem,
+        )?;
+        // The next instruction still runs under the old PSW.
+        assert_eq_hex!(dp.get_psw().get(), SYSTEM_LOC);
+        assert_eq_hex!(dp.get_pc(), 4);
+        run(
+            I::GetPSW(SI::new(false, 16, 0, SS::Reg(0))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(
+            dp.register_file().read(16, 0),
+            0xffffe000 | SYSTEM_LOC as u32
+        );
+        assert_eq_hex!(dp.get_psw().get(), SYSTEM_LOC | CARRY_LOC);
+        Ok(())
+    }
+
+    #[test]
+    fn putpsw_forbids_window_and_cc_changes() -> Result<()> {
+        let new_psw = SYSTEM_LOC | 3 << 10;
+        for next in [
+            I::Callr(LongInstruction::new(false, 31, 0x10)),
+            I::Ret(SC::new(false, Conditional::Alw, 31, SS::Imm13(8))),
+            I::Add(SI::new(true, 16, 0, SS::Imm13(1))),
+            I::PutPSW(SI::new(false, 0, 0, SS::Imm13(SYSTEM_LOC as u32))),
+        ]
+        .iter()
+        {
+            let mut dp = DataPath::new();
+            let mut mem = Memory::from_size(64);
+            dp.set_psw(SYSTEM_LOC);
+            run(
+                I::PutPSW(SI::new(false, 0, 0, SS::Imm13(new_psw as u32))),
+                &mut dp,
+                &mut mem,
+            )?;
+            run(*next, &mut dp, &mut mem)?;
+            assert_eq_hex!(dp.get_pc(), Trap::IllegalInstruction.vector());
+            assert_eq_hex!(dp.get_last_pc(), 4);
+            // The PUTPSW took effect, the aborted instruction did not.
+            let psw = dp.get_psw();
+            assert_eq!(psw.get_cwp(), 3);
+            assert!(!psw.get_cc_zero());
+            assert_eq!(dp.register_file().read(16, 0), 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn getpsw_user_mode() -> Result<()> {
+        let mut dp = user_data_path();
+        let mut mem = Memory::from_size(64);
+        let before = dp.get_psw();
+        run(
+            I::GetPSW(SI::new(false, 16, 0, SS::Reg(0))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(
+            dp.register_file().read(16, before.get_cwp()),
+            0xffffe000 | before.get() as u32
+        );
+        assert!(!dp.get_psw().get_system_mode());
+        Ok(())
+    }
+
+    fn assert_alignment_trap(dp: &DataPath, addr: u32) {
+        assert_eq_hex!(dp.get_pc(), TRAP_VECTOR_BASE);
+        assert_eq_hex!(dp.get_trap_cause(), addr);
+        assert!(dp.get_psw().get_system_mode());
+    }
+
+    #[test]
+    fn ldxw_unaligned_traps() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        run(
+            I::Ldxw(SI::new(false, 16, 0, SS::Imm13(0x22))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_alignment_trap(&dp, 0x22);
+        assert_eq!(dp.register_file().read(16, 0), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn stxh_unaligned_traps() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        run(
+            I::Stxh(SI::new(false, 0, 0, SS::Imm13(0x11))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_alignment_trap(&dp, 0x11);
+        Ok(())
+    }
+
+    #[test]
+    fn ldxhu_aligned() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        mem.set_hword(0x12, 0xbeef)?;
+        run(
+            I::Ldxhu(SI::new(false, 16, 0, SS::Imm13(0x12))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(dp.register_file().read(16, 0), 0xbeef);
+        assert_eq_hex!(dp.get_trap_cause(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn effective_address_modes() {
+        let indexed = Control::of(&I::Stxh(SI::new(false, 16, 17, SS::Reg(18))));
+        assert_eq_hex!(effective_address(&indexed, 0x40, 0x100, 0x24), 0x124);
+        // Wraps like the ALU.
+        assert_eq_hex!(effective_address(&indexed, 0x40, 0xfffffffc, 8), 4);
+        let relative = Control::of(&I::Strw(LongInstruction::new(false, 16, 0x10)));
+        assert_eq_hex!(effective_address(&relative, 0x40, 0x100, 0x10), 0x50);
+    }
+
+    #[test]
+    fn stxh_register_indexed() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        dp.get_register_file().write(16, 0xcafebeef, 0);
+        dp.get_register_file().write(17, 0x20, 0);
+        dp.get_register_file().write(18, 0x6, 0);
+        run(
+            I::Stxh(SI::new(false, 16, 17, SS::Reg(18))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(mem.get_hword(0x26)?, 0xbeef);
+        Ok(())
+    }
+
+    #[test]
+    fn strw_pc_relative() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        dp.set_pc(0x8);
+        dp.get_register_file().write(16, 0x12345678, 0);
+        run(
+            I::Strw(LongInstruction::new(false, 16, 0x28)),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(mem.get_word(0x30)?, 0x12345678);
+        Ok(())
+    }
+
+    #[test]
+    fn ldrw_pc_relative() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        mem.set_word(0x30, 0x12345678)?;
+        dp.set_pc(0xc);
+        run(
+            I::Ldrw(LongInstruction::new(false, 16, 0x24)),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(dp.register_file().read(16, 0), 0x12345678);
+        Ok(())
+    }
+
+    #[test]
+    fn ldhi_fills_the_high_bits() -> Result<()> {
+        for &(imm19, value) in [(1, 0x2000), (0x7ffff, 0xffffe000)].iter() {
+            let mut dp = DataPath::new();
+            let mut mem = Memory::from_size(64);
+            run(
+                I::Ldhi(LongInstruction::new(false, 16, imm19)),
+                &mut dp,
+                &mut mem,
+            )?;
+            assert_eq_hex!(dp.register_file().read(16, 0), value);
+        }
+        Ok(())
+    }
+
+    /// imm19 of -16.
+    const BACK_16: u32 = 0x7fff0;
+
+    #[test]
+    fn relative_branches_sign_extend() -> Result<()> {
+        for next in [
+            I::Jmpr(LongConditional::new(false, Conditional::Alw, BACK_16)),
+            I::Callr(LongInstruction::new(false, 31, BACK_16)),
+        ]
+        .iter()
+        {
+            let mut dp = DataPath::new();
+            let mut mem = Memory::from_size(64);
+            dp.set_pc(0x40);
+            run(*next, &mut dp, &mut mem)?;
+            assert_eq_hex!(dp.get_next_pc(), 0x30);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn relative_loads_sign_extend() -> Result<()> {
+        let back = LongInstruction::new(false, 16, BACK_16);
+        for &(load, value) in [
+            (I::Ldrw(back), 0x80818283),
+            (I::Ldrhs(back), 0xffff8081),
+            (I::Ldrhu(back), 0x8081),
+            (I::Ldrbs(back), 0xffffff80),
+            (I::Ldrbu(back), 0x80),
+        ]
+        .iter()
+        {
+            let mut dp = DataPath::new();
+            let mut mem = Memory::from_size(64);
+            mem.set_word(0x30, 0x80818283)?;
+            dp.set_pc(0x40);
+            run(load, &mut dp, &mut mem)?;
+            assert_eq_hex!(dp.register_file().read(16, 0), value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn relative_stores_sign_extend() -> Result<()> {
+        let back = LongInstruction::new(false, 16, BACK_16);
+        for &(store, value) in [
+            (I::Strw(back), 0x11223344),
+            (I::Strh(back), 0x33440000),
+            (I::Strb(back), 0x44000000),
+        ]
+        .iter()
+        {
+            let mut dp = DataPath::new();
+            let mut mem = Memory::from_size(64);
+            dp.set_pc(0x40);
+            dp.get_register_file().write(16, 0x11223344, 0);
+            run(store, &mut dp, &mut mem)?;
+            assert_eq_hex!(mem.get_word(0x30)?, value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn ldxw_negative_displacement() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        mem.set_word(0x30, 0x12345678)?;
+        dp.get_register_file().write(17, 0x40, 0);
+        run(
+            I::Ldxw(SI::new(false, 16, 17, SS::SImm13(0xfffffff0))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(dp.register_file().read(16, 0), 0x12345678);
+        Ok(())
+    }
+
+    #[test]
+    fn jmpx_odd_target_traps() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        run(
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x101))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_alignment_trap(&dp, 0x101);
+        Ok(())
+    }
+
+    #[test]
+    fn callx_odd_target_keeps_window() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        run(
+            I::Callx(SI::new(false, 16, 0, SS::Imm13(0x41))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_alignment_trap(&dp, 0x41);
+        assert_eq!(dp.get_psw().get_cwp(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn gettcr_reads_fault_address() -> Result<()> {
+        let mut dp = DataPath::new();
+        let mut mem = Memory::from_size(64);
+        run(
+            I::Ldxw(SI::new(false, 16, 0, SS::Imm13(0x2a))),
+            &mut dp,
+            &mut mem,
+        )?;
+        // The handler now runs in system mode.
+        run(
+            I::GetTCR(SI::new(false, 17, 0, SS::Reg(0))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_eq_hex!(dp.register_file().read(17, 0), 0x2a);
+        Ok(())
+    }
+
+    #[test]
+    fn gettcr_user_mode_traps() -> Result<()> {
+        let mut dp = user_data_path();
+        let mut mem = Memory::from_size(64);
+        let before = dp.get_psw();
+        run(
+            I::GetTCR(SI::new(false, 16, 0, SS::Reg(0))),
+            &mut dp,
+            &mut mem,
+        )?;
+        assert_privilege_trap(&dp, before);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_registers_fault() -> Result<()> {
+        let mut dp = DataPath::new();
+        dp.set_pc(0x40);
+        let mut mem = Memory::from_size(64);
+        let add = I::Add(SI::new(false, 16, 17, SS::Reg(31)));
+        assert!(execute(&add, &dp, &mut mem).is_ok());
+
+        for bad in [
+            I::Add(SI::new(false, 32, 17, SS::Reg(18))),
+            I::Add(SI::new(false, 16, 40, SS::Reg(18))),
+            I::Or(SI::new(false, 16, 17, SS::Reg(36))),
+            I::Stxw(SI::new(false, 33, 0, SS::Imm13(4))),
+        ]
+        .iter()
+        {
+            match execute(bad, &dp, &mut mem) {
+                Err(EmulatorError::Internal(s)) => assert!(s.contains("at pc 0x00000040"), "{}", s),
+                _ => panic!("{} did not fault", bad),
+            }
+        }
+        Ok(())
+    }
+}
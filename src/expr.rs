@@ -0,0 +1,409 @@
+// Expressions over the state of the data path.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Expressions are C-like, e.g. `pc == 0x2000 && r5 > 100`. Every value is an
+// unsigned 32 bit word and arithmetic wraps. Comparisons and logical
+// operators give 1 for true and 0 for false. Names are:
+//   pc, nxtpc, lstpc     the program counters,
+//   psw, cwp, swp        the PSW and its window pointers,
+//   n, z, v, c           the condition codes,
+//   r0-r31, sp, fp, ra   registers of the current window.
+// Any other name is a symbol of the guest program, standing for its address.
+
+use cpu::register_from_name;
+use data_path::DataPath;
+use std::fmt;
+use symbols::Symbols;
+use util::Result;
+
+use berr;
+
+/// Value of the data path an expression can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Pc,
+    NxtPc,
+    LstPc,
+    Psw,
+    Cwp,
+    Swp,
+    Neg,
+    Zero,
+    Overflow,
+    Carry,
+    Register(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Neg,
+    Not,
+    LogicalNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Xor,
+    Or,
+    LogicalAnd,
+    LogicalOr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Const(u32),
+    Var(Var),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Num(u32),
+    Name(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    /// Text the expression was parsed from.
+    source: String,
+    /// Root of the expression tree.
+    root: Node,
+}
+
+/// Operators, longest first so that `<=` is not read as `<`.
+const OPERATORS: [&str; 20] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "+", "-", "*", "/", "%", "<", ">", "&", "|",
+    "^", "!", "~",
+];
+
+/// Binary operators from loosest to tightest binding.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::LogicalOr)],
+    &[("&&", BinaryOp::LogicalAnd)],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+    ],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+impl Expr {
+    /// Parse an expression. Return the expression on success and a string
+    /// on error.
+    /// # Arguments
+    /// * `source` - Text of the expression.
+    pub fn parse(source: &str) -> Result<Self> {
+        Self::parse_with_symbols(source, &Symbols::new())
+    }
+
+    /// Parse an expression that may name the guest program's symbols.
+    /// Return the expression on success and a string on error.
+    /// # Arguments
+    /// * `source` - Text of the expression.
+    /// * `symbols` - Symbols of the guest program.
+    pub fn parse_with_symbols(source: &str, symbols: &Symbols) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            symbols: symbols,
+        };
+        let root = parser.binary(0)?;
+        if let Some(t) = parser.peek() {
+            return berr!(Format, format!("Unexpected {:?} in expression `{}`", t, source));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root: root,
+        })
+    }
+
+    /// Evaluate the expression against the data path. Return its value on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `dp` - Data path to read values from.
+    pub fn eval(&self, dp: &DataPath) -> Result<u32> {
+        eval_node(&self.root, dp)
+    }
+
+    /// Evaluate the expression as a condition, true if it is not 0.
+    /// # Arguments
+    /// * `dp` - Data path to read values from.
+    pub fn is_true(&self, dp: &DataPath) -> Result<bool> {
+        Ok(self.eval(dp)? != 0)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+// Private functions.
+
+/// Split an expression into tokens.
+/// # Arguments
+/// * `source` - Text of the expression.
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    'outer: while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                tokens.push(Token::Num(parse_number(&word)?));
+            } else {
+                tokens.push(Token::Name(word));
+            }
+        } else {
+            for op in OPERATORS.iter() {
+                let len = op.len();
+                if i + len <= chars.len() && chars[i..i + len].iter().collect::<String>() == *op {
+                    tokens.push(Token::Op(op));
+                    i += len;
+                    continue 'outer;
+                }
+            }
+            return berr!(Format, format!("Unexpected `{}` in expression `{}`", c, source));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parse a decimal, hex (`0x`) or binary (`0b`) number.
+/// # Arguments
+/// * `word` - Text of the number.
+fn parse_number(word: &str) -> Result<u32> {
+    let lower = word.to_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2)
+    } else {
+        lower.parse::<u32>()
+    };
+    match parsed {
+        Ok(v) => Ok(v),
+        Err(_) => berr!(Format, format!("Invalid number `{}`", word)),
+    }
+}
+
+/// Look up a name in an expression.
+/// # Arguments
+/// * `name` - The name, in lower case.
+fn var_from_name(name: &str) -> Result<Var> {
+    Ok(match name {
+        "pc" => Var::Pc,
+        "nxtpc" => Var::NxtPc,
+        "lstpc" => Var::LstPc,
+        "psw" => Var::Psw,
+        "cwp" => Var::Cwp,
+        "swp" => Var::Swp,
+        "n" => Var::Neg,
+        "z" => Var::Zero,
+        "v" => Var::Overflow,
+        "c" => Var::Carry,
+        _ => match register_from_name(name) {
+            Some(reg) => Var::Register(reg),
+            None => return berr!(Format, format!("Unknown name `{}` in expression", name)),
+        },
+    })
+}
+
+/// Recursive descent parser over a token stream.
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Symbols names may stand for.
+    symbols: &'a Symbols,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let result = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        result
+    }
+
+    /// Parse binary operators binding at least as tightly as `level` of
+    /// `PRECEDENCE`. All binary operators are left associative.
+    fn binary(&mut self, level: usize) -> Result<Node> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(s)) => PRECEDENCE[level].iter().find(|(name, _)| name == s),
+                _ => None,
+            };
+            match op {
+                Some(&(_, op)) => {
+                    self.pos += 1;
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+                }
+                None => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node> {
+        match self.next() {
+            Some(Token::Op("-")) => Ok(Node::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+            Some(Token::Op("~")) => Ok(Node::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+            Some(Token::Op("!")) => Ok(Node::Unary(UnaryOp::LogicalNot, Box::new(self.unary()?))),
+            Some(Token::Num(v)) => Ok(Node::Const(v)),
+            Some(Token::Name(name)) => match var_from_name(&name.to_lowercase()) {
+                Ok(var) => Ok(Node::Var(var)),
+                Err(e) => match self.symbols.address(&name) {
+                    Some(address) => Ok(Node::Const(address)),
+                    None => Err(e),
+                },
+            },
+            Some(Token::LParen) => {
+                let inner = self.binary(0)?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => berr!(Format, "Missing `)` in expression"),
+                }
+            }
+            Some(t) => berr!(Format, format!("Unexpected {:?} in expression", t)),
+            None => berr!(Format, "Unexpected end of expression"),
+        }
+    }
+}
+
+/// Evaluate part of an expression.
+/// # Arguments
+/// * `node` - Part to evaluate.
+/// * `dp` - Data path to read values from.
+fn eval_node(node: &Node, dp: &DataPath) -> Result<u32> {
+    Ok(match node {
+        Node::Const(v) => *v,
+        Node::Var(var) => read_var(*var, dp),
+        Node::Unary(op, a) => {
+            let a = eval_node(a, dp)?;
+            match op {
+                UnaryOp::Neg => a.wrapping_neg(),
+                UnaryOp::Not => !a,
+                UnaryOp::LogicalNot => (a == 0) as u32,
+            }
+        }
+        // Short circuit so that e.g. `r1 != 0 && r2 / r1 > 3` is safe.
+        Node::Binary(BinaryOp::LogicalAnd, a, b) => {
+            (eval_node(a, dp)? != 0 && eval_node(b, dp)? != 0) as u32
+        }
+        Node::Binary(BinaryOp::LogicalOr, a, b) => {
+            (eval_node(a, dp)? != 0 || eval_node(b, dp)? != 0) as u32
+        }
+        Node::Binary(op, a, b) => {
+            let a = eval_node(a, dp)?;
+            let b = eval_node(b, dp)?;
+            match op {
+                BinaryOp::Mul => a.wrapping_mul(b),
+                BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                    return berr!(Format, "Division by zero in expression")
+                }
+                BinaryOp::Div => a / b,
+                BinaryOp::Rem => a % b,
+                BinaryOp::Add => a.wrapping_add(b),
+                BinaryOp::Sub => a.wrapping_sub(b),
+                BinaryOp::Shl => a.checked_shl(b).unwrap_or(0),
+                BinaryOp::Shr => a.checked_shr(b).unwrap_or(0),
+                BinaryOp::Lt => (a < b) as u32,
+                BinaryOp::Le => (a <= b) as u32,
+                BinaryOp::Gt => (a > b) as u32,
+                BinaryOp::Ge => (a >= b) as u32,
+                BinaryOp::Eq => (a == b) as u32,
+                BinaryOp::Ne => (a != b) as u32,
+                BinaryOp::And => a & b,
+                BinaryOp::Xor => a ^ b,
+                BinaryOp::Or => a | b,
+                BinaryOp::LogicalAnd | BinaryOp::LogicalOr => unreachable!(),
+            }
+        }
+    })
+}
+
+/// Read a named value from the data path.
+/// # Arguments
+/// * `var` - Value to read.
+/// * `dp` - Data path to read from.
+fn read_var(var: Var, dp: &DataPath) -> u32 {
+    let psw = dp.psw();
+    match var {
+        Var::Pc => dp.pc(),
+        Var::NxtPc => dp.nxtpc(),
+        Var::LstPc => dp.lstpc(),
+        Var::Psw => psw.get() as u32,
+        Var::Cwp => psw.get_cwp() as u32,
+        Var::Swp => psw.get_swp() as u32,
+        Var::Neg => psw.get_cc_neg() as u32,
+        Var::Zero => psw.get_cc_zero() as u32,
+        Var::Overflow => psw.get_cc_overflow() as u32,
+        Var::Carry => psw.get_cc_carry() as u32,
+        Var::Register(reg) => dp.register_file().read(reg, psw.get_cwp()),
+    }
+}
@@ -0,0 +1,93 @@
+// Test code for data path expressions.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "expr.rs"]
+mod test {
+    use super::super::*;
+    use data_path::DataPath;
+    use expr::*;
+    use symbols::Symbols;
+    use util::Result;
+
+    fn eval(source: &str, dp: &DataPath) -> Result<u32> {
+        Expr::parse(source)?.eval(dp)
+    }
+
+    #[test]
+    fn precedence_and_numbers() -> Result<()> {
+        let dp = DataPath::new();
+        assert_eq!(eval("1 + 2 * 3", &dp)?, 7);
+        assert_eq!(eval("(1 + 2) * 3", &dp)?, 9);
+        assert_eq!(eval("0x10 | 0b11 << 1", &dp)?, 0x16);
+        assert_eq!(eval("10 - 3 - 2", &dp)?, 5);
+        assert_eq!(eval("1 < 2 == 1", &dp)?, 1);
+        assert_eq!(eval("0 - 1", &dp)?, 0xffffffff);
+        assert_eq!(eval("-1 == ~0", &dp)?, 1);
+        assert_eq!(eval("!5 || 3 > 2 && 2 >= 3", &dp)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_registers_and_psw() -> Result<()> {
+        let mut dp = DataPath::new();
+        let cwp = dp.psw().get_cwp();
+        dp.get_register_file().write(5, 101, cwp);
+        dp.get_register_file().write(31, 0x40, cwp);
+        assert!(Expr::parse("r5 > 100 && ra == 0x40")?.is_true(&dp)?);
+        assert!(!Expr::parse("R5 > 101")?.is_true(&dp)?);
+        assert_eq!(eval("pc", &dp)?, dp.pc());
+        assert_eq!(eval("cwp", &dp)?, cwp as u32);
+        assert_eq!(eval("z", &dp)?, dp.psw().get_cc_zero() as u32);
+        Ok(())
+    }
+
+    #[test]
+    fn short_circuits_division() -> Result<()> {
+        let dp = DataPath::new();
+        assert_eq!(eval("r1 != 0 && 4 / r1 > 1", &dp)?, 0);
+        assert!(eval("4 / r1", &dp).is_err());
+        assert!(eval("4 % 0", &dp).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        for source in [
+            "", "pc ==", "(pc", "pc)", "r32", "foo", "0xg", "pc $ 1", "1 2",
+        ]
+        .iter()
+        {
+            assert!(Expr::parse(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn names_can_be_symbols() -> Result<()> {
+        let mut symbols = Symbols::new();
+        symbols.insert("main", 0x100);
+        symbols.insert("r5", 0x200);
+        let mut dp = DataPath::new();
+        dp.set_pc(0x104);
+        let expr = Expr::parse_with_symbols("pc == main + 4 && Main == 0", &symbols);
+        assert!(expr.is_err());
+        let expr = Expr::parse_with_symbols("pc == main + 4 && PC > main", &symbols)?;
+        assert_eq!(expr.eval(&dp)?, 1);
+        // Registers come before symbols.
+        assert_eq!(Expr::parse_with_symbols("r5", &symbols)?.eval(&dp)?, 0);
+        assert!(Expr::parse("main").is_err());
+        Ok(())
+    }
+}
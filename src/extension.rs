@@ -0,0 +1,117 @@
+// Instruction set extensions.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Opcodes with their top bit set are left to extensions, so experimental
+// instructions can be tried without touching the core decode tables. The
+// decoder turns any of them into an `Instruction::Extension` in the short
+// format, and both engines hand it to the first extension attached to the
+// core that implements its opcode. The extension computes the result from
+// the values of rs1 and S2 and the CC's it would set, like the ALU does for
+// the core's arithmetic, or refuses with a trap. Opcodes no extension
+// implements raise an illegal instruction trap.
+
+use cpu::Trap;
+use data_path::SCCBits;
+use std::fmt;
+use std::rc::Rc;
+
+/// A set of instructions in the extension opcode space.
+pub trait ExtensionIsa {
+    /// Name of the extension, for diagnostics.
+    fn name(&self) -> &str;
+
+    /// Get the mnemonic of an opcode, None if the extension does not
+    /// implement it.
+    /// # Arguments
+    /// * `op` - The opcode, the instruction's top seven bits.
+    fn decode(&self, op: u8) -> Option<&'static str>;
+
+    /// Execute an instruction of the extension. Return its result and the
+    /// CC's it sets if asked to on success and the trap it raises if not.
+    /// # Arguments
+    /// * `op` - The opcode, one the extension implements.
+    /// * `s1` - Value of rs1.
+    /// * `s2` - Value of the short source.
+    /// * `carry` - The PSW's carry bit.
+    fn execute(&self, op: u8, s1: u32, s2: u32, carry: bool) -> Result<(u32, SCCBits), Trap>;
+}
+
+/// The extensions attached to a core.
+#[derive(Clone)]
+pub struct Extensions {
+    /// Attached extensions, in order of attachment.
+    units: Vec<Rc<dyn ExtensionIsa>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self { units: Vec::new() }
+    }
+
+    /// Attach an extension. Opcodes an extension attached earlier
+    /// implements stay with that one.
+    /// # Arguments
+    /// * `unit` - Extension to attach.
+    pub fn attach(&mut self, unit: Rc<dyn ExtensionIsa>) {
+        self.units.push(unit);
+    }
+
+    /// Get the extension implementing an opcode, if any.
+    /// # Arguments
+    /// * `op` - The opcode.
+    pub fn find(&self, op: u8) -> Option<&dyn ExtensionIsa> {
+        self.units
+            .iter()
+            .find(|unit| unit.decode(op).is_some())
+            .map(|unit| unit.as_ref())
+    }
+
+    /// Execute an extension instruction. Return its result and CC's on
+    /// success and the trap it raises if not, an illegal instruction trap
+    /// if no extension implements the opcode.
+    /// # Arguments
+    /// * `op` - The opcode.
+    /// * `s1` - Value of rs1.
+    /// * `s2` - Value of the short source.
+    /// * `carry` - The PSW's carry bit.
+    pub fn execute(&self, op: u8, s1: u32, s2: u32, carry: bool) -> Result<(u32, SCCBits), Trap> {
+        match self.find(op) {
+            Some(unit) => unit.execute(op, s1, s2, carry),
+            None => Err(Trap::IllegalInstruction),
+        }
+    }
+
+    /// Get the mnemonic of an opcode, if an extension implements it.
+    /// # Arguments
+    /// * `op` - The opcode.
+    pub fn mnemonic(&self, op: u8) -> Option<&'static str> {
+        self.find(op).and_then(|unit| unit.decode(op))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Get the names of the attached extensions, in order of attachment.
+    pub fn names(&self) -> Vec<&str> {
+        self.units.iter().map(|u| u.name()).collect()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Extensions {:?}", self.names())
+    }
+}
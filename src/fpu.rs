@@ -0,0 +1,318 @@
+// Floating point coprocessor.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Single precision add, subtract, multiply, divide and compare, run
+// alongside the CPU. The FPU has sixteen registers of its own, f0 to f15,
+// holding IEEE 754 single precision bit patterns the guest reads and writes
+// through memory, and a status word. Storing a command (see `fpu_command`)
+// starts an operation once the store retires; it takes a few clock cycles,
+// during which the status reads busy and the registers still hold their old
+// values. A command stored while one runs waits for it, and the CPU is held
+// if a third arrives. A compare also sets the CC's when it finishes: Z if
+// equal, N if less and V if unordered, so the guest can branch on it.
+// Exceptions set sticky flags in the status word instead of trapping, only
+// an unknown command raises a coprocessor trap, with the command as cause.
+//
+// The registers are a device, the operations a coprocessor, and both share
+// the FPU's state.
+
+use commit::RetiredInstruction;
+use coprocessor::{Completion, ConditionCodes, Coprocessor, Status};
+use cpu::Trap;
+use device::{Device, Reset};
+use r2d2::Writer;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Base address of the FPU's registers.
+pub const FPU_BASE: u32 = 0xffff00b0;
+/// First of the FPU's data registers, f0. The others follow a word apart.
+pub const FPU_REGISTERS: u32 = FPU_BASE;
+/// Number of data registers.
+pub const FPU_REGISTER_COUNT: usize = 16;
+/// Command register. Storing a command starts an operation.
+pub const FPU_COMMAND: u32 = FPU_BASE + 4 * FPU_REGISTER_COUNT as u32;
+/// Status register, see the `FPU_*` status bits. Writing it sets the
+/// exception flags.
+pub const FPU_STATUS: u32 = FPU_COMMAND + 4;
+/// Size of the FPU's register block.
+const FPU_SIZE: u32 = 4 * FPU_REGISTER_COUNT as u32 + 8;
+
+/// fd := fs1 + fs2.
+pub const FPU_ADD: u32 = 1;
+/// fd := fs1 - fs2.
+pub const FPU_SUB: u32 = 2;
+/// fd := fs1 * fs2.
+pub const FPU_MUL: u32 = 3;
+/// fd := fs1 / fs2.
+pub const FPU_DIV: u32 = 4;
+/// Compare fs1 with fs2, fd is unused.
+pub const FPU_CMP: u32 = 5;
+
+/// Status bit set while an operation runs.
+pub const FPU_BUSY: u32 = 0x1;
+/// Status bit set if the last compare found fs1 < fs2.
+pub const FPU_LESS: u32 = 0x2;
+/// Status bit set if the last compare found fs1 = fs2.
+pub const FPU_EQUAL: u32 = 0x4;
+/// Status bit set if the last compare found fs1 > fs2.
+pub const FPU_GREATER: u32 = 0x8;
+/// Status bit set if the last compare had a NaN operand.
+pub const FPU_UNORDERED: u32 = 0x10;
+/// Exception flag set when an operation without a NaN operand gave NaN.
+pub const FPU_INVALID: u32 = 0x100;
+/// Exception flag set when a finite number other than 0 was divided by 0.
+pub const FPU_DIVIDE_BY_ZERO: u32 = 0x200;
+/// Exception flag set when finite operands gave an infinite result.
+pub const FPU_OVERFLOW: u32 = 0x400;
+/// Exception flag set when a result was too small to be normal.
+pub const FPU_UNDERFLOW: u32 = 0x800;
+/// Status bits of the last compare.
+const FPU_COMPARE_BITS: u32 = FPU_LESS | FPU_EQUAL | FPU_GREATER | FPU_UNORDERED;
+/// Exception flags.
+const FPU_EXCEPTION_BITS: u32 = FPU_INVALID | FPU_DIVIDE_BY_ZERO | FPU_OVERFLOW | FPU_UNDERFLOW;
+
+/// Get the command that runs an operation.
+/// # Arguments
+/// * `op` - The operation, one of the `FPU_*` operations.
+/// * `fd` - Register the result is written to.
+/// * `fs1` - First operand register.
+/// * `fs2` - Second operand register.
+pub fn fpu_command(op: u32, fd: u8, fs1: u8, fs2: u8) -> u32 {
+    (op << 12) | ((fd as u32 & 0xf) << 8) | ((fs1 as u32 & 0xf) << 4) | (fs2 as u32 & 0xf)
+}
+
+/// State shared between the FPU's registers and its operations.
+struct FpuState {
+    /// Data registers, as bit patterns.
+    regs: [u32; FPU_REGISTER_COUNT],
+    /// Compare bits and exception flags, without the busy bit.
+    status: u32,
+    /// Command of the running operation and the cycles it has left.
+    running: Option<(u32, u32)>,
+    /// Command waiting for the running one to finish.
+    queued: Option<u32>,
+}
+
+/// The FPU's operations, attached to the system as a coprocessor.
+pub struct Fpu {
+    state: Rc<RefCell<FpuState>>,
+}
+
+/// The FPU's registers, attached to memory as a device.
+pub struct FpuRegisters {
+    state: Rc<RefCell<FpuState>>,
+}
+
+impl FpuState {
+    fn new() -> Self {
+        Self {
+            regs: [0; FPU_REGISTER_COUNT],
+            status: 0,
+            running: None,
+            queued: None,
+        }
+    }
+
+    /// Carry out a command. Return its completion, None if it has nothing
+    /// to forward.
+    /// # Arguments
+    /// * `command` - The command.
+    fn execute(&mut self, command: u32) -> Option<Completion> {
+        let fd = ((command >> 8) & 0xf) as usize;
+        let a = f32::from_bits(self.regs[((command >> 4) & 0xf) as usize]);
+        let b = f32::from_bits(self.regs[(command & 0xf) as usize]);
+        let mut flags = 0;
+        let result = match command >> 12 {
+            FPU_ADD => a + b,
+            FPU_SUB => a - b,
+            FPU_MUL => a * b,
+            FPU_DIV => {
+                if b == 0.0 && a.is_finite() && a != 0.0 {
+                    flags |= FPU_DIVIDE_BY_ZERO;
+                }
+                a / b
+            }
+            FPU_CMP => return Some(self.compare(a, b)),
+            _ => {
+                return Some(Completion {
+                    trap: Some(Trap::Coprocessor(command)),
+                    ..Completion::default()
+                })
+            }
+        };
+        if result.is_nan() && !a.is_nan() && !b.is_nan() {
+            flags |= FPU_INVALID;
+        }
+        if result.is_infinite() && a.is_finite() && b.is_finite() && flags == 0 {
+            flags |= FPU_OVERFLOW;
+        }
+        if result != 0.0 && result.is_finite() && !result.is_normal() {
+            flags |= FPU_UNDERFLOW;
+        }
+        self.status |= flags;
+        self.regs[fd] = result.to_bits();
+        None
+    }
+
+    /// Compare two values, setting the compare bits. Return the completion
+    /// setting the CC's.
+    /// # Arguments
+    /// * `a` - Value of fs1.
+    /// * `b` - Value of fs2.
+    fn compare(&mut self, a: f32, b: f32) -> Completion {
+        let bit = if a < b {
+            FPU_LESS
+        } else if a > b {
+            FPU_GREATER
+        } else if a == b {
+            FPU_EQUAL
+        } else {
+            FPU_UNORDERED
+        };
+        self.status = (self.status & !FPU_COMPARE_BITS) | bit;
+        Completion {
+            dest: None,
+            cc: Some(ConditionCodes {
+                zero: bit == FPU_EQUAL,
+                neg: bit == FPU_LESS,
+                overflow: bit == FPU_UNORDERED,
+                carry: false,
+            }),
+            trap: None,
+        }
+    }
+}
+
+impl Fpu {
+    /// Create an FPU with every register 0.
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(FpuState::new())),
+        }
+    }
+
+    /// Get the FPU's registers, to attach to memory.
+    pub fn registers(&self) -> FpuRegisters {
+        FpuRegisters {
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// Get the number of clock cycles a command takes.
+/// # Arguments
+/// * `command` - The command.
+fn latency(command: u32) -> u32 {
+    match command >> 12 {
+        FPU_ADD | FPU_SUB => 2,
+        FPU_MUL => 3,
+        FPU_DIV => 10,
+        _ => 1,
+    }
+}
+
+impl Coprocessor for Fpu {
+    fn name(&self) -> &str {
+        "fpu"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        *self.state.borrow_mut() = FpuState::new();
+    }
+
+    fn issue(&mut self, r: &RetiredInstruction) {
+        let command = match r.access {
+            Some(a) if a.store && a.address == FPU_COMMAND => a.value,
+            _ => return,
+        };
+        let mut state = self.state.borrow_mut();
+        if state.running.is_some() {
+            state.queued = Some(command);
+        } else {
+            state.running = Some((command, latency(command)));
+        }
+    }
+
+    fn tick(&mut self) -> Status {
+        let mut state = self.state.borrow_mut();
+        let (command, left) = match state.running {
+            Some(v) => v,
+            None => return Status::Idle,
+        };
+        if left > 1 {
+            state.running = Some((command, left - 1));
+            return if state.queued.is_some() {
+                Status::Stall
+            } else {
+                Status::Busy
+            };
+        }
+        state.running = state.queued.take().map(|c| (c, latency(c)));
+        match state.execute(command) {
+            Some(completion) => Status::Done(completion),
+            None if state.running.is_some() => Status::Busy,
+            None => Status::Idle,
+        }
+    }
+}
+
+impl Device for FpuRegisters {
+    fn name(&self) -> &str {
+        "fpu"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        *self.state.borrow_mut() = FpuState::new();
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(FPU_BASE..FPU_BASE + FPU_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        let state = self.state.borrow();
+        match addr & !0x3 {
+            FPU_STATUS => state.status | if state.running.is_some() { FPU_BUSY } else { 0 },
+            FPU_COMMAND => 0,
+            reg => state.regs[((reg - FPU_REGISTERS) / 4) as usize],
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        let mut state = self.state.borrow_mut();
+        match addr & !0x3 {
+            FPU_STATUS => {
+                state.status = (state.status & !FPU_EXCEPTION_BITS) | (value & FPU_EXCEPTION_BITS)
+            }
+            // The coprocessor starts the command once the store retires.
+            FPU_COMMAND => {}
+            reg => state.regs[((reg - FPU_REGISTERS) / 4) as usize] = value,
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        let state = self.state.borrow();
+        for reg in state.regs.iter() {
+            w.put_u32(*reg);
+        }
+        w.put_u32(state.status);
+        let (command, left) = state.running.unwrap_or((0, 0));
+        w.put_u32(command);
+        w.put_u32(left);
+        w.put_u32(state.queued.unwrap_or(0));
+    }
+}
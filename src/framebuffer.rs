@@ -0,0 +1,158 @@
+// Memory mapped framebuffer.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// The framebuffer is an ordinary region of memory, so guest programs draw by
+// storing to it. Rows are stored top to bottom, each starting on a byte
+// boundary, and multi-byte pixels are big endian like the rest of memory.
+
+use config::{Config, Endian, PixelFormat};
+use memory::Memory;
+use util::Result;
+
+/// Bitmap display backed by a region of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    /// Address of the first pixel.
+    base: u32,
+    /// Width in pixels.
+    width: u32,
+    /// Height in pixels.
+    height: u32,
+    /// Layout of a pixel.
+    format: PixelFormat,
+}
+
+impl Framebuffer {
+    /// Create a framebuffer.
+    /// # Arguments
+    /// * `base` - Address of the first pixel.
+    /// * `width` - Width in pixels.
+    /// * `height` - Height in pixels.
+    /// * `format` - Layout of a pixel.
+    pub fn new(base: u32, width: u32, height: u32, format: PixelFormat) -> Self {
+        Self {
+            base: base,
+            width: width,
+            height: height,
+            format: format,
+        }
+    }
+
+    /// Create the framebuffer the user configured, None if there is none.
+    /// # Arguments
+    /// * `config` - Configuration to read.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.get_fb_base().map(|base| {
+            Self::new(
+                base,
+                config.get_fb_width(),
+                config.get_fb_height(),
+                config.get_fb_format(),
+            )
+        })
+    }
+
+    /// Get the number of bytes a row of pixels takes.
+    pub fn stride(&self) -> u32 {
+        match self.format {
+            PixelFormat::Mono => (self.width + 7) / 8,
+            PixelFormat::Gray8 | PixelFormat::Rgb332 => self.width,
+            PixelFormat::Rgb565 => self.width * 2,
+            PixelFormat::Xrgb8888 => self.width * 4,
+        }
+    }
+
+    /// Get the number of bytes of memory the framebuffer takes.
+    pub fn size(&self) -> u32 {
+        self.stride() * self.height
+    }
+
+    /// Convert the framebuffer to 24 bit RGB pixels, row by row. Return void
+    /// on success and a string if the framebuffer does not fit in memory.
+    /// # Arguments
+    /// * `mem` - Memory holding the framebuffer.
+    /// * `out` - Buffer to replace with the pixels.
+    pub fn render(&self, mem: &Memory, out: &mut Vec<u8>) -> Result<()> {
+        let bytes = mem.get_bytes(self.base, self.size())?;
+        out.clear();
+        out.reserve((self.width * self.height * 3) as usize);
+        for row in bytes.chunks(self.stride().max(1) as usize) {
+            for x in 0..self.width as usize {
+                out.extend_from_slice(&self.pixel(row, x, mem.endian()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Get the RGB color of a pixel.
+    /// # Arguments
+    /// * `row` - Bytes of the pixel's row.
+    /// * `x` - Column of the pixel.
+    /// * `endian` - Byte order of pixels wider than a byte.
+    fn pixel(&self, row: &[u8], x: usize, endian: Endian) -> [u8; 3] {
+        match self.format {
+            PixelFormat::Mono => {
+                // Most significant bit first, set bits are lit.
+                let v = if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    0xff
+                } else {
+                    0
+                };
+                [v, v, v]
+            }
+            PixelFormat::Gray8 => [row[x], row[x], row[x]],
+            PixelFormat::Rgb332 => {
+                let v = row[x];
+                [scale(v >> 5, 3), scale(v >> 2, 3), scale(v, 2)]
+            }
+            PixelFormat::Rgb565 => {
+                let v = endian.hword([row[x * 2], row[x * 2 + 1]]);
+                [
+                    scale((v >> 11) as u8, 5),
+                    scale((v >> 5) as u8, 6),
+                    scale(v as u8, 5),
+                ]
+            }
+            PixelFormat::Xrgb8888 => {
+                let v = endian.word([row[x * 4], row[x * 4 + 1], row[x * 4 + 2], row[x * 4 + 3]]);
+                [(v >> 16) as u8, (v >> 8) as u8, v as u8]
+            }
+        }
+    }
+}
+
+// Private functions.
+
+/// Scale the low `bits` bits of `v` to the range of a byte.
+fn scale(v: u8, bits: u32) -> u8 {
+    let max = (1u32 << bits) - 1;
+    ((v as u32 & max) * 0xff / max) as u8
+}
@@ -0,0 +1,62 @@
+// Test code for the framebuffer.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "framebuffer.rs"]
+mod test {
+    use super::super::*;
+    use config::PixelFormat;
+    use framebuffer::*;
+    use memory::Memory;
+    use util::Result;
+
+    #[test]
+    fn renders_each_pixel_format() -> Result<()> {
+        let mut mem = Memory::from_size(0x40);
+        mem.set_word(0x10, 0xa0e01cff)?;
+        mem.set_word(0x14, 0xf800001f)?;
+        mem.set_word(0x18, 0x00123456)?;
+        let mut out = Vec::new();
+
+        let mono = Framebuffer::new(0x10, 3, 2, PixelFormat::Mono);
+        assert_eq!(mono.size(), 2);
+        mono.render(&mem, &mut out)?;
+        assert_eq!(
+            out,
+            vec![
+                0xff, 0xff, 0xff, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+                0xff, 0xff, 0xff
+            ]
+        );
+
+        Framebuffer::new(0x12, 2, 1, PixelFormat::Gray8).render(&mem, &mut out)?;
+        assert_eq!(out, vec![0x1c, 0x1c, 0x1c, 0xff, 0xff, 0xff]);
+
+        Framebuffer::new(0x10, 2, 1, PixelFormat::Rgb332).render(&mem, &mut out)?;
+        assert_eq!(out, vec![0xb6, 0x00, 0x00, 0xff, 0x00, 0x00]);
+
+        Framebuffer::new(0x14, 2, 1, PixelFormat::Rgb565).render(&mem, &mut out)?;
+        assert_eq!(out, vec![0xff, 0, 0, 0, 0, 0xff]);
+
+        Framebuffer::new(0x18, 1, 1, PixelFormat::Xrgb8888).render(&mem, &mut out)?;
+        assert_eq!(out, vec![0x12, 0x34, 0x56]);
+
+        // Does not fit in memory.
+        assert!(Framebuffer::new(0x30, 8, 8, PixelFormat::Gray8)
+            .render(&mem, &mut out)
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+// What the main window's front panel shows.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// The panel of lights and dials a minicomputer had, for systems without a
+// framebuffer. It is taken from the boot core once a frame: the PC as a
+// row of lights, the instruction being executed, the PSW flags as LEDs, the
+// window pointers as dials around the register file's windows and the
+// number of clock cycles run so far. Memory is read without going through
+// the devices, so drawing the panel never has side effects.
+
+use decode::decode;
+use std::f64::consts::PI;
+use system::{RunState, System};
+
+/// Names of the PSW flags lit on the panel, from most to least
+/// significant.
+pub const PSW_LED_NAMES: [&str; 7] = ["I", "S", "P", "Z", "N", "V", "C"];
+
+/// What the front panel shows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontPanel {
+    /// Address of the instruction being executed.
+    pub pc: u32,
+    /// The instruction being executed, disassembled, None if the word at
+    /// the PC is not an instruction or is outside of memory.
+    pub instruction: Option<String>,
+    /// PSW flags, in the order of `PSW_LED_NAMES`.
+    pub psw: [bool; 7],
+    /// Current window pointer.
+    pub cwp: u8,
+    /// Saved window pointer.
+    pub swp: u8,
+    /// Number of register windows.
+    pub windows: u8,
+    /// Clock cycles run so far.
+    pub cycles: u64,
+    /// Whether the system runs.
+    pub state: RunState,
+}
+
+impl FrontPanel {
+    /// Take what the front panel shows from a system's boot core.
+    /// # Arguments
+    /// * `system` - The system.
+    pub fn from_system(system: &System) -> Self {
+        let dp = system.data_path();
+        let psw = dp.get_psw();
+        let pc = dp.get_pc();
+        // A pipelined engine knows what it executes, the others execute
+        // the word at the PC.
+        let instruction = match system.cpu().occupancy().execute {
+            Some(i) => Some(i),
+            None => system
+                .memory()
+                .get_word(pc)
+                .ok()
+                .and_then(|word| decode(word).ok()),
+        };
+        Self {
+            pc: pc,
+            instruction: instruction.map(|i| format!("{}", i.disassemble(system.syntax()))),
+            psw: [
+                psw.get_interrupt_enabled(),
+                psw.get_system_mode(),
+                psw.get_previous_system_mode(),
+                psw.get_cc_zero(),
+                psw.get_cc_neg(),
+                psw.get_cc_overflow(),
+                psw.get_cc_carry(),
+            ],
+            cwp: psw.get_cwp(),
+            swp: psw.get_swp(),
+            windows: dp.register_file().windows(),
+            cycles: system.clock().count(),
+            state: system.run_state(),
+        }
+    }
+
+    /// Get the lights of the PC, most significant bit first.
+    pub fn pc_lights(&self) -> [bool; 32] {
+        let mut result = [false; 32];
+        for (i, light) in result.iter_mut().enumerate() {
+            *light = self.pc & (1 << (31 - i)) != 0;
+        }
+        result
+    }
+
+    /// Get the angle a window pointer's dial points at, in radians
+    /// clockwise from twelve o'clock. Every window has its own position.
+    /// # Arguments
+    /// * `pointer` - The window pointer.
+    pub fn dial_angle(&self, pointer: u8) -> f64 {
+        2.0 * PI * (pointer % self.windows.max(1)) as f64 / self.windows.max(1) as f64
+    }
+}
@@ -0,0 +1,189 @@
+// Randomized round-trip tests for the RISC II encoder and decoder.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "decode.rs"]
+mod test {
+    use super::super::*;
+    use util::Result;
+
+    use decode::*;
+    use instruction::*;
+
+    type I = Instruction;
+    type C = Conditional;
+    type SS = ShortSource;
+
+    /// Number of values each test tries.
+    const ITERATIONS: usize = 100_000;
+
+    const CONDITIONALS: [Conditional; 15] = [
+        C::Gt,
+        C::Le,
+        C::Ge,
+        C::Lt,
+        C::Hi,
+        C::Los,
+        C::Lonc,
+        C::Hisc,
+        C::Pl,
+        C::Mi,
+        C::Ne,
+        C::Eq,
+        C::Nv,
+        C::V,
+        C::Alw,
+    ];
+
+    const SHORT: [fn(ShortInstruction) -> Instruction; 26] = [
+        I::Calli,
+        I::GetPSW,
+        I::GetLPC,
+        I::GetTCR,
+        I::PutPSW,
+        I::Callx,
+        I::Sll,
+        I::Srl,
+        I::Sra,
+        I::Or,
+        I::And,
+        I::Xor,
+        I::Add,
+        I::Addc,
+        I::Sub,
+        I::Subc,
+        I::Subi,
+        I::Subci,
+        I::Ldxw,
+        I::Ldxhs,
+        I::Ldxhu,
+        I::Ldxbs,
+        I::Ldxbu,
+        I::Stxw,
+        I::Stxh,
+        I::Stxb,
+    ];
+
+    const LONG: [fn(LongInstruction) -> Instruction; 10] = [
+        I::Callr,
+        I::Ldhi,
+        I::Ldrw,
+        I::Ldrhs,
+        I::Ldrhu,
+        I::Ldrbs,
+        I::Ldrbu,
+        I::Strw,
+        I::Strh,
+        I::Strb,
+    ];
+
+    const SHORT_COND: [fn(ShortConditional) -> Instruction; 3] = [I::Jmpx, I::Ret, I::Reti];
+
+    /// Xorshift generator, so every run tries the same values and failures
+    /// can be reproduced.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+
+        /// Get a random number below `n`.
+        fn below(&mut self, n: usize) -> usize {
+            self.next() as usize % n
+        }
+
+        fn bool(&mut self) -> bool {
+            self.next() & 1 != 0
+        }
+
+        fn reg(&mut self) -> u8 {
+            self.below(32) as u8
+        }
+
+        fn short_source(&mut self) -> ShortSource {
+            if self.bool() {
+                SS::Imm13(self.next() & 0x1fff)
+            } else {
+                SS::Reg(self.reg())
+            }
+        }
+
+        fn conditional(&mut self) -> Conditional {
+            CONDITIONALS[self.below(CONDITIONALS.len())]
+        }
+
+        /// Get a random valid instruction.
+        fn instruction(&mut self) -> Instruction {
+            let scc = self.bool();
+            match self.below(SHORT.len() + LONG.len() + SHORT_COND.len() + 1) {
+                i if i < SHORT.len() => SHORT[i](ShortInstruction::new(
+                    scc,
+                    self.reg(),
+                    self.reg(),
+                    self.short_source(),
+                )),
+                i if i < SHORT.len() + LONG.len() => LONG[i - SHORT.len()](LongInstruction::new(
+                    scc,
+                    self.reg(),
+                    self.next() & 0x7ffff,
+                )),
+                i if i < SHORT.len() + LONG.len() + SHORT_COND.len() => SHORT_COND
+                    [i - SHORT.len() - LONG.len()](
+                    ShortConditional::new(scc, self.conditional(), self.reg(), self.short_source()),
+                ),
+                _ => I::Jmpr(LongConditional::new(
+                    scc,
+                    self.conditional(),
+                    self.next() & 0x7ffff,
+                )),
+            }
+        }
+    }
+
+    #[test]
+    fn encode_decode_round_trip() -> Result<()> {
+        let mut rng = Rng(0x2545f491);
+        for _ in 0..ITERATIONS {
+            let i = rng.instruction().with_immediate_sign();
+            assert_eq!(decode(i.encode())?, i, "{:08x}", i.encode());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decode_random_words() {
+        let mut rng = Rng(0x9e3779b9);
+        for _ in 0..ITERATIONS {
+            let word = rng.next();
+            match decode(word) {
+                // Bits the instruction ignores may be lost, but what was
+                // decoded must survive another round trip.
+                Ok(i) => assert_eq!(decode(i.encode()).ok(), Some(i), "{:08x}", word),
+                Err(e) => assert!(
+                    match e {
+                        EmulatorError::Decode(_) => true,
+                        _ => false,
+                    },
+                    "{:08x}",
+                    word
+                ),
+            }
+        }
+    }
+}
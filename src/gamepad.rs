@@ -0,0 +1,207 @@
+// Memory mapped gamepad controller.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Unlike the keyboard the gamepad does not queue events, the guest polls the
+// current state of the buttons and axes. Button `n` is bit `n` of the buttons
+// register and axis `n` is the register at `GAMEPAD_AXES + n * 4`, holding a
+// signed value from -32768 to 32767 (0 to 32767 for the triggers). Buttons
+// and axes are numbered like SDL's game controller ones. `GAMEPAD_CHANGED` is
+// set whenever the state changes. The host side feeds the state through a
+// `GamepadInput`.
+
+use device::{Device, Reset};
+use r2d2::Writer;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Base address of the gamepad's registers.
+pub const GAMEPAD_BASE: u32 = 0xffff0040;
+/// Buttons register, bit `n` is set while button `n` is held.
+pub const GAMEPAD_BUTTONS: u32 = GAMEPAD_BASE;
+/// Status register, see the `GAMEPAD_*` status bits.
+pub const GAMEPAD_STATUS: u32 = GAMEPAD_BASE + 4;
+/// Control register. Bit 0 enables the interrupt.
+pub const GAMEPAD_CONTROL: u32 = GAMEPAD_BASE + 8;
+/// First axis register.
+pub const GAMEPAD_AXES: u32 = GAMEPAD_BASE + 12;
+/// Number of axes.
+pub const GAMEPAD_NUM_AXES: usize = 6;
+/// Number of buttons.
+pub const GAMEPAD_NUM_BUTTONS: u32 = 32;
+/// Size of the gamepad's register block.
+const GAMEPAD_SIZE: u32 = 12 + GAMEPAD_NUM_AXES as u32 * 4;
+/// Status bit set while a gamepad is connected.
+pub const GAMEPAD_CONNECTED: u32 = 0x1;
+/// Status bit set when the state changed since the status was last read.
+/// Cleared by reading the status register.
+pub const GAMEPAD_CHANGED: u32 = 0x2;
+/// Control bit that raises an interrupt while the state has changed.
+pub const GAMEPAD_INTERRUPT_ENABLE: u32 = 0x1;
+
+/// State shared between the device and its input handles.
+struct GamepadState {
+    /// True if a gamepad is connected.
+    connected: bool,
+    /// Buttons held, one bit each.
+    buttons: u32,
+    /// Position of each axis.
+    axes: [i16; GAMEPAD_NUM_AXES],
+    /// True if the state changed since the status was last read.
+    changed: bool,
+    /// Contents of the control register.
+    control: u32,
+}
+
+/// Gamepad controller guest programs poll buttons and axes from.
+pub struct Gamepad {
+    state: Rc<RefCell<GamepadState>>,
+}
+
+/// Handle the host uses to update the state of a `Gamepad`.
+#[derive(Clone)]
+pub struct GamepadInput {
+    state: Rc<RefCell<GamepadState>>,
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(GamepadState {
+                connected: false,
+                buttons: 0,
+                axes: [0; GAMEPAD_NUM_AXES],
+                changed: false,
+                control: 0,
+            })),
+        }
+    }
+
+    /// Get a handle that updates the state of this gamepad.
+    pub fn input(&self) -> GamepadInput {
+        GamepadInput {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl GamepadInput {
+    /// Connect or disconnect the gamepad. Disconnecting releases every
+    /// button and centers every axis.
+    /// # Arguments
+    /// * `connected` - True if a gamepad is connected.
+    pub fn set_connected(&self, connected: bool) {
+        let mut state = self.state.borrow_mut();
+        if !connected {
+            state.buttons = 0;
+            state.axes = [0; GAMEPAD_NUM_AXES];
+        }
+        state.connected = connected;
+        state.changed = true;
+    }
+
+    /// Press or release a button. Buttons past the last one are ignored.
+    /// # Arguments
+    /// * `button` - Number of the button.
+    /// * `pressed` - True if the button is held.
+    pub fn set_button(&self, button: u32, pressed: bool) {
+        if button >= GAMEPAD_NUM_BUTTONS {
+            return;
+        }
+        let mut state = self.state.borrow_mut();
+        if pressed {
+            state.buttons |= 1 << button;
+        } else {
+            state.buttons &= !(1 << button);
+        }
+        state.changed = true;
+    }
+
+    /// Move an axis. Axes past the last one are ignored.
+    /// # Arguments
+    /// * `axis` - Number of the axis.
+    /// * `value` - New position of the axis.
+    pub fn set_axis(&self, axis: u32, value: i16) {
+        let mut state = self.state.borrow_mut();
+        if let Some(v) = state.axes.get_mut(axis as usize) {
+            *v = value;
+            state.changed = true;
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.borrow().connected
+    }
+}
+
+impl Device for Gamepad {
+    fn name(&self) -> &str {
+        "gamepad"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        // The host's gamepad stays as it is, only the guest's view resets.
+        let mut state = self.state.borrow_mut();
+        state.changed = false;
+        state.control = 0;
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(GAMEPAD_BASE..GAMEPAD_BASE + GAMEPAD_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        let mut state = self.state.borrow_mut();
+        match addr & !0x3 {
+            GAMEPAD_BUTTONS => state.buttons,
+            GAMEPAD_STATUS => {
+                let mut status = 0;
+                if state.connected {
+                    status |= GAMEPAD_CONNECTED;
+                }
+                if state.changed {
+                    status |= GAMEPAD_CHANGED;
+                    state.changed = false;
+                }
+                status
+            }
+            GAMEPAD_CONTROL => state.control,
+            a if a >= GAMEPAD_AXES => state.axes[((a - GAMEPAD_AXES) / 4) as usize] as i32 as u32,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        if addr & !0x3 == GAMEPAD_CONTROL {
+            self.state.borrow_mut().control = value & GAMEPAD_INTERRUPT_ENABLE;
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        let state = self.state.borrow();
+        w.put_bool(state.connected);
+        w.put_u32(state.buttons);
+        for axis in state.axes.iter() {
+            w.put_u16(*axis as u16);
+        }
+        w.put_bool(state.changed);
+        w.put_u32(state.control);
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        let state = self.state.borrow();
+        state.control & GAMEPAD_INTERRUPT_ENABLE != 0 && state.changed
+    }
+}
@@ -0,0 +1,306 @@
+// Guest test program runner.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Runs guest programs that check themselves, so real instruction sequences
+// can be regression tested. A test program is assembly source (`.s`),
+// assembled at 0, or a memory image in any format the loader reads. It runs
+// headless, without waiting on the clock, and reports its result by writing
+// the host's exit register (`HOST_EXIT`): 0 if it passed, anything else (by
+// convention the number of the check that failed) if not. A program that
+// halts by branching to itself without writing it, or is still running when
+// its cycle budget runs out, did not pass either.
+// A program that does not pass can leave a failure bundle behind, a
+// directory named for the program and engine holding what is needed to
+// look into it without running it again:
+//   snapshot.r2d2 - Snapshot of the system as it ended.
+//   trace.txt - The last instructions the boot core retired.
+//   stats.txt - How the program ended, the cycles it ran and the
+//     instructions it retired.
+//   config.txt - Configuration of the system.
+//   image.txt - Hash of the program's image.
+// `riscii test` runs programs from the command line and writes their
+// bundles the same way.
+
+use asm::assemble;
+use commit::RetiredInstruction;
+use config::{Config, Endian, Engine};
+use error::EmulatorError;
+use loader::{Format, Image};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::hash::Hasher;
+use std::path::Path;
+use system::System;
+use trace::text_record;
+use util::{concat_paths, Result};
+
+use berr;
+
+/// Clock cycles a test program may run by default.
+pub const DEFAULT_BUDGET: u64 = 1_000_000;
+/// Instructions at the end of the trace kept for a failure bundle.
+pub const TRACE_TAIL: usize = 64;
+
+/// How a test program ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Exited with code 0.
+    Passed,
+    /// Exited with another code.
+    Failed(u32),
+    /// Branched to itself, at the address held, without exiting.
+    Stopped(u32),
+    /// Ran out of clock cycles.
+    TimedOut,
+}
+
+/// A guest program that checks itself.
+#[derive(Debug, Clone)]
+pub struct TestProgram {
+    /// Path of the program's file.
+    pub path: String,
+    /// The program, ready to load.
+    image: Image,
+}
+
+/// A finished run of a test program.
+struct Run {
+    /// The system the program ran on, as it ended.
+    system: System,
+    /// How the program ended.
+    outcome: Outcome,
+    /// The last `TRACE_TAIL` instructions the boot core retired, oldest
+    /// first.
+    tail: VecDeque<RetiredInstruction>,
+    /// Instructions the boot core retired.
+    retired: u64,
+}
+
+impl TestProgram {
+    /// Read a test program, assembling it if it is source. Return the
+    /// program on success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the program's file.
+    pub fn read(path: &str) -> Result<Self> {
+        let image = if path.ends_with(".s") {
+            let source = match fs::read_to_string(path) {
+                Ok(s) => s,
+                Err(e) => return Err(EmulatorError::io(format!("Could not read {}", path), e)),
+            };
+            let program = match assemble(&source, 0) {
+                Ok(p) => p,
+                Err(e) => return berr!(Asm, format!("{}: {}", path, e)),
+            };
+            Image::parse(&program.to_bytes(Endian::Big), Format::Raw, 0)?
+        } else {
+            Image::read(path, 0)?
+        };
+        Ok(Self {
+            path: path.to_string(),
+            image: image,
+        })
+    }
+
+    /// Run the program on a fresh system. Return how it ended on success
+    /// and a string if the system failed.
+    /// # Arguments
+    /// * `config` - Configuration of the system.
+    /// * `engine` - Execution engine to run on.
+    /// * `budget` - Most clock cycles to run.
+    pub fn run(&self, config: &Config, engine: Engine, budget: u64) -> Result<Outcome> {
+        Ok(self.execute(config, engine, budget)?.outcome)
+    }
+
+    /// Run the program on a fresh system and, if it does not pass, write a
+    /// failure bundle for it, replacing any bundle of an earlier run. Return
+    /// how it ended and the path of the bundle, if one was written, on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `config` - Configuration of the system.
+    /// * `engine` - Execution engine to run on.
+    /// * `budget` - Most clock cycles to run.
+    /// * `bundles` - Directory the bundle's directory goes in.
+    pub fn run_with_bundle(
+        &self,
+        config: &Config,
+        engine: Engine,
+        budget: u64,
+        bundles: &str,
+    ) -> Result<(Outcome, Option<String>)> {
+        let run = self.execute(config, engine, budget)?;
+        if run.outcome == Outcome::Passed {
+            return Ok((Outcome::Passed, None));
+        }
+        let dir = concat_paths(&bundles.to_string(), &self.bundle_name(engine))?;
+        if Path::new(&dir).exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        self.write_bundle(&dir, config, engine, &run)?;
+        Ok((run.outcome, Some(dir)))
+    }
+
+    /// Get the name of the program's failure bundle on an engine, the name
+    /// of its file without the extension and the engine, e.g.
+    /// `arith-cycle`.
+    /// # Arguments
+    /// * `engine` - Execution engine the program ran on.
+    pub fn bundle_name(&self, engine: Engine) -> String {
+        let stem = Path::new(&self.path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("program");
+        format!("{}-{}", stem, engine)
+    }
+
+    /// Get a hash of the program's image, of the address and bytes of each
+    /// of its chunks and its entry point.
+    pub fn image_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for chunk in self.image.chunks.iter() {
+            hasher.write_u32(chunk.address);
+            hasher.write(&chunk.data);
+        }
+        if let Some(pc) = self.image.entry {
+            hasher.write_u32(pc);
+        }
+        hasher.finish()
+    }
+
+    /// Run the program on a fresh system, keeping the end of its trace.
+    /// # Arguments
+    /// * `config` - Configuration of the system.
+    /// * `engine` - Execution engine to run on.
+    /// * `budget` - Most clock cycles to run.
+    fn execute(&self, config: &Config, engine: Engine, budget: u64) -> Result<Run> {
+        let mut system = System::new(config)?;
+        system.set_engine(engine);
+        system.load_image(&self.image)?;
+        if let Some(pc) = self.image.entry {
+            system.set_entry(pc);
+        }
+        let mut tail = VecDeque::with_capacity(TRACE_TAIL);
+        let mut retired = 0;
+        let end = system.clock().count() + budget;
+        let mut outcome = Outcome::TimedOut;
+        while system.clock().count() < end {
+            match system.exit_code() {
+                Some(0) => outcome = Outcome::Passed,
+                Some(code) => outcome = Outcome::Failed(code),
+                None => {}
+            }
+            if outcome != Outcome::TimedOut {
+                break;
+            }
+            if let Some(r) = system.run_until_retired()? {
+                retired += 1;
+                let stopped = r.branch == Some(r.pc);
+                let pc = r.pc;
+                if tail.len() == TRACE_TAIL {
+                    tail.pop_front();
+                }
+                tail.push_back(r);
+                if stopped {
+                    outcome = Outcome::Stopped(pc);
+                    break;
+                }
+            }
+        }
+        Ok(Run {
+            system: system,
+            outcome: outcome,
+            tail: tail,
+            retired: retired,
+        })
+    }
+
+    /// Write the files of a failure bundle.
+    /// # Arguments
+    /// * `dir` - Directory of the bundle, which exists.
+    /// * `config` - Configuration of the system.
+    /// * `engine` - Execution engine the program ran on.
+    /// * `run` - The run that did not pass.
+    fn write_bundle(&self, dir: &String, config: &Config, engine: Engine, run: &Run) -> Result<()> {
+        let path = |name: &str| concat_paths(dir, &name.to_string());
+        run.system.save_snapshot(&path("snapshot.r2d2")?)?;
+
+        let mut trace = String::new();
+        for r in run.tail.iter() {
+            trace.push_str(&text_record(r, run.system.syntax()));
+            trace.push('\n');
+        }
+        fs::write(path("trace.txt")?, trace)?;
+
+        let mut stats = format!(
+            "Program: {}\nEngine: {}\nOutcome: {}\nCycles: {}\nInstructions retired: {}\n",
+            self.path,
+            engine,
+            run.outcome,
+            run.system.clock().count(),
+            run.retired
+        );
+        if let Some(cache) = run.system.decode_cache_stats() {
+            stats.push_str(&cache.to_string());
+        }
+        fs::write(path("stats.txt")?, stats)?;
+
+        fs::write(path("config.txt")?, config.to_string())?;
+        fs::write(
+            path("image.txt")?,
+            format!("{:016x} {}\n", self.image_hash(), self.path),
+        )?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Passed => write!(f, "passed"),
+            Self::Failed(code) => write!(f, "failed check {}", code),
+            Self::Stopped(pc) => write!(f, "stopped at 0x{:08x} without exiting", pc),
+            Self::TimedOut => write!(f, "ran out of cycles"),
+        }
+    }
+}
+
+/// Read every test program in a directory, by path. Files starting with `.`
+/// are left out. Return the programs on success and a string on error.
+/// # Arguments
+/// * `dir` - Path of the directory.
+pub fn read_dir(dir: &str) -> Result<Vec<TestProgram>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => return Err(EmulatorError::io(format!("Could not read {}", dir), e)),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map_or(true, |n| n.starts_with('.'));
+        if path.is_file() && !hidden {
+            match path.to_str() {
+                Some(p) => paths.push(p.to_string()),
+                None => return berr!(Config, format!("{:?} is not valid utf8", path)),
+            }
+        }
+    }
+    paths.sort();
+    paths.iter().map(|p| TestProgram::read(p)).collect()
+}
@@ -0,0 +1,194 @@
+// Pipeline hazard detection.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Looks for pairs of instructions the RISC II pipeline does not allow one
+// right after the other:
+//   Load use - the instruction after a load reads the register it loads,
+//     which is not written until the data cycle is over. A NOP goes in
+//     between.
+//   After PUTPSW - the instruction after PUTPSW changes the window pointers
+//     or condition codes before the new PSW is in effect, which raises an
+//     illegal instruction trap.
+//   Transfer in a delay slot - a jump, call or return sits in the delay slot
+//     of another.
+// The pairs are checked either in a memory image, instruction by
+// instruction in address order (`disasm --hazards`), or in the retired
+// instruction stream of a running system (`--detect_hazards`), which
+// follows the branches taken. A running system reports each instruction
+// once.
+
+use commit::RetiredInstruction;
+use config::Endian;
+use decode::decode;
+use instruction::Instruction;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Kinds of pipeline hazards.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HazardKind {
+    /// The instruction reads this register, loaded by the instruction
+    /// before it.
+    LoadUse(u8),
+    /// The instruction changes the window pointers or condition codes right
+    /// after `PUTPSW`.
+    AfterPutPsw,
+    /// The instruction transfers control from the delay slot of another.
+    TransferInDelaySlot,
+}
+
+/// An instruction that breaks a pipeline rule.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Hazard {
+    /// What rule it breaks.
+    pub kind: HazardKind,
+    /// Address of the instruction.
+    pub pc: u32,
+    /// The instruction.
+    pub instruction: Instruction,
+    /// The instruction before it.
+    pub previous: Instruction,
+}
+
+/// Looks for hazards in the retired instruction streams of the cores.
+pub struct HazardDetector {
+    /// Last instruction each core retired without trapping, by core.
+    last: HashMap<usize, Instruction>,
+    /// Addresses of the instructions already reported.
+    reported: HashSet<u32>,
+    /// Hazards found so far, oldest first.
+    found: Vec<Hazard>,
+}
+
+impl HazardDetector {
+    /// Create a detector.
+    pub fn new() -> Self {
+        Self {
+            last: HashMap::new(),
+            reported: HashSet::new(),
+            found: Vec::new(),
+        }
+    }
+
+    /// Check a retired instruction. Return the hazard it makes with the
+    /// instruction the core retired before it, None if there is none or it
+    /// was reported already.
+    /// # Arguments
+    /// * `core` - Number of the core the instruction retired on.
+    /// * `r` - The retired instruction.
+    pub fn observe(&mut self, core: usize, r: &RetiredInstruction) -> Option<Hazard> {
+        // A trap starts a new stream at the handler.
+        let previous = if r.trap.is_some() {
+            self.last.remove(&core)
+        } else {
+            self.last.insert(core, r.instruction)
+        }?;
+        let kind = check(&previous, &r.instruction)?;
+        if !self.reported.insert(r.pc) {
+            return None;
+        }
+        let hazard = Hazard {
+            kind: kind,
+            pc: r.pc,
+            instruction: r.instruction,
+            previous: previous,
+        };
+        self.found.push(hazard);
+        Some(hazard)
+    }
+
+    /// Get the hazards found so far, oldest first.
+    pub fn found(&self) -> &[Hazard] {
+        &self.found
+    }
+}
+
+impl fmt::Display for HazardKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::LoadUse(reg) => write!(f, "reads r{} loaded by the instruction before", reg),
+            Self::AfterPutPsw => write!(f, "changes CWP, SWP or the flags right after putpsw"),
+            Self::TransferInDelaySlot => write!(f, "transfers control from a delay slot"),
+        }
+    }
+}
+
+impl fmt::Display for Hazard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Hazard at pc 0x{:08x}: {}\n  {}\n  after {}",
+            self.pc, self.kind, self.instruction, self.previous
+        )
+    }
+}
+
+// Public functions.
+
+/// Check whether an instruction may run right after another. Return the
+/// hazard if it may not.
+/// # Arguments
+/// * `previous` - The instruction before.
+/// * `next` - The instruction after.
+pub fn check(previous: &Instruction, next: &Instruction) -> Option<HazardKind> {
+    if previous.is_transfer() && next.is_transfer() {
+        return Some(HazardKind::TransferInDelaySlot);
+    }
+    if let Instruction::PutPSW(_) = *previous {
+        if next.conflicts_with_putpsw() {
+            return Some(HazardKind::AfterPutPsw);
+        }
+    }
+    if previous.is_load() {
+        match previous.dest_reg() {
+            // r0 is always 0.
+            Some(0) | None => {}
+            Some(reg) => {
+                if next.source_regs().contains(&reg) {
+                    return Some(HazardKind::LoadUse(reg));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Check every pair of neighbouring instructions in a memory image. Words
+/// that are not instructions end a pair. Return the hazards, lowest address
+/// first.
+/// # Arguments
+/// * `image` - The memory image. A trailing partial word is ignored.
+/// * `origin` - Address the image is loaded at.
+/// * `endian` - Byte order of the image's words.
+pub fn find_hazards(image: &[u8], origin: u32, endian: Endian) -> Vec<Hazard> {
+    let mut result = Vec::new();
+    let mut previous: Option<Instruction> = None;
+    for (i, bytes) in image.chunks_exact(4).enumerate() {
+        let word = endian.word([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let instruction = decode(word).ok();
+        if let (Some(p), Some(n)) = (previous, instruction) {
+            if let Some(kind) = check(&p, &n) {
+                result.push(Hazard {
+                    kind: kind,
+                    pc: origin.wrapping_add(i as u32 * 4),
+                    instruction: n,
+                    previous: p,
+                });
+            }
+        }
+        previous = instruction;
+    }
+    result
+}
@@ -0,0 +1,95 @@
+// Test code for pipeline hazard detection.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "hazard.rs"]
+mod test {
+    use super::super::*;
+    use config::Endian;
+    use hazard::*;
+    use instruction::{
+        Conditional, Instruction as I, LongInstruction as LI, ShortConditional as SC,
+        ShortInstruction as SI, ShortSource as SS,
+    };
+
+    const NOP: I = I::Add(SI {
+        scc: false,
+        dest: 0,
+        rs1: 0,
+        short_source: SS::Imm13(0),
+    });
+
+    #[test]
+    fn pairs_break_pipeline_rules() {
+        let load = I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x30)));
+        let use_r17 = I::Add(SI::new(false, 18, 17, SS::Imm13(1)));
+        assert_eq!(check(&load, &use_r17), Some(HazardKind::LoadUse(17)));
+        assert_eq!(check(&load, &NOP), None);
+        assert_eq!(
+            check(&load, &I::Stxw(SI::new(false, 17, 0, SS::Imm13(4)))),
+            Some(HazardKind::LoadUse(17))
+        );
+        assert_eq!(
+            check(&load, &I::Sub(SI::new(false, 18, 16, SS::Reg(17)))),
+            Some(HazardKind::LoadUse(17))
+        );
+        assert_eq!(check(&I::Ldrw(LI::new(false, 0, 0x30)), &use_r17), None);
+        // Only the register loaded matters.
+        assert_eq!(
+            check(&load, &I::Add(SI::new(false, 17, 16, SS::Imm13(1)))),
+            None
+        );
+
+        let putpsw = I::PutPSW(SI::new(false, 0, 16, SS::Imm13(0)));
+        assert_eq!(
+            check(&putpsw, &I::Add(SI::new(true, 16, 16, SS::Imm13(1)))),
+            Some(HazardKind::AfterPutPsw)
+        );
+        assert_eq!(check(&putpsw, &use_r17), None);
+
+        let jump = I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x40)));
+        assert_eq!(
+            check(
+                &jump,
+                &I::Ret(SC::new(false, Conditional::Alw, 31, SS::Imm13(8)))
+            ),
+            Some(HazardKind::TransferInDelaySlot)
+        );
+        assert_eq!(check(&jump, &NOP), None);
+    }
+
+    #[test]
+    fn images_are_scanned_in_order() {
+        let program = [
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x30))),
+            NOP,
+            I::Add(SI::new(false, 18, 17, SS::Imm13(1))),
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x30))),
+            I::Add(SI::new(false, 18, 17, SS::Imm13(1))),
+        ];
+        let image: Vec<u8> = program
+            .iter()
+            .flat_map(|i| Endian::Little.word_bytes(i.encode()).to_vec())
+            .collect();
+        let hazards = find_hazards(&image, 0x100, Endian::Little);
+        assert_eq!(hazards.len(), 1);
+        assert_eq!(hazards[0].pc, 0x110);
+        assert_eq!(hazards[0].kind, HazardKind::LoadUse(17));
+        assert!(hazards[0].previous == program[3].with_immediate_sign());
+        assert!(hazards[0]
+            .to_string()
+            .starts_with("Hazard at pc 0x00000110"));
+    }
+}
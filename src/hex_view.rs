@@ -0,0 +1,173 @@
+// Hex and ASCII view of memory for the debug window.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// A page of memory laid out as rows of 16 bytes, each row led by its
+// address and followed by the bytes as ASCII. Memory is read without going
+// through the devices, so looking at a page never has side effects. Bytes
+// the last store wrote are marked so the debug window can highlight them.
+// A cursor picks the byte the debug window edits, the page follows it.
+
+use commit::MemoryAccess;
+use memory::Memory;
+use std::fmt;
+
+/// Number of bytes in a row.
+pub const HEX_VIEW_COLUMNS: u32 = 16;
+
+/// A page of memory shown by the debug window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HexView {
+    /// Address of the first byte shown, a multiple of `HEX_VIEW_COLUMNS`.
+    start: u32,
+    /// Number of rows shown.
+    rows: u32,
+    /// Address of the byte under the cursor, always shown.
+    cursor: u32,
+}
+
+/// A row of a `HexView`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HexRow {
+    /// Address of the first byte.
+    pub address: u32,
+    /// The bytes, None if outside of memory.
+    pub bytes: Vec<Option<u8>>,
+    /// True for each byte the last store wrote.
+    pub written: Vec<bool>,
+}
+
+impl HexView {
+    /// Create a view of memory.
+    /// # Arguments
+    /// * `start` - Address to show, rounded down to the start of its row.
+    /// * `rows` - Number of rows to show.
+    pub fn new(start: u32, rows: u32) -> Self {
+        Self {
+            start: start & !(HEX_VIEW_COLUMNS - 1),
+            rows: rows,
+            cursor: start,
+        }
+    }
+
+    /// Get the address of the first byte shown.
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// Get the number of bytes shown.
+    pub fn size(&self) -> u32 {
+        self.rows * HEX_VIEW_COLUMNS
+    }
+
+    /// Get the address of the byte under the cursor.
+    pub fn cursor(&self) -> u32 {
+        self.cursor
+    }
+
+    /// Move the cursor, scrolling a row at a time to keep it shown and
+    /// wrapping around the address space.
+    /// # Arguments
+    /// * `delta` - Bytes to move by, negative to move back.
+    pub fn move_cursor(&mut self, delta: i32) {
+        self.cursor = self.cursor.wrapping_add(delta as u32);
+        let row = self.cursor & !(HEX_VIEW_COLUMNS - 1);
+        if delta < 0 && row.wrapping_sub(self.start) >= self.size() {
+            self.start = row;
+        } else if row.wrapping_sub(self.start) >= self.size() {
+            self.start = row.wrapping_sub(self.size() - HEX_VIEW_COLUMNS);
+        }
+    }
+
+    /// Show the page holding an address, starting at its row, with the
+    /// cursor on it.
+    /// # Arguments
+    /// * `address` - Address to show.
+    pub fn goto(&mut self, address: u32) {
+        *self = Self::new(address, self.rows);
+    }
+
+    /// Show the previous page, wrapping around the address space.
+    pub fn page_up(&mut self) {
+        self.start = self.start.wrapping_sub(self.size());
+        self.cursor = self.cursor.wrapping_sub(self.size());
+    }
+
+    /// Show the next page, wrapping around the address space.
+    pub fn page_down(&mut self) {
+        self.start = self.start.wrapping_add(self.size());
+        self.cursor = self.cursor.wrapping_add(self.size());
+    }
+
+    /// Read the rows shown.
+    /// # Arguments
+    /// * `mem` - Memory to read.
+    /// * `store` - The last memory access, its bytes are marked as written
+    /// if it was a store.
+    pub fn rows(&self, mem: &Memory, store: Option<&MemoryAccess>) -> Vec<HexRow> {
+        let written = |address: u32| match store {
+            Some(a) if a.store => address.wrapping_sub(a.address) < a.width,
+            _ => false,
+        };
+        (0..self.rows)
+            .map(|row| {
+                let address = self.start.wrapping_add(row * HEX_VIEW_COLUMNS);
+                let addresses: Vec<u32> = (0..HEX_VIEW_COLUMNS)
+                    .map(|i| address.wrapping_add(i))
+                    .collect();
+                HexRow {
+                    address: address,
+                    bytes: addresses
+                        .iter()
+                        .map(|a| mem.get_bytes(*a, 1).ok().map(|b| b[0]))
+                        .collect(),
+                    written: addresses.iter().map(|a| written(*a)).collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+impl HexRow {
+    /// Get a byte in hex, `??` if it is outside of memory.
+    /// # Arguments
+    /// * `i` - Index of the byte in the row.
+    pub fn hex(&self, i: usize) -> String {
+        match self.bytes[i] {
+            Some(b) => format!("{:02x}", b),
+            None => "??".to_string(),
+        }
+    }
+
+    /// Get the bytes as ASCII, with `.` for bytes that do not print.
+    pub fn ascii(&self) -> String {
+        self.bytes
+            .iter()
+            .map(|b| match *b {
+                Some(b) if b.is_ascii_graphic() || b == b' ' => b as char,
+                _ => '.',
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for HexRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x}:", self.address)?;
+        for i in 0..self.bytes.len() {
+            write!(f, " {}", self.hex(i))?;
+        }
+        write!(f, "  {}", self.ascii())
+    }
+}
@@ -0,0 +1,200 @@
+// Memory mapped host services.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Lets cooperative guest code be polite to the host. The status register
+// tells the guest whether the emulator runs in realtime (pacing the clock to
+// the configured rate, e.g. in an interactive session) or in turbo mode (as
+// fast as possible). Writing a number of microseconds to the yield register
+// gives the host CPU back for that long once the current clock cycle ends,
+// so a busy-wait loop does not spin a host core. Yields are ignored in turbo
+// mode, where nobody is waiting on the wall clock. A yield never changes the
+// guest visible state, only how long the host takes to get there.
+// Writing the exit register halts the system once the current clock cycle
+// ends, the value written becomes the emulator's exit code when it runs
+// headless. Writing the snapshot register asks for a snapshot of the whole
+// system once the current clock cycle ends, so a long computation can
+// checkpoint itself. The snapshot goes to the session's snapshot directory
+// like the user's, and reading the register gives the id of the last one the
+// guest took: 1 for the first, counting up for as long as the emulator runs,
+// 0 before any. Snapshots do not hold the devices, a system restored from
+// one reads the id of the last snapshot taken, not its own.
+
+use device::{Device, Reset};
+use r2d2::Writer;
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Base address of the host services' registers.
+pub const HOST_BASE: u32 = 0xffff0070;
+/// Status register, see the `HOST_*` status bits.
+pub const HOST_STATUS: u32 = HOST_BASE;
+/// Yield register. Writing it yields the host CPU for that many
+/// microseconds.
+pub const HOST_YIELD: u32 = HOST_BASE + 4;
+/// Exit register. Writing it halts the system with the value as exit code.
+pub const HOST_EXIT: u32 = HOST_BASE + 8;
+/// Snapshot register. Writing it takes a snapshot, reading it gives the id
+/// of the last one taken.
+pub const HOST_SNAPSHOT: u32 = HOST_BASE + 12;
+/// Size of the host services' register block.
+const HOST_SIZE: u32 = 16;
+/// Status bit set while the emulator paces the clock to the configured
+/// rate, clear in turbo mode.
+pub const HOST_REALTIME: u32 = 0x1;
+/// Longest a single yield may take, in microseconds.
+pub const HOST_MAX_YIELD: u32 = 10_000;
+
+/// State shared between the device and the system.
+struct HostState {
+    /// True if the emulator paces the clock.
+    realtime: bool,
+    /// Microseconds the guest asked to yield for, not honoured yet.
+    requested: u32,
+    /// Total time yielded.
+    yielded: Duration,
+    /// Exit code the guest halted with, None while it runs.
+    exit: Option<u32>,
+    /// True if the guest asked for a snapshot that was not taken yet.
+    snapshot_requested: bool,
+    /// Id of the last snapshot the guest took, 0 for none.
+    snapshot: u32,
+}
+
+/// Host services guest programs use to cooperate with the host.
+pub struct Host {
+    state: Rc<RefCell<HostState>>,
+}
+
+/// Handle the system uses to tell a `Host` how the emulator runs and to
+/// honour its yields.
+#[derive(Clone)]
+pub struct HostControl {
+    state: Rc<RefCell<HostState>>,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(HostState {
+                realtime: false,
+                requested: 0,
+                yielded: Duration::new(0, 0),
+                exit: None,
+                snapshot_requested: false,
+                snapshot: 0,
+            })),
+        }
+    }
+
+    /// Get a handle that controls this device.
+    pub fn control(&self) -> HostControl {
+        HostControl {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl HostControl {
+    /// Tell the guest whether the emulator paces the clock.
+    /// # Arguments
+    /// * `realtime` - True if the clock is paced to the configured rate.
+    pub fn set_realtime(&self, realtime: bool) {
+        self.state.borrow_mut().realtime = realtime;
+    }
+
+    /// Give the host CPU back for as long as the guest asked to, if it did
+    /// and the emulator runs in realtime. Return the time yielded.
+    pub fn run_yield(&self) -> Duration {
+        let mut state = self.state.borrow_mut();
+        let requested = std::mem::replace(&mut state.requested, 0);
+        if requested == 0 || !state.realtime {
+            return Duration::new(0, 0);
+        }
+        let duration = Duration::from_micros(requested as u64);
+        std::thread::sleep(duration);
+        state.yielded += duration;
+        duration
+    }
+
+    /// Get the total time the guest yielded the host CPU for.
+    pub fn yielded(&self) -> Duration {
+        self.state.borrow().yielded
+    }
+
+    /// Get the exit code the guest halted with, None if it has not halted.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.state.borrow().exit
+    }
+
+    /// Consume the guest's request for a snapshot, if it made one, giving
+    /// the snapshot the next id. Return true if it made one.
+    pub fn take_snapshot_request(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        if !std::mem::replace(&mut state.snapshot_requested, false) {
+            return false;
+        }
+        state.snapshot += 1;
+        true
+    }
+}
+
+impl Device for Host {
+    fn name(&self) -> &str {
+        "host"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        let mut state = self.state.borrow_mut();
+        state.requested = 0;
+        state.exit = None;
+        state.snapshot_requested = false;
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(HOST_BASE..HOST_BASE + HOST_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        let state = self.state.borrow();
+        match addr & !0x3 {
+            HOST_STATUS if state.realtime => HOST_REALTIME,
+            HOST_YIELD => state.requested,
+            HOST_SNAPSHOT => state.snapshot,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        let mut state = self.state.borrow_mut();
+        match addr & !0x3 {
+            HOST_YIELD => state.requested = value.min(HOST_MAX_YIELD),
+            HOST_EXIT => state.exit = Some(value),
+            HOST_SNAPSHOT => state.snapshot_requested = true,
+            _ => {}
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        let state = self.state.borrow();
+        w.put_bool(state.realtime);
+        w.put_u32(state.requested);
+        w.put_bool(state.exit.is_some());
+        w.put_u32(state.exit.unwrap_or(0));
+        w.put_bool(state.snapshot_requested);
+        w.put_u32(state.snapshot);
+    }
+}
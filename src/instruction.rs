@@ -15,11 +15,15 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use asm::assemble_line;
 use clock::Phase;
+use config::Syntax;
+use cpu::{register_name, ProcessorStatusWord};
 use data_path::{Control, DataPath};
 use std::fmt;
 use std::fmt::LowerHex;
 use std::ops::Index;
+use util::Result;
 
 pub const SCC_LOC: u32 = 0x1000000;
 pub const DEST_LOC: u32 = 0x00F80000;
@@ -31,11 +35,18 @@ pub const OPCODE_LOC: u32 = 0xFE000000;
 pub const SHORT_IMM_SIGN_LOC: u32 = 0x1000;
 pub const SHORT_IMM_SIGNEXT_BITS: u32 = 0xFFFFE000;
 pub const SIGN_BIT_LOC: u32 = 0x80000000;
+pub const LONG_IMM_SIGN_LOC: u32 = 0x40000;
+pub const LONG_IMM_SIGNEXT_BITS: u32 = 0xFFF80000;
+/// Lowest opcode of the extension opcode space, every opcode with its top
+/// bit set.
+pub const EXTENSION_OPCODE: u8 = 0x40;
 
 // Public functions.
 
 // Enums and structs.
 
+/// Micro operations of an instruction. Entries 0-3 run in phases one to four
+/// of the instruction's execute cycle, entry 4 runs when it commits.
 pub struct InstructionCycle(pub [fn(dp: &mut DataPath); 5]);
 
 pub fn noop(dp: &mut DataPath) {}
@@ -87,54 +98,57 @@ pub enum ShortSource {
     Reg(u8),
     /// Unsigned 13 bit immediate, 0-padded to 32 bits.
     Imm13(u32),
+    /// Signed 13 bit immediate, sign extended to 32 bits.
+    SImm13(u32),
 }
 
 /// Short instruction format data.
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub struct ShortInstruction {
     /// Update CC bit.
-    scc: bool,
+    pub scc: bool,
     /// Destination register.
-    dest: u8,
+    pub dest: u8,
     /// Source register.
-    rs1: u8,
+    pub rs1: u8,
     /// Short source data.
-    short_source: ShortSource,
+    pub short_source: ShortSource,
 }
 
 /// Long instruction format data.
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub struct LongInstruction {
     /// Update CC bit.
-    scc: bool,
+    pub scc: bool,
     /// Destination register.
-    dest: u8,
-    /// 19 bit constant.
-    imm19: u32,
+    pub dest: u8,
+    /// 19 bit constant. A signed offset from the PC for every instruction
+    /// but LDHI, see `offset`.
+    pub imm19: u32,
 }
 
 /// Short conditional instruction format data.
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub struct ShortConditional {
     /// Update CC bit.
-    scc: bool,
+    pub scc: bool,
     /// Destination register.
-    dest: Conditional,
+    pub dest: Conditional,
     /// Source register.
-    rs1: u8,
+    pub rs1: u8,
     /// Short source data.
-    short_source: ShortSource,
+    pub short_source: ShortSource,
 }
 
 /// Long conditional instruction format data.
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub struct LongConditional {
     /// Update CC bit.
-    scc: bool,
+    pub scc: bool,
     /// Destination register.
-    dest: Conditional,
-    /// 19 bit constant.
-    imm19: u32,
+    pub dest: Conditional,
+    /// 19 bit signed offset from the PC, see `offset`.
+    pub imm19: u32,
 }
 
 /// A RISC-II Instruction.
@@ -165,8 +179,9 @@ pub enum Instruction {
     /// - The `RS1` and `RS1` registers are read from the OLD window.
     /// - The PC instruction saved is the `PC` at the `CALLI`.
     /// - The `Rd` refers to the destination register in the NEW window.
-    /// - If the change to `CWP` makes it equal to `SWP`: stop execution,
-    ///   generate a trap, and go to address 0x80000020.
+    /// - If the change to `CWP` makes it equal to `SWP`: stop execution and
+    ///   trap to the window overflow handler. Not emulated yet, the oldest
+    ///   window is overwritten instead.
     /// CWP := CWP - 1 MOD 8, rd := LSTPC;
     /// Iff SCC == true, Z := [LSTPC == 0]; N := LSTPC<31>; V,C := garbage.
     Calli(ShortInstruction),
@@ -195,6 +210,14 @@ pub enum Instruction {
     /// - Not transparent to interrupts.
     /// - rs1 and short_source are discarded.
     GetLPC(ShortInstruction),
+    /// Get the trap cause register. rd := TCR.
+    /// The trap cause register holds the faulting address of the last
+    /// alignment trap.
+    /// Iff SCC == true, Z := [TCR == 0]; N := TCR<31>; V,C := garbage.
+    /// Notes:
+    /// - PRIVILEGED INSTRUCTION.
+    /// - rs1 and short_source are discarded.
+    GetTCR(ShortInstruction),
     /// Set PSW. PSW := [rs1 + ShortSource2]<12:0>;
     /// Format of PSW.
     /// [0]: Carry bit
@@ -210,32 +233,36 @@ pub enum Instruction {
     /// - PRIVILEGED INSTRUCTION.
     /// - SCC-bit MUST be off.
     /// - The next instruction CANNOT be `CALLX`, `CALLR`, `CALLI`, `RET`, `RETI`,
-    /// i.e. it cannot modify CWP/SWP. It also cannot modify the CC's.
+    /// i.e. it cannot modify CWP/SWP. It also cannot modify the CC's. Such an
+    /// instruction raises an illegal instruction trap.
     /// - Rd is discarded.
     /// - New PSW is not in effect until AFTER the next cycle following execution
-    /// of this instruction.
+    /// of this instruction. The next instruction runs and writes its result
+    /// under the old PSW.
     PutPSW(ShortInstruction),
     /// Call procedure at `shortSource` + `rs1`.
     /// - The `RS1` and `RS2` registers are read from the OLD window.
     /// - The PC instruction saved is the `PC` at the `CALLI`.
     /// - The `Rd` refers to the destination register in the NEW window.
-    /// - If the change to `CWP` makes it equal to `SWP`: stop execution,
-    /// generate a trap, and go to address 0x80000020.
+    /// - If the change to `CWP` makes it equal to `SWP`: stop execution and
+    /// trap to the window overflow handler. Not emulated yet, the oldest
+    /// window is overwritten instead.
     /// CWP := CWP - 1 MOD 8, rd := PC; CC's have same rules as getipc.
     Callx(ShortInstruction),
     /// Call procedure at `PC` + `imm19`.
     /// - The `RS1` and `RS1` registers are read from the OLD window.
     /// - The PC instruction saved is the `PC` at the `CALLI`.
     /// - The `Rd` refers to the destination register in the NEW window.
-    /// - If the change to `CWP` makes it equal to `SWP`: stop execution,
-    /// generate a trap, and go to address 0x80000020.
+    /// - If the change to `CWP` makes it equal to `SWP`: stop execution and
+    /// trap to the window overflow handler. Not emulated yet, the oldest
+    /// window is overwritten instead.
     /// CWP := CWP - 1 MOD 8, rd := PC; CC's have same rules as getipc.
     Callr(LongInstruction),
     /// If conditional is true: PC := `rs1` + `shortSource`;
     Jmpx(ShortConditional),
     /// If conditional is true: PC += `imm19`;
-    /// Test alignment: if newPC<0> == 1 then abort instruction and jump
-    /// to 0x80000000.
+    /// Test alignment: if newPC<0> == 1 then abort instruction and raise an
+    /// alignment trap.
     Jmpr(LongConditional),
     /// Return from the current procedure if conditional is true.
     /// CWP := CWP + 1 MOD 8.
@@ -320,11 +347,255 @@ pub enum Instruction {
     Stxb(ShortInstruction),
     /// Store byte, long-immediate.
     Strb(LongInstruction),
+
+    /// Instruction of an ISA extension, any opcode of the extension opcode
+    /// space. Extension instructions have the short format, rd := f(rs1,
+    /// S2), and what `f` is is up to the extension implementing the opcode,
+    /// see `extension::ExtensionIsa`. Opcodes no extension implements raise
+    /// an illegal instruction trap.
+    Extension(u8, ShortInstruction),
+}
+
+/// An instruction disassembled in one of the syntaxes, see
+/// `Instruction::disassemble`.
+#[derive(Copy, Clone)]
+pub struct Disassembly {
+    /// The instruction.
+    instruction: Instruction,
+    /// Syntax to write it in.
+    syntax: Syntax,
 }
 
 // Impls.
 
 impl Instruction {
+    /// Parse an instruction in the modern syntax, e.g. `add r5, r4,
+    /// #0x1f69`. PC relative instructions take their offset. Return the
+    /// instruction on success and a string on error.
+    /// # Arguments
+    /// * `text` - The instruction.
+    pub fn parse(text: &str) -> Result<Self> {
+        assemble_line(text)
+    }
+
+    /// Get the disassembly of `self` in a syntax, for formatting with `{}`.
+    /// # Arguments
+    /// * `syntax` - Syntax to disassemble in.
+    pub fn disassemble(&self, syntax: Syntax) -> Disassembly {
+        Disassembly {
+            instruction: *self,
+            syntax: syntax,
+        }
+    }
+
+    /// Get `self` with its short immediate, if it has one, signed the way
+    /// the opcode reads it. Loads and stores sign extend their displacement
+    /// so they can reach below rs1, everything else zero extends.
+    pub fn with_immediate_sign(self) -> Self {
+        type I = Instruction;
+        match self {
+            I::Ldxw(s) => I::Ldxw(signed_source(s)),
+            I::Ldxhs(s) => I::Ldxhs(signed_source(s)),
+            I::Ldxhu(s) => I::Ldxhu(signed_source(s)),
+            I::Ldxbs(s) => I::Ldxbs(signed_source(s)),
+            I::Ldxbu(s) => I::Ldxbu(signed_source(s)),
+            I::Stxw(s) => I::Stxw(signed_source(s)),
+            I::Stxh(s) => I::Stxh(signed_source(s)),
+            I::Stxb(s) => I::Stxb(signed_source(s)),
+            _ => self,
+        }
+    }
+
+    /// Get the register `self` writes its result to, if it has one.
+    /// Stores, jumps, returns and `PUTPSW` do not write a register.
+    pub fn dest_reg(&self) -> Option<u8> {
+        type I = Instruction;
+        match *self {
+            I::Calli(s)
+            | I::GetPSW(s)
+            | I::GetLPC(s)
+            | I::GetTCR(s)
+            | I::Callx(s)
+            | I::Sll(s)
+            | I::Srl(s)
+            | I::Sra(s)
+            | I::Or(s)
+            | I::And(s)
+            | I::Xor(s)
+            | I::Add(s)
+            | I::Addc(s)
+            | I::Sub(s)
+            | I::Subc(s)
+            | I::Subi(s)
+            | I::Subci(s)
+            | I::Ldxw(s)
+            | I::Ldxhs(s)
+            | I::Ldxhu(s)
+            | I::Ldxbs(s)
+            | I::Ldxbu(s)
+            | I::Extension(_, s) => Some(s.dest),
+            I::Callr(l)
+            | I::Ldhi(l)
+            | I::Ldrw(l)
+            | I::Ldrhs(l)
+            | I::Ldrhu(l)
+            | I::Ldrbs(l)
+            | I::Ldrbu(l) => Some(l.dest),
+            I::PutPSW(_)
+            | I::Jmpx(_)
+            | I::Jmpr(_)
+            | I::Ret(_)
+            | I::Reti(_)
+            | I::Stxw(_)
+            | I::Strw(_)
+            | I::Stxh(_)
+            | I::Strh(_)
+            | I::Stxb(_)
+            | I::Strb(_) => None,
+        }
+    }
+
+    /// Get the registers `self` reads, r0 included. Stores read the
+    /// register they store as well as their address registers.
+    pub fn source_regs(&self) -> Vec<u8> {
+        type I = Instruction;
+        let short = |rs1: u8, source: ShortSource| match source {
+            ShortSource::Reg(rs2) => vec![rs1, rs2],
+            _ => vec![rs1],
+        };
+        match *self {
+            I::PutPSW(s)
+            | I::Callx(s)
+            | I::Sll(s)
+            | I::Srl(s)
+            | I::Sra(s)
+            | I::Or(s)
+            | I::And(s)
+            | I::Xor(s)
+            | I::Add(s)
+            | I::Addc(s)
+            | I::Sub(s)
+            | I::Subc(s)
+            | I::Subi(s)
+            | I::Subci(s)
+            | I::Ldxw(s)
+            | I::Ldxhs(s)
+            | I::Ldxhu(s)
+            | I::Ldxbs(s)
+            | I::Ldxbu(s)
+            | I::Extension(_, s) => short(s.rs1, s.short_source),
+            I::Jmpx(c) | I::Ret(c) | I::Reti(c) => short(c.rs1, c.short_source),
+            I::Stxw(s) | I::Stxh(s) | I::Stxb(s) => {
+                let mut regs = short(s.rs1, s.short_source);
+                regs.push(s.dest);
+                regs
+            }
+            I::Strw(l) | I::Strh(l) | I::Strb(l) => vec![l.dest],
+            I::Calli(_)
+            | I::GetPSW(_)
+            | I::GetLPC(_)
+            | I::GetTCR(_)
+            | I::Callr(_)
+            | I::Jmpr(_)
+            | I::Ldhi(_)
+            | I::Ldrw(_)
+            | I::Ldrhs(_)
+            | I::Ldrhu(_)
+            | I::Ldrbs(_)
+            | I::Ldrbu(_) => Vec::new(),
+        }
+    }
+
+    /// Return true if `self` transfers control, i.e. is a jump, call or
+    /// return and has a delay slot.
+    pub fn is_transfer(&self) -> bool {
+        type I = Instruction;
+        match *self {
+            I::Callx(_) | I::Callr(_) | I::Jmpx(_) | I::Jmpr(_) | I::Ret(_) | I::Reti(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Return true if `self` loads from memory.
+    pub fn is_load(&self) -> bool {
+        self.access_width().is_some() && self.dest_reg().is_some()
+    }
+
+    /// Return true if `self` may not directly follow `PUTPSW`, as it would
+    /// change the window pointers or condition codes before the new PSW is
+    /// in effect.
+    pub fn conflicts_with_putpsw(&self) -> bool {
+        type I = Instruction;
+        match *self {
+            I::Calli(_) | I::Callx(_) | I::Callr(_) | I::Ret(_) | I::Reti(_) | I::PutPSW(_) => true,
+            _ => self.encode() & SCC_LOC != 0,
+        }
+    }
+
+    /// Get the number of bytes `self` loads or stores, None if it does not
+    /// access memory.
+    pub fn access_width(&self) -> Option<u32> {
+        type I = Instruction;
+        match *self {
+            I::Ldxw(_) | I::Ldrw(_) | I::Stxw(_) | I::Strw(_) => Some(4),
+            I::Ldxhs(_) | I::Ldrhs(_) | I::Ldxhu(_) | I::Ldrhu(_) | I::Stxh(_) | I::Strh(_) => {
+                Some(2)
+            }
+            I::Ldxbs(_) | I::Ldrbs(_) | I::Ldxbu(_) | I::Ldrbu(_) | I::Stxb(_) | I::Strb(_) => {
+                Some(1)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the assembly mnemonic of `self`.
+    pub fn mnemonic(&self) -> &'static str {
+        type I = Instruction;
+        match *self {
+            I::Calli(_) => "calli",
+            I::GetPSW(_) => "getpsw",
+            I::GetLPC(_) => "getlpc",
+            I::GetTCR(_) => "gettcr",
+            I::PutPSW(_) => "putpsw",
+            I::Callx(_) => "callx",
+            I::Callr(_) => "callr",
+            I::Jmpx(_) => "jmpx",
+            I::Jmpr(_) => "jmpr",
+            I::Ret(_) => "ret",
+            I::Reti(_) => "reti",
+            I::Sll(_) => "sll",
+            I::Srl(_) => "srl",
+            I::Sra(_) => "sra",
+            I::Or(_) => "or",
+            I::And(_) => "and",
+            I::Xor(_) => "xor",
+            I::Add(_) => "add",
+            I::Addc(_) => "addc",
+            I::Sub(_) => "sub",
+            I::Subc(_) => "subc",
+            I::Subi(_) => "subi",
+            I::Subci(_) => "subci",
+            I::Ldhi(_) => "ldhi",
+            I::Ldxw(_) => "ldxw",
+            I::Ldrw(_) => "ldrw",
+            I::Ldxhs(_) => "ldxhs",
+            I::Ldrhs(_) => "ldrhs",
+            I::Ldxhu(_) => "ldxhu",
+            I::Ldrhu(_) => "ldrhu",
+            I::Ldxbs(_) => "ldxbs",
+            I::Ldrbs(_) => "ldrbs",
+            I::Ldxbu(_) => "ldxbu",
+            I::Ldrbu(_) => "ldrbu",
+            I::Stxw(_) => "stxw",
+            I::Strw(_) => "strw",
+            I::Stxh(_) => "stxh",
+            I::Strh(_) => "strh",
+            I::Stxb(_) => "stxb",
+            I::Strb(_) => "strb",
+            I::Extension(_, _) => "ext",
+        }
+    }
+
     pub fn encode(&self) -> u32 {
         type I = Instruction;
         match *self {
@@ -332,6 +603,7 @@ impl Instruction {
             I::GetPSW(s) => s.encode(0b0000010),
             I::PutPSW(s) => s.encode(0b0000100),
             I::GetLPC(s) => s.encode(0b0000011),
+            I::GetTCR(s) => s.encode(0b0000101),
             I::Callx(s) => s.encode(0b0001000),
             I::Sll(s) => s.encode(0b0010001),
             I::Srl(s) => s.encode(0b0010011),
@@ -368,6 +640,8 @@ impl Instruction {
             I::Strw(l) => l.encode(0b0110111),
             I::Strh(l) => l.encode(0b0111011),
             I::Strb(l) => l.encode(0b0111111),
+
+            I::Extension(op, s) => s.encode(op),
         }
     }
 }
@@ -397,14 +671,13 @@ impl ShortSource {
     pub fn uimm_to_simm(&self) -> Self {
         match *self {
             Self::Imm13(u) => {
-                if u & 0x1000 != 0 {
-                    // Sign-extend the 13 bit value to 32 bits.
-                    Self::Imm13((-(u as i32)) as u32)
+                if u & SHORT_IMM_SIGN_LOC != 0 {
+                    Self::SImm13(u | SHORT_IMM_SIGNEXT_BITS)
                 } else {
-                    Self::Imm13(u)
+                    Self::SImm13(u)
                 }
             }
-            Self::Reg(_) => *self,
+            Self::Reg(_) | Self::SImm13(_) => *self,
         }
     }
 }
@@ -414,6 +687,7 @@ impl fmt::Display for ShortSource {
         match *self {
             Self::Reg(r) => write!(f, "Reg {}", r),
             Self::Imm13(u) => write!(f, "U{}", u),
+            Self::SImm13(s) => write!(f, "S{}", s as i32),
         }
     }
 }
@@ -423,6 +697,7 @@ impl LowerHex for ShortSource {
         match *self {
             Self::Reg(r) => write!(f, "Register {:x}", r),
             Self::Imm13(u) => write!(f, "(UImm) {:x}", u),
+            Self::SImm13(s) => write!(f, "(SImm) {:x}", s),
         }
     }
 }
@@ -431,7 +706,7 @@ impl LongInstruction {
     pub fn encode(&self, opcode: u8) -> u32 {
         let scc = if self.scc { SCC_LOC } else { 0 };
         let dest = (self.dest as u32) << 19;
-        let imm19 = self.imm19;
+        let imm19 = self.imm19 & IMM19_LOC;
 
         ((opcode as u32) << 25) | scc | dest | imm19
     }
@@ -439,7 +714,8 @@ impl LongInstruction {
     /// # Arguments
     /// * `scc` - Should update CC's.
     /// * `dest` - Destination register.
-    /// * `imm19` - 19 bit constant.
+    /// * `imm19` - 19 bit constant, two's complement if it is an offset.
+    /// Bits above the 19th are dropped when encoding.
     pub fn new(scc: bool, dest: u8, imm19: u32) -> Self {
         Self {
             scc: scc,
@@ -447,6 +723,11 @@ impl LongInstruction {
             imm19: imm19,
         }
     }
+
+    /// Get imm19 as an offset from the PC, sign extended to 32 bits.
+    pub fn offset(&self) -> u32 {
+        sign_extend_imm19(self.imm19)
+    }
 }
 
 impl fmt::Display for LongInstruction {
@@ -463,15 +744,15 @@ impl LongConditional {
     pub fn encode(&self, opcode: u8) -> u32 {
         let scc = if self.scc { SCC_LOC } else { 0 };
         let dest = (get_opdata_from_cond(self.dest) as u32) << 19;
-        let imm19 = self.imm19;
-        println!("Lol 0x{:x}, 0x{:x}", dest, imm19);
+        let imm19 = self.imm19 & IMM19_LOC;
         ((opcode as u32) << 25) | scc | dest | imm19
     }
     /// Create a new long conditional instruction.
     /// # Arguments
     /// * `scc` - Should update CC's.
     /// * `dest` - Conditional.
-    /// * `imm19` - 19 bit constant.
+    /// * `imm19` - 19 bit offset from the PC, two's complement. Bits above
+    /// the 19th are dropped when encoding.
     pub fn new(scc: bool, dest: Conditional, imm19: u32) -> Self {
         Self {
             scc: scc,
@@ -479,6 +760,11 @@ impl LongConditional {
             imm19: imm19,
         }
     }
+
+    /// Get imm19 as an offset from the PC, sign extended to 32 bits.
+    pub fn offset(&self) -> u32 {
+        sign_extend_imm19(self.imm19)
+    }
 }
 
 impl fmt::Display for LongConditional {
@@ -499,6 +785,7 @@ impl ShortInstruction {
         let (ss, ss_bit) = match self.short_source {
             ShortSource::Reg(r) => (r as u32, 0),
             ShortSource::Imm13(i) => (i, SHORT_SOURCE_TYPE_LOC),
+            ShortSource::SImm13(i) => (i & 0x1fff, SHORT_SOURCE_TYPE_LOC),
         };
         ((opcode as u32) << 25) | scc | dest | rs1 | ss_bit | ss
     }
@@ -536,6 +823,7 @@ impl ShortConditional {
         let (ss, ss_bit) = match self.short_source {
             ShortSource::Reg(r) => (r as u32, 0),
             ShortSource::Imm13(i) => (i, SHORT_SOURCE_TYPE_LOC),
+            ShortSource::SImm13(i) => (i & 0x1fff, SHORT_SOURCE_TYPE_LOC),
         };
         ((opcode as u32) << 25) | scc | dest | rs1 | ss_bit | ss
     }
@@ -587,6 +875,293 @@ impl fmt::Display for Conditional {
     }
 }
 
+impl Conditional {
+    /// Get a conditional from the 4 bit code in an instruction's destination
+    /// field. Return None for 0, which is not a conditional.
+    /// # Arguments
+    /// * `code` - The code, only the low 4 bits are looked at.
+    pub fn from_code(code: u8) -> Option<Self> {
+        type C = Conditional;
+        Some(match code & 0xf {
+            1 => C::Gt,
+            2 => C::Le,
+            3 => C::Ge,
+            4 => C::Lt,
+            5 => C::Hi,
+            6 => C::Los,
+            7 => C::Lonc,
+            8 => C::Hisc,
+            9 => C::Pl,
+            10 => C::Mi,
+            11 => C::Ne,
+            12 => C::Eq,
+            13 => C::Nv,
+            14 => C::V,
+            15 => C::Alw,
+            _ => return None,
+        })
+    }
+
+    /// Check if the condition holds for the CC's of a PSW. The book writes
+    /// some of these with `+`, which is a logical OR there. Unsigned
+    /// comparisons read the carry as "no borrow", as subtraction sets it.
+    /// # Arguments
+    /// * `psw` - PSW holding the CC's.
+    pub fn evaluate(&self, psw: &ProcessorStatusWord) -> bool {
+        let n = psw.get_cc_neg();
+        let v = psw.get_cc_overflow();
+        let z = psw.get_cc_zero();
+        let c = psw.get_cc_carry();
+        match *self {
+            Self::Gt => !((n ^ v) | z),
+            Self::Le => (n ^ v) | z,
+            Self::Ge => !(n ^ v),
+            Self::Lt => n ^ v,
+            Self::Hi => c & !z,
+            Self::Los => !c | z,
+            Self::Lonc => !c,
+            Self::Hisc => c,
+            Self::Pl => !n,
+            Self::Mi => n,
+            Self::Ne => !z,
+            Self::Eq => z,
+            Self::Nv => !v,
+            Self::V => v,
+            Self::Alw => true,
+        }
+    }
+
+    /// Get a conditional from its assembly name. Return None if `name` is
+    /// not a conditional.
+    /// # Arguments
+    /// * `name` - Assembly name, e.g. `alw`.
+    pub fn from_mnemonic(name: &str) -> Option<Self> {
+        type C = Conditional;
+        [
+            C::Gt,
+            C::Le,
+            C::Ge,
+            C::Lt,
+            C::Hi,
+            C::Los,
+            C::Lonc,
+            C::Hisc,
+            C::Pl,
+            C::Mi,
+            C::Ne,
+            C::Eq,
+            C::Nv,
+            C::V,
+            C::Alw,
+        ]
+        .iter()
+        .cloned()
+        .find(|c| c.mnemonic() == name)
+    }
+
+    /// Get the assembly name of `self`.
+    pub fn mnemonic(&self) -> &'static str {
+        match *self {
+            Self::Gt => "gt",
+            Self::Le => "le",
+            Self::Ge => "ge",
+            Self::Lt => "lt",
+            Self::Hi => "hi",
+            Self::Los => "los",
+            Self::Lonc => "lonc",
+            Self::Hisc => "hisc",
+            Self::Pl => "pl",
+            Self::Mi => "mi",
+            Self::Ne => "ne",
+            Self::Eq => "eq",
+            Self::Nv => "nv",
+            Self::V => "v",
+            Self::Alw => "alw",
+        }
+    }
+}
+
+/// Disassembly of an instruction, e.g. `add.cc r16, r0, 0x1`. The `.cc`
+/// suffix marks instructions that set the CC's. Operands are the
+/// destination (or conditional) first, then the sources.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        type I = Instruction;
+        let mnemonic = self.mnemonic();
+        match *self {
+            I::Jmpx(c) | I::Ret(c) | I::Reti(c) => write!(
+                f,
+                "{}{} {}, {}, {}",
+                mnemonic,
+                scc_suffix(c.scc),
+                c.dest.mnemonic(),
+                register_name(c.rs1),
+                source_operand(c.short_source)
+            ),
+            I::Jmpr(c) => write!(
+                f,
+                "{}{} {}, {}",
+                mnemonic,
+                scc_suffix(c.scc),
+                c.dest.mnemonic(),
+                signed_hex(c.offset())
+            ),
+            I::Extension(op, s) => write!(
+                f,
+                "{}{} 0x{:02x}, {}, {}, {}",
+                mnemonic,
+                scc_suffix(s.scc),
+                op,
+                register_name(s.dest),
+                register_name(s.rs1),
+                source_operand(s.short_source)
+            ),
+            I::Ldhi(l) => write!(
+                f,
+                "{}{} {}, 0x{:x}",
+                mnemonic,
+                scc_suffix(l.scc),
+                register_name(l.dest),
+                l.imm19
+            ),
+            I::Callr(l)
+            | I::Ldrw(l)
+            | I::Ldrhs(l)
+            | I::Ldrhu(l)
+            | I::Ldrbs(l)
+            | I::Ldrbu(l)
+            | I::Strw(l)
+            | I::Strh(l)
+            | I::Strb(l) => write!(
+                f,
+                "{}{} {}, {}",
+                mnemonic,
+                scc_suffix(l.scc),
+                register_name(l.dest),
+                signed_hex(l.offset())
+            ),
+            I::Calli(s)
+            | I::GetPSW(s)
+            | I::GetLPC(s)
+            | I::GetTCR(s)
+            | I::PutPSW(s)
+            | I::Callx(s)
+            | I::Sll(s)
+            | I::Srl(s)
+            | I::Sra(s)
+            | I::Or(s)
+            | I::And(s)
+            | I::Xor(s)
+            | I::Add(s)
+            | I::Addc(s)
+            | I::Sub(s)
+            | I::Subc(s)
+            | I::Subi(s)
+            | I::Subci(s)
+            | I::Ldxw(s)
+            | I::Ldxhs(s)
+            | I::Ldxhu(s)
+            | I::Ldxbs(s)
+            | I::Ldxbu(s)
+            | I::Stxw(s)
+            | I::Stxh(s)
+            | I::Stxb(s) => write!(
+                f,
+                "{}{} {}, {}, {}",
+                mnemonic,
+                scc_suffix(s.scc),
+                register_name(s.dest),
+                register_name(s.rs1),
+                source_operand(s.short_source)
+            ),
+        }
+    }
+}
+
+/// Disassembly in the Berkeley papers' syntax, e.g. `ADD r0,0x1,r16 {C}`.
+/// Mnemonics are upper case and the destination comes last, except for
+/// stores where the register stored comes first. Register indexed addresses
+/// are written `(rx)S2` and PC relative ones as the offset. `{C}` marks
+/// instructions that set the CC's. The modern syntax is `Instruction`'s own.
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        type I = Instruction;
+        if self.syntax == Syntax::Modern {
+            return write!(f, "{}", self.instruction);
+        }
+        let (operands, scc) = match self.instruction {
+            I::Jmpx(c) | I::Ret(c) | I::Reti(c) => (
+                format!(
+                    "{},{}",
+                    c.dest.mnemonic().to_uppercase(),
+                    berkeley_address(c.rs1, c.short_source)
+                ),
+                c.scc,
+            ),
+            I::Jmpr(c) => (
+                format!(
+                    "{},{}",
+                    c.dest.mnemonic().to_uppercase(),
+                    signed_hex(c.offset())
+                ),
+                c.scc,
+            ),
+            I::Extension(op, s) => (
+                format!(
+                    "0x{:02x},r{},{},r{}",
+                    op,
+                    s.rs1,
+                    berkeley_source(s.short_source),
+                    s.dest
+                ),
+                s.scc,
+            ),
+            I::Strw(l) | I::Strh(l) | I::Strb(l) => {
+                (format!("r{},{}", l.dest, signed_hex(l.offset())), l.scc)
+            }
+            I::Ldhi(l) => (format!("0x{:x},r{}", l.imm19, l.dest), l.scc),
+            I::Callr(l) | I::Ldrw(l) | I::Ldrhs(l) | I::Ldrhu(l) | I::Ldrbs(l) | I::Ldrbu(l) => {
+                (format!("{},r{}", signed_hex(l.offset()), l.dest), l.scc)
+            }
+            I::Stxw(s) | I::Stxh(s) | I::Stxb(s) => (
+                format!("r{},{}", s.dest, berkeley_address(s.rs1, s.short_source)),
+                s.scc,
+            ),
+            I::Callx(s) | I::Ldxw(s) | I::Ldxhs(s) | I::Ldxhu(s) | I::Ldxbs(s) | I::Ldxbu(s) => (
+                format!("{},r{}", berkeley_address(s.rs1, s.short_source), s.dest),
+                s.scc,
+            ),
+            I::Calli(s)
+            | I::GetPSW(s)
+            | I::GetLPC(s)
+            | I::GetTCR(s)
+            | I::PutPSW(s)
+            | I::Sll(s)
+            | I::Srl(s)
+            | I::Sra(s)
+            | I::Or(s)
+            | I::And(s)
+            | I::Xor(s)
+            | I::Add(s)
+            | I::Addc(s)
+            | I::Sub(s)
+            | I::Subc(s)
+            | I::Subi(s)
+            | I::Subci(s) => (
+                format!("r{},{},r{}", s.rs1, berkeley_source(s.short_source), s.dest),
+                s.scc,
+            ),
+        };
+        write!(
+            f,
+            "{} {}{}",
+            self.instruction.mnemonic().to_uppercase(),
+            operands,
+            if scc { " {C}" } else { "" }
+        )
+    }
+}
+
 impl InstructionCycle {
     pub fn new(steps: [fn(dp: &mut DataPath); 5]) -> Self {
         Self { 0: steps }
@@ -627,3 +1202,66 @@ fn get_opdata_from_cond(cond: Conditional) -> u8 {
         C::Alw => 15,
     }
 }
+
+/// Get a short instruction with its immediate, if it has one, signed.
+fn signed_source(s: ShortInstruction) -> ShortInstruction {
+    ShortInstruction {
+        short_source: s.short_source.uimm_to_simm(),
+        ..s
+    }
+}
+
+/// Write a signed value in hex, e.g. `-0x10`.
+fn signed_hex(v: u32) -> String {
+    let v = v as i32 as i64;
+    if v < 0 {
+        format!("-0x{:x}", -v)
+    } else {
+        format!("0x{:x}", v)
+    }
+}
+
+/// Sign extend a 19 bit immediate.
+fn sign_extend_imm19(imm19: u32) -> u32 {
+    if imm19 & LONG_IMM_SIGN_LOC != 0 {
+        imm19 | LONG_IMM_SIGNEXT_BITS
+    } else {
+        imm19
+    }
+}
+
+/// Suffix of a mnemonic that sets the CC's.
+fn scc_suffix(scc: bool) -> &'static str {
+    if scc {
+        ".cc"
+    } else {
+        ""
+    }
+}
+
+/// Disassemble a short source operand.
+fn source_operand(source: ShortSource) -> String {
+    match source {
+        ShortSource::Reg(r) => register_name(r),
+        ShortSource::Imm13(i) => format!("0x{:x}", i),
+        ShortSource::SImm13(i) => signed_hex(i),
+    }
+}
+
+/// Disassemble a short source operand in the Berkeley syntax.
+fn berkeley_source(source: ShortSource) -> String {
+    match source {
+        ShortSource::Reg(r) => format!("r{}", r),
+        ShortSource::Imm13(i) => format!("0x{:x}", i),
+        ShortSource::SImm13(i) => signed_hex(i),
+    }
+}
+
+/// Disassemble a register indexed address in the Berkeley syntax, e.g.
+/// `(r1)0x10`.
+fn berkeley_address(rs1: u8, source: ShortSource) -> String {
+    match source {
+        ShortSource::Imm13(0) | ShortSource::SImm13(0) => format!("(r{})", rs1),
+        _ => format!("(r{}){}", rs1, berkeley_source(source)),
+    }
+}
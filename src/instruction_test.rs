@@ -0,0 +1,75 @@
+// Test code for instructions.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "instruction.rs"]
+mod test {
+    use super::super::*;
+    use cpu::ProcessorStatusWord;
+    use instruction::*;
+
+    type C = Conditional;
+
+    /// Every conditional with the NZVC combinations it holds for. Bit `i` of
+    /// the mask is combination `i`, where N is bit 3, Z bit 2, V bit 1 and C
+    /// bit 0 of `i`.
+    const MATRIX: [(Conditional, u16); 15] = [
+        (C::Gt, 0b0000_1100_0000_0011),
+        (C::Le, 0b1111_0011_1111_1100),
+        (C::Ge, 0b1100_1100_0011_0011),
+        (C::Lt, 0b0011_0011_1100_1100),
+        (C::Hi, 0b0000_1010_0000_1010),
+        (C::Los, 0b1111_0101_1111_0101),
+        (C::Lonc, 0b0101_0101_0101_0101),
+        (C::Hisc, 0b1010_1010_1010_1010),
+        (C::Pl, 0b0000_0000_1111_1111),
+        (C::Mi, 0b1111_1111_0000_0000),
+        (C::Ne, 0b0000_1111_0000_1111),
+        (C::Eq, 0b1111_0000_1111_0000),
+        (C::Nv, 0b0011_0011_0011_0011),
+        (C::V, 0b1100_1100_1100_1100),
+        (C::Alw, 0b1111_1111_1111_1111),
+    ];
+
+    #[test]
+    fn conditionals_hold_for_exactly_their_flags() {
+        for (cond, mask) in MATRIX.iter() {
+            for i in 0..16 {
+                let mut psw = ProcessorStatusWord::new();
+                psw.set_cc_neg(i & 8 != 0);
+                psw.set_cc_zero(i & 4 != 0);
+                psw.set_cc_overflow(i & 2 != 0);
+                psw.set_cc_carry(i & 1 != 0);
+                assert_eq!(
+                    cond.evaluate(&psw),
+                    mask & (1 << i) != 0,
+                    "{} with NZVC {:04b}",
+                    cond.mnemonic(),
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn conditional_codes() {
+        assert!(Conditional::from_code(0).is_none());
+        for (code, (cond, _)) in MATRIX.iter().enumerate() {
+            assert!(Conditional::from_code(code as u8 + 1) == Some(*cond));
+            // Only the low 4 bits are the code.
+            assert!(Conditional::from_code(code as u8 + 0x11) == Some(*cond));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// Memory mapped keyboard controller.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Key presses and releases are queued in a FIFO the guest reads one event at
+// a time. An event is the key's scancode, with `KEYBOARD_RELEASED` set if the
+// key was released. The host side feeds events through a `KeyboardInput`.
+
+use device::{Device, Reset};
+use r2d2::Writer;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Base address of the keyboard's registers.
+pub const KEYBOARD_BASE: u32 = 0xffff0010;
+/// Data register. Reading it removes the oldest event from the FIFO, 0 if
+/// it is empty.
+pub const KEYBOARD_DATA: u32 = KEYBOARD_BASE;
+/// Status register, see the `KEYBOARD_*` status bits.
+pub const KEYBOARD_STATUS: u32 = KEYBOARD_BASE + 4;
+/// Control register. Bit 0 enables the interrupt.
+pub const KEYBOARD_CONTROL: u32 = KEYBOARD_BASE + 8;
+/// Size of the keyboard's register block.
+const KEYBOARD_SIZE: u32 = 12;
+/// Status bit set when the FIFO holds an event.
+pub const KEYBOARD_READY: u32 = 0x1;
+/// Status bit set when an event was dropped because the FIFO was full.
+/// Cleared by reading the status register.
+pub const KEYBOARD_OVERFLOW: u32 = 0x2;
+/// Control bit that raises an interrupt while the FIFO holds an event.
+pub const KEYBOARD_INTERRUPT_ENABLE: u32 = 0x1;
+/// Event bit set for key releases.
+pub const KEYBOARD_RELEASED: u32 = 0x80000000;
+/// Number of events the FIFO holds.
+pub const KEYBOARD_FIFO_SIZE: usize = 16;
+
+/// State shared between the device and its input handles.
+struct KeyboardState {
+    /// Events not read yet, oldest first.
+    fifo: VecDeque<u32>,
+    /// True if an event was dropped since the status was last read.
+    overflow: bool,
+    /// Contents of the control register.
+    control: u32,
+}
+
+/// Keyboard controller guest programs read key events from.
+pub struct Keyboard {
+    state: Rc<RefCell<KeyboardState>>,
+}
+
+/// Handle the host uses to send key events to a `Keyboard`.
+#[derive(Clone)]
+pub struct KeyboardInput {
+    state: Rc<RefCell<KeyboardState>>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(KeyboardState {
+                fifo: VecDeque::with_capacity(KEYBOARD_FIFO_SIZE),
+                overflow: false,
+                control: 0,
+            })),
+        }
+    }
+
+    /// Get a handle that sends key events to this keyboard.
+    pub fn input(&self) -> KeyboardInput {
+        KeyboardInput {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl KeyboardInput {
+    /// Queue a key press.
+    /// # Arguments
+    /// * `scancode` - Scancode of the key.
+    pub fn key_down(&self, scancode: u32) {
+        self.push(scancode & !KEYBOARD_RELEASED);
+    }
+
+    /// Queue a key release.
+    /// # Arguments
+    /// * `scancode` - Scancode of the key.
+    pub fn key_up(&self, scancode: u32) {
+        self.push(scancode | KEYBOARD_RELEASED);
+    }
+
+    /// Get the number of events the guest has not read yet.
+    pub fn pending(&self) -> usize {
+        self.state.borrow().fifo.len()
+    }
+
+    /// Queue an event, dropping it if the FIFO is full.
+    fn push(&self, event: u32) {
+        let mut state = self.state.borrow_mut();
+        if state.fifo.len() == KEYBOARD_FIFO_SIZE {
+            state.overflow = true;
+        } else {
+            state.fifo.push_back(event);
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &str {
+        "keyboard"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        let mut state = self.state.borrow_mut();
+        state.fifo.clear();
+        state.overflow = false;
+        state.control = 0;
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(KEYBOARD_BASE..KEYBOARD_BASE + KEYBOARD_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        let mut state = self.state.borrow_mut();
+        match addr & !0x3 {
+            KEYBOARD_DATA => state.fifo.pop_front().unwrap_or(0),
+            KEYBOARD_STATUS => {
+                let mut status = 0;
+                if !state.fifo.is_empty() {
+                    status |= KEYBOARD_READY;
+                }
+                if state.overflow {
+                    status |= KEYBOARD_OVERFLOW;
+                    state.overflow = false;
+                }
+                status
+            }
+            KEYBOARD_CONTROL => state.control,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        if addr & !0x3 == KEYBOARD_CONTROL {
+            self.state.borrow_mut().control = value & KEYBOARD_INTERRUPT_ENABLE;
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        let state = self.state.borrow();
+        w.put_u32(state.fifo.len() as u32);
+        for event in state.fifo.iter() {
+            w.put_u32(*event);
+        }
+        w.put_bool(state.overflow);
+        w.put_u32(state.control);
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        let state = self.state.borrow();
+        state.control & KEYBOARD_INTERRUPT_ENABLE != 0 && !state.fifo.is_empty()
+    }
+}
@@ -0,0 +1,144 @@
+// The RISC-II emulator as a library.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Everything but the SDL front-end, so the emulator can be embedded in a
+// test harness or another front-end. A System is built from a Config and
+// runs whatever is loaded into its Memory, the other modules are the parts
+// it is made of and the tools around it (assembler, loader, tracing, ...).
+
+#[macro_use]
+extern crate assert_hex;
+extern crate core;
+#[macro_use]
+extern crate log;
+#[cfg(test)]
+mod alu_test;
+#[cfg(test)]
+mod asm_test;
+#[cfg(test)]
+mod block_test;
+#[cfg(test)]
+mod clock_test;
+#[cfg(test)]
+mod config_test;
+#[cfg(test)]
+mod cpu_test;
+#[cfg(test)]
+mod decode_test;
+#[cfg(test)]
+mod encode_test;
+#[cfg(test)]
+mod execute_test;
+#[cfg(test)]
+mod expr_test;
+#[cfg(test)]
+mod framebuffer_test;
+#[cfg(test)]
+mod fuzz_test;
+#[cfg(test)]
+mod hazard_test;
+#[cfg(test)]
+mod instruction_test;
+#[cfg(test)]
+mod loader_test;
+#[cfg(test)]
+mod logging_test;
+#[cfg(test)]
+mod memory_test;
+#[cfg(test)]
+mod region_test;
+#[cfg(all(test, feature = "remote"))]
+mod remote_test;
+#[cfg(test)]
+mod runner_test;
+#[cfg(test)]
+mod symbols_test;
+#[cfg(test)]
+mod system_test;
+#[cfg(test)]
+mod window_view_test;
+
+pub mod alignment;
+pub mod alu;
+pub mod anomaly;
+pub mod asm;
+pub mod audit;
+pub mod block;
+pub mod call_stack;
+pub mod clock;
+pub mod commit;
+pub mod config;
+pub mod console;
+pub mod coprocessor;
+pub mod cosim;
+pub mod cpu;
+pub mod data_path;
+pub mod data_path_view;
+pub mod decode;
+pub mod decode_cache;
+pub mod device;
+pub mod doorbell;
+pub mod engine;
+pub mod error;
+pub mod execute;
+pub mod expr;
+pub mod extension;
+#[cfg(feature = "fpu")]
+pub mod fpu;
+pub mod framebuffer;
+pub mod front_panel;
+pub mod gamepad;
+pub mod harness;
+pub mod hazard;
+pub mod hex_view;
+pub mod host;
+pub mod instruction;
+pub mod keyboard;
+pub mod loader;
+pub mod logging;
+pub mod memory;
+pub mod mmu;
+pub mod muldiv;
+pub mod pipeline;
+pub mod pipeline_debug;
+pub mod pipeline_history;
+pub mod profiler;
+pub mod r2d2;
+pub mod region;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod replay;
+pub mod rtc;
+pub mod runner;
+pub mod shifter;
+pub mod snapshot_diff;
+pub mod state;
+pub mod symbols;
+pub mod system;
+pub mod timer;
+pub mod trace;
+pub mod usage;
+pub mod util;
+pub mod window_view;
+
+pub use alu::ALU;
+pub use config::Config;
+pub use data_path::DataPath;
+pub use decode::decode;
+pub use error::EmulatorError;
+pub use instruction::Instruction;
+pub use loader::Image;
+pub use memory::Memory;
+pub use system::System;
@@ -0,0 +1,287 @@
+// Memory image loader.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Reads the files given with --image into memory before the system runs.
+// Three formats are understood, picked by the file's extension:
+//   Raw binary - copied byte for byte to the image's address.
+//   Intel HEX (.hex, .ihex) - text records carrying their own addresses,
+//     with 16 bit segment and 32 bit linear extended addresses.
+//   Motorola S-record (.srec, .s19, .s28, .s37, .mot) - text records with
+//     16, 24 or 32 bit addresses.
+// The address given with a HEX or S-record image is added to the addresses
+// in its records. Both formats may name an entry point, the system starts
+// there unless --entry says otherwise.
+// None of the formats carry symbols, an image's symbols are read from the
+// symbol files given with --symbols.
+
+use berr;
+use error::EmulatorError;
+use memory::Memory;
+use std::fmt;
+use std::fs;
+use util::Result;
+
+/// Format of a memory image file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// Raw binary.
+    Raw,
+    /// Intel HEX.
+    IntelHex,
+    /// Motorola S-record.
+    Srec,
+}
+
+/// Bytes loaded at consecutive addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Address of the first byte.
+    pub address: u32,
+    /// The bytes.
+    pub data: Vec<u8>,
+}
+
+/// Contents of a memory image file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// Bytes to load, in the order the file gives them.
+    pub chunks: Vec<Chunk>,
+    /// Address to start running at, if the file names one.
+    pub entry: Option<u32>,
+}
+
+impl Format {
+    /// Get the format of a file from its extension. Files without a known
+    /// extension are raw binaries.
+    /// # Arguments
+    /// * `path` - Path of the file.
+    pub fn from_path(path: &str) -> Self {
+        let extension = match path.rfind('.') {
+            Some(dot) if !path[dot..].contains('/') => path[dot + 1..].to_lowercase(),
+            _ => return Self::Raw,
+        };
+        match extension.as_str() {
+            "hex" | "ihex" => Self::IntelHex,
+            "srec" | "s19" | "s28" | "s37" | "mot" => Self::Srec,
+            _ => Self::Raw,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Raw => "raw",
+                Self::IntelHex => "Intel HEX",
+                Self::Srec => "S-record",
+            }
+        )
+    }
+}
+
+impl Image {
+    /// Read a memory image file, in the format its extension implies.
+    /// Return the image on success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the file.
+    /// * `address` - Where a raw image goes, added to the addresses of the
+    /// other formats.
+    pub fn read(path: &str, address: u32) -> Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(b) => b,
+            Err(e) => return Err(EmulatorError::io(format!("Could not read {}", path), e)),
+        };
+        match Self::parse(&bytes, Format::from_path(path), address) {
+            Ok(image) => Ok(image),
+            Err(e) => berr!(Format, format!("{}: {}", path, e)),
+        }
+    }
+
+    /// Parse the contents of a memory image file. Return the image on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `bytes` - Contents of the file.
+    /// * `format` - Format of the file.
+    /// * `address` - Where a raw image goes, added to the addresses of the
+    /// other formats.
+    pub fn parse(bytes: &[u8], format: Format, address: u32) -> Result<Self> {
+        if format == Format::Raw {
+            return Ok(Self {
+                chunks: vec![Chunk {
+                    address: address,
+                    data: bytes.to_vec(),
+                }],
+                entry: None,
+            });
+        }
+
+        let text = match std::str::from_utf8(bytes) {
+            Ok(t) => t,
+            Err(_) => return berr!(Format, format!("Not a text file, expected {}", format)),
+        };
+        let mut image = Self {
+            chunks: Vec::new(),
+            entry: None,
+        };
+        // Intel HEX data records are relative to the last extended address.
+        let mut base = 0;
+        let mut ended = false;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || ended {
+                continue;
+            }
+            let result = match format {
+                Format::IntelHex => image.parse_hex_record(line, address, &mut base),
+                _ => image.parse_srec_record(line, address),
+            };
+            match result {
+                Ok(end) => ended = end,
+                Err(e) => return berr!(Format, format!("line {}: {}", i + 1, e)),
+            }
+        }
+        Ok(image)
+    }
+
+    /// Get the number of bytes the image loads.
+    pub fn size(&self) -> usize {
+        self.chunks.iter().map(|c| c.data.len()).sum()
+    }
+
+    /// Copy the image into memory and make the memory it covers executable.
+    /// Return void on success and a string if it does not fit.
+    /// # Arguments
+    /// * `mem` - Memory to copy to.
+    pub fn write_to(&self, mem: &mut Memory) -> Result<()> {
+        for chunk in self.chunks.iter() {
+            mem.write_buf(chunk.address, &chunk.data)?;
+            mem.regions_mut()
+                .mark_executable(chunk.address, chunk.data.len() as u32);
+        }
+        Ok(())
+    }
+
+    /// Parse one Intel HEX record. Return true if it ends the file.
+    fn parse_hex_record(&mut self, line: &str, offset: u32, base: &mut u32) -> Result<bool> {
+        if !line.starts_with(':') {
+            return berr!(Format, "Intel HEX records start with ':'");
+        }
+        let bytes = parse_record_bytes(&line[1..])?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return berr!(Format, "Intel HEX record has the wrong length");
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return berr!(Format, "Intel HEX record has a bad checksum");
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data.len()) {
+            (0x00, _) => self.push(offset.wrapping_add(*base).wrapping_add(address), data),
+            (0x01, _) => return Ok(true),
+            // Extended segment address, a real mode segment.
+            (0x02, 2) => *base = u16::from_be_bytes([data[0], data[1]]) as u32 * 16,
+            // Start segment address, a real mode CS:IP.
+            (0x03, 4) => {
+                let cs = u16::from_be_bytes([data[0], data[1]]) as u32;
+                let ip = u16::from_be_bytes([data[2], data[3]]) as u32;
+                self.entry = Some(offset.wrapping_add(cs * 16 + ip));
+            }
+            // Extended linear address, the upper 16 bits.
+            (0x04, 2) => *base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            // Start linear address.
+            (0x05, 4) => {
+                self.entry = Some(
+                    offset.wrapping_add(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+                )
+            }
+            (kind, _) => return berr!(Format, format!("Invalid Intel HEX record type {:02x}", kind)),
+        }
+        Ok(false)
+    }
+
+    /// Parse one S-record. Return true if it ends the file.
+    fn parse_srec_record(&mut self, line: &str, offset: u32) -> Result<bool> {
+        let kind = match line.as_bytes() {
+            [b'S', kind, ..] if kind.is_ascii_digit() => kind - b'0',
+            _ => return berr!(Format, "S-records start with 'S' and a digit"),
+        };
+        let bytes = parse_record_bytes(&line[2..])?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return berr!(Format, "S-record has the wrong length");
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+            return berr!(Format, "S-record has a bad checksum");
+        }
+        let address_len = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return berr!(Format, format!("Invalid S-record type S{}", kind)),
+        };
+        if bytes.len() < address_len + 2 {
+            return berr!(Format, "S-record is too short for its address");
+        }
+        let address = bytes[1..address_len + 1]
+            .iter()
+            .fold(0u32, |a, b| a << 8 | *b as u32);
+        let data = &bytes[address_len + 1..bytes.len() - 1];
+        match kind {
+            1 | 2 | 3 => self.push(offset.wrapping_add(address), data),
+            7 | 8 | 9 => {
+                self.entry = Some(offset.wrapping_add(address));
+                return Ok(true);
+            }
+            // Headers and record counts.
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// Add bytes to the image, extending the last chunk if they follow it.
+    fn push(&mut self, address: u32, data: &[u8]) {
+        if let Some(last) = self.chunks.last_mut() {
+            if last.address.wrapping_add(last.data.len() as u32) == address {
+                last.data.extend_from_slice(data);
+                return;
+            }
+        }
+        self.chunks.push(Chunk {
+            address: address,
+            data: data.to_vec(),
+        });
+    }
+}
+
+// Private functions.
+
+/// Parse the hexadecimal digits of a record into bytes. Return the bytes on
+/// success and a string on error.
+/// # Arguments
+/// * `digits` - Pairs of hexadecimal digits.
+fn parse_record_bytes(digits: &str) -> Result<Vec<u8>> {
+    if digits.len() % 2 != 0 || !digits.is_ascii() {
+        return berr!(Format, "Record has an odd number of digits");
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| match u8::from_str_radix(&digits[i..i + 2], 16) {
+            Ok(b) => Ok(b),
+            Err(_) => berr!(Format, format!("Invalid hexadecimal byte {}", &digits[i..i + 2])),
+        })
+        .collect()
+}
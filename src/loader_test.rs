@@ -0,0 +1,108 @@
+// Test code for the memory image loader.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "loader.rs"]
+mod test {
+    use super::super::*;
+    use loader::*;
+    use memory::Memory;
+    use util::Result;
+
+    #[test]
+    fn formats_follow_extensions() {
+        assert_eq!(Format::from_path("boot.bin"), Format::Raw);
+        assert_eq!(Format::from_path("boot"), Format::Raw);
+        assert_eq!(Format::from_path("out.d/boot"), Format::Raw);
+        assert_eq!(Format::from_path("boot.HEX"), Format::IntelHex);
+        assert_eq!(Format::from_path("boot.ihex"), Format::IntelHex);
+        assert_eq!(Format::from_path("boot.srec"), Format::Srec);
+        assert_eq!(Format::from_path("boot.s19"), Format::Srec);
+    }
+
+    #[test]
+    fn raw_images_load_at_their_address() -> Result<()> {
+        let image = Image::parse(&[1, 2, 3], Format::Raw, 0x10)?;
+        assert_eq!(image.entry, None);
+        let mut mem = Memory::from_size(0x40);
+        image.write_to(&mut mem)?;
+        assert_eq!(mem.get_bytes(0x10, 3)?, &[1, 2, 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn intel_hex_images_load() -> Result<()> {
+        let hex = ":0400100001020304E2\n\
+                   :020014000506DF\n\
+                   :020000040001F9\n\
+                   :01000000FF00\n\
+                   :0400000500000010E7\n\
+                   :00000001FF\n";
+        let image = Image::parse(hex.as_bytes(), Format::IntelHex, 0)?;
+        assert_eq!(
+            image.chunks,
+            vec![
+                Chunk {
+                    address: 0x10,
+                    data: vec![1, 2, 3, 4, 5, 6],
+                },
+                Chunk {
+                    address: 0x10000,
+                    data: vec![0xff],
+                },
+            ]
+        );
+        assert_eq!(image.entry, Some(0x10));
+        assert_eq!(image.size(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn srec_images_load() -> Result<()> {
+        let srec = "S00600004844521B\n\
+                    S107001001020304DE\n\
+                    S9030010EC\n";
+        let image = Image::parse(srec.as_bytes(), Format::Srec, 0x100)?;
+        assert_eq!(
+            image.chunks,
+            vec![Chunk {
+                address: 0x110,
+                data: vec![1, 2, 3, 4],
+            }]
+        );
+        assert_eq!(image.entry, Some(0x110));
+        Ok(())
+    }
+
+    #[test]
+    fn bad_records_are_rejected() {
+        // Bad checksums.
+        assert!(Image::parse(b":0400100001020304E3\n", Format::IntelHex, 0).is_err());
+        assert!(Image::parse(b"S107001001020304DF\n", Format::Srec, 0).is_err());
+        // Wrong lengths.
+        assert!(Image::parse(b":0500100001020304E1\n", Format::IntelHex, 0).is_err());
+        assert!(Image::parse(b"S10800100102030499\n", Format::Srec, 0).is_err());
+        // Not records at all.
+        assert!(Image::parse(b"0400100001020304E2\n", Format::IntelHex, 0).is_err());
+        assert!(Image::parse(b":04001000010203ZZE2\n", Format::IntelHex, 0).is_err());
+    }
+
+    #[test]
+    fn images_must_fit_in_memory() -> Result<()> {
+        let image = Image::parse(&[0; 8], Format::Raw, 0x3c)?;
+        assert!(image.write_to(&mut Memory::from_size(0x40)).is_err());
+        Ok(())
+    }
+}
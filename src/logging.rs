@@ -0,0 +1,183 @@
+// Log messages of the emulator, with a level per subsystem.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// The emulator logs through the `log` facade, so a program embedding it can
+// install a logger of its own. The one here writes to standard error and
+// takes its levels from a spec like `pipeline=debug,system=warn`: each entry
+// names a subsystem, which is a module of the emulator (`pipeline` covers
+// everything logged from src/pipeline.rs), and an entry without a name sets
+// the level of the rest. Messages from a core go through `core_log!`, which
+// leads them with the clock cycle and the PC.
+
+use log::{set_logger, set_max_level, Level, LevelFilter, Log, Metadata, Record};
+use std::io::{self, Write};
+use util::Result;
+
+use berr;
+
+/// Level of the subsystems a spec does not name.
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Warn;
+/// Crate the subsystems are modules of.
+const CRATE_NAME: &str = "riscii";
+
+/// Log a message from a core, led by the clock cycle and the PC, e.g.
+/// `core_log!(Level::Warn, cycle, pc, "Bad mem read: {}", address)`.
+#[macro_export]
+macro_rules! core_log {
+    ( $level:expr, $cycle:expr, $pc:expr, $($arg:tt)+ ) => {
+        log!(
+            $level,
+            "cycle {} pc {:08x}: {}",
+            $cycle,
+            $pc,
+            format_args!($($arg)+)
+        )
+    };
+}
+
+/// Levels of the subsystems.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogSpec {
+    /// Level of the subsystems not named.
+    default: LevelFilter,
+    /// Subsystems and their levels, in the order given.
+    subsystems: Vec<(String, LevelFilter)>,
+}
+
+/// Logger writing to standard error.
+struct Logger {
+    spec: LogSpec,
+}
+
+impl LogSpec {
+    /// Parse a spec, e.g. `pipeline=debug,system=warn` or `info`. Return
+    /// the spec on success and a string naming the bad entry on error.
+    /// # Arguments
+    /// * `spec` - The spec, empty for the default level everywhere.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut result = Self {
+            default: DEFAULT_LOG_LEVEL,
+            subsystems: Vec::new(),
+        };
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (name, level) = match entry.find('=') {
+                Some(i) => (Some(entry[..i].trim()), entry[i + 1..].trim()),
+                None => (None, entry),
+            };
+            let level = match level.parse::<LevelFilter>() {
+                Ok(l) => l,
+                Err(_) => {
+                    return berr!(
+                        Config,
+                        format!(
+                            "Invalid log level {} in {}, expected off, error, warn, info, debug or trace",
+                            level, entry
+                        )
+                    )
+                }
+            };
+            match name {
+                Some("") => return berr!(Config, format!("Missing subsystem in {}", entry)),
+                Some(name) => result.subsystems.push((name.to_string(), level)),
+                None => result.default = level,
+            }
+        }
+        Ok(result)
+    }
+
+    /// Get the level of the subsystem a message was logged from.
+    /// # Arguments
+    /// * `target` - Target of the message, the path of the module it was
+    /// logged from.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        let module = target
+            .strip_prefix(CRATE_NAME)
+            .and_then(|t| t.strip_prefix("::"))
+            .unwrap_or(target);
+        // A later entry overrides an earlier one.
+        self.subsystems
+            .iter()
+            .rev()
+            .find(|(name, _)| module.split("::").next() == Some(name.as_str()))
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Get the most verbose level of any subsystem.
+    pub fn max_level(&self) -> LevelFilter {
+        self.subsystems
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |a, b| a.max(b))
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.spec.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let module = record
+            .target()
+            .strip_prefix(CRATE_NAME)
+            .and_then(|t| t.strip_prefix("::"))
+            .unwrap_or(record.target());
+        // Nowhere to report a failure to log.
+        let _ = writeln!(
+            io::stderr(),
+            "[{} {}] {}",
+            level_name(record.level()),
+            module,
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+// Public functions.
+
+/// Install the logger, writing to standard error at the levels of a spec.
+/// Return void on success and a string if a logger was installed already.
+/// # Arguments
+/// * `spec` - Levels of the subsystems.
+pub fn init(spec: &LogSpec) -> Result<()> {
+    let logger = Box::new(Logger { spec: spec.clone() });
+    match set_logger(Box::leak(logger)) {
+        Ok(()) => {
+            set_max_level(spec.max_level());
+            Ok(())
+        }
+        Err(_) => berr!(Config, "A logger is installed already"),
+    }
+}
+
+// Private functions.
+
+/// Get the name of a level as the logger writes it.
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
@@ -0,0 +1,50 @@
+// Test code for log levels.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "logging.rs"]
+mod test {
+    use super::super::*;
+    use log::LevelFilter;
+    use logging::*;
+    use util::Result;
+
+    #[test]
+    fn log_specs_parse() -> Result<()> {
+        let spec = LogSpec::parse("")?;
+        assert_eq!(spec.level("riscii::pipeline"), DEFAULT_LOG_LEVEL);
+        assert_eq!(spec.max_level(), DEFAULT_LOG_LEVEL);
+
+        let spec = LogSpec::parse("pipeline=debug, system=error,info")?;
+        assert_eq!(spec.level("riscii::pipeline"), LevelFilter::Debug);
+        assert_eq!(spec.level("riscii::system"), LevelFilter::Error);
+        assert_eq!(spec.level("riscii::memory"), LevelFilter::Info);
+        assert_eq!(spec.level("pipeline"), LevelFilter::Debug);
+        assert_eq!(spec.level("riscii::pipeline_debug"), LevelFilter::Info);
+        assert_eq!(spec.max_level(), LevelFilter::Debug);
+
+        let spec = LogSpec::parse("system=trace,SYSTEM=off")?;
+        assert_eq!(spec.level("riscii::system"), LevelFilter::Trace);
+        assert_eq!(
+            LogSpec::parse("system=trace,system=off")?.level("riscii::system"),
+            LevelFilter::Off
+        );
+
+        assert!(LogSpec::parse("pipeline=loud").is_err());
+        assert!(LogSpec::parse("=debug").is_err());
+        assert!(LogSpec::parse("verbose").is_err());
+        Ok(())
+    }
+}
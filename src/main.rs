@@ -13,120 +13,525 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(feature = "sdl")]
 #[macro_use]
-extern crate assert_hex;
-extern crate core;
+extern crate log;
+extern crate riscii;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 #[cfg(test)]
-mod decode_test;
-#[cfg(test)]
-mod encode_test;
-#[cfg(test)]
 mod main_test;
 
-// Modules declared as pub to shut up rust-analyzer about dead code.
-pub mod alu;
-pub mod clock;
-pub mod config;
-pub mod cpu;
-pub mod data_path;
+// The front-end's modules. They use the library's modules as if they were
+// part of this crate.
+#[cfg(feature = "sdl")]
 pub mod debug_window;
-pub mod decode;
-pub mod instruction;
-pub mod memory;
+#[cfg(feature = "sdl")]
+pub mod main_window;
+#[cfg(feature = "sdl")]
 pub mod sdl;
-pub mod shifter;
-pub mod system;
-pub mod util;
-
-use config::Config;
-use debug_window::DebugWindow;
-use sdl::{make_font_context, Context, Drawable};
-use sdl2::event::{Event, WindowEvent};
+#[cfg(feature = "sdl")]
+pub mod window_manager;
+
+use riscii::*;
+
+use asm::{assemble_with, disassemble};
+use audit::Audit;
+use call_stack::CallStack;
+use config::{AsmArgs, Command, Config, DisasmArgs, ReportFormat, TestArgs};
+use cosim::Cosim;
+use error::EmulatorError;
+#[cfg(feature = "sdl")]
+use framebuffer::Framebuffer;
+use harness::TestProgram;
+use hazard::find_hazards;
+use loader::Image;
+use logging::LogSpec;
+#[cfg(feature = "sdl")]
+use main_window::Link;
+#[cfg(feature = "remote")]
+use remote::RemoteServer;
+use replay::read_inputs;
+#[cfg(feature = "sdl")]
+use runner::Runner;
+#[cfg(feature = "sdl")]
+use sdl::{make_font_context, Context};
+use snapshot_diff::SnapshotDiff;
+use state::{export_state, MemoryRange};
+#[cfg(feature = "sdl")]
 use std::cell::RefCell;
-use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::process;
+#[cfg(feature = "sdl")]
 use std::rc::Rc;
+use std::time::Instant;
+use symbols::Symbols;
+#[cfg(feature = "sdl")]
+use system::RunState;
 use system::System;
+use system::LIMIT_EXIT_CODE;
+use util::{concat_paths, Result};
+#[cfg(feature = "sdl")]
+use window_manager::{GlobalAction, WindowManager};
 
-// Struct/enum declarations.
+/// Run `system` as fast as possible without any windows until it halts.
+fn run_headless(system: &mut System) -> Result<()> {
+    let start = Instant::now();
+    let count = system.run_until_halt()?;
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "{} after {} instructions in {:.3}s ({:.2} MIPS).",
+        match system.exit_code() {
+            Some(code) => format!("Exited with code {}", code),
+            None => match system.limit_hit() {
+                Some(limit) => limit.to_string(),
+                None if system.is_paused() => "Paused".to_string(),
+                None => "Halted".to_string(),
+            },
+        },
+        count,
+        seconds,
+        count as f64 / seconds / 1_000_000.0
+    );
+    if system.limit_hit().is_some() {
+        print!(
+            "Stopped on cycle {} in:\n{}",
+            system.clock().count(),
+            CallStack::new(system.data_path(), system.symbols())
+        );
+    }
+    if let Some(profiler) = system.profiler() {
+        print!("{}", profiler.report(system.symbols(), ReportFormat::Text)?);
+    }
+    if let Some(stats) = system.alignment_stats() {
+        print!("{}", stats);
+    }
+    if let Some(stats) = system.decode_cache_stats() {
+        print!("{}", stats);
+    }
+    if let Some(detector) = system.anomalies() {
+        println!("{} anomalies found.", detector.found().len());
+    }
+    if let Some(detector) = system.hazards() {
+        println!("{} hazards found.", detector.found().len());
+    }
+    Ok(())
+}
 
-enum GlobalAction {
-    None,
-    QuitProgram,
-    CloseDebugWindow,
+/// Run `system` without any windows, serving its state on `address`, until
+/// a client asks to quit.
+#[cfg(feature = "remote")]
+fn run_remote(address: &str, system: &mut System) -> Result<()> {
+    let mut server = RemoteServer::bind(address, system)?;
+    println!("Serving the system on http://{}.", server.local_addr()?);
+    server.serve(system)
 }
 
-fn handle_events(context: &mut Context, debug_window: &mut DebugWindow) -> GlobalAction {
-    let event_pump = &mut context.event_pump;
-    let mut result = GlobalAction::None;
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. } => {
-                return GlobalAction::QuitProgram;
-            }
-            Event::Window {
-                win_event: WindowEvent::Close,
-                ..
-            } => {
-                return GlobalAction::QuitProgram;
-            }
-            Event::KeyDown {
-                keycode: Some(kc), ..
-            } => {
-                debug_window.handle_key_down(kc);
-            }
-            Event::KeyUp {
-                keycode: Some(kc), ..
-            } => {
-                debug_window.handle_key_up(kc);
+#[cfg(not(feature = "remote"))]
+fn run_remote(_address: &str, _system: &mut System) -> Result<()> {
+    berr!(
+        Config,
+        "--remote needs the emulator built with the remote feature"
+    )
+}
+
+/// Create the system, restoring the configured snapshot if there is one and
+/// then loading the configured memory images.
+fn make_system(config: &Config) -> Result<System> {
+    let mut system = System::new(&config)?;
+    if let Some(path) = config.get_load_snapshot() {
+        system.load_snapshot(path)?;
+        println!("Restored snapshot {}.", path);
+    }
+    let mut entry = None;
+    for load in config.get_loads() {
+        let image = Image::read(&load.path, load.address)?;
+        system.load_image(&image)?;
+        println!(
+            "Loaded {} (0x{:x} bytes) at 0x{:08x}.",
+            load.path,
+            image.size(),
+            image.chunks.first().map_or(load.address, |c| c.address)
+        );
+        entry = image.entry.or(entry);
+    }
+    if let Some(pc) = config.get_entry().or(entry) {
+        system.set_entry(pc);
+        println!("Starting at 0x{:08x}.", pc);
+    }
+    if let Some(path) = config.get_replay_inputs() {
+        system.inputs().replay(read_inputs(path)?);
+        println!("Replaying the inputs in {}.", path);
+    } else if config.get_record_inputs().is_some() {
+        system.inputs().record();
+    }
+    Ok(system)
+}
+
+/// Assemble a source file into a memory image.
+/// # Arguments
+/// * `args` - What to assemble and how.
+fn run_asm(args: &AsmArgs) -> Result<()> {
+    let source = match fs::read_to_string(&args.input) {
+        Ok(s) => s,
+        Err(e) => {
+            return Err(EmulatorError::io(
+                format!("Could not read {}", args.input),
+                e,
+            ))
+        }
+    };
+    let program = assemble_with(&source, args.origin, &args.options)?;
+    fs::write(&args.output, program.to_bytes(args.endian))?;
+    println!(
+        "Assembled {} words to {}. {}",
+        program.words.len(),
+        args.output,
+        program.report
+    );
+    if let Some(path) = args.symbols.as_ref() {
+        let symbols = program.symbols();
+        symbols.write(path)?;
+        println!("Wrote {} symbols to {}.", symbols.len(), path);
+    }
+    Ok(())
+}
+
+/// Print the disassembly of a memory image.
+/// # Arguments
+/// * `args` - What to disassemble.
+fn run_disasm(args: &DisasmArgs) -> Result<()> {
+    let image = match fs::read(&args.input) {
+        Ok(b) => b,
+        Err(e) => {
+            return Err(EmulatorError::io(
+                format!("Could not read {}", args.input),
+                e,
+            ))
+        }
+    };
+    let mut symbols = Symbols::new();
+    for path in args.symbols.iter() {
+        symbols.extend(&Symbols::read(path)?);
+    }
+    print!(
+        "{}",
+        disassemble(&image, args.origin, args.syntax, &symbols, args.endian)
+    );
+    if args.hazards {
+        let hazards = find_hazards(&image, args.origin, args.endian);
+        for hazard in hazards.iter() {
+            println!("{}", hazard);
+        }
+        println!("{} hazards found.", hazards.len());
+    }
+    Ok(())
+}
+
+/// Describe a snapshot file: its engine, clock and the state of every core.
+/// # Arguments
+/// * `config` - Configuration of the system the snapshot is of.
+/// * `path` - Path of the snapshot file.
+fn describe_snapshot(config: &Config, path: &String) -> Result<()> {
+    let mut system = System::new(&config)?;
+    system.load_snapshot(path)?;
+    println!(
+        "Snapshot {}: {} engine, clock cycle {}, {} cores.",
+        path,
+        system.engine(),
+        system.clock().count(),
+        system.cores().len()
+    );
+    for (id, core) in system.cores().iter().enumerate() {
+        let dp = core.data_path();
+        println!(
+            "core {}: pc 0x{:08x}{}, psw {}",
+            id,
+            dp.get_pc(),
+            system
+                .symbols()
+                .describe(dp.get_pc())
+                .map_or(String::new(), |s| format!(" <{}>", s)),
+            dp.get_psw()
+        );
+        println!("{:#x}", dp.register_file().dump(dp.get_psw().get_cwp()));
+    }
+    Ok(())
+}
+
+/// Print what differs between two snapshot files.
+/// # Arguments
+/// * `config` - Configuration the systems are created with.
+/// * `a` - Path of the first snapshot.
+/// * `b` - Path of the second snapshot.
+fn diff_snapshots(config: &Config, a: &String, b: &String) -> Result<()> {
+    let mut first = System::new(&config)?;
+    first.load_snapshot(a)?;
+    let mut second = System::new(&config)?;
+    second.load_snapshot(b)?;
+    println!("Snapshot {} -> {}:", a, b);
+    print!("{}", SnapshotDiff::new(&first, &second));
+    Ok(())
+}
+
+/// Run test programs on each engine asked for, writing a failure bundle for
+/// every run that does not pass. Exit with 1 if any did not.
+/// # Arguments
+/// * `config` - Configuration of the systems the programs run on.
+/// * `args` - Programs to run and how.
+fn run_tests(config: &Config, args: &TestArgs) -> Result<()> {
+    let bundles = match args.bundles {
+        Some(ref dir) => dir.clone(),
+        None => concat_paths(config.get_cache_path(), &"bundles".to_string())?,
+    };
+    let mut programs = Vec::new();
+    for path in args.paths.iter() {
+        if Path::new(path).is_dir() {
+            programs.extend(harness::read_dir(path)?);
+        } else {
+            programs.push(TestProgram::read(path)?);
+        }
+    }
+    let mut failures = 0;
+    for program in programs.iter() {
+        for engine in args.engines.iter() {
+            match program.run_with_bundle(config, *engine, args.budget, &bundles)? {
+                (outcome, Some(bundle)) => {
+                    failures += 1;
+                    println!(
+                        "{} on {}: {}, see {}",
+                        program.path, engine, outcome, bundle
+                    );
+                }
+                (outcome, None) => println!("{} on {}: {}", program.path, engine, outcome),
             }
-            _ => {}
         }
     }
-    return result;
+    let runs = programs.len() * args.engines.len();
+    println!("{} of {} runs passed.", runs - failures, runs);
+    if failures > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Report the host memory `system` used, finish its trace, list the
+/// snapshots taken during the session and save a snapshot, dump of its
+/// state or the inputs it got if the user asked for one.
+fn finish_run(config: &Config, system: &mut System) -> Result<()> {
+    if config.is_reporting_usage() {
+        print!("{}", system.usage());
+    }
+    system.set_tracer(None)?;
+    system.set_pipeline_log(None)?;
+    if let Some(trigger) = system.snapshot_trigger() {
+        println!(
+            "Captured {} snapshots when {}.",
+            trigger.captured().len(),
+            trigger.condition()
+        );
+    }
+    let snapshots = system.snapshots();
+    if !snapshots.snapshots().is_empty() {
+        println!("Snapshots taken this session (in {}):", snapshots.dir());
+        for snapshot in snapshots.snapshots() {
+            println!("  {}", snapshot);
+        }
+    }
+    if let Some(path) = config.get_record_inputs() {
+        system.inputs().save(path)?;
+        println!("Wrote the inputs to {}.", path);
+    }
+    if let Some(cycle) = system.inputs().diverged() {
+        eprintln!(
+            "The replay diverged on cycle {}, the guest read the time of day when the recorded run did not.",
+            cycle
+        );
+    }
+    if let Some(path) = config.get_save_snapshot() {
+        system.save_snapshot(path)?;
+        println!("Saved snapshot {}.", path);
+    }
+    if let (Some(profiler), Some(path)) = (system.profiler(), config.get_profile_report()) {
+        fs::write(
+            path,
+            profiler.report(system.symbols(), config.get_profile_format())?,
+        )?;
+        println!("Wrote the profile to {}.", path);
+    }
+    if let Some(path) = config.get_export_state() {
+        let ranges = MemoryRange::parse_list(config.get_export_memory())?;
+        fs::write(
+            path,
+            export_state(system, &ranges, config.get_export_format())?,
+        )?;
+        println!("Exported state to {}.", path);
+    }
+    Ok(())
+}
+
+/// Run the program on both engines side by side until they disagree or it
+/// halts.
+fn run_cosim(config: &Config) -> Result<()> {
+    // Only the functional engine's system traces and captures snapshots,
+    // only the pipeline's logs its inner workings. The functional one is
+    // created second so that its trace replaces the pipeline's.
+    let mut cycle = make_system(&config)?;
+    cycle.set_tracer(None)?;
+    cycle.set_snapshot_trigger(None);
+    cycle.snapshots_mut().set_period(0);
+    let mut functional = make_system(&config)?;
+    functional.set_pipeline_log(None)?;
+    let mut cosim = Cosim::new(functional, cycle);
+    match cosim.run_until_halt()? {
+        Some(divergence) => {
+            print!("{}", divergence);
+            berr!(Mismatch, "The engines diverged")
+        }
+        None => {
+            println!("Engines agreed on all {} instructions.", cosim.retired());
+            Ok(())
+        }
+    }
+}
+
+/// Run the program twice until the runs differ or it halts.
+fn run_audit(config: &Config) -> Result<()> {
+    let mut audit = Audit::new(
+        || {
+            // Only observe the program, do not write anything.
+            let mut system = make_system(&config)?;
+            system.set_tracer(None)?;
+            system.set_pipeline_log(None)?;
+            system.set_snapshot_trigger(None);
+            system.snapshots_mut().set_period(0);
+            Ok(system)
+        },
+        config.get_audit_every() as u64,
+    );
+    match audit.run_until_halt()? {
+        Some(nondeterminism) => {
+            print!("{}", nondeterminism);
+            berr!(Mismatch, "The emulator is not deterministic")
+        }
+        None => {
+            println!("Runs agreed on all {} instructions.", audit.retired());
+            Ok(())
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> Result<()> {
     let config = Config::init()?;
+    logging::init(&LogSpec::parse(config.get_log())?)?;
+    match config.get_command() {
+        Command::Asm(args) => return run_asm(args),
+        Command::Disasm(args) => return run_disasm(args),
+        Command::Snapshot(path) => return describe_snapshot(&config, path),
+        Command::SnapshotDiff(a, b) => return diff_snapshots(&config, a, b),
+        Command::Test(args) => return run_tests(&config, args),
+        Command::DumpConfig => {
+            print!("{}", config.to_toml()?);
+            return Ok(());
+        }
+        Command::Run | Command::Debug => {}
+    }
 
     println!(
         "Running emulator with the following configuration: \n{}\n",
         config
     );
-    let system = Rc::new(RefCell::new(System::new(&config)?));
-    //println!("Opening binary file {}.", path);
-    //let program = fs::read(path)?;
-    let mut sdl_context = Context::new()?;
-    let mut font_context = make_font_context()?;
-
-    let mut debug_window = if config.is_debug_mode() {
-        Some(DebugWindow::new(
-            &config,
-            system.clone(),
-            &mut sdl_context,
-            &mut font_context,
-        )?)
+
+    if config.is_cosim() {
+        return run_cosim(&config);
+    }
+    if config.is_auditing_determinism() {
+        return run_audit(&config);
+    }
+    #[cfg(feature = "sdl")]
+    {
+        if !config.is_headless() && config.get_remote().is_none() {
+            return run_windowed(&config);
+        }
+    }
+    let mut system = make_system(&config)?;
+    match config.get_remote() {
+        Some(address) => run_remote(address, &mut system)?,
+        None => run_headless(&mut system)?,
+    }
+    finish_run(&config, &mut system)?;
+    match system.exit_code() {
+        Some(code) => process::exit(code as i32),
+        None if system.limit_hit().is_some() => process::exit(LIMIT_EXIT_CODE),
+        None => Ok(()),
+    }
+}
+
+/// Run the system with its windows until the user quits. The system runs
+/// on a thread of its own unless the debug window may look at it.
+#[cfg(feature = "sdl")]
+fn run_windowed(config: &Config) -> Result<()> {
+    let system = if config.is_debug_mode() {
+        Some(Rc::new(RefCell::new(make_system(&config)?)))
     } else {
         None
     };
+    let link = match system.as_ref() {
+        Some(system) => Link::Local(system.clone()),
+        None => {
+            let make_config = config.clone();
+            let finish_config = config.clone();
+            Link::Remote(Runner::spawn(
+                Framebuffer::from_config(config),
+                move || make_system(&make_config),
+                move |system| finish_windowed(&finish_config, system),
+            ))
+        }
+    };
+    let mut sdl_context = Context::new()?;
+    let font_context = make_font_context()?;
 
-    'running: loop {
-        system.borrow_mut().tick();
-        debug_window = if let Some(mut win) = debug_window {
-            match { handle_events(&mut sdl_context, &mut win) } {
-                GlobalAction::QuitProgram => {
-                    break 'running;
-                }
-                GlobalAction::CloseDebugWindow => {
-                    debug_window = None;
-                }
-                _ => {}
-            }
-            win.draw(&mut sdl_context)?;
-            Some(win)
-        } else {
-            None
-        };
+    let mut windows = WindowManager::new(&config, link, &mut sdl_context, &font_context)?;
+
+    loop {
+        if let Some(system) = system.as_ref() {
+            system.borrow_mut().run_frame()?;
+        }
+        if windows.handle_events(&mut sdl_context)? == GlobalAction::QuitProgram {
+            break;
+        }
+        windows.draw(&mut sdl_context)?;
+        match system.as_ref() {
+            Some(system) if system.borrow().run_state() == RunState::Running => {}
+            _ => windows.wait_for_frame(),
+        }
     }
-    Ok(())
+    windows.close()?;
+    match system {
+        Some(system) => finish_windowed(&config, &mut system.borrow_mut()),
+        None => Ok(()),
+    }
+}
+
+/// Report what the windowed system's profiler, alignment statistics,
+/// decode cache and anomaly detector found, then finish the run.
+#[cfg(feature = "sdl")]
+fn finish_windowed(config: &Config, system: &mut System) -> Result<()> {
+    if let Some(profiler) = system.profiler() {
+        print!("{}", profiler.report(system.symbols(), ReportFormat::Text)?);
+    }
+    if let Some(stats) = system.alignment_stats() {
+        print!("{}", stats);
+    }
+    if let Some(stats) = system.decode_cache_stats() {
+        print!("{}", stats);
+    }
+    if let Some(detector) = system.anomalies() {
+        println!("{} anomalies found.", detector.found().len());
+    }
+    if let Some(detector) = system.hazards() {
+        println!("{} hazards found.", detector.found().len());
+    }
+    finish_run(config, system)
 }
@@ -0,0 +1,413 @@
+// RISC II emulator main window.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use config::Config;
+use error::EmulatorError;
+use framebuffer::Framebuffer;
+use front_panel::{FrontPanel, PSW_LED_NAMES};
+use replay::Input;
+use runner::{Runner, RunnerCommand, RunnerEvent, RunnerStatus};
+use sdl::{load_font, Context, Drawable, GamepadSink, Pane};
+use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::Texture;
+use sdl2::ttf::{Font, Sdl2TtfContext};
+use std::cell::RefCell;
+use std::rc::Rc;
+use system::{RunState, System};
+use util::Result;
+
+/// Color of the front panel's labels.
+const PANEL_TEXT_COLOR: Color = Color::RGB(0xFF, 0xFF, 0xFF);
+/// Color of a lit LED.
+const LED_ON_COLOR: Color = Color::RGB(0xFa, 0x10, 0x10);
+/// Color of an unlit LED.
+const LED_OFF_COLOR: Color = Color::RGB(0x40, 0x08, 0x08);
+/// Radius of an LED.
+const LED_RADIUS: i16 = 12;
+/// Distance between two LEDs of a row.
+const LED_SPACING: i32 = 40;
+/// Radius of a window pointer dial.
+const DIAL_RADIUS: i16 = 100;
+/// Height of a line of text.
+const TEXT_HEIGHT: u32 = 40;
+/// Width of a character of the panel's font.
+const CHAR_WIDTH: u32 = 18;
+
+/// Where the main window finds the system it shows.
+pub enum Link {
+    /// The system runs on this thread.
+    Local(Rc<RefCell<System>>),
+    /// The system runs on a thread of its own.
+    Remote(Runner),
+}
+
+/// Window showing the system's display, the framebuffer if there is one and
+/// a front panel of the boot core otherwise. Keys pressed in it go to the
+/// guest's keyboard, the host's game controllers to the guest's gamepad.
+pub struct MainWindow<'a> {
+    pane: Pane,
+    link: Link,
+    /// Last status of a system on a thread of its own, None until it sends
+    /// one.
+    status: Option<RunnerStatus>,
+    font: Font<'a, 'static>,
+    /// Framebuffer to show, None to show nothing.
+    framebuffer: Option<Framebuffer>,
+    /// Pixels of the last frame, 24 bit RGB.
+    pixels: Vec<u8>,
+    /// Texture the framebuffer is copied to, None without a framebuffer.
+    texture: Option<Texture>,
+}
+
+impl<'a> MainWindow<'a> {
+    /// Open the main window. Return the window on success and a string on
+    /// error, including when the framebuffer does not fit in memory.
+    /// # Arguments
+    /// * `config` - Configuration giving the window size and framebuffer.
+    /// * `link` - Where the system is.
+    /// * `context` - SDL context.
+    /// * `ttf` - Font context the front panel's font is loaded from.
+    pub fn new(
+        config: &Config,
+        link: Link,
+        context: &mut Context,
+        ttf: &'a Sdl2TtfContext,
+    ) -> Result<Self> {
+        let framebuffer = Framebuffer::from_config(config);
+        let mut pixels = Vec::new();
+        if let (Some(fb), Link::Local(system)) = (framebuffer.as_ref(), &link) {
+            fb.render(system.borrow().memory(), &mut pixels)?;
+        }
+        let pane = Pane::new(config.get_window_layout().main, format!("RISC II"), context)?;
+        let texture = match framebuffer.as_ref() {
+            Some(fb) => Some(
+                pane.texture_creator
+                    .create_texture_streaming(PixelFormatEnum::RGB24, fb.width(), fb.height())
+                    .map_err(EmulatorError::sdl)?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            pane: pane,
+            link: link,
+            status: None,
+            font: load_font(ttf, config)?,
+            framebuffer: framebuffer,
+            pixels: pixels,
+            texture: texture,
+        })
+    }
+
+    /// Take the events a system on a thread of its own sent. Return true
+    /// if it changed, an error if it failed.
+    pub fn update(&mut self) -> Result<bool> {
+        let runner = match self.link {
+            Link::Local(_) => return Ok(false),
+            Link::Remote(ref runner) => runner,
+        };
+        let mut changed = false;
+        for event in runner.poll() {
+            match event {
+                RunnerEvent::Status(status) => {
+                    self.status = Some(status);
+                    changed = true;
+                }
+                RunnerEvent::Breakpoint(pc) => println!("Breakpoint at 0x{:08x}.", pc),
+                RunnerEvent::Halted(code) => println!("Halted with exit code {}.", code),
+                RunnerEvent::Trap { .. } => {}
+                RunnerEvent::Error(e) => return Err(e),
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Get the clock cycle the system is at, as far as the window knows.
+    pub fn cycle(&self) -> u64 {
+        match self.link {
+            Link::Local(ref system) => system.borrow().clock().count(),
+            Link::Remote(_) => self.status.as_ref().map_or(0, |s| s.panel.cycles),
+        }
+    }
+
+    /// Get the system if it runs on this thread.
+    pub fn local_system(&self) -> Option<Rc<RefCell<System>>> {
+        match self.link {
+            Link::Local(ref system) => Some(system.clone()),
+            Link::Remote(_) => None,
+        }
+    }
+
+    /// Stop a system on a thread of its own and wait for its thread.
+    pub fn close(&mut self) -> Result<()> {
+        match self.link {
+            Link::Local(_) => Ok(()),
+            Link::Remote(ref mut runner) => runner.join(),
+        }
+    }
+
+    /// Send a command to a system on a thread of its own. Errors show up
+    /// when the window is updated.
+    /// # Arguments
+    /// * `command` - The command.
+    fn send(&self, command: RunnerCommand) {
+        if let Link::Remote(ref runner) = self.link {
+            let _ = runner.send(command);
+        }
+    }
+
+    /// Get the front panel of the boot core and the framebuffer's pixels.
+    /// Return None if the system has not said what it looks like yet.
+    fn refresh(&mut self) -> Result<Option<FrontPanel>> {
+        match self.link {
+            Link::Local(ref system) => {
+                let system = system.borrow();
+                if let Some(fb) = self.framebuffer {
+                    fb.render(system.memory(), &mut self.pixels)?;
+                }
+                Ok(Some(FrontPanel::from_system(&system)))
+            }
+            Link::Remote(_) => match self.status {
+                Some(ref status) => {
+                    if let Some(ref pixels) = status.pixels {
+                        self.pixels.clone_from(pixels);
+                    }
+                    Ok(Some(status.panel.clone()))
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Draw the front panel of the boot core.
+    /// # Arguments
+    /// * `panel` - The front panel.
+    fn draw_front_panel(&mut self, panel: &FrontPanel) -> Result<()> {
+        // The PC, a light per bit grouped by nibble.
+        self.draw_text("PC", 40, 100)?;
+        for (i, lit) in panel.pc_lights().iter().enumerate() {
+            let x = 160 + i as i32 * LED_SPACING + (i as i32 / 4) * LED_SPACING / 2;
+            self.draw_led(x, 120, *lit)?;
+        }
+        self.draw_text(&format!("{:08x}", panel.pc), 160, 150)?;
+
+        // The instruction being executed.
+        self.draw_text("Instruction", 40, 230)?;
+        let instruction = panel.instruction.clone().unwrap_or("????????".to_string());
+        self.draw_text(&instruction, 300, 230)?;
+
+        // The PSW flags, each under its name.
+        self.draw_text("PSW", 40, 340)?;
+        for (i, name) in PSW_LED_NAMES.iter().enumerate() {
+            let x = 160 + i as i32 * LED_SPACING * 2;
+            self.draw_text(name, x - 9, 300)?;
+            self.draw_led(x, 360, panel.psw[i])?;
+        }
+
+        // The window pointers.
+        self.draw_dial("CWP", panel.cwp, panel, 260, 620)?;
+        self.draw_dial("SWP", panel.swp, panel, 560, 620)?;
+
+        // How far the clock got.
+        self.draw_text(&format!("Cycles {:012}", panel.cycles), 800, 560)?;
+        self.draw_text(
+            match panel.state {
+                RunState::Running => "Running",
+                RunState::Paused => "Paused",
+                RunState::Halted => "Halted",
+            },
+            800,
+            620,
+        )?;
+        Ok(())
+    }
+
+    /// Draw a window pointer as a dial with a mark per window.
+    /// # Arguments
+    /// * `name` - Name of the pointer.
+    /// * `pointer` - The window pointer.
+    /// * `panel` - The front panel it is on.
+    /// * `x` - Horizontal position of the dial's center.
+    /// * `y` - Vertical position of the dial's center.
+    fn draw_dial(
+        &mut self,
+        name: &str,
+        pointer: u8,
+        panel: &FrontPanel,
+        x: i16,
+        y: i16,
+    ) -> Result<()> {
+        self.pane
+            .canvas
+            .circle(x, y, DIAL_RADIUS, PANEL_TEXT_COLOR)
+            .map_err(EmulatorError::sdl)?;
+        for window in 0..panel.windows {
+            let (sin, cos) = panel.dial_angle(window).sin_cos();
+            let inner = DIAL_RADIUS as f64 * 0.85;
+            self.pane
+                .canvas
+                .line(
+                    x + (sin * inner) as i16,
+                    y - (cos * inner) as i16,
+                    x + (sin * DIAL_RADIUS as f64) as i16,
+                    y - (cos * DIAL_RADIUS as f64) as i16,
+                    PANEL_TEXT_COLOR,
+                )
+                .map_err(EmulatorError::sdl)?;
+        }
+        let (sin, cos) = panel.dial_angle(pointer).sin_cos();
+        let needle = DIAL_RADIUS as f64 * 0.75;
+        self.pane
+            .canvas
+            .thick_line(
+                x,
+                y,
+                x + (sin * needle) as i16,
+                y - (cos * needle) as i16,
+                4,
+                LED_ON_COLOR,
+            )
+            .map_err(EmulatorError::sdl)?;
+        self.draw_text(
+            &format!("{} {}", name, pointer),
+            x as i32 - 45,
+            y as i32 + DIAL_RADIUS as i32 + 20,
+        )
+    }
+
+    /// Draw an LED.
+    /// # Arguments
+    /// * `x` - Horizontal position of the LED's center.
+    /// * `y` - Vertical position of the LED's center.
+    /// * `lit` - True if the LED is lit.
+    fn draw_led(&mut self, x: i32, y: i32, lit: bool) -> Result<()> {
+        self.pane
+            .canvas
+            .filled_circle(
+                x as i16,
+                y as i16,
+                LED_RADIUS,
+                if lit { LED_ON_COLOR } else { LED_OFF_COLOR },
+            )
+            .map_err(EmulatorError::sdl)?;
+        Ok(())
+    }
+
+    /// Draw a line of text.
+    /// # Arguments
+    /// * `text` - The text.
+    /// * `x` - Horizontal position of its top left corner.
+    /// * `y` - Vertical position of its top left corner.
+    fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<()> {
+        let location = Rect::new(x, y, text.chars().count() as u32 * CHAR_WIDTH, TEXT_HEIGHT);
+        self.pane
+            .draw_text(&self.font, text, PANEL_TEXT_COLOR, location)
+    }
+}
+
+impl<'a> GamepadSink for MainWindow<'a> {
+    fn set_connected(&self, connected: bool) {
+        match self.link {
+            Link::Local(ref system) => system
+                .borrow_mut()
+                .send_input(Input::GamepadConnected(connected)),
+            Link::Remote(_) => self.send(RunnerCommand::GamepadConnected(connected)),
+        }
+    }
+
+    fn set_button(&self, button: u32, pressed: bool) {
+        match self.link {
+            Link::Local(ref system) => system
+                .borrow_mut()
+                .send_input(Input::GamepadButton(button, pressed)),
+            Link::Remote(_) => self.send(RunnerCommand::GamepadButton(button, pressed)),
+        }
+    }
+
+    fn set_axis(&self, axis: u32, value: i16) {
+        match self.link {
+            Link::Local(ref system) => system
+                .borrow_mut()
+                .send_input(Input::GamepadAxis(axis, value)),
+            Link::Remote(_) => self.send(RunnerCommand::GamepadAxis(axis, value)),
+        }
+    }
+}
+
+impl<'a> Drop for MainWindow<'a> {
+    fn drop(&mut self) {
+        // Textures must go before the renderer.
+        if let Some(texture) = self.texture.take() {
+            unsafe { texture.destroy() };
+        }
+    }
+}
+
+impl<'a> Drawable for MainWindow<'a> {
+    fn draw(&mut self, _context: &mut Context) -> Result<()> {
+        self.pane.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.pane.canvas.clear();
+
+        let panel = match self.refresh()? {
+            Some(panel) => panel,
+            None => {
+                self.pane.present();
+                return Ok(());
+            }
+        };
+        if let (Some(fb), Some(texture)) = (self.framebuffer, self.texture.as_mut()) {
+            texture
+                .update(None, &self.pixels, fb.width() as usize * 3)
+                .map_err(EmulatorError::sdl)?;
+            // Stretch the framebuffer over the whole window.
+            self.pane
+                .canvas
+                .copy(texture, None, None)
+                .map_err(EmulatorError::sdl)?;
+        } else {
+            self.draw_front_panel(&panel)?;
+        }
+        self.pane.present();
+        Ok(())
+    }
+
+    fn handle_key_down(&mut self, kc: Keycode) {
+        if let Some(sc) = Scancode::from_keycode(kc) {
+            match self.link {
+                Link::Local(ref system) => {
+                    system.borrow_mut().send_input(Input::KeyDown(sc as u32))
+                }
+                Link::Remote(_) => self.send(RunnerCommand::KeyDown(sc as u32)),
+            }
+        }
+    }
+
+    fn handle_key_up(&mut self, kc: Keycode) {
+        if let Some(sc) = Scancode::from_keycode(kc) {
+            match self.link {
+                Link::Local(ref system) => system.borrow_mut().send_input(Input::KeyUp(sc as u32)),
+                Link::Remote(_) => self.send(RunnerCommand::KeyUp(sc as u32)),
+            }
+        }
+    }
+
+    fn handle_mouse_down(&mut self, _x: i32, _y: i32) {}
+
+    fn get_window_id(&self) -> u32 {
+        self.pane.get_id()
+    }
+}
@@ -15,15 +15,97 @@
 
 // Struct definitions.
 
-use config::Config;
-use std::convert::TryInto;
-use util::{check_hword_alignment, check_word_alignment, File, Result};
+extern crate serde;
+extern crate serde_derive;
 
-use berr;
+use self::serde::de::Error as DeError;
+use self::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use self::serde_derive::{Deserialize, Serialize};
+use config::{Config, Endian, MemoryFill};
+use device::{Device, Reset};
+use error::EmulatorError;
+use r2d2::{Reader, Snapshot, Writer};
+use region::RegionMap;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use util::{base64_decode, base64_encode, File, Result, Xorshift};
+
+/// Width of a memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    /// 8 bits.
+    Byte,
+    /// 16 bits.
+    Half,
+    /// 32 bits.
+    Word,
+}
+
+/// Whether a memory access reads or writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    /// Write the low bytes of the value.
+    Write(u32),
+}
+
+/// Bus errors, why a memory access failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemError {
+    /// Some of the bytes accessed are past the end of memory.
+    OutOfRange {
+        address: u32,
+        width: Width,
+        size: u32,
+    },
+    /// The address is not a multiple of the width.
+    Misaligned { address: u32, width: Width },
+    /// Some of the bytes of a block copied to or from memory are past its
+    /// end.
+    BlockOutOfRange { address: u32, len: u32, size: u32 },
+}
+
+/// Shortest run of a repeated byte serialized as a fill instead of bytes.
+const MIN_FILL_RUN: usize = 64;
+
+/// How memory is serialized: its size, byte order and contents as chunks.
+/// Bytes not in a chunk are 0.
+#[derive(Serialize, Deserialize)]
+struct MemoryState {
+    size: u32,
+    endian: Endian,
+    chunks: Vec<Chunk>,
+}
+
+/// Contiguous bytes of serialized memory, either a run of one byte or the
+/// bytes themselves in base64.
+#[derive(Serialize, Deserialize)]
+struct Chunk {
+    address: u32,
+    len: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fill: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
 
 /// The real memory of the RISC II emulator.
-#[derive(Debug, Clone)]
-pub struct Memory(Vec<u8>);
+pub struct Memory {
+    /// Memory contents.
+    data: Vec<u8>,
+    /// Devices attached to the system. Memory mapped devices answer accesses
+    /// to their address range instead of memory.
+    devices: Vec<RefCell<Box<dyn Device>>>,
+    /// What memory holds at power on.
+    fill: MemoryFill,
+    /// Seed of the random fill.
+    seed: u32,
+    /// Byte order of words.
+    endian: Endian,
+    /// What each region of memory allows.
+    regions: RegionMap,
+}
 
 // Struct impls.
 
@@ -33,121 +115,475 @@ impl Memory {
     /// * `config` - A configuration object that determines the size of
     /// the memory object.
     pub fn new(config: &Config) -> Self {
-        Self {
-            0: vec![0u8; config.get_mem_size() as usize],
-        }
+        let mut result = Self::from_size(config.get_mem_size());
+        result.endian = config.get_endian();
+        result.regions = RegionMap::new(config.get_regions().to_vec());
+        result.set_fill(config.get_mem_fill(), config.get_mem_fill_seed());
+        result
     }
 
     pub fn from_size(size: u32) -> Self {
+        Self::from_vec(&vec![0u8; size as usize])
+    }
+
+    pub fn from_vec(memory: &Vec<u8>) -> Self {
         Self {
-            0: vec![0u8; size as usize],
+            data: memory.clone(),
+            devices: Vec::new(),
+            fill: MemoryFill::Zero,
+            seed: 0,
+            endian: Endian::Big,
+            regions: RegionMap::default(),
         }
     }
 
-    pub fn from_vec(memory: &Vec<u8>) -> Self {
-        Self { 0: memory.clone() }
+    /// Get the size of memory in bytes.
+    pub fn size(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    /// Set what memory holds at power on and fill it with that.
+    /// # Arguments
+    /// * `fill` - What memory holds at power on.
+    /// * `seed` - Seed of the random fill.
+    pub fn set_fill(&mut self, fill: MemoryFill, seed: u32) {
+        self.fill = fill;
+        self.seed = seed;
+        self.clear();
+    }
+
+    /// Get the byte order of words.
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Set the byte order of words. Memory is not rearranged, its bytes
+    /// just read as different words.
+    /// # Arguments
+    /// * `endian` - Byte order.
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
+    }
+
+    /// Get what each region of memory allows.
+    pub fn regions(&self) -> &RegionMap {
+        &self.regions
+    }
+
+    /// Get what each region of memory allows, to change it.
+    pub fn regions_mut(&mut self) -> &mut RegionMap {
+        &mut self.regions
+    }
+
+    /// Attach a device to the system.
+    /// # Arguments
+    /// * `device` - Device to attach.
+    pub fn attach(&mut self, device: Box<dyn Device>) {
+        self.devices.push(RefCell::new(device));
+    }
+
+    /// Reset every attached device.
+    /// # Arguments
+    /// * `kind` - Kind of reset being performed.
+    pub fn reset_devices(&mut self, kind: Reset) {
+        for device in self.devices.iter_mut() {
+            device.get_mut().reset(kind);
+        }
+    }
+
+    /// Get the name and fingerprint of every attached device, in order of
+    /// attachment.
+    pub fn device_states(&self) -> Vec<(String, Writer)> {
+        self.devices
+            .iter()
+            .map(|d| {
+                let d = d.borrow();
+                let mut w = Writer::new();
+                d.fingerprint(&mut w);
+                (d.name().to_string(), w)
+            })
+            .collect()
+    }
+
+    /// Advance every attached device by a clock cycle.
+    pub fn tick_devices(&mut self) {
+        for device in self.devices.iter_mut() {
+            device.get_mut().tick();
+        }
+    }
+
+    /// Let every attached device access memory directly.
+    pub fn run_dma(&mut self) {
+        for device in self.devices.iter_mut() {
+            device.get_mut().dma(&mut self.data);
+        }
+    }
+
+    /// Return true if any attached device is requesting an interrupt.
+    pub fn interrupt_pending(&self) -> bool {
+        self.devices.iter().any(|d| d.borrow().interrupt_pending())
+    }
+
+    /// Get the memory mapped device answering to `addr`, if any. Devices
+    /// attached later shadow earlier ones.
+    fn device_at(&self, addr: u32) -> Option<&RefCell<Box<dyn Device>>> {
+        self.devices
+            .iter()
+            .rev()
+            .find(|d| match d.borrow().mapping() {
+                Some(range) => range.contains(&addr),
+                None => false,
+            })
+    }
+
+    /// Put all of memory back in its power on state.
+    pub fn clear(&mut self) {
+        match self.fill {
+            MemoryFill::Zero => fill_bytes(&mut self.data, &[0x00]),
+            MemoryFill::Ones => fill_bytes(&mut self.data, &[0xff]),
+            MemoryFill::Deadbeef => {
+                let pattern = self.endian.word_bytes(0xdeadbeef);
+                fill_bytes(&mut self.data, &pattern)
+            }
+            MemoryFill::Random => {
+                let mut rng = Xorshift::new(self.seed);
+                for b in self.data.iter_mut() {
+                    *b = rng.next_u32() as u8;
+                }
+            }
+        }
     }
 
     pub fn write_to_file(&mut self, file: &mut File) -> Result<()> {
-        file.write_vec(&self.0)
+        file.write_vec(&self.data)
     }
 
-    pub fn write_buf(&mut self, addr: u32, buf: &[u8]) {
-        self.0[addr as usize..buf.len()].copy_from_slice(buf);
+    /// Copy bytes into memory, bypassing the devices. Return void on
+    /// success and a string if they do not fit.
+    /// # Arguments
+    /// * `addr` - Address of the first byte.
+    /// * `buf` - Bytes to copy.
+    pub fn write_buf(&mut self, addr: u32, buf: &[u8]) -> Result<()> {
+        let start = addr as usize;
+        let end = start + buf.len();
+        if end > self.data.len() {
+            return Err(EmulatorError::Mem(MemError::BlockOutOfRange {
+                address: addr,
+                len: buf.len() as u32,
+                size: self.size(),
+            }));
+        }
+        self.data[start..end].copy_from_slice(buf);
+        Ok(())
     }
 
-    pub fn get_byte(&self, addr: u32) -> Result<u8> {
-        let addr = addr as usize;
-        if addr >= self.0.len() {
-            berr!(format!(
-                "Memory read: address 0x{:x} is out range (memory is of size 0x{:x})",
-                addr,
-                self.0.len()
-            ))
-        } else {
-            Ok(self.0[addr])
+    /// Read or write memory, or the memory mapped device at the address.
+    /// Return the value read or written, the low `width` bytes only, and a
+    /// bus error if the access is misaligned or outside of memory.
+    /// # Arguments
+    /// * `addr` - Address of the first byte, a multiple of the width.
+    /// * `width` - Width of the access.
+    /// * `access` - Whether to read or write, and what to write.
+    pub fn access(
+        &mut self,
+        addr: u32,
+        width: Width,
+        access: Access,
+    ) -> std::result::Result<u32, MemError> {
+        match access {
+            Access::Read => self.read(addr, width),
+            Access::Write(value) => self.write(addr, width, value),
         }
     }
 
-    pub fn get_hword(&self, addr: u32) -> Result<u16> {
-        check_hword_alignment(addr)?;
-        let addr = addr as usize;
-        if addr >= self.0.len() {
-            berr!(format!(
-                "Memory read: address 0x{:x} is out range (memory is of size 0x{:x})",
-                addr,
-                self.0.len()
-            ))
-        } else {
-            Ok(u16::from_be_bytes(self.0[addr..addr + 1].try_into()?))
+    /// Read memory, see `access`.
+    fn read(&self, addr: u32, width: Width) -> std::result::Result<u32, MemError> {
+        check_alignment(addr, width)?;
+        if let Some(device) = self.device_at(addr) {
+            return Ok(device.borrow_mut().read(addr) & width.mask());
         }
+        let (start, end) = self.range(addr, width)?;
+        let bytes = &self.data[start..end];
+        let value = |value: u32, b: &u8| (value << 8) | *b as u32;
+        Ok(match self.endian {
+            Endian::Big => bytes.iter().fold(0, value),
+            Endian::Little => bytes.iter().rev().fold(0, value),
+        })
     }
 
-    pub fn get_word(&self, addr: u32) -> Result<u32> {
-        check_word_alignment(addr)?;
-        let addr = addr as usize;
-        if addr >= self.0.len() {
-            berr!(format!(
-                "Memory read: address 0x{:x} is out range (memory is of size 0x{:x})",
-                addr,
-                self.0.len()
-            ))
-        } else {
-            Ok(u32::from_be_bytes(self.0[addr..addr + 4].try_into()?))
+    /// Write memory, see `access`.
+    fn write(&mut self, addr: u32, width: Width, value: u32) -> std::result::Result<u32, MemError> {
+        check_alignment(addr, width)?;
+        let value = value & width.mask();
+        if let Some(device) = self.device_at(addr) {
+            device.borrow_mut().write(addr, value);
+            return Ok(value);
         }
+        let (start, end) = self.range(addr, width)?;
+        // The low `width` bytes of the word.
+        let bytes = self.endian.word_bytes(value);
+        self.data[start..end].copy_from_slice(match self.endian {
+            Endian::Big => &bytes[4 - (end - start)..],
+            Endian::Little => &bytes[..end - start],
+        });
+        Ok(value)
     }
 
-    pub fn set_word(&mut self, addr: u32, what: u32) -> Result<u32> {
-        check_word_alignment(addr)?;
-        let addr = addr as usize;
-        if addr >= self.0.len() - 4 {
-            berr!(format!(
-                "Memory write: address 0x{:x} is out range (memory is of size 0x{:x})",
-                addr,
-                self.0.len()
-            ))
+    /// Get the bytes of memory an access covers.
+    /// # Arguments
+    /// * `addr` - Address of the first byte.
+    /// * `width` - Width of the access.
+    fn range(&self, addr: u32, width: Width) -> std::result::Result<(usize, usize), MemError> {
+        let start = addr as usize;
+        let end = start + width.bytes() as usize;
+        if end > self.data.len() {
+            Err(MemError::OutOfRange {
+                address: addr,
+                width: width,
+                size: self.size(),
+            })
         } else {
-            let what_bytes = if cfg!(target_endian = "little") {
-                u32::to_ne_bytes(what.swap_bytes())
-            } else {
-                u32::to_ne_bytes(what)
-            };
-            self.0[addr..addr + 4].copy_from_slice(&what_bytes);
-            Ok(what)
+            Ok((start, end))
         }
     }
 
+    pub fn get_byte(&self, addr: u32) -> Result<u8> {
+        Ok(self.read(addr, Width::Byte)? as u8)
+    }
+
+    pub fn get_hword(&self, addr: u32) -> Result<u16> {
+        Ok(self.read(addr, Width::Half)? as u16)
+    }
+
+    pub fn get_word(&self, addr: u32) -> Result<u32> {
+        Ok(self.read(addr, Width::Word)?)
+    }
+
+    pub fn set_word(&mut self, addr: u32, what: u32) -> Result<u32> {
+        Ok(self.write(addr, Width::Word, what)?)
+    }
+
     pub fn set_hword(&mut self, addr: u32, what: u16) -> Result<u16> {
-        check_word_alignment(addr)?;
-        let addr = addr as usize;
-        if addr >= self.0.len() - 2 {
-            berr!(format!(
-                "Memory write: address 0x{:x} is out range (memory is of size 0x{:x})",
-                addr,
-                self.0.len()
-            ))
+        Ok(self.write(addr, Width::Half, what as u32)? as u16)
+    }
+
+    pub fn set_byte(&mut self, addr: u32, what: u8) -> Result<u8> {
+        Ok(self.write(addr, Width::Byte, what as u32)? as u8)
+    }
+
+    /// Get `len` bytes of memory starting at `addr`, ignoring devices.
+    /// Return the bytes on success and a string on error.
+    /// # Arguments
+    /// * `addr` - Address of the first byte.
+    /// * `len` - Number of bytes.
+    pub fn get_bytes(&self, addr: u32, len: u32) -> Result<&[u8]> {
+        let (start, end) = (addr as usize, addr as usize + len as usize);
+        if end > self.data.len() {
+            Err(EmulatorError::Mem(MemError::BlockOutOfRange {
+                address: addr,
+                len: len,
+                size: self.size(),
+            }))
         } else {
-            let what_bytes = if cfg!(target_endian = "little") {
-                u16::to_ne_bytes(what.swap_bytes())
-            } else {
-                u16::to_ne_bytes(what)
+            Ok(&self.data[start..end])
+        }
+    }
+}
+
+impl Width {
+    /// Get the width of an access of `bytes` bytes. Anything other than a
+    /// word or half word is a byte, like the width code on the pins.
+    /// # Arguments
+    /// * `bytes` - Number of bytes accessed.
+    pub fn from_bytes(bytes: u32) -> Self {
+        match bytes {
+            4 => Width::Word,
+            2 => Width::Half,
+            _ => Width::Byte,
+        }
+    }
+
+    /// Get the number of bytes accessed.
+    pub fn bytes(&self) -> u32 {
+        match *self {
+            Width::Byte => 1,
+            Width::Half => 2,
+            Width::Word => 4,
+        }
+    }
+
+    /// Get the mask of the bits accessed.
+    pub fn mask(&self) -> u32 {
+        u32::max_value() >> (32 - 8 * self.bytes())
+    }
+}
+
+impl fmt::Display for MemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemError::OutOfRange {
+                address,
+                width,
+                size,
+            } => write!(
+                f,
+                "Memory access: 0x{:x} bytes at 0x{:x} are out of range (memory is of size 0x{:x})",
+                width.bytes(),
+                address,
+                size
+            ),
+            MemError::Misaligned { address, width } => write!(
+                f,
+                "Memory access: address 0x{:x} is not aligned to 0x{:x} bytes",
+                address,
+                width.bytes()
+            ),
+            MemError::BlockOutOfRange { address, len, size } => write!(
+                f,
+                "Memory access: 0x{:x} bytes at 0x{:x} are out of range (memory is of size 0x{:x})",
+                len, address, size
+            ),
+        }
+    }
+}
+
+impl Error for MemError {}
+
+impl Snapshot for Memory {
+    /// Only memory contents are saved, attached devices are not.
+    fn save(&self, w: &mut Writer) {
+        w.put_bytes(&self.data);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<()> {
+        self.data = r.get_bytes()?;
+        Ok(())
+    }
+}
+
+/// Only memory contents and byte order are serialized, attached devices and
+/// regions belong to the system.
+impl Serialize for Memory {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        MemoryState {
+            size: self.size(),
+            endian: self.endian,
+            chunks: chunks(&self.data),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Memory {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let state = MemoryState::deserialize(d)?;
+        let mut result = Self::from_size(state.size);
+        result.endian = state.endian;
+        for chunk in state.chunks.iter() {
+            let bytes = match (chunk.fill, chunk.data.as_ref()) {
+                (Some(fill), None) => vec![fill; chunk.len as usize],
+                (None, Some(data)) => base64_decode(data).map_err(D::Error::custom)?,
+                _ => {
+                    return Err(D::Error::custom(format!(
+                        "Chunk at 0x{:08x} needs either a fill or data",
+                        chunk.address
+                    )))
+                }
             };
-            self.0[addr..addr + 2].copy_from_slice(&what_bytes);
-            Ok(what)
+            if bytes.len() != chunk.len as usize {
+                return Err(D::Error::custom(format!(
+                    "Chunk at 0x{:08x} holds {} bytes instead of {}",
+                    chunk.address,
+                    bytes.len(),
+                    chunk.len
+                )));
+            }
+            result
+                .write_buf(chunk.address, &bytes)
+                .map_err(D::Error::custom)?;
         }
+        Ok(result)
     }
+}
 
-    pub fn set_byte(&mut self, addr: u32, what: u8) -> Result<u8> {
-        let addr = addr as usize;
-        if addr >= self.0.len() {
-            berr!(format!(
-                "Memory write: address 0x{:x} is out range (memory is of size 0x{:x})",
-                addr,
-                self.0.len()
-            ))
-        } else {
-            self.0[addr] = what;
-            Ok(what)
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Memory of size 0x{:x} with {} devices",
+            self.data.len(),
+            self.devices.len()
+        )
+    }
+}
+
+// Private functions.
+
+/// Split memory contents into the chunks they are serialized as. Runs of 0
+/// are left out, other runs of a byte at least `MIN_FILL_RUN` long become
+/// fills and the bytes between them data.
+/// # Arguments
+/// * `data` - Memory contents.
+fn chunks(data: &[u8]) -> Vec<Chunk> {
+    let mut result = Vec::new();
+    // Start of the bytes not in a chunk yet.
+    let mut pending = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take_while(|b| **b == data[i]).count();
+        if run >= MIN_FILL_RUN || (data[i] == 0 && i + run == data.len()) {
+            if pending < i {
+                result.push(Chunk {
+                    address: pending as u32,
+                    len: (i - pending) as u32,
+                    fill: None,
+                    data: Some(base64_encode(&data[pending..i])),
+                });
+            }
+            if data[i] != 0 {
+                result.push(Chunk {
+                    address: i as u32,
+                    len: run as u32,
+                    fill: Some(data[i]),
+                    data: None,
+                });
+            }
+            pending = i + run;
         }
+        i += run;
+    }
+    if pending < data.len() {
+        result.push(Chunk {
+            address: pending as u32,
+            len: (data.len() - pending) as u32,
+            fill: None,
+            data: Some(base64_encode(&data[pending..])),
+        });
+    }
+    result
+}
+
+/// Check an access's address is a multiple of its width.
+/// # Arguments
+/// * `addr` - Address of the access.
+/// * `width` - Width of the access.
+fn check_alignment(addr: u32, width: Width) -> std::result::Result<(), MemError> {
+    if addr & (width.bytes() - 1) != 0 {
+        Err(MemError::Misaligned {
+            address: addr,
+            width: width,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Fill `data` with copies of `pattern`.
+/// # Arguments
+/// * `data` - Bytes to fill.
+/// * `pattern` - Bytes repeated over `data`.
+fn fill_bytes(data: &mut [u8], pattern: &[u8]) {
+    for (b, p) in data.iter_mut().zip(pattern.iter().cycle()) {
+        *b = *p;
     }
 }
@@ -0,0 +1,155 @@
+// Test code for RISC II memory.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "memory.rs"]
+mod test {
+    extern crate toml;
+
+    use super::super::*;
+    use config::{Endian, MemoryFill};
+    use memory::*;
+    use util::Result;
+
+    #[test]
+    fn access_widths_are_big_endian() {
+        let mut mem = Memory::from_size(8);
+        assert_eq!(
+            mem.access(0, Width::Word, Access::Write(0x11223344)),
+            Ok(0x11223344)
+        );
+        assert_eq!(mem.get_bytes(0, 4).unwrap(), &[0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(mem.access(0, Width::Half, Access::Read), Ok(0x1122));
+        assert_eq!(mem.access(2, Width::Half, Access::Read), Ok(0x3344));
+        assert_eq!(mem.access(3, Width::Byte, Access::Read), Ok(0x44));
+        // Only the low bytes are written.
+        assert_eq!(
+            mem.access(6, Width::Half, Access::Write(0xaabbccdd)),
+            Ok(0xccdd)
+        );
+        assert_eq!(mem.access(4, Width::Word, Access::Read), Ok(0x0000ccdd));
+    }
+
+    #[test]
+    fn last_bytes_of_memory_are_accessible() -> Result<()> {
+        let mut mem = Memory::from_size(8);
+        mem.set_word(4, 0xdeadbeef)?;
+        assert_eq!(mem.get_word(4)?, 0xdeadbeef);
+        mem.set_hword(6, 0x1234)?;
+        assert_eq!(mem.get_hword(6)?, 0x1234);
+        mem.set_byte(7, 0x56)?;
+        assert_eq!(mem.get_byte(7)?, 0x56);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_accesses_are_bus_errors() {
+        let mut mem = Memory::from_size(8);
+        for &width in [Width::Byte, Width::Half, Width::Word].iter() {
+            assert_eq!(
+                mem.access(8, width, Access::Read),
+                Err(MemError::OutOfRange {
+                    address: 8,
+                    width: width,
+                    size: 8
+                })
+            );
+        }
+        assert_eq!(
+            mem.access(2, Width::Word, Access::Write(0)),
+            Err(MemError::Misaligned {
+                address: 2,
+                width: Width::Word
+            })
+        );
+        assert_eq!(
+            mem.access(5, Width::Half, Access::Read),
+            Err(MemError::Misaligned {
+                address: 5,
+                width: Width::Half
+            })
+        );
+        assert!(mem.set_word(8, 0).is_err());
+        assert_eq!(mem.get_bytes(0, 8).unwrap(), &[0; 8]);
+    }
+
+    #[test]
+    fn little_endian_accesses() -> Result<()> {
+        let mut mem = Memory::from_size(8);
+        mem.set_endian(Endian::Little);
+        mem.set_word(0, 0x11223344)?;
+        assert_eq!(mem.get_bytes(0, 4)?, &[0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(mem.get_hword(0)?, 0x3344);
+        assert_eq!(mem.get_hword(2)?, 0x1122);
+        assert_eq!(mem.get_byte(0)?, 0x44);
+        mem.set_hword(6, 0xaabb)?;
+        assert_eq!(mem.get_bytes(6, 2)?, &[0xbb, 0xaa]);
+        mem.set_fill(MemoryFill::Deadbeef, 0);
+        assert_eq!(mem.get_word(4)?, 0xdeadbeef);
+        Ok(())
+    }
+
+    #[test]
+    fn errors_say_what_went_wrong() {
+        let mut mem = Memory::from_size(8);
+        match mem.get_word(2) {
+            Err(EmulatorError::Mem(e)) => assert_eq!(
+                e,
+                MemError::Misaligned {
+                    address: 2,
+                    width: Width::Word
+                }
+            ),
+            _ => panic!("expected a misaligned access"),
+        }
+        match mem.write_buf(6, &[0; 4]) {
+            Err(EmulatorError::Mem(e)) => assert_eq!(
+                e,
+                MemError::BlockOutOfRange {
+                    address: 6,
+                    len: 4,
+                    size: 8
+                }
+            ),
+            _ => panic!("expected an out of range block"),
+        }
+    }
+
+    #[test]
+    fn contents_round_trip_as_chunks() -> Result<()> {
+        let mut mem = Memory::from_size(0x400);
+        mem.set_endian(Endian::Little);
+        mem.write_buf(0x10, &[1, 2, 3, 4, 5])?;
+        mem.write_buf(0x100, &[0xaa; 0x80])?;
+        mem.set_byte(0x3ff, 7)?;
+
+        let text = toml::to_string(&mem).unwrap();
+        assert!(text.contains("fill = 170"));
+        let copy: Memory = toml::from_str(&text).unwrap();
+        assert_eq!(copy.size(), 0x400);
+        assert_eq!(copy.endian(), Endian::Little);
+        assert_eq!(copy.get_bytes(0, 0x400)?, mem.get_bytes(0, 0x400)?);
+        Ok(())
+    }
+
+    #[test]
+    fn chunks_must_fit_and_match_their_length() {
+        let chunk = "size = 4\nendian = \"big\"\n[[chunks]]\naddress = 0\n";
+        assert!(toml::from_str::<Memory>(&format!("{}len = 2\ndata = \"AQI=\"", chunk)).is_ok());
+        assert!(toml::from_str::<Memory>(&format!("{}len = 3\ndata = \"AQI=\"", chunk)).is_err());
+        assert!(toml::from_str::<Memory>(&format!("{}len = 8\nfill = 1", chunk)).is_err());
+        assert!(toml::from_str::<Memory>(&format!("{}len = 2", chunk)).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+// Memory management unit of the RISC II system.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Every address the CPU puts on the bus goes through the MMU, so memory only
+// ever sees physical addresses. Translation is base and limit: user and
+// system mode each have a segment, picked by the PSW's system mode bit. A
+// virtual address is valid if it is at most the segment's limit, and is
+// translated by adding the segment's base, wrapping around at 4 GiB. Anything
+// else raises an access violation. A system segment based at `TRAP_VECTOR_BASE`
+// thus puts the trap vectors at the bottom of physical memory.
+//
+// The MMU's registers sit at `MMU_BASE` in every address space and are never
+// translated. They can only be accessed a word at a time in system mode. The
+// MMU is off after a reset, when every address is physical. Every core has
+// its own MMU, whose read only `MMU_CPU_ID` register tells the core which one
+// it is. That register can be accessed in user mode too, so a core that just
+// came out of reset can find out. The core's trap vector table sits behind
+// the MMU's registers too, one register per kind of trap from
+// `MMU_TRAP_VECTORS` on, so system code can move its handlers.
+
+use cpu::{Trap, TrapVectors, TRAP_KINDS};
+use r2d2::{Reader, Snapshot, Writer};
+use std::ops::Range;
+use util;
+
+/// Base address of the MMU's registers.
+pub const MMU_BASE: u32 = 0xfffff000;
+/// Control register. Bit 0 turns translation on.
+pub const MMU_CONTROL: u32 = MMU_BASE;
+/// Base of the user segment.
+pub const MMU_USER_BASE: u32 = MMU_BASE + 4;
+/// Highest virtual address of the user segment.
+pub const MMU_USER_LIMIT: u32 = MMU_BASE + 8;
+/// Base of the system segment.
+pub const MMU_SYSTEM_BASE: u32 = MMU_BASE + 12;
+/// Highest virtual address of the system segment.
+pub const MMU_SYSTEM_LIMIT: u32 = MMU_BASE + 16;
+/// Number of the core the MMU belongs to (read only).
+pub const MMU_CPU_ID: u32 = MMU_BASE + 20;
+/// First of the trap vector registers, the address of the handler of the
+/// trap with `Trap::index` `n` is at `MMU_TRAP_VECTORS + 4 * n`.
+pub const MMU_TRAP_VECTORS: u32 = MMU_BASE + 24;
+/// Size of the MMU's register block.
+const MMU_SIZE: u32 = 24 + 4 * TRAP_KINDS as u32;
+/// Control bit that turns translation on.
+pub const MMU_ENABLE: u32 = 0x1;
+
+// Public structs.
+
+/// A contiguous range of physical memory a mode may access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// Physical address of virtual address 0.
+    pub base: u32,
+    /// Highest valid virtual address.
+    pub limit: u32,
+}
+
+/// Where a translated access goes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Memory at a physical address.
+    Memory(u32),
+    /// One of the MMU's own registers.
+    Register,
+}
+
+/// Base and limit memory management unit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mmu {
+    /// Contents of the control register.
+    control: u32,
+    /// Segment for user mode.
+    user: Segment,
+    /// Segment for system mode.
+    system: Segment,
+    /// Number of the core the MMU belongs to.
+    cpu_id: u32,
+    /// The core's trap vector table.
+    vectors: TrapVectors,
+}
+
+// Struct impls.
+
+impl Segment {
+    /// Segment covering the whole address space untranslated.
+    pub fn identity() -> Self {
+        Self {
+            base: 0,
+            limit: u32::MAX,
+        }
+    }
+}
+
+impl Mmu {
+    /// Create an MMU in its reset state, with translation off.
+    pub fn new() -> Self {
+        Self {
+            control: 0,
+            user: Segment::identity(),
+            system: Segment::identity(),
+            cpu_id: 0,
+            vectors: TrapVectors::new(),
+        }
+    }
+
+    /// Get the number of the core the MMU belongs to.
+    pub fn get_cpu_id(&self) -> u32 {
+        self.cpu_id
+    }
+
+    pub fn set_cpu_id(&mut self, id: u32) {
+        self.cpu_id = id;
+    }
+
+    /// Get the core's trap vector table.
+    pub fn get_trap_vectors(&self) -> TrapVectors {
+        self.vectors
+    }
+
+    pub fn set_trap_vectors(&mut self, vectors: TrapVectors) {
+        self.vectors = vectors;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.control & MMU_ENABLE != 0
+    }
+
+    pub fn set_enabled(&mut self, value: bool) {
+        if value {
+            self.control |= MMU_ENABLE;
+        } else {
+            self.control &= !MMU_ENABLE;
+        }
+    }
+
+    /// Get the segment of a mode.
+    /// # Arguments
+    /// * `system_mode` - True for the system segment, false for the user one.
+    pub fn get_segment(&self, system_mode: bool) -> Segment {
+        if system_mode {
+            self.system
+        } else {
+            self.user
+        }
+    }
+
+    /// Set the segment of a mode.
+    /// # Arguments
+    /// * `system_mode` - True for the system segment, false for the user one.
+    /// * `segment` - The new segment.
+    pub fn set_segment(&mut self, system_mode: bool, segment: Segment) {
+        if system_mode {
+            self.system = segment;
+        } else {
+            self.user = segment;
+        }
+    }
+
+    /// Translate a load or store. Return where it goes on success and an
+    /// access violation if it is not allowed.
+    /// # Arguments
+    /// * `addr` - Virtual address of the access.
+    /// * `width` - Number of bytes accessed (1, 2 or 4).
+    /// * `system_mode` - True if the CPU is in system mode.
+    pub fn translate(&self, addr: u32, width: u32, system_mode: bool) -> Result<Target, Trap> {
+        if registers().contains(&addr) {
+            let allowed = system_mode || addr & !0x3 == MMU_CPU_ID;
+            return if allowed && width == 4 {
+                Ok(Target::Register)
+            } else {
+                Err(Trap::AccessViolation(addr))
+            };
+        }
+        if !self.is_enabled() {
+            return Ok(Target::Memory(addr));
+        }
+
+        let segment = self.get_segment(system_mode);
+        let last = addr.checked_add(width - 1);
+        match last {
+            Some(last) if last <= segment.limit => {
+                Ok(Target::Memory(segment.base.wrapping_add(addr)))
+            }
+            _ => Err(Trap::AccessViolation(addr)),
+        }
+    }
+
+    /// Translate an instruction fetch. Return the physical address on
+    /// success and an access violation if it is not allowed.
+    /// # Arguments
+    /// * `addr` - Virtual address of the instruction.
+    /// * `system_mode` - True if the CPU is in system mode.
+    pub fn translate_fetch(&self, addr: u32, system_mode: bool) -> Result<u32, Trap> {
+        match self.translate(addr, 4, system_mode)? {
+            Target::Memory(physical) => Ok(physical),
+            Target::Register => Err(Trap::AccessViolation(addr)),
+        }
+    }
+
+    /// Read one of the MMU's registers.
+    /// # Arguments
+    /// * `addr` - Address of the register.
+    pub fn read_register(&self, addr: u32) -> u32 {
+        match addr & !0x3 {
+            MMU_CONTROL => self.control,
+            MMU_USER_BASE => self.user.base,
+            MMU_USER_LIMIT => self.user.limit,
+            MMU_SYSTEM_BASE => self.system.base,
+            MMU_SYSTEM_LIMIT => self.system.limit,
+            MMU_CPU_ID => self.cpu_id,
+            a if a >= MMU_TRAP_VECTORS => self
+                .vectors
+                .get_index(((a - MMU_TRAP_VECTORS) / 4) as usize),
+            _ => 0,
+        }
+    }
+
+    /// Write one of the MMU's registers.
+    /// # Arguments
+    /// * `addr` - Address of the register.
+    /// * `value` - Value to write.
+    pub fn write_register(&mut self, addr: u32, value: u32) {
+        match addr & !0x3 {
+            MMU_CONTROL => self.control = value & MMU_ENABLE,
+            MMU_USER_BASE => self.user.base = value,
+            MMU_USER_LIMIT => self.user.limit = value,
+            MMU_SYSTEM_BASE => self.system.base = value,
+            MMU_SYSTEM_LIMIT => self.system.limit = value,
+            a if a >= MMU_TRAP_VECTORS => self
+                .vectors
+                .set_index(((a - MMU_TRAP_VECTORS) / 4) as usize, value),
+            _ => {}
+        }
+    }
+}
+
+impl Snapshot for Mmu {
+    fn save(&self, w: &mut Writer) {
+        w.put_u32(self.control);
+        for segment in [self.user, self.system].iter() {
+            w.put_u32(segment.base);
+            w.put_u32(segment.limit);
+        }
+        for i in 0..TRAP_KINDS {
+            w.put_u32(self.vectors.get_index(i));
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> util::Result<()> {
+        self.control = r.get_u32()? & MMU_ENABLE;
+        for segment in [&mut self.user, &mut self.system].iter_mut() {
+            segment.base = r.get_u32()?;
+            segment.limit = r.get_u32()?;
+        }
+        for i in 0..TRAP_KINDS {
+            self.vectors.set_index(i, r.get_u32()?);
+        }
+        Ok(())
+    }
+}
+
+// Private functions.
+
+/// Get the addresses of the MMU's registers.
+fn registers() -> Range<u32> {
+    MMU_BASE..MMU_BASE + MMU_SIZE
+}
@@ -0,0 +1,80 @@
+// Integer multiply and divide extension.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// RISC II has no multiply or divide, compilers of the time called a
+// routine built from shifts and adds. This extension adds them at the
+// bottom of the extension opcode space, all of the form rd := rs1 op S2.
+// Dividing never traps: dividing by zero gives a quotient of all ones and
+// the dividend as remainder, dividing the most negative number by -1 gives
+// it back with a remainder of 0. Both set V. A product that does not fit
+// in 32 signed bits sets V too, the other instructions clear it. Z and N
+// follow the result, C is always cleared. The ALU does the arithmetic.
+
+use alu::ALU;
+use cpu::Trap;
+use data_path::SCCBits;
+use extension::ExtensionIsa;
+use instruction::EXTENSION_OPCODE;
+
+/// Low word of the product.
+pub const MUL_OPCODE: u8 = EXTENSION_OPCODE;
+/// High word of the signed product.
+pub const MULH_OPCODE: u8 = EXTENSION_OPCODE + 1;
+/// High word of the unsigned product.
+pub const MULHU_OPCODE: u8 = EXTENSION_OPCODE + 2;
+/// Signed quotient, rounded towards zero.
+pub const DIV_OPCODE: u8 = EXTENSION_OPCODE + 3;
+/// Unsigned quotient.
+pub const DIVU_OPCODE: u8 = EXTENSION_OPCODE + 4;
+/// Signed remainder, with the sign of the dividend.
+pub const REM_OPCODE: u8 = EXTENSION_OPCODE + 5;
+/// Unsigned remainder.
+pub const REMU_OPCODE: u8 = EXTENSION_OPCODE + 6;
+
+/// Integer multiply and divide instructions.
+pub struct MulDiv;
+
+impl ExtensionIsa for MulDiv {
+    fn name(&self) -> &str {
+        "muldiv"
+    }
+
+    fn decode(&self, op: u8) -> Option<&'static str> {
+        Some(match op {
+            MUL_OPCODE => "mul",
+            MULH_OPCODE => "mulh",
+            MULHU_OPCODE => "mulhu",
+            DIV_OPCODE => "div",
+            DIVU_OPCODE => "divu",
+            REM_OPCODE => "rem",
+            REMU_OPCODE => "remu",
+            _ => return None,
+        })
+    }
+
+    fn execute(&self, op: u8, s1: u32, s2: u32, _carry: bool) -> Result<(u32, SCCBits), Trap> {
+        let alu = ALU { ai: s1, bi: s2 };
+        Ok(match op {
+            MUL_OPCODE => alu.mul_scc(),
+            MULH_OPCODE => alu.mulh_scc(),
+            MULHU_OPCODE => alu.mulhu_scc(),
+            DIV_OPCODE => alu.div_scc(),
+            DIVU_OPCODE => alu.divu_scc(),
+            REM_OPCODE => alu.rem_scc(),
+            REMU_OPCODE => alu.remu_scc(),
+            _ => return Err(Trap::IllegalInstruction),
+        })
+    }
+}
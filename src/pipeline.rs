@@ -0,0 +1,470 @@
+// Cycle accurate RISC II engine. Runs instructions through the data path's
+// three stage pipeline one clock phase at a time.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use clock::Phase;
+use commit::{MemoryAccess, RetiredInstruction};
+use config::Syntax;
+use cpu::{OutputPins, ProcessorStatusWord, Trap};
+use data_path::{instruction_cycle, DataPath};
+use decode::decode;
+use decode_cache::DecodeCacheStats;
+use engine::Cpu;
+use instruction::{Instruction, InstructionCycle};
+use log::Level;
+use memory::{Access, Memory, Width};
+use pipeline_history::Occupancy;
+use r2d2::{Reader, Snapshot, Writer};
+use shifter::lane_shift;
+use std::mem;
+use util::Result;
+
+use berr;
+use core_log;
+
+/// An instruction in the execute stage of the pipeline.
+#[derive(Clone, Copy)]
+struct InFlight {
+    /// The instruction.
+    instruction: Instruction,
+    /// Address the instruction was fetched from.
+    pc: u32,
+    /// PSW at the start of the instruction's execute cycle.
+    psw_before: ProcessorStatusWord,
+}
+
+/// Cycle accurate engine.
+pub struct Pipeline {
+    /// Micro operations of the instruction being decoded.
+    decode_ops: InstructionCycle,
+    /// Next micro operation to perform for the currently executing instruction.
+    cycle_ops: InstructionCycle,
+    /// Micro operations of the committing (previous) instruction.
+    commit_ops: InstructionCycle,
+    /// The instruction being decoded, None if it is invalid.
+    decoding: Option<Instruction>,
+    /// The instruction `cycle_ops` belong to, None for a bubble.
+    cycle_instruction: Option<Instruction>,
+    /// The instruction `commit_ops` belong to, None for a bubble.
+    commit_instruction: Option<Instruction>,
+    /// The instruction being executed, None for a bubble.
+    executing: Option<InFlight>,
+    /// CPU's output pins, input pins for memory. Data addresses on them are
+    /// physical, fetch addresses are translated when the fetch finishes.
+    pins_out: OutputPins,
+    /// True if the pipeline is currently suspended as a result of a memory operation.
+    pipeline_suspended: bool,
+    /// Trap raised fetching the instruction being decoded. It is taken
+    /// instead of executing the instruction.
+    fetch_fault: Option<Trap>,
+}
+
+impl Pipeline {
+    /// Create an empty pipeline that starts fetching at `dp`'s PC.
+    /// # Arguments
+    /// * `dp` - Data path the pipeline drives.
+    pub fn new(dp: &DataPath) -> Self {
+        let mut pins_out = OutputPins::new();
+        pins_out.address = dp.get_pc();
+        Self {
+            decode_ops: InstructionCycle::noop_cycle(),
+            cycle_ops: InstructionCycle::noop_cycle(),
+            commit_ops: InstructionCycle::noop_cycle(),
+            decoding: None,
+            cycle_instruction: None,
+            commit_instruction: None,
+            executing: None,
+            pins_out: pins_out,
+            pipeline_suspended: false,
+            fetch_fault: None,
+        }
+    }
+
+    /// Point the output pins at the next instruction to fetch.
+    fn fetch_from(&mut self, address: u32) {
+        self.pins_out = OutputPins::new();
+        self.pins_out.address = address;
+    }
+
+    /// Perform the data transfer of a load or store on the output pins.
+    /// Stores have their data on every byte lane, loads put theirs on the
+    /// lanes of its address for the shifter to align.
+    fn transfer_data(&mut self, dp: &mut DataPath, mem: &mut Memory, cycle: u64) {
+        let pins = self.pins_out;
+        let lanes = lane_shift((pins.address & 0b11) as u8, pins.width());
+        let width = Width::from_bytes(pins.width());
+        let result = if pins.read_write {
+            mem.access(pins.address, width, Access::Write(pins.data))
+                .map(|_| ())
+        } else {
+            mem.access(pins.address, width, Access::Read)
+                .map(|v| dp.load_data(v << lanes))
+        };
+
+        if let Err(e) = result {
+            core_log!(
+                Level::Warn,
+                cycle,
+                dp.get_pc(),
+                "Bad mem access at {:#010x}: {}",
+                pins.address,
+                e
+            );
+        }
+    }
+
+    /// Describe the load or store that just used the bus.
+    fn data_access(&self, dp: &DataPath) -> MemoryAccess {
+        let pins = self.pins_out;
+        let width = pins.width();
+        let value = if pins.read_write {
+            // Only the low lanes of the replicated data.
+            pins.data >> lane_shift(0, width)
+        } else {
+            dp.dst_latch()
+        };
+        MemoryAccess::new(pins.address, width, value, pins.read_write)
+    }
+
+    /// Retire the executing instruction (if it is not a bubble).
+    fn retire(
+        &mut self,
+        dp: &DataPath,
+        access: Option<MemoryAccess>,
+        branch: Option<u32>,
+        trap: Option<Trap>,
+        cycle: u64,
+    ) -> Option<RetiredInstruction> {
+        let in_flight = self.executing.take()?;
+        let dest = match (trap, in_flight.instruction.dest_reg()) {
+            // Writes to r0 are discarded.
+            (None, Some(0)) => Some((0, 0)),
+            (None, Some(rd)) => Some((rd, dp.dst_latch())),
+            _ => None,
+        };
+        Some(RetiredInstruction {
+            instruction: in_flight.instruction,
+            pc: in_flight.pc,
+            dest: dest,
+            psw_before: in_flight.psw_before,
+            psw_after: dp.get_psw(),
+            access: access,
+            branch: branch,
+            trap: trap,
+            cycle: cycle,
+        })
+    }
+}
+
+impl Cpu for Pipeline {
+    fn tick(
+        &mut self,
+        phase: &Phase,
+        dp: &mut DataPath,
+        mem: &mut Memory,
+        cycle: u64,
+    ) -> Result<Option<RetiredInstruction>> {
+        dp.clear_routes();
+        match *phase {
+            Phase::One => {
+                if !self.pipeline_suspended {
+                    // Tell the pipeline we're moving on to the next instruction.
+                    dp.shift_pipeline_latches();
+                    self.commit_ops = mem::replace(
+                        &mut self.cycle_ops,
+                        mem::replace(&mut self.decode_ops, InstructionCycle::noop_cycle()),
+                    );
+                    self.commit_instruction =
+                        mem::replace(&mut self.cycle_instruction, self.decoding);
+                    self.executing = self.decoding.take().map(|instruction| InFlight {
+                        instruction: instruction,
+                        pc: dp.get_pc(),
+                        psw_before: dp.get_psw(),
+                    });
+                    if let Some(trap) = self.fetch_fault.take() {
+                        dp.abort_executing(trap);
+                    } else if dp.get_pending_psw().is_some()
+                        && self
+                            .cycle_instruction
+                            .map_or(false, |i| i.conflicts_with_putpsw())
+                    {
+                        dp.abort_executing(Trap::IllegalInstruction);
+                        self.cycle_ops = InstructionCycle::noop_cycle();
+                    }
+                    // Registers are read and then sent to the input latches of
+                    // the ALU, bypassing the register file for the result of
+                    // the instruction before.
+                    let committing = self.commit_instruction.and_then(|i| i.dest_reg());
+                    if let Err(e) = dp.route_regs_to_alu(committing) {
+                        return Err(match self.executing {
+                            Some(f) => e.in_instruction(&f.instruction, f.pc),
+                            None => e,
+                        });
+                    }
+                    self.cycle_ops[0](dp);
+                }
+            }
+            Phase::Two => {
+                if !self.pipeline_suspended {
+                    // Route immediate to ALU.
+                    dp.route_imm_to_alu();
+                    self.cycle_ops[1](dp);
+                }
+            }
+            Phase::Three => {
+                if self.pipeline_suspended {
+                    // Data cycle of the load or store from the last cycle.
+                    self.transfer_data(dp, mem, cycle);
+                } else {
+                    // Commit the result of the last instruction.
+                    self.commit_ops[4](dp);
+                    self.cycle_ops[2](dp);
+                    dp.check_access(mem.regions());
+                    // Finish the instruction fetch.
+                    let fetched = match dp.translate_fetch(self.pins_out.address, mem.regions()) {
+                        Ok(physical) => match mem.get_word(physical) {
+                            Ok(v) => v,
+                            Err(_) => {
+                                core_log!(
+                                    Level::Warn,
+                                    cycle,
+                                    dp.get_pc(),
+                                    "Bad mem read: {:#010x}",
+                                    physical
+                                );
+                                0
+                            }
+                        },
+                        Err(trap) => {
+                            self.fetch_fault = Some(trap);
+                            0
+                        }
+                    };
+                    dp.set_input_pins(fetched);
+                }
+            }
+            Phase::Four => {
+                // In actual RISCII this is where the source and dest registers are decoded
+                // for the next instruction, but that is unnecessary here.
+                if self.pipeline_suspended {
+                    self.pipeline_suspended = false;
+                    let access = self.data_access(dp);
+                    self.fetch_from(dp.get_next_pc());
+                    return Ok(self.retire(dp, Some(access), None, None, cycle));
+                }
+
+                if let Some(trap) = dp.take_trap() {
+                    // The executing instruction trapped, throw away it and the
+                    // instruction behind it and fetch the handler.
+                    self.cycle_ops = InstructionCycle::noop_cycle();
+                    self.cycle_instruction = None;
+                    self.decode_ops = InstructionCycle::noop_cycle();
+                    self.decoding = None;
+                    self.fetch_fault = None;
+                    dp.squash_decode();
+                    self.fetch_from(dp.get_pc());
+                    return Ok(self.retire(dp, None, None, Some(trap), cycle));
+                }
+
+                let branch = dp.get_branch_target();
+                self.cycle_ops[3](dp);
+                dp.finish_execute();
+                if branch.is_some() && !dp.has_delay_slots() {
+                    // No delay slot, throw away the instruction fetched
+                    // behind the transfer and fetch the target.
+                    self.fetch_fault = None;
+                    dp.squash_decode();
+                    self.fetch_from(dp.get_pc());
+                    return Ok(self.retire(dp, None, branch, None, cycle));
+                }
+                if self.fetch_fault.is_some() {
+                    // There is no instruction to decode.
+                    dp.squash_decode();
+                } else {
+                    let (ops, instruction) = dp.decode();
+                    self.decode_ops = ops;
+                    self.decoding = instruction;
+                }
+                if dp.current_instruction_is_memory() {
+                    // Suspend the pipeline for a cycle to use the bus for data.
+                    self.pins_out = *dp.get_output_pins_ref();
+                    self.pipeline_suspended = true;
+                } else {
+                    self.fetch_from(dp.get_next_pc());
+                    return Ok(self.retire(dp, None, branch, None, cycle));
+                }
+            }
+            Phase::Interrupt => {}
+        }
+        Ok(None)
+    }
+
+    fn drain(&mut self, dp: &mut DataPath) -> bool {
+        if self.pipeline_suspended {
+            // The load or store has not used the bus yet.
+            return false;
+        }
+        // The last instruction to retire writes its result back during the
+        // next cycle, do it now.
+        dp.shift_pipeline_latches();
+        self.cycle_ops[4](dp);
+        self.cycle_ops = InstructionCycle::noop_cycle();
+        true
+    }
+
+    fn describe(&self, syntax: Syntax) -> String {
+        let pins = &self.pins_out;
+        let bus = if self.pipeline_suspended {
+            format!(
+                "{} {:08x} = {:08x}",
+                if pins.read_write { "st" } else { "ld" },
+                pins.address,
+                pins.data
+            )
+        } else {
+            format!("fetch {:08x}", pins.address)
+        };
+        format!(
+            "D {} | E {} | C {} | {}{}{}",
+            describe_stage(self.decoding, syntax),
+            describe_stage(self.cycle_instruction, syntax),
+            describe_stage(self.commit_instruction, syntax),
+            bus,
+            if self.pipeline_suspended {
+                " suspended"
+            } else {
+                ""
+            },
+            match self.fetch_fault {
+                Some(trap) => format!(" fetch fault: {}", trap),
+                None => String::new(),
+            }
+        )
+    }
+
+    fn occupancy(&self) -> Occupancy {
+        Occupancy {
+            decode: self.decoding,
+            execute: self.cycle_instruction,
+            commit: self.commit_instruction,
+            fetch: if self.pipeline_suspended {
+                None
+            } else {
+                Some(self.pins_out.address)
+            },
+            suspended: self.pipeline_suspended,
+        }
+    }
+
+    fn flush(&mut self, dp: &DataPath) {
+        *self = Pipeline::new(dp);
+    }
+
+    fn decode_cache_stats(&self) -> Option<DecodeCacheStats> {
+        None
+    }
+}
+
+impl Snapshot for Pipeline {
+    fn save(&self, w: &mut Writer) {
+        put_instruction(w, self.decoding);
+        put_instruction(w, self.cycle_instruction);
+        put_instruction(w, self.commit_instruction);
+        match self.executing {
+            Some(in_flight) => {
+                w.put_bool(true);
+                put_instruction(w, Some(in_flight.instruction));
+                w.put_u32(in_flight.pc);
+                w.put_u16(in_flight.psw_before.get());
+            }
+            None => w.put_bool(false),
+        }
+        self.pins_out.save(w);
+        w.put_bool(self.pipeline_suspended);
+        match self.fetch_fault {
+            Some(Trap::AccessViolation(addr)) => {
+                w.put_bool(true);
+                w.put_u32(addr);
+            }
+            _ => w.put_bool(false),
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<()> {
+        self.decoding = get_instruction(r)?;
+        self.cycle_instruction = get_instruction(r)?;
+        self.commit_instruction = get_instruction(r)?;
+        self.executing = if r.get_bool()? {
+            Some(InFlight {
+                instruction: match get_instruction(r)? {
+                    Some(v) => v,
+                    None => return berr!(Format, "Snapshot has an invalid executing instruction"),
+                },
+                pc: r.get_u32()?,
+                psw_before: ProcessorStatusWord::from_u16(r.get_u16()?),
+            })
+        } else {
+            None
+        };
+        self.pins_out.restore(r)?;
+        self.pipeline_suspended = r.get_bool()?;
+        self.fetch_fault = if r.get_bool()? {
+            Some(Trap::AccessViolation(r.get_u32()?))
+        } else {
+            None
+        };
+        // The micro operations follow from the instructions.
+        self.decode_ops = ops_of(self.decoding);
+        self.cycle_ops = ops_of(self.cycle_instruction);
+        self.commit_ops = ops_of(self.commit_instruction);
+        Ok(())
+    }
+}
+
+/// Get the micro operations of `instruction`, a no-op cycle for a bubble.
+fn ops_of(instruction: Option<Instruction>) -> InstructionCycle {
+    match instruction {
+        Some(v) => instruction_cycle(&v),
+        None => InstructionCycle::noop_cycle(),
+    }
+}
+
+/// Describe the instruction in a pipeline stage, `-` for a bubble.
+fn describe_stage(instruction: Option<Instruction>, syntax: Syntax) -> String {
+    match instruction {
+        Some(instruction) => format!("{}", instruction.disassemble(syntax)),
+        None => format!("-"),
+    }
+}
+
+/// Write an instruction (or a bubble) to a snapshot.
+fn put_instruction(w: &mut Writer, instruction: Option<Instruction>) {
+    match instruction {
+        Some(v) => {
+            w.put_bool(true);
+            w.put_u32(v.encode());
+        }
+        None => w.put_bool(false),
+    }
+}
+
+/// Read an instruction (or a bubble) written by `put_instruction`.
+fn get_instruction(r: &mut Reader) -> Result<Option<Instruction>> {
+    Ok(if r.get_bool()? {
+        Some(decode(r.get_u32()?)?)
+    } else {
+        None
+    })
+}
@@ -0,0 +1,159 @@
+// Log of the pipeline's inner workings.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// For debugging the engines rather than the guest. Unlike the instruction
+// trace, which only shows what retired, this shows what the engine holds
+// between instructions. At the cycle level every clock cycle gets a line
+// with the PC latches, the instruction in every stage and the bus, e.g.
+//   12 core 0: pc 00000010 nxtpc 00000014 dst 00000005 src 00000000 | D add ... | E ... | C ... | fetch 00000014
+// At the phase level every phase also gets a line listing the latches,
+// PSW bits and registers it changed, e.g.
+//   12.3 core 0: DST 0->5
+
+use clock::Phase;
+use config::{PipelineDebug, Syntax};
+use data_path::DataPath;
+use data_path_view::DataPathView;
+use engine::Cpu;
+use error::EmulatorError;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use util::Result;
+
+/// Writes the pipeline's inner workings to a log.
+pub struct PipelineLog {
+    /// How much to log.
+    level: PipelineDebug,
+    /// Syntax to disassemble instructions in.
+    syntax: Syntax,
+    /// Where the log goes.
+    out: Box<dyn Write>,
+    /// Each core's data path as of the last phase logged.
+    last: Vec<Option<DataPathView>>,
+}
+
+impl PipelineLog {
+    /// Start a log written to `out`.
+    /// # Arguments
+    /// * `level` - How much to log.
+    /// * `out` - Where to write the log.
+    pub fn new(level: PipelineDebug, out: Box<dyn Write>) -> Self {
+        Self {
+            level: level,
+            syntax: Syntax::Modern,
+            out: out,
+            last: Vec::new(),
+        }
+    }
+
+    /// Start a log written to standard error.
+    /// # Arguments
+    /// * `level` - How much to log.
+    pub fn stderr(level: PipelineDebug) -> Self {
+        Self::new(level, Box::new(io::stderr()))
+    }
+
+    /// Start a log written to a file, replacing it if it exists. Return the
+    /// log on success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the log file.
+    /// * `level` - How much to log.
+    pub fn create(path: &String, level: PipelineDebug) -> Result<Self> {
+        let file = match fs::File::create(path) {
+            Ok(f) => f,
+            Err(e) => return Err(EmulatorError::io(format!("Could not create pipeline log {}", path), e)),
+        };
+        Ok(Self::new(level, Box::new(BufWriter::new(file))))
+    }
+
+    pub fn level(&self) -> PipelineDebug {
+        self.level
+    }
+
+    /// Change how much to log. Phase changes are compared to the next phase
+    /// logged.
+    /// # Arguments
+    /// * `level` - How much to log.
+    pub fn set_level(&mut self, level: PipelineDebug) {
+        self.level = level;
+        self.last.clear();
+    }
+
+    /// Change the syntax instructions are disassembled in.
+    /// # Arguments
+    /// * `syntax` - Syntax to disassemble in.
+    pub fn set_syntax(&mut self, syntax: Syntax) {
+        self.syntax = syntax;
+    }
+
+    /// Log a core after it ran a clock phase. Return void on success and a
+    /// string on error.
+    /// # Arguments
+    /// * `cycle` - Current clock cycle.
+    /// * `phase` - The phase that ran.
+    /// * `id` - Number of the core.
+    /// * `cpu` - The core's engine.
+    /// * `dp` - The core's data path.
+    pub fn record(
+        &mut self,
+        cycle: u64,
+        phase: &Phase,
+        id: usize,
+        cpu: &dyn Cpu,
+        dp: &DataPath,
+    ) -> Result<()> {
+        if self.level == PipelineDebug::Phase {
+            if self.last.len() <= id {
+                self.last.resize(id + 1, None);
+            }
+            let view = DataPathView::new(dp);
+            if let Some(last) = self.last[id].as_ref() {
+                let diff = view.diff(last);
+                if !diff.is_empty() {
+                    writeln!(
+                        self.out,
+                        "{}.{} core {}: {}",
+                        cycle,
+                        phase.clone() as u8,
+                        id,
+                        diff
+                    )?;
+                }
+            }
+            self.last[id] = Some(view);
+        }
+        if self.level != PipelineDebug::Off && *phase == Phase::Four {
+            writeln!(
+                self.out,
+                "{} core {}: pc {:08x} nxtpc {:08x} dst {:08x} src {:08x} | {}",
+                cycle,
+                id,
+                dp.pc(),
+                dp.nxtpc(),
+                dp.dst_latch(),
+                dp.src_latch(),
+                cpu.describe(self.syntax)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write out anything buffered. Return void on success and a string on
+    /// error.
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+// Recent history of what the pipeline's stages held.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// A scoreboard of the pipeline for the debug window. After every clock
+// phase the instruction in each stage is recorded, the oldest records make
+// way for new ones once the history is full. Reading down a column shows an
+// instruction move from decode to execute to commit, a row where nothing
+// moved is a suspension for a load or store using the bus.
+
+use clock::Phase;
+use config::Syntax;
+use instruction::Instruction;
+use std::collections::VecDeque;
+
+/// What each stage of an engine holds between two clock phases. Bubbles and
+/// engines without a pipeline have None.
+#[derive(Clone, Copy, Default)]
+pub struct Occupancy {
+    /// The instruction being decoded.
+    pub decode: Option<Instruction>,
+    /// The instruction being executed.
+    pub execute: Option<Instruction>,
+    /// The instruction being committed.
+    pub commit: Option<Instruction>,
+    /// Address being fetched, None if the bus is not fetching.
+    pub fetch: Option<u32>,
+    /// True if the pipeline is suspended while a load or store uses the bus.
+    pub suspended: bool,
+}
+
+/// The stages' occupancy at the end of a clock phase.
+#[derive(Clone)]
+pub struct StageRecord {
+    /// Clock cycle of the phase.
+    pub cycle: u64,
+    /// The phase.
+    pub phase: Phase,
+    /// What the stages held.
+    pub stages: Occupancy,
+}
+
+/// The last stage records of a core.
+pub struct PipelineHistory {
+    /// The records, oldest first.
+    records: VecDeque<StageRecord>,
+    /// Most records kept.
+    capacity: usize,
+}
+
+impl StageRecord {
+    /// Describe the record in one line, e.g.
+    /// `12.3 D add r16, r0, 0x1 | E - | C - | fetch 00000014 suspended`.
+    /// # Arguments
+    /// * `syntax` - Syntax to disassemble instructions in.
+    pub fn describe(&self, syntax: Syntax) -> String {
+        let stage = |i: Option<Instruction>| match i {
+            Some(i) => format!("{}", i.disassemble(syntax)),
+            None => "-".to_string(),
+        };
+        format!(
+            "{}.{} D {} | E {} | C {} | {}{}",
+            self.cycle,
+            self.phase.clone() as u8,
+            stage(self.stages.decode),
+            stage(self.stages.execute),
+            stage(self.stages.commit),
+            match self.stages.fetch {
+                Some(address) => format!("fetch {:08x}", address),
+                None => "-".to_string(),
+            },
+            if self.stages.suspended {
+                " suspended"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+impl PipelineHistory {
+    /// Create an empty history.
+    /// # Arguments
+    /// * `capacity` - Most records to keep.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity: capacity,
+        }
+    }
+
+    /// Record what the stages held at the end of a clock phase, forgetting
+    /// the oldest record if the history is full.
+    /// # Arguments
+    /// * `cycle` - Clock cycle of the phase.
+    /// * `phase` - The phase.
+    /// * `stages` - What the stages held.
+    pub fn record(&mut self, cycle: u64, phase: &Phase, stages: Occupancy) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(StageRecord {
+            cycle: cycle,
+            phase: phase.clone(),
+            stages: stages,
+        });
+    }
+
+    /// Get the records, oldest first.
+    pub fn records(&self) -> &VecDeque<StageRecord> {
+        &self.records
+    }
+
+    /// Get the most records kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Forget every record.
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
@@ -0,0 +1,272 @@
+// Host time profiler for the RISC II execution engines.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Besides the host time of every opcode, the profiler counts how often the
+// instruction at every address retired, to find the guest's hot spots, and
+// builds a call graph from the calls and returns that push and pop register
+// windows. A procedure is known by its entry address, the one the profile
+// started at stands for the code outside of any call.
+
+use commit::RetiredInstruction;
+use config::ReportFormat;
+use instruction::Instruction;
+use state::{to_json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::time::Duration;
+use symbols::Symbols;
+use util::Result;
+
+/// Number of hot spots reports list.
+pub const HOT_SPOTS: usize = 20;
+
+/// Host time spent emulating one opcode.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct OpcodeProfile {
+    /// Number of times the opcode retired.
+    pub count: u64,
+    /// Total host time spent on the opcode.
+    pub total: Duration,
+}
+
+/// Measures how much host time each guest opcode costs, where the guest
+/// spends its time and which procedures call which.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    /// Time spent per opcode mnemonic.
+    opcodes: BTreeMap<&'static str, OpcodeProfile>,
+    /// Retirements per instruction address.
+    pcs: BTreeMap<u32, u64>,
+    /// Calls per caller and callee entry address.
+    calls: BTreeMap<(u32, u32), u64>,
+    /// Entry addresses of the procedures not returned from yet, outermost
+    /// first.
+    stack: Vec<u32>,
+    /// Time spent since the last retirement, charged to the next
+    /// instruction to retire.
+    pending: Duration,
+}
+
+impl OpcodeProfile {
+    /// Average host time spent per retirement.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::new(0, 0)
+        } else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for `elapsed` host time spent running a clock phase. The
+    /// pipeline spends several phases on an instruction, so time is held
+    /// until something retires and then charged to it.
+    /// # Arguments
+    /// * `elapsed` - Host time spent on the phase.
+    /// * `retired` - Instruction that retired during the phase, if any.
+    pub fn record(&mut self, elapsed: Duration, retired: Option<&RetiredInstruction>) {
+        self.pending += elapsed;
+        let r = match retired {
+            Some(r) => r,
+            None => return,
+        };
+        let entry = self.opcodes.entry(r.instruction.mnemonic()).or_default();
+        entry.count += 1;
+        entry.total += self.pending;
+        self.pending = Duration::new(0, 0);
+        *self.pcs.entry(r.pc).or_default() += 1;
+        if self.stack.is_empty() {
+            self.stack.push(r.pc);
+        }
+        if r.trap.is_some() {
+            return;
+        }
+        match (r.instruction, r.branch) {
+            (Instruction::Callr(_), Some(target)) | (Instruction::Callx(_), Some(target)) => {
+                let caller = self.stack[self.stack.len() - 1];
+                *self.calls.entry((caller, target)).or_default() += 1;
+                self.stack.push(target);
+            }
+            (Instruction::Ret(_), Some(_)) | (Instruction::Reti(_), Some(_)) => {
+                // Returning from the outermost procedure leaves it on.
+                if self.stack.len() > 1 {
+                    self.stack.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Get the measurements for the opcode with mnemonic `mnemonic`.
+    pub fn get(&self, mnemonic: &str) -> Option<&OpcodeProfile> {
+        self.opcodes.get(mnemonic)
+    }
+
+    /// Get the number of times the instruction at an address retired.
+    /// # Arguments
+    /// * `pc` - Address of the instruction.
+    pub fn pc_count(&self, pc: u32) -> u64 {
+        self.pcs.get(&pc).cloned().unwrap_or(0)
+    }
+
+    /// Get the addresses of the instructions that retired most often and
+    /// how often, most first.
+    /// # Arguments
+    /// * `n` - Most addresses to get.
+    pub fn hot_spots(&self, n: usize) -> Vec<(u32, u64)> {
+        let mut spots: Vec<(u32, u64)> = self.pcs.iter().map(|(pc, c)| (*pc, *c)).collect();
+        // Lower addresses first among equals, as the map has them.
+        spots.sort_by_key(|s| std::cmp::Reverse(s.1));
+        spots.truncate(n);
+        spots
+    }
+
+    /// Get the number of times a procedure called another.
+    /// # Arguments
+    /// * `caller` - Entry address of the caller.
+    /// * `callee` - Entry address of the callee.
+    pub fn call_count(&self, caller: u32, callee: u32) -> u64 {
+        self.calls.get(&(caller, callee)).cloned().unwrap_or(0)
+    }
+
+    /// Get the edges of the call graph, as caller, callee and number of
+    /// calls, by caller and callee.
+    pub fn calls(&self) -> Vec<(u32, u32, u64)> {
+        self.calls
+            .iter()
+            .map(|(&(caller, callee), &count)| (caller, callee, count))
+            .collect()
+    }
+
+    /// Write a report of the profile, naming addresses by their symbols.
+    /// Return the report on success and a string on error.
+    /// # Arguments
+    /// * `symbols` - Symbols of the guest program.
+    /// * `format` - Format of the report.
+    pub fn report(&self, symbols: &Symbols, format: ReportFormat) -> Result<String> {
+        let name = |addr: u32| match symbols.describe(addr) {
+            Some(name) => format!("0x{:08x} <{}>", addr, name),
+            None => format!("0x{:08x}", addr),
+        };
+        match format {
+            ReportFormat::Text => {
+                let mut out = self.to_string();
+                writeln!(out, "\n{:<32} {:>12}", "hot spot", "count")?;
+                for (pc, count) in self.hot_spots(HOT_SPOTS) {
+                    writeln!(out, "{:<32} {:>12}", name(pc), count)?;
+                }
+                writeln!(out, "\n{:<32} {:<32} {:>12}", "caller", "callee", "calls")?;
+                for (caller, callee, count) in self.calls() {
+                    writeln!(
+                        out,
+                        "{:<32} {:<32} {:>12}",
+                        name(caller),
+                        name(callee),
+                        count
+                    )?;
+                }
+                Ok(out)
+            }
+            ReportFormat::Json => {
+                let field = |key: &str, value: Value| (key.to_string(), value);
+                let address = |addr: u32| {
+                    let mut fields = vec![field("address", Value::Str(format!("0x{:08x}", addr)))];
+                    if let Some(name) = symbols.describe(addr) {
+                        fields.push(field("symbol", Value::Str(name)));
+                    }
+                    fields
+                };
+                let opcodes = self
+                    .opcodes
+                    .iter()
+                    .map(|(mnemonic, p)| {
+                        Value::Table(vec![
+                            field("opcode", Value::Str(mnemonic.to_string())),
+                            field("count", Value::Int(p.count)),
+                            field("total_ns", Value::Int(p.total.as_nanos() as u64)),
+                        ])
+                    })
+                    .collect();
+                let hot_spots = self
+                    .hot_spots(HOT_SPOTS)
+                    .into_iter()
+                    .map(|(pc, count)| {
+                        let mut fields = address(pc);
+                        fields.push(field("count", Value::Int(count)));
+                        Value::Table(fields)
+                    })
+                    .collect();
+                let calls = self
+                    .calls()
+                    .into_iter()
+                    .map(|(caller, callee, count)| {
+                        Value::Table(vec![
+                            field("caller", Value::Table(address(caller))),
+                            field("callee", Value::Table(address(callee))),
+                            field("count", Value::Int(count)),
+                        ])
+                    })
+                    .collect();
+                to_json(&Value::Table(vec![
+                    field("opcodes", Value::List(opcodes)),
+                    field("hot_spots", Value::List(hot_spots)),
+                    field("calls", Value::List(calls)),
+                ]))
+            }
+        }
+    }
+
+    /// Total host time spent on all retired instructions.
+    pub fn total(&self) -> Duration {
+        self.opcodes.values().map(|p| p.total).sum()
+    }
+
+    /// Forget all measurements.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total().as_nanos().max(1) as f64;
+        let mut rows: Vec<_> = self.opcodes.iter().collect();
+        // Most expensive first.
+        rows.sort_by_key(|r| std::cmp::Reverse(r.1.total));
+        writeln!(
+            f,
+            "{:<8} {:>12} {:>14} {:>10} {:>7}",
+            "opcode", "count", "total (ns)", "mean (ns)", "share"
+        )?;
+        for (mnemonic, p) in rows {
+            writeln!(
+                f,
+                "{:<8} {:>12} {:>14} {:>10} {:>6.2}%",
+                mnemonic,
+                p.count,
+                p.total.as_nanos(),
+                p.mean().as_nanos(),
+                p.total.as_nanos() as f64 / total * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-// Binary memdmp format.
+// Binary snapshot format.
 // (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
 // This program is free software: you can redistribute it and/or modify
 // it under the terms of the GNU Affero General Public License as published by
@@ -13,49 +13,452 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-// Public function definitions.
+// A snapshot is the magic bytes, the format version and then the state of
+// every part of the system, each written by its `Snapshot` impl. All values
+// are big endian.
 
-use memory::Memory;
-use register::State;
-use util::{concat_paths, get_unix_timestamp, File};
+use data_path::DataPath;
+use expr::Expr;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use util::{concat_paths, get_unix_timestamp, read_file_path, File, Result};
 
-use std::time::{SystemTime, UNIX_EPOCH};
+use berr;
 
-use std::fs::OpenOptions;
+/// Bytes every snapshot starts with.
+pub const MAGIC: &[u8; 4] = b"R2D2";
+/// Version of the snapshot format. Bump it whenever the layout changes.
+pub const VERSION: u32 = 6;
 
-/// Write a save file to the cache directory. On success, return the name of
-/// the save file. Return an error string on failure.
-/// # Arguments
-/// * `state` - Register state to save.
-/// * `mem` - Memory state to save.
-pub fn write(config: &Config, state: &State, mem: &Memory) -> Result<String, String> {
-    // TODO human readable dates.
-    let output_file = concat_paths(
-        &config.cache_path,
-        &format!("{:?}.r2d2", get_unix_timestamp()?),
-    )?;
-    let file = File::open_ops(output_file, &OpenOptions::new().write(true))?;
+/// Part of the system that can be saved to and restored from a snapshot.
+pub trait Snapshot {
+    /// Append `self`'s state to a snapshot.
+    /// # Arguments
+    /// * `w` - Snapshot being written.
+    fn save(&self, w: &mut Writer);
 
-    file.write_buf(&state.to_buf())?;
-    mem.write_to_file(&file)?;
-    Ok(output_file)
+    /// Restore `self`'s state from a snapshot, reading it in the order
+    /// `save` wrote it. Return void on success and a string on error.
+    /// # Arguments
+    /// * `r` - Snapshot being read.
+    fn restore(&mut self, r: &mut Reader) -> Result<()>;
 }
 
-pub fn read(config: &Config, which: &String) -> Result<(State, Memory), String> {
-    let mut file = File::open(&which)?;
-    let mut register_state = [0u8; register::TOTAL_NUM_REGISTERS];
-    if file.read(register_state)? < register_state.len() {
-        return Err(format!(
-            "Archive {} is not large enough to have a register window.",
-            which
-        ));
+/// Captures a snapshot every time a condition becomes true.
+pub struct SnapshotTrigger {
+    /// Condition to watch.
+    condition: Expr,
+    /// Directory snapshots are saved to.
+    dir: String,
+    /// True if the condition held the last time it was checked.
+    was_true: bool,
+    /// Paths of the snapshots captured so far.
+    captured: Vec<String>,
+}
+
+/// How a snapshot of the current session was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotKind {
+    /// Asked for by the user.
+    Manual,
+    /// Taken every few clock cycles.
+    Periodic,
+    /// Taken when the snapshot trigger fired.
+    Trigger,
+    /// Asked for by the guest through the host services.
+    Guest,
+}
+
+/// Snapshot taken during the current session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSnapshot {
+    /// Name of the snapshot, its file name without the extension.
+    pub name: String,
+    /// Path of the snapshot file.
+    pub path: String,
+    /// How it was taken.
+    pub kind: SnapshotKind,
+    /// Clock cycle it was taken at.
+    pub cycle: u64,
+    /// Unix time (in seconds) it was taken at.
+    pub time: u64,
+    /// Size of the snapshot file in bytes.
+    pub size: u64,
+}
+
+/// Snapshots taken during the current session, kept in one directory
+/// (normally the cache directory).
+pub struct SnapshotSession {
+    /// Directory snapshots are saved to.
+    dir: String,
+    /// Clock cycles between periodic snapshots, 0 for none.
+    period: u64,
+    /// Clock cycle the next periodic snapshot is due at.
+    next_periodic: u64,
+    /// Snapshots taken so far, oldest first.
+    snapshots: Vec<SessionSnapshot>,
+}
+
+/// Snapshot being written.
+pub struct Writer {
+    /// Snapshot contents.
+    buf: Vec<u8>,
+}
+
+/// Snapshot being read.
+pub struct Reader {
+    /// Snapshot contents.
+    buf: Vec<u8>,
+    /// Offset of the next value to read.
+    pos: usize,
+}
+
+impl SnapshotTrigger {
+    /// Create a trigger that has not fired yet.
+    /// # Arguments
+    /// * `condition` - Condition to watch.
+    /// * `dir` - Directory to save snapshots to.
+    pub fn new(condition: Expr, dir: String) -> Self {
+        Self {
+            condition: condition,
+            dir: dir,
+            was_true: false,
+            captured: Vec::new(),
+        }
+    }
+
+    /// Check the condition against the data path. Return the path to save a
+    /// snapshot to if the condition just became true, None if not, and a
+    /// string on error.
+    /// # Arguments
+    /// * `dp` - Data path to check.
+    /// * `cycle` - Current clock cycle, used to name the snapshot.
+    pub fn poll(&mut self, dp: &DataPath, cycle: u64) -> Result<Option<String>> {
+        let is_true = self.condition.is_true(dp)?;
+        let fired = is_true && !self.was_true;
+        self.was_true = is_true;
+        if !fired {
+            return Ok(None);
+        }
+        fs::create_dir_all(&self.dir)?;
+        let path = concat_paths(&self.dir, &format!("trigger-{}.r2d2", cycle))?;
+        self.captured.push(path.clone());
+        Ok(Some(path))
+    }
+
+    pub fn condition(&self) -> &Expr {
+        &self.condition
+    }
+
+    /// Get the paths of the snapshots captured so far, oldest first.
+    pub fn captured(&self) -> &Vec<String> {
+        &self.captured
+    }
+}
+
+impl SnapshotKind {
+    /// Get the name snapshots of this kind are labeled and named with.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Self::Manual => "manual",
+            Self::Periodic => "periodic",
+            Self::Trigger => "trigger",
+            Self::Guest => "guest",
+        }
+    }
+}
+
+impl fmt::Display for SessionSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Time of day, UTC.
+        let secs = self.time % (24 * 60 * 60);
+        write!(
+            f,
+            "{:<24} {:<8} cycle {:<10} {:02}:{:02}:{:02}",
+            self.name,
+            self.kind.name(),
+            self.cycle,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+
+impl SnapshotSession {
+    /// Create a session without any snapshots.
+    /// # Arguments
+    /// * `dir` - Directory to save snapshots to.
+    /// * `period` - Clock cycles between periodic snapshots, 0 for none.
+    pub fn new(dir: String, period: u64) -> Self {
+        Self {
+            dir: dir,
+            period: period,
+            next_periodic: period,
+            snapshots: Vec::new(),
+        }
+    }
+
+    /// Get a path for a new snapshot that no other snapshot of the session
+    /// uses, creating the directory if needed. Return the path on success
+    /// and a string on error.
+    /// # Arguments
+    /// * `kind` - How the snapshot is being taken.
+    /// * `cycle` - Current clock cycle, used to name the snapshot.
+    pub fn new_path(&self, kind: SnapshotKind, cycle: u64) -> Result<String> {
+        fs::create_dir_all(&self.dir)?;
+        let base = format!("{}-{}", kind.name(), cycle);
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.find(&name).is_some() {
+            n += 1;
+            name = format!("{}-{}", base, n);
+        }
+        concat_paths(&self.dir, &format!("{}.r2d2", name))
+    }
+
+    /// Add a snapshot that was just saved to the session. Return void on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `kind` - How the snapshot was taken.
+    /// * `path` - Path it was saved to.
+    /// * `cycle` - Clock cycle it was taken at.
+    pub fn record(&mut self, kind: SnapshotKind, path: String, cycle: u64) -> Result<()> {
+        let name = match Path::new(&path).file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => return berr!(Format, format!("Snapshot path {} has no file name", path)),
+        };
+        if kind == SnapshotKind::Periodic {
+            self.next_periodic = cycle + self.period;
+        }
+        let size = fs::metadata(&path)?.len();
+        self.snapshots.push(SessionSnapshot {
+            name: name,
+            path: path,
+            kind: kind,
+            cycle: cycle,
+            time: get_unix_timestamp()?.as_secs(),
+            size: size,
+        });
+        Ok(())
+    }
+
+    /// True if a periodic snapshot should be taken.
+    /// # Arguments
+    /// * `cycle` - Current clock cycle.
+    pub fn is_periodic_due(&self, cycle: u64) -> bool {
+        self.period != 0 && cycle >= self.next_periodic
+    }
+
+    /// Set the clock cycles between periodic snapshots.
+    /// # Arguments
+    /// * `period` - Cycles between snapshots, 0 for none.
+    pub fn set_period(&mut self, period: u64) {
+        self.period = period;
+        self.next_periodic = period;
+    }
+
+    /// Get the total size of the session's snapshot files in bytes.
+    pub fn size(&self) -> u64 {
+        self.snapshots.iter().map(|s| s.size).sum()
+    }
+
+    /// Delete the oldest snapshot the user did not ask for. Return it on
+    /// success, None if there is none, and a string on error.
+    pub fn evict_oldest(&mut self) -> Result<Option<SessionSnapshot>> {
+        let index = match self
+            .snapshots
+            .iter()
+            .position(|s| s.kind != SnapshotKind::Manual)
+        {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        fs::remove_file(&self.snapshots[index].path)?;
+        Ok(Some(self.snapshots.remove(index)))
+    }
+
+    /// Rename a snapshot, moving its file within the session's directory.
+    /// Return void on success and a string on error.
+    /// # Arguments
+    /// * `index` - Index of the snapshot.
+    /// * `name` - New name, made of letters, digits, `-` and `_`.
+    pub fn rename(&mut self, index: usize, name: &str) -> Result<()> {
+        if index >= self.snapshots.len() {
+            return berr!(Format, format!("No snapshot {} in this session", index));
+        }
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return berr!(Format, format!("Invalid snapshot name \"{}\"", name));
+        }
+        if self.snapshots[index].name == name {
+            return Ok(());
+        }
+        let path = concat_paths(&self.dir, &format!("{}.r2d2", name))?;
+        if self.find(name).is_some() || Path::new(&path).exists() {
+            return berr!(Format, format!("A snapshot named {} already exists", name));
+        }
+        fs::rename(&self.snapshots[index].path, &path)?;
+        let snapshot = &mut self.snapshots[index];
+        snapshot.name = name.to_string();
+        snapshot.path = path;
+        Ok(())
+    }
+
+    /// Get the index of the snapshot named `name`, None if there is none.
+    /// # Arguments
+    /// * `name` - Name to look for.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.snapshots.iter().position(|s| s.name == name)
+    }
+
+    /// Get the snapshots taken so far, oldest first.
+    pub fn snapshots(&self) -> &Vec<SessionSnapshot> {
+        &self.snapshots
+    }
+
+    pub fn dir(&self) -> &String {
+        &self.dir
+    }
+}
+
+impl Writer {
+    /// Create an empty snapshot holding just the header.
+    pub fn new() -> Self {
+        let mut result = Self { buf: Vec::new() };
+        result.buf.extend_from_slice(MAGIC);
+        result.put_u32(VERSION);
+        result
+    }
+
+    pub fn put_bool(&mut self, v: bool) {
+        self.put_u8(v as u8);
+    }
+
+    pub fn put_u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn put_u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn put_u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn put_u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    /// Write a byte buffer, prefixed with its length.
+    /// # Arguments
+    /// * `v` - Bytes to write.
+    pub fn put_bytes(&mut self, v: &[u8]) {
+        self.put_u32(v.len() as u32);
+        self.buf.extend_from_slice(v);
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Write the snapshot to a file, replacing it if it exists. Return void
+    /// on success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the snapshot file.
+    pub fn write_file(&self, path: &String) -> Result<()> {
+        File::open_ops(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )?
+        .write_buf(&self.buf)
     }
+}
 
-    let metadata = file.get_metadata()?;
-    let mut memory = vec![0u8; metadata.size() as usize - register::TOTAL_NUM_REGISTERS];
+impl Reader {
+    /// Start reading a snapshot, checking its header. Return the reader on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `buf` - Snapshot contents.
+    pub fn new(buf: Vec<u8>) -> Result<Self> {
+        let mut result = Self { buf: buf, pos: 0 };
+        if result.take(MAGIC.len())? != MAGIC {
+            return berr!(Format, "Not a snapshot: bad magic bytes");
+        }
+        let version = result.get_u32()?;
+        if version != VERSION {
+            return berr!(Format, format!(
+                "Snapshot is version {}, expected version {}",
+                version, VERSION
+            ));
+        }
+        Ok(result)
+    }
 
-    file.read_into_vec(&mut memory)?;
+    /// Open a snapshot file. Return the reader on success and a string on
+    /// error.
+    /// # Arguments
+    /// * `path` - Path of the snapshot file.
+    pub fn open(path: &String) -> Result<Self> {
+        Self::new(read_file_path(path)?)
+    }
 
-    // TODO make sure there is no deep copying of memory happening
-    Ok(State::from_buf(register_state), Memory::from_vec(&memory))
+    pub fn get_bool(&mut self) -> Result<bool> {
+        Ok(self.get_u8()? != 0)
+    }
+
+    pub fn get_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn get_u16(&mut self) -> Result<u16> {
+        let mut bytes = [0u8; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    pub fn get_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn get_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Read a byte buffer written by `Writer::put_bytes`.
+    pub fn get_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.get_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Check that the whole snapshot was read. Return void on success and a
+    /// string on error.
+    pub fn finish(&self) -> Result<()> {
+        if self.pos != self.buf.len() {
+            return berr!(Format, format!(
+                "Snapshot has {} trailing bytes",
+                self.buf.len() - self.pos
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the next `n` bytes of the snapshot.
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if self.buf.len() - self.pos < n {
+            return berr!(Format, format!("Snapshot is truncated at offset {}", self.pos));
+        }
+        let start = self.pos;
+        self.pos += n;
+        Ok(&self.buf[start..self.pos])
+    }
 }
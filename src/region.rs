@@ -0,0 +1,227 @@
+// Memory regions and their permissions.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// A map of physical memory saying what may be read, written and executed,
+// declared with --region. Without any regions everything is allowed, like a
+// bare RISC II. Once there are regions, memory outside of them cannot be
+// accessed at all, and regions declared later take precedence over earlier
+// ones where they overlap. Loading an image makes the memory it covers
+// executable, so a program can be put in a read only region and run from
+// there without being able to overwrite itself.
+//
+// Accesses a region does not allow raise an access violation, like the MMU
+// refusing them. The check is on physical addresses, after translation.
+
+use std::fmt;
+use util::Result;
+
+use berr;
+
+/// Kinds of access to a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Read,
+    Write,
+    Execute,
+}
+
+/// What a region allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+/// A range of physical memory and what it allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// Address of the first byte.
+    pub base: u32,
+    /// Size in bytes.
+    pub size: u32,
+    /// What the region allows.
+    pub permissions: Permissions,
+}
+
+/// The regions of physical memory, later ones taking precedence.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegionMap {
+    regions: Vec<Region>,
+}
+
+impl Permissions {
+    /// Get permissions from their name, `rom` (`rx`), `ram` (`rw`), `device`
+    /// (`rw`) or any of the letters `r`, `w` and `x`. Return the permissions
+    /// on success and a string on error.
+    /// # Arguments
+    /// * `name` - Name of the permissions.
+    pub fn from_name(name: &str) -> Result<Self> {
+        let letters = match name {
+            "rom" => "rx",
+            "ram" | "device" => "rw",
+            "" => return berr!(Config, "Region permissions are empty, expected rom, ram, device or rwx"),
+            _ => name,
+        };
+        let mut result = Self::default();
+        for c in letters.chars() {
+            match c {
+                'r' => result.read = true,
+                'w' => result.write = true,
+                'x' => result.execute = true,
+                '-' => {}
+                _ => {
+                    return berr!(Config, format!(
+                        "Invalid region permissions: {}, expected rom, ram, device or rwx",
+                        name
+                    ))
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Return true if `permission` is allowed.
+    /// # Arguments
+    /// * `permission` - Kind of access.
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Read => self.read,
+            Permission::Write => self.write,
+            Permission::Execute => self.execute,
+        }
+    }
+}
+
+impl Region {
+    /// Return true if the region holds `addr`.
+    /// # Arguments
+    /// * `addr` - Physical address.
+    pub fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.base) < self.size
+    }
+}
+
+impl RegionMap {
+    /// Create a map of regions.
+    /// # Arguments
+    /// * `regions` - The regions, later ones taking precedence.
+    pub fn new(regions: Vec<Region>) -> Self {
+        Self { regions: regions }
+    }
+
+    /// Get the regions, later ones taking precedence.
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Get what the memory at an address allows, None if no region holds
+    /// it.
+    /// # Arguments
+    /// * `addr` - Physical address.
+    pub fn permissions_at(&self, addr: u32) -> Option<Permissions> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|r| r.contains(addr))
+            .map(|r| r.permissions)
+    }
+
+    /// Return true if an access is allowed on every byte it covers.
+    /// # Arguments
+    /// * `addr` - Physical address of the first byte.
+    /// * `width` - Number of bytes accessed.
+    /// * `permission` - Kind of access.
+    pub fn allows(&self, addr: u32, width: u32, permission: Permission) -> bool {
+        if self.regions.is_empty() {
+            return true;
+        }
+        (0..width).all(|i| {
+            self.permissions_at(addr.wrapping_add(i))
+                .map_or(false, |p| p.allows(permission))
+        })
+    }
+
+    /// Make a range of memory executable, keeping what else it allows. The
+    /// range is split where the regions it overlaps start and end, so each
+    /// piece keeps its own permissions and memory around it is left alone.
+    /// Does nothing if there are no regions, as everything is executable
+    /// then.
+    /// # Arguments
+    /// * `addr` - Address of the first byte.
+    /// * `size` - Number of bytes.
+    pub fn mark_executable(&mut self, addr: u32, size: u32) {
+        if self.regions.is_empty() || size == 0 {
+            return;
+        }
+        let end = addr as u64 + size as u64;
+        let mut cuts: Vec<u64> = self
+            .regions
+            .iter()
+            .flat_map(|r| vec![r.base as u64, r.base as u64 + r.size as u64])
+            .filter(|&cut| cut > addr as u64 && cut < end)
+            .collect();
+        cuts.push(end);
+        cuts.sort();
+        cuts.dedup();
+        let mut start = addr as u64;
+        for cut in cuts {
+            let mut permissions = self.permissions_at(start as u32).unwrap_or_default();
+            if !permissions.execute {
+                permissions.execute = true;
+                let size = (cut - start) as u32;
+                match self.regions.last_mut() {
+                    // Grow the region made for the piece before.
+                    Some(r)
+                        if r.permissions == permissions
+                            && r.base as u64 + r.size as u64 == start =>
+                    {
+                        r.size += size
+                    }
+                    _ => self.regions.push(Region {
+                        base: start as u32,
+                        size: size,
+                        permissions: permissions,
+                    }),
+                }
+            }
+            start = cut;
+        }
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            if self.read { 'r' } else { '-' },
+            if self.write { 'w' } else { '-' },
+            if self.execute { 'x' } else { '-' }
+        )
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:08x}-{:08x} {}",
+            self.base,
+            self.base.wrapping_add(self.size).wrapping_sub(1),
+            self.permissions
+        )
+    }
+}
@@ -0,0 +1,117 @@
+// Test code for RISC II memory.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "region.rs"]
+mod test {
+    use super::super::*;
+    use region::*;
+
+    fn region(base: u32, size: u32, name: &str) -> Region {
+        Region {
+            base: base,
+            size: size,
+            permissions: Permissions::from_name(name).unwrap(),
+        }
+    }
+
+    #[test]
+    fn permissions_from_name() {
+        let rom = Permissions::from_name("rom").unwrap();
+        assert!(rom.read && !rom.write && rom.execute);
+        assert_eq!(
+            Permissions::from_name("ram").unwrap(),
+            Permissions::from_name("rw").unwrap()
+        );
+        assert_eq!(
+            Permissions::from_name("device").unwrap(),
+            Permissions::from_name("rw-").unwrap()
+        );
+        assert_eq!(format!("{}", Permissions::from_name("xr").unwrap()), "r-x");
+        assert!(Permissions::from_name("").is_err());
+        assert!(Permissions::from_name("rwz").is_err());
+    }
+
+    #[test]
+    fn later_regions_take_precedence() {
+        let map = RegionMap::new(vec![region(0, 0x100, "ram"), region(0x40, 0x10, "rom")]);
+        assert!(map.allows(0x3c, 4, Permission::Write));
+        assert!(!map.allows(0x40, 4, Permission::Write));
+        assert!(map.allows(0x40, 4, Permission::Execute));
+        // Straddling the two regions needs both to allow it.
+        assert!(!map.allows(0x3e, 4, Permission::Write));
+        assert!(map.allows(0x3e, 4, Permission::Read));
+        // Outside every region.
+        assert!(!map.allows(0xfe, 4, Permission::Read));
+        assert!(!map.allows(0x100, 1, Permission::Read));
+        // No regions at all allow everything.
+        assert!(RegionMap::default().allows(0xfffffffc, 4, Permission::Write));
+    }
+
+    #[test]
+    fn mark_executable_covers_the_image() {
+        let mut map = RegionMap::new(vec![region(0, 0x4000, "ram")]);
+        map.mark_executable(0x0ffc, 0x1008);
+        assert_eq!(map.regions().len(), 2);
+        let marked = map.regions()[1];
+        assert_eq!(marked, region(0x0ffc, 0x1008, "rwx"));
+        assert!(map.allows(0x2000, 4, Permission::Execute));
+        assert!(!map.allows(0x0ff8, 4, Permission::Execute));
+        assert!(!map.allows(0x2004, 4, Permission::Execute));
+        // Memory outside every region can only be executed.
+        map.mark_executable(0x8000, 4);
+        assert_eq!(map.permissions_at(0x8000), Permissions::from_name("x").ok());
+
+        let mut empty = RegionMap::default();
+        empty.mark_executable(0, 0x1000);
+        assert!(empty.regions().is_empty());
+    }
+
+    #[test]
+    fn mark_executable_keeps_regions_smaller_than_a_page() {
+        let mut map = RegionMap::new(vec![
+            region(0x1000, 0x800, "rom"),
+            region(0x1800, 0x800, "ram"),
+            region(0x1c00, 0x100, "r"),
+        ]);
+        // An image spanning the ROM, the RAM and the read only hole in it.
+        map.mark_executable(0x1000, 0x1000);
+        assert!(map.allows(0x1800, 4, Permission::Write));
+        assert!(map.allows(0x1800, 4, Permission::Execute));
+        assert!(!map.allows(0x1000, 4, Permission::Write));
+        assert!(!map.allows(0x1c00, 4, Permission::Write));
+        assert!(map.allows(0x1c00, 4, Permission::Execute));
+        assert!(map.allows(0x1d00, 4, Permission::Write));
+        // The ROM was executable already.
+        assert_eq!(
+            &map.regions()[3..],
+            &[
+                region(0x1800, 0x400, "rwx"),
+                region(0x1c00, 0x100, "rx"),
+                region(0x1d00, 0x300, "rwx"),
+            ]
+        );
+
+        // An image smaller than the RAM leaves the rest of it alone.
+        let mut map = RegionMap::new(vec![
+            region(0x1000, 0x800, "rom"),
+            region(0x1800, 0x800, "ram"),
+        ]);
+        map.mark_executable(0x1800, 0x10);
+        assert!(map.allows(0x180c, 4, Permission::Execute));
+        assert!(!map.allows(0x1810, 4, Permission::Execute));
+        assert!(map.allows(0x1810, 4, Permission::Write));
+    }
+}
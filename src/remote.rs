@@ -0,0 +1,400 @@
+// Remote control of a headless system over HTTP.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Serves the state of a system running without windows as JSON and takes run
+// control commands, so a dashboard in a browser, on this machine or another,
+// can follow and drive it. A System is not Send, so the server answers on
+// the thread running the system, between frames, one request per connection:
+//   GET /status               run state, clock cycle, exit code, breakpoints
+//   GET /state                clock, PSW, registers and latches, as
+//                             `--export_state` writes them
+//   GET /trace                the last `TRACE_LEN` instructions that
+//                             retired, oldest first
+//   GET /backtrace            the calls in progress on the boot core,
+//                             innermost first
+//   POST /pause, /resume, /step
+//   POST /reset/warm, /reset/cold
+//   POST /breakpoints/ADDR    set a breakpoint, DELETE clears it
+//   POST /registers/REG/VALUE write a register of the boot core, while
+//                             the system does not run
+//   POST /memory/ADDR/VALUE   write a word of memory, likewise
+//   POST /quit                stop serving and finish the run
+// Commands answer with the status after them. Anything else is a 404, a
+// command that fails a 500 with the error. Responses allow any origin, so a
+// page served from somewhere else can use them.
+
+use call_stack::CallStack;
+use config::StateFormat;
+use device::Reset;
+use runner::{handle, RunnerCommand};
+use state::{export_state, parse_number, to_json, Value};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+use system::{RunState, System, FRAME_TIME};
+use util::Result;
+
+use berr;
+
+/// Number of retired instructions `/trace` lists.
+pub const TRACE_LEN: usize = 64;
+/// Longest a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// Most bytes of a request read, the rest is ignored.
+const MAX_REQUEST: usize = 8192;
+
+/// An instruction that retired, as `/trace` lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TraceEntry {
+    cycle: u64,
+    pc: u32,
+    instruction: String,
+    trap: Option<String>,
+}
+
+/// A response to a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// HTTP status code.
+    pub code: u16,
+    /// JSON body, empty for none.
+    pub body: String,
+}
+
+/// Server answering requests about one system.
+pub struct RemoteServer {
+    listener: TcpListener,
+    /// The last instructions that retired, oldest first.
+    trace: Rc<RefCell<VecDeque<TraceEntry>>>,
+    /// True once a client asked to quit.
+    quit: bool,
+}
+
+impl RemoteServer {
+    /// Listen for requests about a system and start keeping its trace.
+    /// Return the server on success and a string if the address cannot be
+    /// listened on.
+    /// # Arguments
+    /// * `address` - Address to listen on, e.g. `127.0.0.1:8080`.
+    /// * `system` - The system.
+    pub fn bind(address: &str, system: &mut System) -> Result<Self> {
+        let listener = match TcpListener::bind(address) {
+            Ok(l) => l,
+            Err(e) => return berr!(Config, format!("Cannot listen on {}: {}", address, e)),
+        };
+        listener.set_nonblocking(true)?;
+        let trace = Rc::new(RefCell::new(VecDeque::with_capacity(TRACE_LEN)));
+        let hook_trace = trace.clone();
+        system.on_retire(move |r| {
+            let mut trace = hook_trace.borrow_mut();
+            if trace.len() == TRACE_LEN {
+                trace.pop_front();
+            }
+            trace.push_back(TraceEntry {
+                cycle: r.cycle,
+                pc: r.pc,
+                instruction: r.instruction.to_string(),
+                trap: r.trap.map(|t| t.to_string()),
+            });
+        });
+        Ok(Self {
+            listener: listener,
+            trace: trace,
+            quit: false,
+        })
+    }
+
+    /// Get the address the server listens on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// True once a client asked to quit.
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    /// Run the system a frame at a time, answering requests in between,
+    /// until a client asks to quit. A system that does not run keeps being
+    /// served. Return void on success and a string if the system failed.
+    /// # Arguments
+    /// * `system` - The system.
+    pub fn serve(&mut self, system: &mut System) -> Result<()> {
+        while !self.quit {
+            self.poll(system)?;
+            if system.run_state() == RunState::Running {
+                system.run_frame()?;
+            } else {
+                thread::sleep(FRAME_TIME);
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer every request waiting, without blocking if there are none.
+    /// A client that does not get its request through in time is dropped.
+    /// Return void on success and a string if a command failed the system.
+    /// # Arguments
+    /// * `system` - The system.
+    pub fn poll(&mut self, system: &mut System) -> Result<()> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((s, _)) => s,
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            // The client may hang up at any point, that is its business.
+            let _ = self.answer(stream, system);
+        }
+    }
+
+    /// Answer a request. Return the response.
+    /// # Arguments
+    /// * `method` - Method of the request, e.g. `GET`.
+    /// * `path` - Path of the request.
+    /// * `system` - The system.
+    pub fn respond(&mut self, method: &str, path: &str, system: &mut System) -> Response {
+        let command = match (method, path) {
+            ("OPTIONS", _) => return response(204, String::new()),
+            ("GET", "/status") => None,
+            ("GET", "/state") => {
+                return match export_state(system, &[], StateFormat::Json) {
+                    Ok(state) => response(200, state),
+                    Err(e) => error(500, &e.to_string()),
+                };
+            }
+            ("GET", "/trace") => return self.trace_response(),
+            ("GET", "/backtrace") => return backtrace_response(system),
+            ("POST", "/pause") => Some(RunnerCommand::Pause),
+            ("POST", "/resume") => Some(RunnerCommand::Resume),
+            ("POST", "/step") => Some(RunnerCommand::Step),
+            ("POST", "/reset/warm") => Some(RunnerCommand::Reset(Reset::Warm)),
+            ("POST", "/reset/cold") => Some(RunnerCommand::Reset(Reset::Cold)),
+            ("POST", "/quit") => {
+                self.quit = true;
+                None
+            }
+            (m, p) if p.starts_with("/breakpoints/") && (m == "POST" || m == "DELETE") => {
+                match (parse_number(&p["/breakpoints/".len()..]), m) {
+                    (Ok(a), "POST") => Some(RunnerCommand::SetBreakpoint(a)),
+                    (Ok(a), _) => Some(RunnerCommand::ClearBreakpoint(a)),
+                    (Err(e), _) => return error(400, &e.to_string()),
+                }
+            }
+            ("POST", p) if p.starts_with("/registers/") || p.starts_with("/memory/") => {
+                let mut words = p[1..].split('/');
+                let line = format!(
+                    "set {} {}",
+                    if words.next() == Some("registers") {
+                        "reg"
+                    } else {
+                        "mem"
+                    },
+                    words.collect::<Vec<&str>>().join(" ")
+                );
+                match RunnerCommand::parse(&line) {
+                    Ok(c) => Some(c),
+                    Err(e) => return error(400, &e.to_string()),
+                }
+            }
+            _ => return error(404, &format!("No {} {}", method, path)),
+        };
+        if let Some(command) = command {
+            if let Err(e) = handle(system, command) {
+                return error(500, &e.to_string());
+            }
+        }
+        match to_json(&status(system)) {
+            Ok(body) => response(200, body),
+            Err(e) => error(500, &e.to_string()),
+        }
+    }
+
+    /// Get the trace as a response.
+    fn trace_response(&self) -> Response {
+        let entries = self
+            .trace
+            .borrow()
+            .iter()
+            .map(|e| {
+                let mut entry = vec![
+                    ("cycle".to_string(), Value::Int(e.cycle)),
+                    ("pc".to_string(), Value::Str(format!("0x{:08x}", e.pc))),
+                    ("instruction".to_string(), Value::Str(e.instruction.clone())),
+                ];
+                if let Some(trap) = e.trap.as_ref() {
+                    entry.push(("trap".to_string(), Value::Str(trap.clone())));
+                }
+                Value::Table(entry)
+            })
+            .collect();
+        match to_json(&Value::Table(vec![(
+            "retired".to_string(),
+            Value::List(entries),
+        )])) {
+            Ok(body) => response(200, body),
+            Err(e) => error(500, &e.to_string()),
+        }
+    }
+
+    /// Read a request from a client and answer it.
+    /// # Arguments
+    /// * `stream` - Connection to the client.
+    /// * `system` - The system.
+    fn answer(&mut self, mut stream: TcpStream, system: &mut System) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        // Only the request line matters, read up to the end of the headers.
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        let text = String::from_utf8_lossy(&request);
+        let mut words = text.lines().next().unwrap_or("").split_whitespace();
+        let r = match (words.next(), words.next()) {
+            (Some(method), Some(path)) => {
+                // A query string means nothing here.
+                let path = path.split('?').next().unwrap_or(path);
+                self.respond(method, path, system)
+            }
+            _ => error(400, "Malformed request"),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Access-Control-Allow-Origin: *\r\n\
+             Access-Control-Allow-Methods: GET, POST, DELETE, OPTIONS\r\n\
+             Connection: close\r\n\r\n{}",
+            r.code,
+            reason(r.code),
+            r.body.len(),
+            r.body
+        )?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// Get the status of a system.
+/// # Arguments
+/// * `system` - The system.
+fn status(system: &System) -> Value {
+    let mut entries = vec![
+        (
+            "state".to_string(),
+            Value::Str(
+                match system.run_state() {
+                    RunState::Running => "running",
+                    RunState::Paused => "paused",
+                    RunState::Halted => "halted",
+                }
+                .to_string(),
+            ),
+        ),
+        ("cycle".to_string(), Value::Int(system.clock().count())),
+        (
+            "pc".to_string(),
+            Value::Str(format!("0x{:08x}", system.data_path().get_pc())),
+        ),
+        (
+            "breakpoints".to_string(),
+            Value::List(
+                system
+                    .breakpoints()
+                    .iter()
+                    .map(|b| Value::Str(format!("0x{:08x}", b)))
+                    .collect(),
+            ),
+        ),
+    ];
+    if let Some(code) = system.exit_code() {
+        entries.push(("exit_code".to_string(), Value::Int(code as u64)));
+    }
+    Value::Table(entries)
+}
+
+/// Get the call stack of the boot core as a response.
+/// # Arguments
+/// * `system` - The system.
+fn backtrace_response(system: &System) -> Response {
+    let stack = CallStack::new(system.data_path(), system.symbols());
+    let frames = stack
+        .frames
+        .iter()
+        .map(|f| {
+            let mut frame = vec![
+                ("window".to_string(), Value::Int(f.window as u64)),
+                ("pc".to_string(), Value::Str(format!("0x{:08x}", f.pc))),
+                (
+                    "sp".to_string(),
+                    Value::Str(format!("0x{:08x}", f.frame.sp)),
+                ),
+                (
+                    "fp".to_string(),
+                    Value::Str(format!("0x{:08x}", f.frame.fp)),
+                ),
+            ];
+            if let Some(function) = f.function.as_ref() {
+                frame.push(("function".to_string(), Value::Str(function.clone())));
+            }
+            Value::Table(frame)
+        })
+        .collect();
+    match to_json(&Value::Table(vec![(
+        "frames".to_string(),
+        Value::List(frames),
+    )])) {
+        Ok(body) => response(200, body),
+        Err(e) => error(500, &e.to_string()),
+    }
+}
+
+fn response(code: u16, body: String) -> Response {
+    Response {
+        code: code,
+        body: body,
+    }
+}
+
+/// Get a response telling what went wrong.
+/// # Arguments
+/// * `code` - HTTP status code.
+/// * `message` - What went wrong.
+fn error(code: u16, message: &str) -> Response {
+    let body = Value::Table(vec![("error".to_string(), Value::Str(message.to_string()))]);
+    response(code, to_json(&body).unwrap_or_default())
+}
+
+/// Get the reason phrase of an HTTP status code.
+fn reason(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Internal Server Error",
+    }
+}
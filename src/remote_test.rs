@@ -0,0 +1,107 @@
+// Test code for the remote server.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "remote.rs"]
+mod test {
+    use super::super::*;
+    use config::ClockMode;
+    use instruction::*;
+    use remote::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use util::Result;
+
+    /// Make a system running nothing but no-ops.
+    fn make_system() -> Result<System> {
+        let nop = Instruction::Add(ShortInstruction::new(false, 0, 0, ShortSource::Reg(0)));
+        let mut system = System::new(&Config::new()?)?;
+        system.set_clock_mode(ClockMode::Unlimited);
+        for i in 0..16 {
+            system.get_mem_ref().set_word(i * 4, nop.encode())?;
+        }
+        Ok(system)
+    }
+
+    #[test]
+    fn requests_control_the_system() -> Result<()> {
+        let mut system = make_system()?;
+        let mut server = RemoteServer::bind("127.0.0.1:0", &mut system)?;
+
+        let r = server.respond("POST", "/pause", &mut system);
+        assert_eq!(r.code, 200);
+        assert!(r.body.contains("\"state\": \"paused\""), "{}", r.body);
+        let r = server.respond("POST", "/step", &mut system);
+        assert!(r.body.contains("\"state\": \"paused\""), "{}", r.body);
+        let r = server.respond("GET", "/trace", &mut system);
+        assert_eq!(r.code, 200);
+        assert!(r.body.contains("\"pc\": \"0x00000000\""), "{}", r.body);
+        assert!(r.body.contains("\"instruction\": \"add"), "{}", r.body);
+
+        let r = server.respond("POST", "/breakpoints/0x8", &mut system);
+        assert!(r.body.contains("\"0x00000008\""), "{}", r.body);
+        assert!(system.breakpoints().contains(&8));
+        server.respond("DELETE", "/breakpoints/8", &mut system);
+        assert!(system.breakpoints().is_empty());
+        assert_eq!(
+            server.respond("POST", "/breakpoints/x", &mut system).code,
+            400
+        );
+
+        server.respond("POST", "/registers/r16/0x1234", &mut system);
+        assert_eq!(system.data_path().register_file().read(16, 0), 0x1234);
+        server.respond("POST", "/memory/0x3c/0xdeadbeef", &mut system);
+        assert_eq!(system.memory().get_word(0x3c)?, 0xdeadbeef);
+        assert_eq!(
+            server.respond("POST", "/registers/r99/1", &mut system).code,
+            400
+        );
+
+        let r = server.respond("GET", "/backtrace", &mut system);
+        assert_eq!(r.code, 200);
+        assert!(r.body.contains("\"window\""), "{}", r.body);
+
+        let r = server.respond("GET", "/state", &mut system);
+        assert_eq!(r.code, 200);
+        assert!(r.body.contains("\"cycle\""), "{}", r.body);
+        assert_eq!(server.respond("GET", "/pause", &mut system).code, 404);
+        assert_eq!(server.respond("OPTIONS", "/step", &mut system).code, 204);
+
+        assert!(!server.quit_requested());
+        server.respond("POST", "/quit", &mut system);
+        assert!(server.quit_requested());
+        Ok(())
+    }
+
+    #[test]
+    fn requests_are_answered_over_http() -> Result<()> {
+        let mut system = make_system()?;
+        let mut server = RemoteServer::bind("127.0.0.1:0", &mut system)?;
+        let mut client = TcpStream::connect(server.local_addr()?)?;
+        client.write_all(b"POST /pause?now HTTP/1.1\r\nHost: riscii\r\n\r\n")?;
+        server.poll(&mut system)?;
+
+        let mut response = String::new();
+        client.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(
+            response.contains("Access-Control-Allow-Origin: *\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.contains("\"state\": \"paused\""), "{}", response);
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+// Record and replay of the inputs from outside the guest.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Everything else a run depends on (the timer included) counts clock
+// cycles, so a run fed the same inputs at the same cycles as another does
+// exactly what it did. The inputs are the keyboard and gamepad events the
+// host sends and the time of day the guest reads from the real-time clock.
+// Recording logs each with the clock cycle it took effect on, replaying
+// feeds them back on those cycles and ignores the host's. The host sends
+// events through the system, which applies them when the next clock cycle
+// starts, recording or not, so the cycle is the same both times. Whether
+// the clock is paced (see the host device) comes from the configuration,
+// replay a run with the clock mode it was recorded with.
+//
+// An input log is text, one input per line: the clock cycle and then one of
+//   key_down SCANCODE
+//   key_up SCANCODE
+//   gamepad_connected 0|1
+//   gamepad_button BUTTON 0|1
+//   gamepad_axis AXIS VALUE
+//   time SECONDS NANOSECONDS
+// Blank lines and lines starting with `#` are skipped.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::rc::Rc;
+use std::time::Duration;
+use util::{get_unix_timestamp, Result};
+
+use berr;
+
+/// An input from outside the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// A key was pressed, by scancode.
+    KeyDown(u32),
+    /// A key was released, by scancode.
+    KeyUp(u32),
+    /// A gamepad was plugged in or out.
+    GamepadConnected(bool),
+    /// A gamepad button was pressed or released.
+    GamepadButton(u32, bool),
+    /// A gamepad axis moved.
+    GamepadAxis(u32, i16),
+    /// The guest read the time of day.
+    Time(Duration),
+}
+
+/// An input and the clock cycle it took effect on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputRecord {
+    /// Clock cycle the input took effect on.
+    pub cycle: u64,
+    /// The input.
+    pub input: Input,
+}
+
+/// What an input log does with the inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Pass them on.
+    Live,
+    /// Pass them on and log them.
+    Record,
+    /// Feed the logged ones instead.
+    Replay,
+}
+
+/// State shared between the system and the real-time clock.
+struct InputLogState {
+    mode: InputMode,
+    /// Clock cycle running.
+    cycle: u64,
+    /// Inputs logged when recording, the events left to feed when
+    /// replaying, oldest first.
+    records: VecDeque<InputRecord>,
+    /// Times of day left to feed when replaying, oldest first.
+    times: VecDeque<InputRecord>,
+    /// Cycle the replayed run first read the time on a cycle the recorded
+    /// run did not, None while it reads it on the same ones.
+    diverged: Option<u64>,
+}
+
+/// Records or replays the inputs of a run.
+#[derive(Clone)]
+pub struct InputLog {
+    state: Rc<RefCell<InputLogState>>,
+}
+
+impl InputLog {
+    /// Create a log that passes inputs on without logging them.
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(InputLogState {
+                mode: InputMode::Live,
+                cycle: 0,
+                records: VecDeque::new(),
+                times: VecDeque::new(),
+                diverged: None,
+            })),
+        }
+    }
+
+    pub fn mode(&self) -> InputMode {
+        self.state.borrow().mode
+    }
+
+    /// Start logging inputs, forgetting any logged before.
+    pub fn record(&self) {
+        let mut state = self.state.borrow_mut();
+        state.mode = InputMode::Record;
+        state.records.clear();
+        state.times.clear();
+        state.diverged = None;
+    }
+
+    /// Start feeding logged inputs instead of the host's.
+    /// # Arguments
+    /// * `records` - The inputs, oldest first.
+    pub fn replay(&self, records: Vec<InputRecord>) {
+        let mut state = self.state.borrow_mut();
+        state.mode = InputMode::Replay;
+        let (times, events) = records.into_iter().partition(|r| match r.input {
+            Input::Time(_) => true,
+            _ => false,
+        });
+        state.records = events;
+        state.times = times;
+        state.diverged = None;
+    }
+
+    /// Get the inputs logged so far when recording, the ones left to feed
+    /// when replaying, oldest first.
+    pub fn records(&self) -> Vec<InputRecord> {
+        let state = self.state.borrow();
+        let mut records: Vec<InputRecord> = state.records.iter().cloned().collect();
+        records.extend(state.times.iter().cloned());
+        // Stable, so inputs of the same cycle keep their order.
+        records.sort_by_key(|r| r.cycle);
+        records
+    }
+
+    /// Get the first cycle a replayed run read the time on that the
+    /// recorded run did not, None if there is none.
+    pub fn diverged(&self) -> Option<u64> {
+        self.state.borrow().diverged
+    }
+
+    /// Start a clock cycle. Return the inputs that take effect on it: the
+    /// host's, unless replaying, when they are the logged ones.
+    /// # Arguments
+    /// * `cycle` - The clock cycle.
+    /// * `host` - Inputs the host sent since the last cycle started.
+    pub fn start_cycle(&self, cycle: u64, host: Vec<Input>) -> Vec<Input> {
+        let mut state = self.state.borrow_mut();
+        state.cycle = cycle;
+        match state.mode {
+            InputMode::Live => host,
+            InputMode::Record => {
+                for input in host.iter() {
+                    state.records.push_back(InputRecord {
+                        cycle: cycle,
+                        input: *input,
+                    });
+                }
+                host
+            }
+            InputMode::Replay => {
+                let mut inputs = Vec::new();
+                while state.records.front().map_or(false, |r| r.cycle <= cycle) {
+                    inputs.extend(state.records.pop_front().map(|r| r.input));
+                }
+                inputs
+            }
+        }
+    }
+
+    /// Get the time of day for the guest: the host's, unless replaying, when
+    /// it is the logged one.
+    pub fn time(&self) -> Duration {
+        let mut state = self.state.borrow_mut();
+        let cycle = state.cycle;
+        match state.mode {
+            InputMode::Replay => match state.times.pop_front() {
+                Some(InputRecord {
+                    cycle: c,
+                    input: Input::Time(t),
+                }) if c == cycle => t,
+                next => {
+                    state.diverged = state.diverged.or(Some(cycle));
+                    match next.map(|r| r.input) {
+                        Some(Input::Time(t)) => t,
+                        _ => host_time(),
+                    }
+                }
+            },
+            InputMode::Record => {
+                let time = host_time();
+                state.records.push_back(InputRecord {
+                    cycle: cycle,
+                    input: Input::Time(time),
+                });
+                time
+            }
+            InputMode::Live => host_time(),
+        }
+    }
+
+    /// Write the logged inputs to a file. Return void on success and a
+    /// string on error.
+    /// # Arguments
+    /// * `path` - Path of the file.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut text = String::new();
+        for r in self.records().iter() {
+            text.push_str(&format!("{}\n", r));
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Get the host's time of day. A host clock before the epoch reads as the
+/// epoch.
+fn host_time() -> Duration {
+    get_unix_timestamp().unwrap_or(Duration::new(0, 0))
+}
+
+/// Parse an input log. Return the inputs, oldest first, on success and a
+/// string on error.
+/// # Arguments
+/// * `text` - The log.
+pub fn parse_inputs(text: &str) -> Result<Vec<InputRecord>> {
+    let mut records: Vec<InputRecord> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let r = match parse_record(line) {
+            Some(r) => r,
+            None => return berr!(Format, format!("Invalid input on line {}: {}", n + 1, line)),
+        };
+        if records.last().map_or(false, |last| last.cycle > r.cycle) {
+            return berr!(Format, format!("Input on line {} is out of order", n + 1));
+        }
+        records.push(r);
+    }
+    Ok(records)
+}
+
+/// Read an input log. Return the inputs, oldest first, on success and a
+/// string on error.
+/// # Arguments
+/// * `path` - Path of the log.
+pub fn read_inputs(path: &str) -> Result<Vec<InputRecord>> {
+    parse_inputs(&fs::read_to_string(path)?)
+}
+
+/// Parse a line of an input log. Return the input, None if the line is not
+/// one.
+/// # Arguments
+/// * `line` - The line.
+fn parse_record(line: &str) -> Option<InputRecord> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let field = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+    let flag = |i: usize| match fields.get(i) {
+        Some(&"0") => Some(false),
+        Some(&"1") => Some(true),
+        _ => None,
+    };
+    let input = match (fields.get(1).cloned(), fields.len()) {
+        (Some("key_down"), 3) => Input::KeyDown(field(2)? as u32),
+        (Some("key_up"), 3) => Input::KeyUp(field(2)? as u32),
+        (Some("gamepad_connected"), 3) => Input::GamepadConnected(flag(2)?),
+        (Some("gamepad_button"), 4) => Input::GamepadButton(field(2)? as u32, flag(3)?),
+        (Some("gamepad_axis"), 4) => {
+            Input::GamepadAxis(field(2)? as u32, fields[3].parse::<i16>().ok()?)
+        }
+        (Some("time"), 4) => Input::Time(Duration::new(field(2)?, field(3)? as u32)),
+        _ => return None,
+    };
+    Some(InputRecord {
+        cycle: field(0)?,
+        input: input,
+    })
+}
+
+impl fmt::Display for InputRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ", self.cycle)?;
+        match self.input {
+            Input::KeyDown(sc) => write!(f, "key_down {}", sc),
+            Input::KeyUp(sc) => write!(f, "key_up {}", sc),
+            Input::GamepadConnected(c) => write!(f, "gamepad_connected {}", c as u8),
+            Input::GamepadButton(b, p) => write!(f, "gamepad_button {} {}", b, p as u8),
+            Input::GamepadAxis(a, v) => write!(f, "gamepad_axis {} {}", a, v),
+            Input::Time(t) => write!(f, "time {} {}", t.as_secs(), t.subsec_nanos()),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Real-time clock device.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Tells the guest the time of day and how many clock cycles it ran since
+// the last reset, so programs can measure time and benchmarks can report
+// their own runtime. Both values are wider than a word, so reading the low
+// half latches the whole value and the other half is read from the latch:
+// read `RTC_SECONDS` then `RTC_NANOSECONDS`, and `RTC_CYCLES_LOW` then
+// `RTC_CYCLES_HIGH`. The time of day comes from the host's wall clock, so it
+// is left out of the fingerprint, the cycle counter is not. The clock gets
+// the time through an input log, which can record it and replay it.
+
+use device::{Device, Reset};
+use r2d2::Writer;
+use replay::InputLog;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Base address of the clock's registers.
+pub const RTC_BASE: u32 = 0xffff00a0;
+/// Seconds since the unix epoch. Reading it latches the time.
+pub const RTC_SECONDS: u32 = RTC_BASE;
+/// Nanoseconds into the second latched by reading `RTC_SECONDS`.
+pub const RTC_NANOSECONDS: u32 = RTC_BASE + 4;
+/// Low word of the clock cycles since the last reset. Reading it latches
+/// the count.
+pub const RTC_CYCLES_LOW: u32 = RTC_BASE + 8;
+/// High word of the cycle count latched by reading `RTC_CYCLES_LOW`.
+pub const RTC_CYCLES_HIGH: u32 = RTC_BASE + 12;
+/// Size of the clock's register block.
+const RTC_SIZE: u32 = 16;
+
+/// Real-time clock and cycle counter.
+pub struct Rtc {
+    /// Clock cycles since the last reset.
+    cycles: u64,
+    /// Time latched by the last read of the seconds register.
+    time: Duration,
+    /// Cycle count latched by the last read of the low cycles register.
+    latched_cycles: u64,
+    /// Where the time of day comes from.
+    inputs: InputLog,
+}
+
+impl Rtc {
+    /// Create a clock that has not counted any cycles.
+    pub fn new() -> Self {
+        Self::with_inputs(InputLog::new())
+    }
+
+    /// Create a clock that has not counted any cycles and gets the time of
+    /// day through `inputs`.
+    /// # Arguments
+    /// * `inputs` - Input log of the system.
+    pub fn with_inputs(inputs: InputLog) -> Self {
+        Self {
+            cycles: 0,
+            time: Duration::new(0, 0),
+            latched_cycles: 0,
+            inputs: inputs,
+        }
+    }
+}
+
+impl Device for Rtc {
+    fn name(&self) -> &str {
+        "rtc"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        self.cycles = 0;
+        self.time = Duration::new(0, 0);
+        self.latched_cycles = 0;
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(RTC_BASE..RTC_BASE + RTC_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        match addr & !0x3 {
+            RTC_SECONDS => {
+                self.time = self.inputs.time();
+                self.time.as_secs() as u32
+            }
+            RTC_NANOSECONDS => self.time.subsec_nanos(),
+            RTC_CYCLES_LOW => {
+                self.latched_cycles = self.cycles;
+                self.latched_cycles as u32
+            }
+            RTC_CYCLES_HIGH => (self.latched_cycles >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        w.put_u64(self.cycles);
+        w.put_u64(self.latched_cycles);
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+}
@@ -0,0 +1,376 @@
+// Running a system on a thread of its own.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// A System is not Send, its devices share state with the handles that feed
+// them input, so the thread builds the system itself and keeps it. The
+// front-end talks to it over channels: it sends commands and gets back a
+// status after every frame the system ran or every command that changed
+// it, along with traps, breakpoints and the guest halting as they happen.
+// A paused system waits for commands instead of spinning.
+
+use cpu::{register_from_name, Trap};
+use device::Reset;
+use error::EmulatorError;
+use framebuffer::Framebuffer;
+use front_panel::FrontPanel;
+use instruction::Instruction;
+use replay::Input;
+use state::parse_number;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use system::{RunState, System, FRAME_TIME};
+use util::Result;
+
+use berr;
+
+/// What the front-end asks the system to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunnerCommand {
+    Pause,
+    Resume,
+    TogglePause,
+    /// Run until the next instruction retires, paused or not.
+    Step,
+    /// Pause once the instruction at an address retires.
+    SetBreakpoint(u32),
+    ClearBreakpoint(u32),
+    /// Write a register of the boot core, while the system does not run.
+    SetRegister(u8, u32),
+    /// Write a word of memory, while the system does not run.
+    SetMemory(u32, u32),
+    Reset(Reset),
+    /// A key was pressed, by its scancode.
+    KeyDown(u32),
+    /// A key was released, by its scancode.
+    KeyUp(u32),
+    /// A gamepad was plugged in or out.
+    GamepadConnected(bool),
+    /// A gamepad button was pressed or released.
+    GamepadButton(u32, bool),
+    /// A gamepad axis moved.
+    GamepadAxis(u32, i16),
+    /// Stop the thread.
+    Quit,
+}
+
+/// What the system looks like.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunnerStatus {
+    /// The front panel of the boot core.
+    pub panel: FrontPanel,
+    /// Pixels of the framebuffer, 24 bit RGB, None without a framebuffer.
+    pub pixels: Option<Vec<u8>>,
+}
+
+/// What the system tells the front-end.
+#[derive(Debug)]
+pub enum RunnerEvent {
+    /// The system ran a frame or a command changed it.
+    Status(RunnerStatus),
+    /// An instruction trapped.
+    Trap {
+        /// Address of the instruction.
+        pc: u32,
+        /// The trap.
+        trap: Trap,
+    },
+    /// The system paused at a breakpoint, after the instruction at the
+    /// address retired.
+    Breakpoint(u32),
+    /// The guest halted, with its exit code.
+    Halted(u32),
+    /// Running the system failed, the thread stopped.
+    Error(EmulatorError),
+}
+
+impl RunnerCommand {
+    /// Parse a debugger command, `set reg REG VALUE`, `set mem ADDR VALUE`
+    /// or `asm ADDR INSTRUCTION`, e.g. `set reg r5 0x1234` or `asm 0x100
+    /// add r5, r4, 1`. Registers go by number or name, numbers are decimal
+    /// or hexadecimal with a 0x prefix. Return the command on success and a
+    /// string on error.
+    /// # Arguments
+    /// * `line` - The command.
+    pub fn parse(line: &str) -> Result<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["set", "reg", reg, value] => match register_from_name(reg) {
+                Some(reg) => Ok(RunnerCommand::SetRegister(reg, parse_number(value)?)),
+                None => berr!(Format, format!("There is no register {}", reg)),
+            },
+            ["set", "mem", address, value] => Ok(RunnerCommand::SetMemory(
+                parse_number(address)?,
+                parse_number(value)?,
+            )),
+            ["asm", address, instruction @ ..] if !instruction.is_empty() => {
+                Ok(RunnerCommand::SetMemory(
+                    parse_number(address)?,
+                    Instruction::parse(&instruction.join(" "))?.encode(),
+                ))
+            }
+            _ => berr!(
+                Format,
+                format!(
+                    "Unknown command {:?}, try `set reg REG VALUE`, `set mem ADDR VALUE` or \
+                     `asm ADDR INSTRUCTION`",
+                    line.trim()
+                )
+            ),
+        }
+    }
+}
+
+/// A system running on a thread of its own.
+pub struct Runner {
+    /// Commands to the thread.
+    commands: Sender<RunnerCommand>,
+    /// Events from the thread.
+    events: Receiver<RunnerEvent>,
+    /// The thread, None once joined.
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl Runner {
+    /// Start a thread running a system. The system starts out running.
+    /// # Arguments
+    /// * `framebuffer` - Framebuffer to send the pixels of, if any.
+    /// * `make` - Build the system, on the thread.
+    /// * `finish` - Called with the system once the thread is asked to
+    /// quit, on the thread.
+    pub fn spawn<M, F>(framebuffer: Option<Framebuffer>, make: M, finish: F) -> Self
+    where
+        M: FnOnce() -> Result<System> + Send + 'static,
+        F: FnOnce(&mut System) -> Result<()> + Send + 'static,
+    {
+        let (commands, command_rx) = channel();
+        let (event_tx, events) = channel();
+        let thread = thread::spawn(move || {
+            let run_result = make().and_then(|mut system| {
+                run(&mut system, framebuffer, &command_rx, &event_tx)?;
+                Ok(system)
+            });
+            match run_result {
+                Ok(mut system) => finish(&mut system),
+                Err(e) => {
+                    // The front-end finds out when it polls, if it still
+                    // listens.
+                    let _ = event_tx.send(RunnerEvent::Error(e));
+                    Ok(())
+                }
+            }
+        });
+        Self {
+            commands: commands,
+            events: events,
+            thread: Some(thread),
+        }
+    }
+
+    /// Send a command to the system. Return an error if the thread stopped.
+    /// # Arguments
+    /// * `command` - The command.
+    pub fn send(&self, command: RunnerCommand) -> Result<()> {
+        match self.commands.send(command) {
+            Ok(()) => Ok(()),
+            Err(_) => berr!(Device, "The system's thread stopped"),
+        }
+    }
+
+    /// Get the events the system sent since the last call, oldest first.
+    pub fn poll(&self) -> Vec<RunnerEvent> {
+        self.events.try_iter().collect()
+    }
+
+    /// Wait for the next event the system sends. Return None if none came
+    /// in time or the thread stopped.
+    /// # Arguments
+    /// * `timeout` - Longest to wait.
+    pub fn wait(&self, timeout: Duration) -> Option<RunnerEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Ask the thread to quit and wait for it to finish, if it was not
+    /// joined yet. Return the error finishing the system failed with, if
+    /// any.
+    pub fn join(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => {
+                // The thread may have stopped on its own already.
+                let _ = self.commands.send(RunnerCommand::Quit);
+                match thread.join() {
+                    Ok(result) => result,
+                    Err(_) => berr!(Device, "The system's thread panicked"),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+/// Run a system, handling commands, until asked to quit or the front-end
+/// stops listening.
+/// # Arguments
+/// * `system` - The system.
+/// * `framebuffer` - Framebuffer to send the pixels of, if any.
+/// * `commands` - Commands from the front-end.
+/// * `events` - Events to the front-end.
+fn run(
+    system: &mut System,
+    framebuffer: Option<Framebuffer>,
+    commands: &Receiver<RunnerCommand>,
+    events: &Sender<RunnerEvent>,
+) -> Result<()> {
+    // Instructions that trapped since the last frame.
+    let traps = Rc::new(RefCell::new(Vec::new()));
+    let hook_traps = traps.clone();
+    system.on_retire(move |r| {
+        if let Some(trap) = r.trap {
+            hook_traps.borrow_mut().push((r.pc, trap));
+        }
+    });
+    // Address of the last instruction that retired.
+    let last_pc = Rc::new(RefCell::new(None));
+    let hook_last_pc = last_pc.clone();
+    system.on_retire(move |r| *hook_last_pc.borrow_mut() = Some(r.pc));
+
+    let mut changed = true;
+    let mut halted = false;
+    loop {
+        // A system that does not run waits for a command, at most a frame.
+        let first = if system.run_state() == RunState::Running {
+            match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match commands.recv_timeout(FRAME_TIME) {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        };
+        for command in first.into_iter().chain(commands.try_iter()) {
+            if command == RunnerCommand::Quit {
+                return Ok(());
+            }
+            handle(system, command)?;
+            changed = true;
+        }
+
+        if system.run_state() == RunState::Running {
+            system.run_frame()?;
+            changed = true;
+            if system.is_paused() {
+                let pc = *last_pc.borrow();
+                if let Some(pc) = pc.filter(|pc| system.breakpoints().contains(pc)) {
+                    if events.send(RunnerEvent::Breakpoint(pc)).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        for (pc, trap) in traps.borrow_mut().drain(..) {
+            if events
+                .send(RunnerEvent::Trap { pc: pc, trap: trap })
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+        match system.exit_code() {
+            Some(code) if !halted => {
+                halted = true;
+                if events.send(RunnerEvent::Halted(code)).is_err() {
+                    return Ok(());
+                }
+            }
+            Some(_) => {}
+            None => halted = false,
+        }
+        if changed {
+            changed = false;
+            if events
+                .send(RunnerEvent::Status(status(system, framebuffer)?))
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Carry out a command.
+/// # Arguments
+/// * `system` - The system.
+/// * `command` - The command, anything but `Quit`.
+pub fn handle(system: &mut System, command: RunnerCommand) -> Result<()> {
+    match command {
+        RunnerCommand::Pause => system.pause(),
+        RunnerCommand::Resume => system.resume(),
+        RunnerCommand::TogglePause => system.toggle_pause(),
+        RunnerCommand::Step => system.step()?,
+        RunnerCommand::SetBreakpoint(pc) => {
+            system.set_breakpoint(pc);
+        }
+        RunnerCommand::ClearBreakpoint(pc) => {
+            system.clear_breakpoint(pc);
+        }
+        RunnerCommand::SetRegister(reg, value) => system.set_register(0, reg, value)?,
+        RunnerCommand::SetMemory(address, value) => system.set_memory_word(address, value)?,
+        RunnerCommand::Reset(kind) => system.reset(kind)?,
+        RunnerCommand::KeyDown(scancode) => system.send_input(Input::KeyDown(scancode)),
+        RunnerCommand::KeyUp(scancode) => system.send_input(Input::KeyUp(scancode)),
+        RunnerCommand::GamepadConnected(connected) => {
+            system.send_input(Input::GamepadConnected(connected))
+        }
+        RunnerCommand::GamepadButton(button, pressed) => {
+            system.send_input(Input::GamepadButton(button, pressed))
+        }
+        RunnerCommand::GamepadAxis(axis, value) => {
+            system.send_input(Input::GamepadAxis(axis, value))
+        }
+        RunnerCommand::Quit => {}
+    }
+    Ok(())
+}
+
+/// Get what a system looks like.
+/// # Arguments
+/// * `system` - The system.
+/// * `framebuffer` - Framebuffer to get the pixels of, if any.
+fn status(system: &System, framebuffer: Option<Framebuffer>) -> Result<RunnerStatus> {
+    let pixels = match framebuffer {
+        Some(fb) => {
+            let mut pixels = Vec::new();
+            fb.render(system.memory(), &mut pixels)?;
+            Some(pixels)
+        }
+        None => None,
+    };
+    Ok(RunnerStatus {
+        panel: FrontPanel::from_system(system),
+        pixels: pixels,
+    })
+}
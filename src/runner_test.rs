@@ -0,0 +1,126 @@
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "runner.rs"]
+mod test {
+    use super::super::*;
+    use config::ClockMode;
+    use instruction::*;
+    use runner::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use system::RunState;
+    use util::Result;
+
+    /// Wait up to a few seconds for an event matching `f`, dropping the
+    /// others.
+    fn wait_for<F>(runner: &Runner, f: F) -> RunnerEvent
+    where
+        F: Fn(&RunnerEvent) -> bool,
+    {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            match runner.wait(Duration::from_millis(100)) {
+                Some(RunnerEvent::Error(e)) => panic!("{}", e),
+                Some(event) if f(&event) => return event,
+                _ => {}
+            }
+        }
+        panic!("Timed out waiting for the runner");
+    }
+
+    /// Wait for the next status.
+    fn next_status(runner: &Runner) -> RunnerStatus {
+        match wait_for(runner, |e| match e {
+            RunnerEvent::Status(_) => true,
+            _ => false,
+        }) {
+            RunnerEvent::Status(status) => status,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn runner_stops_at_breakpoints_and_takes_commands() -> Result<()> {
+        let nop = Instruction::Add(ShortInstruction::new(false, 0, 0, ShortSource::Reg(0)));
+        let pending = Arc::new(AtomicU32::new(0));
+        let finish_pending = pending.clone();
+        // The configuration goes to the thread with the closure.
+        let config = Config::new()?;
+        let mut runner = Runner::spawn(
+            None,
+            move || {
+                let mut system = System::new(&config)?;
+                system.set_clock_mode(ClockMode::Unlimited);
+                for i in 0..16 {
+                    system.get_mem_ref().set_word(i * 4, nop.encode())?;
+                }
+                system.set_breakpoint(0x8);
+                Ok(system)
+            },
+            move |system| {
+                finish_pending.store(system.keyboard().pending() as u32, Ordering::SeqCst);
+                Ok(())
+            },
+        );
+        wait_for(&runner, |e| match e {
+            RunnerEvent::Breakpoint(0x8) => true,
+            _ => false,
+        });
+        let status = next_status(&runner);
+        assert_eq!(status.panel.state, RunState::Paused);
+
+        runner.send(RunnerCommand::KeyDown(4))?;
+        runner.send(RunnerCommand::Step)?;
+        let status = next_status(&runner);
+        assert_eq!(status.panel.state, RunState::Paused);
+        assert!(status.panel.cycles > 0);
+
+        runner.join()?;
+        assert_eq!(pending.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn commands_parse() -> Result<()> {
+        assert_eq!(
+            RunnerCommand::parse("set reg r5 0x1234")?,
+            RunnerCommand::SetRegister(5, 0x1234)
+        );
+        assert_eq!(
+            RunnerCommand::parse("  set reg sp 7 ")?,
+            RunnerCommand::SetRegister(14, 7)
+        );
+        assert_eq!(
+            RunnerCommand::parse("set mem 0x100 0xdeadbeef")?,
+            RunnerCommand::SetMemory(0x100, 0xdeadbeef)
+        );
+        assert!(RunnerCommand::parse("set reg r32 1").is_err());
+        assert!(RunnerCommand::parse("set mem 0x100").is_err());
+        assert!(RunnerCommand::parse("set mem 0x100 zz").is_err());
+        assert_eq!(
+            RunnerCommand::parse("asm 0x100 add r5,  r4, #1")?,
+            RunnerCommand::SetMemory(
+                0x100,
+                Instruction::Add(ShortInstruction::new(false, 5, 4, ShortSource::Imm13(1)))
+                    .encode()
+            )
+        );
+        assert!(RunnerCommand::parse("asm 0x100").is_err());
+        assert!(RunnerCommand::parse("poke 1 2").is_err());
+        Ok(())
+    }
+}
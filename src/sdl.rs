@@ -17,27 +17,45 @@
 
 extern crate sdl2;
 
-use config::Config;
+use config::{Config, WindowGeometry};
+use error::EmulatorError;
+use sdl2::controller::GameController;
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, TextureCreator};
+use sdl2::render::{Canvas, Texture, TextureCreator};
 use sdl2::ttf::{Font, Sdl2TtfContext};
 use sdl2::video::{Window, WindowContext};
 use sdl2::EventPump;
+use sdl2::GameControllerSubsystem;
 use sdl2::Sdl;
 use sdl2::VideoSubsystem;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::path::Path;
 use system::System;
 use util::Result;
 
+use berr;
+
 // Struct definitions.
 
+/// Takes the host's game controller input to the guest's gamepad.
+pub trait GamepadSink {
+    /// A controller was plugged in or out.
+    fn set_connected(&self, connected: bool);
+    /// A controller button was pressed or released.
+    fn set_button(&self, button: u32, pressed: bool);
+    /// A controller axis moved.
+    fn set_axis(&self, axis: u32, value: i16);
+}
+
 pub trait Drawable {
     fn draw(&mut self, context: &mut Context) -> Result<()>;
     fn handle_key_down(&mut self, kc: Keycode);
     fn handle_key_up(&mut self, kc: Keycode);
+    fn handle_mouse_down(&mut self, x: i32, y: i32);
     fn get_window_id(&self) -> u32;
 }
 
@@ -48,6 +66,17 @@ pub struct Context {
     pub video_system: VideoSubsystem,
     /// Event queue.
     pub event_pump: EventPump,
+    /// Game controllers plugged into the host.
+    pub controllers: Controllers,
+}
+
+/// The host's game controllers. Their state is merged into the guest's
+/// gamepad.
+pub struct Controllers {
+    /// Game controller context.
+    subsystem: GameControllerSubsystem,
+    /// Controllers opened so far. A controller is closed when dropped.
+    open: Vec<GameController>,
 }
 
 /// SDL context structs.
@@ -58,41 +87,191 @@ pub struct Pane {
     window_id: u32,
     /// Texture creator.
     pub texture_creator: TextureCreator<WindowContext>,
+    /// Textures of the text drawn in the window.
+    text_cache: TextCache,
+}
+
+/// A texture of text and whether it was drawn since the last sweep.
+struct CachedText {
+    texture: Texture,
+    used: bool,
+}
+
+/// Textures of the text drawn in a window, keyed by the text and its
+/// color. Rendering text is most of the time it takes to draw a window, and
+/// most text does not change from one frame to the next. Textures not drawn
+/// during a frame are destroyed when it is presented.
+pub struct TextCache {
+    textures: HashMap<(String, [u8; 4]), CachedText>,
+}
+
+/// Point size of the font the windows draw text in.
+const FONT_SIZE: u16 = 20;
+
+pub fn make_font_context() -> Result<Sdl2TtfContext> {
+    sdl2::ttf::init().map_err(EmulatorError::sdl)
 }
 
-pub fn make_font_context() -> std::result::Result<Sdl2TtfContext, String> {
-    sdl2::ttf::init().map_err(|e| e.to_string())
+/// Load the font the windows draw text in, from the first of the
+/// configuration's font paths that exists. Return the font on success and
+/// an error listing the paths tried if there is none.
+/// # Arguments
+/// * `ttf` - Font context to load the font in.
+/// * `config` - Configuration giving the font paths.
+pub fn load_font<'a>(ttf: &'a Sdl2TtfContext, config: &Config) -> Result<Font<'a, 'static>> {
+    let paths = config.get_font_paths()?;
+    match paths.iter().find(|p| Path::new(p).is_file()) {
+        Some(path) => ttf
+            .load_font(path, FONT_SIZE)
+            .map_err(|e| EmulatorError::Sdl(format!("Could not load font {}: {}", path, e))),
+        None => berr!(
+            Config,
+            format!(
+                "No font found at {}, give one with --font",
+                paths.join(", ")
+            )
+        ),
+    }
 }
 
 // Struct impls.
 
 impl Context {
     pub fn new() -> Result<Self> {
-        let sdl = sdl2::init()?;
-        let event_pump = sdl.event_pump()?;
+        let sdl = sdl2::init().map_err(EmulatorError::sdl)?;
+        let event_pump = sdl.event_pump().map_err(EmulatorError::sdl)?;
 
         Ok(Self {
-            video_system: sdl.video()?,
+            video_system: sdl.video().map_err(EmulatorError::sdl)?,
+            controllers: Controllers {
+                subsystem: sdl.game_controller().map_err(EmulatorError::sdl)?,
+                open: Vec::new(),
+            },
             context: sdl,
             event_pump: event_pump,
         })
     }
 }
 
+impl TextCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self {
+            textures: HashMap::new(),
+        }
+    }
+
+    /// Get the texture of a text, rendering it if it is not cached. Return
+    /// the texture on success and an error if rendering failed.
+    /// # Arguments
+    /// * `text` - The text.
+    /// * `color` - Color of the text.
+    /// * `font` - Font to render the text in.
+    /// * `creator` - Texture creator of the window the text is drawn in.
+    pub fn get(
+        &mut self,
+        text: &str,
+        color: Color,
+        font: &Font,
+        creator: &TextureCreator<WindowContext>,
+    ) -> Result<&Texture> {
+        let key = (text.to_string(), [color.r, color.g, color.b, color.a]);
+        let cached = match self.textures.entry(key) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let surface = font
+                    .render(text)
+                    .blended(color)
+                    .map_err(EmulatorError::sdl)?;
+                e.insert(CachedText {
+                    texture: creator
+                        .create_texture_from_surface(&surface)
+                        .map_err(EmulatorError::sdl)?,
+                    used: false,
+                })
+            }
+        };
+        cached.used = true;
+        Ok(&cached.texture)
+    }
+
+    /// Destroy the textures not drawn since the last sweep.
+    pub fn sweep(&mut self) {
+        let stale: Vec<(String, [u8; 4])> = self
+            .textures
+            .iter()
+            .filter(|(_, cached)| !cached.used)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale.iter() {
+            if let Some(cached) = self.textures.remove(key) {
+                // The window's renderer is still alive, it owns the cache.
+                unsafe { cached.texture.destroy() };
+            }
+        }
+        for cached in self.textures.values_mut() {
+            cached.used = false;
+        }
+    }
+
+    /// Destroy every texture.
+    pub fn clear(&mut self) {
+        for (_, cached) in self.textures.drain() {
+            unsafe { cached.texture.destroy() };
+        }
+    }
+}
+
+impl Controllers {
+    /// Pass a game controller event on to the guest's gamepad. Return true
+    /// if `event` was a game controller event.
+    /// # Arguments
+    /// * `event` - Event to handle.
+    /// * `gamepad` - The guest's gamepad.
+    pub fn handle_event(&mut self, event: &Event, gamepad: &dyn GamepadSink) -> bool {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(controller) => {
+                    self.open.push(controller);
+                    gamepad.set_connected(true);
+                }
+                Err(e) => warn!("Could not open game controller {}: {}", which, e),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.open.retain(|c| c.instance_id() != which);
+                if self.open.is_empty() {
+                    gamepad.set_connected(false);
+                }
+            }
+            Event::ControllerButtonDown { button, .. } => gamepad.set_button(button as u32, true),
+            Event::ControllerButtonUp { button, .. } => gamepad.set_button(button as u32, false),
+            Event::ControllerAxisMotion { axis, value, .. } => gamepad.set_axis(axis as u32, value),
+            _ => return false,
+        }
+        true
+    }
+}
+
 impl Pane {
     /// Create a new SDL window/context. Return context on success and a
     /// string on error.
-    pub fn new(width: u32, height: u32, name: String, context: &mut Context) -> Result<Self> {
-        let window = context
-            .video_system
-            .window(name.as_str(), width, height)
-            .position_centered()
-            .opengl()
-            .build()
-            .map_err(|e| e.to_string())?;
+    /// # Arguments
+    /// * `geometry` - Size and place of the window.
+    /// * `name` - Title of the window.
+    /// * `context` - SDL context to create the window in.
+    pub fn new(geometry: WindowGeometry, name: String, context: &mut Context) -> Result<Self> {
+        let mut builder =
+            context
+                .video_system
+                .window(name.as_str(), geometry.width, geometry.height);
+        match (geometry.x, geometry.y) {
+            (Some(x), Some(y)) => builder.position(x, y),
+            _ => builder.position_centered(),
+        };
+        let window = builder.opengl().build().map_err(EmulatorError::sdl)?;
 
         let id = window.id();
-        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let mut canvas = window.into_canvas().build().map_err(EmulatorError::sdl)?;
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
         canvas.present();
@@ -103,10 +282,46 @@ impl Pane {
             canvas: canvas,
             window_id: id,
             texture_creator: texture_creator,
+            text_cache: TextCache::new(),
         })
     }
 
     pub fn get_id(&self) -> u32 {
         self.window_id
     }
+
+    /// Draw a line of text, stretched over `location`.
+    /// # Arguments
+    /// * `font` - Font to render the text in.
+    /// * `text` - The text.
+    /// * `color` - Color of the text.
+    /// * `location` - Where to draw the text.
+    pub fn draw_text(
+        &mut self,
+        font: &Font,
+        text: &str,
+        color: Color,
+        location: Rect,
+    ) -> Result<()> {
+        let texture = self
+            .text_cache
+            .get(text, color, font, &self.texture_creator)?;
+        self.canvas
+            .copy(texture, None, Some(location))
+            .map_err(EmulatorError::sdl)
+    }
+
+    /// Show what was drawn since the last frame, forgetting the text that
+    /// was not drawn again.
+    pub fn present(&mut self) {
+        self.text_cache.sweep();
+        self.canvas.present();
+    }
+}
+
+impl Drop for Pane {
+    fn drop(&mut self) {
+        // Textures must go before the renderer.
+        self.text_cache.clear();
+    }
 }
@@ -16,6 +16,16 @@
 // Public structs.
 use std::fmt;
 
+/// Get how far (in bits) `size` bytes of data at byte `bar` of a word are
+/// from the low end of the word. Memory is big endian, so byte 0 is the high
+/// byte.
+/// # Arguments
+/// * `bar` - Byte address of the data within the word (BAR).
+/// * `size` - Width of the data in bytes.
+pub fn lane_shift(bar: u8, size: u32) -> u32 {
+    (4 - size - bar as u32) * 8
+}
+
 /// Representation of the Shifter for RISCII. Implements left and right shifting.
 #[derive(Clone, Copy)]
 pub struct Shifter {
@@ -47,6 +57,36 @@ impl Shifter {
     pub fn shift_right(&self) -> u32 {
         self.src >> (self.s_ham as u32)
     }
+
+    /// Align the word read from memory in `src` for a load: shift the data
+    /// down to the low end of the word and zero or sign extend it.
+    /// # Arguments
+    /// * `bar` - Byte address of the data within the word (BAR).
+    /// * `size` - Width of the load in bytes.
+    /// * `signed` - True if the data is sign extended.
+    pub fn align_load(&mut self, bar: u8, size: u32, signed: bool) -> u32 {
+        self.s_ham = lane_shift(bar, size) as u8;
+        let value = self.shift_right();
+        match (size, signed) {
+            (4, _) => value,
+            (2, true) => value as u16 as i16 as i32 as u32,
+            (2, false) => value & 0xffff,
+            (_, true) => value as u8 as i8 as i32 as u32,
+            (_, false) => value & 0xff,
+        }
+    }
+
+    /// Replicate the data of a store in `src` on every byte lane it may be
+    /// written from, so memory finds it whatever its address within the word.
+    /// # Arguments
+    /// * `size` - Width of the store in bytes.
+    pub fn replicate_store(&self, size: u32) -> u32 {
+        match size {
+            4 => self.src,
+            2 => (self.src & 0xffff) * 0x10001,
+            _ => (self.src & 0xff) * 0x1010101,
+        }
+    }
 }
 
 impl fmt::Display for Shifter {
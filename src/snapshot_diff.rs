@@ -0,0 +1,223 @@
+// What changed between two snapshots.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Compares two systems, usually restored from snapshots taken at two points
+// of a run, so `riscii snapshot diff` can tell what the program changed in
+// between. Every register of every core is compared, globals once and each
+// window's locals and ins by window (a window's outs are the ins of the
+// window after it), along with the PC and PSW. Memory is compared byte by
+// byte and the bytes that differ are coalesced into ranges.
+
+use cpu::{register_name, NUM_GLOBALS, NUM_SHARED_NEXT, WINDOW_SIZE};
+use std::fmt;
+use system::System;
+
+/// Most bytes of a memory range listed, the rest are elided.
+const MAX_LISTED_BYTES: usize = 16;
+
+/// A register that differs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterChange {
+    /// Number of the core.
+    pub core: usize,
+    /// Name of the register, with its window if it is windowed, e.g. `pc`,
+    /// `r3` or `w2 r16`.
+    pub name: String,
+    /// Value in the first system.
+    pub before: u32,
+    /// Value in the second system.
+    pub after: u32,
+}
+
+/// A range of memory that differs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryChange {
+    /// Address of the first byte.
+    pub start: u32,
+    /// The bytes in the first system.
+    pub before: Vec<u8>,
+    /// The bytes in the second system.
+    pub after: Vec<u8>,
+}
+
+/// Everything that differs between two systems.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    /// Clock cycle of each system.
+    pub cycles: (u64, u64),
+    /// Registers that differ, by core.
+    pub registers: Vec<RegisterChange>,
+    /// Ranges of memory that differ, lowest first.
+    pub memory: Vec<MemoryChange>,
+    /// Memory size of each system, if they differ. Only the memory both
+    /// have is compared.
+    pub memory_sizes: Option<(u32, u32)>,
+    /// Number of cores of each system, if they differ. Only the cores both
+    /// have are compared.
+    pub core_counts: Option<(usize, usize)>,
+}
+
+impl SnapshotDiff {
+    /// Compare two systems.
+    /// # Arguments
+    /// * `a` - The first system.
+    /// * `b` - The second system.
+    pub fn new(a: &System, b: &System) -> Self {
+        let mut registers = Vec::new();
+        for (id, (ca, cb)) in a.cores().iter().zip(b.cores().iter()).enumerate() {
+            let (da, db) = (ca.data_path(), cb.data_path());
+            let mut change = |name: String, before: u32, after: u32| {
+                if before != after {
+                    registers.push(RegisterChange {
+                        core: id,
+                        name: name,
+                        before: before,
+                        after: after,
+                    });
+                }
+            };
+            change("pc".to_string(), da.get_pc(), db.get_pc());
+            change(
+                "psw".to_string(),
+                da.get_psw().get() as u32,
+                db.get_psw().get() as u32,
+            );
+            let (ra, rb) = (da.register_file(), db.register_file());
+            for reg in 1..NUM_GLOBALS as u8 {
+                change(register_name(reg), ra.read(reg, 0), rb.read(reg, 0));
+            }
+            for window in 0..ra.windows().min(rb.windows()) {
+                for reg in (NUM_GLOBALS + NUM_SHARED_NEXT) as u8..(NUM_GLOBALS + WINDOW_SIZE) as u8
+                {
+                    change(
+                        format!("w{} {}", window, register_name(reg)),
+                        ra.read(reg, window),
+                        rb.read(reg, window),
+                    );
+                }
+            }
+        }
+
+        let (ma, mb) = (a.memory(), b.memory());
+        let size = ma.size().min(mb.size());
+        // Both sizes are checked, so the reads cannot fail.
+        let bytes_a = ma.get_bytes(0, size).unwrap_or(&[]);
+        let bytes_b = mb.get_bytes(0, size).unwrap_or(&[]);
+        let mut memory: Vec<MemoryChange> = Vec::new();
+        for (i, (x, y)) in bytes_a.iter().zip(bytes_b.iter()).enumerate() {
+            if x == y {
+                continue;
+            }
+            match memory.last_mut() {
+                Some(m) if m.start as usize + m.before.len() == i => {
+                    m.before.push(*x);
+                    m.after.push(*y);
+                }
+                _ => memory.push(MemoryChange {
+                    start: i as u32,
+                    before: vec![*x],
+                    after: vec![*y],
+                }),
+            }
+        }
+
+        Self {
+            cycles: (a.clock().count(), b.clock().count()),
+            registers: registers,
+            memory: memory,
+            memory_sizes: if ma.size() == mb.size() {
+                None
+            } else {
+                Some((ma.size(), mb.size()))
+            },
+            core_counts: if a.cores().len() == b.cores().len() {
+                None
+            } else {
+                Some((a.cores().len(), b.cores().len()))
+            },
+        }
+    }
+
+    /// Return true if nothing but the clock differs.
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty()
+            && self.memory.is_empty()
+            && self.memory_sizes.is_none()
+            && self.core_counts.is_none()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Clock cycle {} -> {}.", self.cycles.0, self.cycles.1)?;
+        if let Some((a, b)) = self.core_counts {
+            writeln!(
+                f,
+                "Cores: {} -> {}, comparing the first {}.",
+                a,
+                b,
+                a.min(b)
+            )?;
+        }
+        if let Some((a, b)) = self.memory_sizes {
+            writeln!(
+                f,
+                "Memory size: 0x{:x} -> 0x{:x}, comparing the first 0x{:x} bytes.",
+                a,
+                b,
+                a.min(b)
+            )?;
+        }
+        if self.is_empty() {
+            return writeln!(f, "No differences.");
+        }
+        for r in self.registers.iter() {
+            writeln!(
+                f,
+                "core {} {}: {:08x} -> {:08x}",
+                r.core, r.name, r.before, r.after
+            )?;
+        }
+        for m in self.memory.iter() {
+            writeln!(
+                f,
+                "memory {:08x}..{:08x} ({} bytes): {} -> {}",
+                m.start,
+                m.start as u64 + m.before.len() as u64,
+                m.before.len(),
+                hex_bytes(&m.before),
+                hex_bytes(&m.after)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Private functions.
+
+/// Write bytes in hex, the first `MAX_LISTED_BYTES` of them.
+/// # Arguments
+/// * `bytes` - The bytes.
+fn hex_bytes(bytes: &[u8]) -> String {
+    let mut result: Vec<String> = bytes
+        .iter()
+        .take(MAX_LISTED_BYTES)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if bytes.len() > MAX_LISTED_BYTES {
+        result.push("...".to_string());
+    }
+    result.join(" ")
+}
@@ -0,0 +1,374 @@
+// Human readable dumps of the emulator state.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Unlike r2d2 snapshots, which can be restored, a state dump is meant to be
+// read and diffed. It has the clock, the PSW's fields, the registers of the
+// current window grouped the way the window is laid out, the pipeline latches
+// and any memory ranges asked for. Numbers that are addresses or register
+// contents are written as fixed width hex strings so dumps line up.
+
+use clock::Phase;
+use config::StateFormat;
+use cpu::register_name;
+use data_path_view::DataPathView;
+use std::fmt::Write;
+use system::System;
+use util::Result;
+
+use berr;
+
+/// Range of memory to include in a dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRange {
+    /// Address of the first word.
+    pub start: u32,
+    /// Length in bytes.
+    pub len: u32,
+}
+
+/// Value in a dump. Tables keep their keys in the order they were added.
+pub enum Value {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+    List(Vec<Value>),
+    Table(Vec<(String, Value)>),
+}
+
+impl MemoryRange {
+    /// Parse a range written as `start:len`, both decimal or hexadecimal
+    /// with a 0x prefix. Return the range on success and a string on error.
+    /// # Arguments
+    /// * `s` - Text to parse.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (start, len) = match s.find(':') {
+            Some(i) => (parse_number(&s[..i])?, parse_number(&s[i + 1..])?),
+            None => return berr!(Format, format!("Invalid memory range {}, expected start:len", s)),
+        };
+        if start % 4 != 0 || len % 4 != 0 {
+            return berr!(Format, format!(
+                "Invalid memory range {}, the start and length must be multiples of 4",
+                s
+            ));
+        }
+        Ok(Self {
+            start: start,
+            len: len,
+        })
+    }
+
+    /// Parse a comma separated list of ranges. Return the ranges on success
+    /// and a string on error.
+    /// # Arguments
+    /// * `s` - Text to parse.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>> {
+        s.split(',')
+            .filter(|r| !r.trim().is_empty())
+            .map(|r| Self::parse(r.trim()))
+            .collect()
+    }
+}
+
+// Public functions.
+
+/// Dump the state of a system. Return the dump on success and a string if a
+/// memory range is out of bounds.
+/// # Arguments
+/// * `system` - System to dump.
+/// * `ranges` - Memory to include.
+/// * `format` - Format to write the dump in.
+pub fn export_state(
+    system: &System,
+    ranges: &[MemoryRange],
+    format: StateFormat,
+) -> Result<String> {
+    let dp = system.data_path();
+    let psw = dp.psw();
+    let cwp = psw.get_cwp();
+    let view = DataPathView::new(dp);
+
+    let cpu = vec![
+        entry("engine", Value::Str(system.engine().to_string())),
+        entry("cycle", Value::Int(system.clock().count())),
+        entry(
+            "phase",
+            Value::Str(
+                match system.phase() {
+                    Phase::One => "1",
+                    Phase::Two => "2",
+                    Phase::Three => "3",
+                    Phase::Four => "4",
+                    Phase::Interrupt => "interrupt",
+                }
+                .to_string(),
+            ),
+        ),
+        entry("nxtpc", hex(view.nxtpc)),
+        entry("pc", hex(view.pc)),
+        entry("lstpc", hex(view.lstpc)),
+    ];
+    let psw_fields = vec![
+        entry("value", Value::Str(format!("0x{:04x}", psw.get()))),
+        entry("cwp", Value::Int(cwp as u64)),
+        entry("swp", Value::Int(psw.get_swp() as u64)),
+        entry("interrupts", Value::Bool(psw.get_interrupt_enabled())),
+        entry("system", Value::Bool(psw.get_system_mode())),
+        entry(
+            "previous_system",
+            Value::Bool(psw.get_previous_system_mode()),
+        ),
+        entry("z", Value::Bool(psw.get_cc_zero())),
+        entry("n", Value::Bool(psw.get_cc_neg())),
+        entry("v", Value::Bool(psw.get_cc_overflow())),
+        entry("c", Value::Bool(psw.get_cc_carry())),
+    ];
+    // Parts of the window, as the register file lays them out.
+    let registers = [
+        ("globals", 0..10),
+        ("outs", 10..16),
+        ("locals", 16..26),
+        ("ins", 26..32),
+    ]
+    .iter()
+    .map(|(name, regs)| {
+        entry(
+            name,
+            Value::Table(
+                regs.clone()
+                    .map(|r| (register_name(r), hex(view.regs[r as usize])))
+                    .collect(),
+            ),
+        )
+    })
+    .collect();
+    let latches = vec![
+        entry("dst", hex(view.dst)),
+        entry("src", hex(view.src)),
+        entry("imm", hex(view.imm)),
+        entry("bar", Value::Int(view.bar as u64)),
+        entry("op", Value::Str(format!("0x{:02x}", view.op))),
+        entry("rd", Value::Int(view.rd as u64)),
+        entry("rs1", Value::Int(view.rs1 as u64)),
+        entry("rs2", Value::Int(view.rs2 as u64)),
+        entry("s_ham", Value::Int(view.s_ham as u64)),
+        entry("s_dec", Value::Int(view.s_dec as u64)),
+    ];
+    let mut memory = Vec::new();
+    let endian = system.memory().endian();
+    for range in ranges.iter() {
+        let bytes = system.memory().get_bytes(range.start, range.len)?;
+        memory.push(Value::Table(vec![
+            entry("start", hex(range.start)),
+            entry(
+                "words",
+                Value::List(
+                    bytes
+                        .chunks(4)
+                        .map(|w| hex(endian.word([w[0], w[1], w[2], w[3]])))
+                        .collect(),
+                ),
+            ),
+        ]));
+    }
+
+    let mut root = vec![
+        entry("cpu", Value::Table(cpu)),
+        entry("psw", Value::Table(psw_fields)),
+        entry("registers", Value::Table(registers)),
+        entry("latches", Value::Table(latches)),
+    ];
+    if !memory.is_empty() {
+        root.push(entry("memory", Value::List(memory)));
+    }
+
+    let mut out = String::new();
+    match format {
+        StateFormat::Toml => write_toml_table(&mut out, "", &root)?,
+        StateFormat::Json => {
+            write_json(&mut out, &Value::Table(root), 0)?;
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+/// Write a value as indented JSON. Return the JSON on success and a string
+/// if a table is nested in a list of plain values.
+/// # Arguments
+/// * `value` - Value to write.
+pub fn to_json(value: &Value) -> Result<String> {
+    let mut out = String::new();
+    write_json(&mut out, value, 0)?;
+    out.push('\n');
+    Ok(out)
+}
+
+/// Parse a decimal number, or a hexadecimal one with a 0x prefix. Return
+/// the number on success and a string on error.
+/// # Arguments
+/// * `s` - Text to parse.
+pub fn parse_number(s: &str) -> Result<u32> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    match parsed {
+        Ok(v) => Ok(v),
+        Err(e) => berr!(Format, format!("Invalid number {}: {}", s, e)),
+    }
+}
+
+// Private functions.
+
+fn entry(key: &str, value: Value) -> (String, Value) {
+    (key.to_string(), value)
+}
+
+fn hex(v: u32) -> Value {
+    Value::Str(format!("0x{:08x}", v))
+}
+
+/// Write a string with quotes and escapes, valid in both TOML and JSON.
+fn write_str(out: &mut String, s: &str) -> Result<()> {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+/// Write a value that fits on one line.
+fn write_inline(out: &mut String, value: &Value) -> Result<()> {
+    match value {
+        Value::Str(s) => write_str(out, s)?,
+        Value::Int(i) => write!(out, "{}", i)?,
+        Value::Bool(b) => write!(out, "{}", b)?,
+        Value::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_inline(out, item)?;
+            }
+            out.push(']');
+        }
+        Value::Table(_) => return berr!(Format, "Tables can not be written inline"),
+    }
+    Ok(())
+}
+
+/// True if `value` is a non-empty list of tables, written as a TOML array of
+/// tables.
+fn is_table_list(value: &Value) -> bool {
+    match value {
+        Value::List(items) => {
+            items.iter().all(|i| match i {
+                Value::Table(_) => true,
+                _ => false,
+            }) && !items.is_empty()
+        }
+        _ => false,
+    }
+}
+
+/// Write the entries of a TOML table: first its plain values, then its
+/// tables and arrays of tables.
+/// # Arguments
+/// * `out` - String to write to.
+/// * `path` - Dotted name of the table, empty for the root.
+/// * `entries` - Entries of the table.
+fn write_toml_table(out: &mut String, path: &str, entries: &[(String, Value)]) -> Result<()> {
+    for (key, value) in entries.iter() {
+        match value {
+            Value::Table(_) => {}
+            v if is_table_list(v) => {}
+            v => {
+                write!(out, "{} = ", key)?;
+                write_inline(out, v)?;
+                out.push('\n');
+            }
+        }
+    }
+    for (key, value) in entries.iter() {
+        let name = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match value {
+            Value::Table(table) => {
+                // Tables holding only tables need no header of their own.
+                if table.iter().any(|(_, v)| match v {
+                    Value::Table(_) => false,
+                    v => !is_table_list(v),
+                }) {
+                    write!(out, "\n[{}]\n", name)?;
+                }
+                write_toml_table(out, &name, table)?;
+            }
+            Value::List(items) if is_table_list(value) => {
+                for item in items.iter() {
+                    if let Value::Table(table) = item {
+                        write!(out, "\n[[{}]]\n", name)?;
+                        write_toml_table(out, &name, table)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Write a value as indented JSON.
+/// # Arguments
+/// * `out` - String to write to.
+/// * `value` - Value to write.
+/// * `depth` - Nesting depth of the value.
+fn write_json(out: &mut String, value: &Value, depth: usize) -> Result<()> {
+    let indent = "  ".repeat(depth + 1);
+    match value {
+        Value::Table(entries) => {
+            out.push_str("{\n");
+            for (i, (key, value)) in entries.iter().enumerate() {
+                out.push_str(&indent);
+                write_str(out, key)?;
+                out.push_str(": ");
+                write_json(out, value, depth + 1)?;
+                out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+            }
+            write!(out, "{}}}", "  ".repeat(depth))?;
+        }
+        Value::List(items) if is_table_list(value) => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&indent);
+                write_json(out, item, depth + 1)?;
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            write!(out, "{}]", "  ".repeat(depth))?;
+        }
+        v => write_inline(out, v)?,
+    }
+    Ok(())
+}
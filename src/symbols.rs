@@ -0,0 +1,195 @@
+// Symbol tables of guest programs.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Names the guest program gives its addresses, so the disassembler, the
+// debug window and conditions can show `main+0x14` instead of raw hex. The
+// assembler's labels are a symbol table, other programs bring a symbol file.
+// Symbol files have one symbol per line, a hex address and a name, e.g.
+//   00000100 main
+// A type letter between the two is skipped, so the output of `nm` can be
+// used as is. `#` starts a comment.
+
+use berr;
+use error::EmulatorError;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use util::Result;
+
+/// Farthest (in bytes) an address may be past a symbol to be shown relative
+/// to it.
+pub const MAX_SYMBOL_OFFSET: u32 = 0x10000;
+
+/// A guest program's symbols.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    /// Address of every symbol.
+    addresses: HashMap<String, u32>,
+    /// Name of the symbol at every address, the first one given if there
+    /// are several.
+    names: BTreeMap<u32, String>,
+}
+
+impl Symbols {
+    /// Create an empty symbol table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a symbol file. Return the symbols on success and a string
+    /// naming the offending line on error.
+    /// # Arguments
+    /// * `text` - Contents of the symbol file.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut symbols = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(c) => &line[..c],
+                None => line,
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (address, name) = match fields.len() {
+                0 => continue,
+                2 => (fields[0], fields[1]),
+                3 => (fields[0], fields[2]),
+                _ => return berr!(Format, format!("Line {}: expected an address and a name", i + 1)),
+            };
+            let address = address.trim_start_matches("0x");
+            match u32::from_str_radix(address, 16) {
+                Ok(a) => symbols.insert(name, a),
+                Err(_) => return berr!(Format, format!("Line {}: invalid address {}", i + 1, address)),
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// Read a symbol file. Return the symbols on success and a string on
+    /// error.
+    /// # Arguments
+    /// * `path` - Path of the symbol file.
+    pub fn read(path: &String) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(e) => return Err(EmulatorError::io(format!("Could not read symbols {}", path), e)),
+        };
+        match Self::parse(&text) {
+            Ok(s) => Ok(s),
+            Err(e) => berr!(Format, format!("{}: {}", path, e)),
+        }
+    }
+
+    /// Write the symbols to a file in the format `read` reads. Return void on
+    /// success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the symbol file.
+    pub fn write(&self, path: &String) -> Result<()> {
+        if let Err(e) = fs::write(path, self.to_string()) {
+            return Err(EmulatorError::io(format!("Could not write symbols {}", path), e));
+        }
+        Ok(())
+    }
+
+    /// Add a symbol, replacing any symbol of the same name.
+    /// # Arguments
+    /// * `name` - Name of the symbol.
+    /// * `address` - Address it names.
+    pub fn insert(&mut self, name: &str, address: u32) {
+        if let Some(old) = self.addresses.insert(name.to_string(), address) {
+            if self.names.get(&old).map_or(false, |n| n == name) {
+                self.names.remove(&old);
+                // Another symbol at the old address takes its place.
+                if let Some((other, _)) = self.addresses.iter().find(|(_, a)| **a == old) {
+                    self.names.insert(old, other.clone());
+                }
+            }
+        }
+        self.names
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    /// Add every symbol of another table.
+    /// # Arguments
+    /// * `other` - Table to add.
+    pub fn extend(&mut self, other: &Symbols) {
+        for (address, name) in other.names.iter() {
+            self.insert(name, *address);
+        }
+        for (name, address) in other.addresses.iter() {
+            self.insert(name, *address);
+        }
+    }
+
+    /// Get the number of symbols.
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Get the address of a symbol, None if there is no such symbol.
+    /// # Arguments
+    /// * `name` - Name of the symbol.
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.addresses.get(name).cloned()
+    }
+
+    /// Get the name of the symbol exactly at an address, None if there is
+    /// none.
+    /// # Arguments
+    /// * `address` - The address.
+    pub fn at(&self, address: u32) -> Option<&str> {
+        self.names.get(&address).map(|n| n.as_str())
+    }
+
+    /// Get the closest symbol at or before an address and the address's
+    /// offset from it. Return None if there is no symbol at most
+    /// `MAX_SYMBOL_OFFSET` bytes before it.
+    /// # Arguments
+    /// * `address` - The address.
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        match self.names.range(..=address).next_back() {
+            Some((start, name)) if address - start <= MAX_SYMBOL_OFFSET => {
+                Some((name.as_str(), address - start))
+            }
+            _ => None,
+        }
+    }
+
+    /// Describe an address relative to the closest symbol, e.g. `main` or
+    /// `main+0x14`. Return None if no symbol is close enough.
+    /// # Arguments
+    /// * `address` - The address.
+    pub fn describe(&self, address: u32) -> Option<String> {
+        self.lookup(address).map(|(name, offset)| match offset {
+            0 => name.to_string(),
+            _ => format!("{}+0x{:x}", name, offset),
+        })
+    }
+}
+
+impl fmt::Display for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut symbols: Vec<(&u32, &String)> =
+            self.addresses.iter().map(|(n, a)| (a, n)).collect();
+        symbols.sort();
+        for (address, name) in symbols {
+            writeln!(f, "{:08x} {}", address, name)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+// Test code for symbol tables.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "symbols.rs"]
+mod test {
+    use super::super::*;
+    use symbols::*;
+    use util::Result;
+
+    #[test]
+    fn symbol_files_parse() -> Result<()> {
+        let symbols = Symbols::parse(
+            "# From the assembler.
+             00000100 main
+             0x00000200 T helper  # nm style
+             ",
+        )?;
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.address("main"), Some(0x100));
+        assert_eq!(symbols.address("helper"), Some(0x200));
+        assert_eq!(symbols.address("Main"), None);
+        assert!(Symbols::parse("100").is_err());
+        assert!(Symbols::parse("xyz main").is_err());
+
+        let written = symbols.to_string();
+        assert_eq!(written, "00000100 main\n00000200 helper\n");
+        assert_eq!(Symbols::parse(&written)?, symbols);
+        Ok(())
+    }
+
+    #[test]
+    fn addresses_are_described_by_the_closest_symbol() {
+        let mut symbols = Symbols::new();
+        symbols.insert("main", 0x100);
+        symbols.insert("loop", 0x120);
+        assert_eq!(symbols.describe(0x100), Some("main".to_string()));
+        assert_eq!(symbols.describe(0x114), Some("main+0x14".to_string()));
+        assert_eq!(symbols.describe(0x124), Some("loop+0x4".to_string()));
+        assert_eq!(symbols.describe(0xfc), None);
+        assert_eq!(symbols.describe(0x120 + MAX_SYMBOL_OFFSET + 1), None);
+        assert_eq!(symbols.at(0x120), Some("loop"));
+        assert_eq!(symbols.at(0x124), None);
+    }
+
+    #[test]
+    fn moving_a_symbol_keeps_others_at_its_address() {
+        let mut symbols = Symbols::new();
+        symbols.insert("start", 0x100);
+        symbols.insert("_start", 0x100);
+        assert_eq!(symbols.at(0x100), Some("start"));
+        symbols.insert("start", 0x200);
+        assert_eq!(symbols.at(0x100), Some("_start"));
+        assert_eq!(symbols.at(0x200), Some("start"));
+
+        let mut more = Symbols::new();
+        more.insert("data", 0x300);
+        symbols.extend(&more);
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.address("data"), Some(0x300));
+    }
+}
@@ -14,145 +14,1453 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use alignment::AlignmentStats;
+use anomaly::AnomalyDetector;
+use block::BlockDevice;
 use clock::{Clock, Phase};
-use config::Config;
-use cpu::OutputPins;
-use data_path::{Control, DataPath};
-use instruction::{noop, InstructionCycle};
+use commit::RetiredInstruction;
+use config::{ClockMode, Config, Engine, IsaExtension, PipelineDebug, PowerOnState, Syntax};
+use console::Console;
+use coprocessor::{Coprocessor, Coprocessors};
+use cpu::{Trap, TrapVectors, MAX_REG_WINDOWS, MIN_REG_WINDOWS, SIZEOF_INSTRUCTION};
+use data_path::DataPath;
+use decode_cache::DecodeCacheStats;
+use device::{Device, Reset};
+use doorbell::{Doorbell, DoorbellLines};
+use engine::{new_cpu, Cpu};
+use expr::Expr;
+use extension::ExtensionIsa;
+#[cfg(feature = "fpu")]
+use fpu::Fpu;
+use gamepad::{Gamepad, GamepadInput};
+use hazard::HazardDetector;
+use host::{Host, HostControl};
+use keyboard::{Keyboard, KeyboardInput};
+use loader::Image;
+use log::Level;
 use memory::Memory;
-use util::Result;
+use muldiv::MulDiv;
+use pipeline_debug::PipelineLog;
+use pipeline_history::PipelineHistory;
+use profiler::Profiler;
+use r2d2::{Reader, Snapshot, SnapshotKind, SnapshotSession, SnapshotTrigger, Writer};
+use replay::{Input, InputLog};
+use rtc::Rtc;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use symbols::Symbols;
+use timer::Timer;
+use trace::Tracer;
+use usage::{format_bytes, HostUsage, MEGABYTE};
+use util::{Result, Xorshift};
 
-pub struct System {
+use berr;
+use core_log;
+
+/// Callback run every time an instruction retires.
+pub type RetireHook = Box<dyn FnMut(&RetiredInstruction)>;
+
+/// Longest a single step may take (in clock cycles) before giving up on an
+/// instruction retiring.
+const MAX_STEP_CYCLES: u32 = 8;
+/// How long a frame of the windows runs the system for, unless the clock
+/// mode sets a number of instructions.
+pub const FRAME_TIME: Duration = Duration::from_micros(16_667);
+/// Most cores a system may have, one per doorbell bit.
+pub const MAX_CORES: u32 = 32;
+/// Exit code of a headless run stopped by a limit, the one `timeout` uses.
+pub const LIMIT_EXIT_CODE: i32 = 124;
+/// Clock cycles between looks at the time of a headless run with a timeout.
+const TIMEOUT_CHECK_CYCLES: u64 = 4096;
+
+/// Whether a system runs. A running system can be paused and resumed, a
+/// system whose guest halted stays halted until it is reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    /// The clock runs.
+    Running,
+    /// The clock is stopped, by the user or an anomaly.
+    Paused,
+    /// The guest wrote the host's exit register.
+    Halted,
+}
+
+/// A limit that stopped a headless run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunLimit {
+    /// The clock reached this many cycles.
+    Cycles(u64),
+    /// The run took longer than this.
+    Time(Duration),
+}
+
+/// A CPU core: a data path and the engine running instructions on it.
+pub struct Core {
     /// RISCII data path.
     data_path: DataPath,
+    /// Engine running instructions on the data path.
+    cpu: Box<dyn Cpu>,
+    /// Trap raised between instructions, by a coprocessor or an interrupt,
+    /// waiting for the core to reach a point it can be taken at.
+    pending_trap: Option<Trap>,
+}
+
+// The cores share memory and the clock, and run every clock phase in turn,
+// lowest numbered first. Core 0 is the boot core: it is the one stepping,
+// halting and the single core accessors follow, the one the coprocessors
+// are attached to and the one device interrupts go to. The other cores only
+// get interrupted by the doorbell.
+pub struct System {
+    /// The cores, the boot core first.
+    cores: Vec<Core>,
     /// Memory state.
     mem: Memory,
     /// External, four phase clock.
     clock: Clock,
-    /// Next micro operation to perform for the currently executing instruction.
-    cycle_ops: InstructionCycle,
+    /// Which engine the cores run.
+    engine: Engine,
+    /// Entries of each core's decode cache on the fast engine, 0 for none.
+    decode_cache: u32,
     /// Current CPU non-overlapping clock phase.
     phase: Phase,
-    // TODO move below to an MMU emulator.
-    /// CPU's output pins, input pins for memory.
-    pins_out: OutputPins,
-    /// True if the pipeline is currently suspended as a result of a memory operation.
-    pipeline_suspended: bool,
-    /// True if the system's emulation is paused, false if not.
-    is_paused: bool,
+    /// Running or paused, the system is halted regardless once the guest
+    /// halts.
+    state: RunState,
+    /// Program images loaded into memory, loaded again on reset.
+    images: Vec<Image>,
+    /// Address the cores start at instead of the reset vector, if any.
+    entry: Option<u32>,
+    /// Trap vector table every core has after a reset.
+    trap_vectors: TrapVectors,
+    /// What the registers and latches of every core hold at power on and
+    /// after a cold reset.
+    poweron_state: PowerOnState,
+    /// Seed of the random power on state.
+    poweron_seed: u32,
+    /// Addresses of the instructions that pause the system once they
+    /// retire on the boot core, while it runs frame by frame.
+    breakpoints: BTreeSet<u32>,
+    /// Observers of retired instructions.
+    retire_hooks: Vec<RetireHook>,
+    /// Profiler, None if profiling is off.
+    profiler: Option<Profiler>,
+    /// Load and store alignment statistics, None if not tracked.
+    alignment_stats: Option<AlignmentStats>,
+    /// Anomaly detector, None if anomalies are not looked for.
+    anomalies: Option<AnomalyDetector>,
+    /// Pipeline hazard detector, None if hazards are not looked for.
+    hazards: Option<HazardDetector>,
+    /// Execution trace, None if tracing is off.
+    tracer: Option<Tracer>,
+    /// Log of the pipeline's inner workings, None if it is off.
+    pipeline_log: Option<PipelineLog>,
+    /// Recent occupancy of every core's stages, None if not recorded.
+    pipeline_history: Option<Vec<PipelineHistory>>,
+    /// Syntax the trace and pipeline log disassemble instructions in.
+    syntax: Syntax,
+    /// Symbols of the guest program.
+    symbols: Symbols,
+    /// Most host memory (in bytes) the system may use, None for no limit.
+    host_mem_limit: Option<u64>,
+    /// True if the system could not get under its host memory limit and
+    /// said so.
+    over_limit: bool,
+    /// Captures snapshots when a condition becomes true, None if unset.
+    snapshot_trigger: Option<SnapshotTrigger>,
+    /// Snapshots taken during this session.
+    snapshots: SnapshotSession,
+    /// Coprocessors running alongside the CPU.
+    coprocessors: Coprocessors,
+    /// True if the coprocessors hold the CPU for the current clock cycle.
+    stalled: bool,
+    /// Handle feeding host key events to the keyboard.
+    keyboard: KeyboardInput,
+    /// Handle feeding the host's gamepad state to the gamepad.
+    gamepad: GamepadInput,
+    /// Handle telling the host services how the emulator runs.
+    host: HostControl,
+    /// Handle telling which cores the doorbell interrupts.
+    doorbell: DoorbellLines,
+    /// Records or replays the inputs from outside the guest.
+    inputs: InputLog,
+    /// Inputs the host sent that take effect when the next clock cycle
+    /// starts, oldest first.
+    host_inputs: Vec<Input>,
+    /// Clock cycle a headless run stops at, None for no limit.
+    max_cycles: Option<u64>,
+    /// Longest a headless run may take, None for no limit.
+    timeout: Option<Duration>,
+    /// The limit that stopped the last headless run, if one did.
+    limit_hit: Option<RunLimit>,
+}
+
+impl Core {
+    /// Create a core in its reset state.
+    /// # Arguments
+    /// * `id` - Number of the core.
+    /// * `engine` - Engine to run instructions with.
+    /// * `windows` - Number of register windows.
+    /// * `vectors` - Trap vector table.
+    /// * `decode_cache` - Entries of the decode cache, 0 for none.
+    fn new(id: u32, engine: Engine, windows: u8, vectors: TrapVectors, decode_cache: u32) -> Self {
+        let mut dp = DataPath::new();
+        dp.set_cpu_id(id);
+        dp.set_windows(windows);
+        dp.set_trap_vectors(vectors);
+        Self {
+            cpu: new_cpu(engine, &dp, decode_cache),
+            data_path: dp,
+            pending_trap: None,
+        }
+    }
+
+    pub fn data_path(&self) -> &DataPath {
+        &self.data_path
+    }
+
+    pub fn data_path_mut(&mut self) -> &mut DataPath {
+        &mut self.data_path
+    }
+
+    /// Get the engine running instructions, with its instructions in flight.
+    pub fn cpu(&self) -> &dyn Cpu {
+        &*self.cpu
+    }
+}
+
+impl fmt::Display for RunLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Cycles(cycles) => write!(f, "Reached the limit of {} cycles", cycles),
+            Self::Time(timeout) => write!(f, "Timed out after {}s", timeout.as_secs()),
+        }
+    }
 }
 
 impl System {
     pub fn new(config: &Config) -> Result<Self> {
-        let dp = DataPath::new();
-        Ok(Self {
-            data_path: dp,
-            mem: Memory::new(config),
+        let ncpus = config.get_ncpus();
+        if ncpus == 0 || ncpus > MAX_CORES {
+            return berr!(
+                Config,
+                format!("Cannot emulate {} cores, the most is {}", ncpus, MAX_CORES)
+            );
+        }
+        let windows = config.get_windows();
+        if windows < MIN_REG_WINDOWS as u32 || windows > MAX_REG_WINDOWS as u32 {
+            return berr!(
+                Config,
+                format!(
+                    "Cannot emulate {} register windows, there must be {} to {}",
+                    windows, MIN_REG_WINDOWS, MAX_REG_WINDOWS
+                )
+            );
+        }
+        let decode_cache = config.get_decode_cache();
+        if decode_cache != 0 && !decode_cache.is_power_of_two() {
+            return berr!(
+                Config,
+                format!(
+                    "The decode cache must have a power of two entries, not {}",
+                    decode_cache
+                )
+            );
+        }
+        let engine = config.get_engine();
+        let mut mem = Memory::new(config);
+        mem.attach(Box::new(
+            Console::stdout().with_base(config.get_console_base()),
+        ));
+        let keyboard = Keyboard::new();
+        let keyboard_input = keyboard.input();
+        mem.attach(Box::new(keyboard));
+        let gamepad = Gamepad::new();
+        let gamepad_input = gamepad.input();
+        mem.attach(Box::new(gamepad));
+        let host = Host::new();
+        let host_control = host.control();
+        mem.attach(Box::new(host));
+        mem.attach(Box::new(Timer::new()));
+        let inputs = InputLog::new();
+        mem.attach(Box::new(Rtc::with_inputs(inputs.clone())));
+        let doorbell = Doorbell::new(ncpus);
+        let doorbell_lines = doorbell.lines();
+        mem.attach(Box::new(doorbell));
+        if let Some(path) = config.get_disk_image() {
+            mem.attach(Box::new(BlockDevice::open(path)?));
+        }
+        #[cfg(feature = "fpu")]
+        let fpu = if config.has_fpu() {
+            let fpu = Fpu::new();
+            mem.attach(Box::new(fpu.registers()));
+            Some(fpu)
+        } else {
+            None
+        };
+        #[cfg(not(feature = "fpu"))]
+        {
+            if config.has_fpu() {
+                return berr!(
+                    Config,
+                    "The FPU needs the emulator built with the fpu feature"
+                );
+            }
+        }
+        let mut symbols = Symbols::new();
+        for path in config.get_symbols() {
+            symbols.extend(&Symbols::read(path)?);
+        }
+        let snapshot_trigger = match config.get_snapshot_when() {
+            Some(condition) => Some(SnapshotTrigger::new(
+                Expr::parse_with_symbols(condition, &symbols)?,
+                config.get_cache_path().clone(),
+            )),
+            None => None,
+        };
+        let trap_vectors = TrapVectors::at(config.get_trap_vectors());
+        let mut system = Self {
+            cores: (0..ncpus)
+                .map(|id| Core::new(id, engine, windows as u8, trap_vectors, decode_cache))
+                .collect(),
+            engine: engine,
+            decode_cache: decode_cache,
+            mem: mem,
             clock: Clock::new(config),
-            cycle_ops: InstructionCycle::noop_cycle(),
             phase: Phase::One,
-            pins_out: OutputPins::new(),
-            pipeline_suspended: false,
-            is_paused: false,
-        })
+            state: RunState::Running,
+            images: Vec::new(),
+            entry: None,
+            trap_vectors: trap_vectors,
+            poweron_state: config.get_poweron_state(),
+            poweron_seed: config.get_mem_fill_seed(),
+            breakpoints: config.get_breakpoints().iter().cloned().collect(),
+            retire_hooks: Vec::new(),
+            profiler: if config.is_profiling() {
+                Some(Profiler::new())
+            } else {
+                None
+            },
+            alignment_stats: if config.is_tracking_alignment() {
+                Some(AlignmentStats::new())
+            } else {
+                None
+            },
+            anomalies: if config.is_detecting_anomalies() {
+                Some(AnomalyDetector::new(
+                    config.get_livelock_limit() as u64,
+                    config.is_pausing_on_anomaly(),
+                ))
+            } else {
+                None
+            },
+            hazards: if config.is_detecting_hazards() {
+                Some(HazardDetector::new())
+            } else {
+                None
+            },
+            tracer: match config.get_trace() {
+                Some(path) => Some(Tracer::create(path, config.get_trace_format())?),
+                None => None,
+            },
+            pipeline_log: match (config.get_pipeline_debug(), config.get_pipeline_debug_log()) {
+                (PipelineDebug::Off, _) => None,
+                (level, Some(path)) => Some(PipelineLog::create(path, level)?),
+                (level, None) => Some(PipelineLog::stderr(level)),
+            },
+            pipeline_history: None,
+            syntax: Syntax::Modern,
+            symbols: symbols,
+            host_mem_limit: None,
+            over_limit: false,
+            snapshot_trigger: snapshot_trigger,
+            snapshots: SnapshotSession::new(
+                config.get_cache_path().clone(),
+                config.get_snapshot_every() as u64,
+            ),
+            coprocessors: Coprocessors::new(),
+            stalled: false,
+            keyboard: keyboard_input,
+            gamepad: gamepad_input,
+            host: host_control,
+            doorbell: doorbell_lines,
+            inputs: inputs,
+            host_inputs: Vec::new(),
+            max_cycles: match config.get_max_cycles() {
+                0 => None,
+                cycles => Some(cycles),
+            },
+            timeout: match config.get_timeout_seconds() {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds as u64)),
+            },
+            limit_hit: None,
+        };
+        #[cfg(feature = "fpu")]
+        {
+            if let Some(fpu) = fpu {
+                system.attach_coprocessor(Box::new(fpu));
+            }
+        }
+        for ext in config.get_isa_extensions() {
+            system.attach_extension(match *ext {
+                IsaExtension::Mul => Rc::new(MulDiv),
+            });
+        }
+        system.power_on_cores();
+        system.set_delay_slots(config.has_delay_slots());
+        system.set_syntax(config.get_syntax());
+        system.set_host_mem_limit(match config.get_host_mem_limit() {
+            0 => None,
+            mb => Some(mb as u64 * MEGABYTE),
+        })?;
+        Ok(system)
     }
 
     pub fn get_mem_ref(&mut self) -> &mut Memory {
         &mut self.mem
     }
 
+    pub fn memory(&self) -> &Memory {
+        &self.mem
+    }
+
+    /// Get whether the system runs.
+    pub fn run_state(&self) -> RunState {
+        if self.halted() {
+            RunState::Halted
+        } else {
+            self.state
+        }
+    }
+
+    /// Stop the clock. Does nothing if the guest halted.
+    pub fn pause(&mut self) {
+        if !self.halted() {
+            self.state = RunState::Paused;
+        }
+    }
+
+    /// Start the clock again after a pause. Does nothing if the guest
+    /// halted.
+    pub fn resume(&mut self) {
+        if !self.halted() {
+            self.state = RunState::Running;
+        }
+    }
+
+    /// Pause a running system or resume a paused one.
     pub fn toggle_pause(&mut self) {
-        self.is_paused = !self.is_paused
+        match self.state {
+            RunState::Running => self.pause(),
+            _ => self.resume(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.run_state() == RunState::Paused
+    }
+
+    /// Return true if the guest halted by writing the host's exit register.
+    /// A halted system does not run until it is reset.
+    pub fn halted(&self) -> bool {
+        self.host.exit_code().is_some()
+    }
+
+    /// Return true if the clock runs, i.e. the system is neither paused nor
+    /// halted.
+    fn is_running(&self) -> bool {
+        self.run_state() == RunState::Running
     }
 
-    pub fn tick(&mut self) {
-        if self.is_paused {
-            return;
+    /// Return an error if the system runs, so the debugger cannot edit it
+    /// from under an instruction.
+    fn check_editable(&self) -> Result<()> {
+        if self.is_running() {
+            return berr!(Config, "Pause the system before editing it");
         }
+        Ok(())
+    }
 
-        let cur_phase = self.phase.clone();
-        self.clock.tick_and_wait(cur_phase);
-
-        // Fetch
-        // Execute.
-        // Commit.
-
-        let dp = &mut self.data_path;
-        self.phase = match self.phase {
-            Phase::One => {
-                if !self.pipeline_suspended {
-                    // Tell the pipeline we're moving on to the next instruction.
-                    dp.shift_pipeline_latches();
-                    // Registers are read and then sent to the input latches of the ALU.
-                    dp.route_regs_to_alu();
-                    // TODO determine when this callback should be run.
-                    self.cycle_ops[0](dp);
+    /// Pause the system once the instruction at an address retires on the
+    /// boot core, while it runs frame by frame. Return false if there
+    /// already was a breakpoint there.
+    /// # Arguments
+    /// * `pc` - Address of the instruction.
+    pub fn set_breakpoint(&mut self, pc: u32) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    /// Remove a breakpoint. Return false if there was none at the address.
+    /// # Arguments
+    /// * `pc` - Address of the instruction.
+    pub fn clear_breakpoint(&mut self, pc: u32) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    /// Get the addresses of the breakpoints, lowest first.
+    pub fn breakpoints(&self) -> &BTreeSet<u32> {
+        &self.breakpoints
+    }
+
+    /// Write a register of a core from the debugger, as the core's current
+    /// window sees it. Writes to r0 are a no op. Return void on success and
+    /// a string if the system runs or the register does not exist.
+    /// # Arguments
+    /// * `core` - Number of the core.
+    /// * `reg` - Which register, 0 to 31.
+    /// * `value` - Value to write.
+    pub fn set_register(&mut self, core: usize, reg: u8, value: u32) -> Result<()> {
+        self.check_editable()?;
+        let dp = match self.cores.get_mut(core) {
+            Some(c) => c.data_path_mut(),
+            None => return berr!(Config, format!("There is no core {}", core)),
+        };
+        let cwp = dp.psw().get_cwp();
+        dp.get_register_file().try_write(reg, value, cwp)
+    }
+
+    /// Write bytes of memory from the debugger, ignoring devices like the
+    /// hex view does. The fast engine's decode cache checks the word it
+    /// decoded on every fetch, so edited code runs as edited from the next
+    /// fetch on. Like after a store from the guest, an instruction the
+    /// pipeline already fetched runs as it was fetched. Return void on
+    /// success and a string if the system runs or the bytes are outside of
+    /// memory.
+    /// # Arguments
+    /// * `address` - Address of the first byte.
+    /// * `bytes` - The bytes.
+    pub fn set_memory(&mut self, address: u32, bytes: &[u8]) -> Result<()> {
+        self.check_editable()?;
+        self.mem.write_buf(address, bytes)
+    }
+
+    /// Write a word of memory from the debugger, in the byte order of
+    /// memory. Return void on success and a string if the system runs, the
+    /// word is not aligned or it is outside of memory.
+    /// # Arguments
+    /// * `address` - Address of the word.
+    /// * `value` - Value to write.
+    pub fn set_memory_word(&mut self, address: u32, value: u32) -> Result<()> {
+        if address % 4 != 0 {
+            return berr!(
+                Config,
+                format!("Address 0x{:08x} is not word aligned", address)
+            );
+        }
+        let bytes = self.mem.endian().word_bytes(value);
+        self.set_memory(address, &bytes)
+    }
+
+    /// Get the exit code the guest halted with, None if it has not halted.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.host.exit_code()
+    }
+
+    /// Attach a device to the system.
+    /// # Arguments
+    /// * `device` - Device to attach.
+    pub fn attach(&mut self, device: Box<dyn Device>) {
+        self.mem.attach(device);
+    }
+
+    /// Attach a coprocessor to the system.
+    /// # Arguments
+    /// * `unit` - Coprocessor to attach.
+    pub fn attach_coprocessor(&mut self, unit: Box<dyn Coprocessor>) {
+        self.coprocessors.attach(unit);
+    }
+
+    /// Attach an instruction set extension to every core.
+    /// # Arguments
+    /// * `unit` - Extension to attach.
+    pub fn attach_extension(&mut self, unit: Rc<dyn ExtensionIsa>) {
+        for core in self.cores.iter_mut() {
+            let mut extensions = core.data_path.extensions().clone();
+            extensions.attach(unit.clone());
+            core.data_path.set_extensions(extensions);
+        }
+    }
+
+    pub fn coprocessors(&self) -> &Coprocessors {
+        &self.coprocessors
+    }
+
+    /// Get the handle that sends host key events to the keyboard.
+    pub fn keyboard(&self) -> &KeyboardInput {
+        &self.keyboard
+    }
+
+    /// Get the handle that sends the host's gamepad state to the gamepad.
+    pub fn gamepad(&self) -> &GamepadInput {
+        &self.gamepad
+    }
+
+    /// Get the handle that controls the host services.
+    pub fn host(&self) -> &HostControl {
+        &self.host
+    }
+
+    /// Send an input from the host, to take effect when the next clock
+    /// cycle starts. Inputs sent this way are recorded, or ignored while
+    /// replaying, unlike ones sent straight to the keyboard or gamepad. A
+    /// time of day is ignored, the guest reads that from the real-time
+    /// clock.
+    /// # Arguments
+    /// * `input` - The input.
+    pub fn send_input(&mut self, input: Input) {
+        self.host_inputs.push(input);
+    }
+
+    /// Get the log that records or replays the inputs from outside the
+    /// guest.
+    pub fn inputs(&self) -> &InputLog {
+        &self.inputs
+    }
+
+    /// Reset the system. Both kinds of reset restart the CPU at the reset
+    /// vector with a fresh PSW and reset every device, a cold reset also
+    /// clears memory and the register file.
+    /// # Arguments
+    /// * `kind` - Kind of reset to perform.
+    pub fn reset(&mut self, kind: Reset) -> Result<()> {
+        for (id, core) in self.cores.iter_mut().enumerate() {
+            match kind {
+                Reset::Warm => core.data_path.reset(),
+                Reset::Cold => {
+                    let windows = core.data_path.register_file().windows();
+                    let extensions = core.data_path.extensions().clone();
+                    let delay_slots = core.data_path.has_delay_slots();
+                    core.data_path = DataPath::new();
+                    core.data_path.set_cpu_id(id as u32);
+                    core.data_path.set_windows(windows);
+                    core.data_path.set_extensions(extensions);
+                    core.data_path.set_delay_slots(delay_slots);
                 }
-                Phase::Two
             }
-            Phase::Two => {
-                // Memory copies output pin data for writing (if any writing is to be done).
-                dp.get_output_pins_ref().phase_two_copy(&mut self.pins_out);
+            core.data_path.set_trap_vectors(self.trap_vectors);
+            core.pending_trap = None;
+        }
+        if kind == Reset::Cold {
+            self.power_on_cores();
+            self.mem.clear();
+        }
+        self.mem.reset_devices(kind);
+        self.coprocessors.reset(kind);
+        self.stalled = false;
+        self.limit_hit = None;
+        for image in self.images.iter() {
+            image.write_to(&mut self.mem)?;
+        }
+        // Throw away anything in flight.
+        match self.entry {
+            Some(pc) => self.set_entry(pc),
+            None => self.set_engine(self.engine),
+        }
+        Ok(())
+    }
+
+    /// Copy a program image into memory. It is copied again on every reset.
+    /// Return void on success and a string if it does not fit.
+    /// # Arguments
+    /// * `image` - The image.
+    pub fn load_image(&mut self, image: &Image) -> Result<()> {
+        image.write_to(&mut self.mem)?;
+        self.images.push(image.clone());
+        Ok(())
+    }
+
+    /// Switch to a different execution engine. Any instructions in flight
+    /// in the pipeline are discarded and execution restarts at PC.
+    /// # Arguments
+    /// * `engine` - Engine to switch to.
+    pub fn set_engine(&mut self, engine: Engine) {
+        for core in self.cores.iter_mut() {
+            core.cpu = new_cpu(engine, &core.data_path, self.decode_cache);
+        }
+        self.engine = engine;
+        self.phase = Phase::One;
+    }
+
+    /// Start every core at an address instead of the reset vector, now and
+    /// after every reset. Any instructions in flight are discarded.
+    /// # Arguments
+    /// * `pc` - Address of the first instruction to run.
+    pub fn set_entry(&mut self, pc: u32) {
+        self.entry = Some(pc);
+        for core in self.cores.iter_mut() {
+            core.data_path.set_pc(pc);
+        }
+        self.set_engine(self.engine);
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Register a callback that is run every time an instruction retires.
+    /// # Arguments
+    /// * `hook` - Callback to run, given the retired instruction.
+    pub fn on_retire<F>(&mut self, hook: F)
+    where
+        F: FnMut(&RetiredInstruction) + 'static,
+    {
+        self.retire_hooks.push(Box::new(hook));
+    }
+
+    /// Turn profiling on or off. Turning it on
+    /// starts from a clean profile.
+    /// # Arguments
+    /// * `on` - True to profile, false to stop.
+    pub fn set_profiling(&mut self, on: bool) {
+        self.profiler = if on { Some(Profiler::new()) } else { None };
+    }
+
+    /// Get the profile, None if profiling is off.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Turn tracking load and store alignment on or off. Turning it on
+    /// starts from clean statistics.
+    /// # Arguments
+    /// * `on` - True to track, false to stop.
+    pub fn set_alignment_tracking(&mut self, on: bool) {
+        self.alignment_stats = if on {
+            Some(AlignmentStats::new())
+        } else {
+            None
+        };
+    }
+
+    /// Get the alignment statistics, None if they are not tracked.
+    pub fn alignment_stats(&self) -> Option<&AlignmentStats> {
+        self.alignment_stats.as_ref()
+    }
+
+    /// Get how the cores' decode caches did, added up, None if the cores
+    /// have none.
+    pub fn decode_cache_stats(&self) -> Option<DecodeCacheStats> {
+        let mut total: Option<DecodeCacheStats> = None;
+        for stats in self.cores.iter().filter_map(|c| c.cpu.decode_cache_stats()) {
+            total
+                .get_or_insert_with(DecodeCacheStats::default)
+                .add(&stats);
+        }
+        total
+    }
+
+    /// Start or stop looking for anomalies in the instruction stream.
+    /// # Arguments
+    /// * `detector` - Detector to use, None to stop.
+    pub fn set_anomaly_detector(&mut self, detector: Option<AnomalyDetector>) {
+        self.anomalies = detector;
+    }
+
+    /// Get the anomaly detector, None if anomalies are not looked for.
+    pub fn anomalies(&self) -> Option<&AnomalyDetector> {
+        self.anomalies.as_ref()
+    }
+
+    /// Start or stop looking for pipeline hazards in the instruction stream.
+    /// # Arguments
+    /// * `detector` - Detector to use, None to stop.
+    pub fn set_hazard_detector(&mut self, detector: Option<HazardDetector>) {
+        self.hazards = detector;
+    }
+
+    /// Get the hazard detector, None if hazards are not looked for.
+    pub fn hazards(&self) -> Option<&HazardDetector> {
+        self.hazards.as_ref()
+    }
+
+    /// Start or stop tracing retired instructions. A trace being replaced
+    /// is flushed first.
+    /// # Arguments
+    /// * `tracer` - Tracer to log to, None to stop tracing.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Result<()> {
+        if let Some(old) = self.tracer.as_mut() {
+            old.flush()?;
+        }
+        self.tracer = tracer;
+        let syntax = self.syntax;
+        self.set_syntax(syntax);
+        Ok(())
+    }
+
+    /// Start or stop logging the pipeline's inner workings. A log being
+    /// replaced is flushed first.
+    /// # Arguments
+    /// * `log` - Log to write to, None to stop logging.
+    pub fn set_pipeline_log(&mut self, log: Option<PipelineLog>) -> Result<()> {
+        if let Some(old) = self.pipeline_log.as_mut() {
+            old.flush()?;
+        }
+        self.pipeline_log = log;
+        let syntax = self.syntax;
+        self.set_syntax(syntax);
+        Ok(())
+    }
+
+    /// Start or stop recording what every core's stages hold after each
+    /// clock phase. Records from before are forgotten.
+    /// # Arguments
+    /// * `capacity` - Most records to keep per core, None to stop recording.
+    pub fn set_pipeline_history(&mut self, capacity: Option<usize>) {
+        let ncores = self.cores.len();
+        self.pipeline_history =
+            capacity.map(|c| (0..ncores).map(|_| PipelineHistory::new(c)).collect());
+    }
+
+    /// Get the recent occupancy of a core's stages, None if not recorded.
+    /// # Arguments
+    /// * `core` - The core, 0 for the boot core.
+    pub fn pipeline_history(&self, core: usize) -> Option<&PipelineHistory> {
+        self.pipeline_history.as_ref().and_then(|h| h.get(core))
+    }
+
+    /// Get the symbols of the guest program.
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Get the syntax the trace and pipeline log disassemble instructions in.
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    /// Choose whether the instruction after a jump, call or return runs
+    /// before the target on every core. Without delay slots the cycle
+    /// accurate engine throws away the instruction fetched behind a taken
+    /// transfer, a bubble. Returns still add their offset to the saved PC,
+    /// so a program written for delay slots must be changed to run without
+    /// them.
+    /// # Arguments
+    /// * `delay_slots` - True for delay slots, false to go to the target
+    /// right away.
+    pub fn set_delay_slots(&mut self, delay_slots: bool) {
+        for core in self.cores.iter_mut() {
+            core.data_path.set_delay_slots(delay_slots);
+        }
+    }
+
+    /// Change the syntax the trace and pipeline log disassemble instructions
+    /// in, from the next line they write.
+    /// # Arguments
+    /// * `syntax` - Syntax to disassemble in.
+    pub fn set_syntax(&mut self, syntax: Syntax) {
+        self.syntax = syntax;
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.set_syntax(syntax);
+        }
+        if let Some(log) = self.pipeline_log.as_mut() {
+            log.set_syntax(syntax);
+        }
+    }
+
+    /// Get the log of the pipeline's inner workings, None if it is off.
+    pub fn pipeline_log_mut(&mut self) -> Option<&mut PipelineLog> {
+        self.pipeline_log.as_mut()
+    }
+
+    /// Get the host memory the system uses.
+    pub fn usage(&self) -> HostUsage {
+        HostUsage {
+            guest: self.mem.size() as u64,
+            bookkeeping: self.anomalies.as_ref().map_or(0, |d| d.heap_size()),
+            trace: self.tracer.as_ref().map_or(0, |t| t.size()),
+            snapshots: self.snapshots.size(),
+        }
+    }
+
+    /// Limit the host memory the system uses, evicting old data to stay
+    /// under it as the system runs. Return void on success and a string if
+    /// guest memory alone does not fit.
+    /// # Arguments
+    /// * `limit` - Most bytes the system may use, None for no limit.
+    pub fn set_host_mem_limit(&mut self, limit: Option<u64>) -> Result<()> {
+        if let Some(limit) = limit {
+            if self.mem.size() as u64 > limit {
+                return berr!(
+                    Config,
+                    format!(
+                        "Guest memory ({}) does not fit in the host memory limit ({})",
+                        format_bytes(self.mem.size() as u64),
+                        format_bytes(limit)
+                    )
+                );
+            }
+        }
+        self.host_mem_limit = limit;
+        self.over_limit = false;
+        self.enforce_host_mem_limit()
+    }
+
+    /// Limit how long `run_until_halt` runs.
+    /// # Arguments
+    /// * `max_cycles` - Clock cycle to stop at, None for no limit.
+    /// * `timeout` - Longest a run may take, None for no limit.
+    pub fn set_run_limits(&mut self, max_cycles: Option<u64>, timeout: Option<Duration>) {
+        self.max_cycles = max_cycles;
+        self.timeout = timeout;
+    }
+
+    /// Get the limit that stopped the last run, None if none did.
+    pub fn limit_hit(&self) -> Option<RunLimit> {
+        self.limit_hit
+    }
+
+    /// Set the condition that captures a snapshot whenever it becomes true.
+    /// It is checked after every retired instruction.
+    /// # Arguments
+    /// * `trigger` - Trigger to use, None to stop capturing.
+    pub fn set_snapshot_trigger(&mut self, trigger: Option<SnapshotTrigger>) {
+        self.snapshot_trigger = trigger;
+    }
+
+    /// Get the snapshot trigger, None if there is none.
+    pub fn snapshot_trigger(&self) -> Option<&SnapshotTrigger> {
+        self.snapshot_trigger.as_ref()
+    }
+
+    /// Replace the snapshots of this session.
+    /// # Arguments
+    /// * `session` - Session to record snapshots in from now on.
+    pub fn set_snapshot_session(&mut self, session: SnapshotSession) {
+        self.snapshots = session;
+    }
+
+    /// Get the snapshots taken during this session.
+    pub fn snapshots(&self) -> &SnapshotSession {
+        &self.snapshots
+    }
+
+    pub fn snapshots_mut(&mut self) -> &mut SnapshotSession {
+        &mut self.snapshots
+    }
+
+    /// Save a snapshot to the session's directory and add it to the session.
+    /// Return the path of the snapshot on success and a string on error.
+    /// # Arguments
+    /// * `kind` - Why the snapshot is being taken.
+    pub fn take_snapshot(&mut self, kind: SnapshotKind) -> Result<String> {
+        let cycle = self.clock.count();
+        let path = self.snapshots.new_path(kind, cycle)?;
+        self.save_snapshot(&path)?;
+        self.snapshots.record(kind, path.clone(), cycle)?;
+        Ok(path)
+    }
+
+    /// Restore a snapshot of this session. Return the path of the snapshot
+    /// on success and a string on error.
+    /// # Arguments
+    /// * `index` - Index of the snapshot in the session.
+    pub fn restore_session_snapshot(&mut self, index: usize) -> Result<String> {
+        let path = match self.snapshots.snapshots().get(index) {
+            Some(s) => s.path.clone(),
+            None => return berr!(Config, format!("No snapshot {} in this session", index)),
+        };
+        self.load_snapshot(&path)?;
+        Ok(path)
+    }
 
-                if !self.pipeline_suspended {
-                    // Route immediate to ALU.
-                    dp.route_imm_to_alu();
-                    // TODO determine when this callback should be run.
-                    self.cycle_ops[1](dp);
+    /// Save the engine, clock phase, every core's data path and instructions
+    /// in flight, clock and memory to a snapshot file. Attached devices and
+    /// coprocessors are not saved. Return void on success and a string on
+    /// error.
+    /// # Arguments
+    /// * `path` - Path of the snapshot file.
+    pub fn save_snapshot(&self, path: &String) -> Result<()> {
+        let mut w = Writer::new();
+        w.put_u8(match self.engine {
+            Engine::Fast => 0,
+            Engine::Cycle => 1,
+        });
+        self.phase.save(&mut w);
+        w.put_u32(self.cores.len() as u32);
+        for core in self.cores.iter() {
+            core.data_path.save(&mut w);
+            core.cpu.save(&mut w);
+        }
+        self.clock.save(&mut w);
+        self.mem.save(&mut w);
+        w.write_file(path)
+    }
+
+    /// Restore the system from a snapshot file, switching to the engine it
+    /// was saved with. Nothing is restored if the snapshot is truncated, of
+    /// another version or of a system with another number of cores. Return
+    /// void on success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the snapshot file.
+    pub fn load_snapshot(&mut self, path: &String) -> Result<()> {
+        let mut r = Reader::open(path)?;
+        let engine = match r.get_u8()? {
+            0 => Engine::Fast,
+            1 => Engine::Cycle,
+            v => return berr!(Config, format!("Snapshot has invalid engine {}", v)),
+        };
+        let mut phase = Phase::One;
+        phase.restore(&mut r)?;
+        let ncores = r.get_u32()?;
+        if ncores as usize != self.cores.len() {
+            return berr!(
+                Config,
+                format!(
+                    "Snapshot has {} cores, the system has {}",
+                    ncores,
+                    self.cores.len()
+                )
+            );
+        }
+        let mut cores = Vec::new();
+        for core in self.cores.iter() {
+            let mut data_path = core.data_path.clone();
+            data_path.restore(&mut r)?;
+            let mut cpu = new_cpu(engine, &data_path, self.decode_cache);
+            cpu.restore(&mut r)?;
+            cores.push(Core {
+                data_path: data_path,
+                cpu: cpu,
+                pending_trap: None,
+            });
+        }
+        let mut clock = self.clock.clone();
+        clock.restore(&mut r)?;
+        // Memory is last, so it is only overwritten if everything before it
+        // was read.
+        self.mem.restore(&mut r)?;
+        r.finish()?;
+
+        self.engine = engine;
+        self.phase = phase;
+        self.cores = cores;
+        self.clock = clock;
+        Ok(())
+    }
+
+    /// Run whole clock cycles until an instruction retires on the boot core
+    /// (or `MAX_STEP_CYCLES` pass without one retiring).
+    pub fn step(&mut self) -> Result<()> {
+        let mut retired = false;
+        for _ in 0..MAX_STEP_CYCLES * 4 {
+            retired |= self.run_phase(true)?.is_some();
+            if retired && self.phase == Phase::One {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run clock phases, without waiting on the clock, until an instruction
+    /// retires on the boot core. Return the instruction, or None if
+    /// `MAX_STEP_CYCLES` pass without one retiring.
+    pub fn run_until_retired(&mut self) -> Result<Option<RetiredInstruction>> {
+        for _ in 0..MAX_STEP_CYCLES * 4 {
+            if let Some(r) = self.run_phase(false)? {
+                return Ok(Some(r));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run one whole clock cycle, from the current phase, without waiting on
+    /// the clock. Return the number of instructions that retired on the boot
+    /// core.
+    pub fn run_cycle(&mut self) -> Result<u32> {
+        let mut retired = 0;
+        for _ in 0..4 {
+            retired += self.run_phase(false)?.is_some() as u32;
+        }
+        Ok(retired)
+    }
+
+    /// Run the current clock phase, unless emulation is paused or the guest
+    /// halted. Waits to keep the clock rate if the clock is paced in real
+    /// time.
+    pub fn tick(&mut self) -> Result<()> {
+        if self.is_running() {
+            let paced = self.clock.mode() == ClockMode::Realtime;
+            self.run_phase(paced)?;
+        }
+        Ok(())
+    }
+
+    /// Run the system for a frame of its windows, whole clock cycles at a
+    /// time, unless emulation is paused or the guest halted. In the frame
+    /// clock mode that is the configured number of instructions, otherwise a
+    /// frame's worth of time, paced or not. Stops early, pausing the system,
+    /// once the boot core retires the instruction at a breakpoint. Return
+    /// the number of instructions the boot core retired.
+    pub fn run_frame(&mut self) -> Result<u64> {
+        let mode = self.clock.mode();
+        let start = Instant::now();
+        let mut count = 0u64;
+        while self.is_running() {
+            if let Some(r) = self.run_phase(mode == ClockMode::Realtime)? {
+                count += 1;
+                if self.breakpoints.contains(&r.pc) {
+                    self.pause();
+                    break;
                 }
+            }
+            if self.phase != Phase::One {
+                continue;
+            }
+            let done = match mode {
+                ClockMode::Frame => count >= self.clock.frame_instructions(),
+                _ => start.elapsed() >= FRAME_TIME,
+            };
+            if done {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Run up to `cycles` whole clock cycles without waiting on the clock,
+    /// paused or not. Stops early if the guest halts or an anomaly pauses
+    /// the system, a paused system is still paused afterwards. Return the
+    /// number of instructions the boot core retired.
+    /// # Arguments
+    /// * `cycles` - Most clock cycles to run.
+    pub fn run_for(&mut self, cycles: u64) -> Result<u64> {
+        let before = self.state;
+        self.state = RunState::Running;
+        let end = self.clock.count() + cycles;
+        let mut count = 0u64;
+        while self.is_running() && (self.clock.count() < end || self.phase != Phase::One) {
+            count += self.run_phase(false)?.is_some() as u64;
+        }
+        if self.state == RunState::Running {
+            self.state = before;
+        }
+        Ok(count)
+    }
 
-                // Route sources and immediate thru shifter.
-                Phase::Three
-            }
-            Phase::Three => {
-                // Finish read from last cycle.
-                let mem = &self.mem;
-                // TODO check for invalid address from MMU.
-                dp.set_input_pins(match mem.get_word(self.pins_out.address) {
-                    Ok(v) => v,
-                    Err(_) => {
-                        eprint!("Bad mem read: {}", self.pins_out.address);
-                        0
-                    }
-                });
-
-                if self.pipeline_suspended {
-                    self.pipeline_suspended = false;
-                } else if dp.current_instruction_is_memory() {
-                    // Commit the result of the last instruction.
-                    dp.commit();
-                    self.pipeline_suspended = true;
-                } else {
-                    // Commit the result of the last instruction.
-                    dp.commit();
-                    // TODO determine when this callback should be run.
-                    self.cycle_ops[2](dp);
+    /// Run without waiting on the clock, paused or not, until the boot core
+    /// retires the instruction at `pc` or `max_cycles` pass. Stops early
+    /// like `run_for`. Return the instruction, None if it did not retire.
+    /// # Arguments
+    /// * `pc` - Address of the instruction.
+    /// * `max_cycles` - Most clock cycles to run.
+    pub fn run_until(&mut self, pc: u32, max_cycles: u64) -> Result<Option<RetiredInstruction>> {
+        let before = self.state;
+        self.state = RunState::Running;
+        let end = self.clock.count() + max_cycles;
+        let mut found = None;
+        while self.is_running() && (self.clock.count() < end || self.phase != Phase::One) {
+            match self.run_phase(false)? {
+                Some(r) if r.pc == pc => {
+                    found = Some(r);
+                    break;
                 }
-                Phase::Four
+                _ => {}
             }
-            Phase::Four => {
-                // In actual RISCII this is where the source and dest registers are decoded
-                // for the next instruction, but that is unnecessary here.
-                self.pins_out.address = dp.get_out_address();
+        }
+        if self.state == RunState::Running {
+            self.state = before;
+        }
+        Ok(found)
+    }
 
-                if !self.pipeline_suspended {
-                    // TODO determine when this callback should be run.
-                    self.cycle_ops[3](dp);
-                    dp.decode();
+    /// Run instructions back to back, without waiting on the clock, until
+    /// the boot core halts by branching to itself, the guest writes the
+    /// host's exit register, an anomaly pauses the system or a run limit is
+    /// reached, which pauses it too. Return the number of instructions the
+    /// boot core retired.
+    pub fn run_until_halt(&mut self) -> Result<u64> {
+        let started = Instant::now();
+        let mut count = 0u64;
+        while !self.halted() {
+            if let Some(limit) = self.check_run_limits(started) {
+                self.limit_hit = Some(limit);
+                self.pause();
+                break;
+            }
+            if let Some(r) = self.run_phase(false)? {
+                count += 1;
+                if r.branch == Some(r.pc) || self.is_paused() {
+                    break;
                 }
-                // If the instruction was a load, shift the result if necessary.
-                Phase::One
             }
+        }
+        Ok(count)
+    }
+
+    /// Get the limit a run started at `started` is over, None if it may go
+    /// on. Limits are only looked at between clock cycles.
+    /// # Arguments
+    /// * `started` - When the run started.
+    fn check_run_limits(&self, started: Instant) -> Option<RunLimit> {
+        if self.phase != Phase::One {
+            return None;
+        }
+        let cycle = self.clock.count();
+        match self.max_cycles {
+            Some(max) if cycle >= max => return Some(RunLimit::Cycles(max)),
+            _ => {}
+        }
+        match self.timeout {
+            Some(timeout) if cycle % TIMEOUT_CHECK_CYCLES == 0 && started.elapsed() >= timeout => {
+                Some(RunLimit::Time(timeout))
+            }
+            _ => None,
+        }
+    }
+
+    /// Run the current clock phase on every core and move to the next one.
+    /// Return the instruction that retired on the boot core, if any.
+    /// # Arguments
+    /// * `paced` - True if the clock should wait to keep the configured rate.
+    fn run_phase(&mut self, paced: bool) -> Result<Option<RetiredInstruction>> {
+        let cur_phase = self.phase.clone();
+        self.host.set_realtime(paced);
+        if paced {
+            self.clock.tick_and_wait(cur_phase.clone());
+        } else {
+            self.clock.tick(cur_phase.clone());
+        }
+
+        if cur_phase == Phase::One {
+            self.start_cycle();
+        }
+
+        let mut retired = Vec::new();
+        for (id, core) in self.cores.iter_mut().enumerate() {
+            // The coprocessors only hold the boot core.
+            if id == 0 && self.stalled {
+                continue;
+            }
+            let start = self.profiler.as_ref().map(|_| Instant::now());
+            let r = core.cpu.tick(
+                &cur_phase,
+                &mut core.data_path,
+                &mut self.mem,
+                self.clock.count(),
+            )?;
+            if let (Some(profiler), Some(start)) = (self.profiler.as_mut(), start) {
+                profiler.record(start.elapsed(), r.as_ref());
+            }
+            if let Some(log) = self.pipeline_log.as_mut() {
+                log.record(
+                    self.clock.count(),
+                    &cur_phase,
+                    id,
+                    &*core.cpu,
+                    &core.data_path,
+                )?;
+            }
+            if let Some(history) = self.pipeline_history.as_mut() {
+                history[id].record(self.clock.count(), &cur_phase, core.cpu.occupancy());
+            }
+            if let Some(r) = r {
+                retired.push((id, r));
+            }
+        }
+
+        self.phase = match cur_phase {
+            Phase::One => Phase::Two,
+            Phase::Two => Phase::Three,
+            Phase::Three => Phase::Four,
+            Phase::Four => Phase::One,
             Phase::Interrupt => Phase::One,
         };
+        if self.phase == Phase::One {
+            // The guest may have yielded or asked for a snapshot during the
+            // cycle.
+            self.host.run_yield();
+            if self.host.take_snapshot_request() {
+                self.take_snapshot(SnapshotKind::Guest)?;
+            }
+        }
+
+        let mut boot = None;
+        for (id, r) in retired {
+            self.observe_retired(id, &r)?;
+            if id == 0 {
+                boot = Some(r);
+            }
+        }
+        Ok(boot)
+    }
+
+    /// Show an instruction that retired to everything watching the
+    /// instruction stream.
+    /// # Arguments
+    /// * `id` - Number of the core the instruction retired on.
+    /// * `r` - The instruction.
+    fn observe_retired(&mut self, id: usize, r: &RetiredInstruction) -> Result<()> {
+        if let Some(stats) = self.alignment_stats.as_mut() {
+            stats.observe(r);
+        }
+        if let Some(detector) = self.anomalies.as_mut() {
+            if let Some(anomaly) = detector.observe(r) {
+                core_log!(Level::Warn, r.cycle, r.pc, "{}", anomaly);
+                if detector.pauses() {
+                    self.pause();
+                }
+            }
+        }
+        if let Some(hazard) = self.hazards.as_mut().and_then(|d| d.observe(id, r)) {
+            core_log!(Level::Warn, r.cycle, r.pc, "{}", hazard);
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(r)?;
+        }
+        for hook in self.retire_hooks.iter_mut() {
+            hook(r);
+        }
+        if id == 0 {
+            self.coprocessors.issue(r);
+        }
+        let fired = match self.snapshot_trigger.as_mut() {
+            Some(trigger) => trigger.poll(&self.cores[0].data_path, self.clock.count())?,
+            None => None,
+        };
+        if let Some(path) = fired {
+            self.save_snapshot(&path)?;
+            self.snapshots
+                .record(SnapshotKind::Trigger, path, self.clock.count())?;
+        }
+        if self.snapshots.is_periodic_due(self.clock.count()) {
+            self.take_snapshot(SnapshotKind::Periodic)?;
+        }
+        self.enforce_host_mem_limit()
+    }
+
+    /// Put the registers and latches of every core in their power on state,
+    /// the same state for the same seed.
+    fn power_on_cores(&mut self) {
+        let mut rng = Xorshift::new(self.poweron_seed);
+        for core in self.cores.iter_mut() {
+            core.data_path.power_on(self.poweron_state, &mut rng);
+        }
+    }
+
+    /// Evict old data until the system is under its host memory limit: the
+    /// trace, then the anomaly detector's history, then the oldest automatic
+    /// snapshots. Return void on success and a string on error.
+    fn enforce_host_mem_limit(&mut self) -> Result<()> {
+        let limit = match self.host_mem_limit {
+            Some(l) => l,
+            None => return Ok(()),
+        };
+        while self.usage().total() > limit {
+            if let Some(tracer) = self.tracer.as_mut() {
+                if tracer.evict()? {
+                    continue;
+                }
+            }
+            if let Some(detector) = self.anomalies.as_mut() {
+                let before = detector.heap_size();
+                detector.forget();
+                if detector.heap_size() < before {
+                    continue;
+                }
+            }
+            if self.snapshots.evict_oldest()?.is_some() {
+                continue;
+            }
+            if !self.over_limit {
+                self.over_limit = true;
+                warn!(
+                    "Using {} of host memory, over the limit of {} with nothing left to evict.",
+                    format_bytes(self.usage().total()),
+                    format_bytes(limit)
+                );
+            }
+            return Ok(());
+        }
+        self.over_limit = false;
+        Ok(())
+    }
+
+    /// Apply the inputs from outside the guest due, tick the devices, run
+    /// the coprocessors and device DMA for a clock cycle, forward the
+    /// completion the coprocessors report (if any) and take a coprocessor
+    /// trap or a device interrupt.
+    fn start_cycle(&mut self) {
+        let host = std::mem::replace(&mut self.host_inputs, Vec::new());
+        for input in self.inputs.start_cycle(self.clock.count(), host) {
+            match input {
+                Input::KeyDown(scancode) => self.keyboard.key_down(scancode),
+                Input::KeyUp(scancode) => self.keyboard.key_up(scancode),
+                Input::GamepadConnected(connected) => self.gamepad.set_connected(connected),
+                Input::GamepadButton(button, pressed) => self.gamepad.set_button(button, pressed),
+                Input::GamepadAxis(axis, value) => self.gamepad.set_axis(axis, value),
+                Input::Time(_) => {}
+            }
+        }
+        self.mem.tick_devices();
+        self.mem.run_dma();
+        self.stalled = false;
+        if !self.coprocessors.is_empty() {
+            let (completion, stall) = self.coprocessors.tick();
+            self.stalled = stall;
+            let boot = &mut self.cores[0];
+            if let Some(trap) = completion.and_then(|c| c.forward(&mut boot.data_path)) {
+                boot.pending_trap = Some(trap);
+            }
+        }
+        let device_interrupt = self.mem.interrupt_pending();
+        for (id, core) in self.cores.iter_mut().enumerate() {
+            // An interrupt taken between a branch and its delay slot would
+            // lose the branch target, so it waits until the slot has run.
+            let dp = &core.data_path;
+            let in_delay_slot = dp.get_next_pc() != dp.get_pc().wrapping_add(SIZEOF_INSTRUCTION);
+            if core.pending_trap.is_none()
+                && core.data_path.get_psw().get_interrupt_enabled()
+                && !in_delay_slot
+                && ((id == 0 && device_interrupt) || self.doorbell.is_ringing(id))
+            {
+                core.pending_trap = Some(Trap::Interrupt);
+            }
+            if let Some(trap) = core.pending_trap {
+                if core.cpu.drain(&mut core.data_path) {
+                    core.pending_trap = None;
+                    core.data_path.raise_trap(trap);
+                    // The handler starts on an empty pipeline.
+                    core.cpu.flush(&core.data_path);
+                }
+            }
+        }
     }
 
     pub fn clock(&self) -> &Clock {
         &self.clock
     }
 
+    /// Change how the clock is paced.
+    /// # Arguments
+    /// * `mode` - How the clock is paced.
+    pub fn set_clock_mode(&mut self, mode: ClockMode) {
+        self.clock.set_mode(mode);
+    }
+
+    /// Get the cores, the boot core first.
+    pub fn cores(&self) -> &[Core] {
+        &self.cores
+    }
+
+    pub fn cores_mut(&mut self) -> &mut [Core] {
+        &mut self.cores
+    }
+
+    /// Get the boot core's data path.
     pub fn data_path(&self) -> &DataPath {
-        &self.data_path
+        &self.cores[0].data_path
+    }
+
+    /// Get the engine running instructions on the boot core, with its
+    /// instructions in flight.
+    pub fn cpu(&self) -> &dyn Cpu {
+        &*self.cores[0].cpu
+    }
+
+    pub fn data_path_mut(&mut self) -> &mut DataPath {
+        &mut self.cores[0].data_path
     }
 
     pub fn phase(&self) -> Phase {
@@ -0,0 +1,3011 @@
+// Test code for the emulated RISC II system.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "system.rs"]
+mod test {
+    extern crate toml;
+
+    use super::super::*;
+    use anomaly::{AnomalyDetector, AnomalyKind};
+    use asm::assemble;
+    use audit::Audit;
+    use call_stack::CallStack;
+    use clock::Phase;
+    use commit::RetiredInstruction;
+    use config::{
+        ClockMode, Endian, Engine, IsaExtension, MemoryFill, PipelineDebug, PowerOnState,
+        ReportFormat, StateFormat, Syntax, TraceFormat,
+    };
+    use console::{Console, CONSOLE_STATUS, CONSOLE_TX, CONSOLE_TX_READY};
+    use coprocessor::{Completion, ConditionCodes, Coprocessor, Status};
+    use cosim::Cosim;
+    use cpu::{
+        Trap, CARRY_LOC, INTERRUPT_LOC, OVERFLOW_LOC, RESET_VECTOR, RETURN_ADDRESS_REG, SYSTEM_LOC,
+        TRAP_VECTOR_BASE, TRAP_VECTOR_SPACING, ZERO_LOC,
+    };
+    use data_path::Route;
+    use data_path_view::{DataPathView, Latch};
+    use device::{Device, Reset};
+    use doorbell::{DOORBELL_BASE, DOORBELL_PENDING, DOORBELL_RING};
+    use expr::Expr;
+    #[cfg(feature = "fpu")]
+    use fpu::{
+        fpu_command, FPU_BASE, FPU_BUSY, FPU_CMP, FPU_COMMAND, FPU_DIV, FPU_DIVIDE_BY_ZERO,
+        FPU_GREATER, FPU_LESS, FPU_REGISTERS, FPU_STATUS,
+    };
+    use front_panel::{FrontPanel, PSW_LED_NAMES};
+    use gamepad::{
+        GAMEPAD_AXES, GAMEPAD_BUTTONS, GAMEPAD_CHANGED, GAMEPAD_CONNECTED, GAMEPAD_CONTROL,
+        GAMEPAD_INTERRUPT_ENABLE, GAMEPAD_STATUS,
+    };
+    use hazard::{HazardDetector, HazardKind};
+    use hex_view::HexView;
+    use host::{HOST_BASE, HOST_EXIT, HOST_REALTIME, HOST_SNAPSHOT, HOST_STATUS, HOST_YIELD};
+    use instruction::*;
+    use keyboard::{
+        KEYBOARD_BASE, KEYBOARD_CONTROL, KEYBOARD_DATA, KEYBOARD_OVERFLOW, KEYBOARD_READY,
+        KEYBOARD_RELEASED, KEYBOARD_STATUS,
+    };
+    use loader::{Format, Image};
+    use memory::Memory;
+    use mmu::{
+        Mmu, Segment, MMU_BASE, MMU_CPU_ID, MMU_ENABLE, MMU_TRAP_VECTORS, MMU_USER_BASE,
+        MMU_USER_LIMIT,
+    };
+    use muldiv::{MulDiv, DIVU_OPCODE, DIV_OPCODE, MULHU_OPCODE, MUL_OPCODE, REM_OPCODE};
+    use pipeline_debug::PipelineLog;
+    use r2d2::{SnapshotKind, SnapshotSession, SnapshotTrigger};
+    use region::{Permissions, Region};
+    use replay::{parse_inputs, Input, InputRecord};
+    use rtc::{RTC_BASE, RTC_CYCLES_HIGH, RTC_CYCLES_LOW, RTC_NANOSECONDS, RTC_SECONDS};
+    use snapshot_diff::SnapshotDiff;
+    use state::{export_state, MemoryRange};
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+    use system::{RunLimit, RunState};
+    use trace::{Tracer, MAGIC, RECORD_SIZE};
+    use util::{get_unix_timestamp, Result};
+
+    type I = Instruction;
+    type SS = ShortSource;
+    type SI = ShortInstruction;
+    type SC = ShortConditional;
+
+    /// An instruction that does nothing, used to separate dependent
+    /// instructions in the pipeline.
+    const NOP: I = I::Add(SI {
+        scc: false,
+        dest: 0,
+        rs1: 0,
+        short_source: SS::Reg(0),
+    });
+
+    /// Create a system running `engine` with `program` loaded at address 0.
+    fn make_system(program: &[I], engine: Engine) -> Result<System> {
+        make_system_with(&Config::new()?, program, engine)
+    }
+
+    /// Create a system from `config` running `engine` with `program` loaded
+    /// at address 0.
+    fn make_system_with(config: &Config, program: &[I], engine: Engine) -> Result<System> {
+        let mut system = System::new(config)?;
+        system.set_engine(engine);
+        for (i, instruction) in program.iter().enumerate() {
+            system
+                .get_mem_ref()
+                .set_word(i as u32 * 4, instruction.encode())?;
+        }
+        Ok(system)
+    }
+
+    /// Attach a retire hook to `system` that records every retirement.
+    fn record_retirements(system: &mut System) -> Rc<RefCell<Vec<RetiredInstruction>>> {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let hook_log = log.clone();
+        system.on_retire(move |r| hook_log.borrow_mut().push(r.clone()));
+        log
+    }
+
+    #[test]
+    fn retire_hook_sees_result() -> Result<()> {
+        let add = I::Add(SI::new(true, 16, 0, SS::Imm13(5)));
+        let mut system = make_system(&[add], Engine::Fast)?;
+        let log = record_retirements(&mut system);
+        system.step()?;
+
+        let log = log.borrow();
+        assert_eq!(log.len(), 1);
+        let r = &log[0];
+        assert_eq!(r.instruction, add);
+        assert_eq_hex!(r.pc, 0);
+        assert_eq!(r.dest, Some((16, 5)));
+        assert!(!r.psw_after.get_cc_zero());
+        assert_eq!(r.branch, None);
+        assert_eq!(r.trap, None);
+        assert_eq!(r.cycle, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn retire_hook_sees_trap() -> Result<()> {
+        let mut system = make_system(
+            &[
+                I::Add(SI::new(false, 16, 0, SS::Imm13(0))),
+                I::GetLPC(SI::new(false, 17, 0, SS::Reg(0))),
+            ],
+            Engine::Fast,
+        )?;
+        let log = record_retirements(&mut system);
+        system.step()?;
+        system.step()?;
+
+        let log = log.borrow();
+        assert_eq!(log.len(), 2);
+        let r = &log[1];
+        assert_eq_hex!(r.pc, 4);
+        assert_eq!(r.dest, None);
+        assert_eq!(r.trap, Some(Trap::PrivilegeViolation));
+        assert!(!r.psw_before.get_system_mode());
+        assert!(r.psw_after.get_system_mode());
+        assert_eq!(r.cycle, 2);
+        Ok(())
+    }
+
+    /// Run `system`'s pipeline for `cycles` full clock cycles.
+    fn run_cycles(system: &mut System, cycles: usize) -> Result<()> {
+        for _ in 0..cycles * 4 {
+            system.tick()?;
+        }
+        Ok(())
+    }
+
+    /// Run `program` on the pipeline for `cycles` and on the functional
+    /// engine for `steps`, and check that both end with the same registers
+    /// and PSW.
+    fn run_both(program: &[I], cycles: usize, steps: usize) -> Result<System> {
+        run_both_from(program, None, cycles, steps)
+    }
+
+    /// Like `run_both`, starting both engines with `psw` if it is given.
+    fn run_both_from(
+        program: &[I],
+        psw: Option<u16>,
+        cycles: usize,
+        steps: usize,
+    ) -> Result<System> {
+        let mut pipeline = make_system(program, Engine::Cycle)?;
+        let mut functional = make_system(program, Engine::Fast)?;
+        if let Some(psw) = psw {
+            pipeline.data_path_mut().set_psw(psw);
+            functional.data_path_mut().set_psw(psw);
+        }
+        run_cycles(&mut pipeline, cycles)?;
+        for _ in 0..steps {
+            functional.step()?;
+        }
+        assert!(
+            pipeline.data_path().copy_register_file()
+                == functional.data_path().copy_register_file()
+        );
+        assert_eq_hex!(
+            pipeline.data_path().get_psw().get(),
+            functional.data_path().get_psw().get()
+        );
+        Ok(pipeline)
+    }
+
+    #[test]
+    fn pipeline_alu() -> Result<()> {
+        let system = run_both(
+            &[
+                I::Add(SI::new(false, 16, 0, SS::Imm13(5))),
+                I::Add(SI::new(false, 17, 0, SS::Imm13(7))),
+                NOP,
+                I::Add(SI::new(false, 18, 16, SS::Reg(17))),
+            ],
+            6,
+            4,
+        )?;
+        let dp = system.data_path();
+        assert_eq!(dp.register_file().read(18, 0), 12);
+        assert_eq_hex!(dp.get_pc(), 16);
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_load_store() -> Result<()> {
+        let mut system = run_both(
+            &[
+                I::Add(SI::new(false, 16, 0, SS::Imm13(0x123))),
+                NOP,
+                I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x30))),
+                I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x30))),
+                NOP,
+                I::Add(SI::new(false, 18, 17, SS::Imm13(1))),
+            ],
+            10,
+            6,
+        )?;
+        {
+            let dp = system.data_path();
+            assert_eq_hex!(dp.register_file().read(17, 0), 0x123);
+            assert_eq_hex!(dp.register_file().read(18, 0), 0x124);
+        }
+        assert_eq_hex!(system.get_mem_ref().get_word(0x30)?, 0x123);
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_sub_word_load_store() -> Result<()> {
+        let mut system = run_both(
+            &[
+                I::Sub(SI::new(false, 16, 0, SS::Imm13(0x7f))),
+                I::Add(SI::new(false, 17, 0, SS::Imm13(0x123))),
+                I::Stxb(SI::new(false, 16, 0, SS::Imm13(0x31))),
+                I::Stxh(SI::new(false, 17, 0, SS::Imm13(0x36))),
+                I::Stxh(SI::new(false, 16, 0, SS::Imm13(0x34))),
+                I::Ldxw(SI::new(false, 18, 0, SS::Imm13(0x30))),
+                I::Ldxbs(SI::new(false, 19, 0, SS::Imm13(0x31))),
+                I::Ldxbu(SI::new(false, 20, 0, SS::Imm13(0x31))),
+                I::Ldxhs(SI::new(false, 21, 0, SS::Imm13(0x34))),
+                I::Ldxhu(SI::new(false, 22, 0, SS::Imm13(0x36))),
+                I::Ldxbu(SI::new(false, 23, 0, SS::Imm13(0x37))),
+            ],
+            22,
+            11,
+        )?;
+        {
+            let regs = system.data_path().register_file();
+            assert_eq_hex!(regs.read(18, 0), 0x00810000);
+            assert_eq_hex!(regs.read(19, 0), 0xffffff81);
+            assert_eq_hex!(regs.read(20, 0), 0x81);
+            assert_eq_hex!(regs.read(21, 0), 0xffffff81);
+            assert_eq_hex!(regs.read(22, 0), 0x123);
+            assert_eq_hex!(regs.read(23, 0), 0x23);
+        }
+        assert_eq_hex!(system.get_mem_ref().get_word(0x34)?, 0xff810123);
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_pc_relative_load_store() -> Result<()> {
+        let mut system = run_both(
+            &[
+                I::Add(SI::new(false, 16, 0, SS::Imm13(0x123))),
+                NOP,
+                I::Strw(LongInstruction::new(false, 16, 0x28)),
+                I::Ldrw(LongInstruction::new(false, 17, 0x24)),
+                I::Strh(LongInstruction::new(false, 16, 0x26)),
+                I::Ldrhu(LongInstruction::new(false, 18, 0x22)),
+            ],
+            12,
+            6,
+        )?;
+        {
+            let regs = system.data_path().register_file();
+            assert_eq_hex!(regs.read(17, 0), 0x123);
+            assert_eq_hex!(regs.read(18, 0), 0x123);
+        }
+        assert_eq_hex!(system.get_mem_ref().get_word(0x30)?, 0x123);
+        assert_eq_hex!(system.get_mem_ref().get_hword(0x36)?, 0x123);
+        Ok(())
+    }
+
+    #[test]
+    fn little_endian_memory() -> Result<()> {
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 16, 0x11223344 >> 13)),
+            I::Or(SI::new(false, 16, 16, SS::Imm13(0x11223344 & 0x1fff))),
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x30))),
+            I::Ldxbu(SI::new(false, 17, 0, SS::Imm13(0x30))),
+            I::Ldxhu(SI::new(false, 18, 0, SS::Imm13(0x32))),
+            // Lets the pipeline write the last load back.
+            NOP,
+        ];
+        let mut config = Config::new()?;
+        config.set_endian(Endian::Little);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = System::new(&config)?;
+            system.set_engine(*engine);
+            for (i, instruction) in program.iter().enumerate() {
+                system
+                    .get_mem_ref()
+                    .set_word(i as u32 * 4, instruction.encode())?;
+            }
+            // Instructions are fetched in the same byte order.
+            assert_eq!(
+                system.memory().get_bytes(0, 1)?[0],
+                program[0].encode() as u8
+            );
+            for _ in 0..program.len() {
+                system.step()?;
+            }
+            assert_eq!(
+                system.memory().get_bytes(0x30, 4)?,
+                &[0x44, 0x33, 0x22, 0x11]
+            );
+            let regs = system.data_path().register_file();
+            assert_eq_hex!(regs.read(17, 0), 0x44);
+            assert_eq_hex!(regs.read(18, 0), 0x1122);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_forwards_results() -> Result<()> {
+        // Every instruction reads the result of the one before it.
+        let mut system = run_both(
+            &[
+                I::Add(SI::new(false, 16, 0, SS::Imm13(5))),
+                I::Add(SI::new(false, 17, 16, SS::Reg(16))),
+                I::Sub(SI::new(false, 18, 17, SS::Reg(16))),
+                I::Stxw(SI::new(false, 18, 0, SS::Imm13(0x30))),
+                I::Ldxw(SI::new(false, 19, 0, SS::Imm13(0x30))),
+                I::Add(SI::new(false, 20, 19, SS::Reg(17))),
+            ],
+            10,
+            6,
+        )?;
+        {
+            let regs = system.data_path().register_file();
+            assert_eq!(regs.read(17, 0), 10);
+            assert_eq!(regs.read(18, 0), 5);
+            assert_eq!(regs.read(19, 0), 5);
+            assert_eq!(regs.read(20, 0), 15);
+        }
+        assert_eq!(system.get_mem_ref().get_word(0x30)?, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn data_path_shows_stage_latches() -> Result<()> {
+        let add = I::Add(SI::new(true, 16, 0, SS::Imm13(5)));
+        let sub = I::Sub(SI::new(false, 17, 16, SS::Reg(18)));
+        let mut system = make_system(&[add, sub], Engine::Cycle)?;
+        run_cycles(&mut system, 2)?;
+        let dp = system.data_path();
+        assert_eq!(dp.execute_op() as u32, add.encode() >> 25);
+        assert_eq!(dp.decode_op() as u32, sub.encode() >> 25);
+        assert_eq!(dp.execute_rd(), 16);
+        assert_eq!(dp.decode_rd(), 17);
+        assert_eq!(dp.execute_imm(), 5);
+        assert_eq!(dp.execute_flags(), (true, true));
+        assert_eq!(dp.decode_flags(), (false, false));
+        assert_eq!(dp.decode_source_registers(), (16, 18));
+        assert_eq_hex!(dp.dimm(), sub.encode());
+        Ok(())
+    }
+
+    #[test]
+    fn data_path_records_routes_per_phase() -> Result<()> {
+        let add = I::Add(SI::new(false, 16, 0, SS::Imm13(5)));
+        let sub = I::Sub(SI::new(false, 17, 16, SS::Reg(16)));
+        let mut system = make_system(&[add, sub, NOP], Engine::Cycle)?;
+        let mut routes = Vec::new();
+        for _ in 0..12 {
+            system.tick()?;
+            routes.push(system.data_path().routes().to_vec());
+        }
+        // The first cycle only fetches and decodes the add, nothing
+        // executes to move the PCs.
+        assert!(routes[1].is_empty());
+        assert_eq!(routes[2], vec![Route::BusExt]);
+        assert!(routes[3].is_empty());
+        // The add reads r0 on busA and busB, then its immediate on busB.
+        assert_eq!(routes[4], vec![Route::BusA, Route::BusB]);
+        assert_eq!(routes[5], vec![Route::Imm, Route::BusB]);
+        assert_eq!(routes[6], vec![Route::Alu, Route::BusExt]);
+        assert_eq!(routes[7], vec![Route::BusOut]);
+        // The sub has no immediate, and the add's result goes over busD
+        // while the sub computes.
+        assert!(routes[9].is_empty());
+        assert_eq!(routes[10], vec![Route::BusD, Route::Alu, Route::BusExt]);
+        assert!(system.data_path().route_fired(Route::BusOut));
+        assert!(!system.data_path().route_fired(Route::Shifter));
+
+        // The fast engine runs no phases.
+        let mut system = make_system(&[add, sub, NOP], Engine::Fast)?;
+        system.step()?;
+        assert!(system.data_path().routes().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_delayed_branch() -> Result<()> {
+        let system = run_both(
+            &[
+                I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(16))),
+                I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+                I::Add(SI::new(false, 17, 0, SS::Imm13(2))),
+                I::Add(SI::new(false, 18, 0, SS::Imm13(3))),
+                I::Add(SI::new(false, 19, 0, SS::Imm13(4))),
+            ],
+            5,
+            3,
+        )?;
+        let regs = system.data_path().register_file();
+        // The delay slot executes, the skipped instructions do not.
+        assert_eq!(regs.read(16, 0), 1);
+        assert_eq!(regs.read(17, 0), 0);
+        assert_eq!(regs.read(18, 0), 0);
+        assert_eq!(regs.read(19, 0), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_without_delay_slots() -> Result<()> {
+        let program = [
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(16))),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+            I::Add(SI::new(false, 17, 0, SS::Imm13(2))),
+            I::Add(SI::new(false, 18, 0, SS::Imm13(3))),
+            I::Add(SI::new(false, 19, 0, SS::Imm13(4))),
+        ];
+        let mut pipeline = make_system(&program, Engine::Cycle)?;
+        pipeline.set_delay_slots(false);
+        run_cycles(&mut pipeline, 5)?;
+        let mut functional = make_system(&program, Engine::Fast)?;
+        functional.set_delay_slots(false);
+        for _ in 0..2 {
+            functional.step()?;
+        }
+        assert!(
+            pipeline.data_path().copy_register_file()
+                == functional.data_path().copy_register_file()
+        );
+        // The target comes right after the jump, the bubble behind it costs
+        // the cycle engine a cycle.
+        let regs = pipeline.data_path().register_file();
+        assert_eq!(regs.read(16, 0), 0);
+        assert_eq!(regs.read(19, 0), 4);
+        assert_eq_hex!(pipeline.data_path().get_pc(), 20);
+
+        // Delay slots stay off across a reset.
+        pipeline.reset(Reset::Cold)?;
+        assert!(!pipeline.data_path().has_delay_slots());
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_relative_offsets_sign_extend() -> Result<()> {
+        let system = run_both(
+            &[
+                I::Add(SI::new(false, 16, 0, SS::Imm13(3))),
+                NOP,
+                // Count r16 down to 0, branching back 8 bytes.
+                I::Sub(SI::new(true, 16, 16, SS::Imm13(1))),
+                NOP,
+                I::Jmpr(LongConditional::new(false, Conditional::Ne, 0x7fff8)),
+                NOP,
+                // Load the first instruction, 24 bytes back.
+                I::Ldrw(LongInstruction::new(false, 17, 0x7ffe8)),
+            ],
+            18,
+            15,
+        )?;
+        let regs = system.data_path().register_file();
+        assert_eq!(regs.read(16, 0), 0);
+        assert_eq_hex!(
+            regs.read(17, 0),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(3))).encode()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn privileged_reads_set_cc_like_functional() -> Result<()> {
+        // V and C start set, reading LSTPC or the TCR with SCC clears them.
+        let system = run_both_from(
+            &[
+                I::GetLPC(SI::new(true, 16, 0, SS::Reg(0))),
+                NOP,
+                I::GetTCR(SI::new(true, 17, 0, SS::Reg(0))),
+                NOP,
+            ],
+            Some(SYSTEM_LOC | OVERFLOW_LOC | CARRY_LOC),
+            5,
+            3,
+        )?;
+        let psw = system.data_path().get_psw();
+        assert!(psw.get_cc_zero());
+        assert!(!psw.get_cc_overflow());
+        assert!(!psw.get_cc_carry());
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_trap_flushes() -> Result<()> {
+        let mut system = make_system(
+            &[
+                I::GetLPC(SI::new(false, 16, 0, SS::Reg(0))),
+                I::Add(SI::new(false, 17, 0, SS::Imm13(1))),
+            ],
+            Engine::Cycle,
+        )?;
+        run_cycles(&mut system, 4)?;
+        let dp = system.data_path();
+        assert_eq_hex!(dp.get_pc(), Trap::PrivilegeViolation.vector());
+        assert!(dp.get_psw().get_system_mode());
+        assert_eq!(dp.register_file().read(16, 0), 0);
+        assert_eq!(dp.register_file().read(17, 0), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_retires_like_functional() -> Result<()> {
+        let program = [
+            I::Add(SI::new(true, 16, 0, SS::Imm13(3))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(16))),
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x30))),
+            NOP,
+            I::GetLPC(SI::new(false, 17, 0, SS::Reg(0))),
+        ];
+        let mut logs = Vec::new();
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            let log = record_retirements(&mut system);
+            for _ in 0..4 {
+                system.step()?;
+            }
+            let log = log.borrow();
+            assert_eq!(log.len(), 4);
+            logs.push(
+                log.iter()
+                    .map(|r| (r.instruction, r.pc, r.dest, r.branch, r.trap, r.psw_after))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert!(logs[0] == logs[1]);
+        assert_eq!(logs[1][1].3, Some(16));
+        assert_eq!(logs[1][3].4, Some(Trap::PrivilegeViolation));
+        Ok(())
+    }
+
+    /// Device that records the resets it sees.
+    struct ResetRecorder(Rc<RefCell<Vec<Reset>>>);
+
+    impl Device for ResetRecorder {
+        fn name(&self) -> &str {
+            "reset recorder"
+        }
+
+        fn reset(&mut self, kind: Reset) {
+            self.0.borrow_mut().push(kind);
+        }
+    }
+
+    /// Run a short program that writes r16 and memory, then reset with `kind`.
+    fn run_then_reset(kind: Reset) -> Result<(System, Rc<RefCell<Vec<Reset>>>)> {
+        let mut system = make_system(
+            &[
+                I::Add(SI::new(false, 16, 0, SS::Imm13(0x55))),
+                NOP,
+                I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x30))),
+            ],
+            Engine::Fast,
+        )?;
+        let resets = Rc::new(RefCell::new(Vec::new()));
+        system.attach(Box::new(ResetRecorder(resets.clone())));
+        for _ in 0..3 {
+            system.step()?;
+        }
+        system.reset(kind)?;
+        Ok((system, resets))
+    }
+
+    #[test]
+    fn warm_reset_keeps_memory() -> Result<()> {
+        let (mut system, resets) = run_then_reset(Reset::Warm)?;
+        {
+            let dp = system.data_path();
+            assert_eq_hex!(dp.get_pc(), RESET_VECTOR);
+            assert_eq_hex!(dp.get_psw().get(), 0);
+            assert_eq_hex!(dp.register_file().read(16, 0), 0x55);
+        }
+        assert_eq_hex!(system.get_mem_ref().get_word(0x30)?, 0x55);
+        assert_eq!(*resets.borrow(), vec![Reset::Warm]);
+        Ok(())
+    }
+
+    #[test]
+    fn cold_reset_clears_memory() -> Result<()> {
+        let (mut system, resets) = run_then_reset(Reset::Cold)?;
+        assert_eq_hex!(system.data_path().get_pc(), RESET_VECTOR);
+        assert_eq_hex!(system.data_path().register_file().read(16, 0), 0);
+        assert_eq_hex!(system.get_mem_ref().get_word(0x30)?, 0);
+        assert_eq!(*resets.borrow(), vec![Reset::Cold]);
+        Ok(())
+    }
+
+    #[test]
+    fn run_until_halt() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+            NOP,
+            I::Add(SI::new(false, 16, 16, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(12))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            assert_eq!(system.run_until_halt()?, 4);
+            assert_eq!(system.data_path().register_file().read(16, 0), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_limits_stop_loops() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 16, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            system.set_run_limits(Some(100), None);
+            system.run_until_halt()?;
+            assert_eq!(system.limit_hit(), Some(RunLimit::Cycles(100)));
+            assert_eq!(system.clock().count(), 100);
+            assert_eq!(system.run_state(), RunState::Paused);
+            assert!(system.data_path().pc() < 12);
+
+            system.set_run_limits(None, Some(Duration::from_millis(10)));
+            system.resume();
+            system.run_until_halt()?;
+            assert_eq!(
+                system.limit_hit(),
+                Some(RunLimit::Time(Duration::from_millis(10)))
+            );
+            system.reset(Reset::Cold)?;
+            assert_eq!(system.limit_hit(), None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn hazards_follow_the_retired_stream() -> Result<()> {
+        let program = [
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x30))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(16))),
+            I::Add(SI::new(false, 18, 17, SS::Imm13(1))),
+            // Skipped, the load is not before it in the stream.
+            I::Add(SI::new(false, 18, 17, SS::Imm13(1))),
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x30))),
+            I::Add(SI::new(false, 18, 17, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(16))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            system.set_hazard_detector(Some(HazardDetector::new()));
+            system.run_for(60)?;
+            let found: Vec<(u32, HazardKind)> = system
+                .hazards()
+                .unwrap()
+                .found()
+                .iter()
+                .map(|h| (h.pc, h.kind))
+                .collect();
+            assert_eq!(found, vec![(20, HazardKind::LoadUse(17))]);
+        }
+        Ok(())
+    }
+
+    /// Writer that keeps everything written to it for inspection.
+    #[derive(Clone)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn console_prints_stored_bytes() -> Result<()> {
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, CONSOLE_TX >> 13)),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(b'h' as u32))),
+            NOP,
+            I::Stxb(SI::new(false, 16, 17, SS::Imm13(0))),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(b'i' as u32))),
+            NOP,
+            I::Stxb(SI::new(false, 16, 17, SS::Imm13(0))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(28))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            let out = SharedBuf(Rc::new(RefCell::new(Vec::new())));
+            system.attach(Box::new(Console::new(Box::new(out.clone()))));
+            system.run_until_halt()?;
+            assert_eq!(*out.0.borrow(), b"hi".to_vec());
+            assert_eq_hex!(
+                system.get_mem_ref().get_word(CONSOLE_STATUS)?,
+                CONSOLE_TX_READY
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_log_levels() -> Result<()> {
+        let program = [I::Add(SI::new(false, 16, 0, SS::Imm13(5))), NOP, NOP];
+        let mut lines = Vec::new();
+        for level in [PipelineDebug::Cycle, PipelineDebug::Phase].iter() {
+            let mut system = make_system(&program, Engine::Cycle)?;
+            let out = SharedBuf(Rc::new(RefCell::new(Vec::new())));
+            system.set_pipeline_log(Some(PipelineLog::new(*level, Box::new(out.clone()))))?;
+            run_cycles(&mut system, 3)?;
+            let log = String::from_utf8(out.0.borrow().clone()).unwrap();
+            lines.push(log.lines().map(|l| l.to_string()).collect::<Vec<_>>());
+        }
+
+        // A line per cycle, showing the add moving down the pipeline.
+        let (cycles, phases) = (&lines[0], &lines[1]);
+        assert_eq!(cycles.len(), 3);
+        assert!(cycles[0].contains("D add r16, r0, 0x5 | E - | C -"));
+        assert!(cycles[1].contains("E add r16, r0, 0x5"));
+        // The same lines and the changes in between.
+        assert!(phases.len() > cycles.len());
+        assert!(cycles.iter().all(|l| phases.contains(l)));
+        assert!(phases.iter().any(|l| l.contains(".3 core 0: ")));
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_history_shows_suspension() -> Result<()> {
+        let program = [
+            I::Ldxw(SI::new(false, 16, 0, SS::Imm13(0))),
+            I::Add(SI::new(false, 17, 0, SS::Imm13(5))),
+            NOP,
+            NOP,
+        ];
+        let mut system = make_system(&program, Engine::Cycle)?;
+        assert!(system.pipeline_history(0).is_none());
+        system.set_pipeline_history(Some(8));
+        run_cycles(&mut system, 4)?;
+        let history = system.pipeline_history(0).unwrap();
+        // Only the last 8 of 16 phases are kept, oldest first.
+        let records = history.records();
+        assert_eq!(records.len(), 8);
+        assert!(records
+            .iter()
+            .zip(records.iter().skip(1))
+            .all(|(a, b)| { (a.cycle, a.phase.clone() as u8) < (b.cycle, b.phase.clone() as u8) }));
+        // While the load uses the bus nothing is fetched and the add waits in
+        // decode.
+        let lines: Vec<String> = records.iter().map(|r| r.describe(Syntax::Modern)).collect();
+        let suspended: Vec<_> = records.iter().filter(|r| r.stages.suspended).collect();
+        assert!(!suspended.is_empty());
+        assert!(suspended.iter().all(|r| r.stages.fetch.is_none()));
+        assert!(lines
+            .iter()
+            .any(|l| l.contains("D add r17, r0, 0x5") && l.ends_with(" suspended")));
+
+        // The fast engine has nothing in flight.
+        let mut system = make_system(&program, Engine::Fast)?;
+        system.set_pipeline_history(Some(8));
+        run_cycles(&mut system, 1)?;
+        let records = system.pipeline_history(0).unwrap().records();
+        assert_eq!(records.len(), 4);
+        assert!(records
+            .iter()
+            .all(|r| r.stages.decode.is_none() && r.stages.fetch.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn keyboard_queues_key_events() -> Result<()> {
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, KEYBOARD_BASE >> 13)),
+            NOP,
+            I::Ldxw(SI::new(false, 18, 17, SS::Imm13(KEYBOARD_STATUS & 0x1fff))),
+            I::Ldxw(SI::new(false, 19, 17, SS::Imm13(KEYBOARD_DATA & 0x1fff))),
+            I::Ldxw(SI::new(false, 20, 17, SS::Imm13(KEYBOARD_DATA & 0x1fff))),
+            I::Ldxw(SI::new(false, 21, 17, SS::Imm13(KEYBOARD_STATUS & 0x1fff))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(24))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            system.keyboard().key_down(4);
+            system.keyboard().key_up(4);
+            system.run_until_halt()?;
+            let regs = system.data_path().register_file();
+            assert_eq_hex!(regs.read(18, 0), KEYBOARD_READY);
+            assert_eq_hex!(regs.read(19, 0), 4);
+            assert_eq_hex!(regs.read(20, 0), 4 | KEYBOARD_RELEASED);
+            assert_eq_hex!(regs.read(21, 0), 0);
+            assert_eq!(system.keyboard().pending(), 0);
+        }
+
+        // Events that do not fit are dropped and reported once.
+        let mut system = make_system(&[], Engine::Fast)?;
+        for sc in 0..20 {
+            system.keyboard().key_down(sc);
+        }
+        assert_eq!(system.keyboard().pending(), 16);
+        let mem = system.get_mem_ref();
+        assert_eq_hex!(
+            mem.get_word(KEYBOARD_STATUS)?,
+            KEYBOARD_READY | KEYBOARD_OVERFLOW
+        );
+        assert_eq_hex!(mem.get_word(KEYBOARD_STATUS)?, KEYBOARD_READY);
+        Ok(())
+    }
+
+    #[test]
+    fn gamepad_reports_buttons_and_axes() -> Result<()> {
+        let mut system = make_system(&[], Engine::Fast)?;
+        assert_eq_hex!(system.get_mem_ref().get_word(GAMEPAD_STATUS)?, 0);
+        let gamepad = system.gamepad().clone();
+        gamepad.set_connected(true);
+        gamepad.set_button(0, true);
+        gamepad.set_button(11, true);
+        gamepad.set_button(0, false);
+        gamepad.set_button(40, true);
+        gamepad.set_axis(1, -32768);
+        gamepad.set_axis(5, 32767);
+        gamepad.set_axis(9, 1);
+
+        let mem = system.get_mem_ref();
+        assert_eq_hex!(mem.get_word(GAMEPAD_BUTTONS)?, 1 << 11);
+        assert_eq_hex!(mem.get_word(GAMEPAD_AXES)?, 0);
+        assert_eq_hex!(mem.get_word(GAMEPAD_AXES + 4)?, 0xffff8000);
+        assert_eq_hex!(mem.get_word(GAMEPAD_AXES + 20)?, 0x7fff);
+        assert_eq_hex!(
+            mem.get_word(GAMEPAD_STATUS)?,
+            GAMEPAD_CONNECTED | GAMEPAD_CHANGED
+        );
+        assert_eq_hex!(mem.get_word(GAMEPAD_STATUS)?, GAMEPAD_CONNECTED);
+
+        mem.set_word(GAMEPAD_CONTROL, GAMEPAD_INTERRUPT_ENABLE)?;
+        assert!(!mem.interrupt_pending());
+        gamepad.set_connected(false);
+        assert!(mem.interrupt_pending());
+        assert_eq_hex!(mem.get_word(GAMEPAD_BUTTONS)?, 0);
+        assert_eq_hex!(mem.get_word(GAMEPAD_AXES + 4)?, 0);
+        assert_eq_hex!(mem.get_word(GAMEPAD_STATUS)?, GAMEPAD_CHANGED);
+        assert!(!mem.interrupt_pending());
+        Ok(())
+    }
+
+    #[test]
+    fn host_reports_realtime_and_yields() -> Result<()> {
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, HOST_BASE >> 13)),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(100))),
+            NOP,
+            I::Ldxw(SI::new(false, 18, 17, SS::Imm13(HOST_STATUS & 0x1fff))),
+            I::Stxw(SI::new(false, 16, 17, SS::Imm13(HOST_YIELD & 0x1fff))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(20))),
+            NOP,
+        ];
+        // Turbo mode, yields are ignored.
+        let mut system = make_system(&program, Engine::Fast)?;
+        system.run_until_halt()?;
+        assert_eq!(system.data_path().register_file().read(18, 0), 0);
+        assert_eq!(system.host().yielded(), Duration::new(0, 0));
+
+        // Realtime.
+        let mut system = make_system(&program, Engine::Fast)?;
+        for _ in 0..6 {
+            system.step()?;
+        }
+        assert_eq!(
+            system.data_path().register_file().read(18, 0),
+            HOST_REALTIME
+        );
+        assert_eq!(system.host().yielded(), Duration::from_micros(100));
+        Ok(())
+    }
+
+    #[test]
+    fn rtc_tells_time_and_cycles() -> Result<()> {
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, RTC_BASE >> 13)),
+            NOP,
+            I::Ldxw(SI::new(false, 18, 17, SS::Imm13(RTC_SECONDS & 0x1fff))),
+            I::Ldxw(SI::new(false, 19, 17, SS::Imm13(RTC_NANOSECONDS & 0x1fff))),
+            I::Ldxw(SI::new(false, 20, 17, SS::Imm13(RTC_CYCLES_LOW & 0x1fff))),
+            I::Ldxw(SI::new(false, 21, 17, SS::Imm13(RTC_CYCLES_HIGH & 0x1fff))),
+        ];
+        let mut system = make_system(&program, Engine::Fast)?;
+        let before = get_unix_timestamp()?.as_secs() as u32;
+        for _ in 0..program.len() {
+            system.step()?;
+        }
+        let after = get_unix_timestamp()?.as_secs() as u32;
+        let regs = system.data_path().register_file();
+        let seconds = regs.read(18, 0);
+        assert!(before <= seconds && seconds <= after, "{}", seconds);
+        assert!(regs.read(19, 0) < 1_000_000_000);
+        let cycles = regs.read(20, 0);
+        assert!(
+            cycles > 0 && (cycles as u64) < system.clock().count(),
+            "{}",
+            cycles
+        );
+        assert_eq!(regs.read(21, 0), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn replayed_inputs_rerun_a_recording() -> Result<()> {
+        // Sum the scancodes and the seconds read, forever.
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, KEYBOARD_BASE >> 13)),
+            I::Ldhi(LongInstruction::new(false, 22, RTC_BASE >> 13)),
+            I::Ldxw(SI::new(false, 18, 17, SS::Imm13(KEYBOARD_DATA & 0x1fff))),
+            I::Add(SI::new(false, 19, 19, SS::Reg(18))),
+            I::Ldxw(SI::new(false, 20, 22, SS::Imm13(RTC_SECONDS & 0x1fff))),
+            I::Add(SI::new(false, 21, 21, SS::Reg(20))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(8))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut recorded = make_system(&program, *engine)?;
+            recorded.inputs().record();
+            recorded.run_for(10)?;
+            recorded.send_input(Input::KeyDown(30));
+            recorded.run_for(7)?;
+            recorded.send_input(Input::KeyUp(30));
+            recorded.send_input(Input::GamepadButton(2, true));
+            recorded.run_for(20)?;
+            let records = recorded.inputs().records();
+            assert!(records.iter().any(|r| r.input == Input::KeyDown(30)));
+            assert!(records.iter().any(|r| match r.input {
+                Input::Time(_) => true,
+                _ => false,
+            }));
+
+            // The log survives being written out and read back.
+            let text: String = records.iter().map(|r| format!("{}\n", r)).collect();
+            let mut replayed = make_system(&program, *engine)?;
+            replayed.inputs().replay(parse_inputs(&text)?);
+            // The host's inputs do not count while replaying.
+            replayed.send_input(Input::KeyDown(1));
+            replayed.run_for(37)?;
+            assert_eq!(replayed.inputs().diverged(), None);
+            assert!(replayed.inputs().records().is_empty());
+            let (a, b) = (
+                recorded.data_path().register_file(),
+                replayed.data_path().register_file(),
+            );
+            for reg in 16..23 {
+                assert_eq_hex!(a.read(reg, 0), b.read(reg, 0));
+            }
+            assert_eq!(a.read(19, 0), 30 + (30 | KEYBOARD_RELEASED));
+            assert_eq_hex!(replayed.get_mem_ref().get_word(GAMEPAD_BUTTONS)?, 1 << 2);
+
+            // Running past the recording reads times it does not have.
+            replayed.run_for(10)?;
+            assert!(replayed.inputs().diverged().is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn input_logs_are_checked() -> Result<()> {
+        let records = parse_inputs(
+            "
+# A comment.
+3 key_down 30
+3 gamepad_axis 1 -200
+9 time 1650000000 500
+",
+        )?;
+        assert_eq!(
+            records,
+            vec![
+                InputRecord {
+                    cycle: 3,
+                    input: Input::KeyDown(30),
+                },
+                InputRecord {
+                    cycle: 3,
+                    input: Input::GamepadAxis(1, -200),
+                },
+                InputRecord {
+                    cycle: 9,
+                    input: Input::Time(Duration::new(1650000000, 500)),
+                },
+            ]
+        );
+        assert_eq!(records[2].to_string(), "9 time 1650000000 500");
+        assert!(parse_inputs("3 key_down").is_err());
+        assert!(parse_inputs("3 gamepad_button 1 2").is_err());
+        assert!(parse_inputs("x key_up 4").is_err());
+        // Inputs go oldest first.
+        assert!(parse_inputs("4 key_up 4\n3 key_down 4").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn guest_halts_with_exit_code() -> Result<()> {
+        // Write the exit register, then spin.
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, HOST_BASE >> 13)),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(3))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 17, SS::Imm13(HOST_EXIT & 0x1fff))),
+            I::Add(SI::new(false, 18, 18, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(16))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            assert!(!system.halted());
+            system.run_until_halt()?;
+            assert!(system.halted());
+            assert_eq!(system.exit_code(), Some(3));
+            // Halting cannot be paused away.
+            system.toggle_pause();
+            assert_eq!(system.run_state(), RunState::Halted);
+            // A halted system does not run.
+            let spins = system.data_path().register_file().read(18, 0);
+            assert!(spins <= 1);
+            for _ in 0..8 {
+                system.tick()?;
+            }
+            assert_eq!(system.data_path().register_file().read(18, 0), spins);
+
+            system.reset(Reset::Warm)?;
+            assert!(!system.halted());
+            assert_eq!(system.exit_code(), None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn frames_pause_at_breakpoints() -> Result<()> {
+        let mut system = make_system(&[NOP; 16], Engine::Fast)?;
+        system.set_clock_mode(ClockMode::Unlimited);
+        assert!(system.set_breakpoint(0x10));
+        assert!(!system.set_breakpoint(0x10));
+        let log = record_retirements(&mut system);
+        assert_eq!(system.run_frame()?, 5);
+        assert!(system.is_paused());
+        assert_eq_hex!(log.borrow().last().unwrap().pc, 0x10);
+        // A paused system does not run.
+        assert_eq!(system.run_frame()?, 0);
+
+        assert!(system.clear_breakpoint(0x10));
+        assert!(system.breakpoints().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn run_control_pauses_runs_and_resets() -> Result<()> {
+        let program = [I::Add(SI::new(false, 16, 16, SS::Imm13(1))); 16];
+        let bytes: Vec<u8> = program
+            .iter()
+            .flat_map(|i| i.encode().to_be_bytes().to_vec())
+            .collect();
+        let image = Image::parse(&bytes, Format::Raw, 0)?;
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&[], *engine)?;
+            system.load_image(&image)?;
+            system.pause();
+            assert_eq!(system.run_state(), RunState::Paused);
+            // A paused system only runs when asked to.
+            for _ in 0..8 {
+                system.tick()?;
+            }
+            assert_eq!(system.clock().count(), 0);
+            system.run_for(2)?;
+            assert_eq!(system.clock().count(), 2);
+            assert!(system.phase() == Phase::One);
+            let r = system.run_until(0x10, 16)?.expect("0x10 did not retire");
+            assert_eq!(r.dest, Some((16, 5)));
+            assert_eq!(system.run_state(), RunState::Paused);
+            // Giving up once the cycles run out.
+            assert!(system.run_until(0x3c, 2)?.is_none());
+
+            // Resets load the program again.
+            system.get_mem_ref().set_word(0, 0)?;
+            system.reset(Reset::Cold)?;
+            assert_eq!(system.get_mem_ref().get_word(0)?, program[0].encode());
+            assert_eq!(system.data_path().register_file().read(16, 0), 0);
+            assert_eq!(system.run_state(), RunState::Paused);
+            system.toggle_pause();
+            assert_eq!(system.run_state(), RunState::Running);
+            let r = system.run_until_retired()?.expect("nothing retired");
+            assert_eq!(r.dest, Some((16, 1)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn keyboard_interrupts_between_instructions() -> Result<()> {
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, KEYBOARD_BASE >> 13)),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 17, SS::Imm13(KEYBOARD_CONTROL & 0x1fff))),
+            I::Add(SI::new(false, 18, 0, SS::Imm13(1))),
+            I::Add(SI::new(false, 19, 0, SS::Imm13(1))),
+            I::Add(SI::new(false, 20, 0, SS::Imm13(1))),
+            I::Add(SI::new(false, 21, 0, SS::Imm13(1))),
+        ];
+        let mut system = make_system(&program, Engine::Cycle)?;
+        system.data_path_mut().set_psw(INTERRUPT_LOC);
+        system.keyboard().key_down(30);
+        let mut cycles = 0;
+        while !system.data_path().get_psw().get_system_mode() {
+            assert!(cycles < 10, "interrupt never taken");
+            run_cycles(&mut system, 1)?;
+            cycles += 1;
+        }
+        let dp = system.data_path();
+        assert!(!dp.get_psw().get_interrupt_enabled());
+        let resume = dp.get_last_pc();
+        assert!(resume > 12 && resume <= 28, "resumes at {:x}", resume);
+        // Everything before the interrupted instruction finished, nothing
+        // after it started.
+        for (i, reg) in (18..22).enumerate() {
+            let expected = if 16 + i as u32 * 4 < resume { 1 } else { 0 };
+            assert_eq!(dp.register_file().read(reg, 0), expected, "r{}", reg);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn interrupt_handler_returns_to_interrupted_code() -> Result<()> {
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 1, KEYBOARD_BASE >> 13)),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 1, SS::Imm13(KEYBOARD_CONTROL & 0x1fff))),
+            I::Add(SI::new(false, 18, 0, SS::Imm13(1))),
+            I::Add(SI::new(false, 19, 0, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(24))),
+            NOP,
+        ];
+        // Take a window, read the key to acknowledge it, note the PSW and
+        // return.
+        let handler = [
+            I::Calli(SI::new(false, 16, 0, SS::Reg(0))),
+            I::Ldxw(SI::new(false, 17, 1, SS::SImm13(KEYBOARD_DATA & 0x1fff))),
+            I::GetPSW(SI::new(false, 18, 0, SS::Reg(0))),
+            I::Reti(SC::new(false, Conditional::Alw, 16, SS::Reg(0))),
+            NOP,
+        ];
+        let mut config = Config::new()?;
+        config.set_mem_size(0x800);
+        config.set_trap_vectors(0x400);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = System::new(&config)?;
+            system.set_engine(*engine);
+            let vector = system
+                .data_path()
+                .get_mmu()
+                .get_trap_vectors()
+                .get(Trap::Interrupt);
+            for (i, instruction) in program.iter().enumerate() {
+                system
+                    .get_mem_ref()
+                    .set_word(i as u32 * 4, instruction.encode())?;
+            }
+            for (i, instruction) in handler.iter().enumerate() {
+                system
+                    .get_mem_ref()
+                    .set_word(vector + i as u32 * 4, instruction.encode())?;
+            }
+            system.data_path_mut().set_psw(INTERRUPT_LOC);
+            system.keyboard().key_down(30);
+            run_cycles(&mut system, 80)?;
+
+            // Back in user mode, in the original window, with interrupts on
+            // and the rest of the program run.
+            let dp = system.data_path();
+            let psw = dp.get_psw();
+            assert!(!psw.get_system_mode(), "{:?}", engine);
+            assert!(psw.get_interrupt_enabled(), "{:?}", engine);
+            assert_eq!(psw.get_cwp(), 0, "{:?}", engine);
+            assert_eq!(dp.register_file().read(18, 0), 1, "{:?}", engine);
+            assert_eq!(dp.register_file().read(19, 0), 1, "{:?}", engine);
+
+            // The handler ran in the window below, in system mode with
+            // interrupts off, and got the key and the address to resume at.
+            let window = dp.register_file().windows() - 1;
+            let resume = dp.register_file().read(16, window);
+            assert!(resume > 12 && resume <= 28, "resumes at {:x}", resume);
+            assert_eq!(dp.register_file().read(17, window), 30, "{:?}", engine);
+            let handler_psw = dp.register_file().read(18, window) as u16;
+            assert_eq!(handler_psw & SYSTEM_LOC, SYSTEM_LOC, "{:?}", engine);
+            assert_eq!(handler_psw & INTERRUPT_LOC, 0, "{:?}", engine);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn timer_switches_tasks() -> Result<()> {
+        let program = assemble(include_str!("../examples/multitask.s"), 0xc0)?;
+        let mut config = Config::new()?;
+        config.set_mem_size(0x400);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = System::new(&config)?;
+            system.set_engine(*engine);
+            program.load(system.get_mem_ref())?;
+            let out = SharedBuf(Rc::new(RefCell::new(Vec::new())));
+            system.attach(Box::new(Console::new(Box::new(out.clone()))));
+            // The image runs with the trap vectors at the bottom of memory.
+            let mut mmu = Mmu::new();
+            for system_mode in [false, true].iter() {
+                mmu.set_segment(
+                    *system_mode,
+                    Segment {
+                        base: TRAP_VECTOR_BASE,
+                        limit: u32::MAX,
+                    },
+                );
+            }
+            mmu.set_enabled(true);
+            system.data_path_mut().set_mmu(mmu);
+            system.data_path_mut().set_psw(SYSTEM_LOC);
+            system.set_entry(TRAP_VECTOR_BASE + program.labels["boot"]);
+            run_cycles(&mut system, 5000)?;
+
+            // Both tasks ran, taking turns.
+            let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+            assert!(text.starts_with('A'), "{}", text);
+            assert!(text.chars().all(|c| c == 'A' || c == 'B'), "{}", text);
+            let turns = text.as_bytes().windows(2).filter(|w| w[0] != w[1]).count() + 1;
+            assert!(turns >= 8, "{} turns: {}", turns, text);
+            let psw = system.data_path().get_psw();
+            assert!(!psw.get_system_mode() || psw.get_cwp() == 2 || psw.get_cwp() == 5);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn putpsw_takes_effect_after_the_next_instruction() -> Result<()> {
+        let new_psw = SYSTEM_LOC | 1 << 10;
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(0x55))),
+            NOP,
+            I::PutPSW(SI::new(false, 0, 0, SS::Imm13(new_psw as u32))),
+            // Runs in window 0.
+            I::Add(SI::new(false, 17, 16, SS::Imm13(0))),
+            // Runs in window 1.
+            I::Add(SI::new(false, 18, 16, SS::Imm13(1))),
+            I::PutPSW(SI::new(false, 0, 0, SS::Imm13(SYSTEM_LOC as u32))),
+            I::Add(SI::new(true, 19, 0, SS::Imm13(1))),
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            system.data_path_mut().set_psw(SYSTEM_LOC);
+            let r = retire(&mut system, 3)?;
+            assert_eq_hex!(r.psw_after.get(), SYSTEM_LOC);
+            let r = retire(&mut system, 1)?;
+            assert_eq_hex!(r.psw_before.get(), SYSTEM_LOC);
+            assert_eq_hex!(r.psw_after.get(), new_psw);
+            // Setting the CC's right after PUTPSW is not allowed.
+            let r = retire(&mut system, 3)?;
+            assert_eq!(r.trap, Some(Trap::IllegalInstruction));
+            let dp = system.data_path();
+            assert_eq!(dp.register_file().read(17, 0), 0x55);
+            assert_eq!(dp.register_file().read(17, 1), 0);
+            assert_eq!(dp.register_file().read(18, 1), 1);
+            assert_eq!(dp.register_file().read(19, 0), 0);
+            assert_eq!(dp.get_psw().get_cwp(), 0);
+            assert_eq_hex!(dp.get_last_pc(), 24);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn profiler_counts_opcodes() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+            NOP,
+            I::Sub(SI::new(false, 16, 16, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(12))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            assert!(system.profiler().is_none());
+            system.set_profiling(true);
+            system.run_until_halt()?;
+
+            let profiler = system.profiler().unwrap();
+            assert_eq!(profiler.get("add").map(|p| p.count), Some(2));
+            assert_eq!(profiler.get("sub").map(|p| p.count), Some(1));
+            assert_eq!(profiler.get("jmpx").map(|p| p.count), Some(1));
+            assert!(profiler.get("ldxw").is_none());
+            assert!(profiler.to_string().starts_with("opcode"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn profiler_counts_pcs_and_calls() -> Result<()> {
+        let program = [
+            I::Callr(LongInstruction::new(false, RETURN_ADDRESS_REG, 24)),
+            NOP,
+            I::Callr(LongInstruction::new(false, RETURN_ADDRESS_REG, 16)),
+            NOP,
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(16))),
+            NOP,
+            I::Add(SI::new(false, 16, 16, SS::Imm13(1))),
+            I::Ret(SC::new(
+                false,
+                Conditional::Alw,
+                RETURN_ADDRESS_REG,
+                SS::Imm13(8),
+            )),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            system.set_profiling(true);
+            system.run_until_halt()?;
+
+            let profiler = system.profiler().unwrap();
+            assert_eq!(profiler.pc_count(0), 1);
+            assert_eq!(profiler.pc_count(24), 2);
+            assert_eq!(profiler.pc_count(28), 2);
+            assert_eq!(profiler.pc_count(36), 0);
+            assert_eq!(profiler.hot_spots(1)[0].1, 2);
+            assert_eq!(profiler.calls(), vec![(0, 24, 2)]);
+
+            let text = profiler.report(system.symbols(), ReportFormat::Text)?;
+            assert!(text.contains("caller"));
+            let json = profiler.report(system.symbols(), ReportFormat::Json)?;
+            assert!(json.contains("\"calls\""));
+            assert!(json.contains("\"0x00000018\""));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn alignment_stats_count_accesses() -> Result<()> {
+        let program = [
+            I::Ldxhu(SI::new(false, 16, 0, SS::Imm13(0x20))),
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x22))),
+        ];
+        let mut system = make_system(&program, Engine::Fast)?;
+        assert!(system.alignment_stats().is_none());
+        system.set_alignment_tracking(true);
+        system.step()?;
+        system.step()?;
+
+        let stats = system.alignment_stats().unwrap();
+        assert_eq!(stats.width(2).aligned, 1);
+        assert_eq!(stats.width(2).misaligned, 0);
+        assert_eq!(stats.width(4).aligned, 0);
+        assert_eq!(stats.width(4).misaligned, 1);
+        assert_eq!(stats.misaligned(), 1);
+        let offenders = stats.top_offenders(10);
+        assert_eq!(offenders.len(), 1);
+        assert_eq_hex!(offenders[0].pc, 4);
+        assert_eq_hex!(offenders[0].last_address, 0x22);
+        Ok(())
+    }
+
+    /// Get a path in the temporary directory for a test's snapshot.
+    fn snapshot_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("riscii-{}-{}.r2d2", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn snapshot_round_trip() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(2))),
+            I::Add(SI::new(false, 18, 0, SS::Imm13(5))),
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x20))),
+            I::Add(SI::new(true, 17, 18, SS::Imm13(-2i32 as u32 & 0x1fff))),
+        ];
+        let path = snapshot_path("round-trip");
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            system.step()?;
+            system.step()?;
+            system.save_snapshot(&path)?;
+            let count = system.clock().count();
+            let regs = system.data_path().copy_register_file();
+            let psw = system.data_path().get_psw();
+
+            system.step()?;
+            system.step()?;
+            assert_eq_hex!(system.get_mem_ref().get_word(0x20)?, 2);
+            let after = system.data_path().copy_register_file();
+
+            system.set_engine(Engine::Fast);
+            system.load_snapshot(&path)?;
+            assert_eq!(system.engine(), *engine);
+            assert_eq!(system.clock().count(), count);
+            assert!(system.data_path().copy_register_file() == regs);
+            assert!(system.data_path().get_psw() == psw);
+            assert_eq_hex!(system.get_mem_ref().get_word(0x20)?, 0);
+
+            system.step()?;
+            system.step()?;
+            assert_eq_hex!(system.get_mem_ref().get_word(0x20)?, 2);
+            assert!(system.data_path().copy_register_file() == after);
+        }
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_diff_lists_changes() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(0x41))),
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x30))),
+            I::Stxb(SI::new(false, 16, 0, SS::Imm13(0x36))),
+        ];
+        let path = snapshot_path("diff");
+        let mut system = make_system(&program, Engine::Fast)?;
+        system.save_snapshot(&path)?;
+        let mut before = make_system(&program, Engine::Fast)?;
+        before.load_snapshot(&path)?;
+        for _ in 0..3 {
+            system.step()?;
+        }
+        system.save_snapshot(&path)?;
+        let mut after = make_system(&program, Engine::Fast)?;
+        after.load_snapshot(&path)?;
+        std::fs::remove_file(&path)?;
+
+        let diff = SnapshotDiff::new(&before, &after);
+        let cwp = after.data_path().psw().get_cwp();
+        let names: Vec<&str> = diff.registers.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["pc".to_string(), format!("w{} r16", cwp)]);
+        assert_eq!(diff.registers[1].after, 0x41);
+        // The word and the byte two bytes after it, coalesced by run.
+        assert_eq!(diff.memory.len(), 2);
+        assert_eq_hex!(diff.memory[0].start, 0x33);
+        assert_eq!(diff.memory[0].after, vec![0x41]);
+        assert_eq_hex!(diff.memory[1].start, 0x36);
+        let text = diff.to_string();
+        assert!(
+            text.contains("memory 00000036..00000037 (1 bytes): 00 -> 41"),
+            "{}",
+            text
+        );
+        assert!(SnapshotDiff::new(&after, &after).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_rejects_bad_file() -> Result<()> {
+        let path = snapshot_path("bad");
+        std::fs::write(&path, b"R2D2\0\0\0\x63")?;
+        let mut system = make_system(&[NOP], Engine::Fast)?;
+        assert!(system.load_snapshot(&path).is_err());
+        std::fs::write(&path, b"NOPE")?;
+        assert!(system.load_snapshot(&path).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn trace_logs_retired_instructions() -> Result<()> {
+        let program = [
+            I::Add(SI::new(true, 16, 0, SS::Imm13(2))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x20))),
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x20))),
+        ];
+        let mut traces = Vec::new();
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            let out = SharedBuf(Rc::new(RefCell::new(Vec::new())));
+            system.set_tracer(Some(Tracer::new(Box::new(out.clone()), TraceFormat::Text)?))?;
+            for _ in 0..program.len() {
+                system.step()?;
+            }
+            let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+            assert_eq!(lines.len(), 4);
+            assert!(lines[0].contains("00000000"));
+            assert!(lines[0].contains("add.cc r16, r0, 0x2 r16 = 00000002"));
+            assert!(lines[2].contains("stxw r16, r0, 0x20 st4 [00000020] = 00000002"));
+            assert!(lines[3].contains("ldxw r17, r0, 0x20 r17 = 00000002 ld4 [00000020]"));
+            // Drop the cycle numbers, which differ between the engines.
+            traces.push(
+                lines
+                    .iter()
+                    .map(|l| l.splitn(2, ' ').nth(1).unwrap().to_string())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(traces[0], traces[1]);
+
+        let mut system = make_system(&program, Engine::Fast)?;
+        let out = SharedBuf(Rc::new(RefCell::new(Vec::new())));
+        system.set_tracer(Some(Tracer::new(
+            Box::new(out.clone()),
+            TraceFormat::Binary,
+        )?))?;
+        system.step()?;
+        system.step()?;
+        let bytes = out.0.borrow();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(bytes.len(), 8 + 2 * RECORD_SIZE);
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_trigger_fires_when_condition_becomes_true() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(2))),
+            NOP,
+            I::Add(SI::new(false, 16, 16, SS::Imm13(1))),
+            NOP,
+            I::Add(SI::new(false, 16, 0, SS::Imm13(2))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let dir = snapshot_path(&format!("trigger-{}", engine));
+            let mut system = make_system(&program, *engine)?;
+            system.set_snapshot_trigger(Some(SnapshotTrigger::new(
+                Expr::parse("r16 == 2 && cwp == 0")?,
+                dir.clone(),
+            )));
+            for _ in 0..program.len() {
+                system.step()?;
+            }
+            // Fires once per rising edge, not on every instruction it holds.
+            let captured = system.snapshot_trigger().unwrap().captured().clone();
+            assert_eq!(captured.len(), 2);
+
+            system.load_snapshot(&captured[0])?;
+            assert_eq_hex!(system.data_path().register_file().read(16, 0), 2);
+            system.step()?;
+            system.step()?;
+            assert_eq_hex!(system.data_path().register_file().read(16, 0), 3);
+            std::fs::remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_session_lists_restores_and_renames() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(2))),
+            NOP,
+            I::Add(SI::new(false, 16, 16, SS::Imm13(1))),
+            NOP,
+        ];
+        let dir = snapshot_path("session");
+        let mut system = make_system(&program, Engine::Fast)?;
+        system.set_snapshot_session(SnapshotSession::new(dir.clone(), 2));
+        system.step()?;
+        system.step()?;
+        system.take_snapshot(SnapshotKind::Manual)?;
+        system.take_snapshot(SnapshotKind::Manual)?;
+        system.step()?;
+        system.step()?;
+
+        let snapshots = system.snapshots().snapshots().clone();
+        let kinds: Vec<SnapshotKind> = snapshots.iter().map(|s| s.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SnapshotKind::Periodic,
+                SnapshotKind::Manual,
+                SnapshotKind::Manual,
+                SnapshotKind::Periodic,
+            ]
+        );
+        // Snapshots taken at the same cycle get different names.
+        assert_ne!(snapshots[1].name, snapshots[2].name);
+        assert!(snapshots[0].cycle < snapshots[3].cycle);
+
+        system.snapshots_mut().rename(1, "before-increment")?;
+        assert!(system
+            .snapshots_mut()
+            .rename(2, "before-increment")
+            .is_err());
+        assert!(system.snapshots_mut().rename(2, "../escape").is_err());
+        let renamed = system.snapshots().snapshots()[1].clone();
+        assert_eq!(renamed.name, "before-increment");
+        assert!(std::path::Path::new(&renamed.path).exists());
+        assert!(!std::path::Path::new(&snapshots[1].path).exists());
+
+        assert_eq_hex!(system.data_path().register_file().read(16, 0), 3);
+        system.restore_session_snapshot(1)?;
+        assert_eq!(system.clock().count(), renamed.cycle);
+        assert_eq_hex!(system.data_path().register_file().read(16, 0), 2);
+        assert!(system.restore_session_snapshot(4).is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn guest_takes_snapshots() -> Result<()> {
+        // Take two snapshots, reading the id of each, then exit.
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 17, HOST_BASE >> 13)),
+            NOP,
+            I::Ldxw(SI::new(false, 18, 17, SS::Imm13(HOST_SNAPSHOT & 0x1fff))),
+            I::Stxw(SI::new(false, 0, 17, SS::Imm13(HOST_SNAPSHOT & 0x1fff))),
+            I::Ldxw(SI::new(false, 19, 17, SS::Imm13(HOST_SNAPSHOT & 0x1fff))),
+            I::Stxw(SI::new(false, 0, 17, SS::Imm13(HOST_SNAPSHOT & 0x1fff))),
+            I::Ldxw(SI::new(false, 20, 17, SS::Imm13(HOST_SNAPSHOT & 0x1fff))),
+            I::Stxw(SI::new(false, 0, 17, SS::Imm13(HOST_EXIT & 0x1fff))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let dir = snapshot_path("guest");
+            let mut system = make_system(&program, *engine)?;
+            system.set_snapshot_session(SnapshotSession::new(dir.clone(), 0));
+            system.run_until_halt()?;
+            let rf = system.data_path().register_file();
+            assert_eq!(rf.read(18, 0), 0);
+            assert_eq!(rf.read(19, 0), 1);
+            assert_eq!(rf.read(20, 0), 2);
+
+            let snapshots = system.snapshots().snapshots().clone();
+            assert_eq!(snapshots.len(), 2);
+            assert!(snapshots.iter().all(|s| s.kind == SnapshotKind::Guest));
+            assert!(snapshots[0].cycle < snapshots[1].cycle);
+            system.restore_session_snapshot(0)?;
+            assert_eq!(system.clock().count(), snapshots[0].cycle);
+            assert_eq!(system.data_path().register_file().read(20, 0), 0);
+            std::fs::remove_dir_all(&dir)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn host_mem_limit_evicts_old_data() -> Result<()> {
+        // Count forever, a snapshot every other cycle.
+        let program = [
+            I::Add(SI::new(false, 16, 16, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0))),
+            NOP,
+        ];
+        let dir = snapshot_path("usage");
+        let trace = format!("{}.trace", dir);
+        let mut system = make_system(&program, Engine::Fast)?;
+        system.set_snapshot_session(SnapshotSession::new(dir.clone(), 2));
+        system.set_tracer(Some(Tracer::create(&trace, TraceFormat::Text)?))?;
+        system.take_snapshot(SnapshotKind::Manual)?;
+        let manual = system.snapshots().size();
+        let guest = system.usage().guest;
+        assert_eq!(guest, system.memory().size() as u64);
+
+        // Room for the manual snapshot and two more, but not the trace.
+        let limit = guest + manual * 3;
+        system.set_host_mem_limit(Some(limit))?;
+        for _ in 0..20 {
+            system.step()?;
+        }
+        let usage = system.usage();
+        assert!(usage.total() <= limit);
+        let snapshots = system.snapshots().snapshots();
+        assert_eq!(snapshots[0].kind, SnapshotKind::Manual);
+        assert!(snapshots.len() <= 3);
+        // The newest snapshot survives.
+        assert_eq!(snapshots.last().unwrap().kind, SnapshotKind::Periodic);
+        assert!(snapshots.last().unwrap().cycle >= 18);
+        // The trace went first, only its newest part is left.
+        system.set_tracer(None)?;
+        let traced = std::fs::read_to_string(&trace)?;
+        assert_eq!(traced.len() as u64, usage.trace);
+        assert!(traced.lines().count() < 20);
+
+        // Guest memory is never evicted.
+        assert!(system.set_host_mem_limit(Some(guest - 1)).is_err());
+        std::fs::remove_dir_all(&dir)?;
+        std::fs::remove_file(&trace)?;
+        let _ = std::fs::remove_file(format!("{}.old", trace));
+        Ok(())
+    }
+
+    #[test]
+    fn hex_view_marks_stored_bytes() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(0x41))),
+            NOP,
+            I::Stxh(SI::new(false, 16, 0, SS::Imm13(0x32))),
+        ];
+        let mut system = make_system(&program, Engine::Cycle)?;
+        let log = record_retirements(&mut system);
+        for _ in 0..3 {
+            system.step()?;
+        }
+        let access = log.borrow()[2].access;
+        let mut view = HexView::new(0x37, 2);
+        assert_eq_hex!(view.start(), 0x30);
+        let rows = view.rows(system.memory(), access.as_ref());
+        assert_eq!(rows.len(), 2);
+        assert_eq!(
+            rows[0].to_string(),
+            "00000030: 00 00 00 41 00 00 00 00 00 00 00 00 00 00 00 00  ...A............"
+        );
+        let written: Vec<usize> = (0..16).filter(|i| rows[0].written[*i]).collect();
+        assert_eq!(written, vec![2, 3]);
+        // Past the end of memory.
+        assert_eq!(rows[1].hex(0), "??");
+        assert!(rows[1].bytes.iter().all(|b| b.is_none()));
+
+        view.page_down();
+        assert_eq_hex!(view.start(), 0x50);
+        view.page_up();
+        view.page_up();
+        assert_eq_hex!(view.start(), 0x10);
+        view.goto(0x1234);
+        assert_eq_hex!(view.start(), 0x1230);
+        assert_eq_hex!(view.cursor(), 0x1234);
+        // The page follows the cursor a row at a time.
+        view.move_cursor(-0x10);
+        assert_eq_hex!(view.start(), 0x1220);
+        view.move_cursor(0x21);
+        assert_eq_hex!(view.cursor(), 0x1245);
+        assert_eq_hex!(view.start(), 0x1230);
+        view.move_cursor(-1);
+        assert_eq_hex!(view.start(), 0x1230);
+        Ok(())
+    }
+
+    #[test]
+    fn call_stack_follows_the_windows() -> Result<()> {
+        let program = assemble(
+            "
+start:  callr   ra, outer
+        add     r0, r0, r0
+        jmpr    alw, start
+        add     r0, r0, r0
+outer:  callr   ra, inner
+        add     r0, r0, r0
+        ret     alw, ra, 8
+        add     r0, r0, r0
+inner:  add     r16, r0, 1
+spin:   jmpr    alw, spin
+        add     r0, r0, r0
+",
+            0,
+        )?;
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = System::new(&Config::new()?)?;
+            system.set_engine(*engine);
+            program.load(system.get_mem_ref())?;
+            system.run_until(program.labels["inner"], 100)?;
+
+            let symbols = program.symbols();
+            let stack = CallStack::new(system.data_path(), &symbols);
+            let windows: Vec<u8> = stack.frames.iter().map(|f| f.window).collect();
+            let cwp = system.data_path().psw().get_cwp();
+            let rf = system.data_path().register_file();
+            assert_eq!(
+                windows,
+                vec![cwp, (cwp + 1) % rf.windows(), (cwp + 2) % rf.windows()]
+            );
+            let pcs: Vec<u32> = stack.frames.iter().skip(1).map(|f| f.pc).collect();
+            assert_eq!(pcs, vec![program.labels["outer"], program.labels["start"]]);
+            assert_eq!(stack.frames[1].function, Some("outer".to_string()));
+            let text = stack.to_string();
+            assert_eq!(text.lines().count(), 3);
+            assert!(
+                text.lines()
+                    .nth(2)
+                    .unwrap()
+                    .starts_with(&format!("#2 w{} 00000000 start", windows[2])),
+                "{}",
+                text
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn architectural_state_round_trips() -> Result<()> {
+        let program = [
+            I::Add(SI::new(true, 16, 0, SS::Imm13(2))),
+            I::Callr(LongInstruction::new(false, RETURN_ADDRESS_REG, 8)),
+            NOP,
+            NOP,
+        ];
+        let mut system = make_system(&program, Engine::Fast)?;
+        for _ in 0..3 {
+            system.step()?;
+        }
+        let dp = system.data_path();
+
+        let text = toml::to_string(dp).unwrap();
+        assert!(text.starts_with("pc = 12\nnxtpc = 16\nlstpc = 8\n"));
+        let copy: DataPath = toml::from_str(&text).unwrap();
+        assert_eq!(copy.pc(), dp.pc());
+        assert_eq!(copy.nxtpc(), dp.nxtpc());
+        assert_eq!(copy.lstpc(), dp.lstpc());
+        assert_eq!(copy.psw().get(), dp.psw().get());
+        assert_eq!(copy.register_file(), dp.register_file());
+        assert_eq!(copy.register_file().read(RETURN_ADDRESS_REG, 7), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn state_export_is_structured() -> Result<()> {
+        let program = [
+            I::Add(SI::new(true, 16, 0, SS::Imm13(2))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x20))),
+        ];
+        let mut system = make_system(&program, Engine::Fast)?;
+        for _ in 0..program.len() {
+            system.step()?;
+        }
+        let ranges = MemoryRange::parse_list("0x20:8")?;
+
+        let toml = export_state(&system, &ranges, StateFormat::Toml)?;
+        let value = toml.parse::<toml::Value>().unwrap();
+        assert_eq!(value["cpu"]["engine"].as_str(), Some("fast"));
+        assert_eq!(value["psw"]["cwp"].as_integer(), Some(0));
+        assert_eq!(value["psw"]["z"].as_bool(), Some(false));
+        assert_eq!(
+            value["registers"]["locals"]["r16"].as_str(),
+            Some("0x00000002")
+        );
+        assert_eq!(value["memory"][0]["words"].as_array().unwrap().len(), 2);
+        assert!(toml.contains("\n[registers.outs]\nr10 = \"0x00000000\"\n"));
+
+        let json = export_state(&system, &ranges, StateFormat::Json)?;
+        assert!(json.starts_with("{\n  \"cpu\": {\n    \"engine\": \"fast\",\n"));
+        assert!(json.contains("\"r16\": \"0x00000002\""));
+        assert!(json.contains("\"words\": [\"0x00000002\", \"0x00000000\"]"));
+
+        assert!(MemoryRange::parse_list("0x21:4").is_err());
+        assert!(MemoryRange::parse_list("0x20").is_err());
+        let too_far = MemoryRange::parse_list("0x3c:8")?;
+        assert!(export_state(&system, &too_far, StateFormat::Json).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn data_path_view_diff() -> Result<()> {
+        let program = [I::Add(SI::new(true, 16, 0, SS::Imm13(0)))];
+        let mut system = make_system(&program, Engine::Fast)?;
+        let before = DataPathView::new(system.data_path());
+        assert!(before.diff(&before).is_empty());
+
+        system.step()?;
+        let after = DataPathView::new(system.data_path());
+        let diff = after.diff(&before);
+        assert!(diff.has_latch(Latch::Pc));
+        assert!(diff.has_latch(Latch::NxtPc));
+        assert_eq!(diff.psw_bits, ZERO_LOC);
+        // Writing 0 over 0 is not a change.
+        assert!(diff.registers.is_empty());
+        assert!(format!("{}", diff).contains("PSW Z"));
+        Ok(())
+    }
+
+    #[test]
+    fn front_panel_follows_the_boot_core() -> Result<()> {
+        let program = [I::Add(SI::new(true, 16, 0, SS::Imm13(0))), NOP];
+        let mut system = make_system(&program, Engine::Fast)?;
+        system.step()?;
+        let panel = FrontPanel::from_system(&system);
+        assert_eq_hex!(panel.pc, 4);
+        assert_eq!(
+            panel.instruction,
+            Some(format!("{}", NOP.disassemble(system.syntax())))
+        );
+        let lit: Vec<&str> = (0..PSW_LED_NAMES.len())
+            .filter(|i| panel.psw[*i])
+            .map(|i| PSW_LED_NAMES[i])
+            .collect();
+        assert!(lit.contains(&"Z"));
+        assert!(!lit.contains(&"C"));
+        assert_eq!(panel.cycles, system.clock().count());
+        assert_eq!(panel.state, RunState::Running);
+        let lights = panel.pc_lights();
+        assert!(lights[29]);
+        assert_eq!(lights.iter().filter(|l| **l).count(), 1);
+        assert_eq!(panel.dial_angle(0), 0.0);
+        assert_eq!(panel.dial_angle(panel.windows), 0.0);
+        assert!(panel.dial_angle(1) > 0.0);
+
+        // Past the end of memory there is no instruction.
+        system.data_path_mut().set_pc(0x1000);
+        assert_eq!(FrontPanel::from_system(&system).instruction, None);
+        Ok(())
+    }
+
+    #[test]
+    fn cosim_agrees_until_halt() -> Result<()> {
+        let program = [
+            I::Add(SI::new(true, 16, 0, SS::Imm13(3))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x20))),
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x20))),
+            NOP,
+            I::Sub(SI::new(true, 18, 17, SS::Imm13(3))),
+            // Halt.
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x18))),
+            NOP,
+        ];
+        let mut cosim = Cosim::new(
+            make_system(&program, Engine::Fast)?,
+            make_system(&program, Engine::Fast)?,
+        );
+        if let Some(d) = cosim.run_until_halt()? {
+            panic!("{}", d);
+        }
+        assert_eq!(cosim.retired(), 7);
+        assert_eq!(cosim.cycle().engine(), Engine::Cycle);
+        Ok(())
+    }
+
+    #[test]
+    fn cosim_reports_first_divergence() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x20))),
+            NOP,
+        ];
+        let mut cycle = make_system(&program, Engine::Cycle)?;
+        cycle.get_mem_ref().set_word(0x20, 5)?;
+        let mut cosim = Cosim::new(make_system(&program, Engine::Fast)?, cycle);
+        let d = cosim.run_until_halt()?.unwrap();
+        assert_eq!(d.index, 1);
+        assert_eq!(d.history.len(), 1);
+        assert_eq!(d.fast.as_ref().unwrap().dest, Some((17, 0)));
+        assert_eq!(d.cycle.as_ref().unwrap().dest, Some((17, 5)));
+        assert!(d
+            .differences
+            .iter()
+            .any(|l| l == "r17: 00000000 != 00000005"));
+        assert!(format!("{}", d).contains("after 1 instructions"));
+        Ok(())
+    }
+
+    /// Address stores to which start an operation on the `Halver`.
+    const HALVER_OPERAND: u32 = 0x30;
+
+    /// Coprocessor that halves the words stored to `HALVER_OPERAND`, taking
+    /// `latency` cycles. The result goes to r20 and the remainder to the
+    /// carry bit. Halving 1 traps.
+    struct Halver {
+        latency: u32,
+        /// Operand and cycles left of the running operation.
+        running: Option<(u32, u32)>,
+        /// Operand issued while busy.
+        queued: Option<u32>,
+    }
+
+    impl Coprocessor for Halver {
+        fn name(&self) -> &str {
+            "halver"
+        }
+
+        fn reset(&mut self, _kind: Reset) {
+            self.running = None;
+            self.queued = None;
+        }
+
+        fn issue(&mut self, r: &RetiredInstruction) {
+            match r.access {
+                Some(a) if a.store && a.address == HALVER_OPERAND => {
+                    if self.running.is_some() {
+                        self.queued = Some(a.value);
+                    } else {
+                        self.running = Some((a.value, self.latency));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn tick(&mut self) -> Status {
+            let (operand, left) = match self.running {
+                Some(v) => v,
+                None => return Status::Idle,
+            };
+            if left > 1 {
+                self.running = Some((operand, left - 1));
+                return if self.queued.is_some() {
+                    Status::Stall
+                } else {
+                    Status::Busy
+                };
+            }
+            self.running = self.queued.take().map(|v| (v, self.latency));
+            Status::Done(if operand == 1 {
+                Completion {
+                    trap: Some(Trap::Coprocessor(7)),
+                    ..Completion::default()
+                }
+            } else {
+                Completion {
+                    dest: Some((20, operand / 2)),
+                    cc: Some(ConditionCodes {
+                        zero: operand / 2 == 0,
+                        carry: operand & 1 != 0,
+                        ..ConditionCodes::default()
+                    }),
+                    trap: None,
+                }
+            })
+        }
+    }
+
+    /// Create a system running `program` on `engine` with a `Halver` that
+    /// takes `latency` cycles attached.
+    fn make_halver_system(program: &[I], engine: Engine, latency: u32) -> Result<System> {
+        let mut system = make_system(program, engine)?;
+        system.attach_coprocessor(Box::new(Halver {
+            latency: latency,
+            running: None,
+            queued: None,
+        }));
+        Ok(system)
+    }
+
+    #[test]
+    fn coprocessor_forwards_result_and_condition_codes() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(11))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(HALVER_OPERAND))),
+            NOP,
+            NOP,
+            NOP,
+            NOP,
+            NOP,
+            NOP,
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_halver_system(&program, *engine, 3)?;
+            assert_eq!(system.coprocessors().names(), vec!["halver"]);
+            run_cycles(&mut system, 10)?;
+            let dp = system.data_path();
+            assert_eq!(dp.register_file().read(20, 0), 5, "{:?}", engine);
+            assert!(dp.get_psw().get_cc_carry());
+            assert!(!dp.get_psw().get_cc_zero());
+            assert_eq!(system.coprocessors().stalled_cycles(), 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn coprocessor_stalls_cpu_while_busy() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(40))),
+            I::Add(SI::new(false, 17, 0, SS::Imm13(7))),
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(HALVER_OPERAND))),
+            I::Stxw(SI::new(false, 17, 0, SS::Imm13(HALVER_OPERAND))),
+            I::Add(SI::new(false, 18, 0, SS::Imm13(1))),
+            NOP,
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(24))),
+            NOP,
+        ];
+        let mut system = make_halver_system(&program, Engine::Fast, 4)?;
+        let log = record_retirements(&mut system);
+        run_cycles(&mut system, 5)?;
+        // The second store waits on the first operation, so the add behind
+        // it has not run yet.
+        assert_eq!(log.borrow().len(), 4);
+        assert_eq!(system.data_path().register_file().read(18, 0), 0);
+
+        run_cycles(&mut system, 8)?;
+        let dp = system.data_path();
+        assert_eq!(dp.register_file().read(18, 0), 1);
+        assert_eq!(dp.register_file().read(20, 0), 3);
+        assert!(dp.get_psw().get_cc_carry());
+        assert!(system.coprocessors().stalled_cycles() > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn coprocessor_trap_flushes_cpu() -> Result<()> {
+        let mut program = vec![
+            I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(HALVER_OPERAND))),
+        ];
+        program.extend_from_slice(&[NOP; 7]);
+        program.push(I::Add(SI::new(false, 17, 0, SS::Imm13(9))));
+        let mut system = make_halver_system(&program, Engine::Cycle, 2)?;
+        let log = record_retirements(&mut system);
+        let mut cycles = 0;
+        while system.data_path().get_trap_cause() == 0 {
+            assert!(cycles < 10, "coprocessor never trapped");
+            run_cycles(&mut system, 1)?;
+            cycles += 1;
+        }
+        let dp = system.data_path();
+        assert_eq!(dp.get_trap_cause(), 7);
+        assert!(dp.get_psw().get_system_mode());
+        assert_eq!(dp.register_file().read(17, 0), 0);
+        // Traps raised by a coprocessor belong to no instruction.
+        assert!(log.borrow().iter().all(|r| r.trap.is_none()));
+
+        system.reset(Reset::Warm)?;
+        assert_eq!(system.coprocessors().stalled_cycles(), 0);
+        Ok(())
+    }
+
+    /// Address the FPU tests keep their commands at.
+    #[cfg(feature = "fpu")]
+    const FPU_COMMANDS: u32 = 0x800;
+    /// Address of the trap vector table of the FPU tests, inside their
+    /// memory.
+    #[cfg(feature = "fpu")]
+    const FPU_TRAP_VECTORS: u32 = 0x400;
+
+    /// Create a system with an FPU running `program` on `engine`, f1 and f2
+    /// holding `a` and `b`, r16 pointing at the FPU's registers and
+    /// `commands` stored from `FPU_COMMANDS` on. Traps go to the table at
+    /// `FPU_TRAP_VECTORS`.
+    #[cfg(feature = "fpu")]
+    fn make_fpu_system(
+        program: &[I],
+        engine: Engine,
+        a: f32,
+        b: f32,
+        commands: &[u32],
+    ) -> Result<System> {
+        let mut config = Config::new()?;
+        config.set_fpu(true);
+        config.set_mem_size(0x1000);
+        config.set_trap_vectors(FPU_TRAP_VECTORS);
+        let mut words = vec![I::Ldhi(LongInstruction::new(false, 16, FPU_BASE >> 13))];
+        words.extend_from_slice(program);
+        let mut system = make_system_with(&config, &words, engine)?;
+        let mem = system.get_mem_ref();
+        for (i, command) in commands.iter().enumerate() {
+            mem.set_word(FPU_COMMANDS + i as u32 * 4, *command)?;
+        }
+        mem.set_word(FPU_REGISTERS + 4, a.to_bits())?;
+        mem.set_word(FPU_REGISTERS + 8, b.to_bits())?;
+        Ok(system)
+    }
+
+    #[cfg(feature = "fpu")]
+    #[test]
+    fn fpu_runs_alongside_the_cpu() -> Result<()> {
+        let commands = [fpu_command(FPU_DIV, 3, 1, 2), fpu_command(FPU_CMP, 0, 1, 2)];
+        let mut program = vec![
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(FPU_COMMANDS))),
+            I::Ldxw(SI::new(false, 18, 0, SS::Imm13(FPU_COMMANDS + 4))),
+            NOP,
+            I::Stxw(SI::new(false, 17, 16, SS::Imm13(FPU_COMMAND & 0x1fff))),
+            I::Ldxw(SI::new(false, 20, 16, SS::Imm13(FPU_STATUS & 0x1fff))),
+            I::Stxw(SI::new(false, 18, 16, SS::Imm13(FPU_COMMAND & 0x1fff))),
+        ];
+        program.extend_from_slice(&[NOP; 24]);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_fpu_system(&program, *engine, 1.5, 0.25, &commands)?;
+            assert_eq!(system.coprocessors().names(), vec!["fpu"]);
+            run_cycles(&mut system, 30)?;
+            // The divide was still running when the status was read.
+            let dp = system.data_path();
+            assert_eq!(dp.register_file().read(20, 0) & FPU_BUSY, FPU_BUSY);
+            assert_eq!(
+                f32::from_bits(system.memory().get_word(FPU_REGISTERS + 12)?),
+                6.0
+            );
+            assert_eq_hex!(system.memory().get_word(FPU_STATUS)?, FPU_GREATER);
+            // The compare set the CC's.
+            assert!(!dp.get_psw().get_cc_zero());
+            assert!(!dp.get_psw().get_cc_neg());
+            assert!(!dp.get_psw().get_cc_overflow());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "fpu")]
+    #[test]
+    fn fpu_flags_exceptions_and_traps_on_unknown_commands() -> Result<()> {
+        let commands = [
+            fpu_command(FPU_DIV, 3, 1, 2),
+            fpu_command(FPU_CMP, 0, 2, 1),
+            0x7000,
+        ];
+        let mut program = vec![
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(FPU_COMMANDS))),
+            I::Ldxw(SI::new(false, 18, 0, SS::Imm13(FPU_COMMANDS + 4))),
+            NOP,
+            I::Stxw(SI::new(false, 17, 16, SS::Imm13(FPU_COMMAND & 0x1fff))),
+            I::Stxw(SI::new(false, 18, 16, SS::Imm13(FPU_COMMAND & 0x1fff))),
+        ];
+        program.extend_from_slice(&[NOP; 16]);
+        let mut system = make_fpu_system(&program, Engine::Fast, 1.0, 0.0, &commands)?;
+        run_cycles(&mut system, 24)?;
+        assert!(f32::from_bits(system.memory().get_word(FPU_REGISTERS + 12)?).is_infinite());
+        assert_eq_hex!(
+            system.memory().get_word(FPU_STATUS)?,
+            FPU_DIVIDE_BY_ZERO | FPU_LESS
+        );
+        assert!(system.data_path().get_psw().get_cc_neg());
+        // Writing the status clears the exception flags.
+        system.get_mem_ref().set_word(FPU_STATUS, 0)?;
+        assert_eq_hex!(system.memory().get_word(FPU_STATUS)?, FPU_LESS);
+
+        let mut program = vec![
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(FPU_COMMANDS + 8))),
+            NOP,
+            I::Stxw(SI::new(false, 17, 16, SS::Imm13(FPU_COMMAND & 0x1fff))),
+        ];
+        program.extend_from_slice(&[NOP; 8]);
+        let mut system = make_fpu_system(&program, Engine::Fast, 1.0, 0.0, &commands)?;
+        // The handler halts.
+        let handler = FPU_TRAP_VECTORS + Trap::Coprocessor(0).index() as u32 * TRAP_VECTOR_SPACING;
+        let halt = I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(handler)));
+        system.get_mem_ref().set_word(handler, halt.encode())?;
+        system.get_mem_ref().set_word(handler + 4, NOP.encode())?;
+        run_cycles(&mut system, 10)?;
+        assert_eq_hex!(system.data_path().get_trap_cause(), 0x7000);
+        Ok(())
+    }
+
+    /// Number of `HostDependent` devices created so far.
+    static HOST_DEPENDENT_COUNT: AtomicU32 = AtomicU32::new(0);
+
+    /// Device whose register reads differently in every system, like a
+    /// device reading host time.
+    struct HostDependent {
+        value: u32,
+    }
+
+    impl Device for HostDependent {
+        fn name(&self) -> &str {
+            "host dependent"
+        }
+
+        fn reset(&mut self, _kind: Reset) {}
+
+        fn mapping(&self) -> Option<std::ops::Range<u32>> {
+            Some(0x38..0x3c)
+        }
+
+        fn read(&mut self, _addr: u32) -> u32 {
+            self.value
+        }
+    }
+
+    #[test]
+    fn audit_agrees_on_deterministic_program() -> Result<()> {
+        let program = [
+            I::Add(SI::new(true, 16, 0, SS::Imm13(3))),
+            NOP,
+            I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x30))),
+            I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x30))),
+            // Halt.
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x10))),
+            NOP,
+        ];
+        let mut audit = Audit::new(|| make_system(&program, Engine::Cycle), 2);
+        assert!(audit.run_until_halt()?.is_none());
+        assert_eq!(audit.retired(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_finds_nondeterministic_device() -> Result<()> {
+        let program = [
+            NOP,
+            NOP,
+            NOP,
+            I::Ldxw(SI::new(false, 16, 0, SS::Imm13(0x38))),
+            NOP,
+            // Halt.
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x14))),
+            NOP,
+        ];
+        let make = || -> Result<System> {
+            let mut system = make_system(&program, Engine::Fast)?;
+            system.attach(Box::new(HostDependent {
+                value: HOST_DEPENDENT_COUNT.fetch_add(1, Ordering::SeqCst),
+            }));
+            Ok(system)
+        };
+        let found = match Audit::new(make, 3).run_until_halt()? {
+            Some(found) => found,
+            None => panic!("audit missed the nondeterministic load"),
+        };
+        // The load retires on cycle 4 and is the 4th instruction.
+        assert_eq!(found.cycle, Some(4));
+        assert_eq!(found.instructions, 4);
+        assert_eq!(found.last_agreed, 3);
+        assert_eq!(found.subsystems, vec!["data path".to_string()]);
+        assert!(format!("{}", found).contains("Nondeterministic: data path"));
+        Ok(())
+    }
+
+    /// Create a user mode system running `engine` with `program` loaded at
+    /// physical address 0x10 and the MMU mapping virtual 0 to 0x2f there.
+    fn make_segmented_system(program: &[I], engine: Engine) -> Result<System> {
+        let mut system = make_system(&[], engine)?;
+        for (i, instruction) in program.iter().enumerate() {
+            system
+                .get_mem_ref()
+                .set_word(0x10 + i as u32 * 4, instruction.encode())?;
+        }
+        let mut mmu = Mmu::new();
+        mmu.set_segment(
+            false,
+            Segment {
+                base: 0x10,
+                limit: 0x2f,
+            },
+        );
+        mmu.set_enabled(true);
+        system.data_path_mut().set_mmu(mmu);
+        Ok(system)
+    }
+
+    /// Run `system` until it retires `count` instructions, return the last.
+    fn retire(system: &mut System, count: usize) -> Result<RetiredInstruction> {
+        let mut last = None;
+        for _ in 0..count {
+            last = system.run_until_retired()?;
+        }
+        Ok(last.expect("nothing retired"))
+    }
+
+    #[test]
+    fn mmu_translates_user_accesses() -> Result<()> {
+        // Load the address of the MMU's registers into r16.
+        let mmu_base = [
+            I::Ldhi(LongInstruction::new(false, 16, MMU_BASE >> 13)),
+            NOP,
+            I::Add(SI::new(false, 16, 16, SS::Imm13(MMU_BASE & 0x1fff))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let program = [
+                I::Add(SI::new(false, 16, 0, SS::Imm13(0x55))),
+                NOP,
+                I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x20))),
+                I::Ldxw(SI::new(false, 17, 0, SS::Imm13(0x20))),
+                I::Stxb(SI::new(false, 16, 0, SS::Imm13(0x30))),
+            ];
+            let mut system = make_segmented_system(&program, *engine)?;
+            let r = retire(&mut system, 4)?;
+            assert_eq!(r.access.map(|a| a.address), Some(0x30));
+            assert_eq_hex!(system.get_mem_ref().get_word(0x30)?, 0x55);
+            // Past the segment's limit.
+            let r = retire(&mut system, 1)?;
+            assert_eq!(r.trap, Some(Trap::AccessViolation(0x30)));
+            let dp = system.data_path();
+            assert_eq!(dp.register_file().read(17, 0), 0x55);
+            assert_eq_hex!(dp.get_pc(), Trap::AccessViolation(0).vector());
+            assert_eq_hex!(dp.get_last_pc(), 0x10);
+            assert_eq_hex!(dp.get_trap_cause(), 0x30);
+
+            // The MMU's registers are out of reach of user mode.
+            let mut program = mmu_base.to_vec();
+            program.push(I::Ldxw(SI::new(false, 17, 16, SS::Imm13(4))));
+            let mut system = make_segmented_system(&program, *engine)?;
+            let r = retire(&mut system, 5)?;
+            assert_eq!(r.trap, Some(Trap::AccessViolation(MMU_USER_BASE)));
+            assert_eq!(system.data_path().register_file().read(17, 0), 0);
+
+            // Running off the end of the segment faults the fetch, which
+            // retires nothing.
+            let mut system = make_segmented_system(&[NOP; 8], *engine)?;
+            let mut mmu = system.data_path().get_mmu();
+            mmu.set_segment(
+                false,
+                Segment {
+                    base: 0x10,
+                    limit: 0x1f,
+                },
+            );
+            system.data_path_mut().set_mmu(mmu);
+            retire(&mut system, 8)?;
+            let vector = Trap::AccessViolation(0).vector();
+            for _ in 0..4 {
+                if system.data_path().get_pc() == vector {
+                    break;
+                }
+                assert_eq!(system.run_cycle()?, 0);
+            }
+            let dp = system.data_path();
+            assert_eq_hex!(dp.get_pc(), vector);
+            assert_eq_hex!(dp.get_last_pc(), 0x20);
+            assert_eq_hex!(dp.get_trap_cause(), 0x20);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn mmu_registers_control_translation() -> Result<()> {
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let program = [
+                I::Ldhi(LongInstruction::new(false, 16, MMU_BASE >> 13)),
+                NOP,
+                I::Add(SI::new(false, 16, 16, SS::Imm13(MMU_BASE & 0x1fff))),
+                I::Add(SI::new(false, 17, 0, SS::Imm13(0x1f))),
+                NOP,
+                I::Stxw(SI::new(false, 17, 16, SS::Imm13(8))),
+                I::Add(SI::new(false, 17, 0, SS::Imm13(MMU_ENABLE))),
+                NOP,
+                I::Stxw(SI::new(false, 17, 16, SS::Imm13(0))),
+                I::Ldxw(SI::new(false, 18, 16, SS::Imm13(8))),
+                I::Ldxhu(SI::new(false, 19, 16, SS::Imm13(8))),
+            ];
+            let mut system = make_system(&program, *engine)?;
+            system.data_path_mut().set_psw(SYSTEM_LOC);
+            let r = retire(&mut system, 10)?;
+            // Register accesses do not use the bus.
+            assert_eq!(r.access, None);
+            assert_eq!(r.dest, Some((18, 0x1f)));
+            let mmu = system.data_path().get_mmu();
+            assert!(mmu.is_enabled());
+            assert_eq!(mmu.get_segment(false).limit, 0x1f);
+            assert_eq!(mmu.get_segment(true), Segment::identity());
+            // Only whole words.
+            let r = retire(&mut system, 1)?;
+            assert_eq!(r.trap, Some(Trap::AccessViolation(MMU_USER_LIMIT)));
+
+            system.reset(Reset::Warm)?;
+            assert!(!system.data_path().get_mmu().is_enabled());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn extensions_execute_their_opcodes() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(6))),
+            I::Add(SI::new(false, 17, 0, SS::Imm13(7))),
+            NOP,
+            I::Extension(MUL_OPCODE, SI::new(false, 18, 16, SS::Reg(17))),
+            NOP,
+            I::Extension(DIV_OPCODE, SI::new(false, 19, 18, SS::Imm13(5))),
+            I::Extension(REM_OPCODE, SI::new(false, 20, 18, SS::Imm13(5))),
+            I::Extension(DIVU_OPCODE, SI::new(true, 21, 18, SS::Reg(0))),
+            NOP,
+        ];
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = make_system(&program, *engine)?;
+            system.attach_extension(Rc::new(MulDiv));
+            let r = retire(&mut system, program.len())?;
+            assert_eq!(r.trap, None);
+            let dp = system.data_path();
+            let regs = dp.register_file();
+            assert_eq!(regs.read(18, 0), 42);
+            assert_eq!(regs.read(19, 0), 8);
+            assert_eq!(regs.read(20, 0), 2);
+            // Dividing by zero sets V instead of trapping.
+            assert_eq!(regs.read(21, 0), u32::MAX);
+            assert!(dp.get_psw().get_cc_overflow());
+            assert!(dp.get_psw().get_cc_neg());
+
+            // Without the extension the opcodes are illegal.
+            let mut system = make_system(&program, *engine)?;
+            let r = retire(&mut system, 4)?;
+            assert_eq!(r.trap, Some(Trap::IllegalInstruction));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn isa_ext_mul_gives_the_cores_multiply() -> Result<()> {
+        let program = [
+            I::Sub(SI::new(false, 16, 0, SS::Imm13(3))),
+            I::Add(SI::new(false, 17, 0, SS::Imm13(7))),
+            NOP,
+            I::Extension(MUL_OPCODE, SI::new(true, 18, 16, SS::Reg(17))),
+            I::Extension(MULHU_OPCODE, SI::new(false, 19, 16, SS::Reg(17))),
+            NOP,
+        ];
+        let mut config = Config::new()?;
+        config.add_isa_extension(IsaExtension::Mul);
+        config.add_isa_extension(IsaExtension::Mul);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = System::new(&config)?;
+            assert_eq!(system.data_path().extensions().names(), vec!["muldiv"]);
+            system.set_engine(*engine);
+            for (i, instruction) in program.iter().enumerate() {
+                system
+                    .get_mem_ref()
+                    .set_word(i as u32 * 4, instruction.encode())?;
+            }
+            let r = retire(&mut system, program.len())?;
+            assert_eq!(r.trap, None);
+            let regs = system.data_path().register_file();
+            assert_eq!(regs.read(18, 0) as i32, -21);
+            assert_eq_hex!(regs.read(19, 0), 6);
+            assert!(system.data_path().get_psw().get_cc_neg());
+
+            // The extension outlives a cold reset.
+            system.reset(Reset::Cold)?;
+            assert_eq!(system.data_path().extensions().names(), vec!["muldiv"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn traps_go_through_the_vector_table() -> Result<()> {
+        // Move the privilege violation handler, drop to user mode and trap.
+        let privilege = Trap::PrivilegeViolation.index() as u32;
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 16, MMU_BASE >> 13)),
+            NOP,
+            I::Add(SI::new(false, 16, 16, SS::Imm13(MMU_BASE & 0x1fff))),
+            I::Add(SI::new(false, 17, 0, SS::Imm13(0x800))),
+            NOP,
+            I::Stxw(SI::new(
+                false,
+                17,
+                16,
+                SS::Imm13(MMU_TRAP_VECTORS - MMU_BASE + 4 * privilege),
+            )),
+            I::PutPSW(SI::new(false, 0, 0, SS::Imm13(0))),
+            NOP,
+            I::GetLPC(SI::new(false, 18, 0, SS::Reg(0))),
+        ];
+        let mut config = Config::new()?;
+        config.set_trap_vectors(0x1000);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = System::new(&config)?;
+            system.set_engine(*engine);
+            for (i, instruction) in program.iter().enumerate() {
+                system
+                    .get_mem_ref()
+                    .set_word(i as u32 * 4, instruction.encode())?;
+            }
+            let vectors = system.data_path().get_mmu().get_trap_vectors();
+            assert_eq_hex!(vectors.get(Trap::Alignment(0)), 0x1000);
+            assert_eq_hex!(vectors.get(Trap::PrivilegeViolation), 0x1040);
+
+            system.data_path_mut().set_psw(SYSTEM_LOC);
+            let r = retire(&mut system, program.len())?;
+            assert_eq!(r.trap, Some(Trap::PrivilegeViolation));
+            for _ in 0..4 {
+                if system.data_path().get_pc() == 0x800 {
+                    break;
+                }
+                system.run_cycle()?;
+            }
+            assert_eq_hex!(system.data_path().get_pc(), 0x800);
+
+            // A reset puts the configured table back.
+            system.reset(Reset::Warm)?;
+            let vectors = system.data_path().get_mmu().get_trap_vectors();
+            assert_eq_hex!(vectors.get(Trap::PrivilegeViolation), 0x1040);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn regions_trap_denied_accesses() -> Result<()> {
+        let region = |base, size, name| Region {
+            base: base,
+            size: size,
+            permissions: Permissions::from_name(name).unwrap(),
+        };
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            // Stores into ROM fault, stores into RAM do not.
+            let program = [
+                I::Add(SI::new(false, 16, 0, SS::Imm13(0x55))),
+                NOP,
+                I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x30))),
+                I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x10))),
+            ];
+            let mut config = Config::new()?;
+            config.add_region(region(0, 0x20, "rom"));
+            config.add_region(region(0x20, 0x20, "ram"));
+            let mut system = make_system_with(&config, &program, *engine)?;
+            let before = system.get_mem_ref().get_word(0x10)?;
+            let r = retire(&mut system, 3)?;
+            assert_eq!(r.trap, None);
+            assert_eq_hex!(system.get_mem_ref().get_word(0x30)?, 0x55);
+            let r = retire(&mut system, 1)?;
+            assert_eq!(r.trap, Some(Trap::AccessViolation(0x10)));
+            assert_eq_hex!(system.get_mem_ref().get_word(0x10)?, before);
+            let dp = system.data_path();
+            assert_eq_hex!(dp.get_pc(), Trap::AccessViolation(0).vector());
+            assert_eq_hex!(dp.get_trap_cause(), 0x10);
+
+            // Running from RAM faults the fetch, which retires nothing.
+            let mut config = Config::new()?;
+            config.add_region(region(0, 0x10, "rom"));
+            config.add_region(region(0x10, 0x30, "ram"));
+            let mut system = make_system_with(&config, &[NOP; 8], *engine)?;
+            retire(&mut system, 4)?;
+            let vector = Trap::AccessViolation(0).vector();
+            for _ in 0..4 {
+                if system.data_path().get_pc() == vector {
+                    break;
+                }
+                assert_eq!(system.run_cycle()?, 0);
+            }
+            let dp = system.data_path();
+            assert_eq_hex!(dp.get_pc(), vector);
+            assert_eq_hex!(dp.get_trap_cause(), 0x10);
+
+            // Unless the code was loaded there.
+            let mut system = make_system_with(&config, &[], *engine)?;
+            let image: Vec<u8> = (0..8)
+                .flat_map(|_| NOP.encode().to_be_bytes().to_vec())
+                .collect();
+            Image::parse(&image, Format::Raw, 0)?.write_to(system.get_mem_ref())?;
+            let r = retire(&mut system, 8)?;
+            assert_eq!(r.trap, None);
+            assert_eq_hex!(r.pc, 0x1c);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn anomaly_detector_pauses_on_livelock() -> Result<()> {
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let program = [
+                NOP,
+                I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0))),
+                NOP,
+            ];
+            let mut system = make_system(&program, *engine)?;
+            system.set_anomaly_detector(Some(AnomalyDetector::new(3, true)));
+            system.run_until_halt()?;
+            assert!(system.is_paused());
+            let found = system.anomalies().unwrap().found();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].kind, AnomalyKind::Livelock(4));
+            assert_eq_hex!(found[0].retired.pc, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn anomaly_detector_finds_executed_data() -> Result<()> {
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let program = [
+                I::Ldxw(SI::new(false, 16, 0, SS::Imm13(0x10))),
+                NOP,
+                I::Stxw(SI::new(false, 16, 0, SS::Imm13(0x10))),
+                NOP,
+                I::Add(SI::new(false, 17, 0, SS::Imm13(1))),
+                I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x14))),
+                NOP,
+            ];
+            let mut system = make_system(&program, *engine)?;
+            system.set_anomaly_detector(Some(AnomalyDetector::new(100, false)));
+            system.run_until_halt()?;
+            assert!(!system.is_paused());
+            let found = system.anomalies().unwrap().found();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].kind, AnomalyKind::ExecutingData);
+            assert_eq_hex!(found[0].retired.pc, 0x10);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn anomaly_detector_counts_privilege_faults() -> Result<()> {
+        let mut system = make_system(&[NOP], Engine::Fast)?;
+        let mut r = retire(&mut system, 1)?;
+        r.trap = Some(Trap::PrivilegeViolation);
+        let mut detector = AnomalyDetector::new(100, false);
+        assert!(detector.observe(&r).is_none());
+        assert!(detector.observe(&r).is_none());
+        let anomaly = detector.observe(&r).unwrap();
+        assert_eq!(anomaly.kind, AnomalyKind::RepeatedFaults(3));
+        assert!(detector.observe(&r).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn calls_wrap_around_configured_windows() -> Result<()> {
+        let call = I::Callr(LongInstruction::new(false, RETURN_ADDRESS_REG, 8));
+        let program = [
+            I::Add(SI::new(false, 10, 0, SS::Imm13(7))),
+            call,
+            NOP,
+            call,
+            NOP,
+            call,
+            NOP,
+            call,
+            NOP,
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(36))),
+            NOP,
+        ];
+        let mut config = Config::new()?;
+        config.set_windows(4);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let mut system = System::new(&config)?;
+            system.set_engine(*engine);
+            for (i, instruction) in program.iter().enumerate() {
+                system
+                    .get_mem_ref()
+                    .set_word(i as u32 * 4, instruction.encode())?;
+            }
+            system.run_until_halt()?;
+            let dp = system.data_path();
+            // Four calls went all the way around, overflowing once.
+            assert_eq!(dp.get_psw().get_cwp(), 0);
+            assert_eq!(dp.get_psw().get_swp(), 1);
+            assert_eq!(dp.register_file().read(26, 3), 7);
+            assert_eq!(dp.register_file().read(10, 0), 7);
+        }
+        config.set_windows(9);
+        assert!(System::new(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cores_share_memory_and_know_their_id() -> Result<()> {
+        let mut config = Config::new()?;
+        config.set_ncpus(2);
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            // Store the core's number plus one at 0x30 + number * 4.
+            // The MMU's registers are in the top 4K, a negative
+            // displacement from r0 reaches them.
+            let program = [
+                I::Ldxw(SI::new(false, 17, 0, SS::Imm13(MMU_CPU_ID & 0x1fff))),
+                NOP,
+                I::Sll(SI::new(false, 18, 17, SS::Imm13(2))),
+                I::Add(SI::new(false, 19, 17, SS::Imm13(1))),
+                NOP,
+                I::Stxw(SI::new(false, 19, 18, SS::Imm13(0x30))),
+                I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x18))),
+                NOP,
+            ];
+            let mut system = make_system_with(&config, &program, *engine)?;
+            assert_eq!(system.cores().len(), 2);
+            system.run_until_halt()?;
+            // The other core runs in step with the boot core.
+            run_cycles(&mut system, 2)?;
+            assert_eq!(system.memory().get_word(0x30)?, 1);
+            assert_eq!(system.memory().get_word(0x34)?, 2);
+            assert_eq!(system.cores()[1].data_path().get_cpu_id(), 1);
+
+            system.reset(Reset::Cold)?;
+            assert_eq!(system.cores()[1].data_path().get_cpu_id(), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn doorbell_interrupts_one_core() -> Result<()> {
+        // Both cores ring core 1's doorbell, then halt.
+        let program = [
+            I::Ldhi(LongInstruction::new(false, 16, DOORBELL_BASE >> 13)),
+            I::Add(SI::new(false, 17, 0, SS::Imm13(1))),
+            NOP,
+            I::Stxw(SI::new(false, 17, 16, SS::Imm13(DOORBELL_RING & 0x1fff))),
+            I::Ldxw(SI::new(false, 18, 16, SS::Imm13(DOORBELL_PENDING & 0x1fff))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0x14))),
+            NOP,
+        ];
+        let mut config = Config::new()?;
+        config.set_ncpus(2);
+        let mut system = make_system_with(&config, &program, Engine::Cycle)?;
+        system.data_path_mut().set_psw(INTERRUPT_LOC);
+        system.cores_mut()[1].data_path_mut().set_psw(INTERRUPT_LOC);
+        let mut cycles = 0;
+        while !system.cores()[1].data_path().get_psw().get_system_mode() {
+            assert!(cycles < 20, "interrupt never taken");
+            run_cycles(&mut system, 1)?;
+            cycles += 1;
+        }
+        // Only core 1's doorbell rang.
+        assert!(!system.data_path().get_psw().get_system_mode());
+        assert_eq!(system.memory().get_word(DOORBELL_PENDING)?, 0x2);
+        // Writing the pending register acknowledges.
+        system.get_mem_ref().set_word(DOORBELL_PENDING, 0x2)?;
+        assert_eq!(system.memory().get_word(DOORBELL_PENDING)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn core_count_is_checked() -> Result<()> {
+        let mut config = Config::new()?;
+        config.set_ncpus(0);
+        assert!(System::new(&config).is_err());
+        config.set_ncpus(33);
+        assert!(System::new(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn entry_point_replaces_reset_vector() -> Result<()> {
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let program = [
+                // Skipped, the system starts past it.
+                I::Add(SI::new(false, 16, 0, SS::Imm13(1))),
+                I::Add(SI::new(false, 17, 0, SS::Imm13(2))),
+                NOP,
+            ];
+            let mut system = make_system(&program, *engine)?;
+            system.set_entry(4);
+            assert_eq_hex!(retire(&mut system, 1)?.pc, 4);
+            retire(&mut system, 1)?;
+            assert_eq!(system.data_path().register_file().read(16, 0), 0);
+            assert_eq!(system.data_path().register_file().read(17, 0), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn memory_fill_survives_cold_reset() -> Result<()> {
+        let mut system = make_system(&[NOP], Engine::Fast)?;
+        system.get_mem_ref().set_fill(MemoryFill::Deadbeef, 0);
+        assert_eq_hex!(system.memory().get_word(0)?, 0xdeadbeef);
+        assert_eq_hex!(system.memory().get_hword(0x22)?, 0xbeef);
+        system.get_mem_ref().set_word(0x10, 0)?;
+        system.reset(Reset::Cold)?;
+        assert_eq_hex!(system.memory().get_word(0x10)?, 0xdeadbeef);
+
+        system.get_mem_ref().set_fill(MemoryFill::Ones, 0);
+        assert_eq_hex!(system.memory().get_byte(0x3f)?, 0xff);
+        Ok(())
+    }
+
+    #[test]
+    fn random_memory_fill_depends_on_seed() -> Result<()> {
+        let words = |seed: u32| -> Result<Vec<u32>> {
+            let mut mem = Memory::from_size(0x40);
+            mem.set_fill(MemoryFill::Random, seed);
+            (0..0x10).map(|i| mem.get_word(i * 4)).collect()
+        };
+        assert_eq!(words(7)?, words(7)?);
+        assert_ne!(words(7)?, words(8)?);
+        assert!(words(0)?.iter().any(|w| *w != 0));
+        Ok(())
+    }
+
+    #[test]
+    fn poweron_state_fills_registers_and_memory() -> Result<()> {
+        let registers = |state: PowerOnState, seed: u32| -> Result<(Vec<u32>, u32)> {
+            let mut config = Config::new()?;
+            config.set_poweron_state(state, seed);
+            config.set_ncpus(2);
+            let system = System::new(&config)?;
+            let rf = system.cores()[1].data_path().register_file();
+            Ok((
+                (0..32).map(|r| rf.read(r, 0)).collect(),
+                system.memory().get_word(0)?,
+            ))
+        };
+        let (zero, word) = registers(PowerOnState::Zero, 7)?;
+        assert!(zero.iter().all(|r| *r == 0));
+        assert_eq!(word, 0);
+        let (pattern, word) = registers(PowerOnState::Pattern, 7)?;
+        assert_eq!(pattern[0], 0);
+        assert!(pattern[1..].iter().all(|r| *r == 0xdeadbeef));
+        assert_eq_hex!(word, 0xdeadbeef);
+        let (random, _) = registers(PowerOnState::Random, 7)?;
+        assert_eq!(random[0], 0);
+        assert_eq!(random, registers(PowerOnState::Random, 7)?.0);
+        assert_ne!(random, registers(PowerOnState::Random, 8)?.0);
+
+        // A cold reset powers on again, the same way.
+        let mut config = Config::new()?;
+        config.set_poweron_state(PowerOnState::Random, 7);
+        let mut system = System::new(&config)?;
+        let cwp = system.data_path().psw().get_cwp();
+        let before = system.data_path().register_file().read(16, cwp);
+        system.pause();
+        system.set_register(0, 16, before ^ 1)?;
+        system.reset(Reset::Cold)?;
+        assert_eq!(system.data_path().register_file().read(16, cwp), before);
+        Ok(())
+    }
+
+    #[test]
+    fn decode_cache_sees_stores_over_code() -> Result<()> {
+        // Count to 3, then patch the counting instruction to add 100 and
+        // count twice more.
+        let program = [
+            I::Add(SI::new(false, 16, 0, SS::Imm13(3))),
+            I::Add(SI::new(false, 17, 17, SS::Imm13(1))),
+            I::Sub(SI::new(true, 16, 16, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Ne, 0, SS::Imm13(4))),
+            NOP,
+            I::Sub(SI::new(true, 0, 19, SS::Imm13(0))),
+            I::Jmpx(SC::new(false, Conditional::Ne, 0, SS::Imm13(56))),
+            NOP,
+            I::Ldxw(SI::new(false, 18, 0, SS::Imm13(0x100))),
+            I::Add(SI::new(false, 19, 0, SS::Imm13(1))),
+            I::Stxw(SI::new(false, 18, 0, SS::Imm13(4))),
+            I::Add(SI::new(false, 16, 0, SS::Imm13(2))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(4))),
+            NOP,
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(56))),
+            NOP,
+        ];
+        let patch = I::Add(SI::new(false, 17, 17, SS::Imm13(100)));
+        let run = |config: &Config, engine: Engine| -> Result<System> {
+            let mut system = System::new(config)?;
+            system.set_engine(engine);
+            for (i, instruction) in program.iter().enumerate() {
+                system
+                    .get_mem_ref()
+                    .set_word(i as u32 * 4, instruction.encode())?;
+            }
+            system.get_mem_ref().set_word(0x100, patch.encode())?;
+            system.run_until_halt()?;
+            Ok(system)
+        };
+        let mut config = Config::new()?;
+        config.set_mem_size(0x800);
+        config.set_decode_cache(16);
+        let system = run(&config, Engine::Fast)?;
+        assert_eq!(system.data_path().register_file().read(17, 0), 203);
+        let stats = system.decode_cache_stats().unwrap();
+        // The loop hits. Every instruction misses once, but the last delay
+        // slot, which never runs, and the patched one twice.
+        assert!(stats.hits > stats.misses, "{:?}", stats);
+        assert_eq!(stats.misses, program.len() as u64);
+
+        // The same without a cache, or on the cycle engine.
+        config.set_decode_cache(0);
+        let system = run(&config, Engine::Fast)?;
+        assert_eq!(system.data_path().register_file().read(17, 0), 203);
+        assert_eq!(system.decode_cache_stats(), None);
+        let system = run(&config, Engine::Cycle)?;
+        assert_eq!(system.data_path().register_file().read(17, 0), 203);
+        assert_eq!(system.decode_cache_stats(), None);
+
+        config.set_decode_cache(1000);
+        assert!(System::new(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn debugger_edits_registers_and_memory() -> Result<()> {
+        let program = [
+            I::Add(SI::new(false, 17, 17, SS::Imm13(1))),
+            I::Jmpx(SC::new(false, Conditional::Alw, 0, SS::Imm13(0))),
+            NOP,
+        ];
+        let mut system = make_system(&program, Engine::Fast)?;
+        for _ in 0..3 {
+            system.step()?;
+        }
+        assert_eq!(system.data_path().register_file().read(17, 0), 1);
+        assert!(system.set_register(0, 17, 0x1000).is_err());
+        assert!(system.set_memory_word(0, 0).is_err());
+
+        system.pause();
+        system.set_register(0, 17, 0x1000)?;
+        system.set_register(0, 0, 5)?;
+        assert!(system.set_register(0, 32, 5).is_err());
+        assert!(system.set_register(1, 17, 5).is_err());
+        assert!(system.set_memory_word(2, 0).is_err());
+        let patch = I::Add(SI::new(false, 17, 17, SS::Imm13(100)));
+        system.set_memory_word(0, patch.encode())?;
+        assert_eq_hex!(system.memory().get_word(0)?, patch.encode());
+        system.set_memory(0x21, &[0xab])?;
+        assert_eq!(system.memory().get_bytes(0x20, 2)?, &[0, 0xab]);
+
+        // The cached decode of the old instruction is not used.
+        for _ in 0..3 {
+            system.step()?;
+        }
+        assert_eq!(system.data_path().register_file().read(17, 0), 0x1064);
+        assert_eq!(system.data_path().register_file().read(0, 0), 0);
+        assert_eq!(system.decode_cache_stats().unwrap().misses, 4);
+        Ok(())
+    }
+}
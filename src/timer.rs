@@ -0,0 +1,126 @@
+// Interval timer device.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Counts clock cycles down from a period and expires when it reaches zero,
+// then starts over. Expiring sets `TIMER_EXPIRED` in the status register and,
+// if the control register enables it, requests an interrupt until the guest
+// acknowledges it by writing the bit back. Writing the period restarts the
+// count, a period of 0 stops the timer. This is what a guest scheduler
+// preempts its tasks with.
+
+use device::{Device, Reset};
+use r2d2::Writer;
+use std::ops::Range;
+
+/// Base address of the timer's registers.
+pub const TIMER_BASE: u32 = 0xffff0090;
+/// Period register, the number of cycles between expiries. Writing it
+/// restarts the count.
+pub const TIMER_PERIOD: u32 = TIMER_BASE;
+/// Count register, the number of cycles until the timer next expires (read
+/// only).
+pub const TIMER_COUNT: u32 = TIMER_BASE + 4;
+/// Control register. Bit 0 enables the interrupt.
+pub const TIMER_CONTROL: u32 = TIMER_BASE + 8;
+/// Status register. Writing it clears the bits set in the value written.
+pub const TIMER_STATUS: u32 = TIMER_BASE + 12;
+/// Size of the timer's register block.
+const TIMER_SIZE: u32 = 16;
+/// Control bit that raises an interrupt while the timer has expired.
+pub const TIMER_INTERRUPT_ENABLE: u32 = 0x1;
+/// Status bit set when the timer expires.
+pub const TIMER_EXPIRED: u32 = 0x1;
+
+/// Interval timer counting clock cycles.
+pub struct Timer {
+    /// Contents of the period register.
+    period: u32,
+    /// Cycles until the timer expires.
+    count: u32,
+    /// Contents of the control register.
+    control: u32,
+    /// Contents of the status register.
+    status: u32,
+}
+
+impl Timer {
+    /// Create a stopped timer.
+    pub fn new() -> Self {
+        Self {
+            period: 0,
+            count: 0,
+            control: 0,
+            status: 0,
+        }
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn reset(&mut self, _kind: Reset) {
+        *self = Self::new();
+    }
+
+    fn mapping(&self) -> Option<Range<u32>> {
+        Some(TIMER_BASE..TIMER_BASE + TIMER_SIZE)
+    }
+
+    fn read(&mut self, addr: u32) -> u32 {
+        match addr & !0x3 {
+            TIMER_PERIOD => self.period,
+            TIMER_COUNT => self.count,
+            TIMER_CONTROL => self.control,
+            TIMER_STATUS => self.status,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u32, value: u32) {
+        match addr & !0x3 {
+            TIMER_PERIOD => {
+                self.period = value;
+                self.count = value;
+            }
+            TIMER_CONTROL => self.control = value,
+            TIMER_STATUS => self.status &= !value,
+            _ => {}
+        }
+    }
+
+    fn fingerprint(&self, w: &mut Writer) {
+        w.put_u32(self.period);
+        w.put_u32(self.count);
+        w.put_u32(self.control);
+        w.put_u32(self.status);
+    }
+
+    fn tick(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.count -= 1;
+        if self.count == 0 {
+            self.status |= TIMER_EXPIRED;
+            self.count = self.period;
+        }
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.control & TIMER_INTERRUPT_ENABLE != 0 && self.status & TIMER_EXPIRED != 0
+    }
+}
@@ -0,0 +1,280 @@
+// Execution trace logging.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Traces only hold guest state, never host time, so running the same program
+// twice produces the same trace.
+//
+// A binary trace is the magic bytes and the format version, followed by one
+// record per retired instruction. All values are big endian:
+//   u64 cycle, u32 pc, u32 opcode,
+//   u8 flags (bit 0: dest, bit 1: memory access, bit 2: store, bit 3: branch),
+//   u8 rd, u32 rd value,
+//   u32 access address, u8 access width, u32 access value,
+//   u16 psw before, u16 psw after, u32 branch target,
+//   u8 trap (0: none, 1: alignment, 2: privilege violation, 3: coprocessor,
+//   4: interrupt, 5: access violation, 6: illegal instruction),
+//   u32 trap address (the cause for a coprocessor trap).
+// Fields the flags mark absent are 0.
+
+use commit::RetiredInstruction;
+use config::{Syntax, TraceFormat};
+use cpu::{register_name, Trap};
+use error::EmulatorError;
+use std::fs;
+use std::io::{BufWriter, Write};
+use util::Result;
+
+
+/// Bytes every binary trace starts with.
+pub const MAGIC: &[u8; 4] = b"R2TR";
+/// Version of the binary trace format.
+pub const VERSION: u32 = 1;
+/// Size of a binary trace record in bytes.
+pub const RECORD_SIZE: usize = 44;
+
+const FLAG_DEST: u8 = 1;
+const FLAG_ACCESS: u8 = 1 << 1;
+const FLAG_STORE: u8 = 1 << 2;
+const FLAG_BRANCH: u8 = 1 << 3;
+
+/// Writes a record of every retired instruction.
+pub struct Tracer {
+    /// Where the trace goes.
+    out: Box<dyn Write>,
+    /// Format of the trace.
+    format: TraceFormat,
+    /// Path of the trace file, None if it is not written to a file.
+    path: Option<String>,
+    /// Syntax of instructions in a text trace.
+    syntax: Syntax,
+    /// Bytes written to the trace.
+    size: u64,
+    /// Bytes in the previous trace file, 0 if there is none.
+    old_size: u64,
+}
+
+impl Tracer {
+    /// Start a trace written to `out`. Return the tracer on success and a
+    /// string on error.
+    /// # Arguments
+    /// * `out` - Where to write the trace.
+    /// * `format` - Format of the trace.
+    pub fn new(mut out: Box<dyn Write>, format: TraceFormat) -> Result<Self> {
+        let size = write_header(&mut out, format)?;
+        Ok(Self {
+            out: out,
+            format: format,
+            path: None,
+            syntax: Syntax::Modern,
+            size: size,
+            old_size: 0,
+        })
+    }
+
+    /// Start a trace written to a file, replacing it if it exists. Return
+    /// the tracer on success and a string on error.
+    /// # Arguments
+    /// * `path` - Path of the trace file.
+    /// * `format` - Format of the trace.
+    pub fn create(path: &String, format: TraceFormat) -> Result<Self> {
+        let mut tracer = Self::new(create_file(path)?, format)?;
+        tracer.path = Some(path.clone());
+        Ok(tracer)
+    }
+
+    /// Log a retired instruction. Return void on success and a string on
+    /// error.
+    /// # Arguments
+    /// * `retired` - The retired instruction.
+    pub fn record(&mut self, retired: &RetiredInstruction) -> Result<()> {
+        let record = match self.format {
+            TraceFormat::Text => format!("{}\n", text_record(retired, self.syntax)).into_bytes(),
+            TraceFormat::Binary => binary_record(retired),
+        };
+        self.out.write_all(&record)?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Change the syntax instructions are written in, from the next record.
+    /// Binary traces are not affected.
+    /// # Arguments
+    /// * `syntax` - Syntax to disassemble in.
+    pub fn set_syntax(&mut self, syntax: Syntax) {
+        self.syntax = syntax;
+    }
+
+    /// Get the bytes the trace takes, including the previous trace file.
+    pub fn size(&self) -> u64 {
+        self.size + self.old_size
+    }
+
+    /// Throw away the oldest part of a trace written to a file. The first
+    /// time the trace is moved to the file's path with `.old` appended and a
+    /// new one is started, the next time that file is deleted. Return true
+    /// on success if anything was thrown away and a string on error.
+    pub fn evict(&mut self) -> Result<bool> {
+        let path = match self.path.as_ref() {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        let old_path = format!("{}.old", path);
+        if self.old_size > 0 {
+            fs::remove_file(&old_path)?;
+            self.old_size = 0;
+            return Ok(true);
+        }
+        let mut header = Vec::new();
+        let header_size = write_header(&mut header, self.format)?;
+        if self.size <= header_size {
+            return Ok(false);
+        }
+        self.out.flush()?;
+        fs::rename(path, &old_path)?;
+        self.out = create_file(path)?;
+        self.out.write_all(&header)?;
+        self.old_size = self.size;
+        self.size = header_size;
+        Ok(true)
+    }
+
+    /// Write out anything buffered. Return void on success and a string on
+    /// error.
+    pub fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Format a retired instruction as a line of text, e.g.
+/// `3 00000008 6c802020 stxw r16, r0, 0x20 st4 [00000020] = 00000002`.
+/// # Arguments
+/// * `r` - The retired instruction.
+/// * `syntax` - Syntax to disassemble the instruction in.
+pub fn text_record(r: &RetiredInstruction, syntax: Syntax) -> String {
+    let mut line = format!(
+        "{} {:08x} {:08x} {}",
+        r.cycle,
+        r.pc,
+        r.instruction.encode(),
+        r.instruction.disassemble(syntax)
+    );
+    if let Some((rd, value)) = r.dest {
+        line += &format!(" {} = {:08x}", register_name(rd), value);
+    }
+    if let Some(access) = r.access {
+        line += &format!(
+            " {}{} [{:08x}] = {:08x}",
+            if access.store { "st" } else { "ld" },
+            access.width,
+            access.address,
+            access.value
+        );
+    }
+    if r.psw_before != r.psw_after {
+        line += &format!(" psw {} -> {}", r.psw_before, r.psw_after);
+    }
+    if let Some(target) = r.branch {
+        line += &format!(" branch {:08x}", target);
+    }
+    if let Some(trap) = r.trap {
+        line += &format!(" trap: {}", trap);
+    }
+    line
+}
+
+/// Encode a retired instruction as a binary trace record.
+/// # Arguments
+/// * `r` - The retired instruction.
+pub fn binary_record(r: &RetiredInstruction) -> Vec<u8> {
+    let mut flags = 0u8;
+    let (rd, rd_value) = match r.dest {
+        Some(d) => {
+            flags |= FLAG_DEST;
+            d
+        }
+        None => (0, 0),
+    };
+    let (address, width, value) = match r.access {
+        Some(access) => {
+            flags |= FLAG_ACCESS;
+            if access.store {
+                flags |= FLAG_STORE;
+            }
+            (access.address, access.width as u8, access.value)
+        }
+        None => (0, 0, 0),
+    };
+    let branch = match r.branch {
+        Some(target) => {
+            flags |= FLAG_BRANCH;
+            target
+        }
+        None => 0,
+    };
+    let (trap, trap_address) = match r.trap {
+        None => (0u8, 0),
+        Some(Trap::Alignment(addr)) => (1, addr),
+        Some(Trap::PrivilegeViolation) => (2, 0),
+        Some(Trap::Coprocessor(cause)) => (3, cause),
+        Some(Trap::Interrupt) => (4, 0),
+        Some(Trap::AccessViolation(addr)) => (5, addr),
+        Some(Trap::IllegalInstruction) => (6, 0),
+    };
+
+    let mut buf = Vec::with_capacity(RECORD_SIZE);
+    buf.extend_from_slice(&r.cycle.to_be_bytes());
+    buf.extend_from_slice(&r.pc.to_be_bytes());
+    buf.extend_from_slice(&r.instruction.encode().to_be_bytes());
+    buf.push(flags);
+    buf.push(rd);
+    buf.extend_from_slice(&rd_value.to_be_bytes());
+    buf.extend_from_slice(&address.to_be_bytes());
+    buf.push(width);
+    buf.extend_from_slice(&value.to_be_bytes());
+    buf.extend_from_slice(&r.psw_before.get().to_be_bytes());
+    buf.extend_from_slice(&r.psw_after.get().to_be_bytes());
+    buf.extend_from_slice(&branch.to_be_bytes());
+    buf.push(trap);
+    buf.extend_from_slice(&trap_address.to_be_bytes());
+    buf
+}
+
+// Private functions.
+
+/// Write the start of a trace. Return the number of bytes written on
+/// success and a string on error.
+/// # Arguments
+/// * `out` - Where the trace goes.
+/// * `format` - Format of the trace.
+fn write_header<W: Write>(out: &mut W, format: TraceFormat) -> Result<u64> {
+    if format != TraceFormat::Binary {
+        return Ok(0);
+    }
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_be_bytes())?;
+    Ok((MAGIC.len() + 4) as u64)
+}
+
+/// Create a trace file, replacing it if it exists. Return a writer for it
+/// on success and a string on error.
+/// # Arguments
+/// * `path` - Path of the trace file.
+fn create_file(path: &String) -> Result<Box<dyn Write>> {
+    match fs::File::create(path) {
+        Ok(f) => Ok(Box::new(BufWriter::new(f))),
+        Err(e) => Err(EmulatorError::io(format!("Could not create trace {}", path), e)),
+    }
+}
@@ -0,0 +1,86 @@
+// Host memory usage accounting.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Counts what a session costs the host, so a long interactive session does
+// not quietly eat all of it. Guest memory is allocated once, everything else
+// grows as the session goes on:
+//   Bookkeeping - tables the anomaly detector keeps about the guest.
+//   Trace - execution trace written so far, including the previous trace
+//     file kept when it was rotated.
+//   Snapshots - snapshot files taken during the session.
+// With a limit set the system evicts old data whenever the total goes over
+// it: first the trace (rotating it, then dropping the previous file), then
+// the detector's tables, then the oldest periodic and triggered snapshots.
+// Snapshots the user took and guest memory are never evicted.
+
+use std::fmt;
+
+/// Bytes in a megabyte.
+pub const MEGABYTE: u64 = 1024 * 1024;
+
+/// Host memory used by the emulator, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostUsage {
+    /// Guest memory.
+    pub guest: u64,
+    /// Tables kept about the guest while it runs.
+    pub bookkeeping: u64,
+    /// Execution trace.
+    pub trace: u64,
+    /// Snapshots taken during the session.
+    pub snapshots: u64,
+}
+
+impl HostUsage {
+    /// Get the total number of bytes used.
+    pub fn total(&self) -> u64 {
+        self.guest + self.bookkeeping + self.trace + self.snapshots
+    }
+}
+
+impl fmt::Display for HostUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Host memory usage:")?;
+        for (name, bytes) in [
+            ("guest memory", self.guest),
+            ("bookkeeping", self.bookkeeping),
+            ("trace", self.trace),
+            ("snapshots", self.snapshots),
+            ("total", self.total()),
+        ]
+        .iter()
+        {
+            writeln!(f, "  {:<13} {}", name, format_bytes(*bytes))?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a number of bytes with a binary unit, e.g. `1.5 KiB`.
+/// # Arguments
+/// * `bytes` - Number of bytes.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
@@ -13,25 +13,23 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use std::boxed::Box;
+use error::EmulatorError;
 use std::env;
-use std::error::Error;
 use std::ffi::OsString;
 use std::fs;
 use std::fs::{Metadata, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::path::Path;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+pub type Result<T> = std::result::Result<T, EmulatorError>;
 
+/// Return an error of a kind of `EmulatorError` holding a message, e.g.
+/// `berr!(Config, format!("Invalid engine: {}", name))`.
 #[macro_export]
 macro_rules! berr {
-    ( $( $x:expr ),* ) => {
-        {
-            let b: Box<dyn std::error::Error> = $( $x.into() )*;
-            Err(b)
-        }
+    ( $kind:ident, $x:expr ) => {
+        Err($crate::error::EmulatorError::$kind($x.into()))
     };
 }
 
@@ -40,6 +38,12 @@ macro_rules! berr {
 /// Most significant bit of a u32.
 pub const U32_MSB: u32 = 0x80000000;
 
+// Private constants.
+
+/// Digits of base64, by value.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
 // Public struct definitions.
 
 /// File object. Wrapper around fs::File but caches more data.
@@ -50,6 +54,14 @@ pub struct File {
     path: String,
 }
 
+/// Xorshift generator, the same numbers for the same seed so a run that
+/// starts from random state can be reproduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Xorshift {
+    /// Last number generated.
+    state: u32,
+}
+
 // Public function definitions.
 
 /// Return a file's contents as a byte vector on success and a string on error.
@@ -66,16 +78,82 @@ pub fn read_file_path(path: &String) -> Result<Vec<u8>> {
 pub fn concat_paths(base: &String, rest: &String) -> Result<String> {
     let p = Path::new(&base).join(&rest);
     match p.to_str() {
-        None => berr!(format!("{} and {} joined is not valid utf8", base, rest)),
+        None => berr!(Config, format!("{} and {} joined is not valid utf8", base, rest)),
         Some(s) => Ok(s.to_string()),
     }
 }
 
+/// Expand a leading `~` to the user's home directory and every `$NAME` or
+/// `${NAME}` to the value of that environment variable. A `$` not followed
+/// by a name is kept. Return the expanded path on success and a string on
+/// error, if a variable is not set.
+/// # Arguments
+/// * `path` - Path to expand.
+pub fn expand_path(path: &str) -> Result<String> {
+    let mut out = String::new();
+    let rest = if path == "~" || path.starts_with("~/") {
+        out.push_str(&get_home_nofail());
+        &path[1..]
+    } else {
+        path
+    };
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        let braced = chars.peek() == Some(&'{');
+        if braced {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some(&n) = chars.peek() {
+            if !n.is_ascii_alphanumeric() && n != '_' {
+                break;
+            }
+            name.push(n);
+            chars.next();
+        }
+        if braced && (name.is_empty() || chars.next() != Some('}')) {
+            return berr!(Config, format!("Invalid variable in {}", path));
+        }
+        if name.is_empty() {
+            out.push('$');
+            continue;
+        }
+        match env::var(&name) {
+            Ok(v) => out.push_str(&v),
+            Err(_) => return berr!(Config, format!("${} in {} is not set", name, path)),
+        }
+    }
+    Ok(out)
+}
+
+/// Get a base directory of the XDG base directory specification: the
+/// variable's value if it is an absolute path, as the specification
+/// requires, or else a directory in the user's home directory. Return the
+/// directory on success and a string on error.
+/// # Arguments
+/// * `var` - Environment variable naming the directory, e.g.
+/// `XDG_CONFIG_HOME`.
+/// * `fallback` - Directory in the user's home directory to use instead,
+/// e.g. `.config`.
+pub fn xdg_dir(var: &str, fallback: &str) -> Result<String> {
+    match env::var(var) {
+        Ok(v) if Path::new(&v).is_absolute() => Ok(v),
+        _ => concat_paths(&get_home_nofail(), &fallback.to_string()),
+    }
+}
+
 /// Get the current unix timestamp on success and a string on error.
 pub fn get_unix_timestamp() -> Result<Duration> {
     match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(r) => Ok(r),
-        Err(e) => berr!(format!("Could not format unix timestamp: {}", e)),
+        Err(e) => Err(EmulatorError::io(
+            format!("Could not format unix timestamp"),
+            io::Error::new(io::ErrorKind::Other, e),
+        )),
     }
 }
 
@@ -84,7 +162,9 @@ pub fn get_unix_timestamp() -> Result<Duration> {
 /// * `r` - Result to convert.
 pub fn os_string_result_to_strings(r: std::result::Result<String, OsString>) -> Result<String> {
     match r {
-        Err(e) => berr!(match e.into_string() {
+        Err(e) => berr!(
+            Config,
+            match e.into_string() {
             Ok(s) => s,
             Err(ee) => "Could not coerce OS string into utf8 string".to_string(),
         }),
@@ -97,15 +177,15 @@ pub fn os_string_result_to_strings(r: std::result::Result<String, OsString>) ->
 pub fn get_home_nofail() -> String {
     match env::var("HOME") {
         Ok(v) => format!("{}", v),
-        Err(e) => {
-            eprintln!("$HOME is not set. Defaulting to current directory.");
+        Err(_) => {
+            warn!("$HOME is not set. Defaulting to current directory.");
             format!(
                 "{}",
                 match env::current_dir() {
                     Ok(r) => match os_string_result_to_strings(r.into_os_string().into_string()) {
                         Ok(rr) => rr,
                         Err(ee) => {
-                            eprintln!("Could not get current dir as utf8 string. Defaulting to nothing for $HOME: {}", e);
+                            warn!("Could not get current dir as utf8 string. Defaulting to nothing for $HOME: {}", ee);
                             String::new()
                         }
                     },
@@ -116,24 +196,81 @@ pub fn get_home_nofail() -> String {
     }
 }
 
-pub fn check_hword_alignment(addr: u32) -> Result<()> {
-    if addr & 0x1 != 0 {
-        berr!(format!("Bad half word alignment: 0x{:x}", addr))
-    } else {
-        Ok(())
+/// Encode bytes as base64, with padding.
+/// # Arguments
+/// * `bytes` - Bytes to encode.
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64_ALPHABET[((group >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
     }
+    result
 }
 
-pub fn check_word_alignment(addr: u32) -> Result<()> {
-    if addr & 0x3 != 0 {
-        berr!(format!("Bad word alignment: 0x{:x}", addr))
-    } else {
-        Ok(())
+/// Decode base64 with padding. Return the bytes on success and a string on
+/// error.
+/// # Arguments
+/// * `s` - Base64 to decode.
+pub fn base64_decode(s: &str) -> Result<Vec<u8>> {
+    if s.len() % 4 != 0 {
+        return berr!(
+            Format,
+            format!("Base64 of length {} is not padded", s.len())
+        );
+    }
+    let mut result = Vec::with_capacity(s.len() / 4 * 3);
+    let last = s.len() / 4;
+    for (n, chunk) in s.as_bytes().chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && n + 1 != last) {
+            return berr!(Format, "Base64 padding before the end");
+        }
+        let mut group = 0u32;
+        for (i, c) in chunk[..4 - padding].iter().enumerate() {
+            match BASE64_ALPHABET.iter().position(|a| a == c) {
+                Some(v) => group |= (v as u32) << (18 - 6 * i),
+                None => return berr!(Format, format!("Invalid base64 character {}", *c as char)),
+            }
+        }
+        for i in 0..3 - padding {
+            result.push((group >> (16 - 8 * i)) as u8);
+        }
     }
+    Ok(result)
 }
 
 // Struct impls.
 
+impl Xorshift {
+    /// Create a generator.
+    /// # Arguments
+    /// * `seed` - Seed of the numbers generated.
+    pub fn new(seed: u32) -> Self {
+        Self {
+            // Xorshift gets stuck on 0.
+            state: if seed == 0 { 0x9e3779b9 } else { seed },
+        }
+    }
+
+    /// Get the next number.
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+}
+
 impl File {
     /// Open a file from a path. Return File on success and a string on error.
     /// # Arguments
@@ -144,7 +281,7 @@ impl File {
                 file: r,
                 path: format!("{}", path),
             }),
-            Err(e) => berr!(format!("Could not open file {}: {}", path, e)),
+            Err(e) => Err(EmulatorError::io(format!("Could not open file {}", path), e)),
         }
     }
 
@@ -158,7 +295,7 @@ impl File {
                 file: r,
                 path: format!("{}", path),
             }),
-            Err(e) => berr!(format!("Could not open file {}: {}", path, e)),
+            Err(e) => Err(EmulatorError::io(format!("Could not open file {}", path), e)),
         }
     }
 
@@ -170,7 +307,7 @@ impl File {
     pub fn read_into_vec(&mut self, buf: &mut Vec<u8>) -> Result<()> {
         match self.file.read_exact(&mut buf[..]) {
             Ok(r) => Ok(()),
-            Err(e) => berr!(format!("Failed to read file {}, {}", self.path, e)),
+            Err(e) => Err(EmulatorError::io(format!("Failed to read file {}", self.path), e)),
         }
     }
 
@@ -189,7 +326,7 @@ impl File {
     pub fn get_metadata(&mut self) -> Result<Metadata> {
         match self.file.metadata() {
             Ok(r) => Ok(r),
-            Err(e) => berr!(format!("Could not read metadata for {}: {}", self.path, e)),
+            Err(e) => Err(EmulatorError::io(format!("Could not read metadata for {}", self.path), e)),
         }
     }
 
@@ -201,7 +338,7 @@ impl File {
     pub fn read(&mut self, buf: &mut [u8]) -> Result<()> {
         match self.file.read_exact(buf) {
             Ok(r) => Ok(()),
-            Err(e) => berr!(format!("Could not read buffer from {}: {}", self.path, e)),
+            Err(e) => Err(EmulatorError::io(format!("Could not read buffer from {}", self.path), e)),
         }
     }
 
@@ -212,9 +349,9 @@ impl File {
     pub fn write_buf(&mut self, buf: &[u8]) -> Result<()> {
         match self.file.write_all(buf) {
             Ok(r) => Ok(()),
-            Err(e) => berr!(format!(
-                "Could not write byte buffer to {}: {}",
-                self.path, e
+            Err(e) => Err(EmulatorError::io(
+                format!("Could not write byte buffer to {}", self.path),
+                e,
             )),
         }
     }
@@ -226,9 +363,9 @@ impl File {
     pub fn write_vec(&mut self, buf: &Vec<u8>) -> Result<()> {
         match self.file.write_all(&buf[..]) {
             Ok(r) => Ok(()),
-            Err(e) => berr!(format!(
-                "Could not write byte buffer to {}: {}",
-                self.path, e
+            Err(e) => Err(EmulatorError::io(
+                format!("Could not write byte buffer to {}", self.path),
+                e,
             )),
         }
     }
@@ -0,0 +1,263 @@
+// RISC II emulator windows.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// The windows of the front-end. Events are passed to the window they
+// happened in, events of a window that was closed since are dropped.
+// Closing the main window quits, closing the debug window only closes it,
+// and F12 in any window opens or closes the debug window.
+//
+// The system runs a frame at a time between drawing the windows, on this
+// thread or a thread of its own, and the windows are drawn at most once a
+// frame time, only if the system ran or something happened to them since
+// they were last drawn. The debug window looks at the whole system, so it
+// can only be opened when the system runs on this thread.
+
+use config::Config;
+use debug_window::DebugWindow;
+use main_window::{Link, MainWindow};
+use sdl::{Context, Drawable};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::ttf::Sdl2TtfContext;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+use std::time::Instant;
+use system::{System, FRAME_TIME};
+use util::Result;
+
+/// Key opening and closing the debug window.
+const DEBUG_WINDOW_KEY: Keycode = Keycode::F12;
+
+/// What the front-end should do after handling events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlobalAction {
+    None,
+    QuitProgram,
+    CloseDebugWindow,
+    OpenDebugWindow,
+}
+
+/// The windows of the front-end.
+pub struct WindowManager<'a> {
+    config: &'a Config,
+    /// The system if it runs on this thread.
+    system: Option<Rc<RefCell<System>>>,
+    /// Font context the windows load their fonts from.
+    ttf: &'a Sdl2TtfContext,
+    /// Window showing the system's display.
+    main_window: MainWindow<'a>,
+    /// The debug window, None if closed.
+    debug_window: Option<DebugWindow<'a>>,
+    /// When the windows may be drawn next.
+    next_draw: Instant,
+    /// Clock cycle the windows were last drawn at, None if never drawn.
+    drawn_cycle: Option<u64>,
+    /// True if something happened to a window since it was drawn.
+    dirty: bool,
+}
+
+impl<'a> WindowManager<'a> {
+    /// Open the main window, and the debug window in debug mode. Return the
+    /// windows on success and an error if one could not be opened.
+    /// # Arguments
+    /// * `config` - Configuration of the windows.
+    /// * `link` - Where the system the windows show is.
+    /// * `context` - SDL context.
+    /// * `ttf` - Font context the windows load their fonts from.
+    pub fn new(
+        config: &'a Config,
+        link: Link,
+        context: &mut Context,
+        ttf: &'a Sdl2TtfContext,
+    ) -> Result<Self> {
+        let main_window = MainWindow::new(config, link, context, ttf)?;
+        let mut result = Self {
+            config: config,
+            system: main_window.local_system(),
+            main_window: main_window,
+            ttf: ttf,
+            debug_window: None,
+            next_draw: Instant::now(),
+            drawn_cycle: None,
+            dirty: true,
+        };
+        if config.is_debug_mode() {
+            result.open_debug_window(context)?;
+        }
+        Ok(result)
+    }
+
+    /// Open the debug window, doing nothing if it is open.
+    /// # Arguments
+    /// * `context` - SDL context.
+    pub fn open_debug_window(&mut self, context: &mut Context) -> Result<()> {
+        let system = match self.system.as_ref() {
+            Some(system) => system.clone(),
+            None => {
+                eprintln!("The debug window needs --debug, the system runs on another thread.");
+                return Ok(());
+            }
+        };
+        if self.debug_window.is_none() {
+            self.debug_window = Some(DebugWindow::new(self.config, system, context, self.ttf)?);
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Close the debug window, doing nothing if it is closed.
+    pub fn close_debug_window(&mut self) {
+        self.debug_window = None;
+    }
+
+    /// Get an open window.
+    /// # Arguments
+    /// * `id` - Id of the window.
+    fn window_mut(&mut self, id: u32) -> Option<&mut dyn Drawable> {
+        if self.main_window.get_window_id() == id {
+            return Some(&mut self.main_window);
+        }
+        match self.debug_window.as_mut() {
+            Some(win) if win.get_window_id() == id => Some(win),
+            _ => None,
+        }
+    }
+
+    /// Pass pending events to the window they happened in. Return what
+    /// the front-end should do next.
+    /// # Arguments
+    /// * `context` - SDL context.
+    fn poll_events(&mut self, context: &mut Context) -> GlobalAction {
+        let main_id = self.main_window.get_window_id();
+        let debug_id = self.debug_window.as_ref().map(|w| w.get_window_id());
+        let mut action = GlobalAction::None;
+        for event in context.event_pump.poll_iter() {
+            if context.controllers.handle_event(&event, &self.main_window) {
+                continue;
+            }
+            self.dirty = true;
+            match event {
+                Event::Quit { .. } => return GlobalAction::QuitProgram,
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } if window_id == main_id => return GlobalAction::QuitProgram,
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } if Some(window_id) == debug_id => action = GlobalAction::CloseDebugWindow,
+                Event::KeyDown {
+                    keycode: Some(DEBUG_WINDOW_KEY),
+                    repeat,
+                    ..
+                } => {
+                    if !repeat {
+                        action = if debug_id.is_some() {
+                            GlobalAction::CloseDebugWindow
+                        } else {
+                            GlobalAction::OpenDebugWindow
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    window_id,
+                    keycode: Some(kc),
+                    ..
+                } => {
+                    if let Some(win) = self.window_mut(window_id) {
+                        win.handle_key_down(kc);
+                    }
+                }
+                Event::KeyUp {
+                    window_id,
+                    keycode: Some(kc),
+                    ..
+                } => {
+                    if let Some(win) = self.window_mut(window_id) {
+                        win.handle_key_up(kc);
+                    }
+                }
+                Event::MouseButtonDown {
+                    window_id, x, y, ..
+                } => {
+                    if let Some(win) = self.window_mut(window_id) {
+                        win.handle_mouse_down(x, y);
+                    }
+                }
+                _ => {}
+            }
+        }
+        action
+    }
+
+    /// Handle pending events, opening or closing the debug window if asked
+    /// to. Return QuitProgram if the front-end should quit, None otherwise.
+    /// # Arguments
+    /// * `context` - SDL context.
+    pub fn handle_events(&mut self, context: &mut Context) -> Result<GlobalAction> {
+        if self.main_window.update()? {
+            self.dirty = true;
+        }
+        match self.poll_events(context) {
+            GlobalAction::QuitProgram => return Ok(GlobalAction::QuitProgram),
+            GlobalAction::CloseDebugWindow => self.close_debug_window(),
+            GlobalAction::OpenDebugWindow => self.open_debug_window(context)?,
+            GlobalAction::None => {}
+        }
+        Ok(GlobalAction::None)
+    }
+
+    /// Draw every open window if a frame time passed since they were last
+    /// drawn and they changed since.
+    /// # Arguments
+    /// * `context` - SDL context.
+    pub fn draw(&mut self, context: &mut Context) -> Result<()> {
+        let now = Instant::now();
+        if now < self.next_draw {
+            return Ok(());
+        }
+        self.next_draw = (self.next_draw + FRAME_TIME).max(now);
+        let cycle = self.main_window.cycle();
+        if !self.dirty && self.drawn_cycle == Some(cycle) {
+            return Ok(());
+        }
+        self.main_window.draw(context)?;
+        if let Some(win) = self.debug_window.as_mut() {
+            win.draw(context)?;
+        }
+        self.drawn_cycle = Some(cycle);
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Wait until the windows may be drawn next, for when the system does
+    /// not run.
+    pub fn wait_for_frame(&self) {
+        let now = Instant::now();
+        if now < self.next_draw {
+            thread::sleep(self.next_draw - now);
+        }
+    }
+
+    /// Close the windows, stopping a system on a thread of its own and
+    /// waiting for its thread.
+    pub fn close(&mut self) -> Result<()> {
+        self.close_debug_window();
+        self.main_window.close()
+    }
+}
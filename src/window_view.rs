@@ -0,0 +1,143 @@
+// The register window stack as the debug window draws it.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// The windows of the register file form a ring: a call moves CWP one window
+// down, a return one window up, and the windows from CWP up to SWP hold the
+// frames of the calls in progress. When a call wraps around onto SWP the
+// oldest frame has to go to memory (an overflow), when a return wraps onto
+// it the frame has to come back (an underflow). The register file does not
+// spill yet, SWP just moves, but the ring shows where it would happen.
+
+use commit::RetiredInstruction;
+use cpu::{ProcessorStatusWord, RegisterFile, NUM_GLOBALS, WINDOW_SIZE};
+
+/// What a window of the ring holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowUse {
+    /// The frame running, CWP points at it.
+    Current,
+    /// The frame of a call in progress.
+    Caller,
+    /// Nothing, the next calls take it.
+    Free,
+}
+
+/// A window of the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowSlot {
+    /// Number of the window.
+    pub window: u8,
+    /// What it holds.
+    pub usage: WindowUse,
+    /// r10 to r31 as seen from the window.
+    pub registers: [u32; WINDOW_SIZE],
+}
+
+/// Which way a frame moved between the register file and memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpillKind {
+    /// A call needed a window, the oldest frame goes to memory.
+    Overflow,
+    /// A return needed a window back, the frame comes from memory.
+    Underflow,
+}
+
+/// A frame that moved between the register file and memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spill {
+    /// Which way it moved.
+    pub kind: SpillKind,
+    /// Window the frame moved out of or into.
+    pub window: u8,
+    /// Clock cycle the instruction that moved it retired on.
+    pub cycle: u64,
+}
+
+/// The register windows of a core.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowRing {
+    /// Current window pointer.
+    pub cwp: u8,
+    /// Saved window pointer.
+    pub swp: u8,
+    /// Every window, by number.
+    pub slots: Vec<WindowSlot>,
+}
+
+impl WindowRing {
+    /// Look at the windows of a register file.
+    /// # Arguments
+    /// * `rf` - The register file.
+    /// * `psw` - PSW holding the window pointers.
+    pub fn new(rf: &RegisterFile, psw: &ProcessorStatusWord) -> Self {
+        let frames: Vec<u8> = rf.backtrace(psw).iter().map(|f| f.window).collect();
+        let slots = (0..rf.windows())
+            .map(|window| {
+                let mut registers = [0; WINDOW_SIZE];
+                for (i, reg) in registers.iter_mut().enumerate() {
+                    *reg = rf.read((NUM_GLOBALS + i) as u8, window);
+                }
+                WindowSlot {
+                    window: window,
+                    usage: if window == psw.get_cwp() {
+                        WindowUse::Current
+                    } else if frames.contains(&window) {
+                        WindowUse::Caller
+                    } else {
+                        WindowUse::Free
+                    },
+                    registers: registers,
+                }
+            })
+            .collect();
+        Self {
+            cwp: psw.get_cwp(),
+            swp: psw.get_swp(),
+            slots: slots,
+        }
+    }
+
+    /// Get the number of windows.
+    pub fn windows(&self) -> u8 {
+        self.slots.len() as u8
+    }
+}
+
+// Public functions.
+
+/// Find the frame an instruction moved between the register file and
+/// memory. Return it, None if the instruction moved none.
+/// # Arguments
+/// * `r` - The instruction.
+/// * `windows` - Number of windows in the register file.
+pub fn spill(r: &RetiredInstruction, windows: u8) -> Option<Spill> {
+    let (before, after) = (r.psw_before, r.psw_after);
+    if before.get_swp() == after.get_swp() {
+        return None;
+    }
+    let kind = if after.get_cwp() == (before.get_cwp() + windows - 1) % windows {
+        SpillKind::Overflow
+    } else if after.get_cwp() == (before.get_cwp() + 1) % windows {
+        SpillKind::Underflow
+    } else {
+        // The PSW was written.
+        return None;
+    };
+    Some(Spill {
+        kind: kind,
+        window: after.get_cwp(),
+        cycle: r.cycle,
+    })
+}
@@ -0,0 +1,100 @@
+// Test code for the register window view.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(test)]
+#[path = "window_view.rs"]
+mod test {
+    use super::super::*;
+    use commit::RetiredInstruction;
+    use cpu::*;
+    use instruction::*;
+    use window_view::*;
+
+    /// Make a call or return that took the PSW from `before` to `after`.
+    fn retired(before: ProcessorStatusWord, after: ProcessorStatusWord) -> RetiredInstruction {
+        RetiredInstruction {
+            instruction: Instruction::Callr(LongInstruction::new(false, 31, 0x40)),
+            pc: 0x100,
+            dest: None,
+            psw_before: before,
+            psw_after: after,
+            access: None,
+            branch: Some(0x140),
+            trap: None,
+            cycle: 12,
+        }
+    }
+
+    #[test]
+    fn ring_marks_frames() {
+        let mut regs = RegisterFile::with_windows(4);
+        let mut psw = ProcessorStatusWord::new();
+        regs.write(16, 0x1234, 0);
+        psw.push(regs.windows());
+        psw.push(regs.windows());
+
+        let ring = WindowRing::new(&regs, &psw);
+        assert_eq!(ring.windows(), 4);
+        assert_eq!((ring.cwp, ring.swp), (2, 0));
+        let uses: Vec<WindowUse> = ring.slots.iter().map(|s| s.usage).collect();
+        assert_eq!(
+            uses,
+            vec![
+                WindowUse::Caller,
+                WindowUse::Free,
+                WindowUse::Current,
+                WindowUse::Caller
+            ]
+        );
+        // Registers are seen from their own window, r16 first local.
+        assert_eq_hex!(ring.slots[0].registers[16 - NUM_GLOBALS], 0x1234);
+        assert_eq_hex!(ring.slots[2].registers[16 - NUM_GLOBALS], 0);
+    }
+
+    #[test]
+    fn spills_are_found() {
+        let windows = 4;
+        let mut psw = ProcessorStatusWord::new();
+        let start = psw;
+        psw.push(windows);
+        // A call with windows to spare moves nothing.
+        assert_eq!(spill(&retired(start, psw), windows), None);
+        for _ in 0..2 {
+            psw.push(windows);
+        }
+        let before = psw;
+        psw.push(windows);
+        assert_eq!(
+            spill(&retired(before, psw), windows),
+            Some(Spill {
+                kind: SpillKind::Overflow,
+                window: 0,
+                cycle: 12,
+            })
+        );
+        let before = psw;
+        psw.pop(windows);
+        assert_eq!(
+            spill(&retired(before, psw), windows).map(|s| (s.kind, s.window)),
+            Some((SpillKind::Underflow, 1))
+        );
+
+        // Writing the PSW is not a spill.
+        let mut written = psw;
+        written.set_cwp(3);
+        written.set_swp(3);
+        assert_eq!(spill(&retired(psw, written), windows), None);
+    }
+}
@@ -0,0 +1,83 @@
+// Tests of the emulator through its library interface.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+extern crate riscii;
+
+use riscii::asm::assemble;
+use riscii::config::Endian;
+use riscii::instruction::{ShortInstruction, ShortSource};
+use riscii::loader::Format;
+use riscii::memory::{Access, Width};
+use riscii::system::RunState;
+use riscii::util::Result;
+use riscii::{decode, Config, Image, Instruction, Memory, System, ALU};
+
+#[test]
+fn runs_an_assembled_program() -> Result<()> {
+    let program = assemble(
+        "       add r16, r0, 6
+                add r17, r0, 7
+                add r18, r16, r17
+         halt:  jmpx alw, r0, halt
+                add r0, r0, r0",
+        0,
+    )?;
+    let image = Image::parse(&program.to_bytes(Endian::Big), Format::Raw, 0)?;
+    let mut system = System::new(&Config::new()?)?;
+    system.load_image(&image)?;
+    system.run_until_halt()?;
+    let dp = system.data_path();
+    assert_eq!(dp.register_file().read(18, dp.get_psw().get_cwp()), 13);
+
+    // A cold reset loads the program again and starts over.
+    system.get_mem_ref().set_word(0, 0)?;
+    system.reset(riscii::device::Reset::Cold)?;
+    assert_eq!(system.run_state(), RunState::Running);
+    system.run_until_halt()?;
+    let dp = system.data_path();
+    assert_eq!(dp.register_file().read(16, dp.get_psw().get_cwp()), 6);
+    Ok(())
+}
+
+#[test]
+fn decodes_what_it_encodes() -> Result<()> {
+    let add = Instruction::Add(ShortInstruction::new(
+        true,
+        16,
+        17,
+        ShortSource::Imm13(0x42),
+    ));
+    assert!(decode(add.encode())? == add);
+    assert!(decode(0).is_err());
+    Ok(())
+}
+
+#[test]
+fn alu_and_memory_work_alone() {
+    let mut alu = ALU::new();
+    alu.ai = 0xffffffff;
+    alu.bi = 1;
+    let (sum, scc) = alu.add_scc();
+    assert_eq!(sum, 0);
+    assert!(scc.z && scc.c);
+
+    let mut mem = Memory::from_size(8);
+    assert_eq!(
+        mem.access(4, Width::Word, Access::Write(0x11223344)),
+        Ok(0x11223344)
+    );
+    assert_eq!(mem.access(6, Width::Half, Access::Read), Ok(0x3344));
+    assert!(mem.access(8, Width::Byte, Access::Read).is_err());
+}
@@ -0,0 +1,187 @@
+// Guest test programs.
+// (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or (at
+// your option) any later version.
+
+// This program is distributed in the hope that it will be useful, but
+// WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+// General Public License for more details.
+
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+// Runs every program in tests/programs on both engines, see `harness` for
+// how they report their results. A program that fails leaves its failure
+// bundle in target/program-bundles.
+
+extern crate riscii;
+
+use riscii::config::Engine;
+use riscii::harness::{read_dir, Outcome, TestProgram, DEFAULT_BUDGET};
+use riscii::util::Result;
+use riscii::{Config, System};
+use std::env;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+/// Directory failure bundles are written to.
+const BUNDLES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/program-bundles");
+
+/// Create the configuration test programs run with.
+fn config() -> Result<Config> {
+    let mut config = Config::new()?;
+    config.set_mem_size(0x10000);
+    Ok(config)
+}
+
+#[test]
+fn guest_programs_pass() -> Result<()> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs");
+    let programs = read_dir(dir)?;
+    assert!(!programs.is_empty());
+    let config = config()?;
+    let mut failures = Vec::new();
+    for program in programs.iter() {
+        for engine in [Engine::Fast, Engine::Cycle].iter() {
+            let (outcome, bundle) =
+                program.run_with_bundle(&config, *engine, DEFAULT_BUDGET, BUNDLES)?;
+            if let Some(bundle) = bundle {
+                failures.push(format!(
+                    "{} on {}: {}, see {}",
+                    program.path, engine, outcome, bundle
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    Ok(())
+}
+
+#[test]
+fn failures_are_reported() -> Result<()> {
+    let path = env::temp_dir().join("riscii-failing-program.s");
+    let path = path.to_str().unwrap();
+    let source = "      ldhi r9, 0x7fff8
+                        add r1, r0, 3
+                        add r0, r0, r0
+                        stxw r1, r9, 0x78
+                  halt: jmpx alw, r0, halt
+                        add r0, r0, r0";
+    fs::write(path, source)?;
+    let program = TestProgram::read(path)?;
+    let config = config()?;
+    assert_eq!(
+        program.run(&config, Engine::Fast, DEFAULT_BUDGET)?,
+        Outcome::Failed(3)
+    );
+
+    fs::write(path, "loop: jmpx alw, r0, loop\nadd r0, r0, r0")?;
+    let program = TestProgram::read(path)?;
+    assert_eq!(
+        program.run(&config, Engine::Cycle, DEFAULT_BUDGET)?,
+        Outcome::Stopped(0)
+    );
+
+    let source = "a: jmpr alw, b
+                  add r0, r0, r0
+               b: jmpx alw, r0, a
+                  add r0, r0, r0";
+    fs::write(path, source)?;
+    let program = TestProgram::read(path)?;
+    assert_eq!(program.run(&config, Engine::Fast, 100)?, Outcome::TimedOut);
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+#[test]
+fn failures_leave_a_bundle() -> Result<()> {
+    let dir = env::temp_dir().join("riscii-bundles");
+    let dir = dir.to_str().unwrap();
+    let path = env::temp_dir().join("riscii-bundled-program.s");
+    let path = path.to_str().unwrap();
+    let source = "      ldhi r9, 0x7fff8
+                        add r1, r0, 2
+                        add r0, r0, r0
+                        stxw r1, r9, 0x78
+                  halt: jmpx alw, r0, halt
+                        add r0, r0, r0";
+    fs::write(path, source)?;
+    let program = TestProgram::read(path)?;
+    let config = config()?;
+    let (outcome, bundle) = program.run_with_bundle(&config, Engine::Cycle, DEFAULT_BUDGET, dir)?;
+    assert_eq!(outcome, Outcome::Failed(2));
+    let bundle = bundle.unwrap();
+    assert!(bundle.ends_with("riscii-bundled-program-cycle"));
+    let read = |name: &str| fs::read_to_string(format!("{}/{}", bundle, name));
+
+    // The snapshot restores to where the program ended.
+    let mut system = System::new(&config)?;
+    system.load_snapshot(&format!("{}/snapshot.r2d2", bundle))?;
+    assert_eq!(system.data_path().register_file().read(1, 0), 2);
+
+    let trace = read("trace.txt")?;
+    assert!(trace.lines().any(|l| l.contains("stxw")));
+    let stats = read("stats.txt")?;
+    assert!(stats.contains("Engine: cycle"));
+    assert!(stats.contains("Outcome: failed check 2"));
+    assert!(stats.contains("Instructions retired: "));
+    assert_eq!(read("config.txt")?, config.to_string());
+    assert_eq!(
+        read("image.txt")?,
+        format!("{:016x} {}\n", program.image_hash(), path)
+    );
+
+    // A passing run leaves no bundle.
+    fs::write(path, "ldhi r9, 0x7fff8\nstxw r0, r9, 0x78\nadd r0, r0, r0")?;
+    let program = TestProgram::read(path)?;
+    assert_eq!(
+        program.run_with_bundle(&config, Engine::Fast, DEFAULT_BUDGET, dir)?,
+        (Outcome::Passed, None)
+    );
+    fs::remove_file(path)?;
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_command_bundles_each_failing_run() -> Result<()> {
+    let programs = env::temp_dir().join("riscii-test-command");
+    let bundles = env::temp_dir().join("riscii-test-command-bundles");
+    fs::create_dir_all(&programs)?;
+    let pass = "ldhi r9, 0x7fff8\nstxw r0, r9, 0x78\nadd r0, r0, r0";
+    fs::write(programs.join("pass.s"), pass)?;
+    let fail = "      ldhi r9, 0x7fff8
+                      add r1, r0, 4
+                      add r0, r0, r0
+                      stxw r1, r9, 0x78
+                halt: jmpx alw, r0, halt
+                      add r0, r0, r0";
+    fs::write(programs.join("fail.s"), fail)?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_risc-ii"))
+        .arg("test")
+        .arg(&programs)
+        .args(["--mem", "65536", "--bundles"])
+        .arg(&bundles)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(
+        stdout.contains("fail.s on fast: failed check 4"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("2 of 4 runs passed."), "{}", stdout);
+    let mut written: Vec<String> = fs::read_dir(&bundles)?
+        .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+        .collect::<io::Result<_>>()?;
+    written.sort();
+    assert_eq!(written, vec!["fail-cycle", "fail-fast"]);
+    fs::remove_dir_all(&programs)?;
+    fs::remove_dir_all(&bundles)?;
+    Ok(())
+}
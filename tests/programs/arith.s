@@ -0,0 +1,96 @@
+; Integer arithmetic test program.
+; (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+; This program is free software: you can redistribute it and/or modify
+; it under the terms of the GNU Affero General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or (at
+; your option) any later version.
+
+; This program is distributed in the hope that it will be useful, but
+; WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+; General Public License for more details.
+
+; You should have received a copy of the GNU Affero General Public License
+; along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+; Checks the ALU and shifter. Every check puts its number in r1 and exits
+; with it if it fails, the program exits with 0 once every check passed.
+; Dependent instructions are a NOP apart, the pipeline does not forward.
+
+        ldhi    r9, 0x7fff8         ; r9 = devices, 0xffff0000
+
+        add     r1, r0, 1           ; 6 + 7 = 13
+        add     r16, r0, 6
+        add     r17, r0, 7
+        add     r0, r0, r0
+        add     r18, r16, r17
+        add     r0, r0, r0
+        sub.cc  r0, r18, 13
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 2           ; 6 - 7 is negative
+        sub.cc  r18, r16, r17
+        jmpr    pl, fail
+        add     r0, r0, r0
+        xor.cc  r0, r18, r0         ; and all ones
+        add     r0, r0, r0
+        add.cc  r0, r18, 1
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 3           ; carry out of 0xffffffff + 1
+        add.cc  r0, r18, 2
+        jmpr    lonc, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 4           ; 0x0f0f & 0x00ff | 0x1000 ^ 0x0001
+        add     r16, r0, 0xf0f
+        add     r0, r0, r0
+        and     r16, r16, 0xff
+        add     r0, r0, r0
+        or      r16, r16, 0x1000
+        add     r0, r0, r0
+        xor     r16, r16, 1
+        add     r0, r0, r0
+        sub.cc  r0, r16, 0x100e
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 5           ; shifts
+        add     r16, r0, 1
+        add     r0, r0, r0
+        sll     r16, r16, 31        ; 0x80000000
+        add     r0, r0, r0
+        sra     r17, r16, 4         ; 0xf8000000
+        srl     r18, r16, 31        ; 1
+        add     r0, r0, r0
+        sub.cc  r0, r18, 1
+        jmpr    ne, fail
+        add     r0, r0, r0
+        srl     r17, r17, 27        ; 0x1f
+        add     r0, r0, r0
+        sub.cc  r0, r17, 0x1f
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 6           ; ldhi fills the top 19 bits
+        ldhi    r16, 0x12345
+        add     r0, r0, r0
+        srl     r16, r16, 13
+        add     r0, r0, r0
+        ldhi    r17, 0x9
+        add     r0, r0, r0
+        or      r17, r17, 0x345
+        add     r0, r0, r0
+        sub.cc  r0, r16, r17
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        stxw    r0, r9, 0x78        ; passed
+halt:   jmpx    alw, r0, halt
+        add     r0, r0, r0
+
+fail:   stxw    r1, r9, 0x78        ; exit with the failing check
+        jmpx    alw, r0, halt
+        add     r0, r0, r0
@@ -0,0 +1,75 @@
+; Procedure call test program.
+; (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+; This program is free software: you can redistribute it and/or modify
+; it under the terms of the GNU Affero General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or (at
+; your option) any later version.
+
+; This program is distributed in the hope that it will be useful, but
+; WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+; General Public License for more details.
+
+; You should have received a copy of the GNU Affero General Public License
+; along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+; Checks calls and returns through the register windows, recursing deeper
+; than there are windows. Every check puts its number in r1 and exits with it
+; if it fails, the program exits with 0 once every check passed. Dependent
+; instructions are a NOP apart, the pipeline does not forward.
+
+        ldhi    r9, 0x7fff8         ; r9 = devices, 0xffff0000
+
+        add     r1, r0, 1           ; arguments and results pass through
+        add     r10, r0, 20         ; the overlapping registers
+        add     r11, r0, 22
+        callr   ra, sum
+        add     r0, r0, r0
+        sub.cc  r0, r10, 42
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 2           ; locals survive a call
+        add     r16, r0, 0x123
+        add     r10, r0, 1
+        add     r11, r0, 2
+        callr   ra, sum
+        add     r0, r0, r0
+        sub.cc  r0, r16, 0x123
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 3           ; 1 + 2 + ... + 5, five windows deep
+        add     r10, r0, 5
+        callx   ra, r0, triangle
+        add     r0, r0, r0
+        sub.cc  r0, r10, 15
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        stxw    r0, r9, 0x78        ; passed
+halt:   jmpx    alw, r0, halt
+        add     r0, r0, r0
+
+fail:   stxw    r1, r9, 0x78        ; exit with the failing check
+        jmpx    alw, r0, halt
+        add     r0, r0, r0
+
+; r26 := r26 + r27, clobbering the callee's locals.
+sum:
+        add     r16, r0, 0
+        add     r26, r26, r27
+        ret     alw, ra, 8
+        add     r0, r0, r0
+
+; r26 := 1 + 2 + ... + r26, for r26 > 0.
+triangle:
+        sub.cc  r0, r26, 1
+        jmpr    eq, done
+        add     r0, r0, r0
+        sub     r10, r26, 1
+        callx   ra, r0, triangle
+        add     r0, r0, r0
+        add     r26, r26, r10
+done:   ret     alw, ra, 8
+        add     r0, r0, r0
@@ -0,0 +1,92 @@
+; Load and store test program.
+; (C) Ryan Jeffrey <ryan@ryanmj.xyz>, 2022
+; This program is free software: you can redistribute it and/or modify
+; it under the terms of the GNU Affero General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or (at
+; your option) any later version.
+
+; This program is distributed in the hope that it will be useful, but
+; WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+; General Public License for more details.
+
+; You should have received a copy of the GNU Affero General Public License
+; along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+; Checks loads and stores of every width. Every check puts its number in r1
+; and exits with it if it fails, the program exits with 0 once every check
+; passed. Dependent instructions are a NOP apart, the pipeline does not
+; forward.
+
+        ldhi    r9, 0x7fff8         ; r9 = devices, 0xffff0000
+        add     r8, r0, data        ; r8 = scratch data
+
+        add     r1, r0, 1           ; a word reads back
+        ldhi    r16, 0x7f6e5
+        add     r0, r0, r0
+        or      r16, r16, 0x1d4c    ; 0xfedcbd4c
+        add     r0, r0, r0
+        stxw    r16, r8, 0
+        ldxw    r17, r8, 0
+        add     r0, r0, r0
+        sub.cc  r0, r16, r17
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 2           ; bytes are big endian and sign extend
+        ldxbu   r17, r8, 0
+        add     r0, r0, r0
+        sub.cc  r0, r17, 0xfe
+        jmpr    ne, fail
+        add     r0, r0, r0
+        ldxbs   r17, r8, 3
+        add     r0, r0, r0
+        sub.cc  r0, r17, 0x4c
+        jmpr    ne, fail
+        add     r0, r0, r0
+        ldxbs   r17, r8, 1
+        add     r0, r0, r0
+        add.cc  r0, r17, 0x24       ; 0xdc sign extended is -0x24
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 3           ; half words
+        ldxhu   r17, r8, 2
+        ldhi    r18, 0x5
+        add     r0, r0, r0
+        or      r18, r18, 0x1d4c    ; 0xbd4c
+        add     r0, r0, r0
+        sub.cc  r0, r17, r18
+        jmpr    ne, fail
+        add     r0, r0, r0
+        ldxhs   r17, r8, 0
+        add     r0, r0, r0
+        add.cc  r0, r17, 0x124      ; 0xfedc sign extended is -0x124
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        add     r1, r0, 4           ; narrow stores leave the other bytes
+        add     r16, r0, 0x11
+        add     r0, r0, r0
+        stxb    r16, r8, 1
+        add     r16, r0, 0x223
+        add     r0, r0, r0
+        stxh    r16, r8, 2
+        ldxw    r17, r8, 0
+        ldhi    r18, 0x7f088
+        add     r0, r0, r0
+        or      r18, r18, 0x223     ; 0xfe110223
+        add     r0, r0, r0
+        sub.cc  r0, r17, r18
+        jmpr    ne, fail
+        add     r0, r0, r0
+
+        stxw    r0, r9, 0x78        ; passed
+halt:   jmpx    alw, r0, halt
+        add     r0, r0, r0
+
+fail:   stxw    r1, r9, 0x78        ; exit with the failing check
+        jmpx    alw, r0, halt
+        add     r0, r0, r0
+
+data:   .word   0, 0